@@ -0,0 +1,95 @@
+//! Encode/decode and framing cost of the heap-based and fixed-buffer codecs.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use embedded_recruitment_task::codec::{self, FrameDecoder};
+use embedded_recruitment_task::fixed::{FrameBuf, Request};
+use embedded_recruitment_task::message::{client_message, AddRequest, ClientMessage, EchoMessage};
+
+fn echo(len: usize) -> ClientMessage {
+    ClientMessage {
+        message: Some(client_message::Message::EchoMessage(EchoMessage {
+            content: "x".repeat(len),
+        })),
+        ..Default::default()
+    }
+}
+
+fn add() -> ClientMessage {
+    ClientMessage {
+        message: Some(client_message::Message::AddRequest(AddRequest {
+            a: 1,
+            b: 2,
+        })),
+        ..Default::default()
+    }
+}
+
+fn bench_encode_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("codec");
+    for (name, message) in [
+        ("add", add()),
+        ("echo_16", echo(16)),
+        ("echo_4k", echo(4096)),
+    ] {
+        let frame = codec::encode(&message).unwrap();
+        group.throughput(Throughput::Bytes(frame.len() as u64));
+        group.bench_function(format!("encode/{}", name), |b| {
+            b.iter(|| codec::encode(black_box(&message)).unwrap())
+        });
+        group.bench_function(format!("decode_frame/{}", name), |b| {
+            b.iter(|| codec::decode_frame::<ClientMessage>(black_box(&frame)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_fixed(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fixed");
+    let frame = codec::encode(&echo(16)).unwrap();
+    group.bench_function("encode/echo_16", |b| {
+        let content = "x".repeat(16);
+        let mut buf = FrameBuf::<64>::new();
+        b.iter(|| {
+            buf.encode_request(black_box(&Request::Echo(&content)))
+                .unwrap()
+                .len()
+        })
+    });
+    group.bench_function("decode/echo_16", |b| {
+        b.iter(|| Request::decode(black_box(&frame)).unwrap())
+    });
+    group.finish();
+}
+
+// Reassembling a stream of 100 small frames delivered in reads of various sizes
+fn bench_framing(c: &mut Criterion) {
+    let mut stream = Vec::new();
+    for _ in 0..100 {
+        stream.extend(codec::encode(&echo(32)).unwrap());
+    }
+
+    let mut group = c.benchmark_group("framing");
+    group.throughput(Throughput::Bytes(stream.len() as u64));
+    for read_size in [1, 64, 512, stream.len()] {
+        group.bench_function(format!("frame_decoder/read_{}", read_size), |b| {
+            b.iter_batched(
+                FrameDecoder::new,
+                |mut decoder| {
+                    let mut frames = 0;
+                    for read in stream.chunks(read_size) {
+                        decoder.extend(read);
+                        while decoder.next_frame().unwrap().is_some() {
+                            frames += 1;
+                        }
+                    }
+                    frames
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_encode_decode, bench_fixed, bench_framing);
+criterion_main!(benches);
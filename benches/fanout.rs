@@ -0,0 +1,69 @@
+//! Payloads forwarded to many devices, copied and shared.
+//!
+//! `fanout` queues one 4 KiB payload for each of up to 1000 devices, once
+//! copying it per device and once handing every device the same [`Bytes`].
+//! `decode` decodes a `Delivery` carrying that payload from a plain slice,
+//! which copies the payload out, and from a shared frame, which does not.
+//!
+//! ```text
+//! cargo bench --bench fanout --features server
+//! ```
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use embedded_recruitment_task::codec::{self, Bytes, FrameDecoder};
+use embedded_recruitment_task::message::Delivery;
+use embedded_recruitment_task::outbox::Outboxes;
+
+const PAYLOAD: usize = 4096;
+
+fn bench_fanout(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fanout");
+    let payload = Bytes::from(vec![0x5a; PAYLOAD]);
+    for devices in [10, 100, 1000] {
+        let names: Vec<String> = (0..devices).map(|d| format!("device-{}", d)).collect();
+        group.throughput(Throughput::Elements(devices as u64));
+        group.bench_with_input(BenchmarkId::new("copied", devices), &names, |b, names| {
+            b.iter(|| {
+                let mut outboxes = Outboxes::default();
+                for name in names {
+                    outboxes.push(name, payload.to_vec(), None);
+                }
+                outboxes
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("shared", devices), &names, |b, names| {
+            b.iter(|| {
+                let mut outboxes = Outboxes::default();
+                for name in names {
+                    outboxes.push(name, payload.clone(), None);
+                }
+                outboxes
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    let frame = codec::encode(&Delivery {
+        sequence: 1,
+        payload: Bytes::from(vec![0x5a; PAYLOAD]),
+    })
+    .unwrap();
+    group.throughput(Throughput::Bytes(frame.len() as u64));
+    group.bench_function("slice", |b| {
+        b.iter(|| codec::decode_frame::<Delivery>(&frame).unwrap())
+    });
+    group.bench_function("shared", |b| {
+        b.iter(|| {
+            let mut decoder = FrameDecoder::new();
+            decoder.extend(&frame);
+            decoder.next_message::<Delivery>().unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_fanout, bench_decode);
+criterion_main!(benches);
@@ -0,0 +1,102 @@
+//! End-to-end request/response throughput over loopback TCP.
+//!
+//! `start_server` is the only backend-specific code. To compare the threaded
+//! server with another backend (such as a future async one), save a baseline,
+//! switch `start_server` to the other backend and compare against it:
+//!
+//! ```text
+//! cargo bench --bench server -- --save-baseline threaded
+//! cargo bench --bench server -- --baseline threaded
+//! ```
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::message::{client_message, AddRequest, EchoMessage};
+use embedded_recruitment_task::server::Server;
+use std::{sync::Arc, thread};
+
+// Starts a server on an ephemeral port and returns it with its port
+fn start_server() -> (Arc<Server>, thread::JoinHandle<()>, u16) {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let port = server.local_addr().expect("Server has no address").port();
+    let server_clone = Arc::clone(&server);
+    let handle = thread::spawn(move || server_clone.run().expect("Server encountered an error"));
+    (server, handle, port)
+}
+
+fn connect(port: u16) -> Client {
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    client
+}
+
+fn bench_round_trip(c: &mut Criterion) {
+    let (server, handle, port) = start_server();
+
+    let mut group = c.benchmark_group("round_trip");
+    group.throughput(Throughput::Elements(1));
+    let requests = [
+        (
+            "add",
+            client_message::Message::AddRequest(AddRequest { a: 1, b: 2 }),
+        ),
+        (
+            "echo_1k",
+            client_message::Message::EchoMessage(EchoMessage {
+                content: "x".repeat(1024),
+            }),
+        ),
+    ];
+    for (name, request) in requests {
+        let mut client = connect(port);
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                client.send(request.clone()).unwrap();
+                client.receive().unwrap()
+            })
+        });
+        client.disconnect().ok();
+    }
+    group.finish();
+
+    // Several connected clients in parallel, each doing a fixed number of round trips
+    let mut group = c.benchmark_group("concurrent");
+    for clients in [4, 16] {
+        let per_client: i32 = 100;
+        let mut connected: Vec<Client> = (0..clients).map(|_| connect(port)).collect();
+        group.throughput(Throughput::Elements((clients * per_client) as u64));
+        group.bench_function(format!("add/{}_clients", clients), |b| {
+            b.iter(|| {
+                thread::scope(|scope| {
+                    for client in connected.iter_mut() {
+                        scope.spawn(move || {
+                            for i in 0..per_client {
+                                client
+                                    .send(client_message::Message::AddRequest(AddRequest {
+                                        a: i,
+                                        b: 1,
+                                    }))
+                                    .unwrap();
+                                client.receive().unwrap();
+                            }
+                        });
+                    }
+                })
+            })
+        });
+        for mut client in connected {
+            client.disconnect().ok();
+        }
+    }
+    group.finish();
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(20);
+    targets = bench_round_trip
+}
+criterion_main!(benches);
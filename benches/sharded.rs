@@ -0,0 +1,189 @@
+//! Contention on the server's shared maps, behind one lock and split up.
+//!
+//! Each thread queues messages for its own devices and takes them again, as
+//! connection handlers do. With one lock, adding threads adds waiting; with
+//! shards, throughput should keep growing with the cores. The topic trie is
+//! exercised the same way: each thread subscribes, matches publications
+//! against every thread's filters and unsubscribes, behind a mutex and behind
+//! a read-write lock that lets publishers match at once. Parked sessions are
+//! churned behind one lock and in shards. Run it on the target machine, since
+//! the numbers mean little on fewer than 8 cores:
+//!
+//! ```text
+//! cargo bench --bench sharded --features server
+//! ```
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use embedded_recruitment_task::outbox::Outboxes;
+use embedded_recruitment_task::resume::{SessionState, SessionStore};
+use embedded_recruitment_task::sharded::Sharded;
+use embedded_recruitment_task::topic::TopicTrie;
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Mutex, RwLock},
+    thread,
+};
+
+const OPERATIONS: usize = 10_000; // Per thread
+const DEVICES: usize = 64; // Per thread
+const PUBLISHES: usize = 16; // Matched per subscription change, as publishing is the common case
+
+// Queues for and takes from each of a thread's devices, locking with `lock`
+fn churn<G: DerefMut<Target = Outboxes>>(thread: usize, lock: impl Fn(&str) -> G) {
+    let devices: Vec<String> = (0..DEVICES)
+        .map(|device| format!("device-{}-{}", thread, device))
+        .collect();
+    for operation in 0..OPERATIONS {
+        let device = &devices[operation % DEVICES];
+        lock(device).push(device, vec![0; 16], None);
+        lock(device).take(device);
+    }
+}
+
+// Subscribes each of a thread's devices in turn, matches publications against
+// the trie and unsubscribes again, taking the trie with `read` or `write`
+fn subscribe_and_publish<R, W>(thread: usize, read: impl Fn() -> R, write: impl Fn() -> W)
+where
+    R: Deref<Target = TopicTrie<usize>>,
+    W: DerefMut<Target = TopicTrie<usize>>,
+{
+    let filters: Vec<String> = (0..DEVICES)
+        .map(|device| format!("site/{}/device-{}/+", thread, device))
+        .collect();
+    let topics: Vec<String> = (0..DEVICES)
+        .map(|device| format!("site/{}/device-{}/temperature", thread, device))
+        .collect();
+    for operation in 0..OPERATIONS / PUBLISHES {
+        let device = operation % DEVICES;
+        write().insert(&filters[device], device).unwrap();
+        for publish in 0..PUBLISHES {
+            let matched = read().matches(&topics[(device + publish) % DEVICES]).len();
+            criterion::black_box(matched);
+        }
+        write().remove(&filters[device], &device);
+    }
+}
+
+// Parks a session for each of a thread's devices and resumes it, locking with `lock`
+fn park_and_resume<G: DerefMut<Target = SessionStore>>(thread: usize, lock: impl Fn(&[u8]) -> G) {
+    let tokens: Vec<Vec<u8>> = (0..DEVICES)
+        .map(|device| format!("token-{}-{}", thread, device).into_bytes())
+        .collect();
+    for operation in 0..OPERATIONS {
+        let token = &tokens[operation % DEVICES];
+        let state = SessionState {
+            dedup: Default::default(),
+            device: None,
+        };
+        lock(token).park(token.clone(), state);
+        lock(token).resume(token);
+    }
+}
+
+fn bench_outboxes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("outboxes");
+    group.sample_size(10);
+    for threads in [1, 2, 4, 8, 16] {
+        group.throughput(Throughput::Elements((threads * OPERATIONS) as u64));
+
+        let single = Mutex::new(Outboxes::default());
+        group.bench_function(format!("mutex/{}_threads", threads), |b| {
+            b.iter(|| {
+                thread::scope(|scope| {
+                    for thread in 0..threads {
+                        let single = &single;
+                        scope.spawn(move || churn(thread, |_| single.lock().unwrap()));
+                    }
+                })
+            })
+        });
+
+        let sharded: Sharded<Outboxes> = Sharded::default();
+        group.bench_function(format!("sharded/{}_threads", threads), |b| {
+            b.iter(|| {
+                thread::scope(|scope| {
+                    for thread in 0..threads {
+                        let sharded = &sharded;
+                        scope.spawn(move || churn(thread, |device| sharded.lock(device)));
+                    }
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_topics(c: &mut Criterion) {
+    let mut group = c.benchmark_group("topics");
+    group.sample_size(10);
+    for threads in [1, 2, 4, 8, 16] {
+        group.throughput(Throughput::Elements((threads * OPERATIONS) as u64));
+
+        let single = Mutex::new(TopicTrie::new());
+        group.bench_function(format!("mutex/{}_threads", threads), |b| {
+            b.iter(|| {
+                thread::scope(|scope| {
+                    for thread in 0..threads {
+                        let lock = || single.lock().unwrap();
+                        scope.spawn(move || subscribe_and_publish(thread, lock, lock));
+                    }
+                })
+            })
+        });
+
+        let shared = RwLock::new(TopicTrie::new());
+        group.bench_function(format!("rwlock/{}_threads", threads), |b| {
+            b.iter(|| {
+                thread::scope(|scope| {
+                    for thread in 0..threads {
+                        let shared = &shared;
+                        scope.spawn(move || {
+                            subscribe_and_publish(
+                                thread,
+                                || shared.read().unwrap(),
+                                || shared.write().unwrap(),
+                            )
+                        });
+                    }
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_sessions(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sessions");
+    group.sample_size(10);
+    for threads in [1, 2, 4, 8, 16] {
+        group.throughput(Throughput::Elements((threads * OPERATIONS) as u64));
+
+        let single = Mutex::new(SessionStore::default());
+        group.bench_function(format!("mutex/{}_threads", threads), |b| {
+            b.iter(|| {
+                thread::scope(|scope| {
+                    for thread in 0..threads {
+                        let single = &single;
+                        scope.spawn(move || park_and_resume(thread, |_| single.lock().unwrap()));
+                    }
+                })
+            })
+        });
+
+        let sharded: Sharded<SessionStore> = Sharded::default();
+        group.bench_function(format!("sharded/{}_threads", threads), |b| {
+            b.iter(|| {
+                thread::scope(|scope| {
+                    for thread in 0..threads {
+                        let sharded = &sharded;
+                        scope.spawn(move || park_and_resume(thread, |token| sharded.lock(token)));
+                    }
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_outboxes, bench_topics, bench_sessions);
+criterion_main!(benches);
@@ -1,7 +1,52 @@
-use std::error::Error;
+use std::{env, error::Error, path::PathBuf, process::Command};
+
+// Current schema, then the released revisions kept for `compat`
+const PROTOS: &[&str] = &["proto/messages.proto", "proto/compat/v1.proto"];
 
 fn main() -> Result<(), Box<dyn Error>> {
-    prost_build::compile_protos(&["proto/messages.proto"], &["proto/"])?;
+    println!("cargo:rerun-if-env-changed=PROTOC");
+    println!("cargo:rerun-if-changed=proto");
+    let mut config = prost_build::Config::new();
+    if let Some(protoc) = fallback_protoc() {
+        config.protoc_executable(protoc);
+    }
+    let descriptors = PathBuf::from(env::var("OUT_DIR")?).join("descriptors.bin");
+    config.file_descriptor_set_path(&descriptors);
+    // Shared with the read buffer when decoded, and among the devices or subscribers a
+    // payload is queued for
+    config.bytes([
+        ".messages.Delivery.payload",
+        ".messages.PublishRequest.payload",
+        ".messages.Publication.payload",
+        ".messages.ClusterPublish.payload",
+        ".messages.ClusterSend.payload",
+    ]);
+    config.compile_protos(PROTOS, &["proto/"])?;
+    #[cfg(feature = "json")]
+    pbjson_build::Builder::new()
+        .register_descriptors(&std::fs::read(&descriptors)?)?
+        .build(&[".messages"])?;
 
     Ok(())
 }
+
+// The bundled protoc, when neither `PROTOC` nor a `protoc` on the path is there
+fn fallback_protoc() -> Option<PathBuf> {
+    let installed =
+        env::var_os("PROTOC").is_some() || Command::new("protoc").arg("--version").output().is_ok();
+    match installed {
+        true => None,
+        false => vendored_protoc(),
+    }
+}
+
+#[cfg(feature = "vendored-protoc")]
+fn vendored_protoc() -> Option<PathBuf> {
+    protoc_bin_vendored::protoc_bin_path().ok()
+}
+
+#[cfg(not(feature = "vendored-protoc"))]
+fn vendored_protoc() -> Option<PathBuf> {
+    println!("cargo:warning=protoc not found; install it or enable the `vendored-protoc` feature");
+    None
+}
@@ -0,0 +1,14 @@
+#![no_main]
+
+use embedded_recruitment_task::codec::decode_frame;
+use embedded_recruitment_task::message::{ClientMessage, ServerMessage};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(Some((_, used))) = decode_frame::<ClientMessage>(data) {
+        assert!(used <= data.len());
+    }
+    if let Ok(Some((_, used))) = decode_frame::<ServerMessage>(data) {
+        assert!(used <= data.len());
+    }
+});
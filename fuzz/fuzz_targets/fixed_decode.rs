@@ -0,0 +1,13 @@
+#![no_main]
+
+use embedded_recruitment_task::fixed::{Request, Response};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(Some((_, used))) = Request::decode(data) {
+        assert!(used <= data.len());
+    }
+    if let Ok(Some((_, used))) = Response::decode(data) {
+        assert!(used <= data.len());
+    }
+});
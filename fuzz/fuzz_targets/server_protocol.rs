@@ -0,0 +1,32 @@
+#![no_main]
+
+// Feeds arbitrary bytes to the server side exactly as the TCP server does,
+// split into reads whose size is picked by the first input byte.
+
+use embedded_recruitment_task::handler::handle_message;
+use embedded_recruitment_task::message::ServerMessage;
+use embedded_recruitment_task::protocol::{Event, ServerProtocol};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&chunk, data)) = data.split_first() else {
+        return;
+    };
+
+    let mut protocol = ServerProtocol::new();
+    for read in data.chunks(usize::from(chunk).max(1)) {
+        for event in protocol.feed_bytes(read) {
+            if let Event::Message(message) = event {
+                if let Some(request) = message.message {
+                    let response = ServerMessage {
+                        message: Some(handle_message(request)),
+                        ..Default::default()
+                    };
+                    let _ = protocol.send(&response);
+                }
+            }
+        }
+        while protocol.poll_transmit().is_some() {}
+    }
+    protocol.feed_eof();
+});
@@ -0,0 +1,174 @@
+//! Who may send which requests and use which topics.
+//!
+//! An [`Authorizer`] set with
+//! [`Server::authorizer`](crate::server::Server::authorizer) is asked about
+//! every request before it is handled, except the `ResumeRequest` a device
+//! names itself with and the `ResyncRequest` it recovers missed deliveries
+//! with, and about the topic of every publish and the filter of
+//! every subscribe. A refused request is answered with an `ErrorResponse`
+//! whose code is `FORBIDDEN`, logged on the `audit` target and counted in
+//! [`Stats::denied_requests`](crate::stats::Stats::denied_requests). Without
+//! an authorizer every request is allowed but admin ones, such as `TailLogs`,
+//! which only an authorizer can allow.
+//!
+//! [`StaticPolicy`] is a fixed set of [`Grant`]s per identity:
+//!
+//! ```
+//! # use embedded_recruitment_task::{authz::{Grant, StaticPolicy}, handler::MessageKind};
+//! let policy = StaticPolicy::new()
+//!     .everyone(Grant::new().send(MessageKind::Ping))
+//!     .grant(
+//!         "thermo-1",
+//!         Grant::new()
+//!             .send(MessageKind::Telemetry)
+//!             .publish("site/+/thermo-1/#")
+//!             .subscribe("site/commands/thermo-1"),
+//!     );
+//! ```
+//!
+//! The identity is the `device_id` of the connection's `ResumeRequest`, or
+//! `None` before it sends one. Devices choose their own IDs until the server
+//! authenticates them, so a policy keeps honest devices apart rather than
+//! stopping a hostile one.
+
+use crate::handler::MessageKind;
+use crate::topic;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+/// What an identity wants to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action<'a> {
+    /// Send a request of this kind
+    Send(MessageKind),
+    /// Publish on this topic
+    Publish(&'a str),
+    /// Subscribe with this filter
+    Subscribe(&'a str),
+}
+
+impl fmt::Display for Action<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Send(kind) => write!(f, "send {}", kind.name()),
+            Action::Publish(topic) => write!(f, "publish on {}", topic),
+            Action::Subscribe(filter) => write!(f, "subscribe to {}", filter),
+        }
+    }
+}
+
+/// Decides whether an identity may take an action
+pub trait Authorizer: Send + Sync {
+    /// Whether `identity`, `None` if the client has not named itself, may take `action`
+    fn authorize(&self, identity: Option<&str>, action: Action<'_>) -> bool;
+}
+
+/// What an identity is allowed; nothing by default
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Grant {
+    kinds: HashSet<MessageKind>,
+    publish: Vec<String>,   // Filters of the topics it may publish on
+    subscribe: Vec<String>, // Filters it may subscribe with, or narrower ones
+}
+
+impl Grant {
+    /// Allows nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows every kind of request but admin ones, and no topics
+    pub fn all_requests() -> Self {
+        Grant {
+            kinds: (MessageKind::ALL.into_iter())
+                .filter(|kind| !kind.is_admin())
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Also allows requests of `kind`
+    pub fn send(mut self, kind: MessageKind) -> Self {
+        self.kinds.insert(kind);
+        self
+    }
+
+    /// Also allows publishing on the topics `filter` matches
+    pub fn publish(mut self, filter: &str) -> Self {
+        self.publish.push(filter.to_string());
+        self
+    }
+
+    /// Also allows subscribing with `filter` or any filter it covers
+    pub fn subscribe(mut self, filter: &str) -> Self {
+        self.subscribe.push(filter.to_string());
+        self
+    }
+
+    fn allows(&self, action: Action<'_>) -> bool {
+        match action {
+            Action::Send(kind) => self.kinds.contains(&kind),
+            Action::Publish(topic) => self.publish.iter().any(|f| topic::matches(f, topic)),
+            Action::Subscribe(filter) => self.subscribe.iter().any(|f| covers(f, filter)),
+        }
+    }
+}
+
+// Whether every topic `filter` matches is also matched by `allowed`
+fn covers(allowed: &str, filter: &str) -> bool {
+    if topic::validate_filter(allowed).is_err() || topic::validate_filter(filter).is_err() {
+        return false;
+    }
+    let mut levels = filter.split(topic::SEPARATOR);
+    for level in allowed.split(topic::SEPARATOR) {
+        if level == topic::MULTI_LEVEL {
+            return true;
+        }
+        match levels.next() {
+            Some(topic::MULTI_LEVEL) | None => return false,
+            Some(_) if level == topic::SINGLE_LEVEL => {}
+            Some(requested) if requested == level => {}
+            Some(_) => return false,
+        }
+    }
+    levels.next().is_none()
+}
+
+/// Grants fixed when the server starts; an action is allowed if any grant
+/// that applies to the identity allows it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StaticPolicy {
+    everyone: Grant,                    // Applies to every client, named or not
+    identities: HashMap<String, Grant>, // Apply to clients that named themselves so
+}
+
+impl StaticPolicy {
+    /// Allows nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows every client, named or not, what `grant` allows
+    pub fn everyone(mut self, grant: Grant) -> Self {
+        self.everyone = grant;
+        self
+    }
+
+    /// Allows the client named `identity` what `grant` allows, besides what
+    /// everyone is allowed
+    pub fn grant(mut self, identity: &str, grant: Grant) -> Self {
+        self.identities.insert(identity.to_string(), grant);
+        self
+    }
+}
+
+impl Authorizer for StaticPolicy {
+    fn authorize(&self, identity: Option<&str>, action: Action<'_>) -> bool {
+        self.everyone.allows(action)
+            || identity
+                .and_then(|identity| self.identities.get(identity))
+                .is_some_and(|grant| grant.allows(action))
+    }
+}
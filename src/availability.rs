@@ -0,0 +1,177 @@
+//! Uptime and availability of a server, for SLA reports.
+//!
+//! A running server counts the time it could not serve as it should:
+//!
+//! - **Accept stalls**: the accept loop normally turns at least every 100 ms.
+//!   A gap of [`STALL_THRESHOLD`] or more, such as while every worker is busy
+//!   and the queue of accepted connections is full, is counted with its length.
+//! - **Overload periods**: from the first turn of the accept loop that finds
+//!   the server overloaded (as [`Server::health`] reports it: too many
+//!   connections waiting for a worker, or the overload policy shedding load) to
+//!   the first that does not.
+//!
+//! [`Availability`] sums these up with the uptime. [`Stats::availability`]
+//! holds it, and an `AvailabilityRequest` gets it as an `AvailabilityReport`.
+//!
+//! Restarts are only known to a server given a file to count its starts in,
+//! with [`Server::persist_availability`]. The file holds the number of starts
+//! as a decimal number and is rewritten on each start.
+//!
+//! [`Server::health`]: crate::server::Server::health
+//! [`Server::persist_availability`]: crate::server::Server::persist_availability
+//! [`Stats::availability`]: crate::stats::Stats::availability
+
+use crate::message::AvailabilityReport;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Shortest gap between two turns of the accept loop counted as a stall
+pub const STALL_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// How available a server has been since it started running
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Availability {
+    /// Time since `run()` was called; zero before
+    pub uptime: Duration,
+    /// When `run()` was called, in milliseconds since the Unix epoch
+    pub started_at_ms: u64,
+    /// Times the accept loop stalled
+    pub accept_stalls: u64,
+    /// Total length of the stalls
+    pub stalled: Duration,
+    /// Times the server became overloaded, including a period still going on
+    pub overload_periods: u64,
+    /// Total length of the overload periods so far
+    pub overloaded: Duration,
+    /// Starts before this one, if the server counts them
+    pub restarts: Option<u64>,
+}
+
+impl Availability {
+    /// Share of the uptime neither stalled nor overloaded, from 0 to 1; 1
+    /// before the server starts. Stalls during overload count once.
+    pub fn ratio(&self) -> f64 {
+        if self.uptime.is_zero() {
+            return 1.0;
+        }
+        let lost = self.stalled.max(self.overloaded).min(self.uptime);
+        1.0 - lost.as_secs_f64() / self.uptime.as_secs_f64()
+    }
+}
+
+impl From<&Availability> for AvailabilityReport {
+    fn from(availability: &Availability) -> Self {
+        AvailabilityReport {
+            uptime_ms: availability.uptime.as_millis() as u64,
+            started_at_ms: availability.started_at_ms,
+            accept_stalls: availability.accept_stalls,
+            stalled_ms: availability.stalled.as_millis() as u64,
+            overload_periods: availability.overload_periods,
+            overloaded_ms: availability.overloaded.as_millis() as u64,
+            persisted: availability.restarts.is_some(),
+            restarts: availability.restarts.unwrap_or(0),
+            availability: availability.ratio(),
+        }
+    }
+}
+
+// What the server counts towards its `Availability`
+#[derive(Debug, Default)]
+pub(crate) struct Tracker {
+    started: OnceLock<(Instant, SystemTime)>, // Set by the first `run()`
+    stalls: AtomicU64,
+    stalled_micros: AtomicU64,
+    overload_periods: AtomicU64,
+    overloaded_micros: AtomicU64, // Of the periods that have ended
+    overload_since: Mutex<Option<Instant>>, // Start of the current period
+    restarts: Mutex<Option<(u64, PathBuf)>>, // And the file they are counted in
+}
+
+impl Tracker {
+    pub(crate) fn start(&self) {
+        self.started
+            .get_or_init(|| (Instant::now(), SystemTime::now()));
+    }
+
+    // Counts a stall if the accept loop took `gap` to turn
+    pub(crate) fn turned_after(&self, gap: Duration) {
+        if gap >= STALL_THRESHOLD {
+            self.stalls.fetch_add(1, Ordering::Relaxed);
+            let micros = gap.as_micros() as u64;
+            self.stalled_micros.fetch_add(micros, Ordering::Relaxed);
+        }
+    }
+
+    // Starts or ends an overload period as the server is found `overloaded` or not
+    pub(crate) fn overloaded(&self, overloaded: bool) {
+        let mut since = self.overload_since.lock().unwrap();
+        match (*since, overloaded) {
+            (None, true) => {
+                *since = Some(Instant::now());
+                self.overload_periods.fetch_add(1, Ordering::Relaxed);
+            }
+            (Some(start), false) => {
+                *since = None;
+                let micros = start.elapsed().as_micros() as u64;
+                self.overloaded_micros.fetch_add(micros, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+    }
+
+    // Counts this start in `path`, returning the starts before it
+    pub(crate) fn persist(&self, path: &Path) -> io::Result<u64> {
+        let restarts = match fs::read_to_string(path) {
+            Ok(text) => text.trim().parse().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} does not hold a count of starts", path.display()),
+                )
+            })?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        fs::write(path, format!("{}\n", restarts + 1))?;
+        *self.restarts.lock().unwrap() = Some((restarts, path.to_path_buf()));
+        Ok(restarts)
+    }
+
+    // The file starts are counted in, if any
+    pub(crate) fn file(&self) -> Option<PathBuf> {
+        let restarts = self.restarts.lock().unwrap();
+        restarts.as_ref().map(|(_, path)| path.clone())
+    }
+
+    pub(crate) fn snapshot(&self) -> Availability {
+        let restarts = self.restarts.lock().unwrap().as_ref().map(|(n, _)| *n);
+        let Some((started, started_at)) = self.started.get() else {
+            return Availability {
+                restarts,
+                ..Default::default()
+            };
+        };
+        let ongoing = self
+            .overload_since
+            .lock()
+            .unwrap()
+            .map(|start| start.elapsed());
+        let ended = Duration::from_micros(self.overloaded_micros.load(Ordering::Relaxed));
+        Availability {
+            uptime: started.elapsed(),
+            started_at_ms: (started_at.duration_since(UNIX_EPOCH))
+                .map_or(0, |since| since.as_millis() as u64),
+            accept_stalls: self.stalls.load(Ordering::Relaxed),
+            stalled: Duration::from_micros(self.stalled_micros.load(Ordering::Relaxed)),
+            overload_periods: self.overload_periods.load(Ordering::Relaxed),
+            overloaded: ended + ongoing.unwrap_or_default(),
+            restarts,
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! Batch envelope.
+//!
+//! A client that holds several requests back for one write may also wrap them
+//! in a single `Batch` message, so they cost one frame and one length prefix
+//! instead of one each. [`pack`] turns the frames waiting to be written into
+//! that frame.
+//!
+//! The server [`unpack`]s a batch as soon as it is read, before flow control
+//! and its priority queue, so each request in it is credited, deduplicated,
+//! authorized, charged and answered on its own, as if it had come in a frame
+//! of its own. Batches do not nest: one found inside another is refused.
+
+use crate::codec::{self, CodecError};
+use crate::message::{client_message, Batch, ClientMessage};
+use alloc::{vec, vec::Vec};
+
+/// Wraps the back-to-back `frames` of several requests in one `Batch` frame.
+///
+/// The batch states the protocol version its first request states. Fails if
+/// a frame cannot be decoded or the batch is too large for one frame, in
+/// which case the frames are sent as they are.
+pub fn pack(mut frames: &[u8]) -> Result<Vec<u8>, CodecError> {
+    let mut messages = Vec::new();
+    while let Some((message, used)) = codec::decode_frame::<ClientMessage>(frames)? {
+        messages.push(message);
+        frames = &frames[used..];
+    }
+    let protocol_version = messages.first().map_or(0, |first| first.protocol_version);
+    codec::encode(&ClientMessage {
+        message: Some(client_message::Message::Batch(Batch { messages })),
+        protocol_version,
+        ..Default::default()
+    })
+}
+
+/// The requests `message` holds, or `message` itself if it is not a batch.
+///
+/// A protocol version stated on the batch is passed to its first request, as
+/// the first request of a connection states it.
+pub fn unpack(message: ClientMessage) -> Vec<ClientMessage> {
+    match message.message {
+        Some(client_message::Message::Batch(batch)) => {
+            let mut messages = batch.messages;
+            if let Some(first) = messages.first_mut() {
+                first.protocol_version = first.protocol_version.max(message.protocol_version);
+            }
+            messages
+        }
+        other => vec![ClientMessage {
+            message: other,
+            ..message
+        }],
+    }
+}
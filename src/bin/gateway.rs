@@ -0,0 +1,127 @@
+//! Runs a server with the default configuration.
+//!
+//! ```text
+//! cargo run --bin gateway -- --addr 0.0.0.0:8080
+//! ```
+//!
+//! With the `windows-service` feature it also runs as a Windows service. From
+//! an administrator prompt, `install` registers it to start at boot with the
+//! given options, `start` and `stop` start and stop it, and `uninstall`
+//! removes it:
+//!
+//! ```text
+//! gateway install --name gateway --addr 0.0.0.0:8080 --grace 30
+//! gateway start --name gateway
+//! ```
+//!
+//! The service control manager starts it with the `service` command. A Stop
+//! drains the server, and closes connections still open after `--grace`
+//! seconds.
+
+use embedded_recruitment_task::server::Server;
+use std::{env, io, process, time::Duration};
+
+const USAGE: &str = "Usage: gateway [run|install|uninstall|start|stop|service] \
+[--addr HOST:PORT] [--name SERVICE] [--grace SECONDS]";
+
+// Command line options
+#[derive(Debug)]
+struct Options {
+    command: String,
+    addr: String,
+    name: String,    // Of the Windows service
+    grace: Duration, // Open connections get to finish when the service stops
+}
+
+impl Options {
+    fn parse(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options {
+            command: "run".to_string(),
+            addr: "0.0.0.0:8080".to_string(),
+            name: "gateway".to_string(),
+            grace: Duration::from_secs(30),
+        };
+
+        let mut args = args.peekable();
+        if let Some(command) = args.next_if(|arg| !arg.starts_with("--")) {
+            options.command = command;
+        }
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for {}", flag))?;
+            match flag.as_str() {
+                "--addr" => options.addr = value,
+                "--name" => options.name = value,
+                "--grace" => {
+                    options.grace = Duration::from_secs(
+                        value
+                            .parse()
+                            .map_err(|_| format!("Invalid value for {}: {}", flag, value))?,
+                    )
+                }
+                _ => return Err(format!("Unknown option {}", flag)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+fn run(options: &Options) -> io::Result<()> {
+    match options.command.as_str() {
+        "run" => Server::new(&options.addr)?.run(),
+        #[cfg(all(feature = "windows-service", windows))]
+        command => service(command, options),
+        #[cfg(not(all(feature = "windows-service", windows)))]
+        "install" | "uninstall" | "start" | "stop" | "service" => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Built without Windows service support",
+        )),
+        #[cfg(not(all(feature = "windows-service", windows)))]
+        command => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Unknown command {}", command),
+        )),
+    }
+}
+
+#[cfg(all(feature = "windows-service", windows))]
+fn service(command: &str, options: &Options) -> io::Result<()> {
+    use embedded_recruitment_task::service;
+    match command {
+        "install" => {
+            let arguments = [
+                "service".to_string(),
+                "--name".to_string(),
+                options.name.clone(),
+                "--addr".to_string(),
+                options.addr.clone(),
+                "--grace".to_string(),
+                options.grace.as_secs().to_string(),
+            ];
+            service::install(&options.name, &options.name, &arguments)
+        }
+        "uninstall" => service::uninstall(&options.name),
+        "start" => service::start(&options.name),
+        "stop" => service::stop(&options.name),
+        "service" => service::run(&options.name, Server::new(&options.addr)?, options.grace),
+        command => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Unknown command {}", command),
+        )),
+    }
+}
+
+fn main() {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(&options) {
+        eprintln!("{} on {} failed: {}", options.command, options.addr, e);
+        process::exit(1);
+    }
+}
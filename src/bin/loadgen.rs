@@ -0,0 +1,267 @@
+//! Load generator for characterizing server capacity.
+//!
+//! Opens `--connections` concurrent clients that together send `--rate`
+//! requests per second for `--duration` seconds, picking each request from the
+//! weighted `--mix`, then prints latency percentiles and error rates:
+//!
+//! ```text
+//! cargo run --release --bin loadgen -- --addr localhost:8080 \
+//!     --connections 32 --rate 5000 --duration 30 --mix echo=3,add=1,ping=1
+//! ```
+
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::message::{
+    client_message, AddRequest, EchoMessage, PingRequest, TelemetryReport,
+};
+use std::{
+    env, process,
+    str::FromStr,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const USAGE: &str = "Usage: loadgen [--addr HOST:PORT] [--connections N] [--rate REQ_PER_SEC] \
+[--duration SECS] [--timeout MS] [--mix echo=W,add=W,ping=W,telemetry=W]";
+
+// Command line options
+#[derive(Debug, Clone)]
+struct Options {
+    host: String,
+    port: u32,
+    connections: usize,
+    rate: f64,             // Requests per second across all connections
+    duration: Duration,    // How long to keep sending
+    timeout: u64,          // Per-request timeout in milliseconds
+    mix: Vec<(Kind, u32)>, // Message kinds and their relative weights
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Echo,
+    Add,
+    Ping,
+    Telemetry,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options {
+            host: "localhost".to_string(),
+            port: 8080,
+            connections: 4,
+            rate: 1000.0,
+            duration: Duration::from_secs(10),
+            timeout: 1000,
+            mix: vec![(Kind::Echo, 1), (Kind::Add, 1)],
+        };
+
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for {}", flag))?;
+            match flag.as_str() {
+                "--addr" => {
+                    let (host, port) = value
+                        .rsplit_once(':')
+                        .ok_or_else(|| format!("Expected HOST:PORT, got {}", value))?;
+                    options.host = host.to_string();
+                    options.port = parse(&flag, port)?;
+                }
+                "--connections" => options.connections = parse(&flag, &value)?,
+                "--rate" => options.rate = parse(&flag, &value)?,
+                "--duration" => options.duration = Duration::from_secs_f64(parse(&flag, &value)?),
+                "--timeout" => options.timeout = parse(&flag, &value)?,
+                "--mix" => options.mix = parse_mix(&value)?,
+                _ => return Err(format!("Unknown option {}", flag)),
+            }
+        }
+
+        if options.connections == 0 || options.rate <= 0.0 {
+            return Err("--connections and --rate must be positive".to_string());
+        }
+        Ok(options)
+    }
+}
+
+fn parse<T: FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", flag, value))
+}
+
+fn parse_mix(value: &str) -> Result<Vec<(Kind, u32)>, String> {
+    let mut mix = Vec::new();
+    for entry in value.split(',') {
+        let (name, weight) = entry.split_once('=').unwrap_or((entry, "1"));
+        let kind = match name {
+            "echo" => Kind::Echo,
+            "add" => Kind::Add,
+            "ping" => Kind::Ping,
+            "telemetry" => Kind::Telemetry,
+            _ => return Err(format!("Unknown message kind {}", name)),
+        };
+        let weight = weight
+            .parse()
+            .map_err(|_| format!("Invalid weight in {}", entry))?;
+        mix.push((kind, weight));
+    }
+    if mix.iter().all(|(_, weight)| *weight == 0) {
+        return Err("--mix needs at least one non-zero weight".to_string());
+    }
+    Ok(mix)
+}
+
+// Picks the request kind for the n-th request by walking the weights, so the
+// mix is exact over every full cycle of the weights
+fn pick(mix: &[(Kind, u32)], n: u64) -> Kind {
+    let total: u64 = mix.iter().map(|(_, weight)| u64::from(*weight)).sum();
+    let mut slot = n % total;
+    for (kind, weight) in mix {
+        if slot < u64::from(*weight) {
+            return *kind;
+        }
+        slot -= u64::from(*weight);
+    }
+    unreachable!("slot is below the total weight")
+}
+
+fn request(kind: Kind, n: u64) -> client_message::Message {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    match kind {
+        Kind::Echo => client_message::Message::EchoMessage(EchoMessage {
+            content: format!("loadgen {}", n),
+        }),
+        Kind::Add => client_message::Message::AddRequest(AddRequest { a: n as i32, b: 1 }),
+        Kind::Ping => client_message::Message::PingRequest(PingRequest { timestamp }),
+        Kind::Telemetry => client_message::Message::TelemetryReport(TelemetryReport {
+            sensor_id: (n % 16) as u32,
+            value: n as f32,
+            timestamp,
+        }),
+    }
+}
+
+// Results gathered by one connection
+#[derive(Debug, Default)]
+struct Report {
+    latencies: Vec<Duration>, // Successful round trips
+    errors: u64,              // Failed sends or receives, including timeouts
+    reconnects: u64,          // Connections re-opened after an error
+}
+
+impl Report {
+    fn merge(&mut self, other: Report) {
+        self.latencies.extend(other.latencies);
+        self.errors += other.errors;
+        self.reconnects += other.reconnects;
+    }
+}
+
+// Sends requests on one connection at a fixed pace until the deadline
+fn run_connection(options: &Options, index: usize, start: Instant) -> Report {
+    let mut report = Report::default();
+    let mut client = Client::new(&options.host, options.port, options.timeout);
+    if let Err(e) = client.connect() {
+        eprintln!("Connection {} failed to connect: {}", index, e);
+        report.errors += 1;
+        return report;
+    }
+
+    // Offset each connection so the combined schedule is evenly spread
+    let interval = Duration::from_secs_f64(options.connections as f64 / options.rate);
+    let mut next = start + interval.mul_f64(index as f64 / options.connections as f64);
+    let mut n = index as u64;
+    while next < start + options.duration {
+        if let Some(wait) = next.checked_duration_since(Instant::now()) {
+            thread::sleep(wait);
+        }
+        next += interval;
+
+        let sent = Instant::now();
+        let result = client
+            .send(request(pick(&options.mix, n), n))
+            .and_then(|_| client.receive());
+        n += options.connections as u64;
+        match result {
+            Ok(_) => report.latencies.push(sent.elapsed()),
+            Err(_) => {
+                // The stream may be out of step with its requests now; start over
+                report.errors += 1;
+                client.disconnect().ok();
+                if client.connect().is_ok() {
+                    report.reconnects += 1;
+                }
+            }
+        }
+    }
+
+    client.disconnect().ok();
+    report
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn main() {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+
+    println!(
+        "Sending {} req/s over {} connections to {}:{} for {:?}",
+        options.rate, options.connections, options.host, options.port, options.duration
+    );
+
+    let start = Instant::now();
+    let workers: Vec<_> = (0..options.connections)
+        .map(|index| {
+            let options = options.clone();
+            thread::spawn(move || run_connection(&options, index, start))
+        })
+        .collect();
+
+    let mut report = Report::default();
+    for worker in workers {
+        report.merge(worker.join().expect("Connection thread panicked"));
+    }
+    let elapsed = start.elapsed();
+
+    report.latencies.sort_unstable();
+    let completed = report.latencies.len() as u64;
+    let total = completed + report.errors;
+    println!(
+        "Completed {} requests in {:.2?} ({:.0} req/s)",
+        completed,
+        elapsed,
+        completed as f64 / elapsed.as_secs_f64()
+    );
+    println!(
+        "Errors: {} ({:.2}%), reconnects: {}",
+        report.errors,
+        if total == 0 {
+            0.0
+        } else {
+            report.errors as f64 * 100.0 / total as f64
+        },
+        report.reconnects
+    );
+    for p in [50.0, 90.0, 99.0, 99.9] {
+        println!("p{:<5} {:>10.2?}", p, percentile(&report.latencies, p));
+    }
+    println!(
+        "max    {:>10.2?}",
+        report.latencies.last().copied().unwrap_or_default()
+    );
+}
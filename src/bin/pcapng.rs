@@ -0,0 +1,47 @@
+//! Converts capture files recorded with `Server::capture_to` into one pcapng file.
+//!
+//! ```text
+//! cargo run --bin pcapng -- traffic.pcapng captures/*.cap
+//! wireshark -X lua_script:wireshark/ert.lua traffic.pcapng
+//! ```
+
+use embedded_recruitment_task::capture::CaptureReader;
+use embedded_recruitment_task::pcapng::PcapngWriter;
+use std::{
+    env,
+    fs::File,
+    io::{self, BufWriter},
+    path::PathBuf,
+    process,
+};
+
+const USAGE: &str = "Usage: pcapng OUTPUT CAPTURE...";
+
+fn run() -> Result<(), String> {
+    let mut args = env::args().skip(1).map(PathBuf::from);
+    let output = args.next().ok_or("Missing output file")?;
+    let captures: Vec<PathBuf> = args.collect();
+    if captures.is_empty() {
+        return Err("No capture files given".to_string());
+    }
+
+    let file = File::create(&output).map_err(|e| format!("{}: {}", output.display(), e))?;
+    let mut writer = PcapngWriter::new(BufWriter::new(file)).map_err(|e| e.to_string())?;
+    for path in &captures {
+        let error = |e: io::Error| format!("{}: {}", path.display(), e);
+        let reader = CaptureReader::open(path).map_err(error)?;
+        let name = reader.peer().to_string();
+        let records = reader.collect::<io::Result<Vec<_>>>().map_err(error)?;
+        let frames = writer.write_connection(&name, &records).map_err(error)?;
+        println!("{}: {} frames from {}", path.display(), frames, name);
+    }
+    writer.into_inner().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}\n{}", e, USAGE);
+        process::exit(2);
+    }
+}
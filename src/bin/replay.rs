@@ -0,0 +1,131 @@
+//! Replays a capture recorded with `Server::capture_to`.
+//!
+//! By default the inbound traffic is fed through the request handler in
+//! process; with `--server` it is sent to a running server instead. Either
+//! way the responses are compared with the ones in the capture:
+//!
+//! ```text
+//! cargo run --bin replay -- captures/000001-127.0.0.1_51234.cap
+//! cargo run --bin replay -- captures/000001-127.0.0.1_51234.cap --server localhost:8080 --realtime
+//! ```
+
+use embedded_recruitment_task::capture::{self, CaptureReader, Direction, Record};
+use embedded_recruitment_task::codec::FrameDecoder;
+use embedded_recruitment_task::message::ServerMessage;
+use std::{
+    env,
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream},
+    path::PathBuf,
+    process, thread,
+    time::{Duration, Instant},
+};
+
+const USAGE: &str = "Usage: replay CAPTURE [--server HOST:PORT] [--realtime]";
+
+// Sends the inbound records to a server and returns everything it answers
+fn replay_to_server(records: &[Record], addr: &str, realtime: bool) -> io::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect(addr)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    // Read concurrently so a server that answers early never blocks on a full socket
+    let mut reader = stream.try_clone()?;
+    let responses = thread::spawn(move || {
+        let mut responses = Vec::new();
+        reader.read_to_end(&mut responses).map(|_| responses)
+    });
+
+    let start = Instant::now();
+    for record in records {
+        if record.direction != Direction::Inbound {
+            continue;
+        }
+        if realtime {
+            if let Some(wait) = record.timestamp.checked_sub(start.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+        stream.write_all(&record.bytes)?;
+    }
+    stream.shutdown(Shutdown::Write)?; // The server answers the rest and closes
+
+    responses.join().expect("Reader thread panicked")
+}
+
+fn messages(bytes: &[u8]) -> Vec<ServerMessage> {
+    let mut decoder = FrameDecoder::new();
+    decoder.extend(bytes);
+    std::iter::from_fn(|| decoder.next_message().ok().flatten()).collect()
+}
+
+fn run() -> Result<bool, String> {
+    let mut args = env::args().skip(1);
+    let path = PathBuf::from(args.next().ok_or("Missing capture file")?);
+    let mut server = None;
+    let mut realtime = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--server" => server = Some(args.next().ok_or("Missing value for --server")?),
+            "--realtime" => realtime = true,
+            _ => return Err(format!("Unknown option {}", arg)),
+        }
+    }
+
+    let reader = CaptureReader::open(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    println!("Capture of {}", reader.peer());
+    let records = reader
+        .collect::<io::Result<Vec<_>>>()
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let recorded: Vec<u8> = records
+        .iter()
+        .filter(|record| record.direction == Direction::Outbound)
+        .flat_map(|record| record.bytes.iter().copied())
+        .collect();
+
+    let replayed = match server {
+        Some(addr) => replay_to_server(&records, &addr, realtime)
+            .map_err(|e| format!("Replay against {} failed: {}", addr, e))?,
+        None => {
+            let replay = capture::replay(&records);
+            if let Some(e) = replay.error {
+                println!("Handler stopped decoding: {}", e);
+            }
+            replay.responses.concat()
+        }
+    };
+
+    println!(
+        "{} records, {} recorded response bytes, {} replayed",
+        records.len(),
+        recorded.len(),
+        replayed.len()
+    );
+    if replayed == recorded {
+        println!("Responses match");
+        return Ok(true);
+    }
+
+    let recorded = messages(&recorded);
+    let replayed = messages(&replayed);
+    for i in 0..recorded.len().max(replayed.len()) {
+        if recorded.get(i) != replayed.get(i) {
+            println!("First difference at response {}:", i);
+            println!("  recorded: {:?}", recorded.get(i));
+            println!("  replayed: {:?}", replayed.get(i));
+            break;
+        }
+    }
+    Ok(false)
+}
+
+fn main() {
+    match run() {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Prints a server's log as it is written, for operators without a shell on
+//! the machine it runs on.
+//!
+//! The server needs a `LogTail` and an authorizer that lets the connection send
+//! `TailLogs`; `--device` names the connection for the authorizer:
+//!
+//! ```text
+//! cargo run --bin tail -- --addr gateway:8080 --device operator-1 \
+//!     --level debug --filter server
+//! ```
+
+use embedded_recruitment_task::client::{Client, Push};
+use embedded_recruitment_task::message::{log_event, LogEvent};
+use std::{env, io, process};
+
+const USAGE: &str = "Usage: tail [--addr HOST:PORT] [--device NAME] \
+[--level error|warn|info|debug|trace] [--filter TEXT]";
+
+// Command line options
+#[derive(Debug)]
+struct Options {
+    host: String,
+    port: u32,
+    device: Option<String>, // Identity to present to the authorizer
+    level: log_event::Level,
+    filter: String,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options {
+            host: "localhost".to_string(),
+            port: 8080,
+            device: None,
+            level: log_event::Level::Info,
+            filter: String::new(),
+        };
+
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for {}", flag))?;
+            match flag.as_str() {
+                "--addr" => {
+                    let (host, port) = value
+                        .rsplit_once(':')
+                        .ok_or_else(|| format!("Expected HOST:PORT, got {}", value))?;
+                    options.host = host.to_string();
+                    options.port = port
+                        .parse()
+                        .map_err(|_| format!("Invalid value for {}: {}", flag, value))?;
+                }
+                "--device" => options.device = Some(value),
+                "--level" => {
+                    options.level = log_event::Level::from_str_name(&value.to_uppercase())
+                        .filter(|level| *level != log_event::Level::Unspecified)
+                        .ok_or_else(|| format!("Invalid value for {}: {}", flag, value))?
+                }
+                "--filter" => options.filter = value,
+                _ => return Err(format!("Unknown option {}", flag)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+fn print(event: &LogEvent) {
+    let level = log_event::Level::from_i32(event.level).unwrap_or(log_event::Level::Unspecified);
+    let mut line = format!(
+        "{} {:5} {}: {}",
+        event.time_ms,
+        level.as_str_name(),
+        event.target,
+        event.message
+    );
+    for field in &event.fields {
+        line.push_str(&format!(" {}={}", field.key, field.value));
+    }
+    println!("{}", line);
+}
+
+fn run(options: &Options) -> io::Result<()> {
+    let mut client = Client::new(&options.host, options.port, 1000);
+    client.connect()?;
+    if let Some(device) = &options.device {
+        client.subscribe(device)?;
+    }
+    let recent = client.tail_logs(options.level, &options.filter)?;
+    eprintln!("{} recent records, then live ones", recent);
+    for push in client.pushes() {
+        match push {
+            Ok(Push::Log(event)) => print(&event),
+            Ok(_) => {}
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(&options) {
+        eprintln!("Tailing {}:{} failed: {}", options.host, options.port, e);
+        process::exit(1);
+    }
+}
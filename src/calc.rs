@@ -0,0 +1,168 @@
+//! Arithmetic expressions evaluated for `CalcRequest`s.
+//!
+//! The grammar is small on purpose: decimal numbers, `+ - * / %`, unary minus
+//! and parentheses, with the usual precedence. Nothing in an expression can
+//! name a variable or call a function, and nesting is capped at [`MAX_DEPTH`],
+//! so evaluating a request from an untrusted device is safe. Like the codec,
+//! this module only depends on `core`.
+
+use core::fmt;
+
+/// Deepest nesting of parentheses and unary operators accepted
+pub const MAX_DEPTH: usize = 64;
+
+/// Why an expression could not be evaluated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CalcError {
+    /// A character the grammar does not allow there, at this byte offset
+    Unexpected(usize),
+    /// The expression ended in the middle of a term
+    UnexpectedEnd,
+    /// Nested deeper than [`MAX_DEPTH`]
+    TooDeep,
+    /// A division or remainder by zero
+    DivisionByZero,
+    /// A value too large to represent
+    Overflow,
+}
+
+impl fmt::Display for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalcError::Unexpected(at) => write!(f, "unexpected character at offset {}", at),
+            CalcError::UnexpectedEnd => write!(f, "unexpected end of expression"),
+            CalcError::TooDeep => write!(f, "nested deeper than {} levels", MAX_DEPTH),
+            CalcError::DivisionByZero => write!(f, "division by zero"),
+            CalcError::Overflow => write!(f, "result out of range"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CalcError {}
+
+/// Evaluates `expression`, such as `"2 * (3 + 4) / -5"`
+pub fn evaluate(expression: &str) -> Result<f64, CalcError> {
+    let mut parser = Parser {
+        input: expression.as_bytes(),
+        at: 0,
+        depth: 0,
+    };
+    let value = parser.sum()?;
+    match parser.peek() {
+        Some(_) => Err(CalcError::Unexpected(parser.at)),
+        None => Ok(value),
+    }
+}
+
+// Recursive descent, one method per precedence level
+struct Parser<'a> {
+    input: &'a [u8],
+    at: usize,    // Offset of the next unread byte
+    depth: usize, // Current nesting, bounded by `MAX_DEPTH`
+}
+
+impl Parser<'_> {
+    // Next byte after any whitespace, without consuming it
+    fn peek(&mut self) -> Option<u8> {
+        while self.input.get(self.at).is_some_and(u8::is_ascii_whitespace) {
+            self.at += 1;
+        }
+        self.input.get(self.at).copied()
+    }
+
+    // Terms joined by `+` and `-`
+    fn sum(&mut self) -> Result<f64, CalcError> {
+        let mut value = self.product()?;
+        loop {
+            let op = match self.peek() {
+                Some(op @ (b'+' | b'-')) => op,
+                _ => return Ok(value),
+            };
+            self.at += 1;
+            let rhs = self.product()?;
+            value = finite(match op {
+                b'+' => value + rhs,
+                _ => value - rhs,
+            })?;
+        }
+    }
+
+    // Factors joined by `*`, `/` and `%`
+    fn product(&mut self) -> Result<f64, CalcError> {
+        let mut value = self.factor()?;
+        loop {
+            let op = match self.peek() {
+                Some(op @ (b'*' | b'/' | b'%')) => op,
+                _ => return Ok(value),
+            };
+            self.at += 1;
+            let rhs = self.factor()?;
+            if op != b'*' && rhs == 0.0 {
+                return Err(CalcError::DivisionByZero);
+            }
+            value = finite(match op {
+                b'*' => value * rhs,
+                b'/' => value / rhs,
+                _ => value % rhs,
+            })?;
+        }
+    }
+
+    // A number, a parenthesized sum, or either behind a sign
+    fn factor(&mut self) -> Result<f64, CalcError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(CalcError::TooDeep);
+        }
+        let value = match self.peek() {
+            Some(b'-') => {
+                self.at += 1;
+                -self.factor()?
+            }
+            Some(b'+') => {
+                self.at += 1;
+                self.factor()?
+            }
+            Some(b'(') => {
+                self.at += 1;
+                let value = self.sum()?;
+                match self.peek() {
+                    Some(b')') => self.at += 1,
+                    Some(_) => return Err(CalcError::Unexpected(self.at)),
+                    None => return Err(CalcError::UnexpectedEnd),
+                }
+                value
+            }
+            Some(b'0'..=b'9' | b'.') => self.number()?,
+            Some(_) => return Err(CalcError::Unexpected(self.at)),
+            None => return Err(CalcError::UnexpectedEnd),
+        };
+        self.depth -= 1;
+        Ok(value)
+    }
+
+    // Digits with at most one decimal point
+    fn number(&mut self) -> Result<f64, CalcError> {
+        let start = self.at;
+        let len = self.input[start..]
+            .iter()
+            .take_while(|&&b| b.is_ascii_digit() || b == b'.')
+            .count();
+        self.at += len;
+        // Only ASCII digits and points were taken, so this is valid UTF-8
+        let digits = core::str::from_utf8(&self.input[start..self.at]).unwrap_or_default();
+        match digits.parse::<f64>() {
+            Ok(value) => finite(value),
+            Err(_) => Err(CalcError::Unexpected(start)),
+        }
+    }
+}
+
+fn finite(value: f64) -> Result<f64, CalcError> {
+    match value.is_finite() {
+        true => Ok(value),
+        false => Err(CalcError::Overflow),
+    }
+}
@@ -0,0 +1,202 @@
+//! Recording and replaying wire traffic.
+//!
+//! A server started with [`Server::capture_to`] writes one capture file per
+//! connection. Each record holds the bytes of one read from the client or one
+//! frame written back, with the time since the connection was accepted.
+//! Inbound records keep the original read boundaries, so partial and packed
+//! frames replay exactly as they arrived. [`replay`] feeds a capture back
+//! through the request handler; the `replay` binary does the same against a
+//! handler or a running server.
+//!
+//! File layout, all integers little-endian:
+//!
+//! ```text
+//! header: b"ERTCAP" version:u16 peer_len:u16 peer:[u8; peer_len]
+//! record: micros:u64 direction:u8 len:u32 bytes:[u8; len]
+//! ```
+//!
+//! [`Server::capture_to`]: crate::server::Server::capture_to
+
+use crate::codec::CodecError;
+use crate::session::{Session, SessionError};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::SocketAddr,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+const MAGIC: &[u8; 6] = b"ERTCAP";
+const VERSION: u16 = 1;
+
+/// Which way a record travelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Bytes read from the client
+    Inbound,
+    /// A frame written to the client
+    Outbound,
+}
+
+/// One captured read or write
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Time since the connection was accepted
+    pub timestamp: Duration,
+    pub direction: Direction,
+    pub bytes: Vec<u8>,
+}
+
+/// Writes records for one connection
+#[derive(Debug)]
+pub struct CaptureWriter<W: Write> {
+    output: W,
+    start: Instant,
+}
+
+impl CaptureWriter<BufWriter<File>> {
+    /// Creates `<dir>/<connection>-<peer>.cap`
+    pub fn create(dir: &Path, connection: u64, peer: SocketAddr) -> io::Result<Self> {
+        let name = format!("{:06}-{}.cap", connection, peer).replace(':', "_");
+        CaptureWriter::new(BufWriter::new(File::create(dir.join(name))?), peer)
+    }
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// Writes the file header; timestamps are measured from now
+    pub fn new(mut output: W, peer: SocketAddr) -> io::Result<Self> {
+        let peer = peer.to_string();
+        output.write_all(MAGIC)?;
+        output.write_all(&VERSION.to_le_bytes())?;
+        output.write_all(&(peer.len() as u16).to_le_bytes())?;
+        output.write_all(peer.as_bytes())?;
+        Ok(CaptureWriter {
+            output,
+            start: Instant::now(),
+        })
+    }
+
+    /// Appends a record and flushes it, so a crash loses nothing already seen
+    pub fn record(&mut self, direction: Direction, bytes: &[u8]) -> io::Result<()> {
+        let micros = self.start.elapsed().as_micros() as u64;
+        self.output.write_all(&micros.to_le_bytes())?;
+        self.output.write_all(&[match direction {
+            Direction::Inbound => 0,
+            Direction::Outbound => 1,
+        }])?;
+        self.output.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        self.output.write_all(bytes)?;
+        self.output.flush()
+    }
+}
+
+/// Reads the records of a capture file
+#[derive(Debug)]
+pub struct CaptureReader<R: Read> {
+    input: R,
+    peer: String,
+}
+
+impl CaptureReader<BufReader<File>> {
+    /// Opens a capture file
+    pub fn open(path: &Path) -> io::Result<Self> {
+        CaptureReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Reads and checks the file header
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut magic = [0; 6];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a capture file"));
+        }
+        if read_u16(&mut input)? != VERSION {
+            return Err(invalid("unsupported capture version"));
+        }
+        let mut peer = vec![0; read_u16(&mut input)? as usize];
+        input.read_exact(&mut peer)?;
+        let peer = String::from_utf8(peer).map_err(|_| invalid("invalid peer address"))?;
+        Ok(CaptureReader { input, peer })
+    }
+
+    /// Address of the client the capture was taken from
+    pub fn peer(&self) -> &str {
+        &self.peer
+    }
+
+    // Reads one record, or `None` at a clean end of file
+    fn next_record(&mut self) -> io::Result<Option<Record>> {
+        let mut micros = [0; 8];
+        match self.input.read_exact(&mut micros) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let mut direction = [0; 1];
+        self.input.read_exact(&mut direction)?;
+        let direction = match direction[0] {
+            0 => Direction::Inbound,
+            1 => Direction::Outbound,
+            _ => return Err(invalid("invalid record direction")),
+        };
+        let mut len = [0; 4];
+        self.input.read_exact(&mut len)?;
+        let mut bytes = vec![0; u32::from_le_bytes(len) as usize];
+        self.input.read_exact(&mut bytes)?;
+        Ok(Some(Record {
+            timestamp: Duration::from_micros(u64::from_le_bytes(micros)),
+            direction,
+            bytes,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+/// Result of feeding a capture's inbound records through the handler
+#[derive(Debug, Default)]
+pub struct Replay {
+    /// Response frames produced, in order
+    pub responses: Vec<Vec<u8>>,
+    /// Set if the inbound bytes stopped decoding, as the server would have reported
+    pub error: Option<CodecError>,
+}
+
+/// Replays inbound records through the protocol and request handler
+pub fn replay<'a>(records: impl IntoIterator<Item = &'a Record>) -> Replay {
+    let mut session = Session::new();
+    let mut replay = Replay::default();
+    for record in records {
+        if record.direction != Direction::Inbound {
+            continue;
+        }
+        match session.feed_bytes(&record.bytes) {
+            Ok(()) => {}
+            Err(SessionError::Codec(e)) => replay.error = Some(e),
+            Err(SessionError::Flow(_)) => return replay, // The server closes the connection here
+        }
+        replay
+            .responses
+            .extend(core::iter::from_fn(|| session.poll_transmit()));
+    }
+    replay
+}
+
+fn read_u16(input: &mut impl Read) -> io::Result<u16> {
+    let mut bytes = [0; 2];
+    input.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
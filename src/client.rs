@@ -0,0 +1,1298 @@
+use crate::close::Disconnected; // Why the server closed the connection
+use crate::codec::Bytes; // Shared payloads
+use crate::connect; // Races the addresses a host resolves to
+use crate::failover::{
+    Endpoint, EndpointSet, DEFAULT_HEALTH_CHECK_INTERVAL, MAX_RECONNECT_BACKOFF,
+    MIN_RECONNECT_BACKOFF,
+}; // Redundant servers
+use crate::handler::MessageKind; // Names requests in logs
+use crate::message::{
+    client_message, error_response, log_event, server_message, transform_request, AddRequest,
+    AvailabilityReport, AvailabilityRequest, CalcRequest, ClientMessage, ConnectionHistoryRequest,
+    ConnectionRecord, DeadLetterRecord, DeadLettersRequest, Delivery, DescribeRequest,
+    DescribeResponse, DiagnosticsReport, DiagnosticsRequest, EchoBytes, EchoMessage, ErrorResponse,
+    FaultRule, FaultRulesRequest, GoAway, LogEvent, PingRequest, Publication, PublishRequest,
+    QuotaRequest, QuotaStatus, RandomRequest, ResumeRequest, ResyncRequest, ServerMessage,
+    SubscribeRequest, TailLogs, TelemetryReport, TransformRequest, UnsubscribeRequest,
+}; // Protobuf message formats
+use crate::proxy::Proxy; // Optional SOCKS5 or HTTP CONNECT tunnel
+use crate::sequence::{Reorderer, FIRST_SEQUENCE}; // Deliveries put back in order
+use crate::spool::Spool; // Messages kept while disconnected
+use crate::transport::Connection; // Framing over the TCP stream
+use log::{error, info, trace, warn}; // Import logging macros
+use prost::Message; // For measuring logged messages
+use std::{
+    collections::VecDeque, // Pushes and responses read ahead of their turn
+    fmt,                   // For describing refused requests
+    io,                    // For input/output operations
+    net::{SocketAddr, TcpStream, ToSocketAddrs}, // For network operations
+    time::{Duration, Instant}, // For timeouts and health checks
+};
+
+// Deliveries held back behind a gap before the client gives up on it
+const REORDER_CAPACITY: usize = 256;
+
+// Typed calls number their requests from here up, clear of the IDs an
+// application gives `send_with_id`
+const FIRST_CALL_ID: u64 = 1 << 63;
+
+// Stream topic subscriptions are renewed on, so their answers are not taken
+// for those of the application's requests
+const RENEWAL_STREAM: u32 = u32::MAX;
+
+// TCP/IP Client
+pub struct Client {
+    endpoints: EndpointSet,
+    current: Option<usize>, // Endpoint of the open connection
+    timeout: Duration,
+    connection: Option<Connection<TcpStream>>,
+    in_flight: usize, // Requests sent and not yet answered; fail-back waits for 0
+    last_health_check: Instant, // When better endpoints were last probed
+    health_check_interval: Duration,
+    proxy: Option<Proxy>, // Tunnel every connection through this proxy
+    moving: Option<Move>, // Reconnect the server asked for with a `GoAway`
+    spool: Option<Spool>, // Keeps what cannot be sent until the client is connected
+    batching: Option<Batching>,
+    held_since: Option<Instant>, // When the oldest request held for a batch was sent
+    subscription: Option<Subscription>, // Device whose messages are pushed to this client
+    topics: Vec<String>, // Filters of the topics subscribed to, renewed unless the session resumes
+    renewing: usize,     // Responses awaited to renewed topic subscriptions
+    pushes: VecDeque<Push>, // Received while waiting for a response
+    responses: VecDeque<ServerMessage>, // Received while waiting for a push
+    disconnected: Option<Disconnected>, // Why the server closed the last connection, if it said
+    next_call: u64,      // Message ID of the next typed call
+}
+
+// The device a client receives messages for, renewed on each connection
+struct Subscription {
+    device: String,
+    token: Vec<u8>,                     // Session to resume on the next connection
+    pending: bool,                      // A `ResumeRequest` awaits its response
+    renew: bool, // Topics are renewed once it comes, unless the session resumed
+    order: Option<Reorderer<Delivery>>, // Started at the first delivery of a session
+    resync: Option<u64>, // End of the range a `ResyncRequest` asked for, until answered
+    replaying: u64, // Deliveries before this are being sent again
+}
+
+/// A message the server sends unasked, rather than in answer to a request
+#[derive(Debug, Clone, PartialEq)]
+pub enum Push {
+    /// A message addressed to the subscribed device, or broadcast to all
+    Delivery(Delivery),
+    /// The server asks the client to reconnect, which it does before its next send
+    GoAway(GoAway),
+    /// A server log record, after a [`Client::tail_logs`] call
+    Log(LogEvent),
+    /// A message published on a topic the client subscribed to with [`Client::subscribe_to`]
+    Publication(Publication),
+}
+
+impl Push {
+    /// Whether `message` is a push rather than a response
+    pub fn is_push(message: &ServerMessage) -> bool {
+        matches!(
+            message.message,
+            Some(
+                server_message::Message::Delivery(_)
+                    | server_message::Message::GoAway(_)
+                    | server_message::Message::LogEvent(_)
+                    | server_message::Message::Publication(_)
+            )
+        )
+    }
+}
+
+impl From<Push> for ServerMessage {
+    fn from(push: Push) -> Self {
+        let message = match push {
+            Push::Delivery(delivery) => server_message::Message::Delivery(delivery),
+            Push::GoAway(go_away) => server_message::Message::GoAway(go_away),
+            Push::Log(event) => server_message::Message::LogEvent(event),
+            Push::Publication(publication) => server_message::Message::Publication(publication),
+        };
+        ServerMessage {
+            message: Some(message),
+            ..Default::default()
+        }
+    }
+}
+
+impl TryFrom<ServerMessage> for Push {
+    type Error = ServerMessage; // A response, handed back
+
+    fn try_from(message: ServerMessage) -> Result<Self, ServerMessage> {
+        match message.message {
+            Some(server_message::Message::Delivery(delivery)) => Ok(Push::Delivery(delivery)),
+            Some(server_message::Message::GoAway(go_away)) => Ok(Push::GoAway(go_away)),
+            Some(server_message::Message::LogEvent(event)) => Ok(Push::Log(event)),
+            Some(server_message::Message::Publication(publication)) => {
+                Ok(Push::Publication(publication))
+            }
+            _ => Err(message),
+        }
+    }
+}
+
+/// How long and how much a client holds requests back to send them together
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Batching {
+    /// Bytes of frames held before they are written
+    pub max_bytes: usize,
+    /// Longest a request is held, checked as later requests are sent
+    pub max_delay: Duration,
+    /// Whether requests written together travel in one `Batch` frame, which
+    /// servers built before it drop unanswered
+    pub envelope: bool,
+}
+
+// Where a `GoAway` sends the client, and when to try
+struct Move {
+    to: Option<usize>, // Alternate endpoint the server named
+    at: Instant,
+    backoff: Duration, // Wait after the next failed attempt
+}
+
+impl Client {
+    pub fn new(ip: &str, port: u32, timeout_ms: u64) -> Self {
+        Self::with_endpoints(vec![Endpoint::new(ip, port)], timeout_ms)
+    }
+
+    // client for redundant servers; see `failover` for how endpoints are chosen
+    pub fn with_endpoints(endpoints: Vec<Endpoint>, timeout_ms: u64) -> Self {
+        Client {
+            endpoints: EndpointSet::new(endpoints),
+            current: None,
+            timeout: Duration::from_millis(timeout_ms),
+            connection: None,
+            in_flight: 0,
+            last_health_check: Instant::now(),
+            health_check_interval: DEFAULT_HEALTH_CHECK_INTERVAL,
+            proxy: None,
+            moving: None,
+            spool: None,
+            batching: None,
+            held_since: None,
+            subscription: None,
+            topics: Vec::new(),
+            renewing: 0,
+            pushes: VecDeque::new(),
+            responses: VecDeque::new(),
+            disconnected: None,
+            next_call: FIRST_CALL_ID,
+        }
+    }
+
+    // reach every endpoint through a proxy, e.g. `Proxy::parse("socks5://proxy:1080")?`;
+    // applies from the next connect
+    pub fn set_proxy(&mut self, proxy: Option<Proxy>) {
+        self.proxy = proxy;
+    }
+
+    // keep messages that cannot be sent, while disconnected or when the
+    // connection breaks, and send them in order once connected; see `spool`
+    pub fn set_spool(&mut self, spool: Option<Spool>) {
+        self.spool = spool;
+        self.send_spooled();
+    }
+
+    // messages waiting in the spool to be sent
+    pub fn spooled(&self) -> usize {
+        self.spool.as_ref().map_or(0, Spool::len)
+    }
+
+    // whether the client has a connection; with a spool, sends succeed without one
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    // hold small requests back and write them together, once `max_bytes` of
+    // them are waiting or the oldest has waited `max_delay`, so a radio wakes
+    // once for several; there is no timer, so `flush` before going to sleep
+    pub fn set_batching(&mut self, batching: Option<Batching>) -> io::Result<()> {
+        self.batching = batching;
+        if let Some(connection) = self.connection.as_mut() {
+            connection.set_batch_bytes(batching.map_or(0, |b| b.max_bytes));
+            connection.set_batch_envelope(batching.is_some_and(|b| b.envelope));
+        }
+        match batching {
+            Some(_) => Ok(()),
+            None => self.flush(),
+        }
+    }
+
+    // write the requests held back for a batch
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.held_since = None;
+        let Some(connection) = self.connection.as_mut() else {
+            return Ok(());
+        };
+        let result = connection.flush();
+        self.check(result.map_err(io::Error::from))
+    }
+
+    // how often better endpoints are probed while on a fallback one
+    pub fn set_health_check_interval(&mut self, interval: Duration) {
+        self.health_check_interval = interval;
+    }
+
+    // why the server closed the connection, if it said so before closing it;
+    // cleared on the next connection. See `close`.
+    pub fn disconnect_reason(&self) -> Option<&Disconnected> {
+        self.disconnected.as_ref()
+    }
+
+    // endpoint the client is connected to
+    pub fn endpoint(&self) -> Option<&Endpoint> {
+        self.current.map(|i| self.endpoints.get(i))
+    }
+
+    // connect the client to the best endpoint that answers
+    pub fn connect(&mut self) -> io::Result<()> {
+        let mut last_error = io::Error::new(io::ErrorKind::InvalidInput, "No endpoints");
+        for i in self.endpoints.candidates() {
+            match self.open(i) {
+                Ok(connection) => {
+                    self.endpoints.mark_up(i);
+                    self.use_connection(i, connection);
+                    info!("Connected to the server!");
+                    return Ok(());
+                }
+                Err(e) => {
+                    warn!("Failed to connect to {:?}: {}", self.endpoints.get(i), e);
+                    self.endpoints.mark_down(i, Instant::now());
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    // disconnect the client
+    pub fn disconnect(&mut self) -> io::Result<()> {
+        if let Err(e) = self.flush() {
+            warn!("Failed to write held requests: {}", e);
+        }
+        self.current = None;
+        self.in_flight = 0;
+        self.moving = None;
+        if let Some(connection) = self.connection.take() {
+            connection.get_ref().shutdown(std::net::Shutdown::Both)?;
+        }
+
+        info!("Disconnected from the server!");
+        Ok(())
+    }
+
+    // generic message to send message to the server
+    pub fn send(&mut self, message: client_message::Message) -> io::Result<()> {
+        self.send_on(0, message)
+    }
+
+    // send on a logical stream; the response comes back on the same stream
+    pub fn send_on(&mut self, stream: u32, message: client_message::Message) -> io::Result<()> {
+        self.send_with_id(stream, 0, message)
+    }
+
+    // send with a message ID; a retry with the same ID gets the first attempt's response
+    pub fn send_with_id(
+        &mut self,
+        stream: u32,
+        message_id: u64,
+        message: client_message::Message,
+    ) -> io::Result<()> {
+        self.move_on();
+        self.fail_back();
+        self.send_spooled(); // Kept messages go first
+        if let Some(spool) = self.spool.as_mut() {
+            if self.connection.is_none() || !spool.is_empty() {
+                info!("Keeping message {} until connected", message_id);
+                spool.push(ClientMessage {
+                    message: Some(message),
+                    message_id,
+                    stream_id: stream,
+                    ..Default::default()
+                });
+                return Ok(());
+            }
+        }
+        if let Some(ref mut connection) = self.connection {
+            info!(
+                "Sending {} {} on stream {} ({} bytes)",
+                MessageKind::of(&message).name(),
+                message_id,
+                stream,
+                message.encoded_len()
+            );
+            trace!("Sending message {}: {:?}", message_id, message);
+            let kept = self.spool.is_some().then(|| message.clone());
+            let result = connection.send_with_id(stream, message_id, message);
+            match (self.check(result.map_err(io::Error::from)), kept) {
+                (Err(e), Some(message)) if Self::broken(&e) => {
+                    warn!("Keeping message {} until reconnected: {}", message_id, e);
+                    if self.endpoints.len() <= 1 {
+                        self.connection = None; // Nothing failed over; the caller reconnects
+                        self.current = None;
+                    }
+                    if let Some(spool) = self.spool.as_mut() {
+                        spool.push(ClientMessage {
+                            message: Some(message),
+                            message_id,
+                            stream_id: stream,
+                            ..Default::default()
+                        });
+                    }
+                    self.send_spooled(); // Onto the endpoint failed over to, if any
+                    return Ok(());
+                }
+                (result, _) => result?,
+            }
+            self.in_flight += 1;
+            self.hold()
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "No active connection",
+            ))
+        }
+    }
+
+    // receive the messages the server addresses to `device`, which names itself
+    // with a `ResumeRequest`; from then on pushes are kept apart from responses,
+    // for `next_push`. Renewed, resuming the session, on every new connection.
+    // Deliveries are handed out in order; any that went missing are asked for
+    // again, and skipped if the server no longer has them.
+    pub fn subscribe(&mut self, device: &str) -> io::Result<()> {
+        self.subscription = Some(Subscription {
+            device: device.to_string(),
+            token: Vec::new(),
+            pending: false,
+            renew: false,
+            order: None,
+            resync: None,
+            replaying: 0,
+        });
+        self.resubscribe();
+        // Wait for the server to confirm; anything else received is kept
+        while self.subscription.as_ref().is_some_and(|s| s.pending) {
+            let Some(message) = self.read(Some(0))? else {
+                continue;
+            };
+            match Push::try_from(message) {
+                Ok(push) => self.pushes.push_back(push),
+                Err(response) => self.responses.push_back(response),
+            }
+        }
+        Ok(())
+    }
+
+    // next message the server sent unasked; responses received meanwhile are
+    // kept for `receive`
+    pub fn next_push(&mut self) -> io::Result<Push> {
+        loop {
+            if let Some(push) = self.pushes.pop_front() {
+                return Ok(push);
+            }
+            let Some(message) = self.read(None)? else {
+                continue;
+            };
+            match Push::try_from(message) {
+                Ok(push) => return Ok(push),
+                Err(response) => self.responses.push_back(response),
+            }
+        }
+    }
+
+    // pushes as they arrive; a read timeout is returned as an error and the
+    // iterator goes on, any other error ends it
+    pub fn pushes(&mut self) -> impl Iterator<Item = io::Result<Push>> + '_ {
+        let mut ended = false;
+        std::iter::from_fn(move || {
+            if ended {
+                return None;
+            }
+            let push = self.next_push();
+            if let Err(e) = &push {
+                ended = Self::broken(e);
+            }
+            Some(push)
+        })
+    }
+
+    // every message from the server as it arrives, responses and pushes alike,
+    // for a device main loop; waits as long as it takes for each. A broken
+    // connection is returned as an error and ends it.
+    pub fn incoming(&mut self) -> impl Iterator<Item = io::Result<ServerMessage>> + '_ {
+        let mut ended = false;
+        std::iter::from_fn(move || {
+            if ended {
+                return None;
+            }
+            let message = loop {
+                match self.next_message(None) {
+                    Err(e)
+                        if matches!(
+                            e.kind(),
+                            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                        ) => {}
+                    other => break other,
+                }
+            };
+            ended = message.as_ref().is_err_and(Self::broken);
+            Some(message)
+        })
+    }
+
+    // like `incoming`, but returns a `TimedOut` error whenever nothing arrives
+    // within `timeout` and goes on, so the loop can do other work meanwhile
+    pub fn incoming_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> impl Iterator<Item = io::Result<ServerMessage>> + '_ {
+        let mut ended = false;
+        std::iter::from_fn(move || {
+            if ended {
+                return None;
+            }
+            let message = self
+                .next_message(Some(timeout))
+                .map_err(|e| match e.kind() {
+                    io::ErrorKind::WouldBlock => io::Error::new(io::ErrorKind::TimedOut, e),
+                    _ => e,
+                });
+            ended = message.as_ref().is_err_and(Self::broken);
+            Some(message)
+        })
+    }
+
+    // Next message of any kind, oldest kept first, reading for up to `timeout`
+    // instead of the client's own
+    fn next_message(&mut self, timeout: Option<Duration>) -> io::Result<ServerMessage> {
+        if let Some(response) = self.responses.pop_front() {
+            return Ok(response);
+        }
+        if let Some(push) = self.pushes.pop_front() {
+            return Ok(push.into());
+        }
+        let set_timeout = |client: &Self, timeout| match &client.connection {
+            Some(connection) => connection.get_ref().set_read_timeout(Some(timeout)),
+            None => Ok(()),
+        };
+        if let Some(timeout) = timeout {
+            set_timeout(self, timeout)?;
+        }
+        let message = loop {
+            match self.read(None) {
+                Ok(None) => match self.pushes.pop_front() {
+                    Some(push) => break Ok(push.into()), // A delivery, now in order
+                    None => continue,                    // A renewed subscription, taken in
+                },
+                Ok(Some(message)) => break Ok(message),
+                Err(e) => break Err(e),
+            }
+        };
+        if timeout.is_some() {
+            set_timeout(self, self.timeout)?;
+        }
+        message
+    }
+
+    // next response on any stream; a `GoAway` pushed by the server is returned
+    // too, unless subscribed, and the client reconnects as it asks before a
+    // later send
+    pub fn receive(&mut self) -> io::Result<ServerMessage> {
+        self.receive_from(None)
+    }
+
+    // next response on `stream`; responses for other streams are kept for later
+    pub fn receive_on(&mut self, stream: u32) -> io::Result<ServerMessage> {
+        self.receive_from(Some(stream))
+    }
+
+    // sends `content` to be echoed and returns the echo
+    pub fn echo(&mut self, content: &str) -> io::Result<String> {
+        let request = client_message::Message::EchoMessage(EchoMessage {
+            content: content.to_string(),
+        });
+        match self.call(request)? {
+            server_message::Message::EchoMessage(echo) => Ok(echo.content),
+            other => Err(Self::unexpected(other)),
+        }
+    }
+
+    // like `echo`, for binary data
+    pub fn echo_bytes(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let request = client_message::Message::EchoBytes(EchoBytes {
+            data: data.to_vec(),
+        });
+        match self.call(request)? {
+            server_message::Message::EchoBytes(echo) => Ok(echo.data),
+            other => Err(Self::unexpected(other)),
+        }
+    }
+
+    // asks the server for the sum of `a` and `b`
+    pub fn add(&mut self, a: i32, b: i32) -> io::Result<i32> {
+        match self.call(client_message::Message::AddRequest(AddRequest { a, b }))? {
+            server_message::Message::AddResponse(response) => Ok(response.result),
+            other => Err(Self::unexpected(other)),
+        }
+    }
+
+    // pings the server and returns the round-trip time
+    pub fn ping(&mut self) -> io::Result<Duration> {
+        let started = Instant::now();
+        let request = client_message::Message::PingRequest(PingRequest { timestamp: 0 });
+        match self.call(request)? {
+            server_message::Message::PingResponse(_) => Ok(started.elapsed()),
+            other => Err(Self::unexpected(other)),
+        }
+    }
+
+    // reports a sensor reading and waits for the server to acknowledge it
+    pub fn report(&mut self, sensor_id: u32, value: f32, timestamp: u64) -> io::Result<()> {
+        let request = client_message::Message::TelemetryReport(TelemetryReport {
+            sensor_id,
+            value,
+            timestamp,
+        });
+        match self.call(request)? {
+            server_message::Message::TelemetryAck(_) => Ok(()),
+            other => Err(Self::unexpected(other)),
+        }
+    }
+
+    // asks the server to apply `op` to `content`
+    pub fn transform(&mut self, content: &str, op: transform_request::Op) -> io::Result<String> {
+        let request = client_message::Message::TransformRequest(TransformRequest {
+            content: content.to_string(),
+            op: op as i32,
+        });
+        match self.call(request)? {
+            server_message::Message::TransformResponse(response) => Ok(response.content),
+            other => Err(Self::unexpected(other)),
+        }
+    }
+
+    // asks the server for `num_bytes` random bytes
+    pub fn random(&mut self, num_bytes: u32) -> io::Result<Vec<u8>> {
+        let request = client_message::Message::RandomRequest(RandomRequest { num_bytes });
+        match self.call(request)? {
+            server_message::Message::RandomResponse(response) => Ok(response.data),
+            other => Err(Self::unexpected(other)),
+        }
+    }
+
+    // asks the server to evaluate an arithmetic expression
+    pub fn calc(&mut self, expression: &str) -> io::Result<f64> {
+        let request = client_message::Message::CalcRequest(CalcRequest {
+            expression: expression.to_string(),
+        });
+        match self.call(request)? {
+            server_message::Message::CalcResponse(response) => Ok(response.result),
+            other => Err(Self::unexpected(other)),
+        }
+    }
+
+    // asks what the server supports
+    pub fn describe(&mut self) -> io::Result<DescribeResponse> {
+        match self.call(client_message::Message::DescribeRequest(DescribeRequest {}))? {
+            server_message::Message::DescribeResponse(response) => Ok(response),
+            other => Err(Self::unexpected(other)),
+        }
+    }
+
+    // asks the server to run its self-checks; see `diagnostics`
+    pub fn diagnostics(&mut self) -> io::Result<DiagnosticsReport> {
+        match self.call(client_message::Message::DiagnosticsRequest(
+            DiagnosticsRequest {},
+        ))? {
+            server_message::Message::DiagnosticsReport(report) => Ok(report),
+            other => Err(Self::unexpected(other)),
+        }
+    }
+
+    // asks how available the server has been since it started
+    pub fn availability(&mut self) -> io::Result<AvailabilityReport> {
+        match self.call(client_message::Message::AvailabilityRequest(
+            AvailabilityRequest {},
+        ))? {
+            server_message::Message::AvailabilityReport(report) => Ok(report),
+            other => Err(Self::unexpected(other)),
+        }
+    }
+
+    // asks for the last `limit` closed connections of `device`, or of every
+    // client if empty, newest first; an admin request, see `authz`
+    pub fn connection_history(
+        &mut self,
+        device: &str,
+        limit: u32,
+    ) -> io::Result<Vec<ConnectionRecord>> {
+        let request = client_message::Message::ConnectionHistoryRequest(ConnectionHistoryRequest {
+            device: device.to_string(),
+            limit,
+        });
+        match self.call(request)? {
+            server_message::Message::ConnectionHistoryResponse(response) => {
+                Ok(response.connections)
+            }
+            other => Err(Self::unexpected(other)),
+        }
+    }
+
+    // asks for the last `limit` messages the server could not deliver to
+    // `device`, or to any device if empty, newest first; an admin request, see
+    // `authz`
+    pub fn dead_letters(&mut self, device: &str, limit: u32) -> io::Result<Vec<DeadLetterRecord>> {
+        let request = client_message::Message::DeadLettersRequest(DeadLettersRequest {
+            device: device.to_string(),
+            limit,
+        });
+        match self.call(request)? {
+            server_message::Message::DeadLettersResponse(response) => Ok(response.letters),
+            other => Err(Self::unexpected(other)),
+        }
+    }
+
+    // removes every fault rule of a server that injects faults if `clear`,
+    // then adds `add`, returning the rules in effect; an admin request, see
+    // `authz`
+    pub fn fault_rules(&mut self, clear: bool, add: Vec<FaultRule>) -> io::Result<Vec<FaultRule>> {
+        let request = client_message::Message::FaultRulesRequest(FaultRulesRequest { clear, add });
+        match self.call(request)? {
+            server_message::Message::FaultRulesResponse(response) => Ok(response.rules),
+            other => Err(Self::unexpected(other)),
+        }
+    }
+
+    // asks how much of its daily quota the device has used
+    pub fn quota(&mut self) -> io::Result<QuotaStatus> {
+        match self.call(client_message::Message::QuotaRequest(QuotaRequest {}))? {
+            server_message::Message::QuotaStatus(status) => Ok(status),
+            other => Err(Self::unexpected(other)),
+        }
+    }
+
+    // streams the server's log records at `level` or more severe whose target
+    // or message contains `filter`, as `Push::Log` from `next_push`; returns
+    // how many of them were recent records logged before the call
+    pub fn tail_logs(&mut self, level: log_event::Level, filter: &str) -> io::Result<u32> {
+        let request = TailLogs {
+            level: level as i32,
+            filter: filter.to_string(),
+        };
+        match self.call(client_message::Message::TailLogs(request))? {
+            server_message::Message::TailLogsResponse(response) => Ok(response.recent),
+            other => Err(Self::unexpected(other)),
+        }
+    }
+
+    // receive the messages published on topics `filter` matches, as
+    // `Push::Publication` from `next_push`; renewed on every new connection.
+    // Returns how many retained messages follow, ahead of any new ones.
+    pub fn subscribe_to(&mut self, filter: &str) -> io::Result<u32> {
+        let request = SubscribeRequest {
+            filter: filter.to_string(),
+        };
+        match self.call(client_message::Message::SubscribeRequest(request))? {
+            server_message::Message::SubscribeResponse(response) => {
+                if !self.topics.iter().any(|topic| topic == filter) {
+                    self.topics.push(filter.to_string());
+                }
+                Ok(response.retained)
+            }
+            other => Err(Self::unexpected(other)),
+        }
+    }
+
+    // stop receiving the messages `filter` matches; returns whether the
+    // connection was subscribed with it
+    pub fn unsubscribe_from(&mut self, filter: &str) -> io::Result<bool> {
+        self.topics.retain(|topic| topic != filter);
+        let request = UnsubscribeRequest {
+            filter: filter.to_string(),
+        };
+        match self.call(client_message::Message::UnsubscribeRequest(request))? {
+            server_message::Message::UnsubscribeResponse(response) => Ok(response.subscribed),
+            other => Err(Self::unexpected(other)),
+        }
+    }
+
+    // publish `payload` on `topic`; returns how many subscribers it was sent to
+    pub fn publish(&mut self, topic: &str, payload: impl Into<Bytes>) -> io::Result<u32> {
+        self.publish_with(topic, payload.into(), false)
+    }
+
+    // publish `payload` on `topic` and have the server keep it for later
+    // subscribers; an empty payload removes the message kept on the topic
+    pub fn publish_retained(&mut self, topic: &str, payload: impl Into<Bytes>) -> io::Result<u32> {
+        self.publish_with(topic, payload.into(), true)
+    }
+
+    fn publish_with(&mut self, topic: &str, payload: Bytes, retain: bool) -> io::Result<u32> {
+        let request = PublishRequest {
+            topic: topic.to_string(),
+            payload,
+            retain,
+        };
+        match self.call(client_message::Message::PublishRequest(request))? {
+            server_message::Message::PublishResponse(response) => Ok(response.subscribers),
+            other => Err(Self::unexpected(other)),
+        }
+    }
+
+    // Sends `request` on stream 0 and waits for its response, keeping pushes
+    // received meanwhile for `next_push`. The request carries an ID of its own,
+    // so a late response to an earlier call that timed out is dropped rather
+    // than taken for this one's; a server that does not echo IDs answers with 0.
+    // An `ErrorResponse` becomes an error that carries it.
+    fn call(&mut self, request: client_message::Message) -> io::Result<server_message::Message> {
+        let id = self.next_call;
+        self.next_call = self.next_call.checked_add(1).unwrap_or(FIRST_CALL_ID);
+        self.send_with_id(0, id, request)?;
+        loop {
+            let message = self.receive_on(0)?;
+            match Push::try_from(message) {
+                Ok(push) => self.pushes.push_back(push),
+                Err(response) if response.message_id != id && response.message_id != 0 => {
+                    warn!("Dropping a late response to call {}", response.message_id)
+                }
+                Err(response) => match response.message {
+                    Some(server_message::Message::ErrorResponse(refusal)) => {
+                        return Err(io::Error::new(refusal.kind(), refusal))
+                    }
+                    Some(message) => return Ok(message),
+                    None => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "Response without a message",
+                        ))
+                    }
+                },
+            }
+        }
+    }
+
+    fn unexpected(message: server_message::Message) -> io::Error {
+        error!("Unexpected response: {:?}", message);
+        io::Error::new(io::ErrorKind::InvalidData, "Unexpected response type")
+    }
+
+    fn receive_from(&mut self, stream: Option<u32>) -> io::Result<ServerMessage> {
+        let wanted = |message: &ServerMessage| stream.is_none_or(|id| message.stream_id == id);
+        if let Some(i) = self.responses.iter().position(wanted) {
+            return Ok(self.responses.remove(i).expect("Position is in range"));
+        }
+        loop {
+            let Some(message) = self.read(stream)? else {
+                continue;
+            };
+            if self.subscription.is_none() || !Push::is_push(&message) {
+                return Ok(message);
+            }
+            if let Ok(push) = Push::try_from(message) {
+                self.pushes.push_back(push); // For `next_push`
+            }
+        }
+    }
+
+    // Reads the next message on `stream`, or on any stream; `None` if it was
+    // the response to a renewed subscription, which is taken in here
+    fn read(&mut self, stream: Option<u32>) -> io::Result<Option<ServerMessage>> {
+        let Some(ref mut connection) = self.connection else {
+            error!("No active connection");
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "No active connection",
+            ));
+        };
+        trace!("Receiving message from the server");
+        let result = match stream {
+            Some(stream) => connection.receive_on(stream),
+            None => connection.receive(),
+        };
+        self.held_since = None; // Written before reading
+        let message = self.check(result.map_err(io::Error::from))?;
+        info!(
+            "Received {} {} on stream {} ({} bytes)",
+            message
+                .message
+                .as_ref()
+                .map_or("empty message", response_name),
+            message.message_id,
+            message.stream_id,
+            message.encoded_len()
+        );
+        trace!("Received message {}: {:?}", message.message_id, message);
+        match &message.message {
+            Some(server_message::Message::Close(close)) => {
+                let disconnected = Disconnected::from(close.clone());
+                warn!("{}", disconnected);
+                self.disconnected = Some(disconnected.clone());
+                return self.check(Err(disconnected.into())); // Fails over like a lost connection
+            }
+            Some(server_message::Message::GoAway(go_away)) => self.follow(go_away),
+            Some(server_message::Message::Delivery(delivery))
+                if self.subscription.is_some() && delivery.sequence >= FIRST_SEQUENCE =>
+            {
+                self.reorder(delivery.clone());
+                return Ok(None); // Kept for `next_push` once its turn comes
+            }
+            Some(server_message::Message::ResyncResponse(response))
+                if self
+                    .subscription
+                    .as_ref()
+                    .is_some_and(|s| s.resync.is_some()) =>
+            {
+                self.in_flight = self.in_flight.saturating_sub(1);
+                if let Some(subscription) = self.subscription.as_mut() {
+                    subscription.replaying = subscription.resync.take().unwrap_or_default();
+                    if let Some(order) = subscription.order.as_mut() {
+                        order.skip_to(response.first); // Those before it are lost
+                    }
+                }
+                self.release();
+                return Ok(None); // Answers the client's own request
+            }
+            Some(
+                server_message::Message::Delivery(_)
+                | server_message::Message::LogEvent(_)
+                | server_message::Message::Publication(_),
+            ) => {}
+            Some(server_message::Message::ResumeResponse(response)) => {
+                self.in_flight = self.in_flight.saturating_sub(1);
+                if let Some(subscription) = self.subscription.as_mut().filter(|s| s.pending) {
+                    subscription.pending = false;
+                    subscription.token = response.token.clone();
+                    let renew = std::mem::take(&mut subscription.renew);
+                    if !response.resumed {
+                        subscription.order = None; // Numbered afresh, perhaps by another server
+                        if renew {
+                            self.renew_topics(); // As the device named, for the authorizer
+                        }
+                    }
+                    return Ok(None); // Answers the client's own request
+                }
+            }
+            Some(
+                server_message::Message::SubscribeResponse(_)
+                | server_message::Message::ErrorResponse(_),
+            ) if self.renewing > 0 && message.stream_id == RENEWAL_STREAM => {
+                self.in_flight = self.in_flight.saturating_sub(1);
+                self.renewing -= 1;
+                if let Some(server_message::Message::ErrorResponse(refusal)) = &message.message {
+                    warn!("Failed to renew a topic subscription: {}", refusal.detail);
+                }
+                return Ok(None); // Answers the client's own request
+            }
+            _ => self.in_flight = self.in_flight.saturating_sub(1),
+        }
+        Ok(Some(message))
+    }
+
+    // Resolves and connects to one endpoint
+    fn open(&self, index: usize) -> io::Result<Connection<TcpStream>> {
+        let endpoint = self.endpoints.get(index);
+        info!("Connecting to {}:{}", endpoint.host, endpoint.port);
+
+        let stream = match &self.proxy {
+            // The proxy resolves the host itself
+            Some(proxy) => {
+                let port = u16::try_from(endpoint.port)
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid port"))?;
+                proxy.connect(&endpoint.host, port, self.timeout)?
+            }
+            None => {
+                // Resolve the address
+                let address = format!("{}:{}", endpoint.host, endpoint.port);
+                let socket_addrs: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+
+                if socket_addrs.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Invalid IP or port",
+                    ));
+                }
+
+                // Race the resolved addresses, so one unreachable address does not fail the connect
+                connect::connect(&socket_addrs, self.timeout)?
+            }
+        };
+        stream.set_read_timeout(Some(self.timeout))?; // Don't wait forever for a response
+        stream.set_write_timeout(Some(self.timeout))?;
+        let mut connection = Connection::new(stream);
+        connection.set_batch_bytes(self.batching.map_or(0, |b| b.max_bytes));
+        connection.set_batch_envelope(self.batching.is_some_and(|b| b.envelope));
+        Ok(connection)
+    }
+
+    fn use_connection(&mut self, index: usize, connection: Connection<TcpStream>) {
+        if let Some(old) = self.connection.replace(connection) {
+            let _ = old.get_ref().shutdown(std::net::Shutdown::Both); // Nothing is waiting on it
+        }
+        self.current = Some(index);
+        self.in_flight = 0;
+        self.renewing = 0;
+        if let Some(subscription) = self.subscription.as_mut() {
+            (subscription.resync, subscription.replaying) = (None, 0); // Unanswered on the old one
+        }
+        self.last_health_check = Instant::now();
+        self.moving = None; // A new connection answers any `GoAway`
+        self.disconnected = None;
+        self.resubscribe();
+        match self.subscription.as_mut().filter(|s| s.pending) {
+            // The server keeps the subscriptions of a session it resumes
+            Some(subscription) => subscription.renew = true,
+            None => self.renew_topics(),
+        }
+        self.send_spooled();
+    }
+
+    // Names the subscribed device on the connection, resuming its session
+    fn resubscribe(&mut self) {
+        let (Some(subscription), Some(connection)) =
+            (self.subscription.as_mut(), self.connection.as_mut())
+        else {
+            return;
+        };
+        let request = ResumeRequest {
+            token: subscription.token.clone(),
+            device_id: subscription.device.clone(),
+            ..Default::default()
+        };
+        match connection.send(client_message::Message::ResumeRequest(request)) {
+            Ok(()) => {
+                subscription.pending = true;
+                self.in_flight += 1;
+            }
+            Err(e) => warn!("Failed to subscribe as {}: {}", subscription.device, e),
+        }
+    }
+
+    // Takes in a numbered delivery, releasing those now in order to `next_push`
+    fn reorder(&mut self, delivery: Delivery) {
+        let Some(subscription) = self.subscription.as_mut() else {
+            return;
+        };
+        let order = subscription.order.get_or_insert_with(|| {
+            let mut order = Reorderer::new(REORDER_CAPACITY);
+            order.skip_to(delivery.sequence); // Whatever came before is not ours to wait for
+            order
+        });
+        if let Err(overflow) = order.push(delivery.sequence, delivery) {
+            warn!("Giving up on deliveries {:?}", overflow.missing);
+            order.skip_to(overflow.missing.end);
+        }
+        self.release();
+    }
+
+    // Queues the deliveries that are next in order, and asks the server to send
+    // again those missing ahead of them
+    fn release(&mut self) {
+        let Some(subscription) = self.subscription.as_mut() else {
+            return;
+        };
+        let Some(order) = subscription.order.as_mut() else {
+            return;
+        };
+        while let Some(delivery) = order.pop() {
+            self.pushes.push_back(Push::Delivery(delivery));
+        }
+        let Some(missing) = order.missing() else {
+            return;
+        };
+        if subscription.resync.is_some() || missing.start < subscription.replaying {
+            return; // Already asked for
+        }
+        let Some(connection) = self.connection.as_mut() else {
+            return;
+        };
+        let request = ResyncRequest {
+            from: missing.start,
+            to: missing.end,
+        };
+        match connection.send(client_message::Message::ResyncRequest(request)) {
+            Ok(()) => {
+                subscription.resync = Some(missing.end);
+                self.in_flight += 1;
+            }
+            Err(e) => warn!("Failed to ask for deliveries {:?}: {}", missing, e),
+        }
+    }
+
+    // Subscribes the connection to the topics again, without waiting for the
+    // answers; needed on a new connection unless it resumed the session
+    fn renew_topics(&mut self) {
+        let Some(connection) = self.connection.as_mut() else {
+            return;
+        };
+        for filter in &self.topics {
+            let request = SubscribeRequest {
+                filter: filter.clone(),
+            };
+            match connection.send_on(
+                RENEWAL_STREAM,
+                client_message::Message::SubscribeRequest(request),
+            ) {
+                Ok(()) => {
+                    self.renewing += 1;
+                    self.in_flight += 1;
+                }
+                Err(e) => warn!("Failed to subscribe to {}: {}", filter, e),
+            }
+        }
+    }
+
+    // Sends the spooled messages, oldest first, until one fails
+    fn send_spooled(&mut self) {
+        let (Some(spool), Some(connection)) = (self.spool.as_mut(), self.connection.as_mut())
+        else {
+            return;
+        };
+        let (waiting, mut sent) = (spool.len(), 0);
+        while let Some(kept) = spool.front() {
+            let (stream, message_id) = (kept.stream_id, kept.message_id);
+            let Some(message) = kept.message.clone() else {
+                spool.pop_front(); // Nothing to send
+                continue;
+            };
+            match connection.send_with_id(stream, message_id, message) {
+                Ok(()) => sent += 1,
+                Err(e) => {
+                    let e = io::Error::from(e);
+                    if Self::broken(&e) {
+                        warn!("Failed to send spooled messages: {}", e);
+                        break;
+                    }
+                    error!("Dropping spooled message {}: {}", message_id, e); // Would never go
+                }
+            }
+            spool.pop_front();
+        }
+        if sent > 0 {
+            info!("Sent {} spooled messages", sent);
+            self.in_flight += sent;
+        }
+        if spool.len() < waiting {
+            spool.save();
+        }
+    }
+
+    // Writes the held requests once the oldest has waited long enough
+    fn hold(&mut self) -> io::Result<()> {
+        let (Some(batching), Some(connection)) = (self.batching, self.connection.as_ref()) else {
+            return Ok(());
+        };
+        if connection.held() == 0 {
+            self.held_since = None;
+            return Ok(());
+        }
+        let since = *self.held_since.get_or_insert_with(Instant::now);
+        match since.elapsed() >= batching.max_delay {
+            true => self.flush(),
+            false => Ok(()),
+        }
+    }
+
+    // Schedules the reconnect a `GoAway` asks for
+    fn follow(&mut self, go_away: &GoAway) {
+        let alternate = match go_away.alternate_server.as_str() {
+            "" => None,
+            address => {
+                let endpoint = Endpoint::parse(address);
+                if endpoint.is_none() {
+                    warn!("Ignoring alternate server {:?}", address);
+                }
+                endpoint
+            }
+        };
+        // The alternate ranks with the server it replaces
+        let priority = self.endpoint().map_or(0, |current| current.priority);
+        let to = alternate.map(|endpoint| self.endpoints.insert(endpoint.with_priority(priority)));
+        let wait = Duration::from_millis(go_away.reconnect_after_ms);
+        info!(
+            "Server asked to reconnect in {:?} ({:?})",
+            wait,
+            go_away.reason()
+        );
+        self.moving = Some(Move {
+            to,
+            at: Instant::now() + wait,
+            backoff: MIN_RECONNECT_BACKOFF,
+        });
+    }
+
+    // Reconnects as a `GoAway` asked once it is due, between requests only;
+    // stays on the current connection, and backs off, while nothing else answers
+    fn move_on(&mut self) {
+        let Some(moving) = &self.moving else {
+            return;
+        };
+        if self.in_flight > 0 || Instant::now() < moving.at {
+            return;
+        }
+        let (to, backoff) = (moving.to, moving.backoff);
+        let current = self.current;
+        let mut order: Vec<usize> = to.into_iter().collect();
+        order.extend(
+            (self.endpoints.candidates().into_iter())
+                .filter(|&i| Some(i) != to && Some(i) != current),
+        );
+        for i in order {
+            match self.open(i) {
+                Ok(connection) => {
+                    info!("Moving to {:?}", self.endpoints.get(i));
+                    if let Some(current) = current {
+                        self.endpoints.mark_down(current, Instant::now()); // It is going away
+                    }
+                    self.endpoints.mark_up(i);
+                    self.use_connection(i, connection);
+                    return;
+                }
+                Err(e) => {
+                    warn!("Failed to connect to {:?}: {}", self.endpoints.get(i), e);
+                    self.endpoints.mark_down(i, Instant::now());
+                }
+            }
+        }
+        self.moving = Some(Move {
+            to,
+            at: Instant::now() + backoff,
+            backoff: (backoff * 2).min(MAX_RECONNECT_BACKOFF),
+        });
+    }
+
+    // Fails over to another endpoint when the connection broke, then hands the error back;
+    // requests in flight are lost, so the caller decides whether to retry
+    fn check<T>(&mut self, result: io::Result<T>) -> io::Result<T> {
+        let Err(e) = result else {
+            return result;
+        };
+        if Self::broken(&e) && self.endpoints.len() > 1 {
+            if let Some(current) = self.current {
+                warn!("Lost {:?}: {}", self.endpoints.get(current), e);
+                self.endpoints.mark_down(current, Instant::now());
+                if let Err(reconnect) = self.connect() {
+                    error!("No endpoint to fail over to: {}", reconnect);
+                    self.connection = None;
+                    self.current = None;
+                }
+            }
+        }
+        Err(e)
+    }
+
+    // Whether `e` means the connection is gone, rather than slow or sent garbage
+    fn broken(e: &io::Error) -> bool {
+        !matches!(
+            e.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::InvalidData
+        )
+    }
+
+    // Moves back to a more preferred endpoint once it answers a ping, between requests only
+    fn fail_back(&mut self) {
+        let Some(current) = self.current else {
+            return;
+        };
+        if self.in_flight > 0 || self.last_health_check.elapsed() < self.health_check_interval {
+            return;
+        }
+        self.last_health_check = Instant::now();
+        for i in self.endpoints.better_than(current) {
+            match self.open(i).and_then(Self::probe) {
+                Ok(connection) => {
+                    info!("Failing back to {:?}", self.endpoints.get(i));
+                    self.endpoints.mark_up(i);
+                    self.use_connection(i, connection);
+                    return;
+                }
+                Err(_) => self.endpoints.mark_down(i, Instant::now()),
+            }
+        }
+    }
+
+    // Health check: the endpoint must answer a ping
+    fn probe(mut connection: Connection<TcpStream>) -> io::Result<Connection<TcpStream>> {
+        connection.send(client_message::Message::PingRequest(PingRequest {
+            timestamp: 0,
+        }))?;
+        match connection.receive()?.message {
+            Some(server_message::Message::PingResponse(_)) => Ok(connection),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Unexpected health check response",
+            )),
+        }
+    }
+}
+
+// Name of the protobuf message a response carries, for logs
+fn response_name(message: &server_message::Message) -> &'static str {
+    match message {
+        server_message::Message::EchoMessage(_) => "EchoMessage",
+        server_message::Message::AddResponse(_) => "AddResponse",
+        server_message::Message::PingResponse(_) => "PingResponse",
+        server_message::Message::TelemetryAck(_) => "TelemetryAck",
+        server_message::Message::WindowUpdate(_) => "WindowUpdate",
+        server_message::Message::ErrorResponse(_) => "ErrorResponse",
+        server_message::Message::EchoBytes(_) => "EchoBytes",
+        server_message::Message::TransformResponse(_) => "TransformResponse",
+        server_message::Message::RandomResponse(_) => "RandomResponse",
+        server_message::Message::CalcResponse(_) => "CalcResponse",
+        server_message::Message::DescribeResponse(_) => "DescribeResponse",
+        server_message::Message::ResumeResponse(_) => "ResumeResponse",
+        server_message::Message::Delivery(_) => "Delivery",
+        server_message::Message::QuotaStatus(_) => "QuotaStatus",
+        server_message::Message::GoAway(_) => "GoAway",
+        server_message::Message::TailLogsResponse(_) => "TailLogsResponse",
+        server_message::Message::LogEvent(_) => "LogEvent",
+        server_message::Message::DiagnosticsReport(_) => "DiagnosticsReport",
+        server_message::Message::AvailabilityReport(_) => "AvailabilityReport",
+        server_message::Message::ConnectionHistoryResponse(_) => "ConnectionHistoryResponse",
+        server_message::Message::Close(_) => "Close",
+        server_message::Message::SubscribeResponse(_) => "SubscribeResponse",
+        server_message::Message::UnsubscribeResponse(_) => "UnsubscribeResponse",
+        server_message::Message::PublishResponse(_) => "PublishResponse",
+        server_message::Message::Publication(_) => "Publication",
+        server_message::Message::ResyncResponse(_) => "ResyncResponse",
+        server_message::Message::ClusterAck(_) => "ClusterAck",
+        server_message::Message::DeadLettersResponse(_) => "DeadLettersResponse",
+        server_message::Message::FaultRulesResponse(_) => "FaultRulesResponse",
+    }
+}
+
+impl ErrorResponse {
+    /// The kind of I/O error the typed client calls return for this refusal
+    pub fn kind(&self) -> io::ErrorKind {
+        match error_response::Code::from_i32(self.code) {
+            Some(error_response::Code::Busy) => io::ErrorKind::ResourceBusy,
+            Some(error_response::Code::Timeout) => io::ErrorKind::TimedOut,
+            Some(error_response::Code::Invalid) => io::ErrorKind::InvalidInput,
+            Some(error_response::Code::Unsupported) => io::ErrorKind::Unsupported,
+            Some(error_response::Code::Forbidden) => io::ErrorKind::PermissionDenied,
+            Some(error_response::Code::QuotaExceeded) => io::ErrorKind::QuotaExceeded,
+            _ => io::ErrorKind::Other,
+        }
+    }
+}
+
+impl fmt::Display for ErrorResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code =
+            error_response::Code::from_i32(self.code).map_or("UNKNOWN", |code| code.as_str_name());
+        write!(f, "Request refused: {}", code)?;
+        if !self.field.is_empty() {
+            write!(f, " ({})", self.field)?;
+        }
+        if !self.detail.is_empty() {
+            write!(f, ": {}", self.detail)?;
+        }
+        if self.retry_after_ms > 0 {
+            write!(f, "; retry after {} ms", self.retry_after_ms)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ErrorResponse {}
@@ -0,0 +1,95 @@
+//! Why a connection closed.
+//!
+//! Before the server closes a connection, it sends a [`Close`] frame naming
+//! the reason, if the connection can still carry it. Then it shuts its side
+//! down:
+//!
+//! | Reason            | When                                                        | Sent |
+//! |-------------------|-------------------------------------------------------------|------|
+//! | `IDLE_TIMEOUT`    | The first frame, or the rest of a frame, is overdue         | Yes  |
+//! | `PROTOCOL_ERROR`  | A frame does not decode, or breaks flow control             | Yes  |
+//! | `AUTH_FAILURE`    | The denial limit was reached                                | Yes  |
+//! | `SERVER_SHUTDOWN` | `Server::stop()` was called                                 | Yes  |
+//! | `KICKED`          | `Server::kick()` named the connection's device              | Yes  |
+//! | `RESOURCE_LIMIT`  | Over the memory limit, or too slow to take its broadcasts   | Yes  |
+//! | `CLIENT_CLOSED`   | The client hung up                                          | No   |
+//! | `CONNECTION_LOST` | Reading or writing failed                                   | No   |
+//!
+//! A client that is too slow to take its broadcasts is cut off at once,
+//! since it would not read the frame either. Otherwise the server waits up to
+//! [`CLOSE_GRACE`] for the frame to go out.
+//!
+//! The client returns the frame as an error of kind
+//! [`ConnectionAborted`](std::io::ErrorKind::ConnectionAborted) carrying a
+//! [`Disconnected`], which [`Disconnected::of`] gets back, and keeps it for
+//! `Client::disconnect_reason()`. The server's connection history records
+//! every reason, including the two it never sends; see [`crate::history`].
+
+use crate::message::{close::Reason, Close};
+use std::{error::Error, fmt, io, time::Duration};
+
+/// Longest the server spends sending the [`Close`] frame
+pub const CLOSE_GRACE: Duration = Duration::from_millis(100);
+
+/// The reason the server gave for closing a connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disconnected {
+    /// What closed it
+    pub reason: Reason,
+    /// What happened, for people reading logs
+    pub detail: String,
+}
+
+impl Disconnected {
+    /// The reason carried by `error`, if the server gave one
+    pub fn of(error: &io::Error) -> Option<&Disconnected> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl From<Close> for Disconnected {
+    fn from(close: Close) -> Self {
+        Disconnected {
+            reason: close.reason(),
+            detail: close.detail,
+        }
+    }
+}
+
+impl From<Disconnected> for io::Error {
+    fn from(disconnected: Disconnected) -> Self {
+        io::Error::new(io::ErrorKind::ConnectionAborted, disconnected)
+    }
+}
+
+impl fmt::Display for Disconnected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Closed by the server ({})", self.reason.as_str_name())?;
+        if !self.detail.is_empty() {
+            write!(f, ": {}", self.detail)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for Disconnected {}
+
+/// Whether the server tells the client about closing for `reason`
+pub fn is_sent(reason: Reason) -> bool {
+    !matches!(
+        reason,
+        Reason::Unspecified | Reason::ClientClosed | Reason::ConnectionLost
+    )
+}
+
+// The reason a connection handler failed with `error`
+#[cfg(feature = "server")]
+pub(crate) fn reason_of(error: &io::Error) -> Reason {
+    match error.kind() {
+        io::ErrorKind::TimedOut => Reason::IdleTimeout,
+        io::ErrorKind::InvalidData => Reason::ProtocolError,
+        io::ErrorKind::PermissionDenied => Reason::AuthFailure,
+        io::ErrorKind::OutOfMemory => Reason::ResourceLimit,
+        _ => Reason::ConnectionLost,
+    }
+}
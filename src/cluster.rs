@@ -0,0 +1,525 @@
+//! Servers sharing topics and devices as one cluster.
+//!
+//! A server given a [`Cluster`] with [`Server::cluster`] is one node of it. It
+//! opens a link to every other node, a client connection that names itself
+//! with the node's name, and sends [`ClusterEvent`]s over it:
+//!
+//! - the filters its devices subscribe to, as the first subscription to each
+//!   starts and the last one ends, so each node knows which others have
+//!   subscribers for a topic;
+//! - each message published on it, to the nodes with subscribers for its
+//!   topic, which hand it to their own subscribers without passing it on.
+//!   Retained messages, and the clearing of one, go to every node, so later
+//!   subscribers anywhere get them;
+//! - each device that names itself on it. [`Server::send_to`] on another node
+//!   queues the message on the node the device last named itself on, so its
+//!   deliveries are numbered in one place, and messages already queued for it
+//!   elsewhere are handed over there.
+//!
+//! A link starts with everything the node shares, and is reopened with it
+//! after a failure, so nodes that restart or lose touch catch up. What a node
+//! learnt from a link is forgotten when the link closes, except where devices
+//! are. Events wait in a queue of their own for each link. Publications that
+//! find it full, or the link down, are dropped; a message for a device whose
+//! node is unreachable is queued where it was sent instead.
+//!
+//! `ClusterEvent` is an admin request, so nodes only take it from identities
+//! an [`Authorizer`](crate::authz::Authorizer) allows, and only with the node
+//! name the link named itself with.
+//!
+//! [`Server::cluster`]: crate::server::Server::cluster
+//! [`Server::send_to`]: crate::server::Server::send_to
+
+use crate::message::{
+    client_message, cluster_event::Event, server_message, ClusterDevice, ClusterEvent,
+    ClusterInterest, ClusterPublish, ClusterSend, ClusterSync, ResumeRequest,
+};
+use crate::share;
+use crate::topic::TopicTrie;
+use crate::transport::Connection;
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, TrySendError};
+use log::{info, warn};
+use prost::bytes::Bytes;
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, ErrorKind},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// Connect and response timeout of the links
+const LINK_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How often a link that is down is reopened
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+// Events waiting for each link before publications are dropped
+const LINK_QUEUE: usize = 1024;
+
+/// The nodes a server shares its topics and devices with
+#[derive(Debug, Clone)]
+pub struct Cluster {
+    node: String,
+    peers: Vec<(String, String)>, // Name and address of each other node
+}
+
+impl Cluster {
+    /// A cluster in which the server is the node `node`
+    pub fn new(node: &str) -> Self {
+        Cluster {
+            node: node.to_string(),
+            peers: Vec::new(),
+        }
+    }
+
+    /// Adds the node named `name`, whose server listens on `addr`
+    pub fn peer(mut self, name: &str, addr: &str) -> Self {
+        self.peers.push((name.to_string(), addr.to_string()));
+        self
+    }
+}
+
+// What a node knows about itself and the others
+#[derive(Default)]
+struct State {
+    interest: HashMap<String, usize>, // Filters subscribed to here, with how many subscriptions
+    devices: HashMap<String, u64>,    // Devices that last named themselves here, with when
+    remote: TopicTrie<String>,        // Names of the other nodes, under their filters
+    filters: HashMap<String, HashSet<String>>, // Of each other node, as in `remote`
+    links: HashMap<String, u64>,      // Connection each other node's events last came on
+    routes: HashMap<String, (String, u64)>, // Devices that last named themselves elsewhere: where and when
+}
+
+impl State {
+    // Everything the node shares, for the start of a link
+    fn sync(&self) -> ClusterSync {
+        ClusterSync {
+            filters: self.interest.keys().cloned().collect(),
+            devices: (self.devices.iter())
+                .map(|(device, named_at_ms)| ClusterDevice {
+                    device: device.clone(),
+                    named_at_ms: *named_at_ms,
+                })
+                .collect(),
+        }
+    }
+
+    // Records that `peer` has subscribers for `filter`
+    fn add_filter(&mut self, peer: &str, filter: String) {
+        if self
+            .filters
+            .entry(peer.to_string())
+            .or_default()
+            .insert(filter.clone())
+        {
+            let _ = self.remote.insert(&filter, peer.to_string()); // Invalid filters match nothing
+        }
+    }
+
+    fn remove_filter(&mut self, peer: &str, filter: &str) {
+        if let Some(filters) = self.filters.get_mut(peer) {
+            if filters.remove(filter) {
+                self.remote.remove(filter, &peer.to_string());
+            }
+        }
+    }
+
+    fn forget(&mut self, peer: &str) {
+        for filter in self.filters.remove(peer).unwrap_or_default() {
+            self.remote.remove(&filter, &peer.to_string());
+        }
+    }
+
+    // Records that `device` named itself on `peer`; whether that is the latest
+    // place it did
+    fn route(&mut self, peer: &str, device: ClusterDevice) -> bool {
+        let here = self.devices.get(&device.device);
+        let there = self.routes.get(&device.device).map(|(_, at)| at);
+        if here
+            .into_iter()
+            .chain(there)
+            .any(|at| *at > device.named_at_ms)
+        {
+            return false;
+        }
+        self.devices.remove(&device.device);
+        let route = (peer.to_string(), device.named_at_ms);
+        self.routes.insert(device.device, route);
+        true
+    }
+}
+
+/// What the server does about an event from another node
+pub(crate) enum Received {
+    /// Hands the message to the subscribers here
+    Publish(ClusterPublish),
+    /// Queues the message for the device here
+    Send(ClusterSend),
+    /// Hands the messages queued here for `devices` to `peer`
+    Moved { peer: String, devices: Vec<String> },
+    /// Nothing more
+    Noted,
+}
+
+// An event waiting for a link, and where its acknowledgement goes
+struct Outgoing {
+    event: Event,
+    ack: Option<Sender<u64>>, // Dropped unanswered if the event does not get through
+}
+
+// The server's side of the cluster
+pub(crate) struct Node {
+    name: String,
+    state: Arc<Mutex<State>>,
+    links: HashMap<String, Sender<Outgoing>>, // To each other node, by name
+}
+
+impl Node {
+    // Starts a link to each other node; they end once the node is dropped
+    pub(crate) fn new(cluster: Cluster) -> Self {
+        let state = Arc::new(Mutex::new(State::default()));
+        let mut links = HashMap::new();
+        for (peer, addr) in cluster.peers {
+            let (sender, events) = crossbeam_channel::bounded(LINK_QUEUE);
+            let link = Link {
+                node: cluster.node.clone(),
+                peer: peer.clone(),
+                addr,
+                state: state.clone(),
+                connection: None,
+            };
+            thread::spawn(move || link.run(events));
+            links.insert(peer, sender);
+        }
+        Node {
+            name: cluster.node,
+            state,
+            links,
+        }
+    }
+
+    // Whether `identity` is another node of the cluster
+    pub(crate) fn is_peer(&self, identity: &str) -> bool {
+        self.links.contains_key(identity)
+    }
+
+    // Counts a subscription here under `key`, a filter that may be shared
+    pub(crate) fn subscribed(&self, key: &str) {
+        let filter = interest(key);
+        let count = {
+            let mut state = self.state.lock().unwrap();
+            let count = state.interest.entry(filter.to_string()).or_default();
+            *count += 1;
+            *count
+        };
+        if count == 1 {
+            self.tell_all(Event::Interest(ClusterInterest {
+                filter: filter.to_string(),
+                subscribed: true,
+            }));
+        }
+    }
+
+    // Ends a subscription here under `key`
+    pub(crate) fn unsubscribed(&self, key: &str) {
+        let filter = interest(key);
+        let left = {
+            let mut state = self.state.lock().unwrap();
+            let Some(count) = state.interest.get_mut(filter) else {
+                return;
+            };
+            *count -= 1;
+            let left = *count;
+            if left == 0 {
+                state.interest.remove(filter);
+            }
+            left
+        };
+        if left == 0 {
+            self.tell_all(Event::Interest(ClusterInterest {
+                filter: filter.to_string(),
+                subscribed: false,
+            }));
+        }
+    }
+
+    // Passes a message published here on `topic` to the nodes with subscribers
+    // for it, or to every node if it is retained
+    pub(crate) fn published(&self, topic: &str, name: &str, payload: Bytes, retain: bool) {
+        let peers: Vec<String> = match retain {
+            true => self.links.keys().cloned().collect(),
+            false => {
+                let state = self.state.lock().unwrap();
+                let mut peers: Vec<String> =
+                    state.remote.matches(topic).into_iter().cloned().collect();
+                peers.sort();
+                peers.dedup(); // One copy per node, however many of its filters match
+                peers
+            }
+        };
+        for peer in peers {
+            let publish = ClusterPublish {
+                topic: topic.to_string(),
+                name: name.to_string(),
+                payload: payload.clone(),
+                retain,
+            };
+            self.tell(&peer, Event::Publish(publish), None);
+        }
+    }
+
+    // Records that `device` named itself here, and tells the other nodes
+    pub(crate) fn named(&self, device: &str) {
+        let named_at_ms = now_ms();
+        {
+            let mut state = self.state.lock().unwrap();
+            state.routes.remove(device);
+            state.devices.insert(device.to_string(), named_at_ms);
+        }
+        self.tell_all(Event::Device(ClusterDevice {
+            device: device.to_string(),
+            named_at_ms,
+        }));
+    }
+
+    // Queues `payload` for `device` on the node it last named itself on, if
+    // that is another one; its sequence number there, or `None` if it is to be
+    // queued here
+    pub(crate) fn send(&self, device: &str, payload: Bytes, ttl: Option<Duration>) -> Option<u64> {
+        let peer = {
+            let state = self.state.lock().unwrap();
+            state.routes.get(device)?.0.clone()
+        };
+        let (ack, acked) = crossbeam_channel::bounded(1);
+        let send = ClusterSend {
+            device: device.to_string(),
+            payload,
+            ttl_ms: ttl.map_or(0, |ttl| ttl.as_millis().clamp(1, u32::MAX as u128) as u32),
+        };
+        self.tell(&peer, Event::Send(send), Some(ack));
+        // A reconnect and a retry may come before the answer
+        acked.recv_timeout(LINK_TIMEOUT * 3).ok()
+    }
+
+    // Updates what the node knows with `event`, which came from another node
+    // on connection `connection`, and returns what is left for the server to do
+    pub(crate) fn receive(&self, connection: u64, event: ClusterEvent) -> Received {
+        let peer = event.node;
+        let mut state = self.state.lock().unwrap();
+        state.links.insert(peer.clone(), connection);
+        match event.event {
+            Some(Event::Sync(sync)) => {
+                state.forget(&peer);
+                for filter in sync.filters {
+                    state.add_filter(&peer, filter);
+                }
+                let devices = (sync.devices.into_iter())
+                    .filter_map(|device| {
+                        let name = device.device.clone();
+                        state.route(&peer, device).then_some(name)
+                    })
+                    .collect();
+                Received::Moved { peer, devices }
+            }
+            Some(Event::Interest(interest)) => {
+                match interest.subscribed {
+                    true => state.add_filter(&peer, interest.filter),
+                    false => state.remove_filter(&peer, &interest.filter),
+                }
+                Received::Noted
+            }
+            Some(Event::Device(device)) => {
+                let name = device.device.clone();
+                match state.route(&peer, device) {
+                    true => Received::Moved {
+                        peer,
+                        devices: vec![name],
+                    },
+                    false => Received::Noted,
+                }
+            }
+            Some(Event::Publish(publish)) => Received::Publish(publish),
+            Some(Event::Send(send)) => Received::Send(send),
+            None => Received::Noted,
+        }
+    }
+
+    // Forgets the filters `peer` sent on `connection`, which closed, unless it
+    // has opened another link since
+    pub(crate) fn closed(&self, peer: &str, connection: u64) {
+        let mut state = self.state.lock().unwrap();
+        if state.links.get(peer) == Some(&connection) {
+            state.links.remove(peer);
+            state.forget(peer);
+        }
+    }
+
+    fn tell_all(&self, event: Event) {
+        for peer in self.links.keys() {
+            self.tell(peer, event.clone(), None);
+        }
+    }
+
+    fn tell(&self, peer: &str, event: Event, ack: Option<Sender<u64>>) {
+        let Some(link) = self.links.get(peer) else {
+            return;
+        };
+        if let Err(TrySendError::Full(_)) = link.try_send(Outgoing { event, ack }) {
+            warn!(
+                "Cluster link from {} to {} is backed up; dropping an event",
+                self.name, peer
+            );
+        }
+    }
+}
+
+// Filter a subscription under `key` needs messages for, without its group if
+// it is shared
+fn interest(key: &str) -> &str {
+    share::parse(key).map_or(key, |(_, filter)| filter)
+}
+
+fn now_ms() -> u64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    since_epoch.as_millis() as u64
+}
+
+// One node's link to another, run on a thread of its own
+struct Link {
+    node: String,
+    peer: String,
+    addr: String,
+    state: Arc<Mutex<State>>,
+    connection: Option<Connection<TcpStream>>, // `None` while down
+}
+
+impl Link {
+    fn run(mut self, events: Receiver<Outgoing>) {
+        self.reopen();
+        loop {
+            match events.recv_timeout(RECONNECT_INTERVAL) {
+                Ok(outgoing) => {
+                    let sequence = self.send(outgoing.event);
+                    if let (Some(ack), Some(sequence)) = (outgoing.ack, sequence) {
+                        let _ = ack.send(sequence);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) if self.connection.is_none() => self.reopen(),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return, // The node is gone
+            }
+        }
+    }
+
+    // Sends `event`, reopening the link once if it has broken; the sequence
+    // number in the answer, or `None` if it did not get through
+    fn send(&mut self, event: Event) -> Option<u64> {
+        for _ in 0..2 {
+            if self.connection.is_none() {
+                self.reopen();
+            }
+            let connection = self.connection.as_mut()?;
+            match exchange(connection, &self.node, event.clone()) {
+                Ok(sequence) => return Some(sequence),
+                Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+                    warn!(
+                        "Cluster node {} refused an event from {}: {}",
+                        self.peer, self.node, e
+                    );
+                    return None;
+                }
+                Err(e) => {
+                    warn!(
+                        "Cluster link from {} to {} failed: {}",
+                        self.node, self.peer, e
+                    );
+                    self.connection = None;
+                }
+            }
+        }
+        None
+    }
+
+    // Opens the link, naming the node and sending what it shares; leaves it
+    // down if that fails
+    fn reopen(&mut self) {
+        match self.open() {
+            Ok(connection) => {
+                info!("Opened cluster link from {} to {}", self.node, self.peer);
+                self.connection = Some(connection);
+            }
+            Err(e) => {
+                // Tried every second while the other node is down, so only logged when it went down
+                if self.connection.take().is_some() {
+                    warn!("Cluster node {} is unreachable: {}", self.peer, e);
+                }
+            }
+        }
+    }
+
+    fn open(&self) -> io::Result<Connection<TcpStream>> {
+        let mut last_error = io::Error::new(ErrorKind::InvalidInput, "No address for the node");
+        let stream = (self.addr.to_socket_addrs()?)
+            .find_map(|addr| {
+                TcpStream::connect_timeout(&addr, LINK_TIMEOUT)
+                    .map_err(|e| last_error = e)
+                    .ok()
+            })
+            .ok_or(last_error)?;
+        stream.set_read_timeout(Some(LINK_TIMEOUT))?;
+        stream.set_write_timeout(Some(LINK_TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        let mut connection = Connection::new(stream);
+        let resume = ResumeRequest {
+            device_id: self.node.clone(), // The identity the other node authorizes
+            ..Default::default()
+        };
+        connection.send(client_message::Message::ResumeRequest(resume))?;
+        answer(&mut connection)?;
+        let sync = self.state.lock().unwrap().sync();
+        exchange(&mut connection, &self.node, Event::Sync(sync))?;
+        Ok(connection)
+    }
+}
+
+// Sends `event` as `node` and waits for the answer
+fn exchange(connection: &mut Connection<TcpStream>, node: &str, event: Event) -> io::Result<u64> {
+    let event = ClusterEvent {
+        node: node.to_string(),
+        event: Some(event),
+    };
+    connection.send(client_message::Message::ClusterEvent(event))?;
+    match answer(connection)? {
+        server_message::Message::ClusterAck(ack) => Ok(ack.sequence),
+        other => Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Unexpected answer to a cluster event: {:?}", other),
+        )),
+    }
+}
+
+// Next answer on the link, skipping what the other node pushes unasked
+fn answer(connection: &mut Connection<TcpStream>) -> io::Result<server_message::Message> {
+    loop {
+        match connection.receive()?.message {
+            Some(server_message::Message::ErrorResponse(error)) => {
+                return Err(io::Error::new(ErrorKind::PermissionDenied, error.detail));
+            }
+            Some(server_message::Message::Close(close)) => {
+                return Err(io::Error::new(ErrorKind::ConnectionAborted, close.detail));
+            }
+            Some(
+                server_message::Message::Delivery(_)
+                | server_message::Message::Publication(_)
+                | server_message::Message::GoAway(_)
+                | server_message::Message::LogEvent(_),
+            )
+            | None => {}
+            Some(answer) => return Ok(answer),
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Wire format shared by the server and every client implementation.
+//!
+//! Each frame on the wire is a protobuf message prefixed by its encoded length
+//! as a varint (the same layout produced by `Message::encode_length_delimited`).
+//! This module only depends on `core` and `alloc`, so firmware targets can reuse
+//! the exact framing logic that the std server and client use.
+//!
+//! [`FrameDecoder`] keeps received bytes in one reference-counted buffer and
+//! splits each frame off it as [`Bytes`] without copying. Decoding a message
+//! from such a frame with [`decode_bytes`] makes its `bytes` fields declared as
+//! shared (the payload of a `Delivery`) views of the same buffer, so forwarding
+//! a payload, or queueing it for many devices, never copies it.
+
+use crate::fixed::{self, FixedError};
+use alloc::vec::Vec;
+use core::fmt;
+use prost::bytes::{Buf, BytesMut};
+use prost::Message;
+
+pub use crate::fixed::MAX_FRAME_SIZE;
+/// Shared, reference-counted bytes, as in the `bytes` crate
+pub use prost::bytes::Bytes;
+
+/// Version of the wire protocol, raised whenever a change would break peers
+/// built against an earlier one; reported in `DescribeResponse`
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Errors produced while framing or decoding messages
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodecError {
+    /// The frame body is larger than `MAX_FRAME_SIZE`
+    FrameTooLarge(usize),
+    /// The length prefix is not a valid varint
+    InvalidLength,
+    /// The frame body is not a valid protobuf message
+    Decode(prost::DecodeError),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::FrameTooLarge(len) => write!(
+                f,
+                "frame of {} bytes exceeds the {} byte limit",
+                len, MAX_FRAME_SIZE
+            ),
+            CodecError::InvalidLength => write!(f, "invalid frame length prefix"),
+            CodecError::Decode(e) => write!(f, "failed to decode message: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for CodecError {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            CodecError::FrameTooLarge(len) => defmt::write!(f, "FrameTooLarge({})", len),
+            CodecError::InvalidLength => defmt::write!(f, "InvalidLength"),
+            CodecError::Decode(e) => defmt::write!(f, "Decode({})", defmt::Display2Format(e)),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CodecError {}
+
+#[cfg(feature = "std")]
+impl From<CodecError> for std::io::Error {
+    fn from(error: CodecError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, error)
+    }
+}
+
+/// Encodes a message into a complete, length-prefixed frame
+pub fn encode<M: Message>(message: &M) -> Result<Vec<u8>, CodecError> {
+    let len = message.encoded_len();
+    if len > MAX_FRAME_SIZE {
+        return Err(CodecError::FrameTooLarge(len));
+    }
+    Ok(message.encode_length_delimited_to_vec())
+}
+
+/// Decodes a message from a frame body (without its length prefix)
+pub fn decode<M: Message + Default>(body: &[u8]) -> Result<M, CodecError> {
+    M::decode(body).map_err(CodecError::Decode)
+}
+
+/// Decodes a message from a shared frame body; its shared `bytes` fields point
+/// into `body` instead of being copied out
+pub fn decode_bytes<M: Message + Default>(body: Bytes) -> Result<M, CodecError> {
+    M::decode(body).map_err(CodecError::Decode)
+}
+
+/// Decodes the frame at the start of `bytes`, returning the message and the number of bytes used.
+///
+/// Returns `None` while the frame is incomplete. Any input, however malformed,
+/// yields a value or an error rather than a panic, which makes this the entry
+/// point used by the fuzz targets.
+pub fn decode_frame<M: Message + Default>(bytes: &[u8]) -> Result<Option<(M, usize)>, CodecError> {
+    match split_frame(bytes)? {
+        Some((body, used)) => decode(body).map(|message| Some((message, used))),
+        None => Ok(None),
+    }
+}
+
+/// Reassembles frames from a byte stream that may arrive in arbitrary chunks
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buffer: BytesMut, // Bytes received but not yet returned as a frame
+}
+
+impl FrameDecoder {
+    /// Creates an empty decoder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends bytes read from the transport
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Number of buffered bytes that have not been returned as a frame yet
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Returns the next complete frame body, or `None` if more bytes are needed
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>, CodecError> {
+        Ok(self.next_frame_bytes()?.map(Vec::from))
+    }
+
+    /// Like [`next_frame`](FrameDecoder::next_frame), but the body shares the
+    /// decoder's buffer instead of being copied out of it
+    pub fn next_frame_bytes(&mut self) -> Result<Option<Bytes>, CodecError> {
+        let (prefix, used) = match split_frame(&self.buffer)? {
+            Some((body, used)) => (used - body.len(), used),
+            None => return Ok(None),
+        };
+        let mut frame = self.buffer.split_to(used).freeze();
+        frame.advance(prefix);
+        Ok(Some(frame))
+    }
+
+    /// Returns the next complete message, or `None` if more bytes are needed
+    pub fn next_message<M: Message + Default>(&mut self) -> Result<Option<M>, CodecError> {
+        match self.next_frame_bytes()? {
+            Some(body) => decode_bytes(body).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+// Splits the first frame off `buf`, returning its body and its total length including the prefix
+fn split_frame(buf: &[u8]) -> Result<Option<(&[u8], usize)>, CodecError> {
+    let (len, prefix) = match parse_length(buf)? {
+        Some(parsed) => parsed,
+        None => return Ok(None),
+    };
+    if buf.len() < prefix + len {
+        return Ok(None); // The body has not fully arrived yet
+    }
+    Ok(Some((&buf[prefix..prefix + len], prefix + len)))
+}
+
+// Parses the length prefix with the same rules as the fixed-buffer codec
+fn parse_length(buf: &[u8]) -> Result<Option<(usize, usize)>, CodecError> {
+    fixed::parse_length(buf).map_err(|e| match e {
+        FixedError::FrameTooLarge(len) => CodecError::FrameTooLarge(len),
+        _ => CodecError::InvalidLength,
+    })
+}
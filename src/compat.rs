@@ -0,0 +1,21 @@
+//! Earlier revisions of the message schema.
+//!
+//! Devices in the field keep the firmware they shipped with, so the schema may
+//! only grow in ways their decoders tolerate: new messages and fields get new
+//! field numbers, and numbers are never reused or retyped. Each released
+//! revision is kept under `proto/compat/` and compiled here, so tests can
+//! check that current code decodes what old firmware sends, and that old
+//! firmware decodes what current code sends, skipping what it does not know.
+
+use prost::{DecodeError, Message};
+
+/// The schema as first released: echo and add only
+pub mod v1 {
+    include!(concat!(env!("OUT_DIR"), "/messages.v1.rs"));
+}
+
+/// Re-encodes `message` and decodes it as `T`, as a peer built against the
+/// revision of `T` would see it
+pub fn convert<T: Message + Default>(message: &impl Message) -> Result<T, DecodeError> {
+    T::decode(message.encode_to_vec().as_slice())
+}
@@ -0,0 +1,85 @@
+//! Connecting to a host that resolves to several addresses.
+//!
+//! [`connect`] races the addresses in the manner of Happy Eyeballs
+//! (RFC 8305): IPv6 and IPv4 addresses are interleaved, a new attempt starts
+//! every [`CONNECTION_ATTEMPT_DELAY`] or as soon as the previous one fails,
+//! and the first connection to succeed wins. A host with one unreachable
+//! address therefore costs a short delay instead of a failed connect.
+
+use log::debug;
+use std::{
+    io,
+    net::{SocketAddr, TcpStream},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Head start each attempt gets before the next address is tried
+pub const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Addresses in the order they are attempted: alternating families, starting with the first one resolved
+pub fn attempt_order(addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return Vec::new();
+    };
+    let (mut preferred, mut other): (Vec<SocketAddr>, Vec<SocketAddr>) = addrs
+        .iter()
+        .partition(|addr| addr.is_ipv6() == first.is_ipv6());
+    let mut order = Vec::with_capacity(addrs.len());
+    preferred.reverse(); // Popped from the back
+    other.reverse();
+    while !preferred.is_empty() || !other.is_empty() {
+        order.extend(preferred.pop());
+        order.extend(other.pop());
+    }
+    order
+}
+
+/// Connects to whichever of `addrs` answers first, giving up after `timeout`
+pub fn connect(addrs: &[SocketAddr], timeout: Duration) -> io::Result<TcpStream> {
+    let deadline = Instant::now() + timeout;
+    let (results, attempts) = mpsc::channel();
+    let mut pending = attempt_order(addrs).into_iter();
+    let mut running = 0;
+    let mut last_error = io::Error::new(io::ErrorKind::InvalidInput, "No address to connect to");
+
+    loop {
+        // Start the next attempt now if nothing is running, otherwise after its head start
+        if let Some(addr) = pending.next() {
+            let results = results.clone();
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            debug!("Attempting {}", addr);
+            thread::spawn(move || {
+                // A loser's stream is dropped with its message once the winner is chosen
+                let _ = results.send((addr, TcpStream::connect_timeout(&addr, remaining)));
+            });
+            running += 1;
+        } else if running == 0 {
+            return Err(last_error);
+        }
+
+        let wait = match pending.len() {
+            0 => deadline.saturating_duration_since(Instant::now()),
+            _ => CONNECTION_ATTEMPT_DELAY,
+        };
+        match attempts.recv_timeout(wait) {
+            Ok((addr, Ok(stream))) => {
+                debug!("Connected to {}", addr);
+                return Ok(stream);
+            }
+            Ok((addr, Err(e))) => {
+                debug!("Attempt to {} failed: {}", addr, e);
+                running -= 1;
+                last_error = e;
+            }
+            Err(_) if Instant::now() >= deadline => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "Connection timed out",
+                ));
+            }
+            Err(_) => {} // Head start over; start the next attempt alongside
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Messages that could not be delivered, kept for debugging.
+//!
+//! A message queued for a device (see [`crate::outbox`]) that expires before
+//! the device connects, or is pushed out of a full queue, is handed to a
+//! [`DeadLetterSink`] rather than silently dropped. So is a message addressed
+//! to a device the server does not know, once it has been given its devices
+//! with [`Server::set_known_devices`](crate::server::Server::set_known_devices);
+//! such a message is never queued, and carries sequence number 0. The default sink,
+//! [`DeadLetters`], keeps the last [`DEFAULT_DEAD_LETTERS`] of them in memory,
+//! where [`Server::dead_letters`](crate::server::Server::dead_letters) can list
+//! them; another sink can be set with
+//! [`Server::set_dead_letter_sink`](crate::server::Server::set_dead_letter_sink)
+//! to write them elsewhere. Over the protocol, the admin request
+//! `DeadLettersRequest` lists them, which only an authorizer can allow; see
+//! [`crate::authz`].
+//!
+//! Queued messages are never retried, so there is no retry limit to exceed.
+
+use crate::message::{dead_letter_record, DeadLetterRecord};
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Dead letters kept by [`DeadLetters::default`]
+pub const DEFAULT_DEAD_LETTERS: usize = 1000;
+
+/// Why a message was not delivered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeadReason {
+    /// It waited longer than its or its queue's time to live
+    Expired,
+    /// It was dropped to make room in a full queue
+    Overflowed,
+    /// It was addressed to a device the server does not know
+    UnknownDevice,
+}
+
+impl fmt::Display for DeadReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeadReason::Expired => write!(f, "expired"),
+            DeadReason::Overflowed => write!(f, "overflowed"),
+            DeadReason::UnknownDevice => write!(f, "unknown device"),
+        }
+    }
+}
+
+/// A message that was not delivered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadLetter {
+    /// Device it was queued for
+    pub device: String,
+    /// Sequence number it was given; 0 if it was never queued
+    pub sequence: u64,
+    /// The message as queued
+    pub payload: Vec<u8>,
+    /// Why it was dropped
+    pub reason: DeadReason,
+    /// When it was dropped
+    pub dropped_at: SystemTime,
+}
+
+impl From<&DeadLetter> for DeadLetterRecord {
+    fn from(letter: &DeadLetter) -> Self {
+        let reason = match letter.reason {
+            DeadReason::Expired => dead_letter_record::Reason::Expired,
+            DeadReason::Overflowed => dead_letter_record::Reason::Overflowed,
+            DeadReason::UnknownDevice => dead_letter_record::Reason::UnknownDevice,
+        };
+        DeadLetterRecord {
+            device: letter.device.clone(),
+            sequence: letter.sequence,
+            payload: letter.payload.clone(),
+            reason: reason as i32,
+            dropped_at_ms: (letter.dropped_at.duration_since(UNIX_EPOCH))
+                .map_or(0, |since| since.as_millis() as u64),
+        }
+    }
+}
+
+/// Receives the messages that could not be delivered
+pub trait DeadLetterSink: Send + Sync {
+    /// Records `letter`. Called with the device queues locked, so it should
+    /// be quick.
+    fn record(&self, letter: DeadLetter);
+
+    /// The letters recorded lately, oldest first; none if the sink does not
+    /// keep them
+    fn recent(&self) -> Vec<DeadLetter> {
+        Vec::new()
+    }
+}
+
+/// Keeps the last dead letters in memory, dropping the oldest when full
+pub struct DeadLetters {
+    capacity: usize,
+    letters: Mutex<VecDeque<DeadLetter>>,
+}
+
+impl DeadLetters {
+    /// Creates a buffer of at most `capacity` letters
+    pub fn new(capacity: usize) -> Self {
+        DeadLetters {
+            capacity,
+            letters: Mutex::new(VecDeque::new()),
+        }
+    }
+}
+
+impl Default for DeadLetters {
+    fn default() -> Self {
+        Self::new(DEFAULT_DEAD_LETTERS)
+    }
+}
+
+impl DeadLetterSink for DeadLetters {
+    fn record(&self, letter: DeadLetter) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut letters = self.letters.lock().unwrap();
+        if letters.len() >= self.capacity {
+            letters.pop_front();
+        }
+        letters.push_back(letter);
+    }
+
+    fn recent(&self) -> Vec<DeadLetter> {
+        self.letters.lock().unwrap().iter().cloned().collect()
+    }
+}
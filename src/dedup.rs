@@ -0,0 +1,70 @@
+//! Idempotent retries by client-supplied message ID.
+//!
+//! A client that retries after a timeout cannot tell whether its first attempt
+//! was handled. By setting `ClientMessage::message_id` it lets the server
+//! recognise the retry: responses to the most recent [`DEDUP_WINDOW`] IDs of a
+//! connection are kept, and a request whose ID is among them is answered with
+//! the stored response instead of being handled again. Every response carries
+//! the ID of its request. ID 0, the default, opts out.
+
+use crate::message::server_message;
+use alloc::collections::{BTreeMap, VecDeque};
+
+/// Message IDs per connection whose responses are kept for retries
+pub const DEDUP_WINDOW: usize = 256;
+
+/// Responses to recent message IDs, oldest evicted first
+#[derive(Debug)]
+pub struct DedupWindow {
+    capacity: usize,
+    order: VecDeque<u64>, // IDs in arrival order
+    responses: BTreeMap<u64, server_message::Message>,
+}
+
+impl DedupWindow {
+    /// Creates an empty window remembering up to `capacity` IDs
+    pub fn new(capacity: usize) -> Self {
+        DedupWindow {
+            capacity,
+            order: VecDeque::new(),
+            responses: BTreeMap::new(),
+        }
+    }
+
+    /// Stored response to `message_id`, if the request was already handled
+    pub fn get(&self, message_id: u64) -> Option<&server_message::Message> {
+        self.responses.get(&message_id)
+    }
+
+    /// Stores the response to `message_id`; ID 0 is not remembered
+    pub fn insert(&mut self, message_id: u64, response: server_message::Message) {
+        if message_id == 0 || self.capacity == 0 {
+            return;
+        }
+        if self.responses.insert(message_id, response).is_some() {
+            return; // Already in the window; it keeps its place
+        }
+        self.order.push_back(message_id);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.responses.remove(&oldest);
+            }
+        }
+    }
+
+    /// Number of IDs remembered
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// Whether no IDs are remembered
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+}
+
+impl Default for DedupWindow {
+    fn default() -> Self {
+        Self::new(DEDUP_WINDOW)
+    }
+}
@@ -0,0 +1,48 @@
+//! Self-checks a server runs on request.
+//!
+//! [`Health`](crate::health::Health) answers yes or no for load balancers;
+//! a [`DiagnosticsReport`] says what was checked and how close each part of
+//! the server is to its limits, for fleet health pollers. A client sends a
+//! `DiagnosticsRequest` and gets the report, or the application calls
+//! [`Server::diagnostics`](crate::server::Server::diagnostics). The checks, by
+//! name:
+//!
+//! | Name          | Fails when                                      | Warns when                                  |
+//! |---------------|-------------------------------------------------|---------------------------------------------|
+//! | `listener`    | The accept loop has stopped turning             |                                             |
+//! | `worker_pool` | A request handler has been busy too long        | Connections are waiting for a worker        |
+//! | `memory`      |                                                 | Buffers are near the per-connection limit   |
+//! | `queues`      |                                                 | A mailbox or device queue is full           |
+//! | `upstream`    | The relay upstream is unreachable               |                                             |
+//! | `persistence` | Where it writes to disk is missing or read-only |                                             |
+//!
+//! The server writes capture files and its availability file to disk. Checks
+//! that do not apply, such as `upstream` on a server that does not relay, are
+//! skipped. The report is healthy unless a check failed.
+
+use crate::message::{diagnostic_check::Status, DiagnosticCheck, DiagnosticsReport};
+
+/// Share of a limit from which a check warns
+pub const NEAR_LIMIT: f64 = 0.8;
+
+// The outcome of the check named `name`
+pub(crate) fn check(name: &str, status: Status, detail: String) -> DiagnosticCheck {
+    DiagnosticCheck {
+        name: name.to_string(),
+        status: status as i32,
+        detail,
+    }
+}
+
+// The report of `checks`, healthy unless one failed
+pub(crate) fn report(checks: Vec<DiagnosticCheck>) -> DiagnosticsReport {
+    DiagnosticsReport {
+        healthy: checks.iter().all(|check| check.status() != Status::Failed),
+        checks,
+    }
+}
+
+/// The check named `name` in `report`, if it ran
+pub fn find<'a>(report: &'a DiagnosticsReport, name: &str) -> Option<&'a DiagnosticCheck> {
+    report.checks.iter().find(|check| check.name == name)
+}
@@ -0,0 +1,123 @@
+//! Finding servers on the LAN through mDNS/DNS-SD.
+//!
+//! A server announces itself with an [`Advertisement`] under
+//! [`SERVICE_TYPE`]; devices being commissioned call [`discover`] to list the
+//! servers that answer, instead of shipping with a hard-coded address. Both
+//! sides run their own mDNS responder thread (mdns-sd), which stops when the
+//! advertisement is dropped or the search ends.
+
+use log::{debug, info, warn};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use std::{
+    collections::BTreeMap,
+    io,
+    net::{IpAddr, SocketAddr, TcpStream},
+    time::{Duration, Instant},
+};
+
+/// DNS-SD service type servers are advertised under
+pub const SERVICE_TYPE: &str = "_embedded-task._tcp.local.";
+
+// How long a discovered address gets to accept a TCP connection
+const REACH_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// A server announced on the LAN until dropped
+pub struct Advertisement {
+    daemon: ServiceDaemon,
+    fullname: String, // Instance name qualified with the service type
+}
+
+impl Advertisement {
+    /// Announces a server listening on `port` on every address of this host, as instance `name`
+    pub fn new(name: &str, port: u16) -> io::Result<Self> {
+        let daemon = ServiceDaemon::new().map_err(io::Error::other)?;
+        let host = format!("{}.local.", name);
+        let service = ServiceInfo::new(SERVICE_TYPE, name, &host, (), port, None)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+            .enable_addr_auto(); // Follow the host's addresses as they change
+        let fullname = service.get_fullname().to_string();
+        daemon.register(service).map_err(io::Error::other)?;
+        info!("Advertising {} on port {}", fullname, port);
+        Ok(Advertisement { daemon, fullname })
+    }
+
+    /// Instance name qualified with the service type
+    pub fn fullname(&self) -> &str {
+        &self.fullname
+    }
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        // Tell browsers the server is gone rather than letting the record expire
+        if let Ok(unregistered) = self.daemon.unregister(&self.fullname) {
+            let _ = unregistered.recv_timeout(Duration::from_secs(1));
+        }
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// A server found by [`discover`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredServer {
+    /// Instance name qualified with the service type
+    pub name: String,
+    /// Addresses that accepted a TCP connection
+    pub addresses: Vec<IpAddr>,
+    /// Port the server listens on
+    pub port: u16,
+}
+
+impl DiscoveredServer {
+    /// Addresses to connect to, in the order they were checked
+    pub fn socket_addrs(&self) -> impl Iterator<Item = SocketAddr> + '_ {
+        self.addresses
+            .iter()
+            .map(|&address| SocketAddr::new(address, self.port))
+    }
+}
+
+/// Browses the LAN for `timeout` and returns the servers that can be reached, by name
+pub fn discover(timeout: Duration) -> io::Result<Vec<DiscoveredServer>> {
+    let daemon = ServiceDaemon::new().map_err(io::Error::other)?;
+    let events = daemon.browse(SERVICE_TYPE).map_err(io::Error::other)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut resolved = BTreeMap::new(); // Latest record per instance
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        match events.recv_timeout(remaining) {
+            Ok(ServiceEvent::ServiceResolved(service)) => {
+                debug!("Resolved {}", service.get_fullname());
+                resolved.insert(service.get_fullname().to_string(), service);
+            }
+            Ok(ServiceEvent::ServiceRemoved(_, name)) => {
+                resolved.remove(&name);
+            }
+            Ok(_) => {}
+            Err(_) => break, // Timed out, or the daemon went away
+        }
+    }
+    let _ = daemon.shutdown();
+
+    let servers: Vec<_> = resolved
+        .into_iter()
+        .filter_map(|(name, service)| {
+            let port = service.get_port();
+            let mut addresses: Vec<IpAddr> = service.get_addresses().iter().copied().collect();
+            addresses.sort(); // IPv4 first, for a stable order
+            addresses.retain(|&address| {
+                TcpStream::connect_timeout(&SocketAddr::new(address, port), REACH_TIMEOUT).is_ok()
+            });
+            if addresses.is_empty() {
+                warn!("{} is advertised but not reachable", name);
+                return None;
+            }
+            Some(DiscoveredServer {
+                name,
+                addresses,
+                port,
+            })
+        })
+        .collect();
+    Ok(servers)
+}
@@ -0,0 +1,201 @@
+//! Choosing between redundant servers.
+//!
+//! A [`Client`](crate::client::Client) built with several [`Endpoint`]s
+//! connects to the best one that answers. Endpoints with a lower `priority`
+//! value are preferred; among endpoints of equal priority, connections are
+//! spread in proportion to their `weight`. When the connected endpoint fails,
+//! the client marks it down and fails over to the next candidate. While it is
+//! connected to anything but the most preferred endpoint, the client checks
+//! the better ones every [`DEFAULT_HEALTH_CHECK_INTERVAL`] unless configured
+//! otherwise, and fails back as soon as one of them answers a ping.
+//!
+//! A server may also ask its clients to leave with a `GoAway`, naming an
+//! alternate server or not. Once the wait it asks for is over and no requests
+//! are in flight, the client connects to the alternate, which joins its
+//! endpoints, or else to the best of the others. Until one answers it stays on
+//! the old connection and tries again after a backoff that doubles from
+//! [`MIN_RECONNECT_BACKOFF`] up to [`MAX_RECONNECT_BACKOFF`].
+//!
+//! [`EndpointSet`] only keeps this bookkeeping; the client does the I/O.
+
+use std::time::{Duration, Instant};
+
+/// How often a client on a fallback endpoint checks whether a better one is back, by default
+pub const DEFAULT_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Wait before trying again after the first failed attempt to leave a server
+pub const MIN_RECONNECT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Longest wait between attempts to leave a server
+pub const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+/// One server a client may connect to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    /// Host name or IP address
+    pub host: String,
+    /// TCP port
+    pub port: u32,
+    /// Preference; lower values are tried first
+    pub priority: u32,
+    /// Share of connections among endpoints of the same priority
+    pub weight: u32,
+}
+
+impl Endpoint {
+    /// An endpoint of priority 0 and weight 1
+    pub fn new(host: &str, port: u32) -> Self {
+        Endpoint {
+            host: host.to_string(),
+            port,
+            priority: 0,
+            weight: 1,
+        }
+    }
+
+    /// The endpoint at `host:port`, such as a `GoAway` names, with brackets
+    /// around an IPv6 host; `None` if it is not one
+    pub fn parse(address: &str) -> Option<Self> {
+        let (host, port) = address.rsplit_once(':')?;
+        let host = host
+            .strip_prefix('[')
+            .and_then(|h| h.strip_suffix(']'))
+            .unwrap_or(host);
+        match (host.is_empty(), port.parse()) {
+            (false, Ok(port)) => Some(Endpoint::new(host, port)),
+            _ => None,
+        }
+    }
+
+    /// Sets the preference; lower values are tried first
+    pub fn with_priority(mut self, priority: u32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Sets the share of connections among endpoints of the same priority
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+}
+
+/// Endpoints with their health, in the order they should be tried
+#[derive(Debug)]
+pub struct EndpointSet {
+    endpoints: Vec<Endpoint>,
+    down_since: Vec<Option<Instant>>, // When each endpoint last failed, while it is down
+    credit: Vec<i64>,                 // Smooth weighted round-robin state
+}
+
+impl EndpointSet {
+    /// Creates the set with every endpoint considered healthy
+    pub fn new(endpoints: Vec<Endpoint>) -> Self {
+        let count = endpoints.len();
+        EndpointSet {
+            endpoints,
+            down_since: vec![None; count],
+            credit: vec![0; count],
+        }
+    }
+
+    /// The endpoint at `index`
+    pub fn get(&self, index: usize) -> &Endpoint {
+        &self.endpoints[index]
+    }
+
+    /// Index of the endpoint at `endpoint`'s host and port, adding it if there is none
+    pub fn insert(&mut self, endpoint: Endpoint) -> usize {
+        let existing = (self.endpoints.iter())
+            .position(|e| e.host == endpoint.host && e.port == endpoint.port);
+        existing.unwrap_or_else(|| {
+            self.endpoints.push(endpoint);
+            self.down_since.push(None);
+            self.credit.push(0);
+            self.endpoints.len() - 1
+        })
+    }
+
+    /// Number of endpoints
+    pub fn len(&self) -> usize {
+        self.endpoints.len()
+    }
+
+    /// Whether there are no endpoints
+    pub fn is_empty(&self) -> bool {
+        self.endpoints.is_empty()
+    }
+
+    /// Whether the endpoint at `index` last answered
+    pub fn is_up(&self, index: usize) -> bool {
+        self.down_since[index].is_none()
+    }
+
+    /// Records that the endpoint at `index` failed
+    pub fn mark_down(&mut self, index: usize, now: Instant) {
+        self.down_since[index].get_or_insert(now);
+    }
+
+    /// Records that the endpoint at `index` answered
+    pub fn mark_up(&mut self, index: usize) {
+        self.down_since[index] = None;
+    }
+
+    /// Indices to try connecting to, best first: healthy endpoints by priority,
+    /// the weighted pick of each priority ahead of its peers, then the endpoints
+    /// that are down, longest-failed first, as a last resort
+    pub fn candidates(&mut self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.endpoints.len()).collect();
+        let first_pick = self.weighted_picks();
+        order.sort_by_key(|&i| {
+            let endpoint = &self.endpoints[i];
+            (
+                self.down_since[i],
+                endpoint.priority,
+                !first_pick.contains(&i),
+                std::cmp::Reverse(endpoint.weight),
+            )
+        });
+        order
+    }
+
+    /// Indices of endpoints more preferred than the one at `current`, best first
+    pub fn better_than(&self, current: usize) -> Vec<usize> {
+        let priority = self.endpoints[current].priority;
+        let mut better: Vec<usize> = (0..self.endpoints.len())
+            .filter(|&i| self.endpoints[i].priority < priority)
+            .collect();
+        better.sort_by_key(|&i| self.endpoints[i].priority);
+        better
+    }
+
+    // Picks one healthy endpoint per priority by smooth weighted round-robin,
+    // so repeated connects spread over equal-priority endpoints by weight
+    fn weighted_picks(&mut self) -> Vec<usize> {
+        let mut priorities: Vec<u32> = self.endpoints.iter().map(|e| e.priority).collect();
+        priorities.sort_unstable();
+        priorities.dedup();
+
+        let mut picks = Vec::new();
+        for priority in priorities {
+            let peers: Vec<usize> = (0..self.endpoints.len())
+                .filter(|&i| self.endpoints[i].priority == priority && self.is_up(i))
+                .collect();
+            let total: i64 = peers
+                .iter()
+                .map(|&i| i64::from(self.endpoints[i].weight))
+                .sum();
+            for &i in &peers {
+                self.credit[i] += i64::from(self.endpoints[i].weight);
+            }
+            if let Some(&pick) = peers
+                .iter()
+                .max_by_key(|&&i| (self.credit[i], std::cmp::Reverse(i)))
+            {
+                self.credit[pick] -= total;
+                picks.push(pick);
+            }
+        }
+        picks
+    }
+}
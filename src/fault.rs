@@ -0,0 +1,309 @@
+//! Fault injection for testing clients against a misbehaving server.
+//!
+//! Only built with the `fault-injection` feature, which is meant for test and
+//! staging builds. A server started with [`Server::with_fault_injector`] passes
+//! every response through the configured faults before writing it. Decisions
+//! come from a seeded generator, so a failing scenario can be replayed.
+//!
+//! Besides the blanket faults set on the builder, [`Rule`]s target a single
+//! message type and can be replaced while the server runs with
+//! [`FaultInjector::set_rules`]; every connection sees the new rules on its
+//! next request. Operators can change them remotely too, with the admin
+//! request `FaultRulesRequest`, which adds rules, clears them, or both, and
+//! answers with the rules in effect. Only an authorizer can allow it; see
+//! [`crate::authz`].
+//!
+//! [`FaultInjector::hold_connections`] stalls the server itself rather than
+//! one response: accepted connections wait for the dispatcher, as they would
+//! behind a stuck one, until released or until the server stops.
+//!
+//! [`Server::with_fault_injector`]: crate::server::Server::with_fault_injector
+
+use crate::handler::MessageKind;
+use crate::message::{fault_rule, FaultRule};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
+
+/// How long responses are held back before being written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Delay {
+    /// Responses are written immediately
+    #[default]
+    None,
+    /// Every response is delayed by the same amount
+    Fixed(Duration),
+    /// Each response is delayed by a duration drawn uniformly from `min..=max`
+    Uniform { min: Duration, max: Duration },
+}
+
+/// What a [`Rule`] does to a response it selects
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Never send the response
+    Drop,
+    /// Hold the response back for this long
+    Delay(Duration),
+    /// Send a response that fails to decode
+    Corrupt,
+    /// Close the connection instead of responding
+    Reset,
+}
+
+/// Applies an action to a fraction of the responses to one message type
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rule {
+    pub kind: MessageKind,
+    pub probability: f64,
+    pub action: Action,
+}
+
+impl Rule {
+    /// E.g. `Rule::new(MessageKind::Add, 0.1, Action::Delay(Duration::from_millis(500)))`
+    pub fn new(kind: MessageKind, probability: f64, action: Action) -> Self {
+        Rule {
+            kind,
+            probability,
+            action,
+        }
+    }
+}
+
+impl From<&Rule> for FaultRule {
+    fn from(rule: &Rule) -> Self {
+        let (action, delay) = match rule.action {
+            Action::Drop => (fault_rule::Action::Drop, Duration::ZERO),
+            Action::Delay(delay) => (fault_rule::Action::Delay, delay),
+            Action::Corrupt => (fault_rule::Action::Corrupt, Duration::ZERO),
+            Action::Reset => (fault_rule::Action::Reset, Duration::ZERO),
+        };
+        FaultRule {
+            message_type: rule.kind.name().to_string(),
+            probability: rule.probability,
+            action: action as i32,
+            delay_ms: delay.as_millis() as u64,
+        }
+    }
+}
+
+impl TryFrom<&FaultRule> for Rule {
+    type Error = String;
+
+    // Fails on an unknown message type or action, naming it
+    fn try_from(rule: &FaultRule) -> Result<Self, String> {
+        let kind = MessageKind::from_name(&rule.message_type)
+            .ok_or_else(|| format!("unknown message type {}", rule.message_type))?;
+        let action = match fault_rule::Action::from_i32(rule.action) {
+            Some(fault_rule::Action::Drop) => Action::Drop,
+            Some(fault_rule::Action::Delay) => Action::Delay(Duration::from_millis(rule.delay_ms)),
+            Some(fault_rule::Action::Corrupt) => Action::Corrupt,
+            Some(fault_rule::Action::Reset) => Action::Reset,
+            None => return Err(format!("unknown action {}", rule.action)),
+        };
+        Ok(Rule::new(kind, rule.probability, action))
+    }
+}
+
+/// Faults the server applies to its responses
+#[derive(Debug, Clone)]
+pub struct FaultInjector {
+    seed: u64,
+    drop: f64,                     // Probability that a response is never sent
+    corrupt: f64,                  // Probability that a response body is overwritten
+    delay: Delay,                  // Hold-back applied to every response that is sent
+    reset_after: Option<usize>,    // Close each connection after this many responses
+    rules: Arc<RwLock<Vec<Rule>>>, // Shared with every connection so updates apply live
+    held: Arc<AtomicBool>,         // Whether the dispatcher holds accepted connections
+}
+
+impl FaultInjector {
+    /// Creates an injector with no faults enabled
+    pub fn new(seed: u64) -> Self {
+        FaultInjector {
+            seed,
+            drop: 0.0,
+            corrupt: 0.0,
+            delay: Delay::None,
+            reset_after: None,
+            rules: Arc::default(),
+            held: Arc::default(),
+        }
+    }
+
+    /// Silently drops each response with the given probability
+    pub fn drop_responses(mut self, probability: f64) -> Self {
+        self.drop = probability;
+        self
+    }
+
+    /// Delays each response that is sent
+    pub fn delay_responses(mut self, delay: Delay) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Overwrites the body of each response with the given probability.
+    ///
+    /// The length prefix is kept, so the client sees a frame that fails to
+    /// decode rather than a stream that is out of sync.
+    pub fn corrupt_frames(mut self, probability: f64) -> Self {
+        self.corrupt = probability;
+        self
+    }
+
+    /// Closes each connection instead of sending its response number `responses + 1`
+    pub fn reset_after(mut self, responses: usize) -> Self {
+        self.reset_after = Some(responses);
+        self
+    }
+
+    /// Adds a rule for one message type
+    pub fn rule(self, rule: Rule) -> Self {
+        self.rules.write().unwrap().push(rule);
+        self
+    }
+
+    /// Replaces the per-message-type rules of a running server
+    pub fn set_rules(&self, rules: Vec<Rule>) {
+        *self.rules.write().unwrap() = rules;
+    }
+
+    /// Per-message-type rules currently in effect
+    pub fn rules(&self) -> Vec<Rule> {
+        self.rules.read().unwrap().clone()
+    }
+
+    // Removes every rule if `clear`, then adds `added`, in one step; returns
+    // the rules in effect
+    pub(crate) fn change_rules(&self, clear: bool, added: Vec<Rule>) -> Vec<Rule> {
+        let mut rules = self.rules.write().unwrap();
+        if clear {
+            rules.clear();
+        }
+        rules.extend(added);
+        rules.clone()
+    }
+
+    /// Holds accepted connections before they reach a worker while `held`.
+    /// The accept loop stops accepting once its queue is full, leaving later
+    /// clients in the listen backlog. `stop()` releases them.
+    pub fn hold_connections(&self, held: bool) {
+        self.held.store(held, Ordering::Relaxed);
+    }
+
+    // Whether the dispatcher should wait before handing on a connection
+    pub(crate) fn connections_held(&self) -> bool {
+        self.held.load(Ordering::Relaxed)
+    }
+
+    // Fault state for one connection; each connection draws from its own sequence
+    pub(crate) fn connection(&self, index: u64) -> ConnectionFaults {
+        ConnectionFaults {
+            config: self.clone(),
+            rng: (self.seed ^ index.wrapping_mul(0x9e37_79b9_7f4a_7c15)).max(1),
+            responses: 0,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+// What to do with one outgoing frame
+pub(crate) enum Fault {
+    Deliver(Vec<u8>, Duration), // Write these bytes after waiting
+    Drop,
+    Reset,
+}
+
+// Faults picked for one response when its request arrived
+#[derive(Default)]
+struct Decision {
+    drop: bool,
+    corrupt: bool,
+    reset: bool,
+    delay: Duration,
+}
+
+// Per-connection fault state
+pub(crate) struct ConnectionFaults {
+    config: FaultInjector,
+    rng: u64, // xorshift state
+    responses: usize,
+    pending: VecDeque<Decision>, // One entry per queued response, in order
+}
+
+impl ConnectionFaults {
+    // The injector the connection's faults come from
+    pub(crate) fn injector(&self) -> &FaultInjector {
+        &self.config
+    }
+
+    // Picks the faults for the response to a request of this kind
+    pub(crate) fn request(&mut self, kind: MessageKind) {
+        let mut decision = Decision {
+            drop: self.chance(self.config.drop),
+            corrupt: self.chance(self.config.corrupt),
+            reset: false,
+            delay: match self.config.delay {
+                Delay::None => Duration::ZERO,
+                Delay::Fixed(delay) => delay,
+                Delay::Uniform { min, max } => {
+                    let span = max.saturating_sub(min).as_micros() as u64;
+                    min + Duration::from_micros(self.next() % (span + 1))
+                }
+            },
+        };
+
+        for rule in self.config.rules() {
+            if rule.kind != kind || !self.chance(rule.probability) {
+                continue;
+            }
+            match rule.action {
+                Action::Drop => decision.drop = true,
+                Action::Delay(delay) => decision.delay += delay,
+                Action::Corrupt => decision.corrupt = true,
+                Action::Reset => decision.reset = true,
+            }
+        }
+        self.pending.push_back(decision);
+    }
+
+    // Applies the faults picked for the next response
+    pub(crate) fn apply(&mut self, mut frame: Vec<u8>) -> Fault {
+        let decision = self.pending.pop_front().unwrap_or_default();
+        if decision.reset
+            || self
+                .config
+                .reset_after
+                .is_some_and(|limit| self.responses >= limit)
+        {
+            return Fault::Reset;
+        }
+        self.responses += 1;
+
+        if decision.drop {
+            return Fault::Drop;
+        }
+        if decision.corrupt {
+            // 0xff is never a valid field key, so the body cannot decode
+            let prefix = frame.iter().position(|byte| byte & 0x80 == 0).unwrap_or(0) + 1;
+            frame[prefix..].fill(0xff);
+        }
+        Fault::Deliver(frame, decision.delay)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+}
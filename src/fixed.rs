@@ -0,0 +1,620 @@
+//! Allocation-free encoding and decoding into caller-provided buffers.
+//!
+//! The types generated by prost own their strings, so they need a heap. This
+//! module hand-encodes the messages a heap-less device needs (echo, add, ping,
+//! telemetry, byte echo, flow control grants and errors) using the same frame layout as the `codec` module, producing
+//! bytes that are identical to what prost would emit. Decoded messages borrow
+//! their string contents from the input buffer instead of copying them.
+
+use core::fmt;
+
+/// Largest message body accepted or produced on the wire, in bytes
+pub const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// Longest varint needed to encode `MAX_FRAME_SIZE`
+const MAX_LENGTH_PREFIX: usize = 3;
+
+// Protobuf wire types used by the messages in this module
+const WIRE_VARINT: u8 = 0;
+const WIRE_FIXED64: u8 = 1;
+const WIRE_LEN: u8 = 2;
+const WIRE_FIXED32: u8 = 5;
+
+// Oneof field numbers shared by `ClientMessage` and `ServerMessage`
+const FIELD_ECHO: u32 = 1;
+const FIELD_ADD: u32 = 2;
+const FIELD_PING: u32 = 3;
+const FIELD_TELEMETRY: u32 = 4;
+const FIELD_WINDOW_UPDATE: u32 = 5; // Server messages only
+const FIELD_ERROR: u32 = 6; // Server messages only
+const FIELD_ECHO_BYTES: u32 = 7;
+
+/// [`Response::Error`] code of a server too busy to handle the request
+pub const ERROR_BUSY: i32 = 1;
+
+/// [`Response::Error`] code of a request whose handler took too long
+pub const ERROR_TIMEOUT: i32 = 2;
+
+/// [`Response::Error`] code of a request that broke a validation rule; the
+/// field and detail sent with it are skipped when decoding
+pub const ERROR_INVALID: i32 = 3;
+
+/// [`Response::Error`] code of a request type the server cannot handle
+pub const ERROR_UNSUPPORTED: i32 = 4;
+
+/// [`Response::Error`] code of a request the client is not allowed to send
+pub const ERROR_FORBIDDEN: i32 = 5;
+
+/// [`Response::Error`] code of a request over the device's daily quota
+pub const ERROR_QUOTA_EXCEEDED: i32 = 6;
+
+// Envelope field holding the logical stream, outside the oneof
+const FIELD_STREAM_ID: u32 = 15;
+
+/// Errors produced by the fixed-buffer codec
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FixedError {
+    /// The output buffer cannot hold the encoded frame
+    BufferTooSmall { needed: usize, available: usize },
+    /// The frame body is larger than `MAX_FRAME_SIZE`
+    FrameTooLarge(usize),
+    /// The length prefix is not a valid varint
+    InvalidLength,
+    /// The frame body is not valid protobuf for the expected message
+    Malformed,
+    /// A string field does not contain valid UTF-8
+    InvalidUtf8,
+    /// The envelope is empty or carries a message this module does not support
+    UnsupportedMessage,
+}
+
+impl fmt::Display for FixedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FixedError::BufferTooSmall { needed, available } => write!(
+                f,
+                "buffer too small: frame needs {} bytes but only {} are available",
+                needed, available
+            ),
+            FixedError::FrameTooLarge(len) => write!(
+                f,
+                "frame of {} bytes exceeds the {} byte limit",
+                len, MAX_FRAME_SIZE
+            ),
+            FixedError::InvalidLength => write!(f, "invalid frame length prefix"),
+            FixedError::Malformed => write!(f, "malformed message"),
+            FixedError::InvalidUtf8 => write!(f, "string field is not valid UTF-8"),
+            FixedError::UnsupportedMessage => write!(f, "unsupported message type"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FixedError {}
+
+/// A request as sent by a client, borrowing any string contents
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Request<'a> {
+    Echo(&'a str),
+    EchoBytes(&'a [u8]),
+    Add {
+        a: i32,
+        b: i32,
+    },
+    Ping {
+        timestamp: u64,
+    },
+    Telemetry {
+        sensor_id: u32,
+        value: f32,
+        timestamp: u64,
+    },
+}
+
+/// A response as sent by the server, borrowing any string contents
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Response<'a> {
+    Echo(&'a str),
+    EchoBytes(&'a [u8]),
+    Add {
+        result: i32,
+    },
+    Ping {
+        timestamp: u64,
+    },
+    TelemetryAck {
+        sensor_id: u32,
+        timestamp: u64,
+    },
+    /// Flow control credits for the response's stream; see the `flow` module
+    WindowUpdate {
+        credits: u32,
+    },
+    /// A request the server did not handle, such as [`ERROR_BUSY`]
+    Error {
+        code: i32,
+        retry_after_ms: u32,
+    },
+}
+
+impl<'a> Request<'a> {
+    /// Encodes the request as a complete frame, returning the number of bytes written
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, FixedError> {
+        self.encode_on(0, buf)
+    }
+
+    /// Encodes the request for a logical stream; see `ClientMessage::stream_id`
+    pub fn encode_on(&self, stream: u32, buf: &mut [u8]) -> Result<usize, FixedError> {
+        let (field, body): (u32, BodyWriter) = match *self {
+            Request::Echo(content) => (FIELD_ECHO, &move |s| put_str(s, 1, content)),
+            Request::EchoBytes(data) => (FIELD_ECHO_BYTES, &move |s| put_bytes(s, 1, data)),
+            Request::Add { a, b } => (FIELD_ADD, &move |s| {
+                put_int32(s, 1, a);
+                put_int32(s, 2, b);
+            }),
+            Request::Ping { timestamp } => (FIELD_PING, &move |s| put_uint64(s, 1, timestamp)),
+            Request::Telemetry {
+                sensor_id,
+                value,
+                timestamp,
+            } => (FIELD_TELEMETRY, &move |s| {
+                put_uint64(s, 1, sensor_id.into());
+                put_float(s, 2, value);
+                put_uint64(s, 3, timestamp);
+            }),
+        };
+        encode_frame(field, body, stream, buf)
+    }
+
+    /// Decodes the first frame in `bytes`.
+    ///
+    /// Returns the request and the number of bytes consumed, or `None` if the
+    /// frame has not fully arrived yet.
+    pub fn decode(bytes: &'a [u8]) -> Result<Option<(Self, usize)>, FixedError> {
+        Ok(Self::decode_with_stream(bytes)?.map(|(request, _, consumed)| (request, consumed)))
+    }
+
+    /// Like [`Request::decode`], also returning the request's stream
+    pub fn decode_with_stream(bytes: &'a [u8]) -> Result<Option<(Self, u32, usize)>, FixedError> {
+        let (field, body, stream, consumed) = match split_frame(bytes)? {
+            Some(parts) => parts,
+            None => return Ok(None),
+        };
+
+        let request = match field {
+            FIELD_ECHO => Request::Echo(decode_str(body, 1)?),
+            FIELD_ECHO_BYTES => Request::EchoBytes(decode_bytes(body, 1)?),
+            FIELD_ADD => {
+                let fields = decode_scalars(body)?;
+                Request::Add {
+                    a: fields[1] as i32,
+                    b: fields[2] as i32,
+                }
+            }
+            FIELD_PING => Request::Ping {
+                timestamp: decode_scalars(body)?[1],
+            },
+            FIELD_TELEMETRY => {
+                let fields = decode_scalars(body)?;
+                Request::Telemetry {
+                    sensor_id: fields[1] as u32,
+                    value: f32::from_bits(fields[2] as u32),
+                    timestamp: fields[3],
+                }
+            }
+            _ => return Err(FixedError::UnsupportedMessage),
+        };
+        Ok(Some((request, stream, consumed)))
+    }
+}
+
+impl<'a> Response<'a> {
+    /// Encodes the response as a complete frame, returning the number of bytes written
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, FixedError> {
+        self.encode_on(0, buf)
+    }
+
+    /// Encodes the response for the stream of the request it answers
+    pub fn encode_on(&self, stream: u32, buf: &mut [u8]) -> Result<usize, FixedError> {
+        let (field, body): (u32, BodyWriter) = match *self {
+            Response::Echo(content) => (FIELD_ECHO, &move |s| put_str(s, 1, content)),
+            Response::EchoBytes(data) => (FIELD_ECHO_BYTES, &move |s| put_bytes(s, 1, data)),
+            Response::Add { result } => (FIELD_ADD, &move |s| put_int32(s, 1, result)),
+            Response::Ping { timestamp } => (FIELD_PING, &move |s| put_uint64(s, 1, timestamp)),
+            Response::TelemetryAck {
+                sensor_id,
+                timestamp,
+            } => (FIELD_TELEMETRY, &move |s| {
+                put_uint64(s, 1, sensor_id.into());
+                put_uint64(s, 2, timestamp);
+            }),
+            Response::WindowUpdate { credits } => (FIELD_WINDOW_UPDATE, &move |s| {
+                put_uint64(s, 1, credits.into());
+            }),
+            Response::Error {
+                code,
+                retry_after_ms,
+            } => (FIELD_ERROR, &move |s| {
+                put_int32(s, 1, code);
+                put_uint64(s, 2, retry_after_ms.into());
+            }),
+        };
+        encode_frame(field, body, stream, buf)
+    }
+
+    /// Decodes the first frame in `bytes`.
+    ///
+    /// Returns the response and the number of bytes consumed, or `None` if the
+    /// frame has not fully arrived yet.
+    pub fn decode(bytes: &'a [u8]) -> Result<Option<(Self, usize)>, FixedError> {
+        Ok(Self::decode_with_stream(bytes)?.map(|(response, _, consumed)| (response, consumed)))
+    }
+
+    /// Like [`Response::decode`], also returning the stream the response belongs to
+    pub fn decode_with_stream(bytes: &'a [u8]) -> Result<Option<(Self, u32, usize)>, FixedError> {
+        let (field, body, stream, consumed) = match split_frame(bytes)? {
+            Some(parts) => parts,
+            None => return Ok(None),
+        };
+
+        let response = match field {
+            FIELD_ECHO => Response::Echo(decode_str(body, 1)?),
+            FIELD_ECHO_BYTES => Response::EchoBytes(decode_bytes(body, 1)?),
+            FIELD_ADD => Response::Add {
+                result: decode_scalars(body)?[1] as i32,
+            },
+            FIELD_PING => Response::Ping {
+                timestamp: decode_scalars(body)?[1],
+            },
+            FIELD_TELEMETRY => {
+                let fields = decode_scalars(body)?;
+                Response::TelemetryAck {
+                    sensor_id: fields[1] as u32,
+                    timestamp: fields[2],
+                }
+            }
+            FIELD_WINDOW_UPDATE => Response::WindowUpdate {
+                credits: decode_scalars(body)?[1] as u32,
+            },
+            FIELD_ERROR => {
+                let fields = decode_scalars(body)?;
+                Response::Error {
+                    code: fields[1] as i32,
+                    retry_after_ms: fields[2] as u32,
+                }
+            }
+            _ => return Err(FixedError::UnsupportedMessage),
+        };
+        Ok(Some((response, stream, consumed)))
+    }
+}
+
+/// A fixed-capacity buffer holding one encoded frame
+#[derive(Debug, Clone)]
+pub struct FrameBuf<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> FrameBuf<N> {
+    /// Creates an empty buffer
+    pub const fn new() -> Self {
+        FrameBuf {
+            bytes: [0; N],
+            len: 0,
+        }
+    }
+
+    /// Encodes a request into the buffer, replacing its previous contents
+    pub fn encode_request(&mut self, request: &Request<'_>) -> Result<&[u8], FixedError> {
+        self.len = 0;
+        self.len = request.encode(&mut self.bytes)?;
+        Ok(self.as_bytes())
+    }
+
+    /// Encodes a response into the buffer, replacing its previous contents
+    pub fn encode_response(&mut self, response: &Response<'_>) -> Result<&[u8], FixedError> {
+        self.len = 0;
+        self.len = response.encode(&mut self.bytes)?;
+        Ok(self.as_bytes())
+    }
+
+    /// Encodes a request for a logical stream, replacing the previous contents
+    pub fn encode_request_on(
+        &mut self,
+        stream: u32,
+        request: &Request<'_>,
+    ) -> Result<&[u8], FixedError> {
+        self.len = 0;
+        self.len = request.encode_on(stream, &mut self.bytes)?;
+        Ok(self.as_bytes())
+    }
+
+    /// The encoded frame
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl<const N: usize> Default for FrameBuf<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Parses the varint length prefix at the start of `buf`.
+///
+/// Returns the body length and the size of the prefix, or `None` if the prefix
+/// itself is still incomplete.
+pub(crate) fn parse_length(buf: &[u8]) -> Result<Option<(usize, usize)>, FixedError> {
+    let mut len = 0usize;
+    for (i, byte) in buf.iter().enumerate() {
+        if i == MAX_LENGTH_PREFIX {
+            return Err(FixedError::InvalidLength);
+        }
+        len |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            if len > MAX_FRAME_SIZE {
+                return Err(FixedError::FrameTooLarge(len));
+            }
+            return Ok(Some((len, i + 1)));
+        }
+    }
+
+    if buf.len() >= MAX_LENGTH_PREFIX {
+        return Err(FixedError::InvalidLength);
+    }
+    Ok(None)
+}
+
+// Destination for encoded bytes; lets the same code measure and write a message
+trait Sink {
+    fn put(&mut self, bytes: &[u8]);
+}
+
+// Writes the fields of one message body into a sink
+type BodyWriter<'a> = &'a dyn Fn(&mut dyn Sink);
+
+// Counts bytes without writing them
+struct Counter(usize);
+
+impl Sink for Counter {
+    fn put(&mut self, bytes: &[u8]) {
+        self.0 += bytes.len();
+    }
+}
+
+// Writes into a slice whose capacity has already been checked
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl Sink for Writer<'_> {
+    fn put(&mut self, bytes: &[u8]) {
+        self.buf[self.pos..self.pos + bytes.len()].copy_from_slice(bytes);
+        self.pos += bytes.len();
+    }
+}
+
+fn encode_frame(
+    field: u32,
+    body: BodyWriter,
+    stream: u32,
+    buf: &mut [u8],
+) -> Result<usize, FixedError> {
+    let mut counter = Counter(0);
+    body(&mut counter);
+    let inner = counter.0;
+    let mut stream_field = Counter(0);
+    put_uint64(&mut stream_field, FIELD_STREAM_ID, stream.into());
+
+    // The envelope holds the message as a single length-delimited oneof field,
+    // followed by the stream unless it is the default one
+    let envelope =
+        varint_len(tag(field, WIRE_LEN)) + varint_len(inner as u64) + inner + stream_field.0;
+    if envelope > MAX_FRAME_SIZE {
+        return Err(FixedError::FrameTooLarge(envelope));
+    }
+
+    let needed = varint_len(envelope as u64) + envelope;
+    if needed > buf.len() {
+        return Err(FixedError::BufferTooSmall {
+            needed,
+            available: buf.len(),
+        });
+    }
+
+    let mut writer = Writer { buf, pos: 0 };
+    put_varint(&mut writer, envelope as u64);
+    put_varint(&mut writer, tag(field, WIRE_LEN));
+    put_varint(&mut writer, inner as u64);
+    body(&mut writer);
+    put_uint64(&mut writer, FIELD_STREAM_ID, stream.into());
+    Ok(writer.pos)
+}
+
+fn tag(field: u32, wire_type: u8) -> u64 {
+    (u64::from(field) << 3) | u64::from(wire_type)
+}
+
+fn varint_len(value: u64) -> usize {
+    // Each byte carries 7 bits; zero still takes one byte
+    (64 - (value | 1).leading_zeros() as usize).div_ceil(7)
+}
+
+fn put_varint(sink: &mut dyn Sink, mut value: u64) {
+    let mut bytes = [0u8; 10];
+    let mut len = 0;
+    loop {
+        if value < 0x80 {
+            bytes[len] = value as u8;
+            len += 1;
+            break;
+        }
+        bytes[len] = (value as u8 & 0x7f) | 0x80;
+        value >>= 7;
+        len += 1;
+    }
+    sink.put(&bytes[..len]);
+}
+
+// Proto3 omits fields holding their default value, so the helpers below do too
+
+fn put_uint64(sink: &mut dyn Sink, field: u32, value: u64) {
+    if value != 0 {
+        put_varint(sink, tag(field, WIRE_VARINT));
+        put_varint(sink, value);
+    }
+}
+
+fn put_int32(sink: &mut dyn Sink, field: u32, value: i32) {
+    // Negative int32 values are sign-extended to ten bytes on the wire
+    put_uint64(sink, field, i64::from(value) as u64);
+}
+
+fn put_float(sink: &mut dyn Sink, field: u32, value: f32) {
+    if value.to_bits() != 0 {
+        put_varint(sink, tag(field, WIRE_FIXED32));
+        sink.put(&value.to_le_bytes());
+    }
+}
+
+fn put_str(sink: &mut dyn Sink, field: u32, value: &str) {
+    put_bytes(sink, field, value.as_bytes());
+}
+
+fn put_bytes(sink: &mut dyn Sink, field: u32, value: &[u8]) {
+    if !value.is_empty() {
+        put_varint(sink, tag(field, WIRE_LEN));
+        put_varint(sink, value.len() as u64);
+        sink.put(value);
+    }
+}
+
+// Cursor over an encoded message
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+// A decoded field value
+enum Value<'a> {
+    Scalar(u64),
+    Bytes(&'a [u8]),
+}
+
+impl<'a> Reader<'a> {
+    fn varint(&mut self) -> Result<u64, FixedError> {
+        let mut value = 0u64;
+        for i in 0..10 {
+            let (&byte, rest) = self.bytes.split_first().ok_or(FixedError::Malformed)?;
+            self.bytes = rest;
+            value |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(FixedError::Malformed)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], FixedError> {
+        if len > self.bytes.len() {
+            return Err(FixedError::Malformed);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    // Returns the next field number and value, or `None` at the end of the message
+    fn field(&mut self) -> Result<Option<(u32, Value<'a>)>, FixedError> {
+        if self.bytes.is_empty() {
+            return Ok(None);
+        }
+
+        let key = self.varint()?;
+        let field = u32::try_from(key >> 3).map_err(|_| FixedError::Malformed)?;
+        let value = match (key & 0x7) as u8 {
+            WIRE_VARINT => Value::Scalar(self.varint()?),
+            WIRE_FIXED64 => {
+                let mut raw = [0u8; 8];
+                raw.copy_from_slice(self.take(8)?);
+                Value::Scalar(u64::from_le_bytes(raw))
+            }
+            WIRE_LEN => {
+                let len = usize::try_from(self.varint()?).map_err(|_| FixedError::Malformed)?;
+                Value::Bytes(self.take(len)?)
+            }
+            WIRE_FIXED32 => {
+                let mut raw = [0u8; 4];
+                raw.copy_from_slice(self.take(4)?);
+                Value::Scalar(u32::from_le_bytes(raw).into())
+            }
+            _ => return Err(FixedError::Malformed),
+        };
+        Ok(Some((field, value)))
+    }
+}
+
+// A frame split into its oneof field number, the message body, the stream and the bytes consumed
+type RawFrame<'a> = (u32, &'a [u8], u32, usize);
+
+fn split_frame(bytes: &[u8]) -> Result<Option<RawFrame<'_>>, FixedError> {
+    let (len, prefix) = match parse_length(bytes)? {
+        Some(parsed) => parsed,
+        None => return Ok(None),
+    };
+    if bytes.len() < prefix + len {
+        return Ok(None); // The body has not fully arrived yet
+    }
+
+    // As in protobuf, the last oneof field on the wire wins and unknown fields are skipped
+    let mut reader = Reader {
+        bytes: &bytes[prefix..prefix + len],
+    };
+    let mut message = None;
+    let mut stream = 0;
+    while let Some((field, value)) = reader.field()? {
+        match value {
+            Value::Bytes(body) if (FIELD_ECHO..=FIELD_ECHO_BYTES).contains(&field) => {
+                message = Some((field, body));
+            }
+            Value::Scalar(id) if field == FIELD_STREAM_ID => stream = id as u32, // Truncated like prost's uint32
+            _ => {}
+        }
+    }
+
+    let (field, body) = message.ok_or(FixedError::UnsupportedMessage)?;
+    Ok(Some((field, body, stream, prefix + len)))
+}
+
+// Collects the scalar fields 1..=3 of a message, leaving absent fields at zero
+fn decode_scalars(body: &[u8]) -> Result<[u64; 4], FixedError> {
+    let mut fields = [0u64; 4];
+    let mut reader = Reader { bytes: body };
+    while let Some((field, value)) = reader.field()? {
+        if let (Value::Scalar(value), Some(slot)) = (value, fields.get_mut(field as usize)) {
+            *slot = value;
+        }
+    }
+    fields[0] = 0; // Field number zero is never valid
+    Ok(fields)
+}
+
+// Finds the string stored in `field`, defaulting to the empty string
+fn decode_str(body: &[u8], field: u32) -> Result<&str, FixedError> {
+    core::str::from_utf8(decode_bytes(body, field)?).map_err(|_| FixedError::InvalidUtf8)
+}
+
+// Finds the bytes stored in `field`, defaulting to none
+fn decode_bytes(body: &[u8], field: u32) -> Result<&[u8], FixedError> {
+    let mut content: &[u8] = &[];
+    let mut reader = Reader { bytes: body };
+    while let Some((number, value)) = reader.field()? {
+        if let (true, Value::Bytes(bytes)) = (number == field, value) {
+            content = bytes;
+        }
+    }
+    Ok(content)
+}
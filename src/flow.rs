@@ -0,0 +1,148 @@
+//! Credit-based flow control per logical stream.
+//!
+//! Every stream starts with [`INITIAL_WINDOW`] credits on the client; each
+//! request spends one. The server hands credits back in a `WindowUpdate` once
+//! the responses to earlier requests have been written, batching them until
+//! the client is down to half its window. A stream whose responses back up
+//! therefore runs dry and its sender waits, while other streams on the same
+//! connection keep flowing. Requests beyond the window are a protocol
+//! violation and close the connection.
+//!
+//! Credits came with version 2 of the protocol, which a client states in the
+//! `protocol_version` of its first message. Clients that state none were
+//! built against version 1: they may wait for each response in turn and would
+//! take a `WindowUpdate` for one, so the server neither grants them credits
+//! nor holds them to a window.
+
+use crate::message::{server_message, ServerMessage, WindowUpdate};
+use alloc::collections::BTreeMap;
+use core::fmt;
+
+/// Requests a client may have outstanding on a stream before hearing back
+pub const INITIAL_WINDOW: u32 = 32;
+
+/// First protocol version whose clients are granted credits and held to them
+pub const FLOW_CONTROL_VERSION: u32 = 2;
+
+/// A client sent more requests on a stream than it had credits for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FlowError {
+    /// Stream the excess request arrived on
+    pub stream_id: u32,
+}
+
+impl fmt::Display for FlowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "flow control window of stream {} exceeded",
+            self.stream_id
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FlowError {}
+
+/// The message granting `credits` more requests on `stream_id`
+pub fn window_update(stream_id: u32, credits: u32) -> ServerMessage {
+    ServerMessage {
+        message: Some(server_message::Message::WindowUpdate(WindowUpdate {
+            credits,
+        })),
+        message_id: 0,
+        stream_id,
+    }
+}
+
+/// Credits left per stream, as tracked by a client
+#[derive(Debug, Default)]
+pub struct SendWindows {
+    credits: BTreeMap<u32, u32>, // Streams not listed have the full initial window
+}
+
+impl SendWindows {
+    /// Creates windows with every stream at its initial size
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Credits left on `stream`
+    pub fn available(&self, stream: u32) -> u32 {
+        self.credits.get(&stream).copied().unwrap_or(INITIAL_WINDOW)
+    }
+
+    /// Spends a credit for one request, or returns `false` if none are left
+    pub fn consume(&mut self, stream: u32) -> bool {
+        let credits = self.credits.entry(stream).or_insert(INITIAL_WINDOW);
+        if *credits == 0 {
+            return false;
+        }
+        *credits -= 1;
+        true
+    }
+
+    /// Adds credits granted by the server
+    pub fn grant(&mut self, stream: u32, credits: u32) {
+        let available = self.available(stream).saturating_add(credits);
+        if available == INITIAL_WINDOW {
+            self.credits.remove(&stream);
+        } else {
+            self.credits.insert(stream, available);
+        }
+    }
+}
+
+/// Credits per stream as tracked by the server
+#[derive(Debug, Default)]
+pub struct ReceiveWindows {
+    streams: BTreeMap<u32, Window>, // Streams not listed are idle with a full window
+}
+
+#[derive(Debug)]
+struct Window {
+    remaining: u32, // Requests the client may still send
+    owed: u32,      // Requests answered since the last grant
+}
+
+impl ReceiveWindows {
+    /// Creates windows with every stream at its initial size
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accounts for a request that arrived on `stream`
+    pub fn received(&mut self, stream: u32) -> Result<(), FlowError> {
+        let window = self.streams.entry(stream).or_insert(Window {
+            remaining: INITIAL_WINDOW,
+            owed: 0,
+        });
+        window.remaining = window
+            .remaining
+            .checked_sub(1)
+            .ok_or(FlowError { stream_id: stream })?;
+        Ok(())
+    }
+
+    /// Marks a request on `stream` as answered; its credit goes back with the next grant
+    pub fn completed(&mut self, stream: u32) {
+        if let Some(window) = self.streams.get_mut(&stream) {
+            window.owed += 1;
+        }
+    }
+
+    /// Next stream to send a `WindowUpdate` for and the credits to grant
+    pub fn poll_grant(&mut self) -> Option<(u32, u32)> {
+        let (&stream, window) = self
+            .streams
+            .iter_mut()
+            .find(|(_, window)| window.owed > 0 && window.remaining <= INITIAL_WINDOW / 2)?;
+        let credits = core::mem::take(&mut window.owed);
+        window.remaining += credits;
+        if window.remaining == INITIAL_WINDOW {
+            self.streams.remove(&stream); // Idle again; forget it
+        }
+        Some((stream, credits))
+    }
+}
@@ -0,0 +1,62 @@
+//! Logging macros for the embedded paths of the crate.
+//!
+//! Diagnostics go through `log` by default. Firmware that logs with defmt over
+//! RTT enables the `defmt` feature instead; the macros take the same `{}` format
+//! strings either way, so every argument implements both `Display` and
+//! `defmt::Format`.
+#![allow(unused_macros, unused_imports)]
+
+macro_rules! log_trace {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "defmt")]
+        ::defmt::trace!($($arg)*);
+        #[cfg(not(feature = "defmt"))]
+        ::log::trace!($($arg)*);
+    }};
+}
+
+macro_rules! log_debug {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "defmt")]
+        ::defmt::debug!($($arg)*);
+        #[cfg(not(feature = "defmt"))]
+        ::log::debug!($($arg)*);
+    }};
+}
+
+macro_rules! log_info {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "defmt")]
+        ::defmt::info!($($arg)*);
+        #[cfg(not(feature = "defmt"))]
+        ::log::info!($($arg)*);
+    }};
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "defmt")]
+        ::defmt::warn!($($arg)*);
+        #[cfg(not(feature = "defmt"))]
+        ::log::warn!($($arg)*);
+    }};
+}
+
+// An info line written for each message; on std builds it counts against the
+// `Message` class of `loglimit`, so a flood of messages cannot flood the log
+macro_rules! log_message {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "defmt")]
+        ::defmt::info!($($arg)*);
+        #[cfg(all(not(feature = "defmt"), feature = "std"))]
+        $crate::loglimit::limited!(
+            $crate::loglimit::LogClass::Message,
+            ::log::Level::Info,
+            $($arg)*
+        );
+        #[cfg(all(not(feature = "defmt"), not(feature = "std")))]
+        ::log::info!($($arg)*);
+    }};
+}
+
+pub(crate) use {log_debug, log_info, log_message, log_trace, log_warn};
@@ -0,0 +1,366 @@
+//! Request handling, independent of how requests reach the server.
+
+use crate::calc;
+use crate::codec::{MAX_FRAME_SIZE, PROTOCOL_VERSION};
+use crate::fmt::log_message;
+use crate::message::{
+    client_message, error_response, server_message, transform_request, AddResponse, CalcResponse,
+    DescribeResponse, ErrorResponse, PingResponse, RandomRequest, TelemetryAck, TransformRequest,
+    TransformResponse,
+};
+use alloc::string::{String, ToString};
+use core::fmt::Write;
+use sha2::{Digest, Sha256};
+
+/// Most random bytes handed out for one `RandomRequest`
+pub const MAX_RANDOM_BYTES: u32 = 1024;
+
+/// Kind of request, for configuration and statistics kept per message type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MessageKind {
+    Echo,
+    Add,
+    Ping,
+    Telemetry,
+    EchoBytes,
+    Transform,
+    Random,
+    Calc,
+    Describe,
+    Resume,
+    Quota,
+    TailLogs,
+    Diagnostics,
+    Availability,
+    ConnectionHistory,
+    Subscribe,
+    Unsubscribe,
+    Publish,
+    Resync,
+    Cluster,
+    DeadLetters,
+    FaultRules,
+    Batch,
+}
+
+impl MessageKind {
+    /// Every kind, in declaration order
+    pub const ALL: [MessageKind; 23] = [
+        MessageKind::Echo,
+        MessageKind::Add,
+        MessageKind::Ping,
+        MessageKind::Telemetry,
+        MessageKind::EchoBytes,
+        MessageKind::Transform,
+        MessageKind::Random,
+        MessageKind::Calc,
+        MessageKind::Describe,
+        MessageKind::Resume,
+        MessageKind::Quota,
+        MessageKind::TailLogs,
+        MessageKind::Diagnostics,
+        MessageKind::Availability,
+        MessageKind::ConnectionHistory,
+        MessageKind::Subscribe,
+        MessageKind::Unsubscribe,
+        MessageKind::Publish,
+        MessageKind::Resync,
+        MessageKind::Cluster,
+        MessageKind::DeadLetters,
+        MessageKind::FaultRules,
+        MessageKind::Batch,
+    ];
+
+    /// Kind of the given request
+    pub fn of(request: &client_message::Message) -> Self {
+        match request {
+            client_message::Message::EchoMessage(_) => MessageKind::Echo,
+            client_message::Message::AddRequest(_) => MessageKind::Add,
+            client_message::Message::PingRequest(_) => MessageKind::Ping,
+            client_message::Message::TelemetryReport(_) => MessageKind::Telemetry,
+            client_message::Message::EchoBytes(_) => MessageKind::EchoBytes,
+            client_message::Message::TransformRequest(_) => MessageKind::Transform,
+            client_message::Message::RandomRequest(_) => MessageKind::Random,
+            client_message::Message::CalcRequest(_) => MessageKind::Calc,
+            client_message::Message::DescribeRequest(_) => MessageKind::Describe,
+            client_message::Message::ResumeRequest(_) => MessageKind::Resume,
+            client_message::Message::QuotaRequest(_) => MessageKind::Quota,
+            client_message::Message::TailLogs(_) => MessageKind::TailLogs,
+            client_message::Message::DiagnosticsRequest(_) => MessageKind::Diagnostics,
+            client_message::Message::AvailabilityRequest(_) => MessageKind::Availability,
+            client_message::Message::ConnectionHistoryRequest(_) => MessageKind::ConnectionHistory,
+            client_message::Message::SubscribeRequest(_) => MessageKind::Subscribe,
+            client_message::Message::UnsubscribeRequest(_) => MessageKind::Unsubscribe,
+            client_message::Message::PublishRequest(_) => MessageKind::Publish,
+            client_message::Message::ResyncRequest(_) => MessageKind::Resync,
+            client_message::Message::ClusterEvent(_) => MessageKind::Cluster,
+            client_message::Message::DeadLettersRequest(_) => MessageKind::DeadLetters,
+            client_message::Message::FaultRulesRequest(_) => MessageKind::FaultRules,
+            client_message::Message::Batch(_) => MessageKind::Batch,
+        }
+    }
+
+    /// Name of the protobuf message of this kind of request
+    pub fn name(self) -> &'static str {
+        match self {
+            MessageKind::Echo => "EchoMessage",
+            MessageKind::Add => "AddRequest",
+            MessageKind::Ping => "PingRequest",
+            MessageKind::Telemetry => "TelemetryReport",
+            MessageKind::EchoBytes => "EchoBytes",
+            MessageKind::Transform => "TransformRequest",
+            MessageKind::Random => "RandomRequest",
+            MessageKind::Calc => "CalcRequest",
+            MessageKind::Describe => "DescribeRequest",
+            MessageKind::Resume => "ResumeRequest",
+            MessageKind::Quota => "QuotaRequest",
+            MessageKind::TailLogs => "TailLogs",
+            MessageKind::Diagnostics => "DiagnosticsRequest",
+            MessageKind::Availability => "AvailabilityRequest",
+            MessageKind::ConnectionHistory => "ConnectionHistoryRequest",
+            MessageKind::Subscribe => "SubscribeRequest",
+            MessageKind::Unsubscribe => "UnsubscribeRequest",
+            MessageKind::Publish => "PublishRequest",
+            MessageKind::Resync => "ResyncRequest",
+            MessageKind::Cluster => "ClusterEvent",
+            MessageKind::DeadLetters => "DeadLettersRequest",
+            MessageKind::FaultRules => "FaultRulesRequest",
+            MessageKind::Batch => "Batch",
+        }
+    }
+
+    /// Whether only operators send this kind; see [`crate::authz`]
+    pub fn is_admin(self) -> bool {
+        matches!(
+            self,
+            MessageKind::TailLogs
+                | MessageKind::ConnectionHistory
+                | MessageKind::Cluster
+                | MessageKind::DeadLetters
+                | MessageKind::FaultRules
+        )
+    }
+
+    /// Kind of the requests whose protobuf message is named `name`
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+/// Computes the response for a single request
+pub fn handle_message(request: client_message::Message) -> server_message::Message {
+    match request {
+        client_message::Message::EchoMessage(echo) => {
+            log_message!("Received: {}", echo.content.as_str()); // Log the received message
+            server_message::Message::EchoMessage(echo) // Echo the message back to the client
+        }
+        client_message::Message::AddRequest(add) => {
+            log_message!("Received: {} + {}", add.a, add.b);
+            server_message::Message::AddResponse(AddResponse {
+                result: add.a.wrapping_add(add.b), // Overflow wraps instead of panicking the worker
+            })
+        }
+        client_message::Message::PingRequest(ping) => {
+            server_message::Message::PingResponse(PingResponse {
+                timestamp: ping.timestamp, // Return the client's timestamp so it can measure RTT
+            })
+        }
+        client_message::Message::TelemetryReport(report) => {
+            log_message!(
+                "Telemetry from sensor {}: {} at {}",
+                report.sensor_id,
+                report.value,
+                report.timestamp
+            );
+            server_message::Message::TelemetryAck(TelemetryAck {
+                sensor_id: report.sensor_id,
+                timestamp: report.timestamp,
+            })
+        }
+        client_message::Message::EchoBytes(echo) => {
+            log_message!("Received {} bytes", echo.data.len());
+            server_message::Message::EchoBytes(echo) // Returned unchanged, whatever the bytes are
+        }
+        client_message::Message::TransformRequest(request) => transform(request),
+        client_message::Message::RandomRequest(request) => random(request),
+        client_message::Message::CalcRequest(request) => {
+            match calc::evaluate(&request.expression) {
+                Ok(result) => server_message::Message::CalcResponse(CalcResponse { result }),
+                Err(e) => error(error_response::Code::Invalid, "expression", e.to_string()),
+            }
+        }
+        client_message::Message::DescribeRequest(_) => describe(),
+        // Sessions belong to the connection; the TCP server answers these itself
+        client_message::Message::ResumeRequest(_) => error(
+            error_response::Code::Unsupported,
+            "",
+            "sessions are not kept here".to_string(),
+        ),
+        // Deliveries belong to the device; the TCP server sends them again itself
+        client_message::Message::ResyncRequest(_) => error(
+            error_response::Code::Unsupported,
+            "",
+            "deliveries are not kept here".to_string(),
+        ),
+        // Quotas belong to the device; the TCP server answers these itself
+        client_message::Message::QuotaRequest(_) => error(
+            error_response::Code::Unsupported,
+            "",
+            "quotas are not kept here".to_string(),
+        ),
+        // The log belongs to the process; the TCP server streams it itself
+        client_message::Message::TailLogs(_) => error(
+            error_response::Code::Unsupported,
+            "",
+            "logs are not kept here".to_string(),
+        ),
+        // So is the server's health
+        client_message::Message::DiagnosticsRequest(_) => error(
+            error_response::Code::Unsupported,
+            "",
+            "no server to diagnose here".to_string(),
+        ),
+        client_message::Message::AvailabilityRequest(_) => error(
+            error_response::Code::Unsupported,
+            "",
+            "uptime is not tracked here".to_string(),
+        ),
+        client_message::Message::ConnectionHistoryRequest(_) => error(
+            error_response::Code::Unsupported,
+            "",
+            "connections are not kept here".to_string(),
+        ),
+        client_message::Message::DeadLettersRequest(_) => error(
+            error_response::Code::Unsupported,
+            "",
+            "dead letters are not kept here".to_string(),
+        ),
+        client_message::Message::FaultRulesRequest(_) => error(
+            error_response::Code::Unsupported,
+            "",
+            "no faults are injected here".to_string(),
+        ),
+        // Batches are unpacked as they are read, so one that gets here was inside another
+        client_message::Message::Batch(_) => error(
+            error_response::Code::Unsupported,
+            "",
+            "batches do not nest".to_string(),
+        ),
+        // Subscriptions belong to the connections; the TCP server brokers topics itself
+        client_message::Message::SubscribeRequest(_)
+        | client_message::Message::UnsubscribeRequest(_)
+        | client_message::Message::PublishRequest(_) => error(
+            error_response::Code::Unsupported,
+            "",
+            "topics are not brokered here".to_string(),
+        ),
+        // So are the other nodes of a cluster
+        client_message::Message::ClusterEvent(_) => error(
+            error_response::Code::Unsupported,
+            "",
+            "no cluster here".to_string(),
+        ),
+    }
+}
+
+// Applies the requested operation to the content
+fn transform(request: TransformRequest) -> server_message::Message {
+    use transform_request::Op;
+    let content = request.content;
+    let transformed = match Op::from_i32(request.op) {
+        Some(Op::Uppercase) => content.to_uppercase(),
+        Some(Op::Reverse) => content.chars().rev().collect(),
+        Some(Op::Length) => content.len().to_string(),
+        Some(Op::Sha256) => {
+            let mut hex = String::with_capacity(64);
+            for byte in Sha256::digest(content.as_bytes()) {
+                let _ = write!(hex, "{:02x}", byte); // Writing to a `String` cannot fail
+            }
+            hex
+        }
+        Some(Op::Unspecified) | None => {
+            return error(
+                error_response::Code::Invalid,
+                "op",
+                alloc::format!("unknown operation {}", request.op),
+            );
+        }
+    };
+    server_message::Message::TransformResponse(TransformResponse {
+        content: transformed,
+    })
+}
+
+// What this build supports; features are the optional ones compiled in
+fn describe() -> server_message::Message {
+    let features = [
+        ("client", cfg!(feature = "client")),
+        ("discovery", cfg!(feature = "discovery")),
+        ("fault-injection", cfg!(feature = "fault-injection")),
+        ("handover", cfg!(feature = "handover")),
+        ("hardening", cfg!(feature = "hardening")),
+        ("named-pipe", cfg!(feature = "named-pipe")),
+        ("native-plugins", cfg!(feature = "native-plugins")),
+        ("privileges", cfg!(feature = "privileges")),
+        ("quic", cfg!(feature = "quic")),
+        ("scripting", cfg!(feature = "scripting")),
+        ("server", cfg!(feature = "server")),
+        ("std", cfg!(feature = "std")),
+        ("wasm", cfg!(feature = "wasm")),
+    ];
+    server_message::Message::DescribeResponse(DescribeResponse {
+        protocol_version: PROTOCOL_VERSION,
+        message_types: MessageKind::ALL
+            .iter()
+            .map(|kind| kind.name().to_string())
+            .collect(),
+        features: features
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| name.to_string())
+            .collect(),
+        max_frame_size: MAX_FRAME_SIZE as u32,
+        server_version: env!("CARGO_PKG_VERSION").to_string(),
+    })
+}
+
+// Fills the requested number of bytes from the operating system's generator
+#[cfg(feature = "std")]
+fn random(request: RandomRequest) -> server_message::Message {
+    if request.num_bytes > MAX_RANDOM_BYTES {
+        return error(
+            error_response::Code::Invalid,
+            "num_bytes",
+            alloc::format!("more than the limit of {}", MAX_RANDOM_BYTES),
+        );
+    }
+    let mut data = alloc::vec![0; request.num_bytes as usize];
+    match getrandom::getrandom(&mut data) {
+        Ok(()) => server_message::Message::RandomResponse(crate::message::RandomResponse { data }),
+        Err(e) => error(
+            error_response::Code::Unspecified,
+            "",
+            alloc::format!("no random source: {}", e),
+        ),
+    }
+}
+
+// Without `std` there is no generator to rely on
+#[cfg(not(feature = "std"))]
+fn random(_: RandomRequest) -> server_message::Message {
+    error(
+        error_response::Code::Unsupported,
+        "",
+        "no random source".to_string(),
+    )
+}
+
+fn error(code: error_response::Code, field: &str, detail: String) -> server_message::Message {
+    server_message::Message::ErrorResponse(ErrorResponse {
+        code: code as i32,
+        field: field.to_string(),
+        detail,
+        ..Default::default()
+    })
+}
@@ -0,0 +1,154 @@
+//! Handing the listening socket to a replacement server process.
+//!
+//! An upgrade without a reconnect storm keeps the listening socket open across
+//! processes. The old server offers its listener on a Unix socket with
+//! [`Server::hand_over`](crate::server::Server::hand_over); the new one, once
+//! started, picks it up with [`receive`] and builds its server with
+//! [`Server::from_listener`](crate::server::Server::from_listener). The socket
+//! is passed with `SCM_RIGHTS`, so both processes share one listen backlog and
+//! no connection attempt is refused in between. The old server then drains:
+//! it stops accepting and exits once its devices have disconnected.
+//!
+//! Under systemd socket activation, [`systemd_listener`] takes the socket
+//! systemd passed instead, which survives restarts of the service itself.
+
+use log::info;
+use std::{
+    io::{self, ErrorKind},
+    mem,
+    net::TcpListener,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    ptr,
+};
+
+/// First file descriptor systemd passes to a socket-activated service
+pub const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// Waits on a Unix socket at `path` for the replacement process and sends it `listener`
+pub fn offer(listener: &TcpListener, path: &Path) -> io::Result<()> {
+    let _ = std::fs::remove_file(path); // Left over from an earlier handover
+    let control = UnixListener::bind(path)?;
+    let result = control
+        .accept()
+        .and_then(|(successor, _)| send_fd(&successor, listener.as_raw_fd()));
+    let _ = std::fs::remove_file(path);
+    result?;
+    info!(
+        "Handed the listening socket over through {}",
+        path.display()
+    );
+    Ok(())
+}
+
+/// Takes over the listening socket offered at `path` by the server being replaced
+pub fn receive(path: &Path) -> io::Result<TcpListener> {
+    let predecessor = UnixStream::connect(path)?;
+    let listener = TcpListener::from(receive_fd(&predecessor)?);
+    listener.local_addr()?; // Fails unless it really is a socket
+    Ok(listener)
+}
+
+/// The listening socket systemd passed through socket activation, if any
+pub fn systemd_listener() -> io::Result<Option<TcpListener>> {
+    let ours = std::env::var("LISTEN_PID").ok() == Some(std::process::id().to_string());
+    let count: usize = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|fds| fds.parse().ok())
+        .unwrap_or(0);
+    if !ours || count == 0 {
+        return Ok(None);
+    }
+    // Not meant for any children we start
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+
+    set_cloexec(SD_LISTEN_FDS_START)?;
+    // Safety: systemd hands this descriptor to the service to own
+    let listener = TcpListener::from(unsafe { OwnedFd::from_raw_fd(SD_LISTEN_FDS_START) });
+    listener.local_addr()?;
+    Ok(Some(listener))
+}
+
+// Room for the control message carrying one descriptor, aligned for `cmsghdr`
+fn control_buffer() -> (Vec<u64>, usize) {
+    // Safety: only computes a size
+    let space = unsafe { libc::CMSG_SPACE(mem::size_of::<RawFd>() as u32) } as usize;
+    (vec![0; space.div_ceil(mem::size_of::<u64>())], space)
+}
+
+fn send_fd(socket: &UnixStream, fd: RawFd) -> io::Result<()> {
+    let mut byte = [0u8; 1]; // At least one byte of data must carry the descriptor
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr().cast(),
+        iov_len: byte.len(),
+    };
+    let (mut control, space) = control_buffer();
+    // Safety: `msg` points at `iov` and `control`, which outlive the call, and
+    // the header written fits in `space` bytes
+    unsafe {
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = space as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<RawFd>() as u32) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>(), fd);
+        if libc::sendmsg(socket.as_raw_fd(), &msg, 0) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn receive_fd(socket: &UnixStream) -> io::Result<OwnedFd> {
+    let mut byte = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr().cast(),
+        iov_len: byte.len(),
+    };
+    let (mut control, space) = control_buffer();
+    // Safety: as in `send_fd`; the descriptor read is only taken over once the
+    // kernel has confirmed it installed one
+    let fd = unsafe {
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = space as _;
+        if libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        if cmsg.is_null()
+            || (*cmsg).cmsg_level != libc::SOL_SOCKET
+            || (*cmsg).cmsg_type != libc::SCM_RIGHTS
+        {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "No listening socket was handed over",
+            ));
+        }
+        OwnedFd::from_raw_fd(ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>()))
+    };
+    set_cloexec(fd.as_raw_fd())?; // Received descriptors are inheritable by default
+    Ok(fd)
+}
+
+fn set_cloexec(fd: RawFd) -> io::Result<()> {
+    // Safety: plain flag manipulation on a descriptor we own
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,200 @@
+//! Sandboxing the server on Linux.
+//!
+//! Once a server is set up, [`Sandbox::apply`] limits what the whole process may
+//! still do, so a bug in the decode path cannot be turned into access to the
+//! rest of the machine:
+//!
+//! - Landlock takes away all filesystem access except below the directories
+//!   allowed explicitly, such as a capture directory. Older kernels enforce
+//!   what they can, and kernels without Landlock nothing; see
+//!   [`SandboxStatus::filesystem_restricted`].
+//! - A seccomp filter allows only the system calls the server uses to serve
+//!   connections. Any other call fails with `EPERM`, so a call missing from the
+//!   list shows up as an error in the log rather than a killed server.
+//!
+//! Both apply to every thread and cannot be lifted. Apply the sandbox after
+//! binding the listener, loading keys and dropping privileges, and before
+//! [`Server::run`](crate::server::Server::run):
+//!
+//! ```no_run
+//! # use embedded_recruitment_task::{hardening::Sandbox, server::Server};
+//! let server = Server::new("0.0.0.0:8080")?.capture_to("/var/lib/captures");
+//! Sandbox::new().allow_write("/var/lib/captures").apply()?;
+//! server.run()?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use landlock::{
+    path_beneath_rules, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr, RulesetStatus,
+    ABI,
+};
+use log::{info, warn};
+use seccompiler::{BpfProgram, SeccompAction, SeccompFilter};
+use std::{collections::BTreeMap, io, path::PathBuf};
+
+// Newest Landlock ABI whose filesystem rights are requested; older kernels get a subset
+const LANDLOCK_ABI: ABI = ABI::V5;
+
+// System calls a running server makes: I/O on sockets and open files, memory,
+// threads for the pools, timers, and connections to a relay upstream
+const ALLOWED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_read,
+    libc::SYS_write,
+    libc::SYS_readv,
+    libc::SYS_writev,
+    libc::SYS_pread64,
+    libc::SYS_pwrite64,
+    libc::SYS_close,
+    libc::SYS_lseek,
+    libc::SYS_fstat,
+    libc::SYS_newfstatat,
+    libc::SYS_statx,
+    libc::SYS_openat,
+    libc::SYS_mkdirat,
+    libc::SYS_fsync,
+    libc::SYS_fdatasync,
+    libc::SYS_fcntl,
+    libc::SYS_ioctl,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mprotect,
+    libc::SYS_mremap,
+    libc::SYS_madvise,
+    libc::SYS_brk,
+    libc::SYS_membarrier,
+    libc::SYS_futex,
+    libc::SYS_sched_yield,
+    libc::SYS_sched_getaffinity,
+    libc::SYS_nanosleep,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_clock_gettime,
+    libc::SYS_gettimeofday,
+    libc::SYS_getrandom,
+    libc::SYS_getpid,
+    libc::SYS_gettid,
+    libc::SYS_rt_sigaction,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_sigaltstack,
+    libc::SYS_restart_syscall,
+    libc::SYS_clone,
+    libc::SYS_clone3,
+    libc::SYS_set_robust_list,
+    libc::SYS_rseq,
+    libc::SYS_prctl,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+    libc::SYS_socket,
+    libc::SYS_connect,
+    libc::SYS_accept4,
+    libc::SYS_shutdown,
+    libc::SYS_sendto,
+    libc::SYS_recvfrom,
+    libc::SYS_sendmsg,
+    libc::SYS_recvmsg,
+    libc::SYS_setsockopt,
+    libc::SYS_getsockopt,
+    libc::SYS_getsockname,
+    libc::SYS_getpeername,
+    libc::SYS_ppoll,
+    libc::SYS_epoll_create1,
+    libc::SYS_epoll_ctl,
+    libc::SYS_epoll_pwait,
+    libc::SYS_eventfd2,
+    libc::SYS_pipe2,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_accept,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_poll,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_epoll_wait,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_open,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_stat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_mkdir,
+];
+
+/// What the sandbox could enforce on the running kernel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SandboxStatus {
+    /// Whether Landlock restricts filesystem access, at least in part
+    pub filesystem_restricted: bool,
+}
+
+/// Filesystem access left to the server once sandboxed; nothing by default
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sandbox {
+    readable: Vec<PathBuf>,
+    writable: Vec<PathBuf>,
+}
+
+impl Sandbox {
+    /// A sandbox allowing no filesystem access at all
+    pub fn new() -> Self {
+        Sandbox::default()
+    }
+
+    /// Allows reading files below `path`
+    pub fn allow_read(mut self, path: impl Into<PathBuf>) -> Self {
+        self.readable.push(path.into());
+        self
+    }
+
+    /// Allows reading, creating and writing files below `path`
+    pub fn allow_write(mut self, path: impl Into<PathBuf>) -> Self {
+        self.writable.push(path.into());
+        self
+    }
+
+    /// Restricts the whole process for the rest of its life. Fails if the seccomp
+    /// filter cannot be installed; a kernel without Landlock is only reported.
+    pub fn apply(&self) -> io::Result<SandboxStatus> {
+        // Landlock first: the seccomp filter does not allow its system calls
+        let status = self.restrict_filesystem()?;
+        let filesystem_restricted = status != RulesetStatus::NotEnforced;
+        if !filesystem_restricted {
+            warn!("Landlock is not available; filesystem access is not restricted");
+        }
+
+        let arch = std::env::consts::ARCH
+            .try_into()
+            .map_err(io::Error::other)?;
+        let rules = ALLOWED_SYSCALLS
+            .iter()
+            .map(|&syscall| (syscall, Vec::new()))
+            .collect::<BTreeMap<_, _>>();
+        let filter = SeccompFilter::new(
+            rules,
+            SeccompAction::Errno(libc::EPERM as u32),
+            SeccompAction::Allow,
+            arch,
+        )
+        .map_err(io::Error::other)?;
+        let program: BpfProgram = filter.try_into().map_err(io::Error::other)?;
+        seccompiler::apply_filter_all_threads(&program).map_err(io::Error::other)?;
+
+        info!("Sandbox applied ({:?} filesystem restrictions)", status);
+        Ok(SandboxStatus {
+            filesystem_restricted,
+        })
+    }
+
+    fn restrict_filesystem(&self) -> io::Result<RulesetStatus> {
+        let all = AccessFs::from_all(LANDLOCK_ABI);
+        let status = Ruleset::default()
+            .handle_access(all)
+            .and_then(|ruleset| ruleset.create())
+            .and_then(|ruleset| {
+                ruleset.add_rules(path_beneath_rules(
+                    &self.readable,
+                    AccessFs::from_read(LANDLOCK_ABI),
+                ))
+            })
+            .and_then(|ruleset| ruleset.add_rules(path_beneath_rules(&self.writable, all)))
+            .and_then(|ruleset| ruleset.restrict_self())
+            .map_err(io::Error::other)?;
+        Ok(status.ruleset)
+    }
+}
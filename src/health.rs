@@ -0,0 +1,144 @@
+//! Liveness and readiness of a server.
+//!
+//! The two answer different questions. A server is *live* while its accept loop
+//! keeps turning and no request handler is stuck; if it is not, restarting the
+//! process is the only cure. It is *ready* while it is live, accepting (not
+//! stopped or draining), not overloaded and able to reach its relay upstream;
+//! if it is not, new devices should be sent elsewhere for a while.
+//!
+//! [`Server::health`](crate::server::Server::health) reports both. A
+//! [`HealthEndpoint`] serves them over HTTP for probes such as Kubernetes':
+//! `GET /livez` and `GET /readyz` answer `200` or `503`, listing what is wrong
+//! in the body.
+
+use log::{error, info, warn};
+use std::{
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+/// How long the accept loop may go without turning, or a handler may spend on
+/// the requests of one read, before the server counts as no longer live
+pub const LIVENESS_TIMEOUT: Duration = Duration::from_secs(30);
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1); // For reading a probe's request
+const POLL_INTERVAL: Duration = Duration::from_millis(100); // Between checks for `Drop`
+
+/// The server's health at one moment
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Health {
+    /// The accept loop and the request handlers are making progress
+    pub live: bool,
+    /// Live, accepting, not overloaded and with its dependencies reachable
+    pub ready: bool,
+    /// What keeps the server from being live or ready, one line each
+    pub problems: Vec<String>,
+}
+
+/// HTTP endpoint answering health probes, until dropped
+pub struct HealthEndpoint {
+    addr: SocketAddr,
+    stopped: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl HealthEndpoint {
+    /// Serves `/livez` and `/readyz` on `addr`, calling `probe` for each request
+    pub fn start<F>(addr: &str, probe: F) -> io::Result<Self>
+    where
+        F: Fn() -> Health + Send + 'static,
+    {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stopped_clone = Arc::clone(&stopped);
+        let thread = thread::Builder::new()
+            .name("health".to_string())
+            .spawn(move || {
+                while !stopped_clone.load(Ordering::Relaxed) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            if let Err(e) = answer(stream, &probe) {
+                                warn!("Failed to answer health probe: {}", e);
+                            }
+                        }
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                        Err(e) => error!("Error accepting health probe: {}", e),
+                    }
+                }
+            })?;
+        info!("Health endpoint listening on {}", addr);
+        Ok(HealthEndpoint {
+            addr,
+            stopped,
+            thread: Some(thread),
+        })
+    }
+
+    /// Address the endpoint is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for HealthEndpoint {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Answers one probe; probes are rare and small, so on the endpoint's own thread
+fn answer(stream: TcpStream, probe: &impl Fn() -> Health) -> io::Result<()> {
+    stream.set_nonblocking(false)?; // Accepted sockets may inherit non-blocking mode
+    stream.set_read_timeout(Some(PROBE_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // Headers are not needed, but unread ones would make closing reset the connection
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(path)) => match path {
+            "/livez" | "/readyz" => {
+                let health = probe();
+                let healthy = match path {
+                    "/livez" => health.live,
+                    _ => health.ready,
+                };
+                match healthy {
+                    true => ("200 OK", "ok\n".to_string()),
+                    false => (
+                        "503 Service Unavailable",
+                        health.problems.iter().map(|p| format!("{}\n", p)).collect(),
+                    ),
+                }
+            }
+            _ => ("404 Not Found", "not found\n".to_string()),
+        },
+        _ => (
+            "405 Method Not Allowed",
+            "only GET is supported\n".to_string(),
+        ),
+    };
+    write!(
+        &stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
@@ -0,0 +1,117 @@
+//! Recently closed connections.
+//!
+//! Finding out why a device dropped at 02:13 should not mean searching the
+//! logs. The server keeps the last [`DEFAULT_HISTORY`] closed connections in a
+//! [`ConnectionHistory`], each with its peer, the device it named, how long it
+//! was open, how many requests it sent and why it closed. The size is changed
+//! with [`Server::set_history_capacity`].
+//!
+//! Operators list them with
+//! [`Server::connection_history`](crate::server::Server::connection_history),
+//! or over the protocol with the admin request `ConnectionHistoryRequest`,
+//! which only an authorizer can allow; see [`crate::authz`].
+//!
+//! [`Server::set_history_capacity`]: crate::server::Server::set_history_capacity
+
+use crate::message::{close::Reason, ConnectionRecord};
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Closed connections kept unless changed with
+/// [`Server::set_history_capacity`](crate::server::Server::set_history_capacity)
+pub const DEFAULT_HISTORY: usize = 256;
+
+/// A connection that has closed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClosedConnection {
+    /// Number of the connection, as given to observers
+    pub id: u64,
+    /// Address of the client, if known
+    pub peer: Option<SocketAddr>,
+    /// Device the client named itself, if any
+    pub device: Option<String>,
+    /// When a worker picked the connection up
+    pub connected_at: SystemTime,
+    /// How long it was open
+    pub duration: Duration,
+    /// Requests handled on it
+    pub messages: u64,
+    /// Why it closed; see [`crate::close`]
+    pub reason: Reason,
+    /// What happened, such as the error that closed it
+    pub detail: String,
+}
+
+impl From<&ClosedConnection> for ConnectionRecord {
+    fn from(closed: &ClosedConnection) -> Self {
+        ConnectionRecord {
+            connection_id: closed.id,
+            peer: closed.peer.map(|peer| peer.to_string()).unwrap_or_default(),
+            device: closed.device.clone().unwrap_or_default(),
+            connected_at_ms: (closed.connected_at.duration_since(UNIX_EPOCH))
+                .map_or(0, |since| since.as_millis() as u64),
+            duration_ms: closed.duration.as_millis() as u64,
+            messages: closed.messages,
+            reason: closed.reason as i32,
+            detail: closed.detail.clone(),
+        }
+    }
+}
+
+/// The last closed connections, dropping the oldest when full
+#[derive(Debug)]
+pub struct ConnectionHistory {
+    closed: Mutex<(usize, VecDeque<ClosedConnection>)>, // Capacity and the connections
+}
+
+impl ConnectionHistory {
+    /// Creates a history of at most `capacity` connections
+    pub fn new(capacity: usize) -> Self {
+        ConnectionHistory {
+            closed: Mutex::new((capacity, VecDeque::new())),
+        }
+    }
+
+    /// Keeps at most `capacity` connections from now on, dropping the oldest
+    /// ones over it
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut closed = self.closed.lock().unwrap();
+        closed.0 = capacity;
+        let excess = closed.1.len().saturating_sub(capacity);
+        closed.1.drain(..excess);
+    }
+
+    /// Adds `connection`, dropping the oldest if the history is full
+    pub fn record(&self, connection: ClosedConnection) {
+        let mut closed = self.closed.lock().unwrap();
+        let (capacity, connections) = &mut *closed;
+        if *capacity == 0 {
+            return;
+        }
+        if connections.len() >= *capacity {
+            connections.pop_front();
+        }
+        connections.push_back(connection);
+    }
+
+    /// The last `limit` connections of `device`, or of every client if
+    /// `None`, newest first
+    pub fn recent(&self, device: Option<&str>, limit: usize) -> Vec<ClosedConnection> {
+        let closed = self.closed.lock().unwrap();
+        (closed.1.iter().rev())
+            .filter(|connection| device.is_none() || connection.device.as_deref() == device)
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+impl Default for ConnectionHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY)
+    }
+}
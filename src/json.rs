@@ -0,0 +1,69 @@
+//! JSON form of the messages.
+//!
+//! With the `json` feature, every message type implements `serde::Serialize`
+//! and `serde::Deserialize` following the proto3 JSON mapping, generated by
+//! pbjson from the same `.proto` files as the messages: fields are
+//! lowerCamelCase, enums are written by name, `bytes` as base64 and 64-bit
+//! integers as strings, and fields at their default value are left out. A
+//! oneof appears as the one field that is set:
+//!
+//! ```
+//! # use embedded_recruitment_task::{json, message::{client_message, AddRequest, ClientMessage}};
+//! let request = ClientMessage {
+//!     message: Some(client_message::Message::AddRequest(AddRequest { a: 1, b: 2 })),
+//!     message_id: 7,
+//!     ..Default::default()
+//! };
+//! let value = json::to_json(&request)?;
+//! assert_eq!(value, serde_json::json!({ "addRequest": { "a": 1, "b": 2 }, "messageId": "7" }));
+//! assert_eq!(json::from_json::<ClientMessage>(value)?, request);
+//! # Ok::<(), serde_json::Error>(())
+//! ```
+//!
+//! An enum value this build does not know, such as an error code added by a
+//! newer peer, cannot be written by name, so converting a message holding one
+//! fails.
+
+use crate::message::{
+    close, dead_letter_record, diagnostic_check, error_response, fault_rule, go_away, log_event,
+    transform_request,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+pub use serde_json::{Error, Value};
+
+/// The JSON form of `message`
+pub fn to_json(message: &impl Serialize) -> Result<Value, Error> {
+    serde_json::to_value(message)
+}
+
+/// The message of type `M` whose JSON form is `value`
+pub fn from_json<M: DeserializeOwned>(value: Value) -> Result<M, Error> {
+    serde_json::from_value(value)
+}
+
+// prost 0.11 enums only have `from_i32`; the generated serde code uses `TryFrom`
+macro_rules! try_from_i32 {
+    ($($enum:ty),*) => {
+        $(
+            impl TryFrom<i32> for $enum {
+                type Error = i32;
+
+                fn try_from(value: i32) -> Result<Self, i32> {
+                    Self::from_i32(value).ok_or(value)
+                }
+            }
+        )*
+    };
+}
+
+try_from_i32!(
+    close::Reason,
+    dead_letter_record::Reason,
+    diagnostic_check::Status,
+    error_response::Code,
+    fault_rule::Action,
+    go_away::Reason,
+    log_event::Level,
+    transform_request::Op
+);
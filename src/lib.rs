@@ -1,5 +1,157 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "message")]
+extern crate alloc;
+
+mod fmt;
+
+#[cfg(feature = "server")]
+pub mod authz;
+#[cfg(feature = "server")]
+pub mod availability;
+#[cfg(feature = "message")]
+pub mod batch;
+#[cfg(feature = "message")]
+pub mod calc;
+#[cfg(feature = "std")]
+pub mod capture;
+#[cfg(feature = "std")]
+pub mod close;
+#[cfg(feature = "server")]
+pub mod cluster;
+#[cfg(feature = "message")]
+pub mod codec;
+#[cfg(feature = "message")]
+pub mod compat;
+#[cfg(feature = "client")]
+pub mod connect;
+#[cfg(feature = "server")]
+pub mod deadletter;
+#[cfg(feature = "message")]
+pub mod dedup;
+#[cfg(feature = "server")]
+pub mod diagnostics;
+#[cfg(feature = "discovery")]
+pub mod discovery;
+#[cfg(feature = "client")]
+pub mod failover;
+#[cfg(feature = "fault-injection")]
+pub mod fault;
+pub mod fixed;
+#[cfg(feature = "message")]
+pub mod flow;
+#[cfg(feature = "message")]
+pub mod handler;
+#[cfg(all(feature = "handover", unix))]
+pub mod handover;
+#[cfg(all(feature = "hardening", target_os = "linux"))]
+pub mod hardening;
+#[cfg(feature = "server")]
+pub mod health;
+#[cfg(feature = "server")]
+pub mod history;
+#[cfg(feature = "json")]
+pub mod json;
+#[cfg(feature = "server")]
+pub mod link;
+#[cfg(feature = "json-log")]
+pub mod logging;
+#[cfg(feature = "std")]
+pub mod loglimit;
+#[cfg(feature = "server")]
+pub mod logtail;
+#[cfg(feature = "server")]
+pub mod mailbox;
+#[cfg(feature = "server")]
+pub mod middleware;
+#[cfg(feature = "native-plugins")]
+pub mod native;
+#[cfg(feature = "server")]
+pub mod observer;
+#[cfg(feature = "server")]
+pub mod outbox;
+#[cfg(feature = "server")]
+pub mod overload;
+#[cfg(feature = "server")]
+pub mod panics;
+#[cfg(feature = "std")]
+pub mod pcapng;
+#[cfg(all(feature = "named-pipe", windows))]
+pub mod pipe;
+#[cfg(feature = "message")]
+pub mod priority;
+#[cfg(all(feature = "privileges", unix))]
+pub mod privileges;
+#[cfg(feature = "message")]
+pub mod protocol;
+#[cfg(feature = "client")]
+pub mod proxy;
+#[cfg(feature = "quic")]
+pub mod quic;
+#[cfg(feature = "server")]
+pub mod quota;
+#[cfg(feature = "server")]
+mod relay;
+#[cfg(feature = "server")]
+pub mod resume;
+#[cfg(feature = "message")]
+pub mod retained;
+#[cfg(feature = "server")]
+pub mod router;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+#[cfg(feature = "message")]
+pub mod sequence;
+#[cfg(all(feature = "windows-service", windows))]
+pub mod service;
+#[cfg(feature = "message")]
+pub mod session;
+#[cfg(feature = "std")]
+pub mod sharded;
+#[cfg(feature = "message")]
+pub mod share;
+#[cfg(feature = "server")]
+pub mod shutdown;
+#[cfg(feature = "message")]
+pub mod slab;
+#[cfg(feature = "smoltcp")]
+pub mod smoltcp_client;
+#[cfg(feature = "server")]
+pub mod socket;
+#[cfg(feature = "client")]
+pub mod spool;
+#[cfg(feature = "server")]
+pub mod stats;
+#[cfg(feature = "std")]
+mod sync;
+#[cfg(feature = "server")]
+pub mod tenant;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "server")]
+pub mod throttle;
+#[cfg(feature = "message")]
+pub mod topic;
+pub mod transport;
+#[cfg(feature = "server")]
+pub mod validation;
+#[cfg(feature = "server")]
+pub mod vhost;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "server")]
+pub mod watchdog;
+#[cfg(feature = "std")]
+pub mod wirelog;
+
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "server")]
 pub mod server;
 
+#[cfg(feature = "message")]
 pub mod message {
     include!(concat!(env!("OUT_DIR"), "/messages.rs"));
+    #[cfg(feature = "json")]
+    include!(concat!(env!("OUT_DIR"), "/messages.serde.rs"));
 }
@@ -0,0 +1,94 @@
+//! Connections of other transports, served like TCP ones.
+//!
+//! The server's handler works on a [`Link`]: a byte stream it can read with a
+//! timeout, peek into and shut down from another thread. A TCP socket is one.
+//! A [`Listener`] added with [`Server::listener`] accepts links of another
+//! transport, such as a named pipe, and hands them to the server through
+//! [`Incoming`]. From there they take the same path as TCP connections: the
+//! connection limit and overload policy, the worker pool, the router and its
+//! middleware, authorization, quotas, tenants, topics and statistics.
+//!
+//! [`Server::listener`]: crate::server::Server::listener
+
+use crate::server::Incoming;
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
+    time::Duration,
+};
+
+/// A client connection as the server's handler uses it
+pub trait Link: Read + Write + Send {
+    /// Address of the client, if the transport has one
+    fn peer_addr(&self) -> Option<SocketAddr>;
+
+    /// Makes reads fail with `WouldBlock` or `TimedOut` once `timeout` passes
+    /// without data; `None` waits for ever
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Makes writes fail once `timeout` passes without progress; `None` waits for ever
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Copies what has arrived into `buf` without consuming it or waiting; the
+    /// server orders connections waiting for a worker by the request found there
+    fn peek(&self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Closes one or both directions. Shutting reading down makes a read blocked
+    /// on another handle to the connection return at once.
+    fn shutdown(&self, how: Shutdown) -> io::Result<()>;
+
+    /// Another handle to the same connection, for closing it from another thread
+    fn try_clone(&self) -> io::Result<Box<dyn Link>>;
+
+    /// Host name the client asked for in its TLS handshake (SNI), if the
+    /// transport has one; a name the server has a virtual host for picks it
+    fn server_name(&self) -> Option<String> {
+        None
+    }
+}
+
+impl Link for TcpStream {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        TcpStream::peer_addr(self).ok()
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.set_nonblocking(true)?;
+        let peeked = TcpStream::peek(self, buf);
+        self.set_nonblocking(false)?;
+        match peeked {
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(0),
+            peeked => peeked,
+        }
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        TcpStream::shutdown(self, how)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Link>> {
+        Ok(Box::new(TcpStream::try_clone(self)?))
+    }
+}
+
+/// Accepts the connections of a transport other than TCP for a server
+pub trait Listener: Send + Sync {
+    /// Accepts connections and hands each to `incoming` until [`stop`](Self::stop)
+    /// is called. The server runs it on a thread of its own.
+    fn run(&self, incoming: &Incoming) -> io::Result<()>;
+
+    /// Makes [`run`](Self::run) return; the server calls it once it stops
+    /// accepting. Connections already handed over are the server's to close.
+    fn stop(&self);
+
+    /// Where the listener can be reached, for logs
+    fn name(&self) -> String;
+}
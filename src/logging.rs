@@ -0,0 +1,173 @@
+//! Structured JSON log output.
+//!
+//! [`JsonLogger`] is a `log` backend writing one JSON object per line: the
+//! time in milliseconds since the Unix epoch, the level, the target and the
+//! message, followed by the record's key-value pairs. The server attaches the
+//! same keys to its connection events wherever they apply:
+//!
+//! | Key             | Value                                          |
+//! |-----------------|------------------------------------------------|
+//! | `connection_id` | Number of the connection, counting from 1      |
+//! | `peer`          | Address of the client                          |
+//! | `message_type`  | Request type, as in `MessageKind::name`        |
+//! | `duration_ms`   | How long the request or connection took        |
+//!
+//! so a log pipeline can select on fields instead of parsing messages:
+//!
+//! ```text
+//! {"time_ms":1700000000000,"level":"WARN","target":"embedded_recruitment_task::server","message":"Slow request: ...","connection_id":3,"peer":"10.0.0.7:50412","message_type":"AddRequest","duration_ms":250}
+//! ```
+//!
+//! Numbers, booleans and strings keep their JSON types and a `None` is written
+//! as `null`; any other value is written as its `Display` text.
+
+use log::kv::{self, Key, Value, VisitSource, VisitValue};
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use serde_json::{Map, Number};
+use std::{
+    fmt,
+    io::{self, Write},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// `log` backend writing each record as a line of JSON
+pub struct JsonLogger {
+    level: LevelFilter,
+    output: Mutex<Box<dyn Write + Send>>, // Stderr unless replaced with `with_writer`
+}
+
+impl JsonLogger {
+    /// Creates a logger writing records up to `level` to stderr
+    pub fn new(level: LevelFilter) -> Self {
+        JsonLogger {
+            level,
+            output: Mutex::new(Box::new(io::stderr())),
+        }
+    }
+
+    /// Writes to `output` instead of stderr
+    pub fn with_writer(mut self, output: impl Write + Send + 'static) -> Self {
+        self.output = Mutex::new(Box::new(output));
+        self
+    }
+
+    /// Installs the logger for the rest of the process; fails if a logger
+    /// is already installed
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        let level = self.level;
+        log::set_logger(Box::leak(Box::new(self)))?;
+        log::set_max_level(level);
+        Ok(())
+    }
+
+    /// The JSON object written for `record`
+    pub fn format(&self, record: &Record) -> Map<String, serde_json::Value> {
+        let time_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64);
+        let mut fields = Map::new();
+        fields.insert("time_ms".into(), time_ms.into());
+        fields.insert("level".into(), record.level().as_str().into());
+        fields.insert("target".into(), record.target().into());
+        fields.insert("message".into(), record.args().to_string().into());
+        // `Fields` never fails, so neither does the visit
+        let _ = record.key_values().visit(&mut Fields(&mut fields));
+        fields
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut line = serde_json::to_vec(&self.format(record)).unwrap_or_default();
+        line.push(b'\n');
+        let mut output = self.output.lock().unwrap_or_else(|e| e.into_inner());
+        // Nowhere left to report a failure to log
+        let _ = output.write_all(&line);
+    }
+
+    fn flush(&self) {
+        let _ = self
+            .output
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .flush();
+    }
+}
+
+impl fmt::Debug for JsonLogger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JsonLogger")
+            .field("level", &self.level)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Installs a [`JsonLogger`] writing records up to `level` to stderr
+pub fn init_json(level: LevelFilter) -> Result<(), SetLoggerError> {
+    JsonLogger::new(level).init()
+}
+
+// Adds each key-value pair of a record to its JSON object
+struct Fields<'a>(&'a mut Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.insert(key.as_str().to_string(), json(&value));
+        Ok(())
+    }
+}
+
+// Numbers, booleans, strings and `None` as themselves, anything else as its text
+fn json(value: &Value) -> serde_json::Value {
+    let mut json = Json(serde_json::Value::Null);
+    // `Json` never fails either
+    let _ = value.visit(&mut json);
+    json.0
+}
+
+struct Json(serde_json::Value);
+
+impl<'v> VisitValue<'v> for &mut Json {
+    fn visit_any(&mut self, value: Value) -> Result<(), kv::Error> {
+        self.0 = value.to_string().into();
+        Ok(())
+    }
+
+    fn visit_null(&mut self) -> Result<(), kv::Error> {
+        self.0 = serde_json::Value::Null;
+        Ok(())
+    }
+
+    fn visit_u64(&mut self, value: u64) -> Result<(), kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_i64(&mut self, value: i64) -> Result<(), kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_f64(&mut self, value: f64) -> Result<(), kv::Error> {
+        self.0 = Number::from_f64(value).map_or_else(|| value.to_string().into(), Into::into);
+        Ok(())
+    }
+
+    fn visit_bool(&mut self, value: bool) -> Result<(), kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_str(&mut self, value: &str) -> Result<(), kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+}
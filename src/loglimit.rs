@@ -0,0 +1,205 @@
+//! Rate limiting and sampling of noisy log events.
+//!
+//! Some events happen as often as a peer cares to cause them: a scanning bot
+//! connecting and sending garbage produces a decode error per connection, and
+//! a device flooding echoes produces a line per message. Each such event
+//! belongs to a [`LogClass`], and [`LogLimits`] caps how many lines of each
+//! class are written per second, optionally writing only one in every `n`
+//! events first. Lines over the limit are counted, and the count is reported
+//! with the next line of the class that is written:
+//!
+//! ```text
+//! WARN 1532 client error log lines not written (rate limit)
+//! ```
+//!
+//! The limits are process-wide, like the logger they protect, and shared by
+//! every server in the process: [`global`] returns them, as does
+//! [`Server::log_limits`]. Audit lines and lifecycle events such as startup
+//! and shutdown are never limited.
+//!
+//! [`Server::log_limits`]: crate::server::Server::log_limits
+
+use log::{log, Level};
+use std::{
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        OnceLock,
+    },
+    time::Instant,
+};
+
+/// Kind of frequent log event, limited separately from the others
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LogClass {
+    /// Connections accepted, refused and closed
+    Connection,
+    /// Connections closed on an error, such as a frame that does not decode
+    ClientError,
+    /// Requests refused while overloaded or over quota
+    Refusal,
+    /// Requests that were slow, timed out or went unanswered
+    SlowRequest,
+    /// Lines the built-in handlers write for each message
+    Message,
+}
+
+impl LogClass {
+    /// Every class, in declaration order
+    pub const ALL: [LogClass; 5] = [
+        LogClass::Connection,
+        LogClass::ClientError,
+        LogClass::Refusal,
+        LogClass::SlowRequest,
+        LogClass::Message,
+    ];
+
+    /// Name used in the suppression notice
+    pub fn name(self) -> &'static str {
+        match self {
+            LogClass::Connection => "connection",
+            LogClass::ClientError => "client error",
+            LogClass::Refusal => "refusal",
+            LogClass::SlowRequest => "slow request",
+            LogClass::Message => "message",
+        }
+    }
+
+    /// Lines written per second unless changed with [`LogLimits::set_rate_limit`]
+    pub fn default_rate_limit(self) -> u32 {
+        match self {
+            LogClass::Connection | LogClass::Message => 100,
+            LogClass::ClientError | LogClass::Refusal | LogClass::SlowRequest => 10,
+        }
+    }
+}
+
+/// Per-class limits on how many log lines are written
+#[derive(Debug)]
+pub struct LogLimits {
+    epoch: Instant, // Window starts are kept as microseconds since this
+    classes: [ClassLimit; LogClass::ALL.len()],
+}
+
+// Settings and counters of one class; updated without a lock, so a window
+// boundary crossed by several threads at once may let a few extra lines through
+#[derive(Debug)]
+struct ClassLimit {
+    rate_limit: AtomicU32,   // Lines per second
+    sample_rate: AtomicU32,  // One event in this many is considered at all
+    seen: AtomicU64,         // Events so far, for sampling
+    window_start: AtomicU64, // Start of the current one-second window
+    written: AtomicU32,      // Lines written in the current window
+    pending: AtomicU64,      // Suppressed since the last written line
+    suppressed: AtomicU64,   // Suppressed in total
+}
+
+impl LogLimits {
+    /// Creates limits with each class's default rate and no sampling
+    pub fn new() -> Self {
+        LogLimits {
+            epoch: Instant::now(),
+            classes: LogClass::ALL.map(|class| ClassLimit {
+                rate_limit: AtomicU32::new(class.default_rate_limit()),
+                sample_rate: AtomicU32::new(1),
+                seen: AtomicU64::new(0),
+                window_start: AtomicU64::new(0),
+                written: AtomicU32::new(0),
+                pending: AtomicU64::new(0),
+                suppressed: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Sets how many lines of `class` are written per second at most;
+    /// `u32::MAX` writes them all and 0 none
+    pub fn set_rate_limit(&self, class: LogClass, lines_per_second: u32) {
+        self.class(class)
+            .rate_limit
+            .store(lines_per_second, Ordering::Relaxed);
+    }
+
+    /// Considers only one event of `class` in every `one_in`, before the rate
+    /// limit applies; 1, the default, considers them all
+    pub fn set_sample_rate(&self, class: LogClass, one_in: u32) {
+        self.class(class)
+            .sample_rate
+            .store(one_in.max(1), Ordering::Relaxed);
+    }
+
+    /// Lines of `class` not written so far
+    pub fn suppressed(&self, class: LogClass) -> u64 {
+        self.class(class).suppressed.load(Ordering::Relaxed)
+    }
+
+    /// Counts one event of `class` and returns whether to write its line. When
+    /// it is written after others were not, a notice with their number is
+    /// logged first at `level`.
+    pub fn admit(&self, class: LogClass, level: Level) -> bool {
+        let limit = self.class(class);
+        let seen = limit.seen.fetch_add(1, Ordering::Relaxed);
+        let sampled = seen.is_multiple_of(u64::from(limit.sample_rate.load(Ordering::Relaxed)));
+        if !sampled || !self.within_rate(limit) {
+            limit.pending.fetch_add(1, Ordering::Relaxed);
+            limit.suppressed.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        let pending = limit.pending.swap(0, Ordering::Relaxed);
+        if pending > 0 {
+            log!(
+                level,
+                "{} {} log lines not written (rate limit)",
+                pending,
+                class.name()
+            );
+        }
+        true
+    }
+
+    // Takes one line from the current window's allowance, starting a new
+    // window once a second has passed
+    fn within_rate(&self, limit: &ClassLimit) -> bool {
+        let rate_limit = limit.rate_limit.load(Ordering::Relaxed);
+        if rate_limit == u32::MAX {
+            return true;
+        }
+        let now = self.epoch.elapsed().as_micros() as u64;
+        let start = limit.window_start.load(Ordering::Relaxed);
+        if now.saturating_sub(start) >= 1_000_000
+            && (limit.window_start)
+                .compare_exchange(start, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            limit.written.store(0, Ordering::Relaxed);
+        }
+        limit.written.fetch_add(1, Ordering::Relaxed) < rate_limit
+    }
+
+    fn class(&self, class: LogClass) -> &ClassLimit {
+        &self.classes[class as usize]
+    }
+}
+
+impl Default for LogLimits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The limits applied to the crate's own log lines
+pub fn global() -> &'static LogLimits {
+    static LIMITS: OnceLock<LogLimits> = OnceLock::new();
+    LIMITS.get_or_init(LogLimits::new)
+}
+
+// Logs like `log::log!` when `$level` is enabled and the class is within its
+// limits; events that would not be logged anyway are not counted
+macro_rules! limited {
+    ($class:expr, $level:expr, $($arg:tt)+) => {{
+        let level = $level;
+        if ::log::log_enabled!(level) && $crate::loglimit::global().admit($class, level) {
+            ::log::log!(level, $($arg)+);
+        }
+    }};
+}
+
+pub(crate) use limited;
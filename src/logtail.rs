@@ -0,0 +1,258 @@
+//! Log records streamed to operators over the protocol.
+//!
+//! [`LogTail`] is a `log` backend that keeps the most recent records and
+//! hands new ones to every connection tailing the log, besides passing them
+//! on to the backend it wraps, if any:
+//!
+//! ```no_run
+//! # use embedded_recruitment_task::{logtail::LogTail, server::Server};
+//! # use log::LevelFilter;
+//! let tail = LogTail::new(LevelFilter::Info).install().unwrap();
+//! let server = Server::new("0.0.0.0:8080")?.log_tail(tail);
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! A connection sends a `TailLogs` request with the least severe level it
+//! wants and a filter on the target and message. It is answered with the
+//! number of recent records that follow, then gets each record as a
+//! `LogEvent` on the request's stream until it closes. The records go
+//! through the connection's mailbox, dropping the oldest when it is full, so
+//! a slow operator never holds up logging.
+//!
+//! `TailLogs` is an admin request: without an authorizer that allows it the
+//! server refuses it, and so it does on servers without a `LogTail`. While
+//! someone tails at a level more verbose than the installed one, the `log`
+//! maximum level is raised to match, and lowered again once they are gone.
+//! Records of the `wire` target are never streamed, since sending them would
+//! log more of them.
+
+use crate::codec;
+use crate::mailbox::{Mailbox, MailboxLimits, Overflow, Posted};
+use crate::message::{log_event, server_message, LogEvent, LogField, ServerMessage, TailLogs};
+use log::kv::{self, Key, Value, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use prost::bytes::Bytes;
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Records kept for new tails unless changed with [`LogTail::capacity`]
+pub const DEFAULT_CAPACITY: usize = 256;
+
+/// `log` backend keeping recent records and streaming new ones to connections
+pub struct LogTail {
+    level: LevelFilter,          // Kept, and passed on, up to this level
+    inner: Option<Box<dyn Log>>, // Also gets every record
+    capacity: usize,             // Recent records kept
+    recent: Mutex<VecDeque<Arc<Recorded>>>,
+    followers: Mutex<Vec<Follower>>,
+}
+
+// A record with the level it was logged at, for matching against later tails
+struct Recorded {
+    level: Level,
+    event: LogEvent,
+}
+
+// A connection tailing the log
+struct Follower {
+    level: LevelFilter,
+    filter: String,
+    stream_id: u32,
+    mailbox: Arc<Mailbox>,
+}
+
+impl Follower {
+    fn wants(&self, recorded: &Recorded) -> bool {
+        recorded.level <= self.level
+            && (recorded.event.target.contains(&self.filter)
+                || recorded.event.message.contains(&self.filter))
+    }
+
+    // Hands `recorded` to the connection; false once it has closed
+    fn post(&self, recorded: &Recorded) -> bool {
+        let message = ServerMessage {
+            message: Some(server_message::Message::LogEvent(recorded.event.clone())),
+            stream_id: self.stream_id,
+            ..Default::default()
+        };
+        let Ok(frame) = codec::encode(&message) else {
+            return true; // Too large to send; later records may not be
+        };
+        let limits = MailboxLimits {
+            overflow: Overflow::DropOldest,
+            ..Default::default()
+        };
+        self.mailbox.post(Bytes::from(frame), limits) != Posted::Dropped
+    }
+}
+
+impl LogTail {
+    /// Creates a tail keeping records up to `level`, and more verbose ones
+    /// while a connection asks for them
+    pub fn new(level: LevelFilter) -> Self {
+        LogTail {
+            level,
+            inner: None,
+            capacity: DEFAULT_CAPACITY,
+            recent: Mutex::new(VecDeque::new()),
+            followers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Also passes the records up to the tail's level to `inner`, such as a
+    /// [`JsonLogger`](crate::logging::JsonLogger) writing the log to a file
+    pub fn forward_to(mut self, inner: impl Log + 'static) -> Self {
+        self.inner = Some(Box::new(inner));
+        self
+    }
+
+    /// Keeps the last `records` records for connections that start tailing
+    pub fn capacity(mut self, records: usize) -> Self {
+        self.capacity = records;
+        self
+    }
+
+    /// Installs the tail for the rest of the process, returning it for
+    /// [`Server::log_tail`]; fails if a logger is already installed
+    ///
+    /// [`Server::log_tail`]: crate::server::Server::log_tail
+    pub fn install(self) -> Result<&'static LogTail, SetLoggerError> {
+        let tail: &'static LogTail = Box::leak(Box::new(self));
+        log::set_logger(tail)?;
+        log::set_max_level(tail.level);
+        Ok(tail)
+    }
+
+    /// Connections tailing the log
+    pub fn followers(&self) -> usize {
+        self.followers.lock().unwrap().len()
+    }
+
+    // Streams records `request` asks for to `mailbox`, the recent ones first,
+    // returning how many of those there were
+    pub(crate) fn follow(&self, request: &TailLogs, stream_id: u32, mailbox: Arc<Mailbox>) -> u32 {
+        let level = match log_event::Level::from_i32(request.level) {
+            Some(log_event::Level::Error) => LevelFilter::Error,
+            Some(log_event::Level::Warn) => LevelFilter::Warn,
+            Some(log_event::Level::Debug) => LevelFilter::Debug,
+            Some(log_event::Level::Trace) => LevelFilter::Trace,
+            _ => LevelFilter::Info,
+        };
+        let follower = Follower {
+            level,
+            filter: request.filter.clone(),
+            stream_id,
+            mailbox,
+        };
+        // Under the lock, so no record falls between the recent ones and the live ones
+        let mut followers = self.followers.lock().unwrap();
+        let recent: Vec<_> = (self.recent.lock().unwrap().iter())
+            .filter(|recorded| follower.wants(recorded))
+            .cloned()
+            .collect();
+        for recorded in &recent {
+            follower.post(recorded);
+        }
+        followers.push(follower);
+        self.raise_max_level(&followers);
+        recent.len() as u32
+    }
+
+    // Lets through the most verbose level anyone wants
+    fn raise_max_level(&self, followers: &[Follower]) {
+        let wanted = followers.iter().map(|follower| follower.level);
+        log::set_max_level(wanted.fold(self.level, Ord::max));
+    }
+}
+
+impl Log for LogTail {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() <= self.level {
+            if let Some(inner) = &self.inner {
+                inner.log(record);
+            }
+        }
+        if record.target() == "wire" || !self.enabled(record.metadata()) {
+            return;
+        }
+        let recorded = Arc::new(Recorded {
+            level: record.level(),
+            event: event(record),
+        });
+        let mut followers = self.followers.lock().unwrap();
+        let before = followers.len();
+        followers.retain(|follower| !follower.wants(&recorded) || follower.post(&recorded));
+        if followers.len() != before {
+            self.raise_max_level(&followers);
+        }
+        drop(followers);
+        if record.level() <= self.level && self.capacity > 0 {
+            let mut recent = self.recent.lock().unwrap();
+            if recent.len() == self.capacity {
+                recent.pop_front();
+            }
+            recent.push_back(recorded);
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(inner) = &self.inner {
+            inner.flush();
+        }
+    }
+}
+
+impl fmt::Debug for LogTail {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogTail")
+            .field("level", &self.level)
+            .field("capacity", &self.capacity)
+            .field("followers", &self.followers())
+            .finish_non_exhaustive()
+    }
+}
+
+// The message sent for `record`
+fn event(record: &Record) -> LogEvent {
+    let time_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64);
+    let level = match record.level() {
+        Level::Error => log_event::Level::Error,
+        Level::Warn => log_event::Level::Warn,
+        Level::Info => log_event::Level::Info,
+        Level::Debug => log_event::Level::Debug,
+        Level::Trace => log_event::Level::Trace,
+    };
+    let mut fields = Fields(Vec::new());
+    // `Fields` never fails, so neither does the visit
+    let _ = record.key_values().visit(&mut fields);
+    LogEvent {
+        time_ms,
+        level: level as i32,
+        target: record.target().to_string(),
+        message: record.args().to_string(),
+        fields: fields.0,
+    }
+}
+
+// Collects a record's key-value pairs as text
+struct Fields(Vec<LogField>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.push(LogField {
+            key: key.to_string(),
+            value: value.to_string(),
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+//! Bounded queues of frames pushed to each connection.
+//!
+//! [`Server::broadcast`] hands every connected device's handler the same
+//! encoded frame through the connection's mailbox, which the handler empties
+//! as it polls for deliveries. A device that stops reading leaves its handler
+//! stuck writing, and its mailbox would grow with every broadcast. Each
+//! mailbox therefore holds at most [`MailboxLimits::capacity`] frames, and the
+//! [`Overflow`] policy decides what happens to one more:
+//!
+//! - [`Overflow::DropOldest`], the default, makes room by dropping the oldest
+//!   frame waiting, so a slow device misses messages but gets the latest.
+//! - [`Overflow::Disconnect`] closes the slow consumer's connection; its
+//!   device gets everything or reconnects and knows it may have missed some.
+//! - [`Overflow::Block`] makes the publisher wait for room, up to a timeout
+//!   after which the frame is dropped for that connection, so one stuck
+//!   device cannot hold broadcasts up forever.
+//!
+//! Dropped frames are counted in [`Stats::mailbox_drops`] and disconnects in
+//! [`Stats::slow_consumer_disconnects`]; [`Stats::mailbox_frames`] and
+//! [`Stats::deepest_mailbox`] show what is waiting.
+//!
+//! [`Server::broadcast`]: crate::server::Server::broadcast
+//! [`Stats::mailbox_drops`]: crate::stats::Stats::mailbox_drops
+//! [`Stats::slow_consumer_disconnects`]: crate::stats::Stats::slow_consumer_disconnects
+//! [`Stats::mailbox_frames`]: crate::stats::Stats::mailbox_frames
+//! [`Stats::deepest_mailbox`]: crate::stats::Stats::deepest_mailbox
+
+use crate::codec::Bytes;
+use crate::sync::{AtomicBool, Condvar, Mutex, Ordering};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Frames a mailbox holds by default
+pub const DEFAULT_MAILBOX_CAPACITY: usize = 256;
+
+/// What happens to a frame pushed to a full mailbox
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Drop the oldest frame waiting to make room
+    #[default]
+    DropOldest,
+    /// Close the connection that is not keeping up
+    Disconnect,
+    /// Wait up to this long for room, then drop the new frame for that connection
+    Block(Duration),
+}
+
+/// Size of each connection's mailbox and what to do when it is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MailboxLimits {
+    /// Frames waiting before the mailbox counts as full, at least one
+    pub capacity: usize,
+    /// What a full mailbox does with one more frame
+    pub overflow: Overflow,
+}
+
+impl Default for MailboxLimits {
+    fn default() -> Self {
+        MailboxLimits {
+            capacity: DEFAULT_MAILBOX_CAPACITY,
+            overflow: Overflow::default(),
+        }
+    }
+}
+
+/// What became of a posted frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Posted {
+    /// Waiting for the handler
+    Queued,
+    /// Waiting, after the oldest frame was dropped to make room
+    QueuedDroppingOldest,
+    /// Not queued: no room in time, or the connection is closing
+    Dropped,
+    /// Not queued, and the connection should be closed; reported once
+    Overflowed,
+}
+
+/// Frames handed to one connection by other threads
+#[derive(Default)]
+#[cfg_attr(not(loom), derive(Debug))]
+pub struct Mailbox {
+    pub(crate) listening: AtomicBool, // Set once the connection names a device, after which its handler polls
+    pub(crate) parked: AtomicBool, // Set while its session waits to be resumed, with no handler to take the frames
+    state: Mutex<State>,
+    room: Condvar, // Signalled when the handler takes the frames, or the connection closes
+}
+
+#[derive(Debug, Default)]
+struct State {
+    frames: VecDeque<Bytes>, // Shared with every other recipient, not copied
+    overflowed: bool,        // Refused a frame under `Overflow::Disconnect`
+    closed: bool,
+}
+
+impl Mailbox {
+    /// Adds `frame` for the handler, applying `limits` if the mailbox is full
+    pub fn post(&self, frame: Bytes, limits: MailboxLimits) -> Posted {
+        let capacity = limits.capacity.max(1);
+        let mut state = self.state.lock().unwrap();
+        if state.closed || state.overflowed {
+            return Posted::Dropped;
+        }
+        if state.frames.len() < capacity {
+            state.frames.push_back(frame);
+            return Posted::Queued;
+        }
+        match limits.overflow {
+            Overflow::DropOldest => {
+                state.frames.pop_front();
+                state.frames.push_back(frame);
+                Posted::QueuedDroppingOldest
+            }
+            Overflow::Disconnect => {
+                state.overflowed = true;
+                state.frames.clear(); // Never sent; the connection is closing
+                Posted::Overflowed
+            }
+            Overflow::Block(timeout) => {
+                let deadline = Instant::now() + timeout;
+                while state.frames.len() >= capacity && !state.closed {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return Posted::Dropped;
+                    }
+                    state = self.room.wait_timeout(state, remaining).unwrap().0;
+                }
+                if state.closed {
+                    return Posted::Dropped;
+                }
+                state.frames.push_back(frame);
+                Posted::Queued
+            }
+        }
+    }
+
+    /// Takes every waiting frame; `None` once the mailbox overflowed and the connection has to go
+    pub fn take(&self) -> Option<VecDeque<Bytes>> {
+        let mut state = self.state.lock().unwrap();
+        if state.overflowed {
+            return None;
+        }
+        let frames = std::mem::take(&mut state.frames);
+        self.room.notify_all();
+        Some(frames)
+    }
+
+    /// Refuses further frames and releases blocked publishers
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        state.frames.clear();
+        self.room.notify_all();
+    }
+
+    /// Frames waiting
+    pub fn depth(&self) -> usize {
+        self.state.lock().unwrap().frames.len()
+    }
+
+    pub(crate) fn is_listening(&self) -> bool {
+        self.listening.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn is_parked(&self) -> bool {
+        self.parked.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,96 @@
+//! Middleware wrapped around request handling.
+//!
+//! Cross-cutting concerns such as authentication, rate limiting, logging,
+//! metrics and validation are written once as a [`Middleware`] and added to a
+//! server with [`Server::layer`](crate::server::Server::layer), rather than
+//! built into the connection loop. Each layer sees the request first, and
+//! decides whether to answer it itself or pass it on with [`Next::run`], which
+//! calls the next layer and, after the last one, the router (or the relay
+//! upstream). The first layer added is the outermost:
+//!
+//! ```
+//! # use embedded_recruitment_task::{middleware, server::Server};
+//! let server = Server::new("localhost:0")?.layer(middleware::from_fn(|request, next| {
+//!     let kind = request.kind;
+//!     let response = next.run(request);
+//!     log::info!("{:?} answered: {}", kind, response.is_ok());
+//!     response
+//! }));
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! Layers run on the connection's worker, inside the handler timeout if one is
+//! set, and only for requests that are not answered from the dedup window or
+//! refused as busy.
+
+use crate::handler::MessageKind;
+use crate::message::{client_message, server_message};
+use std::{io, net::SocketAddr, sync::Arc};
+
+/// A request on its way to the handler
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    /// The request itself
+    pub message: client_message::Message,
+    /// Type of `message`
+    pub kind: MessageKind,
+    /// Message ID the client gave the request; 0 if none
+    pub message_id: u64,
+    /// Stream the request arrived on
+    pub stream_id: u32,
+    /// Address of the client, if known
+    pub peer: Option<SocketAddr>,
+}
+
+/// Outcome of handling a request. An error leaves the request unanswered, as
+/// when the relay upstream cannot be reached, so the device retries.
+pub type Response = io::Result<server_message::Message>;
+
+/// A layer around request handling
+pub trait Middleware: Send + Sync {
+    /// Answers `request`, usually by passing it on with `next.run(request)`
+    fn call(&self, request: Request, next: Next<'_>) -> Response;
+}
+
+/// The layers after the current one, and the handler after those
+pub struct Next<'a> {
+    layers: &'a [Arc<dyn Middleware>],
+    handler: &'a dyn Fn(client_message::Message) -> Response,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(
+        layers: &'a [Arc<dyn Middleware>],
+        handler: &'a dyn Fn(client_message::Message) -> Response,
+    ) -> Self {
+        Next { layers, handler }
+    }
+
+    /// Passes `request` on to the next layer, or to the handler after the last one
+    pub fn run(self, request: Request) -> Response {
+        match self.layers.split_first() {
+            Some((layer, layers)) => layer.call(request, Next { layers, ..self }),
+            None => (self.handler)(request.message),
+        }
+    }
+}
+
+/// Middleware calling a closure
+pub struct FromFn<F>(F);
+
+/// Turns a closure taking the request and the rest of the chain into middleware
+pub fn from_fn<F>(f: F) -> FromFn<F>
+where
+    F: Fn(Request, Next<'_>) -> Response + Send + Sync,
+{
+    FromFn(f)
+}
+
+impl<F> Middleware for FromFn<F>
+where
+    F: Fn(Request, Next<'_>) -> Response + Send + Sync,
+{
+    fn call(&self, request: Request, next: Next<'_>) -> Response {
+        (self.0)(request, next)
+    }
+}
@@ -0,0 +1,253 @@
+//! Handlers loaded from native dynamic libraries.
+//!
+//! For processing that must run at native speed, a [`PluginDir`] loads the
+//! dynamic libraries in a directory and offers each request to them, as
+//! middleware (see [`crate::middleware`]). Unlike [`crate::wasm`] plugins,
+//! native ones run unsandboxed inside the server process: a crash in one
+//! takes the server down, so only load libraries you would link in.
+//!
+//! ```no_run
+//! # use embedded_recruitment_task::{native::PluginDir, server::Server};
+//! let plugins = PluginDir::open("/usr/lib/broker/plugins")?;
+//! let server = Server::new("localhost:0")?.layer(plugins.clone());
+//! // Later, from an admin command:
+//! plugins.unload("libacme.so");
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! # C ABI
+//!
+//! A plugin exports `plugin_register`, an `extern "C" fn() -> *const
+//! PluginVtable` returning a table that lives as long as the library. Its
+//! [`abi_version`](PluginVtable::abi_version) must be [`ABI_VERSION`], which
+//! changes whenever the table does. `handle` gets the request as an encoded
+//! `ClientMessage` and returns [`PASS`] to pass the request on, or [`HANDLED`]
+//! after filling in the response buffer with an encoded `ServerMessage`, which
+//! the server hands back to `free` once it has copied it. Any other status is
+//! logged and the request passed on.
+//!
+//! Plugins are offered a request in the order of their file names; the first
+//! to answer it wins.
+
+use crate::message::{client_message, server_message, ClientMessage, ServerMessage};
+use crate::middleware::{Middleware, Next, Request, Response};
+use libloading::Library;
+use log::{info, warn};
+use prost::Message;
+use std::{
+    collections::BTreeMap,
+    env::consts::DLL_EXTENSION,
+    fs, io,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+/// Version of [`PluginVtable`] this server understands
+pub const ABI_VERSION: u32 = 1;
+
+/// Symbol a plugin library exports to be registered
+pub const ENTRY_SYMBOL: &str = "plugin_register";
+
+/// Status of a plugin passing the request on
+pub const PASS: i32 = 0;
+
+/// Status of a plugin that has filled in the response
+pub const HANDLED: i32 = 1;
+
+/// Bytes allocated by the plugin
+#[repr(C)]
+#[derive(Debug)]
+pub struct Buffer {
+    pub ptr: *mut u8,
+    pub len: usize,
+}
+
+/// Functions a plugin registers
+#[repr(C)]
+pub struct PluginVtable {
+    /// [`ABI_VERSION`] the plugin was built against
+    pub abi_version: u32,
+    /// Answers the encoded request of `len` bytes at `request` through `response`
+    pub handle: unsafe extern "C" fn(request: *const u8, len: usize, response: *mut Buffer) -> i32,
+    /// Releases a response filled in by `handle`
+    pub free: unsafe extern "C" fn(response: Buffer),
+}
+
+// A registered plugin; unloaded once the last request using it is done
+struct Plugin {
+    handle: unsafe extern "C" fn(*const u8, usize, *mut Buffer) -> i32,
+    free: unsafe extern "C" fn(Buffer),
+    _library: Library, // Keeps the functions above valid
+}
+
+/// The plugins of one directory, loaded and unloaded while the server runs
+#[derive(Clone)]
+pub struct PluginDir {
+    dir: PathBuf,
+    plugins: Arc<RwLock<BTreeMap<String, Arc<Plugin>>>>, // By file name
+}
+
+impl PluginDir {
+    /// Loads every dynamic library in `dir`; an error only if the directory
+    /// cannot be read, as libraries that fail to load are logged and skipped
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let plugins = PluginDir {
+            dir: dir.into(),
+            plugins: Arc::new(RwLock::new(BTreeMap::new())),
+        };
+        plugins.rescan()?;
+        Ok(plugins)
+    }
+
+    /// Loads the libraries added to the directory since it was last scanned,
+    /// returning their names
+    pub fn rescan(&self) -> io::Result<Vec<String>> {
+        let mut added = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|e| e != DLL_EXTENSION) {
+                continue;
+            }
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            if self.plugins.read().unwrap().contains_key(name) {
+                continue;
+            }
+            match self.load(name) {
+                Ok(()) => added.push(name.to_string()),
+                Err(e) => warn!("Skipping plugin {}: {}", path.display(), e),
+            }
+        }
+        Ok(added)
+    }
+
+    /// Loads the library `name` in the directory, replacing a plugin of that
+    /// name; requests already in the replaced plugin finish there
+    pub fn load(&self, name: &str) -> io::Result<()> {
+        if name.contains(['/', '\\']) || name == ".." {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} is not a file name", name),
+            ));
+        }
+        let plugin = Arc::new(register(self.dir.join(name))?);
+        self.plugins
+            .write()
+            .unwrap()
+            .insert(name.to_string(), plugin);
+        info!("Loaded plugin {}", name);
+        Ok(())
+    }
+
+    /// Unloads the plugin `name` once the requests in it are done; whether it
+    /// was loaded
+    pub fn unload(&self, name: &str) -> bool {
+        let unloaded = self.plugins.write().unwrap().remove(name).is_some();
+        if unloaded {
+            info!("Unloaded plugin {}", name);
+        }
+        unloaded
+    }
+
+    /// Names of the loaded plugins, in the order they see requests
+    pub fn loaded(&self) -> Vec<String> {
+        self.plugins.read().unwrap().keys().cloned().collect()
+    }
+
+    /// The first response a plugin gives to `request`; `None` if all of them
+    /// pass it on or fail
+    pub fn handle(&self, request: &client_message::Message) -> Option<server_message::Message> {
+        let plugins: Vec<_> = self
+            .plugins
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, plugin)| (name.clone(), plugin.clone()))
+            .collect();
+        let request = ClientMessage {
+            message: Some(request.clone()),
+            ..Default::default()
+        }
+        .encode_to_vec();
+        plugins
+            .iter()
+            .find_map(|(name, plugin)| match plugin.call(&request) {
+                Ok(response) => response,
+                Err(e) => {
+                    warn!("Plugin {}: {}", name, e);
+                    None
+                }
+            })
+    }
+}
+
+// Loads the library at `path` and checks what it registers
+fn register(path: PathBuf) -> io::Result<Plugin> {
+    let invalid = |detail: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), detail),
+        )
+    };
+    // Safety: loading runs the library's initializers; plugin directories only
+    // hold libraries trusted to run in the server
+    let library = unsafe { Library::new(&path) }.map_err(|e| invalid(e.to_string()))?;
+    // Safety: the ABI defines the entry point with this signature
+    let vtable = unsafe {
+        let entry = library
+            .get::<extern "C" fn() -> *const PluginVtable>(ENTRY_SYMBOL.as_bytes())
+            .map_err(|e| invalid(e.to_string()))?;
+        entry().as_ref()
+    };
+    let Some(vtable) = vtable else {
+        return Err(invalid("registered no functions".to_string()));
+    };
+    if vtable.abi_version != ABI_VERSION {
+        return Err(invalid(format!(
+            "built for ABI version {}, not {}",
+            vtable.abi_version, ABI_VERSION
+        )));
+    }
+    Ok(Plugin {
+        handle: vtable.handle,
+        free: vtable.free,
+        _library: library,
+    })
+}
+
+impl Plugin {
+    // The response to an encoded request, if the plugin gives one
+    fn call(&self, request: &[u8]) -> Result<Option<server_message::Message>, String> {
+        let mut response = Buffer {
+            ptr: std::ptr::null_mut(),
+            len: 0,
+        };
+        // Safety: `request` and `response` outlive the call, as the ABI requires
+        match unsafe { (self.handle)(request.as_ptr(), request.len(), &mut response) } {
+            PASS => Ok(None),
+            HANDLED if response.ptr.is_null() => Err("handled without a response".to_string()),
+            HANDLED => {
+                // Safety: the plugin filled in `response` and owns it until `free`
+                let bytes = unsafe { std::slice::from_raw_parts(response.ptr, response.len) };
+                let decoded = ServerMessage::decode(bytes);
+                // Safety: handed back once, as filled in, after its last use
+                unsafe { (self.free)(response) };
+                match decoded.map_err(|e| e.to_string())?.message {
+                    Some(response) => Ok(Some(response)),
+                    None => Err("response without a message".to_string()),
+                }
+            }
+            status => Err(format!("failed with status {}", status)),
+        }
+    }
+}
+
+impl Middleware for PluginDir {
+    fn call(&self, request: Request, next: Next<'_>) -> Response {
+        match self.handle(&request.message) {
+            Some(response) => Ok(response),
+            None => next.run(request),
+        }
+    }
+}
@@ -0,0 +1,60 @@
+//! Hooks into the lifecycle of each connection.
+//!
+//! An [`Observer`] added with [`Server::observer`](crate::server::Server::observer)
+//! is told when a connection opens, when each of its requests has been
+//! answered, when it fails and when it closes, so an application can keep its
+//! own session state or audit trail without changes to the server:
+//!
+//! ```
+//! # use embedded_recruitment_task::{observer::{ConnectionInfo, Observer}, server::Server};
+//! # use std::time::Duration;
+//! struct Audit;
+//!
+//! impl Observer for Audit {
+//!     fn on_disconnect(&self, connection: &ConnectionInfo, duration: Duration) {
+//!         log::info!("Connection {} closed after {:?}", connection.id, duration);
+//!     }
+//! }
+//!
+//! let server = Server::new("localhost:0")?.observer(Audit);
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! Every method does nothing by default. They are called on the connection's
+//! worker, in the order observers were added, so a slow observer holds the
+//! connection up; hand lengthy work off to a thread of its own.
+
+use crate::handler::MessageKind;
+use std::{io, net::SocketAddr, time::Duration, time::SystemTime};
+
+/// The connection an event is about
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// Number of the connection, counting from 1 since the server started
+    pub id: u64,
+    /// Address of the client, if known
+    pub peer: Option<SocketAddr>,
+    /// When a worker picked the connection up
+    pub connected_at: SystemTime,
+}
+
+/// Callbacks for the events of each connection
+pub trait Observer: Send + Sync {
+    /// A worker has picked the connection up
+    fn on_connect(&self, _connection: &ConnectionInfo) {}
+
+    /// The client has proven it is `identity`. Reserved: the server does not
+    /// authenticate clients yet, so this is never called.
+    fn on_authenticated(&self, _connection: &ConnectionInfo, _identity: &str) {}
+
+    /// A request of type `kind` has been answered, `elapsed` after handling started.
+    /// Requests answered from the dedup window, refused as busy or left
+    /// unanswered are not reported.
+    fn on_message(&self, _connection: &ConnectionInfo, _kind: MessageKind, _elapsed: Duration) {}
+
+    /// The connection failed with `error` and is about to close
+    fn on_error(&self, _connection: &ConnectionInfo, _error: &io::Error) {}
+
+    /// The connection has closed, `duration` after it opened
+    fn on_disconnect(&self, _connection: &ConnectionInfo, _duration: Duration) {}
+}
@@ -0,0 +1,304 @@
+//! Messages queued for devices by ID.
+//!
+//! A device names itself in the `device_id` of its `ResumeRequest`. An
+//! application can then address it with
+//! [`Server::send_to`](crate::server::Server::send_to), whether it is connected
+//! or not: messages wait in the device's [`Outboxes`] queue and are delivered,
+//! in the order they were queued, as `Delivery` messages. A device that is
+//! offline gets them right after the `ResumeResponse` of its next connection;
+//! one that is connected within [`DELIVERY_POLL_INTERVAL`].
+//!
+//! Each device's deliveries are numbered from 1 (see [`crate::sequence`]) for
+//! as long as the server runs, so a device can spot one that went missing. A
+//! queue holds at most [`DEFAULT_MAX_QUEUED`] messages and drops the oldest to
+//! make room; a message not delivered within [`DEFAULT_QUEUE_TTL`] is dropped
+//! too. A message can also be given a shorter time to live of its own, so a
+//! command that is stale by then is not carried out by a device reconnecting
+//! hours later. Delivery is at most once: a message handed to a connection
+//! that then breaks is not queued again. Dropped messages go to a
+//! [`DeadLetterSink`].
+//!
+//! The last messages taken for each device, as many as its queue holds, are
+//! kept a while longer, so a device that spots a gap can have them sent
+//! again with a `ResyncRequest` (see [`Outboxes::replay`]).
+//!
+//! Expired messages are otherwise only dropped when their queue is next used,
+//! so the server sweeps every queue each [`SWEEP_INTERVAL`] (see
+//! [`Outboxes::sweep`]). The sweep also forgets the queues of devices that are
+//! gone: empty and unused for the queues' time to live, with neither a
+//! connection nor a session to resume. Such a device's numbering starts again
+//! at 1, as the device starts a new session anyway.
+//!
+//! [`Server::broadcast`](crate::server::Server::broadcast) sends a payload to
+//! every connected device at once instead. Broadcasts are not queued for
+//! devices that are offline and carry [`BROADCAST_SEQUENCE`], outside the
+//! numbering of each device's deliveries.
+
+use crate::deadletter::{DeadLetter, DeadLetterSink, DeadLetters, DeadReason};
+use crate::message::Delivery;
+use crate::sequence::FIRST_SEQUENCE;
+use prost::bytes::Bytes;
+use std::{
+    collections::{HashMap, VecDeque},
+    ops::Range,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Sequence number of broadcast deliveries, below those of queued ones
+pub const BROADCAST_SEQUENCE: u64 = 0;
+
+/// Messages each device's queue holds by default
+pub const DEFAULT_MAX_QUEUED: usize = 100;
+
+/// How long a message waits for its device by default
+pub const DEFAULT_QUEUE_TTL: Duration = Duration::from_secs(3600);
+
+/// How often a connected device's queue is checked between its requests
+pub const DELIVERY_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How often the server sweeps the queues by default
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Messages dropped instead of delivered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Dropped {
+    /// Dropped for waiting longer than its or the queue's time to live
+    pub expired: usize,
+    /// Dropped to make room in a full queue
+    pub overflowed: usize,
+}
+
+// One device's queue
+struct Queue {
+    next_sequence: u64, // Kept once the queue empties, so numbers are never reused
+    messages: VecDeque<Queued>,
+    delivered: VecDeque<Delivery>, // Taken most recently, for `replay`
+    used: Instant,                 // Last pushed to or taken from
+}
+
+// A message waiting in a queue
+struct Queued {
+    delivery: Delivery,
+    queued: Instant,
+    ttl: Option<Duration>, // Its own time to live, if it was given one
+}
+
+/// The queue of every device that has been sent a message
+pub struct Outboxes {
+    max_queued: usize,
+    ttl: Duration,
+    device_limits: HashMap<String, usize>, // Lower limits of devices with a quota of their own
+    default_device_limit: Option<usize>,   // Lower limit of the other devices
+    queues: HashMap<String, Queue>,
+    sink: Arc<dyn DeadLetterSink>, // Handed every dropped message
+}
+
+impl Outboxes {
+    /// Creates queues of at most `max_queued` messages, each kept for `ttl`
+    pub fn new(max_queued: usize, ttl: Duration) -> Self {
+        Outboxes {
+            max_queued,
+            ttl,
+            device_limits: HashMap::new(),
+            default_device_limit: None,
+            queues: HashMap::new(),
+            sink: Arc::new(DeadLetters::default()),
+        }
+    }
+
+    /// Hands dropped messages to `sink` from now on, instead of a
+    /// [`DeadLetters`] buffer
+    pub fn set_sink(&mut self, sink: Arc<dyn DeadLetterSink>) {
+        self.sink = sink;
+    }
+
+    /// The sink dropped messages are handed to
+    pub fn sink(&self) -> &Arc<dyn DeadLetterSink> {
+        &self.sink
+    }
+
+    /// Queues hold at most `max_queued` messages from now on, each kept for `ttl`
+    pub fn set_limits(&mut self, max_queued: usize, ttl: Duration) {
+        self.max_queued = max_queued;
+        self.ttl = ttl;
+    }
+
+    /// `device`'s queue holds at most `max_queued` messages, if fewer than
+    /// every queue may; the default device limit applies again if `None`
+    pub fn set_device_limit(&mut self, device: &str, max_queued: Option<usize>) {
+        match max_queued {
+            Some(max_queued) => self.device_limits.insert(device.to_string(), max_queued),
+            None => self.device_limits.remove(device),
+        };
+    }
+
+    /// The queues of devices without a limit of their own hold at most
+    /// `max_queued` messages, if fewer than every queue may
+    pub fn set_default_device_limit(&mut self, max_queued: Option<usize>) {
+        self.default_device_limit = max_queued;
+    }
+
+    // Most messages `device`'s queue holds
+    fn limit(&self, device: &str) -> usize {
+        let own = self.device_limits.get(device).copied();
+        own.or(self.default_device_limit)
+            .map_or(self.max_queued, |limit| limit.min(self.max_queued))
+    }
+
+    /// Queues `payload` for `device`, returning its sequence number and what
+    /// was dropped to make room. It expires after `ttl`, if given, or the
+    /// queue's time to live, whichever is shorter. A [`Bytes`] payload is
+    /// shared, not copied, so the same one can be queued for many devices.
+    pub fn push(
+        &mut self,
+        device: &str,
+        payload: impl Into<Bytes>,
+        ttl: Option<Duration>,
+    ) -> (u64, Dropped) {
+        let limit = self.limit(device);
+        let queue = self
+            .queues
+            .entry(device.to_string())
+            .or_insert_with(|| Queue {
+                next_sequence: FIRST_SEQUENCE,
+                messages: VecDeque::new(),
+                delivered: VecDeque::new(),
+                used: Instant::now(),
+            });
+        queue.used = Instant::now();
+        let mut dropped = Self::expire(queue, self.ttl, device, &*self.sink);
+        let sequence = queue.next_sequence;
+        queue.next_sequence += 1;
+        queue.messages.push_back(Queued {
+            delivery: Delivery {
+                sequence,
+                payload: payload.into(),
+            },
+            queued: Instant::now(),
+            ttl,
+        });
+        while queue.messages.len() > limit {
+            let Some(oldest) = queue.messages.pop_front() else {
+                break;
+            };
+            bury(&*self.sink, device, oldest.delivery, DeadReason::Overflowed);
+            dropped.overflowed += 1;
+        }
+        (sequence, dropped)
+    }
+
+    /// Takes every message waiting for `device`, oldest first, along with what
+    /// expired while waiting
+    pub fn take(&mut self, device: &str) -> (Vec<Delivery>, Dropped) {
+        let limit = self.limit(device);
+        let Some(queue) = self.queues.get_mut(device) else {
+            return (Vec::new(), Dropped::default());
+        };
+        queue.used = Instant::now();
+        let dropped = Self::expire(queue, self.ttl, device, &*self.sink);
+        let deliveries: Vec<Delivery> = queue.messages.drain(..).map(|q| q.delivery).collect();
+        queue.delivered.extend(deliveries.iter().cloned()); // Payloads are shared
+        let excess = queue.delivered.len().saturating_sub(limit);
+        queue.delivered.drain(..excess);
+        (deliveries, dropped)
+    }
+
+    /// The messages numbered in `sequences` that were taken for `device` and
+    /// are still kept, oldest first. Only the run that ends the range without
+    /// a gap is returned, since the messages before a gap are lost anyway.
+    pub fn replay(&self, device: &str, sequences: Range<u64>) -> Vec<Delivery> {
+        let Some(queue) = self.queues.get(device) else {
+            return Vec::new();
+        };
+        let mut run: Vec<Delivery> = Vec::new();
+        let mut expected = sequences.end;
+        let newest_first = queue.delivered.iter().rev();
+        for delivery in newest_first.skip_while(|d| d.sequence >= sequences.end) {
+            if delivery.sequence < sequences.start || delivery.sequence + 1 != expected {
+                break;
+            }
+            expected = delivery.sequence;
+            run.push(delivery.clone());
+        }
+        run.reverse();
+        run
+    }
+
+    /// Drops the expired messages of every queue, and forgets the queues left
+    /// empty that were not used for the queues' time to live, unless `keep`
+    /// holds for their device. Returns what was dropped and how many queues
+    /// were forgotten.
+    pub fn sweep(&mut self, keep: impl Fn(&str) -> bool) -> (Dropped, usize) {
+        let (ttl, sink) = (self.ttl, &*self.sink);
+        let before = self.queues.len();
+        let mut dropped = Dropped::default();
+        self.queues.retain(|device, queue| {
+            dropped.expired += Self::expire(queue, ttl, device, sink).expired;
+            !queue.messages.is_empty() || queue.used.elapsed() < ttl || keep(device)
+        });
+        (dropped, before - self.queues.len())
+    }
+
+    /// Messages waiting for `device`, including expired ones not yet dropped
+    pub fn depth(&self, device: &str) -> usize {
+        self.queues
+            .get(device)
+            .map_or(0, |queue| queue.messages.len())
+    }
+
+    /// Messages waiting for each device with any
+    pub fn depths(&self) -> HashMap<String, usize> {
+        self.queues
+            .iter()
+            .filter(|(_, queue)| !queue.messages.is_empty())
+            .map(|(device, queue)| (device.clone(), queue.messages.len()))
+            .collect()
+    }
+
+    /// Queues holding as many messages as they may, which drop their oldest
+    /// for each new one
+    pub fn full(&self) -> usize {
+        (self.queues.iter())
+            .filter(|(device, queue)| queue.messages.len() >= self.limit(device))
+            .count()
+    }
+
+    // Drops the messages in `queue` that have waited too long. With times to
+    // live of their own these need not be at the front.
+    fn expire(
+        queue: &mut Queue,
+        ttl: Duration,
+        device: &str,
+        sink: &dyn DeadLetterSink,
+    ) -> Dropped {
+        let mut dropped = Dropped::default();
+        for message in std::mem::take(&mut queue.messages) {
+            let ttl = message.ttl.map_or(ttl, |own| own.min(ttl));
+            if message.queued.elapsed() < ttl {
+                queue.messages.push_back(message);
+            } else {
+                bury(sink, device, message.delivery, DeadReason::Expired);
+                dropped.expired += 1;
+            }
+        }
+        dropped
+    }
+}
+
+// Hands a message dropped from `device`'s queue to `sink`
+fn bury(sink: &dyn DeadLetterSink, device: &str, delivery: Delivery, reason: DeadReason) {
+    sink.record(DeadLetter {
+        device: device.to_string(),
+        sequence: delivery.sequence,
+        payload: delivery.payload.into(), // Only copied if a connection still shares it
+        reason,
+        dropped_at: SystemTime::now(),
+    });
+}
+
+impl Default for Outboxes {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_QUEUED, DEFAULT_QUEUE_TTL)
+    }
+}
@@ -0,0 +1,159 @@
+//! Shedding load once the server is overloaded.
+//!
+//! Without a policy, an overloaded server lets work queue up: new connections
+//! wait for a free worker and their requests time out on the device. With an
+//! [`OverloadPolicy`] set through
+//! [`Server::set_overload_policy`](crate::server::Server::set_overload_policy),
+//! the server answers at once with an `ErrorResponse { code: BUSY,
+//! retry_after_ms }` instead:
+//!
+//! - A new connection is refused that way, then closed, while the open
+//!   connections or the connections queued for a worker are at their limit.
+//! - A request on an open connection is refused that way while the queue or
+//!   the process's memory is at its limit. Refused requests are not
+//!   remembered for deduplication, so their retries are handled normally.
+//!
+//! Refusals are counted in [`Stats::shed_connections`] and
+//! [`Stats::shed_requests`].
+//!
+//! [`Stats::shed_connections`]: crate::stats::Stats::shed_connections
+//! [`Stats::shed_requests`]: crate::stats::Stats::shed_requests
+
+use crate::message::{error_response, server_message, ErrorResponse};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+// How long a memory reading is reused; reading it costs a file read
+const MEMORY_SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Limits beyond which the server sheds load; all off by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverloadPolicy {
+    /// Accepted connections waiting for a worker
+    pub max_queued: Option<u64>,
+    /// Open connections, including those waiting for a worker
+    pub max_connections: Option<u64>,
+    /// Resident memory of the process, in bytes; only measured on Linux
+    pub max_memory: Option<u64>,
+    /// Suggested wait before retrying, sent with every refusal
+    pub retry_after: Duration,
+}
+
+impl Default for OverloadPolicy {
+    fn default() -> Self {
+        OverloadPolicy {
+            max_queued: None,
+            max_connections: None,
+            max_memory: None,
+            retry_after: Duration::from_secs(1),
+        }
+    }
+}
+
+// The policy as atomics, so handlers can check it without locking
+pub(crate) struct OverloadLimits {
+    max_queued: AtomicU64, // `u64::MAX` disables each limit
+    max_connections: AtomicU64,
+    max_memory: AtomicU64,
+    retry_after_ms: AtomicU64,
+    memory: Mutex<Option<(Instant, u64)>>, // Last resident memory reading
+}
+
+impl OverloadLimits {
+    pub(crate) fn new(policy: OverloadPolicy) -> Self {
+        let limits = OverloadLimits {
+            max_queued: AtomicU64::new(u64::MAX),
+            max_connections: AtomicU64::new(u64::MAX),
+            max_memory: AtomicU64::new(u64::MAX),
+            retry_after_ms: AtomicU64::new(0),
+            memory: Mutex::new(None),
+        };
+        limits.set(policy);
+        limits
+    }
+
+    pub(crate) fn set(&self, policy: OverloadPolicy) {
+        let store = |limit: &AtomicU64, value: Option<u64>| {
+            limit.store(
+                value.map_or(u64::MAX, |v| v.min(u64::MAX - 1)),
+                Ordering::Relaxed,
+            )
+        };
+        store(&self.max_queued, policy.max_queued);
+        store(&self.max_connections, policy.max_connections);
+        store(&self.max_memory, policy.max_memory);
+        self.retry_after_ms.store(
+            policy.retry_after.as_millis().min(u32::MAX.into()) as u64,
+            Ordering::Relaxed,
+        );
+    }
+
+    // Why a new connection should be refused, if it should
+    pub(crate) fn refuse_connection(&self, queued: u64, connections: u64) -> Option<&'static str> {
+        if connections >= self.max_connections.load(Ordering::Relaxed) {
+            Some("connection limit")
+        } else if queued >= self.max_queued.load(Ordering::Relaxed) {
+            Some("queue limit")
+        } else {
+            None
+        }
+    }
+
+    // Whether a new connection would be refused for the number already open
+    pub(crate) fn at_connection_limit(&self, connections: u64) -> bool {
+        connections >= self.max_connections.load(Ordering::Relaxed)
+    }
+
+    // Why a request on an open connection should be refused, if it should
+    pub(crate) fn refuse_request(&self, queued: u64) -> Option<&'static str> {
+        if queued >= self.max_queued.load(Ordering::Relaxed) {
+            return Some("queue limit");
+        }
+        let max_memory = self.max_memory.load(Ordering::Relaxed);
+        if max_memory != u64::MAX && self.resident_memory().is_some_and(|m| m >= max_memory) {
+            return Some("memory limit");
+        }
+        None
+    }
+
+    // The refusal sent to the device
+    pub(crate) fn busy(&self) -> server_message::Message {
+        server_message::Message::ErrorResponse(ErrorResponse {
+            code: error_response::Code::Busy as i32,
+            retry_after_ms: self.retry_after_ms.load(Ordering::Relaxed) as u32,
+            ..Default::default()
+        })
+    }
+
+    // Resident memory, read at most every `MEMORY_SAMPLE_INTERVAL`
+    fn resident_memory(&self) -> Option<u64> {
+        let mut memory = self.memory.lock().unwrap();
+        match *memory {
+            Some((read, bytes)) if read.elapsed() < MEMORY_SAMPLE_INTERVAL => Some(bytes),
+            _ => {
+                let bytes = read_resident_memory()?;
+                *memory = Some((Instant::now(), bytes));
+                Some(bytes)
+            }
+        }
+    }
+}
+
+// The `VmRSS` line of /proc/self/status, which is in kB
+#[cfg(target_os = "linux")]
+fn read_resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_resident_memory() -> Option<u64> {
+    None
+}
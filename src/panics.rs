@@ -0,0 +1,145 @@
+//! Opt-in reporting of panics through the log.
+//!
+//! A connection handler that panics takes its connection down and is counted
+//! in `PoolStats::panicked`, but the panic message only reaches stderr, with
+//! no hint of which device caused it. [`install_hook`] replaces the panic hook
+//! with one that logs every panic at error level under the `panic` target,
+//! with the thread, the location, a backtrace and, on a connection handler,
+//! the connection it was serving:
+//!
+//! | Key             | Value                                          |
+//! |-----------------|------------------------------------------------|
+//! | `thread`        | Name of the thread that panicked               |
+//! | `location`      | File, line and column of the panic             |
+//! | `connection_id` | Connection being handled, if any               |
+//! | `peer`          | Address of its client                          |
+//! | `message_type`  | Request being handled, if any                  |
+//!
+//! These are the fields of the `logging` module, so the JSON logger writes them
+//! as such. Every panic is counted in [`count`], wherever it happened. While
+//! no logger takes error records for the `panic` target, the previous hook
+//! runs instead, so panics are never lost for want of a logger.
+
+use log::{error, log_enabled, Level};
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    cell::Cell,
+    net::SocketAddr,
+    panic,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Once,
+    },
+    thread,
+};
+
+static PANICS: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    // What the current thread is working on, for the report of its panic
+    static CONTEXT: Cell<Option<Context>> = const { Cell::new(None) };
+}
+
+// The connection and request a thread is handling
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Context {
+    connection_id: u64,
+    peer: Option<SocketAddr>,
+    message_type: Option<&'static str>,
+}
+
+// Restores the thread's previous context when dropped
+pub(crate) struct Entered {
+    previous: Option<Context>,
+}
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        CONTEXT.with(|context| context.set(self.previous));
+    }
+}
+
+/// Installs the reporting panic hook; later calls do nothing
+pub fn install_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            PANICS.fetch_add(1, Ordering::Relaxed);
+            if !log_enabled!(target: "panic", Level::Error) {
+                return previous(info);
+            }
+            let thread = thread::current();
+            let name = thread.name().unwrap_or("unnamed");
+            let location = info.location().map(ToString::to_string);
+            let context = current();
+            let peer = context
+                .and_then(|context| context.peer)
+                .map(|p| p.to_string());
+            error!(
+                target: "panic",
+                thread = name,
+                location = location.as_deref(),
+                connection_id = context.map(|context| context.connection_id),
+                peer = peer.as_deref(),
+                message_type = context.and_then(|context| context.message_type);
+                "Thread {} panicked at {}: {}\n{}",
+                name,
+                location.as_deref().unwrap_or("unknown location"),
+                message(info.payload()),
+                Backtrace::force_capture()
+            );
+        }));
+    });
+}
+
+/// Panics seen by the hook since it was installed
+pub fn count() -> u64 {
+    PANICS.load(Ordering::Relaxed)
+}
+
+// Marks the thread as handling connection `connection_id` until the guard drops
+pub(crate) fn enter(connection_id: u64, peer: Option<SocketAddr>) -> Entered {
+    replace(Some(Context {
+        connection_id,
+        peer,
+        message_type: None,
+    }))
+}
+
+// Marks the thread's connection as handling a `message_type` request until the guard drops
+pub(crate) fn request(message_type: &'static str) -> Entered {
+    replace(current().map(|context| Context {
+        message_type: Some(message_type),
+        ..context
+    }))
+}
+
+// The thread's context, to carry over to a thread working on its behalf
+pub(crate) fn current() -> Option<Context> {
+    CONTEXT.with(Cell::get)
+}
+
+// Takes over the context of the thread this one works for until the guard drops
+pub(crate) fn resume(context: Option<Context>) -> Entered {
+    replace(context)
+}
+
+fn replace(context: Option<Context>) -> Entered {
+    Entered {
+        previous: CONTEXT.with(|current| current.replace(context)),
+    }
+}
+
+// The text passed to `panic!`, which is a `&str` or a `String` unless raised
+// with `panic_any`
+fn message(payload: &(dyn Any + Send)) -> &str {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => match payload.downcast_ref::<String>() {
+            Some(message) => message,
+            None => "Box<dyn Any>",
+        },
+    }
+}
@@ -0,0 +1,172 @@
+//! Export of captured traffic as pcapng for Wireshark.
+//!
+//! Each packet is one application frame (length prefix and protobuf body)
+//! behind a one-byte pseudo-header giving its direction, stored with the
+//! `LINKTYPE_USER0` link type. Every connection becomes its own interface,
+//! named after the peer. `wireshark/ert.lua` dissects these packets with
+//! Wireshark's protobuf dissector and `proto/messages.proto`.
+
+use crate::capture::{Direction, Record};
+use crate::fixed;
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
+
+/// Link type reserved for private use, which the Lua dissector registers for
+pub const LINKTYPE_USER0: u16 = 147;
+
+/// Pseudo-header byte for frames sent by the client
+pub const CLIENT_TO_SERVER: u8 = 0;
+
+/// Pseudo-header byte for frames sent by the server
+pub const SERVER_TO_CLIENT: u8 = 1;
+
+const SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const INTERFACE_DESCRIPTION: u32 = 1;
+const ENHANCED_PACKET: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+const OPT_END: u16 = 0;
+const OPT_IF_NAME: u16 = 2;
+const OPT_EPB_FLAGS: u16 = 2;
+
+/// Writes a pcapng section with one interface per connection
+#[derive(Debug)]
+pub struct PcapngWriter<W: Write> {
+    output: W,
+    interfaces: u32,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Writes the section header
+    pub fn new(mut output: W) -> io::Result<Self> {
+        let mut body = Vec::new();
+        body.extend(BYTE_ORDER_MAGIC.to_le_bytes());
+        body.extend(1u16.to_le_bytes()); // Major version
+        body.extend(0u16.to_le_bytes()); // Minor version
+        body.extend((-1i64).to_le_bytes()); // Section length not specified
+        write_block(&mut output, SECTION_HEADER, &body)?;
+        Ok(PcapngWriter {
+            output,
+            interfaces: 0,
+        })
+    }
+
+    /// Adds an interface for one connection, returning its id
+    pub fn add_connection(&mut self, name: &str) -> io::Result<u32> {
+        let mut body = Vec::new();
+        body.extend(LINKTYPE_USER0.to_le_bytes());
+        body.extend(0u16.to_le_bytes()); // Reserved
+        body.extend(0u32.to_le_bytes()); // No snapshot length limit
+        push_option(&mut body, OPT_IF_NAME, name.as_bytes());
+        push_option(&mut body, OPT_END, &[]);
+        write_block(&mut self.output, INTERFACE_DESCRIPTION, &body)?;
+
+        self.interfaces += 1;
+        Ok(self.interfaces - 1)
+    }
+
+    /// Writes one frame; timestamps use the default microsecond resolution
+    pub fn write_frame(
+        &mut self,
+        interface: u32,
+        timestamp: Duration,
+        direction: Direction,
+        frame: &[u8],
+    ) -> io::Result<()> {
+        let micros = timestamp.as_micros() as u64;
+        let (pseudo_header, flags) = match direction {
+            Direction::Inbound => (CLIENT_TO_SERVER, 1u32),
+            Direction::Outbound => (SERVER_TO_CLIENT, 2u32),
+        };
+        let len = frame.len() as u32 + 1;
+
+        let mut body = Vec::with_capacity(frame.len() + 40);
+        body.extend(interface.to_le_bytes());
+        body.extend(((micros >> 32) as u32).to_le_bytes());
+        body.extend((micros as u32).to_le_bytes());
+        body.extend(len.to_le_bytes()); // Captured length
+        body.extend(len.to_le_bytes()); // Original length
+        body.push(pseudo_header);
+        body.extend(frame);
+        pad(&mut body);
+        push_option(&mut body, OPT_EPB_FLAGS, &flags.to_le_bytes());
+        push_option(&mut body, OPT_END, &[]);
+        write_block(&mut self.output, ENHANCED_PACKET, &body)
+    }
+
+    /// Writes a connection's records as frames, returning how many were written.
+    ///
+    /// Inbound records hold raw reads, so they are re-split into frames. Bytes
+    /// that do not parse as a frame are written as one final packet for that
+    /// direction, where the dissector will show them as malformed.
+    pub fn write_connection(&mut self, name: &str, records: &[Record]) -> io::Result<usize> {
+        let interface = self.add_connection(name)?;
+        let mut pending: Vec<u8> = Vec::new(); // Inbound bytes not yet forming a frame
+        let mut broken = false; // Inbound framing was lost; pass the rest through
+        let mut frames = 0;
+
+        for record in records {
+            if record.direction == Direction::Outbound {
+                self.write_frame(interface, record.timestamp, record.direction, &record.bytes)?;
+                frames += 1;
+                continue;
+            }
+
+            pending.extend_from_slice(&record.bytes);
+            while !broken {
+                let len = match fixed::parse_length(&pending) {
+                    Ok(Some((len, prefix))) if pending.len() >= prefix + len => prefix + len,
+                    Ok(_) => break, // Wait for the rest of the frame
+                    Err(_) => {
+                        broken = true;
+                        break;
+                    }
+                };
+                self.write_frame(
+                    interface,
+                    record.timestamp,
+                    Direction::Inbound,
+                    &pending[..len],
+                )?;
+                pending.drain(..len);
+                frames += 1;
+            }
+        }
+
+        if !pending.is_empty() {
+            let timestamp = records
+                .last()
+                .map_or(Duration::ZERO, |record| record.timestamp);
+            self.write_frame(interface, timestamp, Direction::Inbound, &pending)?;
+            frames += 1;
+        }
+        Ok(frames)
+    }
+
+    /// Flushes and returns the underlying writer
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.output.flush()?;
+        Ok(self.output)
+    }
+}
+
+// Block layout: type, total length, body (padded to 32 bits), total length again
+fn write_block(output: &mut impl Write, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let total = (body.len() + 12) as u32;
+    output.write_all(&block_type.to_le_bytes())?;
+    output.write_all(&total.to_le_bytes())?;
+    output.write_all(body)?;
+    output.write_all(&total.to_le_bytes())
+}
+
+fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend(code.to_le_bytes());
+    body.extend((value.len() as u16).to_le_bytes());
+    body.extend(value);
+    pad(body);
+}
+
+fn pad(body: &mut Vec<u8>) {
+    body.resize(body.len().next_multiple_of(4), 0);
+}
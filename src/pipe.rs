@@ -0,0 +1,313 @@
+//! Windows named pipe listener.
+//!
+//! [`PipeServer`] listens on a named pipe such as `\\.\pipe\gateway`, so tools
+//! on the same machine can reach the server where policy blocks TCP ports.
+//! It is a [`Listener`] added with [`Server::listener`]: every client that
+//! opens the pipe gets an instance of its own, which the server's workers
+//! serve like a TCP connection, with the same router, middleware,
+//! authorization, quotas, limits and statistics. While the server has as many
+//! connections open as its overload policy allows, no instance is offered, so
+//! further clients wait in [`connect`] as in a listen backlog. Remote clients
+//! are refused, so the pipe is only reachable locally.
+//!
+//! Clients open the pipe with [`connect`] and talk over the returned
+//! [`Connection`] as over a TCP one.
+//!
+//! [`Server::listener`]: crate::server::Server::listener
+
+use crate::link::{Link, Listener};
+use crate::server::Incoming;
+use crate::transport::{Connection, Transport};
+use log::{info, warn};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, ErrorKind, Read, Write},
+    net::{Shutdown, SocketAddr},
+    os::windows::{
+        ffi::OsStrExt,
+        io::{AsRawHandle, FromRawHandle},
+    },
+    ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use windows_sys::Win32::{
+    Foundation::{ERROR_PIPE_BUSY, ERROR_PIPE_CONNECTED, INVALID_HANDLE_VALUE},
+    Storage::FileSystem::{FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX},
+    System::Pipes::{
+        ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, PeekNamedPipe, WaitNamedPipeW,
+        PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES,
+        PIPE_WAIT,
+    },
+};
+
+/// Prefix of the names of local pipes
+pub const PIPE_PREFIX: &str = r"\\.\pipe\";
+
+// Bytes the system buffers in each direction of an instance
+const PIPE_BUFFER: u32 = 64 * 1024;
+
+// How often a waiting read checks for data, a timeout or a shutdown
+const READ_POLL: Duration = Duration::from_millis(20);
+
+/// Named pipe listener for a [`Server`](crate::server::Server)
+pub struct PipeServer {
+    name: String,
+    first: Mutex<Option<File>>, // Instance created with the listener, so the name is ours
+    stopped: AtomicBool,
+}
+
+impl PipeServer {
+    /// Creates the pipe `name`, which must start with [`PIPE_PREFIX`]. Fails if
+    /// another process already listens on it.
+    pub fn new(name: &str) -> io::Result<Self> {
+        if !name.starts_with(PIPE_PREFIX) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Pipe names start with {}", PIPE_PREFIX),
+            ));
+        }
+        let first = instance(name, FILE_FLAG_FIRST_PIPE_INSTANCE)?;
+        Ok(PipeServer {
+            name: name.to_string(),
+            first: Mutex::new(Some(first)),
+            stopped: AtomicBool::new(false),
+        })
+    }
+}
+
+impl Listener for PipeServer {
+    // Offers one instance at a time and hands it to the server once a client opens it
+    fn run(&self, incoming: &Incoming) -> io::Result<()> {
+        let mut next = self.first.lock().unwrap().take();
+        // Offers no instance while the server is at its connection limit
+        while incoming.wait_for_room() {
+            let pipe = match next.take() {
+                Some(pipe) => pipe,
+                None => instance(&self.name, 0)?,
+            };
+            // Checked once the instance exists, so that `stop` can connect to it
+            if self.stopped.load(Ordering::SeqCst) {
+                break;
+            }
+            // Blocks until a client opens the pipe
+            // SAFETY: the handle is an open pipe instance owned by `pipe`
+            let connected = unsafe { ConnectNamedPipe(pipe.as_raw_handle(), ptr::null_mut()) };
+            if connected == 0 {
+                let e = io::Error::last_os_error();
+                if e.raw_os_error() != Some(ERROR_PIPE_CONNECTED as i32) {
+                    warn!("Failed to connect a pipe client: {}", e);
+                    continue; // The client left before it was connected
+                }
+            }
+            if self.stopped.load(Ordering::SeqCst) {
+                break; // `stop` woke the loop
+            }
+            let link = PipeLink(Arc::new(Instance {
+                pipe,
+                read_timeout: Mutex::new(None),
+                read_shut: AtomicBool::new(false),
+            }));
+            if incoming.accept(Box::new(link)).is_err() {
+                break; // The server stopped accepting
+            }
+        }
+        info!("Named pipe listener on {} stopped.", self.name);
+        Ok(())
+    }
+
+    fn stop(&self) {
+        if self.stopped.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        // Wake the accept loop by connecting to it; it may not be running yet
+        if self.first.lock().unwrap().take().is_none() {
+            let _ = OpenOptions::new().read(true).write(true).open(&self.name);
+        }
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+// Creates another instance of the pipe, waiting for a client
+fn instance(name: &str, flags: u32) -> io::Result<File> {
+    let wide = wide(name);
+    // SAFETY: `wide` is a NUL-terminated string that outlives the call
+    let handle = unsafe {
+        CreateNamedPipeW(
+            wide.as_ptr(),
+            PIPE_ACCESS_DUPLEX | flags,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            PIPE_UNLIMITED_INSTANCES, // Capped by the server's connection limit instead
+            PIPE_BUFFER,
+            PIPE_BUFFER,
+            0,
+            ptr::null(),
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the handle was just created and nothing else owns it
+    Ok(unsafe { File::from_raw_handle(handle) })
+}
+
+// `name` as a NUL-terminated wide string
+fn wide(name: &str) -> Vec<u16> {
+    std::ffi::OsStr::new(name)
+        .encode_wide()
+        .chain(Some(0))
+        .collect()
+}
+
+// A connected instance, shared by the handler and the server's handle to it
+struct Instance {
+    pipe: File,
+    read_timeout: Mutex<Option<Duration>>,
+    read_shut: AtomicBool, // Reading was shut down, so reads end the stream
+}
+
+// Server end of one client's instance. A blocking read on a pipe can neither
+// time out nor be woken from another thread, so reads poll for data instead.
+#[derive(Clone)]
+struct PipeLink(Arc<Instance>);
+
+impl PipeLink {
+    // Copies what has arrived into `buf`, if given, without consuming it;
+    // returns the bytes copied and those available
+    fn peek_pipe(&self, buf: &mut [u8]) -> io::Result<(usize, usize)> {
+        let (mut copied, mut available) = (0, 0);
+        // SAFETY: the handle is open while `self` is, and `buf` outlives the call
+        let peeked = unsafe {
+            PeekNamedPipe(
+                self.0.pipe.as_raw_handle(),
+                buf.as_mut_ptr().cast(),
+                u32::try_from(buf.len()).unwrap_or(u32::MAX),
+                &mut copied,
+                &mut available,
+                ptr::null_mut(),
+            )
+        };
+        match peeked {
+            0 => Err(io::Error::last_os_error()),
+            _ => Ok((copied as usize, available as usize)),
+        }
+    }
+}
+
+impl Read for PipeLink {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout = *self.0.read_timeout.lock().unwrap();
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            if self.0.read_shut.load(Ordering::SeqCst) {
+                return Ok(0);
+            }
+            match self.peek_pipe(&mut []) {
+                Ok((_, 0)) => {}
+                // Data, or a client that has gone, which the read reports
+                _ => return (&self.0.pipe).read(buf),
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(io::Error::new(ErrorKind::TimedOut, "Pipe read timed out"));
+            }
+            thread::sleep(READ_POLL);
+        }
+    }
+}
+
+impl Write for PipeLink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&self.0.pipe).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&self.0.pipe).flush()
+    }
+}
+
+impl Link for PipeLink {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        *self.0.read_timeout.lock().unwrap() = timeout;
+        Ok(())
+    }
+
+    // Pipe writes cannot time out; a client that stops reading is disconnected
+    // once the server's close grace has passed
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.peek_pipe(buf).map(|(copied, _)| copied)
+    }
+
+    // A pipe cannot be half closed. Shutting writing down waits for the client
+    // to read what was written, as closing the instance would discard it.
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match how {
+            Shutdown::Read => self.0.read_shut.store(true, Ordering::SeqCst),
+            Shutdown::Write => self.0.pipe.sync_all()?,
+            Shutdown::Both => {
+                self.0.read_shut.store(true, Ordering::SeqCst);
+                // SAFETY: the handle is open while `self` is
+                if unsafe { DisconnectNamedPipe(self.0.pipe.as_raw_handle()) } == 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Link>> {
+        Ok(Box::new(self.clone()))
+    }
+}
+
+/// Client end of a named pipe
+#[derive(Debug)]
+pub struct Pipe(File);
+
+impl Transport for Pipe {
+    type Error = io::Error;
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.0.write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Opens the pipe `name`, waiting up to `timeout` while every instance is busy
+pub fn connect(name: &str, timeout: Duration) -> io::Result<Connection<Pipe>> {
+    loop {
+        match OpenOptions::new().read(true).write(true).open(name) {
+            Ok(file) => return Ok(Connection::new(Pipe(file))),
+            Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY as i32) => {
+                let wide = wide(name);
+                let timeout_ms = u32::try_from(timeout.as_millis()).unwrap_or(u32::MAX);
+                // SAFETY: `wide` is a NUL-terminated string that outlives the call
+                if unsafe { WaitNamedPipeW(wide.as_ptr(), timeout_ms) } == 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
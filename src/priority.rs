@@ -0,0 +1,159 @@
+//! Priority classes for requests waiting to be handled.
+//!
+//! A client that sends faster than the server answers gets several requests
+//! handled per read. [`PriorityQueue`] hands them out control traffic first,
+//! then ordinary requests, then bulk telemetry, so a ping is answered promptly
+//! even behind a burst of telemetry. Requests of one class keep their order;
+//! responses to different classes may leave in a different order than their
+//! requests arrived.
+//!
+//! The server's worker queue is one too: when every thread is busy, the
+//! connection whose first request has the highest class takes the next thread
+//! to free up. Its class can change while it waits, as the request arrives, so
+//! [`PriorityQueue::reclassify`] sorts queued items again before each pop.
+//!
+//! Lower classes cannot starve: once [`MAX_BURST`] requests in a row have been
+//! taken ahead of a waiting lower-class request, the oldest waiting request of
+//! a lower class goes next.
+
+use crate::handler::MessageKind;
+use crate::message::ClientMessage;
+use alloc::{collections::VecDeque, vec::Vec};
+
+/// Requests taken ahead of a waiting lower-class request before it gets a turn
+pub const MAX_BURST: usize = 8;
+
+/// Priority class of a request, highest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Priority {
+    /// Liveness checks and other control traffic
+    Control,
+    /// Ordinary requests
+    Normal,
+    /// High-volume traffic that can tolerate delay
+    Bulk,
+}
+
+impl Priority {
+    /// Class of the given kind of request
+    pub fn of(kind: MessageKind) -> Self {
+        match kind {
+            MessageKind::Ping
+            | MessageKind::Resume
+            | MessageKind::Quota
+            | MessageKind::Resync
+            | MessageKind::Diagnostics
+            | MessageKind::Availability => Priority::Control,
+            MessageKind::Echo
+            | MessageKind::EchoBytes
+            | MessageKind::Add
+            | MessageKind::Transform
+            | MessageKind::Random
+            | MessageKind::Calc
+            | MessageKind::Describe
+            | MessageKind::TailLogs
+            | MessageKind::ConnectionHistory
+            | MessageKind::DeadLetters
+            | MessageKind::FaultRules
+            | MessageKind::Batch
+            | MessageKind::Subscribe
+            | MessageKind::Unsubscribe
+            | MessageKind::Publish
+            | MessageKind::Cluster => Priority::Normal,
+            MessageKind::Telemetry => Priority::Bulk,
+        }
+    }
+}
+
+/// Class of a decoded request; empty messages count as ordinary requests
+pub fn priority(message: &ClientMessage) -> Priority {
+    message
+        .message
+        .as_ref()
+        .map_or(Priority::Normal, |request| {
+            Priority::of(MessageKind::of(request))
+        })
+}
+
+/// Queue that releases items by priority without starving lower classes
+#[derive(Debug)]
+pub struct PriorityQueue<T> {
+    classes: [VecDeque<(u64, T)>; 3], // Indexed by `Priority`; items tagged with their arrival number
+    pushed: u64,
+    burst: usize, // Items taken in a row while a lower class waited
+}
+
+impl<T> PriorityQueue<T> {
+    /// Creates an empty queue
+    pub fn new() -> Self {
+        PriorityQueue {
+            classes: Default::default(),
+            pushed: 0,
+            burst: 0,
+        }
+    }
+
+    /// Queues an item behind others of the same class
+    pub fn push(&mut self, priority: Priority, item: T) {
+        self.classes[priority as usize].push_back((self.pushed, item));
+        self.pushed += 1;
+    }
+
+    /// Takes the next item to handle
+    pub fn pop(&mut self) -> Option<T> {
+        let highest = self.classes.iter().position(|class| !class.is_empty())?;
+        // Oldest item among the classes below the highest one
+        let waiting = self.classes[highest + 1..]
+            .iter()
+            .enumerate()
+            .filter_map(|(i, class)| class.front().map(|(pushed, _)| (*pushed, highest + 1 + i)))
+            .min();
+
+        let class = match waiting {
+            Some((_, lower)) if self.burst >= MAX_BURST => {
+                self.burst = 0;
+                lower
+            }
+            Some(_) => {
+                self.burst += 1;
+                highest
+            }
+            None => {
+                self.burst = 0;
+                highest
+            }
+        };
+        self.classes[class].pop_front().map(|(_, item)| item)
+    }
+
+    /// Moves each queued item to the class `classify` now gives it, keeping
+    /// arrival order within each class
+    pub fn reclassify(&mut self, mut classify: impl FnMut(&T) -> Priority) {
+        let mut items: Vec<_> = self
+            .classes
+            .iter_mut()
+            .flat_map(|class| class.drain(..))
+            .collect();
+        items.sort_unstable_by_key(|(pushed, _)| *pushed);
+        for (pushed, item) in items {
+            self.classes[classify(&item) as usize].push_back((pushed, item));
+        }
+    }
+
+    /// Number of queued items
+    pub fn len(&self) -> usize {
+        self.classes.iter().map(VecDeque::len).sum()
+    }
+
+    /// Whether nothing is queued
+    pub fn is_empty(&self) -> bool {
+        self.classes.iter().all(VecDeque::is_empty)
+    }
+}
+
+impl<T> Default for PriorityQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
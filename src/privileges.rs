@@ -0,0 +1,175 @@
+//! Giving up root after startup.
+//!
+//! A server started as root, to bind a port below 1024 or read TLS keys only
+//! root may read, should not keep root while it handles untrusted input. Once
+//! the listener is bound and keys are loaded, [`DropPrivileges::apply`] switches
+//! the process to an unprivileged user and group, optionally confining it to a
+//! chroot directory first:
+//!
+//! ```no_run
+//! # use embedded_recruitment_task::{privileges::DropPrivileges, server::Server};
+//! let server = Server::new("0.0.0.0:443")?;
+//! DropPrivileges::to_user("nobody").chroot("/var/empty").apply()?;
+//! server.run()?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! This applies to the whole process, every thread included, so call it before
+//! the server starts its threads.
+
+use log::info;
+use std::{
+    ffi::{CStr, CString},
+    io::{self, ErrorKind},
+    mem,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    ptr,
+};
+
+/// The user, group and root directory to switch to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DropPrivileges {
+    user: String,
+    group: Option<String>,
+    chroot: Option<PathBuf>,
+}
+
+impl DropPrivileges {
+    /// Switches to `user`, and to that user's primary group
+    pub fn to_user(user: &str) -> Self {
+        DropPrivileges {
+            user: user.to_string(),
+            group: None,
+            chroot: None,
+        }
+    }
+
+    /// Switches to `group` instead of the user's primary group
+    pub fn group(mut self, group: &str) -> Self {
+        self.group = Some(group.to_string());
+        self
+    }
+
+    /// Changes the root directory to `dir` before switching user. Files opened
+    /// afterwards, such as capture files, are resolved inside it.
+    pub fn chroot(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.chroot = Some(dir.into());
+        self
+    }
+
+    /// Drops the privileges. Fails if the user or group does not exist, if the
+    /// process is not root and not already running as that user, or if root could
+    /// be regained afterwards.
+    pub fn apply(&self) -> io::Result<()> {
+        // Looked up before the chroot hides /etc/passwd
+        let (uid, primary_gid) = lookup_user(&self.user)?;
+        let gid = match &self.group {
+            Some(group) => lookup_group(group)?,
+            None => primary_gid,
+        };
+
+        // Safety: the libc calls below only read the arguments passed
+        unsafe {
+            if libc::geteuid() != 0 {
+                if libc::getuid() == uid && libc::geteuid() == uid && libc::getegid() == gid {
+                    return Ok(()); // Nothing to give up
+                }
+                return Err(io::Error::new(
+                    ErrorKind::PermissionDenied,
+                    "Only root can switch to another user",
+                ));
+            }
+
+            if let Some(dir) = &self.chroot {
+                chroot(dir)?;
+            }
+            // Supplementary groups first; they cannot be changed once root is gone
+            check(libc::setgroups(1, &gid))?;
+            check(libc::setgid(gid))?;
+            check(libc::setuid(uid))?;
+
+            if uid != 0 && libc::setuid(0) == 0 {
+                return Err(io::Error::new(
+                    ErrorKind::PermissionDenied,
+                    "Root privileges could be regained",
+                ));
+            }
+        }
+        info!("Running as user {} ({}), group {}", self.user, uid, gid);
+        Ok(())
+    }
+}
+
+fn check(result: libc::c_int) -> io::Result<()> {
+    match result {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+fn chroot(dir: &Path) -> io::Result<()> {
+    let dir = c_string(dir.as_os_str().as_bytes())?;
+    // Safety: `dir` is a valid NUL-terminated path
+    unsafe {
+        check(libc::chroot(dir.as_ptr()))?;
+        check(libc::chdir(c"/".as_ptr())) // Leave no directory open outside the new root
+    }
+}
+
+fn c_string(bytes: &[u8]) -> io::Result<CString> {
+    CString::new(bytes).map_err(|_| io::Error::new(ErrorKind::InvalidInput, "Contains a NUL byte"))
+}
+
+// Buffer for the strings `getpwnam_r` and `getgrnam_r` return; large enough for
+// any sane passwd or group entry
+const ENTRY_BUFFER: usize = 16 * 1024;
+
+fn lookup_user(name: &str) -> io::Result<(libc::uid_t, libc::gid_t)> {
+    let name = c_string(name.as_bytes())?;
+    let mut buffer = vec![0 as libc::c_char; ENTRY_BUFFER];
+    // Safety: every pointer passed is valid for the duration of the call
+    unsafe {
+        let mut entry: libc::passwd = mem::zeroed();
+        let mut found = ptr::null_mut();
+        let error = libc::getpwnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut found,
+        );
+        lookup_result(error, found.is_null(), "user", &name)?;
+        Ok((entry.pw_uid, entry.pw_gid))
+    }
+}
+
+fn lookup_group(name: &str) -> io::Result<libc::gid_t> {
+    let name = c_string(name.as_bytes())?;
+    let mut buffer = vec![0 as libc::c_char; ENTRY_BUFFER];
+    // Safety: as in `lookup_user`
+    unsafe {
+        let mut entry: libc::group = mem::zeroed();
+        let mut found = ptr::null_mut();
+        let error = libc::getgrnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut found,
+        );
+        lookup_result(error, found.is_null(), "group", &name)?;
+        Ok(entry.gr_gid)
+    }
+}
+
+fn lookup_result(error: libc::c_int, missing: bool, kind: &str, name: &CStr) -> io::Result<()> {
+    match (error, missing) {
+        (0, false) => Ok(()),
+        (0, true) => Err(io::Error::new(
+            ErrorKind::NotFound,
+            format!("No such {}: {}", kind, name.to_string_lossy()),
+        )),
+        (error, _) => Err(io::Error::from_raw_os_error(error)),
+    }
+}
@@ -0,0 +1,155 @@
+//! Sans-IO protocol state machine.
+//!
+//! [`Protocol`] holds everything about a connection except the I/O itself:
+//! drivers hand it received bytes with [`Protocol::feed_bytes`], act on the
+//! returned [`Event`]s, and write out whatever [`Protocol::poll_transmit`]
+//! yields. The TCP server and every client flavour are thin drivers around it,
+//! which keeps the protocol logic in one place and lets tests run it without
+//! sockets.
+
+use crate::codec::{self, Bytes, CodecError, FrameDecoder};
+use crate::message::{ClientMessage, ServerMessage};
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use prost::Message;
+
+/// Protocol as run by a client: receives `ServerMessage`s, sends `ClientMessage`s
+pub type ClientProtocol = Protocol<ServerMessage, ClientMessage>;
+
+/// Protocol as run by the server: receives `ClientMessage`s, sends `ServerMessage`s
+pub type ServerProtocol = Protocol<ClientMessage, ServerMessage>;
+
+/// Something the driver has to act on
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event<M> {
+    /// A complete message arrived from the peer
+    Message(M),
+    /// The byte stream is corrupt; the connection cannot recover and should be closed
+    Error(CodecError),
+    /// The peer closed its side of the connection
+    Closed,
+}
+
+/// Lifecycle of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// Messages are flowing in both directions
+    Open,
+    /// The peer has closed the connection
+    Closed,
+    /// The peer sent bytes that could not be decoded
+    Failed,
+}
+
+/// One side of a connection, receiving `In` messages and sending `Out` messages
+#[derive(Debug)]
+pub struct Protocol<In, Out> {
+    state: State,
+    decoder: FrameDecoder,     // Reassembles frames split across reads
+    transmit: VecDeque<Bytes>, // Encoded frames waiting to be written
+    transmit_bytes: usize,     // Total length of `transmit`
+    _messages: PhantomData<fn(In) -> Out>,
+}
+
+impl<In: Message + Default, Out: Message> Protocol<In, Out> {
+    /// Creates the state machine for a freshly opened connection
+    pub fn new() -> Self {
+        Protocol {
+            state: State::Open,
+            decoder: FrameDecoder::new(),
+            transmit: VecDeque::new(),
+            transmit_bytes: 0,
+            _messages: PhantomData,
+        }
+    }
+
+    /// Current lifecycle state
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Whether messages can still be received
+    pub fn is_open(&self) -> bool {
+        self.state == State::Open
+    }
+
+    /// Processes bytes received from the peer, returning the events they complete
+    pub fn feed_bytes(&mut self, bytes: &[u8]) -> Vec<Event<In>> {
+        let mut events = Vec::new();
+        if !self.is_open() {
+            return events; // Nothing after a close or a framing error can be trusted
+        }
+
+        self.decoder.extend(bytes);
+        loop {
+            match self.decoder.next_message() {
+                Ok(Some(message)) => events.push(Event::Message(message)),
+                Ok(None) => break,
+                Err(e) => {
+                    self.state = State::Failed;
+                    events.push(Event::Error(e));
+                    break;
+                }
+            }
+        }
+        events
+    }
+
+    /// Records that the peer closed the stream
+    pub fn feed_eof(&mut self) -> Vec<Event<In>> {
+        if !self.is_open() {
+            return Vec::new();
+        }
+        self.state = State::Closed;
+        alloc::vec![Event::Closed]
+    }
+
+    /// Queues a message for the peer
+    pub fn send(&mut self, message: &Out) -> Result<(), CodecError> {
+        let frame = codec::encode(message)?;
+        self.send_frame(frame.into());
+        Ok(())
+    }
+
+    /// Queues a frame already encoded with [`codec::encode`], such as one
+    /// shared by every connection a message is broadcast to
+    pub fn send_frame(&mut self, frame: Bytes) {
+        self.transmit_bytes += frame.len();
+        self.transmit.push_back(frame);
+    }
+
+    /// Next chunk of bytes the driver must write to the peer
+    pub fn poll_transmit(&mut self) -> Option<Vec<u8>> {
+        self.poll_transmit_bytes().map(Vec::from)
+    }
+
+    /// Like [`poll_transmit`](Protocol::poll_transmit), without copying frames
+    /// queued with [`send_frame`](Protocol::send_frame) out of their shared buffer
+    pub fn poll_transmit_bytes(&mut self) -> Option<Bytes> {
+        let frame = self.transmit.pop_front()?;
+        self.transmit_bytes -= frame.len();
+        Some(frame)
+    }
+
+    /// Whether there are queued bytes waiting for `poll_transmit`
+    pub fn has_pending_transmit(&self) -> bool {
+        !self.transmit.is_empty()
+    }
+
+    /// Number of received bytes not yet forming a complete frame
+    pub fn buffered(&self) -> usize {
+        self.decoder.buffered()
+    }
+
+    /// Number of encoded bytes waiting for `poll_transmit`
+    pub fn queued(&self) -> usize {
+        self.transmit_bytes
+    }
+}
+
+impl<In: Message + Default, Out: Message> Default for Protocol<In, Out> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,348 @@
+//! QUIC listener.
+//!
+//! [`QuicServer`] accepts QUIC connections through quinn and treats every
+//! bidirectional stream a client opens like one TCP connection. It is a
+//! [`Listener`] added with [`Server::listener`], so each stream is handed to
+//! the server's workers and served by the same pipeline as TCP: the router and
+//! its middleware, authorization, quotas, tenants, topics, statistics and
+//! limits. The stream carries the same length-prefixed protobuf frames.
+//! Opening several streams multiplexes independent exchanges without
+//! head-of-line blocking between them, and quinn keeps a connection alive when
+//! a device's address changes (e.g. on a new cellular IP).
+//!
+//! TLS is mandatory in QUIC; the listener is given its certificate chain and
+//! private key. The host name a client asks for in its handshake (SNI) picks
+//! the virtual host of its streams, if the server has one by that name (see
+//! [`crate::vhost`]).
+//!
+//! [`Server::listener`]: crate::server::Server::listener
+
+use crate::link::{Link, Listener};
+use crate::server::Incoming;
+use log::{info, warn};
+use quinn::crypto::rustls::HandshakeData;
+use quinn::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use quinn::{Endpoint, RecvStream, SendStream, ServerConfig};
+use std::{
+    future::{poll_fn, Future},
+    io::{self, ErrorKind, Read, Write},
+    net::{Shutdown, SocketAddr, ToSocketAddrs},
+    pin::pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::Poll,
+    time::Duration,
+};
+use tokio::runtime::{Handle, Runtime};
+use tokio::sync::{mpsc, Notify};
+use tokio::task::{JoinHandle, JoinSet};
+
+// A stream a client opened, with the address of its connection and the
+// server name it asked for
+type Opened = (SendStream, RecvStream, SocketAddr, Option<String>);
+
+/// QUIC listener for a [`Server`](crate::server::Server)
+pub struct QuicServer {
+    endpoint: Endpoint,
+    runtime: Runtime, // Drives the endpoint, every connection and the streams' reads and writes
+    accepting: Mutex<Option<JoinHandle<()>>>, // Accepts connections and their streams until stopped
+    stopped: AtomicBool,
+}
+
+impl QuicServer {
+    /// Binds a UDP socket and prepares to accept connections authenticated with `cert_chain`
+    pub fn new(
+        addr: &str,
+        cert_chain: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> io::Result<Self> {
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "No address to bind"))?;
+        let config = ServerConfig::with_single_cert(cert_chain, key)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        let endpoint = {
+            let _context = runtime.enter(); // The endpoint registers its socket with the runtime
+            Endpoint::server(config, addr)?
+        };
+        Ok(QuicServer {
+            endpoint,
+            runtime,
+            accepting: Mutex::new(None),
+            stopped: AtomicBool::new(false),
+        })
+    }
+
+    /// Address the listener is bound to, e.g. to find the port picked for port 0
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.endpoint.local_addr()
+    }
+}
+
+impl Listener for QuicServer {
+    // Hands each stream to the server as it is opened
+    fn run(&self, incoming: &Incoming) -> io::Result<()> {
+        let (opened, mut streams) = mpsc::unbounded_channel();
+        {
+            let mut accepting = self.accepting.lock().unwrap();
+            if self.stopped.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            let endpoint = self.endpoint.clone();
+            *accepting = Some(self.runtime.spawn(accept_connections(endpoint, opened)));
+        }
+        // Ends once `stop` has dropped every sender along with the tasks
+        while let Some((send, recv, peer, server_name)) = streams.blocking_recv() {
+            let runtime = self.runtime.handle().clone();
+            let link = QuicLink::new(send, recv, peer, server_name, runtime);
+            if incoming.accept(Box::new(link)).is_err() {
+                break; // The server stopped accepting
+            }
+        }
+        info!("QUIC listener stopped.");
+        Ok(())
+    }
+
+    // Refuses new connections and streams; open streams stay with their handlers
+    fn stop(&self) {
+        let mut accepting = self.accepting.lock().unwrap();
+        self.stopped.store(true, Ordering::SeqCst);
+        self.endpoint.set_server_config(None);
+        if let Some(accepting) = accepting.take() {
+            accepting.abort();
+        }
+    }
+
+    fn name(&self) -> String {
+        match self.local_addr() {
+            Ok(addr) => format!("quic://{}", addr),
+            Err(_) => "QUIC".to_string(),
+        }
+    }
+}
+
+impl Drop for QuicServer {
+    fn drop(&mut self) {
+        self.endpoint.close(0u32.into(), b"server stopped");
+    }
+}
+
+// Accepts connections, and the streams each client opens, until aborted; the
+// connections' tasks are aborted with it
+async fn accept_connections(endpoint: Endpoint, opened: mpsc::UnboundedSender<Opened>) {
+    let mut connections = JoinSet::new();
+    while let Some(incoming) = endpoint.accept().await {
+        let opened = opened.clone();
+        connections.spawn(async move {
+            let connection = match incoming.await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    warn!("QUIC handshake failed: {}", e);
+                    return;
+                }
+            };
+            let peer = connection.remote_address();
+            let server_name = connection
+                .handshake_data()
+                .and_then(|data| data.downcast::<HandshakeData>().ok())
+                .and_then(|data| data.server_name);
+            info!("New QUIC client connected: {}", peer);
+            while let Ok((send, recv)) = connection.accept_bi().await {
+                if opened
+                    .send((send, recv, peer, server_name.clone()))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            info!("QUIC client disconnected: {}", peer);
+        });
+        while connections.try_join_next().is_some() {} // Forget the finished ones
+    }
+}
+
+// One stream as the server's handler uses it. Reads and writes block the
+// worker thread while the listener's runtime drives the connection.
+struct QuicLink {
+    recv: Option<RecvStream>, // Only on the handler's handle
+    stream: Arc<Stream>,
+}
+
+// What every handle to one stream shares
+struct Stream {
+    send: Mutex<SendStream>,
+    runtime: Handle,
+    peer: SocketAddr,
+    server_name: Option<String>, // Asked for in the connection's handshake
+    read_timeout: Mutex<Option<Duration>>,
+    write_timeout: Mutex<Option<Duration>>,
+    read_shut: AtomicBool,
+    write_shut: AtomicBool,
+    shut: Notify, // Wakes reads and writes once either flag is set
+}
+
+impl QuicLink {
+    fn new(
+        send: SendStream,
+        recv: RecvStream,
+        peer: SocketAddr,
+        server_name: Option<String>,
+        runtime: Handle,
+    ) -> Self {
+        QuicLink {
+            recv: Some(recv),
+            stream: Arc::new(Stream {
+                send: Mutex::new(send),
+                runtime,
+                peer,
+                server_name,
+                read_timeout: Mutex::new(None),
+                write_timeout: Mutex::new(None),
+                read_shut: AtomicBool::new(false),
+                write_shut: AtomicBool::new(false),
+                shut: Notify::new(),
+            }),
+        }
+    }
+}
+
+impl Stream {
+    // Runs `operation` on the runtime, giving up after `timeout`; `None` if
+    // `shut` is or becomes set first
+    fn block_on<T>(
+        &self,
+        timeout: Option<Duration>,
+        shut: &AtomicBool,
+        operation: impl Future<Output = io::Result<T>>,
+    ) -> Option<io::Result<T>> {
+        self.runtime.block_on(async {
+            let mut operation = pin!(operation);
+            let mut expired = pin!(async move {
+                match timeout {
+                    Some(timeout) => tokio::time::sleep(timeout).await,
+                    None => std::future::pending().await,
+                }
+            });
+            loop {
+                let mut woken = pin!(self.shut.notified());
+                woken.as_mut().enable(); // Before the check, so no shutdown is missed
+                if shut.load(Ordering::SeqCst) {
+                    return None;
+                }
+                let done = poll_fn(|cx| {
+                    if let Poll::Ready(result) = operation.as_mut().poll(cx) {
+                        return Poll::Ready(Some(result));
+                    }
+                    if expired.as_mut().poll(cx).is_ready() {
+                        let timed_out =
+                            io::Error::new(ErrorKind::TimedOut, "QUIC stream timed out");
+                        return Poll::Ready(Some(Err(timed_out)));
+                    }
+                    woken.as_mut().poll(cx).map(|()| None)
+                })
+                .await;
+                if done.is_some() {
+                    return done;
+                }
+            }
+        })
+    }
+
+    fn shut(&self, flag: &AtomicBool) {
+        flag.store(true, Ordering::SeqCst);
+        self.shut.notify_waiters();
+    }
+}
+
+impl Read for QuicLink {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(recv) = self.recv.as_mut() else {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "Only the handler reads",
+            ));
+        };
+        let stream = &self.stream;
+        let timeout = *stream.read_timeout.lock().unwrap();
+        let read = async { Ok(recv.read(buf).await?.unwrap_or(0)) }; // `None` at the end of the stream
+        stream
+            .block_on(timeout, &stream.read_shut, read)
+            .unwrap_or(Ok(0))
+    }
+}
+
+impl Write for QuicLink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let stream = &self.stream;
+        let timeout = *stream.write_timeout.lock().unwrap();
+        let mut send = stream.send.lock().unwrap();
+        let write = async { Ok(send.write(buf).await?) };
+        let shut = || io::Error::new(ErrorKind::NotConnected, "QUIC stream shut down");
+        stream
+            .block_on(timeout, &stream.write_shut, write)
+            .unwrap_or_else(|| Err(shut()))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(()) // Writes are handed to quinn, which sends them as the connection allows
+    }
+}
+
+impl Link for QuicLink {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        Some(self.stream.peer)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        *self.stream.read_timeout.lock().unwrap() = timeout;
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        *self.stream.write_timeout.lock().unwrap() = timeout;
+        Ok(())
+    }
+
+    // A stream cannot be read without consuming it, so waiting streams keep
+    // their order
+    fn peek(&self, _buf: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+
+    // Shutting writing down finishes the stream after what was written;
+    // shutting both down resets it
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        let stream = &self.stream;
+        match how {
+            Shutdown::Read => stream.shut(&stream.read_shut),
+            Shutdown::Write => {
+                stream.shut(&stream.write_shut);
+                let _ = stream.send.lock().unwrap().finish(); // The client may have stopped it
+            }
+            Shutdown::Both => {
+                stream.shut(&stream.read_shut);
+                stream.shut(&stream.write_shut);
+                // A writer woken above lets go of the stream
+                let _ = stream.send.lock().unwrap().reset(0u32.into());
+            }
+        }
+        Ok(())
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Link>> {
+        Ok(Box::new(QuicLink {
+            recv: None,
+            stream: Arc::clone(&self.stream),
+        }))
+    }
+
+    fn server_name(&self) -> Option<String> {
+        self.stream.server_name.clone()
+    }
+}
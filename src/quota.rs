@@ -0,0 +1,158 @@
+//! Daily quotas per device.
+//!
+//! One customer's fleet should not be able to take the whole server. A
+//! [`Quota`] caps what one identity, the `device_id` a connection names itself
+//! with, may do each day: the requests it sends and their bytes, the messages
+//! waiting in its queue (see [`crate::outbox`]), and the topic subscriptions it
+//! holds across its connections. Quotas are set with
+//! [`Server::set_quota`](crate::server::Server::set_quota) for one device and
+//! [`Server::set_default_quota`](crate::server::Server::set_default_quota) for
+//! the rest; every limit is off by default.
+//!
+//! A request over the day's quota is answered with an `ErrorResponse` whose
+//! code is `QUOTA_EXCEEDED` and whose `retry_after_ms` runs until the counts
+//! start over at midnight UTC. A subscription over the limit is refused with
+//! the same code, without `retry_after_ms`; it is admitted once the device
+//! unsubscribes from another filter. A device can check its usage with a
+//! `QuotaRequest`, which is never charged. Clients that have not named
+//! themselves are not charged; an authorizer (see [`crate::authz`]) can refuse
+//! them instead.
+
+use crate::message::QuotaStatus;
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// What one identity may use; `None` is no limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    /// Requests per day
+    pub messages_per_day: Option<u64>,
+    /// Bytes of requests per day
+    pub bytes_per_day: Option<u64>,
+    /// Topic subscriptions held at once, across the device's connections
+    pub max_subscriptions: Option<u64>,
+    /// Messages waiting in the device's queue, below the server-wide limit
+    pub max_queued: Option<usize>,
+}
+
+/// Which limit a request would break
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exceeded {
+    Messages,
+    Bytes,
+    Subscriptions,
+}
+
+impl fmt::Display for Exceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exceeded::Messages => write!(f, "daily message quota exceeded"),
+            Exceeded::Bytes => write!(f, "daily byte quota exceeded"),
+            Exceeded::Subscriptions => write!(f, "subscription quota exceeded"),
+        }
+    }
+}
+
+/// Quotas and what each identity has used of them today
+#[derive(Debug, Default)]
+pub struct Quotas {
+    default: Quota,
+    identities: HashMap<String, Quota>,
+    day: u64,                           // Days since the epoch that `usage` counts
+    usage: HashMap<String, (u64, u64)>, // Requests and bytes of each identity today
+}
+
+impl Quotas {
+    /// Creates quotas without limits
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies `quota` to the identities without one of their own
+    pub fn set_default(&mut self, quota: Quota) {
+        self.default = quota;
+    }
+
+    /// Applies `quota` to `identity`, or the default quota again if `None`
+    pub fn set(&mut self, identity: &str, quota: Option<Quota>) {
+        match quota {
+            Some(quota) => self.identities.insert(identity.to_string(), quota),
+            None => self.identities.remove(identity),
+        };
+    }
+
+    /// Quota that applies to `identity`
+    pub fn quota(&self, identity: &str) -> Quota {
+        self.identities
+            .get(identity)
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    /// Counts a request of `bytes` sent by `identity` at `now`, unless it would
+    /// break a limit
+    pub fn charge(&mut self, identity: &str, bytes: u64, now: SystemTime) -> Result<(), Exceeded> {
+        self.check(identity, bytes, now)?;
+        let (messages, used) = self.usage.entry(identity.to_string()).or_default();
+        *messages += 1;
+        *used += bytes;
+        Ok(())
+    }
+
+    /// Whether `identity` could send a request of `bytes` at `now`, without
+    /// counting it
+    pub fn check(&mut self, identity: &str, bytes: u64, now: SystemTime) -> Result<(), Exceeded> {
+        let quota = self.quota(identity);
+        self.roll_over(now);
+        let (messages, used) = self.usage.get(identity).copied().unwrap_or_default();
+        if quota.messages_per_day.is_some_and(|max| messages >= max) {
+            return Err(Exceeded::Messages);
+        }
+        if quota.bytes_per_day.is_some_and(|max| used + bytes > max) {
+            return Err(Exceeded::Bytes);
+        }
+        Ok(())
+    }
+
+    /// Usage and limits of `identity` at `now`, with `queued` messages waiting for it
+    pub fn status(&mut self, identity: &str, queued: usize, now: SystemTime) -> QuotaStatus {
+        let quota = self.quota(identity);
+        self.roll_over(now);
+        let (messages, bytes) = self.usage.get(identity).copied().unwrap_or_default();
+        QuotaStatus {
+            messages,
+            max_messages: quota.messages_per_day.unwrap_or(0),
+            bytes,
+            max_bytes: quota.bytes_per_day.unwrap_or(0),
+            queued: queued as u64,
+            max_queued: quota.max_queued.map_or(0, |max| max as u64),
+            subscriptions: 0, // Filled in by the server, which holds the subscriptions
+            max_subscriptions: quota.max_subscriptions.unwrap_or(0),
+            resets_in_ms: until_reset(now).as_millis() as u64,
+        }
+    }
+
+    // Forgets the usage of past days
+    fn roll_over(&mut self, now: SystemTime) {
+        let day = since_epoch(now).as_secs() / DAY.as_secs();
+        if day != self.day {
+            self.day = day;
+            self.usage.clear();
+        }
+    }
+}
+
+/// Time from `now` until the daily counts start over, at midnight UTC
+pub fn until_reset(now: SystemTime) -> Duration {
+    let into_day = since_epoch(now).as_millis() % DAY.as_millis();
+    DAY - Duration::from_millis(into_day as u64)
+}
+
+fn since_epoch(now: SystemTime) -> Duration {
+    now.duration_since(UNIX_EPOCH).unwrap_or_default()
+}
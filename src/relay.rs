@@ -0,0 +1,105 @@
+//! Forwarding requests to an upstream server.
+//!
+//! A server started with [`Server::relay_to`] acts as an edge concentrator:
+//! it terminates device connections as usual but, instead of handling each
+//! request itself, sends it over one of a few long-lived links to the upstream
+//! server and relays the response back. Each link carries one request at a
+//! time; a broken link is reopened on its next use. Requests are forwarded
+//! without their message IDs, which are only unique per device; the edge
+//! answers retries itself.
+//!
+//! [`Server::relay_to`]: crate::server::Server::relay_to
+
+use crate::message::{client_message, server_message};
+use crate::transport::Connection;
+use log::info;
+use std::{
+    io,
+    net::{TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(5); // Connect and response timeout per request
+
+// Links to the upstream server shared by every connection handler
+pub(crate) struct Upstream {
+    addr: String,
+    links: Vec<Mutex<Option<Connection<TcpStream>>>>, // `None` until opened, or after a failure
+    next: AtomicUsize,                                // Round-robin start for picking a link
+    reachable: AtomicBool,                            // Whether the last request got through
+}
+
+impl Upstream {
+    pub(crate) fn new(addr: &str, links: usize) -> Self {
+        Upstream {
+            addr: addr.to_string(),
+            links: (0..links.max(1)).map(|_| Mutex::new(None)).collect(),
+            next: AtomicUsize::new(0),
+            reachable: AtomicBool::new(true),
+        }
+    }
+
+    // Reported by the server's readiness; optimistic until a request fails
+    pub(crate) fn is_reachable(&self) -> bool {
+        self.reachable.load(Ordering::Relaxed)
+    }
+
+    // Sends one request upstream and waits for its response
+    pub(crate) fn forward(
+        &self,
+        request: client_message::Message,
+    ) -> io::Result<server_message::Message> {
+        // Prefer an idle link; wait for the round-robin one if all are busy
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let count = self.links.len();
+        let mut link = (0..count)
+            .find_map(|i| self.links[(start + i) % count].try_lock().ok())
+            .unwrap_or_else(|| self.links[start % count].lock().unwrap());
+
+        let connection = match link.as_mut() {
+            Some(connection) => connection,
+            None => match self.open() {
+                Ok(connection) => link.insert(connection),
+                Err(e) => {
+                    self.reachable.store(false, Ordering::Relaxed);
+                    return Err(e);
+                }
+            },
+        };
+        let result = connection
+            .send(request) // Without an ID: IDs from different devices could collide upstream
+            .and_then(|()| connection.receive());
+        self.reachable.store(result.is_ok(), Ordering::Relaxed);
+        match result {
+            Ok(response) => response.message.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "Empty upstream response")
+            }),
+            Err(e) => {
+                *link = None; // Reopened by the next request
+                Err(e.into())
+            }
+        }
+    }
+
+    fn open(&self) -> io::Result<Connection<TcpStream>> {
+        let mut last_error = io::Error::new(io::ErrorKind::InvalidInput, "No upstream address");
+        let stream = self
+            .addr
+            .to_socket_addrs()?
+            .find_map(|addr| {
+                TcpStream::connect_timeout(&addr, UPSTREAM_TIMEOUT)
+                    .map_err(|e| last_error = e)
+                    .ok()
+            })
+            .ok_or(last_error)?;
+        stream.set_read_timeout(Some(UPSTREAM_TIMEOUT))?;
+        stream.set_write_timeout(Some(UPSTREAM_TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        info!("Opened upstream link to {}", self.addr);
+        Ok(Connection::new(stream))
+    }
+}
@@ -0,0 +1,157 @@
+//! Sessions that outlive a connection.
+//!
+//! A device on a cellular link may lose its connection many times a day. So
+//! that it does not start from scratch each time, it sends a `ResumeRequest`
+//! first on every connection. The server answers with a token naming the
+//! connection's session. When the connection closes, the session's state is
+//! parked under the token in a [`SessionStore`]. A `ResumeRequest` presenting
+//! the token on a later connection takes the state back, if it has not expired
+//! in the meantime:
+//!
+//! ```text
+//! device                          server
+//!   ResumeRequest { token: "" } ->
+//!                               <- ResumeResponse { token: T, resumed: false }
+//!   ... connection drops; the session is parked under T ...
+//!   ResumeRequest { token: T }  ->
+//!                               <- ResumeResponse { token: T, resumed: true }
+//! ```
+//!
+//! A session belongs to the device the connection named, and only that device
+//! can resume it. It carries the connection's dedup window, so a request
+//! retried over the new connection is answered without being handled twice.
+//! The server also keeps the connection's topic subscriptions while it is
+//! parked, along with the publications that arrive for them meanwhile, and
+//! hands both to the connection that resumes it; the client does not subscribe
+//! again. Messages sent to the device are kept in its queue regardless (see
+//! [`crate::outbox`]). An unknown or expired token starts a new session, as
+//! does an empty one or another device's.
+
+use crate::dedup::DedupWindow;
+use std::{
+    collections::HashMap,
+    io,
+    time::{Duration, Instant},
+};
+
+/// How long a parked session can be resumed by default
+pub const DEFAULT_SESSION_EXPIRY: Duration = Duration::from_secs(300);
+
+/// Most sessions parked at once; the oldest is dropped to make room
+pub const MAX_PARKED_SESSIONS: usize = 10_000;
+
+/// Length of a session token in bytes, long enough that it cannot be guessed
+pub const TOKEN_LEN: usize = 16;
+
+/// What a session keeps across connections
+#[derive(Debug)]
+pub struct SessionState {
+    /// Responses to recent message IDs
+    pub dedup: DedupWindow,
+    /// Device the connection named, the only one that may resume the session
+    pub device: Option<String>,
+}
+
+// A session waiting for its device to reconnect
+struct Parked {
+    state: SessionState,
+    since: Instant, // When its connection closed
+}
+
+/// Sessions of closed connections, by token
+pub struct SessionStore {
+    expiry: Duration,
+    capacity: usize,
+    parked: HashMap<Vec<u8>, Parked>,
+}
+
+impl SessionStore {
+    /// Creates an empty store whose sessions can be resumed for `expiry`,
+    /// parking at most [`MAX_PARKED_SESSIONS`]
+    pub fn new(expiry: Duration) -> Self {
+        Self::with_capacity(expiry, MAX_PARKED_SESSIONS)
+    }
+
+    /// Creates an empty store parking at most `capacity` sessions, for one
+    /// shard of several that share [`MAX_PARKED_SESSIONS`]
+    pub fn with_capacity(expiry: Duration, capacity: usize) -> Self {
+        SessionStore {
+            expiry,
+            capacity: capacity.max(1),
+            parked: HashMap::new(),
+        }
+    }
+
+    /// Parked sessions can be resumed for `expiry` after their connection closed
+    pub fn set_expiry(&mut self, expiry: Duration) {
+        self.expiry = expiry;
+    }
+
+    /// A fresh random token for a new session
+    pub fn issue(&self) -> io::Result<Vec<u8>> {
+        let mut token = vec![0; TOKEN_LEN];
+        getrandom::getrandom(&mut token).map_err(|e| io::Error::other(e.to_string()))?;
+        Ok(token)
+    }
+
+    /// Keeps `state` until it is resumed with `token` or expires. Returns the
+    /// tokens of the sessions dropped meanwhile, as they expired or to make room.
+    pub fn park(&mut self, token: Vec<u8>, state: SessionState) -> Vec<Vec<u8>> {
+        let mut dropped = self.expire();
+        if self.parked.len() >= self.capacity {
+            let oldest = self
+                .parked
+                .iter()
+                .min_by_key(|(_, parked)| parked.since)
+                .map(|(token, _)| token.clone());
+            if let Some(oldest) = oldest {
+                self.parked.remove(&oldest);
+                dropped.push(oldest);
+            }
+        }
+        let since = Instant::now();
+        self.parked.insert(token, Parked { state, since });
+        dropped
+    }
+
+    /// Takes back the state parked under `token`, unless it has expired
+    pub fn resume(&mut self, token: &[u8]) -> Option<SessionState> {
+        let parked = self.parked.remove(token)?;
+        (parked.since.elapsed() < self.expiry).then_some(parked.state)
+    }
+
+    /// Devices named by the parked sessions, including expired ones not yet
+    /// dropped
+    pub fn devices(&self) -> impl Iterator<Item = &str> {
+        (self.parked.values()).filter_map(|parked| parked.state.device.as_deref())
+    }
+
+    /// Number of sessions parked, including expired ones not yet dropped
+    pub fn len(&self) -> usize {
+        self.parked.len()
+    }
+
+    /// Whether no sessions are parked
+    pub fn is_empty(&self) -> bool {
+        self.parked.is_empty()
+    }
+
+    /// Drops the sessions that can no longer be resumed, returning their tokens
+    pub fn expire(&mut self) -> Vec<Vec<u8>> {
+        let expiry = self.expiry;
+        let expired: Vec<Vec<u8>> = (self.parked.iter())
+            .filter(|(_, parked)| parked.since.elapsed() >= expiry)
+            .map(|(token, _)| token.clone())
+            .collect();
+        for token in &expired {
+            self.parked.remove(token);
+        }
+        expired
+    }
+}
+
+impl Default for SessionStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_SESSION_EXPIRY)
+    }
+}
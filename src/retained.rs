@@ -0,0 +1,105 @@
+//! The last message published on each topic, for new subscribers.
+//!
+//! A dashboard that subscribes to `site/+/thermo-1/temperature` wants the
+//! current reading at once, not after the next report. [`Retained`] keeps the
+//! last message published on a topic when the publisher asks for it with the
+//! retain flag, or always for topics matching a filter set with
+//! [`Retained::always_retain`]. A new subscription is first handed the
+//! retained messages [`matching`](Retained::matching) its filter (see
+//! [`crate::topic`]).
+//!
+//! As in MQTT, a message published without the flag leaves the retained one in
+//! place, and [`Retained::clear`] removes it. At most `capacity` topics are
+//! retained, so devices publishing to ever new topics cannot exhaust memory.
+
+use crate::topic::{self, TopicError, TopicTrie, SEPARATOR};
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::ops::Bound;
+
+/// Retained messages by topic
+#[derive(Debug)]
+pub struct Retained<T> {
+    capacity: usize,
+    always: TopicTrie<()>, // Filters of the topics retained without the flag
+    messages: BTreeMap<String, T>,
+}
+
+impl<T> Retained<T> {
+    /// Creates a store retaining messages on at most `capacity` topics
+    pub fn new(capacity: usize) -> Self {
+        Retained {
+            capacity,
+            always: TopicTrie::new(),
+            messages: BTreeMap::new(),
+        }
+    }
+
+    /// Retains every message published on a topic matching `filter`, flag or not
+    pub fn always_retain(&mut self, filter: &str) -> Result<(), TopicError> {
+        self.always.insert(filter, ())
+    }
+
+    /// Records `message` published on `topic`, replacing the retained one if
+    /// `retain` is set or the topic is always retained. Whether it was
+    /// retained: `false` also when a new topic would exceed the capacity.
+    pub fn publish(&mut self, topic: &str, message: T, retain: bool) -> Result<bool, TopicError> {
+        topic::validate_topic(topic)?;
+        if !retain && self.always.matches(topic).is_empty() {
+            return Ok(false);
+        }
+        if let Some(retained) = self.messages.get_mut(topic) {
+            *retained = message;
+            return Ok(true);
+        }
+        if self.messages.len() >= self.capacity {
+            return Ok(false);
+        }
+        self.messages.insert(topic.into(), message);
+        Ok(true)
+    }
+
+    /// Whether a message published on `topic` with `retain` would be kept, so
+    /// callers can tell before taking exclusive access
+    pub fn retains(&self, topic: &str, retain: bool) -> bool {
+        retain || !self.always.matches(topic).is_empty()
+    }
+
+    /// Removes the message retained on `topic`, returning it
+    pub fn clear(&mut self, topic: &str) -> Option<T> {
+        self.messages.remove(topic)
+    }
+
+    /// The message retained on `topic`
+    pub fn get(&self, topic: &str) -> Option<&T> {
+        self.messages.get(topic)
+    }
+
+    /// Retained messages on the topics `filter` matches, by topic
+    pub fn matching(&self, filter: &str) -> Vec<(&str, &T)> {
+        if topic::validate_filter(filter).is_err() {
+            return Vec::new();
+        }
+        // Only topics starting with the levels before the first wildcard can match
+        let prefix = filter
+            .split(SEPARATOR)
+            .take_while(|level| !matches!(*level, topic::SINGLE_LEVEL | topic::MULTI_LEVEL))
+            .collect::<Vec<_>>()
+            .join("/");
+        self.messages
+            .range::<str, _>((Bound::Included(prefix.as_str()), Bound::Unbounded))
+            .take_while(|(topic, _)| topic.starts_with(&prefix))
+            .filter(|(topic, _)| topic::matches(filter, topic))
+            .map(|(topic, message)| (topic.as_str(), message))
+            .collect()
+    }
+
+    /// Number of topics with a retained message
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Whether no messages are retained
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+}
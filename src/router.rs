@@ -0,0 +1,233 @@
+//! Application handlers registered per message type.
+//!
+//! A [`Router`] passed to [`Server::router`](crate::server::Server::router)
+//! decides how the server answers each request. Handlers are registered for
+//! the message types the application cares about; every other request goes to
+//! the fallback, which gives the built-in responses of
+//! [`handle_message`] unless replaced:
+//!
+//! ```
+//! # use embedded_recruitment_task::{message::{server_message, AddResponse}, router::Router};
+//! let router = Router::new().on_add(|add| {
+//!     server_message::Message::AddResponse(AddResponse {
+//!         result: add.a.saturating_add(add.b),
+//!     })
+//! });
+//! ```
+//!
+//! Handlers are called from every connection's worker, so they must be `Send`
+//! and `Sync`.
+
+use crate::handler::handle_message;
+use crate::message::{
+    client_message, server_message, AddRequest, CalcRequest, DescribeRequest, EchoBytes,
+    EchoMessage, PingRequest, RandomRequest, TelemetryReport, TransformRequest,
+};
+
+type Handler<T> = Box<dyn Fn(T) -> server_message::Message + Send + Sync>;
+
+/// Handlers for each message type, and for the rest
+pub struct Router {
+    echo: Option<Handler<EchoMessage>>,
+    add: Option<Handler<AddRequest>>,
+    ping: Option<Handler<PingRequest>>,
+    telemetry: Option<Handler<TelemetryReport>>,
+    echo_bytes: Option<Handler<EchoBytes>>,
+    transform: Option<Handler<TransformRequest>>,
+    random: Option<Handler<RandomRequest>>,
+    calc: Option<Handler<CalcRequest>>,
+    describe: Option<Handler<DescribeRequest>>,
+    fallback: Handler<client_message::Message>,
+}
+
+impl Router {
+    /// A router giving the built-in response to every request
+    pub fn new() -> Self {
+        Router {
+            echo: None,
+            add: None,
+            ping: None,
+            telemetry: None,
+            echo_bytes: None,
+            transform: None,
+            random: None,
+            calc: None,
+            describe: None,
+            fallback: Box::new(handle_message),
+        }
+    }
+
+    /// Answers `EchoMessage`s with `handler`
+    pub fn on_echo(
+        mut self,
+        handler: impl Fn(EchoMessage) -> server_message::Message + Send + Sync + 'static,
+    ) -> Self {
+        self.echo = Some(Box::new(handler));
+        self
+    }
+
+    /// Answers `AddRequest`s with `handler`
+    pub fn on_add(
+        mut self,
+        handler: impl Fn(AddRequest) -> server_message::Message + Send + Sync + 'static,
+    ) -> Self {
+        self.add = Some(Box::new(handler));
+        self
+    }
+
+    /// Answers `PingRequest`s with `handler`
+    pub fn on_ping(
+        mut self,
+        handler: impl Fn(PingRequest) -> server_message::Message + Send + Sync + 'static,
+    ) -> Self {
+        self.ping = Some(Box::new(handler));
+        self
+    }
+
+    /// Answers `TelemetryReport`s with `handler`
+    pub fn on_telemetry(
+        mut self,
+        handler: impl Fn(TelemetryReport) -> server_message::Message + Send + Sync + 'static,
+    ) -> Self {
+        self.telemetry = Some(Box::new(handler));
+        self
+    }
+
+    /// Answers `EchoBytes` requests with `handler`
+    pub fn on_echo_bytes(
+        mut self,
+        handler: impl Fn(EchoBytes) -> server_message::Message + Send + Sync + 'static,
+    ) -> Self {
+        self.echo_bytes = Some(Box::new(handler));
+        self
+    }
+
+    /// Answers `TransformRequest`s with `handler`
+    pub fn on_transform(
+        mut self,
+        handler: impl Fn(TransformRequest) -> server_message::Message + Send + Sync + 'static,
+    ) -> Self {
+        self.transform = Some(Box::new(handler));
+        self
+    }
+
+    /// Answers `RandomRequest`s with `handler`
+    pub fn on_random(
+        mut self,
+        handler: impl Fn(RandomRequest) -> server_message::Message + Send + Sync + 'static,
+    ) -> Self {
+        self.random = Some(Box::new(handler));
+        self
+    }
+
+    /// Answers `CalcRequest`s with `handler`
+    pub fn on_calc(
+        mut self,
+        handler: impl Fn(CalcRequest) -> server_message::Message + Send + Sync + 'static,
+    ) -> Self {
+        self.calc = Some(Box::new(handler));
+        self
+    }
+
+    /// Answers `DescribeRequest`s with `handler`
+    pub fn on_describe(
+        mut self,
+        handler: impl Fn(DescribeRequest) -> server_message::Message + Send + Sync + 'static,
+    ) -> Self {
+        self.describe = Some(Box::new(handler));
+        self
+    }
+
+    /// Answers every request without a handler of its own with `handler`
+    /// instead of the built-in response
+    pub fn fallback(
+        mut self,
+        handler: impl Fn(client_message::Message) -> server_message::Message + Send + Sync + 'static,
+    ) -> Self {
+        self.fallback = Box::new(handler);
+        self
+    }
+
+    /// Computes the response to `request` with the handler registered for its type
+    pub fn handle(&self, request: client_message::Message) -> server_message::Message {
+        use client_message::Message;
+        match request {
+            Message::EchoMessage(echo) => self.route(&self.echo, echo, Message::EchoMessage),
+            Message::AddRequest(add) => self.route(&self.add, add, Message::AddRequest),
+            Message::PingRequest(ping) => self.route(&self.ping, ping, Message::PingRequest),
+            Message::TelemetryReport(report) => {
+                self.route(&self.telemetry, report, Message::TelemetryReport)
+            }
+            Message::EchoBytes(echo) => self.route(&self.echo_bytes, echo, Message::EchoBytes),
+            Message::TransformRequest(request) => {
+                self.route(&self.transform, request, Message::TransformRequest)
+            }
+            Message::RandomRequest(request) => {
+                self.route(&self.random, request, Message::RandomRequest)
+            }
+            Message::CalcRequest(calc) => self.route(&self.calc, calc, Message::CalcRequest),
+            Message::DescribeRequest(describe) => {
+                self.route(&self.describe, describe, Message::DescribeRequest)
+            }
+            // The server resumes sessions before requests reach the router
+            Message::ResumeRequest(resume) => (self.fallback)(Message::ResumeRequest(resume)),
+            // And reports quotas
+            Message::QuotaRequest(quota) => (self.fallback)(Message::QuotaRequest(quota)),
+            // And replays deliveries
+            Message::ResyncRequest(resync) => (self.fallback)(Message::ResyncRequest(resync)),
+            // And streams the log
+            Message::TailLogs(tail) => (self.fallback)(Message::TailLogs(tail)),
+            // And checks itself
+            Message::DiagnosticsRequest(request) => {
+                (self.fallback)(Message::DiagnosticsRequest(request))
+            }
+            // And tracks its uptime
+            Message::AvailabilityRequest(request) => {
+                (self.fallback)(Message::AvailabilityRequest(request))
+            }
+            // And remembers closed connections
+            Message::ConnectionHistoryRequest(request) => {
+                (self.fallback)(Message::ConnectionHistoryRequest(request))
+            }
+            // And keeps what it could not deliver
+            Message::DeadLettersRequest(request) => {
+                (self.fallback)(Message::DeadLettersRequest(request))
+            }
+            // And injects faults
+            Message::FaultRulesRequest(request) => {
+                (self.fallback)(Message::FaultRulesRequest(request))
+            }
+            // And brokers topics
+            Message::SubscribeRequest(request) => {
+                (self.fallback)(Message::SubscribeRequest(request))
+            }
+            Message::UnsubscribeRequest(request) => {
+                (self.fallback)(Message::UnsubscribeRequest(request))
+            }
+            Message::PublishRequest(request) => (self.fallback)(Message::PublishRequest(request)),
+            // And links the nodes of a cluster
+            Message::ClusterEvent(event) => (self.fallback)(Message::ClusterEvent(event)),
+            // And unpacks batches
+            Message::Batch(batch) => (self.fallback)(Message::Batch(batch)),
+        }
+    }
+
+    // Calls `handler` if one is registered, the fallback otherwise
+    fn route<T>(
+        &self,
+        handler: &Option<Handler<T>>,
+        request: T,
+        unhandled: fn(T) -> client_message::Message,
+    ) -> server_message::Message {
+        match handler {
+            Some(handler) => handler(request),
+            None => (self.fallback)(unhandled(request)),
+        }
+    }
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,363 @@
+//! Handlers and middleware written as rhai scripts.
+//!
+//! A [`Script`] is middleware (see [`crate::middleware`]) running a
+//! [rhai](https://rhai.rs) script, so simple behavior changes need an edit to
+//! a file rather than a new build of the server. The script defines
+//! `handle(request)`, which gets the request as a map and returns either the
+//! response as a map or `()` to pass the request on unchanged:
+//!
+//! ```text
+//! fn handle(request) {
+//!     if request.kind == "add" && request.b == 0 {
+//!         return #{ kind: "error", code: 3, field: "b", detail: "must not be 0" };
+//!     }
+//! }
+//! ```
+//!
+//! Restricted to some message types with [`Script::only`], a script that
+//! always answers replaces their handlers; otherwise it sits in the chain like
+//! any other layer. The file is checked for changes every
+//! [`RELOAD_INTERVAL`] and compiled again when it has changed. A script that
+//! fails to compile or to run is logged and the request passed on, so a bad
+//! edit never takes the server down.
+//!
+//! Requests and responses are maps with a `kind` naming the message type
+//! (`echo`, `add`, `ping`, `telemetry`, `echo_bytes`, `transform`, `random`,
+//! `calc`, and `describe`, `resume` and `quota` for requests only; responses also `error`) and one entry per protobuf field, under the
+//! field's name. `bytes` fields are blobs and enums are integers. Fields a
+//! response leaves out take their default value.
+
+use crate::handler::MessageKind;
+use crate::message::{
+    client_message, server_message, AddResponse, CalcResponse, EchoBytes, EchoMessage,
+    ErrorResponse, PingResponse, RandomResponse, TelemetryAck, TransformResponse,
+};
+use crate::middleware::{Middleware, Next, Request, Response};
+use log::{error, info, warn};
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// How often the script file is checked for changes by default
+pub const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+// Operations a script may run per request, so a runaway loop cannot hold a
+// worker forever
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// A script answering requests, reloaded when its file changes
+pub struct Script {
+    path: PathBuf,
+    engine: Engine,
+    kinds: Option<Vec<MessageKind>>, // `None` runs the script for every type
+    reload_interval: Duration,
+    loaded: Mutex<Loaded>,
+}
+
+// The script as last compiled
+struct Loaded {
+    ast: Arc<AST>,
+    modified: Option<SystemTime>, // Modification time of the file compiled
+    checked: Instant,             // Last time the file was looked at
+}
+
+impl Script {
+    /// Compiles the script at `path`; an error if it cannot be read or compiled
+    pub fn load(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let modified = fs::metadata(&path)?.modified().ok();
+        let ast = compile(&engine, &path)?;
+        Ok(Script {
+            path,
+            engine,
+            kinds: None,
+            reload_interval: RELOAD_INTERVAL,
+            loaded: Mutex::new(Loaded {
+                ast: Arc::new(ast),
+                modified,
+                checked: Instant::now(),
+            }),
+        })
+    }
+
+    /// Runs the script only for requests of these types; the rest pass on
+    pub fn only(mut self, kinds: &[MessageKind]) -> Self {
+        self.kinds = Some(kinds.to_vec());
+        self
+    }
+
+    /// Checks the file for changes every `interval` instead of [`RELOAD_INTERVAL`]
+    pub fn reload_interval(mut self, interval: Duration) -> Self {
+        self.reload_interval = interval;
+        self
+    }
+
+    /// The script's response to `request`; `None` if it passes the request on
+    /// or fails
+    pub fn handle(&self, request: &client_message::Message) -> Option<server_message::Message> {
+        let ast = self.current();
+        let mut scope = Scope::new();
+        let result = self
+            .engine
+            .call_fn::<Dynamic>(&mut scope, &ast, "handle", (to_map(request),))
+            .map_err(|e| e.to_string())
+            .and_then(|response| match response.is_unit() {
+                true => Ok(None),
+                false => from_dynamic(response).map(Some),
+            });
+        match result {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("Script {}: {}", self.path.display(), e);
+                None
+            }
+        }
+    }
+
+    // The compiled script, compiled again first if the file has changed
+    fn current(&self) -> Arc<AST> {
+        let mut loaded = self.loaded.lock().unwrap();
+        if loaded.checked.elapsed() >= self.reload_interval {
+            loaded.checked = Instant::now();
+            let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+            if modified != loaded.modified {
+                loaded.modified = modified; // A broken edit is only reported once
+                match compile(&self.engine, &self.path) {
+                    Ok(ast) => {
+                        info!("Reloaded script {}", self.path.display());
+                        loaded.ast = Arc::new(ast);
+                    }
+                    Err(e) => error!("Keeping the previous script: {}", e),
+                }
+            }
+        }
+        loaded.ast.clone()
+    }
+}
+
+fn compile(engine: &Engine, path: &Path) -> io::Result<AST> {
+    let source = fs::read_to_string(path)?;
+    engine.compile(source).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), e),
+        )
+    })
+}
+
+impl Middleware for Script {
+    fn call(&self, request: Request, next: Next<'_>) -> Response {
+        if self
+            .kinds
+            .as_ref()
+            .is_some_and(|k| !k.contains(&request.kind))
+        {
+            return next.run(request);
+        }
+        match self.handle(&request.message) {
+            Some(response) => Ok(response),
+            None => next.run(request),
+        }
+    }
+}
+
+// The request as the map handed to the script
+fn to_map(request: &client_message::Message) -> Map {
+    use client_message::Message;
+    let mut map = Map::new();
+    let mut set = |name: &str, value: Dynamic| {
+        map.insert(name.into(), value);
+    };
+    let kind = match request {
+        Message::EchoMessage(echo) => {
+            set("content", echo.content.clone().into());
+            "echo"
+        }
+        Message::AddRequest(add) => {
+            set("a", Dynamic::from_int(add.a.into()));
+            set("b", Dynamic::from_int(add.b.into()));
+            "add"
+        }
+        Message::PingRequest(ping) => {
+            set("timestamp", Dynamic::from_int(ping.timestamp as i64));
+            "ping"
+        }
+        Message::TelemetryReport(report) => {
+            set("sensor_id", Dynamic::from_int(report.sensor_id.into()));
+            set("value", Dynamic::from_float(report.value.into()));
+            set("timestamp", Dynamic::from_int(report.timestamp as i64));
+            "telemetry"
+        }
+        Message::EchoBytes(echo) => {
+            set("data", Dynamic::from_blob(echo.data.clone()));
+            "echo_bytes"
+        }
+        Message::TransformRequest(transform) => {
+            set("content", transform.content.clone().into());
+            set("op", Dynamic::from_int(transform.op.into()));
+            "transform"
+        }
+        Message::RandomRequest(random) => {
+            set("num_bytes", Dynamic::from_int(random.num_bytes.into()));
+            "random"
+        }
+        Message::CalcRequest(calc) => {
+            set("expression", calc.expression.clone().into());
+            "calc"
+        }
+        Message::DescribeRequest(_) => "describe",
+        Message::ResumeRequest(resume) => {
+            set("token", Dynamic::from_blob(resume.token.clone()));
+            set("device_id", resume.device_id.clone().into());
+            set("vhost", resume.vhost.clone().into());
+            "resume"
+        }
+        Message::QuotaRequest(_) => "quota",
+        Message::ResyncRequest(resync) => {
+            set("from", Dynamic::from_int(resync.from as i64));
+            set("to", Dynamic::from_int(resync.to as i64));
+            "resync"
+        }
+        Message::DiagnosticsRequest(_) => "diagnostics",
+        Message::AvailabilityRequest(_) => "availability",
+        Message::ConnectionHistoryRequest(request) => {
+            set("device", request.device.clone().into());
+            set("limit", Dynamic::from_int(request.limit.into()));
+            "connection_history"
+        }
+        Message::DeadLettersRequest(request) => {
+            set("device", request.device.clone().into());
+            set("limit", Dynamic::from_int(request.limit.into()));
+            "dead_letters"
+        }
+        Message::FaultRulesRequest(request) => {
+            set("clear", request.clear.into());
+            "fault_rules"
+        }
+        Message::SubscribeRequest(request) => {
+            set("filter", request.filter.clone().into());
+            "subscribe"
+        }
+        Message::UnsubscribeRequest(request) => {
+            set("filter", request.filter.clone().into());
+            "unsubscribe"
+        }
+        Message::PublishRequest(request) => {
+            set("topic", request.topic.clone().into());
+            set("payload", Dynamic::from_blob(request.payload.to_vec()));
+            set("retain", request.retain.into());
+            "publish"
+        }
+        Message::TailLogs(tail) => {
+            set("level", Dynamic::from_int(tail.level.into()));
+            set("filter", tail.filter.clone().into());
+            "tail_logs"
+        }
+        Message::ClusterEvent(event) => {
+            set("node", event.node.clone().into());
+            "cluster"
+        }
+        Message::Batch(batch) => {
+            set("messages", Dynamic::from_int(batch.messages.len() as i64));
+            "batch"
+        }
+    };
+    map.insert("kind".into(), kind.into());
+    map
+}
+
+// The response returned by the script, as a map
+fn from_dynamic(response: Dynamic) -> Result<server_message::Message, String> {
+    let type_name = response.type_name();
+    let mut fields = Fields(
+        response
+            .try_cast::<Map>()
+            .ok_or_else(|| format!("returned a {}, not a map", type_name))?,
+    );
+    use server_message::Message;
+    let kind = fields.string("kind")?;
+    let response = match kind.as_str() {
+        "echo" => Message::EchoMessage(EchoMessage {
+            content: fields.string("content")?,
+        }),
+        "add" => Message::AddResponse(AddResponse {
+            result: fields.int("result")?,
+        }),
+        "ping" => Message::PingResponse(PingResponse {
+            timestamp: fields.int("timestamp")?,
+        }),
+        "telemetry" => Message::TelemetryAck(TelemetryAck {
+            sensor_id: fields.int("sensor_id")?,
+            timestamp: fields.int("timestamp")?,
+        }),
+        "echo_bytes" => Message::EchoBytes(EchoBytes {
+            data: fields.blob("data")?,
+        }),
+        "transform" => Message::TransformResponse(TransformResponse {
+            content: fields.string("content")?,
+        }),
+        "random" => Message::RandomResponse(RandomResponse {
+            data: fields.blob("data")?,
+        }),
+        "calc" => Message::CalcResponse(CalcResponse {
+            result: fields.float("result")?,
+        }),
+        "error" => Message::ErrorResponse(ErrorResponse {
+            code: fields.int("code")?,
+            retry_after_ms: fields.int("retry_after_ms")?,
+            field: fields.string("field")?,
+            detail: fields.string("detail")?,
+        }),
+        _ => return Err(format!("returned a response of unknown kind {:?}", kind)),
+    };
+    Ok(response)
+}
+
+// Fields of a returned map, each defaulting when missing
+struct Fields(Map);
+
+impl Fields {
+    fn int<T: TryFrom<i64> + Default>(&mut self, name: &str) -> Result<T, String> {
+        let Some(value) = self.0.remove(name) else {
+            return Ok(T::default());
+        };
+        let value = value
+            .as_int()
+            .map_err(|t| format!("`{}` is a {}, not an integer", name, t))?;
+        T::try_from(value).map_err(|_| format!("`{}` is out of range: {}", name, value))
+    }
+
+    fn float(&mut self, name: &str) -> Result<f64, String> {
+        match self.0.remove(name) {
+            None => Ok(0.0),
+            Some(value) if value.is_int() => Ok(value.as_int().unwrap_or_default() as f64),
+            Some(value) => value
+                .as_float()
+                .map_err(|t| format!("`{}` is a {}, not a number", name, t)),
+        }
+    }
+
+    fn string(&mut self, name: &str) -> Result<String, String> {
+        match self.0.remove(name) {
+            None => Ok(String::new()),
+            Some(value) => value
+                .into_string()
+                .map_err(|t| format!("`{}` is a {}, not a string", name, t)),
+        }
+    }
+
+    fn blob(&mut self, name: &str) -> Result<Vec<u8>, String> {
+        match self.0.remove(name) {
+            None => Ok(Vec::new()),
+            Some(value) => value
+                .into_blob()
+                .map_err(|t| format!("`{}` is a {}, not a blob", name, t)),
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! Ordered delivery of sequence-numbered messages.
+//!
+//! A subscriber numbers nothing itself: the sender stamps each message of a
+//! session with the next sequence number, starting at 1. [`Reorderer`] takes
+//! them as they arrive, hands them out in order, holds back those that arrived
+//! early and reports the range still [`missing`](Reorderer::missing), so the
+//! subscriber can ask for it again or, once it gives up, [`skip_to`](Reorderer::skip_to)
+//! the next message it has.
+
+use alloc::collections::BTreeMap;
+use core::ops::Range;
+
+/// Sequence number of the first message of a session
+pub const FIRST_SEQUENCE: u64 = 1;
+
+/// Too many messages arrived ahead of a gap to keep holding them
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Overflow {
+    /// Sequence numbers that never arrived
+    pub missing: Range<u64>,
+}
+
+/// Puts sequence-numbered messages back in order
+#[derive(Debug)]
+pub struct Reorderer<T> {
+    next: u64, // Sequence number to deliver next
+    early: BTreeMap<u64, T>,
+    capacity: usize, // Messages held back at most
+}
+
+impl<T> Reorderer<T> {
+    /// Creates a reorderer that holds back at most `capacity` early messages
+    pub fn new(capacity: usize) -> Self {
+        Reorderer {
+            next: FIRST_SEQUENCE,
+            early: BTreeMap::new(),
+            capacity,
+        }
+    }
+
+    /// Accepts a message; duplicates and messages already delivered are dropped.
+    ///
+    /// Fails if holding it would exceed the capacity; the message is still kept
+    /// and the caller should resync or skip past the gap.
+    pub fn push(&mut self, sequence: u64, message: T) -> Result<(), Overflow> {
+        if sequence < self.next {
+            return Ok(());
+        }
+        self.early.entry(sequence).or_insert(message);
+        if self.early.len() > self.capacity {
+            if let Some(missing) = self.missing() {
+                return Err(Overflow { missing });
+            }
+        }
+        Ok(())
+    }
+
+    /// Next message in order, if it has arrived
+    pub fn pop(&mut self) -> Option<T> {
+        let message = self.early.remove(&self.next)?;
+        self.next += 1;
+        Some(message)
+    }
+
+    /// Sequence numbers holding up delivery: from the next one due to the earliest held back
+    pub fn missing(&self) -> Option<Range<u64>> {
+        let (&first, _) = self.early.first_key_value()?;
+        (first > self.next).then_some(self.next..first)
+    }
+
+    /// Gives up on everything before `sequence`, e.g. after a resync or a lost gap
+    pub fn skip_to(&mut self, sequence: u64) {
+        if sequence > self.next {
+            self.next = sequence;
+            self.early = self.early.split_off(&sequence);
+        }
+    }
+
+    /// Sequence number expected next
+    pub fn next_sequence(&self) -> u64 {
+        self.next
+    }
+}
@@ -1,103 +1,3310 @@
-use crate::message::EchoMessage; // Import the message format defined by protobuf
-use log::{error, info, warn}; // Import logging macros
-use prost::Message; // For encoding and decoding protobuf messages
+use crate::authz::{Action, Authorizer}; // Who may send which requests
+use crate::availability::Tracker; // Uptime, stalls and overload for SLA reports
+use crate::batch; // Unpacks requests sent in one frame
+use crate::capture::{CaptureWriter, Direction}; // Optional per-connection traffic capture
+use crate::close::{is_sent, reason_of, CLOSE_GRACE}; // Why connections close, told to clients
+use crate::cluster::{Cluster, Node, Received}; // Topics and devices shared with other servers
+use crate::codec::{self, CodecError}; // Encodes flow control grants and broadcasts
+use crate::deadletter::{DeadLetter, DeadLetterSink, DeadLetters, DeadReason}; // Keeps messages that were not delivered
+use crate::dedup::DedupWindow; // Answers retried requests without handling them again
+use crate::diagnostics; // Self-checks reported on request
+#[cfg(feature = "fault-injection")]
+use crate::fault::{ConnectionFaults, Fault, FaultInjector, Rule}; // Test-only response faults
+use crate::flow::{window_update, ReceiveWindows, FLOW_CONTROL_VERSION}; // Per-stream credits
+use crate::handler::MessageKind; // Per-type configuration and statistics
+use crate::health::{Health, LIVENESS_TIMEOUT}; // Liveness and readiness probes
+use crate::history::{ClosedConnection, ConnectionHistory}; // Recently closed connections
+use crate::link::{Link, Listener}; // Connections of TCP and other transports
+use crate::loglimit::{self, limited, LogClass, LogLimits}; // Caps on noisy log lines
+use crate::logtail::LogTail; // Streams the log to operators
+use crate::mailbox::{Mailbox, MailboxLimits, Overflow, Posted}; // Bounded queues of pushed frames
+use crate::message::{
+    client_message, close::Reason, diagnostic_check::Status, error_response, server_message,
+    AvailabilityReport, ClientMessage, Close, ClusterAck, ClusterEvent, ConnectionHistoryResponse,
+    ConnectionRecord, DeadLetterRecord, DeadLettersResponse, Delivery, DiagnosticsReport,
+    ErrorResponse, FaultRulesRequest, GoAway, Publication, PublishResponse, QuotaStatus,
+    ResumeRequest, ResumeResponse, ResyncRequest, ResyncResponse, ServerMessage, SubscribeResponse,
+    TailLogsResponse, UnsubscribeResponse,
+}; // Import the message formats defined by protobuf
+#[cfg(feature = "fault-injection")]
+use crate::message::{FaultRule, FaultRulesResponse}; // Fault rules as admins see them
+use crate::middleware::{self, Middleware, Next}; // Layers around request handling
+use crate::observer::{ConnectionInfo, Observer}; // Connection lifecycle hooks
+use crate::outbox::{Outboxes, BROADCAST_SEQUENCE, DELIVERY_POLL_INTERVAL, SWEEP_INTERVAL}; // Messages queued for devices
+use crate::overload::{OverloadLimits, OverloadPolicy}; // Busy responses once overloaded
+use crate::panics; // Connection context for the panic hook
+use crate::priority::{priority, Priority, PriorityQueue}; // Order in which queued requests and connections are handled
+use crate::protocol::{Event, ServerProtocol}; // Sans-IO protocol state machine
+use crate::quota::{self, Quota, Quotas}; // Daily limits per device
+use crate::relay::Upstream; // Forwards requests in relay mode
+use crate::resume::{SessionState, SessionStore, DEFAULT_SESSION_EXPIRY, MAX_PARKED_SESSIONS}; // Sessions parked between connections
+use crate::retained::Retained; // Last messages kept for new subscribers
+use crate::router::Router; // Computes the response to each request
+use crate::sharded::{Sharded, DEFAULT_SHARDS}; // Maps locked in parts, so handlers contend less
+use crate::share::{self, SharedSubscriptions, SHARE_PREFIX}; // Groups that split a topic's messages
+use crate::shutdown::{Registry, StopSignal}; // Stop flag and open connections, safe against its races
+use crate::slab::Key; // Handles of open connections
+use crate::socket::ServerConfig; // Backlog, buffer sizes and marking of the sockets
+use crate::stats::{Counters, Stats, StatsSnapshot, TenantCounters, TenantStats}; // Request and thread pool counters
+use crate::tenant::{self, Tenant}; // Customers sharing the server
+use crate::throttle::TokenBucket; // Per-connection bandwidth limits
+use crate::topic::{self, TopicError, TopicTrie}; // Subscriptions matched against published topics
+use crate::vhost::VirtualHost; // Per-customer configuration on one listener
+use crate::watchdog::{self, Watchdog, CHECK_INTERVAL}; // Acts on a stuck server
+use crate::wirelog::WireLog; // Runtime-togglable hex dumps of the traffic
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender}; // Accepted connections, the stop signal and handler results
+use log::{debug, error, info, warn, Level}; // Import logging macros
+use prost::bytes::Bytes; // Payloads queued for devices, shared rather than copied
+use prost::Message; // For measuring request sizes
 use std::{
-    io::{self, ErrorKind, Read, Write}, // For input/output operations
-    net::{TcpListener, TcpStream},      // For network operations
-    sync::{
-        atomic::{AtomicBool, Ordering}, // For atomic operations on shared state
-        Arc,                            // For sharing state across threads
-    },
-    time::Duration, // For adding delays
+    collections::{HashMap, HashSet}, // Tenants and virtual hosts by name, known devices
+    fs::File,                        // Capture files
+    io::{self, BufWriter, ErrorKind, Read, Write}, // For input/output operations
+    net::{Shutdown, SocketAddr, TcpListener}, // For network operations
+    panic::{self, AssertUnwindSafe}, // Panicking handlers take down only their connection
+    path::{Path, PathBuf},           // Capture directory
+    sync::atomic::{AtomicU64, AtomicU8, Ordering}, // For atomic operations on shared state
+    sync::{Arc, Condvar, Mutex, RwLock}, // For sharing state across threads
+    thread,                          // Dispatcher and worker threads, and core count
+    time::{Duration, Instant, SystemTime}, // For adding delays and timing requests
 };
-use threadpool::ThreadPool; // For managing a pool of threads
+
+// State shared by the server and all of its connections
+struct Shared {
+    wire_log: WireLog,              // Hex-dump logging, off unless enabled
+    counters: Counters,             // Exposed through `Server::stats`
+    slow_request_micros: AtomicU64, // Slow-request threshold; `u64::MAX` disables it
+    first_frame_micros: AtomicU64, // Time a connection has to send its first frame; `u64::MAX` disables it
+    frame_micros: AtomicU64,       // Time to complete a frame once started; `u64::MAX` disables it
+    handler_micros: AtomicU64,     // Time a handler may take; `u64::MAX` disables it
+    denial_limit: AtomicU64, // Denied requests that close a connection; `u64::MAX` disables it
+    overload_queued: AtomicU64, // Not ready from this many queued connections; `u64::MAX` disables it
+    workers: usize,             // Threads serving connections, split over the shards
+    handlers: HandlerPool,      // Runs handlers while a handler timeout is set
+    overload: OverloadLimits,   // When to answer busy instead of handling
+    epoch: Instant,             // Origin of the liveness timestamps, in microseconds
+    accept_beat: AtomicU64,     // Last turn of the accept loop; 0 until `run()`
+    availability: Tracker,      // Uptime, accept stalls and overload periods
+    history: ConnectionHistory, // Connections closed lately, for the admin API
+    upload_limit: AtomicU64,    // Bytes per second read from each client; `u64::MAX` disables it
+    download_limit: AtomicU64,  // Bytes per second written to each client; `u64::MAX` disables it
+    connection_memory: AtomicU64, // Bytes each connection may buffer; `u64::MAX` disables it
+    connections: Sharded<Registry<OpenConnection>>, // Sockets closed by `stop()` to wake their handlers
+    mailbox_limits: Mutex<MailboxLimits>, // Size of each connection's mailbox, and what overflow does
+    sessions: Sharded<SessionStore>, // Sessions of closed connections by token, until resumed or expired
+    outboxes: Sharded<Outboxes>,     // Messages waiting for each device, by device
+    sweep_micros: AtomicU64,         // How often the device queues are swept
+    known_devices: Mutex<Option<HashSet<String>>>, // Devices messages may be sent to; `None` for any
+    quotas: Sharded<Quotas>, // Daily limits of each device, and its usage today, by device
+    tenants: Mutex<HashMap<String, Arc<TenantCounters>>>, // Registered tenants
+    tenant_quotas: Sharded<Quotas>, // Daily limits of each tenant's devices together, by tenant
+    topics: RwLock<Topics>,  // Subscriptions of the open connections; publishing only reads them
+    retained: RwLock<Retained<Bytes>>, // Encoded `Publication` frames, flagged as retained; locked after `topics`
+    cluster: Option<Node>,             // Other servers sharing the topics and devices, if clustered
+}
+
+// A connection whose handler has not finished yet
+struct OpenConnection {
+    number: u64,           // Counting from 1, as shown to observers and in capture files
+    stream: Box<dyn Link>, // Clone of the handler's socket
+    gauges: Arc<Gauges>,   // Updated by the handler
+    mailbox: Arc<Mailbox>, // Emptied by the handler
+}
+
+// Handle to a registered connection: its shard of the registry and its slot there
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct ConnectionId {
+    shard: usize,
+    key: Key,
+}
+
+// A connection subscribed to topics, as publications reach it
+#[derive(Clone)]
+struct Subscriber {
+    connection: ConnectionId,
+    mailbox: Arc<Mailbox>, // Publications wait here for the handler
+}
+
+impl PartialEq for Subscriber {
+    fn eq(&self, other: &Self) -> bool {
+        self.connection == other.connection
+    }
+}
+
+// Topic subscriptions of the open connections; see `crate::topic` and `crate::share`
+#[derive(Default)]
+struct Topics {
+    subscriptions: TopicTrie<Subscriber>,
+    shared: SharedSubscriptions<Subscriber>, // Each message goes to one member of a group
+    filters: HashMap<ConnectionId, Vec<String>>, // Of each subscriber, dropped when it closes
+    holders: HashMap<ConnectionId, String>,  // Device each subscriber named, if it did
+    held: HashMap<String, usize>,            // Subscriptions of each device, across its connections
+    parked: HashMap<Vec<u8>, ParkedTopics>,  // Of the sessions waiting to be resumed, by token
+}
+
+// Subscriptions of a closed connection, kept with its session
+struct ParkedTopics {
+    subscriber: Subscriber, // The closed connection, with a mailbox that keeps what is published
+    filters: Vec<String>,
+    device: Option<String>,
+}
+
+impl Topics {
+    // Adds `subscriber` under `filter`, which may name a shared subscription
+    fn subscribe(&mut self, filter: &str, subscriber: Subscriber) -> Result<(), TopicError> {
+        match share::parse(filter) {
+            Some((group, filter)) => self.shared.subscribe(group, filter, subscriber),
+            None => self.subscriptions.insert(filter, subscriber),
+        }
+    }
+
+    // Removes `subscriber` from under `filter`, as it subscribed
+    fn unsubscribe(&mut self, filter: &str, subscriber: &Subscriber) {
+        match share::parse(filter) {
+            Some((group, filter)) => self.shared.unsubscribe(group, filter, subscriber),
+            None => self.subscriptions.remove(filter, subscriber),
+        };
+    }
+
+    // Counts `subscriptions` more, or fewer if negative, against the device of
+    // `connection`, if it named one
+    fn hold(&mut self, connection: &ConnectionId, subscriptions: isize) {
+        if let Some(device) = self.holders.get(connection).cloned() {
+            self.count(&device, subscriptions);
+        }
+    }
+
+    fn count(&mut self, device: &str, subscriptions: isize) {
+        let held = self.held.entry(device.to_string()).or_default();
+        *held = held.saturating_add_signed(subscriptions);
+        if *held == 0 {
+            self.held.remove(device);
+        }
+    }
+
+    // Subscriptions `device` holds, across its connections
+    fn held(&self, device: &str) -> usize {
+        self.held.get(device).copied().unwrap_or(0)
+    }
+}
+
+// What a connection's handler reports about itself, for health checks, the watchdog and stats
+#[derive(Default)]
+struct Gauges {
+    busy_since: AtomicU64, // When the handler started on the current requests; 0 when idle
+    buffered: AtomicU64,   // Bytes held in the connection's receive and transmit buffers
+    device: Mutex<Option<String>>, // Device the client named itself, for `kick()`
+    closed_by: Mutex<Option<Close>>, // Why another thread closed the connection, if one did
+}
+
+// What a registered connection's handler shares with the server
+struct Registration {
+    id: ConnectionId,
+    gauges: Arc<Gauges>,
+    mailbox: Arc<Mailbox>,
+}
+
+impl Shared {
+    // Where messages that were not delivered go; every shard has the same sink
+    fn dead_letter_sink(&self) -> Option<Arc<dyn DeadLetterSink>> {
+        let first = self.outboxes.shards().next();
+        first.map(|outboxes| Arc::clone(outboxes.sink()))
+    }
+
+    // Keeps a handle for `close_connections`, or returns `None` if the server
+    // is stopping. Connections take the shards in turn.
+    fn register(&self, number: u64, stream: &dyn Link) -> io::Result<Option<Registration>> {
+        let shard = number as usize % self.connections.shard_count();
+        let mut connections = self.connections.lock_index(shard);
+        let gauges = Arc::new(Gauges::default());
+        let mailbox = Arc::new(Mailbox::default());
+        let open = OpenConnection {
+            number,
+            stream: stream.try_clone()?,
+            gauges: gauges.clone(),
+            mailbox: mailbox.clone(),
+        };
+        let Ok(key) = connections.insert(open) else {
+            return Ok(None); // Closed by `stop()`
+        };
+        Ok(Some(Registration {
+            id: ConnectionId { shard, key },
+            gauges,
+            mailbox,
+        }))
+    }
+
+    fn deregister(&self, connection: ConnectionId) {
+        let mut connections = self.connections.lock_index(connection.shard);
+        if let Some(open) = connections.remove(connection.key) {
+            open.mailbox.close();
+        }
+    }
+
+    // Shuts one connection's socket down, so its handler returns at once; it
+    // is not told `close`, as it would not read it either
+    fn disconnect(&self, connection: ConnectionId, close: Close) {
+        let connections = self.connections.lock_index(connection.shard);
+        if let Some(open) = connections.get(connection.key) {
+            *open.gauges.closed_by.lock().unwrap() = Some(close);
+            let _ = open.stream.shutdown(Shutdown::Both); // The client may already be gone
+        }
+    }
+
+    // Shuts the reading side of every open socket down, so blocked reads return
+    // at once and the handlers tell their clients; returns their numbers
+    fn close_connections(&self) -> Vec<u64> {
+        let mut numbers = Vec::new();
+        self.connections.for_each(|connections| {
+            for open in connections.close() {
+                let _ = open.stream.shutdown(Shutdown::Read); // The client may already be gone
+                numbers.push(open.number);
+            }
+        });
+        numbers
+    }
+
+    // Closes the connections of `device` like `close_connections`, after which
+    // their handlers tell them they were kicked; returns their numbers
+    fn kick(&self, device: &str) -> Vec<u64> {
+        let mut numbers = Vec::new();
+        self.connections.for_each(|connections| {
+            for open in connections.values() {
+                if open.gauges.device.lock().unwrap().as_deref() != Some(device) {
+                    continue;
+                }
+                *open.gauges.closed_by.lock().unwrap() = Some(Close {
+                    reason: Reason::Kicked as i32,
+                    detail: "closed by an operator".to_string(),
+                });
+                let _ = open.stream.shutdown(Shutdown::Read); // The client may already be gone
+                numbers.push(open.number);
+            }
+        });
+        numbers
+    }
+
+    // Shuts down whatever remains of the connections numbered `numbers` once
+    // `CLOSE_GRACE` has passed, for handlers stuck writing to a client that
+    // does not read
+    fn close_after_grace(self: &Arc<Self>, numbers: Vec<u64>) {
+        if numbers.is_empty() {
+            return;
+        }
+        let shared = Arc::clone(self);
+        std::thread::spawn(move || {
+            std::thread::sleep(CLOSE_GRACE);
+            shared.connections.for_each(|connections| {
+                let stuck = (connections.values()).filter(|open| numbers.contains(&open.number));
+                for open in stuck {
+                    let _ = open.stream.shutdown(Shutdown::Both); // The client may already be gone
+                }
+            });
+        });
+    }
+
+    // Answers the topic requests, which the server brokers itself, within the
+    // namespace of the subscriber's `tenant`, if it has one; hands any
+    // other request back. Subscriptions count against the quota of the
+    // subscriber's `device`, if it named one.
+    fn broker(
+        &self,
+        subscriber: &Subscriber,
+        device: Option<&str>,
+        tenant: Option<&str>,
+        request: client_message::Message,
+    ) -> Result<server_message::Message, client_message::Message> {
+        match request {
+            client_message::Message::SubscribeRequest(request) => {
+                let shared = share::parse(&request.filter);
+                let filter = shared.map_or(request.filter.as_str(), |(_, filter)| filter);
+                if let Err(e) = topic::validate_filter(filter) {
+                    return Ok(invalid("filter", e.to_string()));
+                }
+                if shared.is_none() && filter.split(topic::SEPARATOR).next() == Some(SHARE_PREFIX) {
+                    let detail = "a shared subscription needs a group and a filter";
+                    return Ok(invalid("filter", detail.to_string()));
+                }
+                if filter.starts_with(RESERVED) {
+                    return Ok(invalid("filter", reserved()));
+                }
+                let key = tenant_subscription(tenant, &request.filter);
+                let limit = device.and_then(|device| {
+                    let quotas = self.quotas.lock(device);
+                    quotas.quota(device).max_subscriptions
+                });
+                let retained: Vec<Bytes> = {
+                    let mut topics = self.topics.write().unwrap();
+                    let connection = subscriber.connection;
+                    let subscribed = (topics.filters.get(&connection))
+                        .is_some_and(|filters| filters.contains(&key));
+                    if !subscribed {
+                        if let (Some(device), Some(limit)) = (device, limit) {
+                            if topics.held(device) as u64 >= limit {
+                                drop(topics);
+                                return Ok(self.refuse_subscription(tenant));
+                            }
+                        }
+                        if let Some(device) = device {
+                            topics
+                                .holders
+                                .entry(connection)
+                                .or_insert(device.to_string());
+                        }
+                        topics
+                            .filters
+                            .entry(connection)
+                            .or_default()
+                            .push(key.clone());
+                        topics.hold(&connection, 1);
+                        // Cannot fail, as the filter is valid
+                        let _ = topics.subscribe(&key, subscriber.clone());
+                        if let Some(node) = &self.cluster {
+                            node.subscribed(&key);
+                        }
+                    }
+                    // As in MQTT, the members of a group are not sent retained messages
+                    match shared {
+                        Some(_) => Vec::new(),
+                        None => (self.retained.read().unwrap())
+                            .matching(&tenant_topic(tenant, filter))
+                            .into_iter()
+                            .map(|(_, frame)| frame.clone())
+                            .collect(),
+                    }
+                };
+                // Queued behind the response, which the handler sends first
+                let mailbox = [(subscriber.connection, subscriber.mailbox.clone())];
+                let retained = (retained.into_iter())
+                    .map(|frame| self.post_to(frame, mailbox.to_vec()))
+                    .sum::<usize>();
+                Ok(server_message::Message::SubscribeResponse(
+                    SubscribeResponse {
+                        retained: retained as u32,
+                    },
+                ))
+            }
+            client_message::Message::UnsubscribeRequest(request) => {
+                let key = tenant_subscription(tenant, &request.filter);
+                let mut topics = self.topics.write().unwrap();
+                let filters = topics.filters.entry(subscriber.connection).or_default();
+                let subscribed = filters.contains(&key);
+                filters.retain(|filter| *filter != key);
+                if filters.is_empty() {
+                    topics.filters.remove(&subscriber.connection);
+                }
+                if subscribed {
+                    topics.hold(&subscriber.connection, -1);
+                    topics.unsubscribe(&key, subscriber);
+                    if let Some(node) = &self.cluster {
+                        node.unsubscribed(&key);
+                    }
+                }
+                Ok(server_message::Message::UnsubscribeResponse(
+                    UnsubscribeResponse { subscribed },
+                ))
+            }
+            client_message::Message::PublishRequest(request) => {
+                if let Err(e) = topic::validate_topic(&request.topic) {
+                    return Ok(invalid("topic", e.to_string()));
+                }
+                if request.topic.starts_with(RESERVED) {
+                    return Ok(invalid("topic", reserved()));
+                }
+                let scoped = tenant_topic(tenant, &request.topic);
+                let subscribers = match self.publish_here(
+                    &scoped,
+                    &request.topic,
+                    &request.payload,
+                    request.retain,
+                ) {
+                    Ok(subscribers) => subscribers,
+                    Err(e) => return Ok(invalid("payload", e.to_string())),
+                };
+                if let Some(node) = &self.cluster {
+                    node.published(&scoped, &request.topic, request.payload, request.retain);
+                }
+                Ok(server_message::Message::PublishResponse(PublishResponse {
+                    subscribers: subscribers as u32,
+                }))
+            }
+            request => Err(request),
+        }
+    }
+
+    // Hands a message published on `scoped` to the subscribers here, and keeps
+    // or clears it as retained; returns how many connections it went to. It is
+    // sent as the publisher named it, `name`, as only the tenant's own devices
+    // receive it.
+    fn publish_here(
+        &self,
+        scoped: &str,
+        name: &str,
+        payload: &Bytes,
+        retain: bool,
+    ) -> Result<usize, CodecError> {
+        let live = publication(name, payload.clone(), false)?;
+        let kept = publication(name, payload.clone(), true)?;
+        // Collected first, so a publisher blocked on a full mailbox holds no lock;
+        // a connection gets the message once, however many of its filters match
+        let mut mailboxes: Vec<(ConnectionId, Arc<Mailbox>)> = Vec::new();
+        {
+            // Read-locked, so publishers only wait for subscription changes
+            let topics = self.topics.read().unwrap();
+            if self.retained.read().unwrap().retains(scoped, retain) {
+                let mut retained = self.retained.write().unwrap();
+                if retain && payload.is_empty() {
+                    retained.clear(scoped);
+                } else {
+                    let _ = retained.publish(scoped, kept, retain);
+                }
+            }
+            // One member of each matching group, the one with the fewest frames waiting
+            let members = (topics.shared).least_loaded(scoped, |member| member.mailbox.depth());
+            let subscribers = topics.subscriptions.matches(scoped);
+            for subscriber in subscribers.into_iter().chain(members) {
+                if !mailboxes.iter().any(|(id, _)| *id == subscriber.connection) {
+                    mailboxes.push((subscriber.connection, subscriber.mailbox.clone()));
+                }
+            }
+        }
+        Ok(self.post_to(live, mailboxes))
+    }
+
+    // Acts on an event from another node of the cluster, which came on
+    // connection `connection`
+    fn clustered(
+        self: &Arc<Self>,
+        connection: u64,
+        event: ClusterEvent,
+    ) -> server_message::Message {
+        let Some(node) = &self.cluster else {
+            return server_message::Message::ErrorResponse(ErrorResponse {
+                code: error_response::Code::Unsupported as i32,
+                detail: "not clustered".to_string(),
+                ..Default::default()
+            });
+        };
+        let sequence = match node.receive(connection, event) {
+            Received::Publish(publish) => {
+                let payload = &publish.payload;
+                if let Err(e) =
+                    self.publish_here(&publish.topic, &publish.name, payload, publish.retain)
+                {
+                    return invalid("payload", e.to_string());
+                }
+                0
+            }
+            // Queued here whatever this node knows, so a message is passed on at most once
+            Received::Send(send) => {
+                let ttl =
+                    Some(Duration::from_millis(send.ttl_ms.into())).filter(|ttl| !ttl.is_zero());
+                let mut outboxes = self.outboxes.lock(&send.device);
+                let (sequence, dropped) = outboxes.push(&send.device, send.payload, ttl);
+                self.counters.dropped(dropped);
+                sequence
+            }
+            Received::Moved { peer, devices } => {
+                let queued: Vec<String> = (devices.into_iter())
+                    .filter(|device| self.outboxes.lock(device).depth(device) > 0)
+                    .collect();
+                if !queued.is_empty() {
+                    // Off the handler, as each message waits for the other node to queue it
+                    let shared = self.clone();
+                    thread::spawn(move || shared.hand_over_queues(&peer, queued));
+                }
+                0
+            }
+            Received::Noted => 0,
+        };
+        server_message::Message::ClusterAck(ClusterAck { sequence })
+    }
+
+    // Queues the messages waiting here for `devices` on the node `peer`, where
+    // they named themselves since; those it does not take stay here
+    fn hand_over_queues(&self, peer: &str, devices: Vec<String>) {
+        let Some(node) = &self.cluster else {
+            return;
+        };
+        for device in devices {
+            let (deliveries, dropped) = self.outboxes.lock(&device).take(&device);
+            self.counters.dropped(dropped);
+            let count = deliveries.len();
+            for delivery in deliveries {
+                if node.send(&device, delivery.payload.clone(), None).is_none() {
+                    let mut outboxes = self.outboxes.lock(&device);
+                    let (_, dropped) = outboxes.push(&device, delivery.payload, None);
+                    self.counters.dropped(dropped);
+                }
+            }
+            info!(
+                "Handed {} queued messages for {} to {}",
+                count, device, peer
+            );
+        }
+    }
+
+    // Drops the subscriptions of a connection that is closing
+    fn leave_topics(&self, subscriber: &Subscriber) {
+        let mut topics = self.topics.write().unwrap();
+        let Some(filters) = topics.filters.remove(&subscriber.connection) else {
+            return;
+        };
+        topics.hold(&subscriber.connection, -(filters.len() as isize));
+        topics.holders.remove(&subscriber.connection);
+        for filter in filters {
+            topics.unsubscribe(&filter, subscriber);
+            if let Some(node) = &self.cluster {
+                node.unsubscribed(&filter);
+            }
+        }
+    }
+
+    // Keeps the subscriptions of a closing connection with its session `token`,
+    // moving them to a mailbox of their own that keeps what is published until
+    // the session is resumed
+    fn park_topics(&self, token: &[u8], subscriber: &Subscriber) {
+        let mut topics = self.topics.write().unwrap();
+        let Some(filters) = topics.filters.remove(&subscriber.connection) else {
+            return;
+        };
+        let device = topics.holders.remove(&subscriber.connection);
+        let parked = Subscriber {
+            connection: subscriber.connection, // Never matches a later connection
+            mailbox: Arc::new(Mailbox::default()),
+        };
+        parked.mailbox.parked.store(true, Ordering::Relaxed);
+        let limits = parked_limits(*self.mailbox_limits.lock().unwrap());
+        for frame in subscriber.mailbox.take().unwrap_or_default() {
+            parked.mailbox.post(frame, limits); // Not sent before it closed
+        }
+        for filter in &filters {
+            topics.unsubscribe(filter, subscriber);
+            let _ = topics.subscribe(filter, parked.clone()); // Valid, as it was subscribed
+        }
+        let parked = ParkedTopics {
+            subscriber: parked,
+            filters,
+            device,
+        };
+        topics.parked.insert(token.to_vec(), parked);
+    }
+
+    // Hands the subscriptions parked with the session `token` to `subscriber`,
+    // which resumed it, followed by what was published for them meanwhile
+    fn resume_topics(&self, token: &[u8], subscriber: &Subscriber) {
+        let frames = {
+            let mut topics = self.topics.write().unwrap();
+            let Some(parked) = topics.parked.remove(token) else {
+                return;
+            };
+            let connection = subscriber.connection;
+            if let Some(device) = parked.device {
+                topics.holders.entry(connection).or_insert(device);
+            }
+            for filter in parked.filters {
+                topics.unsubscribe(&filter, &parked.subscriber);
+                let filters = topics.filters.entry(connection).or_default();
+                if filters.contains(&filter) {
+                    topics.hold(&connection, -1); // Subscribed again before resuming
+                    if let Some(node) = &self.cluster {
+                        node.unsubscribed(&filter);
+                    }
+                } else {
+                    filters.push(filter.clone());
+                    let _ = topics.subscribe(&filter, subscriber.clone());
+                }
+            }
+            parked.subscriber.mailbox.take().unwrap_or_default()
+        };
+        let mailbox = [(subscriber.connection, subscriber.mailbox.clone())];
+        for frame in frames {
+            self.post_to(frame, mailbox.to_vec());
+        }
+    }
+
+    // Drops the subscriptions parked with the sessions `tokens`, which can no
+    // longer be resumed
+    fn forget_topics(&self, tokens: &[Vec<u8>]) {
+        let mut topics = self.topics.write().unwrap();
+        for token in tokens {
+            let Some(parked) = topics.parked.remove(token) else {
+                continue;
+            };
+            for filter in &parked.filters {
+                topics.unsubscribe(filter, &parked.subscriber);
+                if let Some(node) = &self.cluster {
+                    node.unsubscribed(filter);
+                }
+            }
+            if let Some(device) = &parked.device {
+                topics.count(device, -(parked.filters.len() as isize));
+            }
+        }
+    }
+
+    // Refuses a subscription over the device's quota, counting the refusal for
+    // the server and the device's `tenant`, if it has one
+    fn refuse_subscription(&self, tenant: Option<&str>) -> server_message::Message {
+        (self.counters.local().quota_refusals).fetch_add(1, Ordering::Relaxed);
+        if let Some(counters) =
+            tenant.and_then(|tenant| self.tenants.lock().unwrap().get(tenant).cloned())
+        {
+            counters.quota_refusals.fetch_add(1, Ordering::Relaxed);
+        }
+        server_message::Message::ErrorResponse(ErrorResponse {
+            code: error_response::Code::QuotaExceeded as i32,
+            field: "filter".to_string(),
+            detail: quota::Exceeded::Subscriptions.to_string(),
+            ..Default::default()
+        })
+    }
+
+    fn open_connections(&self) -> u64 {
+        let shards = self.connections.shards();
+        shards.map(|connections| connections.len() as u64).sum()
+    }
+
+    // Microseconds since `epoch`, never 0
+    fn now_micros(&self) -> u64 {
+        (self.epoch.elapsed().as_micros() as u64).max(1)
+    }
+
+    // Time since the accept loop last turned, or `None` before `run()`
+    fn accept_idle(&self) -> Option<Duration> {
+        match self.accept_beat.load(Ordering::Relaxed) {
+            0 => None,
+            beat => Some(Duration::from_micros(
+                self.now_micros().saturating_sub(beat),
+            )),
+        }
+    }
+
+    // What every open connection's handler is doing, for the watchdog's diagnostics
+    fn describe_connections(&self) -> String {
+        let now = self.now_micros();
+        let mut lines: Vec<String> = Vec::new();
+        self.connections.for_each(|connections| {
+            lines.extend(connections.values().map(|open| {
+                let peer = peer_name(open.stream.peer_addr());
+                let buffered = open.gauges.buffered.load(Ordering::Relaxed);
+                match open.gauges.busy_since.load(Ordering::Relaxed) {
+                    0 => format!(
+                        "  connection {} ({}): idle, {} bytes buffered",
+                        open.number, peer, buffered
+                    ),
+                    since => format!(
+                        "  connection {} ({}): busy for {:?}, {} bytes buffered",
+                        open.number,
+                        peer,
+                        Duration::from_micros(now.saturating_sub(since)),
+                        buffered
+                    ),
+                }
+            }))
+        });
+        lines.sort();
+        lines.join("\n")
+    }
+
+    // Longest time any handler has been working on its current requests
+    fn longest_busy(&self) -> Duration {
+        let now = self.now_micros();
+        let mut busy = None;
+        self.connections.for_each(|connections| {
+            let longest = (connections.values())
+                .map(|open| open.gauges.busy_since.load(Ordering::Relaxed))
+                .filter(|&since| since != 0)
+                .map(|since| now.saturating_sub(since))
+                .max();
+            busy = busy.max(longest);
+        });
+        Duration::from_micros(busy.unwrap_or(0))
+    }
+
+    // Frames waiting in all mailboxes, and in the fullest one
+    fn mailbox_depths(&self) -> (u64, u64) {
+        let mut depths = (0, 0);
+        self.connections.for_each(|connections| {
+            for open in connections.values() {
+                let depth = open.mailbox.depth() as u64;
+                depths = (depths.0 + depth, depths.1.max(depth));
+            }
+        });
+        depths
+    }
+
+    // Puts `frame` in the mailbox of every connection that has named a device,
+    // or of every open connection if `anonymous`, returning how many took it
+    fn post(&self, frame: Bytes, anonymous: bool) -> usize {
+        // Collected first, so a publisher blocked on a full mailbox holds no shard
+        let mut mailboxes = Vec::new();
+        for (shard, connections) in self.connections.shards().enumerate() {
+            let recipients = (connections.iter())
+                .filter(|(_, open)| anonymous || open.mailbox.is_listening())
+                .map(|(key, open)| (ConnectionId { shard, key }, open.mailbox.clone()));
+            mailboxes.extend(recipients);
+        }
+        self.post_to(frame, mailboxes)
+    }
+
+    // Puts `frame` in each of `mailboxes`, returning how many took it
+    fn post_to(&self, frame: Bytes, mailboxes: Vec<(ConnectionId, Arc<Mailbox>)>) -> usize {
+        let limits = *self.mailbox_limits.lock().unwrap();
+        let stripe = self.counters.local();
+        let mut recipients = 0;
+        for (connection, mailbox) in mailboxes {
+            let limits = match mailbox.is_parked() {
+                true => parked_limits(limits),
+                false => limits,
+            };
+            match mailbox.post(frame.clone(), limits) {
+                Posted::Queued => recipients += 1,
+                Posted::QueuedDroppingOldest => {
+                    recipients += 1;
+                    stripe.mailbox_drops.fetch_add(1, Ordering::Relaxed);
+                }
+                Posted::Dropped => {
+                    stripe.mailbox_drops.fetch_add(1, Ordering::Relaxed);
+                }
+                Posted::Overflowed => {
+                    warn!("Disconnecting connection too slow to take its broadcasts");
+                    stripe
+                        .slow_consumer_disconnects
+                        .fetch_add(1, Ordering::Relaxed);
+                    self.disconnect(
+                        connection,
+                        Close {
+                            reason: Reason::ResourceLimit as i32,
+                            detail: "too slow to take its broadcasts".to_string(),
+                        },
+                    );
+                }
+            }
+        }
+        recipients
+    }
+
+    // Bytes buffered by all open connections together
+    fn buffered_bytes(&self) -> u64 {
+        let shards = self.connections.shards();
+        shards
+            .map(|connections| {
+                (connections.values())
+                    .map(|open| open.gauges.buffered.load(Ordering::Relaxed))
+                    .sum::<u64>()
+            })
+            .sum()
+    }
+
+    fn stats(&self) -> Stats {
+        let mut stats = self.counters.snapshot();
+        stats.pool.workers = self.workers;
+        stats.abandoned_handlers = self.handlers.abandoned();
+        stats.buffered_bytes = self.buffered_bytes();
+        (stats.mailbox_frames, stats.deepest_mailbox) = self.mailbox_depths();
+        stats.tenants = (self.tenants.lock().unwrap().iter())
+            .map(|(name, counters)| (name.clone(), counters.snapshot()))
+            .collect();
+        stats.availability = self.availability.snapshot();
+        stats
+    }
+
+    // What makes the server overloaded: connections waiting for a worker past
+    // the threshold, or the overload policy shedding load
+    fn overload_problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let queued = self.counters.pool.queued_now();
+        if queued >= self.overload_queued.load(Ordering::Relaxed) {
+            problems.push(format!("{} connections waiting for a worker", queued));
+        }
+        let shedding = (self.overload)
+            .refuse_connection(queued, self.open_connections())
+            .or_else(|| self.overload.refuse_request(queued));
+        if let Some(reason) = shedding {
+            problems.push(format!("Shedding load at the {}", reason));
+        }
+        problems
+    }
+
+    // Runs the self-checks listed in `diagnostics`
+    fn diagnose(
+        &self,
+        upstream: Option<&Upstream>,
+        capture_dir: Option<&Path>,
+    ) -> DiagnosticsReport {
+        let stats = self.stats();
+        let mut checks = Vec::new();
+
+        checks.push(match self.accept_idle() {
+            None => diagnostics::check(
+                "listener",
+                Status::Failed,
+                "Accept loop has not started".to_string(),
+            ),
+            Some(idle) if idle >= LIVENESS_TIMEOUT => diagnostics::check(
+                "listener",
+                Status::Failed,
+                format!("Accept loop has not run for {:?}", idle),
+            ),
+            Some(idle) => diagnostics::check(
+                "listener",
+                Status::Ok,
+                format!("Accept loop ran {:?} ago", idle),
+            ),
+        });
+
+        let pool = &stats.pool;
+        let busy = self.longest_busy();
+        let detail = format!(
+            "{} connections on {} workers, {} waiting for a first turn, {} handlers panicked",
+            pool.active, pool.workers, pool.queued, pool.panicked
+        );
+        let status = if busy >= LIVENESS_TIMEOUT {
+            Status::Failed
+        } else if pool.queued >= self.overload_queued.load(Ordering::Relaxed) {
+            Status::Warning
+        } else {
+            Status::Ok
+        };
+        checks.push(diagnostics::check("worker_pool", status, detail));
+
+        let limit = self.connection_memory.load(Ordering::Relaxed);
+        let open = self.open_connections();
+        checks.push(match limit {
+            u64::MAX => diagnostics::check(
+                "memory",
+                Status::Ok,
+                format!("{} bytes buffered, no limit", stats.buffered_bytes),
+            ),
+            limit => {
+                let allowed = limit.saturating_mul(open);
+                let near = stats.buffered_bytes as f64 >= allowed as f64 * diagnostics::NEAR_LIMIT;
+                diagnostics::check(
+                    "memory",
+                    if near && open > 0 {
+                        Status::Warning
+                    } else {
+                        Status::Ok
+                    },
+                    format!(
+                        "{} bytes buffered by {} connections, {} allowed each",
+                        stats.buffered_bytes, open, limit
+                    ),
+                )
+            }
+        });
+
+        let capacity = self.mailbox_limits.lock().unwrap().capacity.max(1) as u64;
+        let mut full_queues = 0;
+        self.outboxes
+            .for_each(|outboxes| full_queues += outboxes.full());
+        let detail = format!(
+            "{} frames in mailboxes, deepest {} of {}; {} device queues full",
+            stats.mailbox_frames, stats.deepest_mailbox, capacity, full_queues
+        );
+        let status = match stats.deepest_mailbox >= capacity || full_queues > 0 {
+            true => Status::Warning,
+            false => Status::Ok,
+        };
+        checks.push(diagnostics::check("queues", status, detail));
+
+        checks.push(match upstream {
+            None => diagnostics::check("upstream", Status::Skipped, "Not relaying".to_string()),
+            Some(upstream) if upstream.is_reachable() => {
+                diagnostics::check("upstream", Status::Ok, "Reachable".to_string())
+            }
+            Some(_) => diagnostics::check("upstream", Status::Failed, "Unreachable".to_string()),
+        });
+
+        // Whatever the server writes to disk must be writable
+        let availability_file = self.availability.file();
+        let targets: Vec<(&Path, bool)> = (capture_dir.map(|dir| (dir, true)).into_iter())
+            .chain(availability_file.as_deref().map(|file| (file, false)))
+            .collect();
+        let problem = targets
+            .iter()
+            .find_map(|&(path, directory)| match path.metadata() {
+                Ok(metadata) if directory && !metadata.is_dir() => {
+                    Some(format!("{} is not a directory", path.display()))
+                }
+                Ok(metadata) if !directory && metadata.is_dir() => {
+                    Some(format!("{} is a directory", path.display()))
+                }
+                Ok(metadata) if metadata.permissions().readonly() => {
+                    Some(format!("{} is read-only", path.display()))
+                }
+                Ok(_) => None,
+                Err(e) => Some(format!("{}: {}", path.display(), e)),
+            });
+        let written = (targets.iter())
+            .map(|(path, _)| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        checks.push(match (targets.is_empty(), problem) {
+            (true, _) => diagnostics::check(
+                "persistence",
+                Status::Skipped,
+                "Nothing is written to disk".to_string(),
+            ),
+            (false, Some(problem)) => diagnostics::check("persistence", Status::Failed, problem),
+            (false, None) => {
+                diagnostics::check("persistence", Status::Ok, format!("Writing to {}", written))
+            }
+        });
+
+        diagnostics::report(checks)
+    }
+
+    fn is_closing(&self) -> bool {
+        self.connections
+            .shards()
+            .any(|connections| connections.is_closed())
+    }
+
+    fn slow_request_threshold(&self) -> Option<Duration> {
+        Shared::duration(&self.slow_request_micros)
+    }
+
+    fn duration(micros: &AtomicU64) -> Option<Duration> {
+        match micros.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            micros => Some(Duration::from_micros(micros)),
+        }
+    }
+
+    fn limit(limit: &AtomicU64) -> Option<u64> {
+        match limit.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            bytes_per_second => Some(bytes_per_second),
+        }
+    }
+}
+
+/// Time a connection has to send its first frame, unless
+/// [`Server::set_first_frame_deadline`] says otherwise
+pub const DEFAULT_FIRST_FRAME_DEADLINE: Duration = Duration::from_secs(10);
+
+/// Time a frame has to arrive in full once its first bytes have, unless
+/// [`Server::set_frame_deadline`] says otherwise
+pub const DEFAULT_FRAME_DEADLINE: Duration = Duration::from_secs(30);
+
+const DEFAULT_WORKERS: usize = 16; // Threads serving connections, unless `Server::workers` says otherwise
+const DEFAULT_HANDLER_THREADS: usize = 64; // Threads handlers run on while a handler timeout is set
+const ACCEPT_QUEUE: usize = 64; // Accepted connections waiting for the dispatcher
+const LONE_TURN: Duration = Duration::from_millis(50); // Longest turn while no other connection waits on the shard
+const SHARED_TURN: Duration = Duration::from_millis(2); // Longest wait for bytes while others wait their turn
+const RETAINED_TOPICS: usize = 10_000; // Topics a message is retained on at most
+const RESERVED: char = '$'; // Starts the first level of the topics the server keeps for itself
+const THROTTLE_STEP: Duration = Duration::from_millis(100); // Longest sleep between checks for `stop()`
+
+// The workers split into one shard per core, each with a queue and threads of
+// its own, so shards never contend with each other. A connection stays on the
+// shard it was placed on and is served in turns: a thread takes it from the
+// queue, handles what has arrived or waits briefly for more, and puts it back.
+// An idle connection holds no thread, so a shard serves any number of them;
+// turns of one connection never overlap, so its messages are processed in
+// order. The queue hands out new connections by the class of their first request.
+struct Shards {
+    shards: Vec<Arc<Shard>>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+struct Shard {
+    queue: Mutex<ShardQueue>,
+    ready: Condvar, // Signalled when a connection is queued or the shard closes
+}
+
+struct ShardQueue {
+    waiting: PriorityQueue<Served>, // Connections between turns
+    fresh: usize,                   // Waiting connections that have not had a turn yet
+    connections: usize,             // Placed on the shard and not closed yet
+    closing: bool,                  // No more come; threads return once the last closes
+}
+
+impl Shards {
+    // One shard per core, or `count` if given, each with at least one of the `workers` threads
+    fn new(workers: usize, count: Option<usize>) -> Self {
+        let count = count
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |cores| cores.get()))
+            .clamp(1, workers);
+        let shards: Vec<_> = (0..count)
+            .map(|_| {
+                Arc::new(Shard {
+                    queue: Mutex::new(ShardQueue {
+                        waiting: PriorityQueue::new(),
+                        fresh: 0,
+                        connections: 0,
+                        closing: false,
+                    }),
+                    ready: Condvar::new(),
+                })
+            })
+            .collect();
+        let mut threads = Vec::new();
+        for (i, shard) in shards.iter().enumerate() {
+            // Spread the remainder over the first shards
+            for _ in 0..workers / count + usize::from(i < workers % count) {
+                let shard = shard.clone();
+                let thread = thread::Builder::new()
+                    .name(format!("shard-{}", i))
+                    .spawn(move || shard.serve())
+                    .expect("Failed to spawn a worker thread");
+                threads.push(thread);
+            }
+        }
+        Shards { shards, threads }
+    }
+
+    // Queues a new connection on the shard with the fewest; ties go to the first
+    fn place(&self, served: Served) {
+        let shard = (self.shards.iter())
+            .min_by_key(|shard| shard.queue.lock().unwrap().connections)
+            .expect("At least one shard");
+        let mut queue = shard.queue.lock().unwrap();
+        queue.connections += 1;
+        queue.fresh += 1;
+        queue.waiting.push(Priority::Normal, served);
+        shard.ready.notify_one();
+    }
+
+    // Lets every thread return once its shard's last connection has closed
+    fn join(self) {
+        for shard in &self.shards {
+            shard.queue.lock().unwrap().closing = true;
+            shard.ready.notify_all();
+        }
+        for thread in self.threads {
+            let _ = thread.join(); // Panics of handlers are caught; nothing else panics
+        }
+    }
+}
+
+impl Shard {
+    // Gives the queued connections turns until the shard closes. A panicking
+    // handler takes down its connection, not the thread.
+    fn serve(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            // New connections are ordered by what they have sent by now
+            if queue.fresh > 0 && queue.waiting.len() > 1 {
+                queue.waiting.reclassify(Served::class);
+            }
+            let Some(served) = queue.waiting.pop() else {
+                if queue.closing && queue.connections == 0 {
+                    return;
+                }
+                queue = self.ready.wait(queue).unwrap();
+                continue;
+            };
+            queue.fresh -= usize::from(served.job.is_none());
+            let wait = if queue.waiting.is_empty() {
+                LONE_TURN
+            } else {
+                SHARED_TURN
+            };
+            drop(queue);
+            let turn = panic::catch_unwind(AssertUnwindSafe(move || served.turn(wait)));
+            queue = self.queue.lock().unwrap();
+            match turn {
+                Ok(Some(served)) => queue.waiting.push(Priority::Normal, served),
+                _ => {
+                    queue.connections -= 1;
+                    if queue.closing && queue.connections == 0 {
+                        self.ready.notify_all();
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Class of the first request a waiting connection has sent, going by the bytes
+// on its socket; ordinary until the whole frame is there
+fn first_request_priority(stream: &dyn Link) -> Priority {
+    let mut buffer = [0; 256]; // Enough for any control request
+    match stream.peek(&mut buffer) {
+        Ok(read) => match codec::decode_frame::<ClientMessage>(&buffer[..read]) {
+            Ok(Some((message, _))) => priority(&message),
+            _ => Priority::Normal,
+        },
+        Err(_) => Priority::Normal,
+    }
+}
+
+// A connection placed on a shard, between its turns
+struct Served {
+    client: Client,
+    job: Option<PoolJob>, // Set on its first turn
+}
+
+impl Served {
+    // New connections go by their first request; the others queue as ordinary
+    fn class(&self) -> Priority {
+        match self.job {
+            None => first_request_priority(self.client.stream.as_ref()),
+            Some(_) => Priority::Normal,
+        }
+    }
+
+    // Handles one read, waiting at most `wait` for it; `None` once the connection has closed
+    fn turn(mut self, wait: Duration) -> Option<Self> {
+        let client = &mut self.client;
+        let _context = panics::enter(client.info.id, client.peer);
+        if self.job.is_none() {
+            let connection = client.connection.expect("Registered before it is placed");
+            self.job = Some(PoolJob::start(client.shared.clone(), connection));
+            client.observe(|observer, info| observer.on_connect(info));
+        }
+        // `stop()` shuts the socket down, which ends the connection like a disconnect
+        let ended = match client.handle(wait) {
+            Ok(true) => return Some(self),
+            Ok(false) => Ok(()),
+            Err(e) => {
+                limited!(
+                    LogClass::ClientError,
+                    Level::Error,
+                    connection_id = client.info.id,
+                    peer:% = client.peer_name();
+                    "Error handling client: {}", e
+                );
+                client.observe(|observer, info| observer.on_error(info, &e));
+                Err(e)
+            }
+        };
+        client.finish(ended);
+        None // Dropping the job forgets the socket and counts the connection done
+    }
+}
+
+// Counts a connection as active from its first turn until it closes or its handler
+// panics, then forgets its socket
+struct PoolJob {
+    shared: Arc<Shared>,
+    connection: ConnectionId,
+}
+
+impl PoolJob {
+    fn start(shared: Arc<Shared>, connection: ConnectionId) -> Self {
+        shared.counters.pool.started();
+        PoolJob { shared, connection }
+    }
+}
+
+impl Drop for PoolJob {
+    fn drop(&mut self) {
+        self.shared.deregister(self.connection);
+        self.shared.counters.pool.finished(std::thread::panicking());
+    }
+}
 
 // A struct representing the client connected to the server
 struct Client {
-    stream: TcpStream, // Network stream for communicating with the client
+    stream: Box<dyn Link>,    // Network stream for communicating with the client
+    protocol: ServerProtocol, // Protocol state; this struct only moves bytes in and out
+    windows: Option<ReceiveWindows>, // Credits of each stream, once the client states it has them
+    dedup: DedupWindow,       // Responses kept for retried message IDs
+    session: Option<Vec<u8>>, // Token of the connection's session, once it has one
+    device: Option<String>,   // Device the client named itself, whose messages it gets
+    tenant: Option<(String, Arc<TenantCounters>)>, // Registered tenant of the device, if any
+    vhost: Option<String>,    // Virtual host the client picked, if any
+    virtual_hosts: Arc<HashMap<String, VirtualHost>>, // Hosts it can pick from
+    upload: TokenBucket,      // Meters reads against the upload limit
+    download: TokenBucket,    // Meters writes against the download limit
+    capture: Option<CaptureWriter<BufWriter<File>>>, // Records traffic when capture is enabled
+    upstream: Option<Arc<Upstream>>, // Handles requests instead of this server in relay mode
+    router: Arc<Router>,      // Application handlers for each message type
+    layers: Arc<[Arc<dyn Middleware>]>, // Wrapped around the router or relay, outermost first
+    observers: Arc<[Arc<dyn Observer>]>, // Told about this connection's events
+    authorizer: Option<Arc<dyn Authorizer>>, // Asked about each request; all but admin ones allowed without one
+    log_tail: Option<&'static LogTail>,      // Answers `TailLogs`, if the server has one
+    tailing: bool,                           // Log records arrive in the mailbox
+    capture_dir: Option<Arc<Path>>,          // Checked by `DiagnosticsRequest`
+    info: ConnectionInfo,                    // Handed to the observers
+    gauges: Arc<Gauges>, // Busy marker and buffered bytes, shared with the server
+    mailbox: Arc<Mailbox>, // Frames other threads hand this connection
+    connection: Option<ConnectionId>, // Where the server registered it, once it has
+    subscribed: bool,    // Publications arrive in the mailbox
+    cluster_peer: Option<String>, // Node of the cluster the connection is the link of, if any
+    shared: Arc<Shared>, // Settings and counters shared with the server
+    peer: Option<SocketAddr>, // Shown in wire and slow-request logs
+    started: Instant,    // When the connection was accepted
+    messages: u64,       // Requests handled so far, for the connection history
+    denials: u64,        // Requests refused by the authorizer so far
+    first_frame: bool,   // Whether a complete frame has arrived yet
+    partial_since: Option<Instant>, // When the partial frame in the buffer was started
+    #[cfg(feature = "fault-injection")]
+    faults: Option<ConnectionFaults>, // Faults applied to this connection's responses
+}
+
+impl Client {
+    // Constructor to create a new client instance
+    fn new(stream: Box<dyn Link>, shared: Arc<Shared>) -> Self {
+        Client {
+            peer: stream.peer_addr(),
+            info: ConnectionInfo {
+                id: 0,
+                peer: stream.peer_addr(),
+                connected_at: SystemTime::now(),
+            },
+            stream,
+            protocol: ServerProtocol::new(),
+            windows: None,
+            dedup: DedupWindow::default(),
+            session: None,
+            device: None,
+            tenant: None,
+            vhost: None,
+            virtual_hosts: Arc::default(),
+            upload: TokenBucket::new(Instant::now()),
+            download: TokenBucket::new(Instant::now()),
+            capture: None,
+            upstream: None,
+            router: Arc::default(),
+            layers: Arc::new([]),
+            observers: Arc::new([]),
+            authorizer: None,
+            log_tail: None,
+            tailing: false,
+            capture_dir: None,
+            gauges: Arc::default(),
+            mailbox: Arc::default(),
+            connection: None,
+            subscribed: false,
+            cluster_peer: None,
+            shared,
+            started: Instant::now(),
+            messages: 0,
+            denials: 0,
+            first_frame: false,
+            partial_since: None,
+            #[cfg(feature = "fault-injection")]
+            faults: None,
+        }
+    }
+
+    // Handles communication with the client, waiting at most `wait` for bytes;
+    // returns `false` once it has disconnected
+    fn handle(&mut self, wait: Duration) -> io::Result<bool> {
+        let mut buffer = [0; 512]; // Buffer to store incoming data
+        let Some(bytes_read) = self.read(&mut buffer, wait)? else {
+            // Nothing arrived, but messages may have been queued for the device
+            self.deliver()?;
+            if !self.transmit()? {
+                return Ok(false);
+            }
+            self.stream.flush()?;
+            self.account();
+            return Ok(true);
+        };
+        if bytes_read > 0 {
+            self.record(Direction::Inbound, &buffer[..bytes_read]);
+            self.throttle(Direction::Inbound, bytes_read); // Holds off the next read
+        }
+
+        // No data means the client has disconnected; a single read may also
+        // contain several frames, or only part of one
+        let events = if bytes_read == 0 {
+            self.protocol.feed_eof()
+        } else {
+            self.protocol.feed_bytes(&buffer[..bytes_read])
+        };
+        // A partial frame over the memory limit is never going to fit; give up on it
+        if self.account() > self.shared.connection_memory.load(Ordering::Relaxed) {
+            self.shared
+                .counters
+                .local()
+                .memory_disconnects
+                .fetch_add(1, Ordering::Relaxed);
+            return Err(io::Error::new(
+                ErrorKind::OutOfMemory,
+                format!(
+                    "Connection buffers {} bytes, over its memory limit",
+                    self.protocol.buffered()
+                ),
+            ));
+        }
+
+        let mut pending = PriorityQueue::new(); // Control requests are answered ahead of bulk ones
+        let completed = events
+            .iter()
+            .any(|event| matches!(event, Event::Message(_)));
+        self.first_frame |= completed;
+        self.partial_since = match self.protocol.buffered() {
+            0 => None,
+            _ if completed => Some(Instant::now()),
+            _ => self.partial_since.or_else(|| Some(Instant::now())),
+        };
+        for event in events {
+            match event {
+                Event::Message(message) => {
+                    for message in batch::unpack(message) {
+                        if message.protocol_version >= FLOW_CONTROL_VERSION {
+                            self.windows.get_or_insert_with(ReceiveWindows::new);
+                        }
+                        if let Some(windows) = self.windows.as_mut() {
+                            windows
+                                .received(message.stream_id)
+                                .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+                        }
+                        pending.push(priority(&message), message);
+                    }
+                }
+                Event::Error(e) => return Err(e.into()),
+                Event::Closed => {
+                    limited!(
+                        LogClass::Connection,
+                        Level::Info,
+                        connection_id = self.info.id,
+                        peer:% = self.peer_name();
+                        "Client disconnected."
+                    );
+                    return Ok(false);
+                }
+            }
+        }
+        if !pending.is_empty() {
+            self.gauges
+                .busy_since
+                .store(self.shared.now_micros(), Ordering::Relaxed);
+        }
+        while let Some(message) = pending.pop() {
+            let stream = message.stream_id;
+            self.respond(message)?; // On error the connection is dropped, marker and all
+            if let Some(windows) = self.windows.as_mut() {
+                windows.completed(stream);
+            }
+            // Responses over the memory limit are written out before handling more,
+            // which holds a slow reader up rather than letting its queue grow
+            if self.account() > self.shared.connection_memory.load(Ordering::Relaxed)
+                && !self.transmit()?
+            {
+                return Ok(false);
+            }
+        }
+        self.deliver()?;
+        self.gauges.busy_since.store(0, Ordering::Relaxed);
+
+        if !self.transmit()? {
+            return Ok(false);
+        }
+
+        // Return credits once the responses are on their way; faults never apply to these
+        while let Some((stream, credits)) =
+            self.windows.as_mut().and_then(ReceiveWindows::poll_grant)
+        {
+            let bytes = codec::encode(&window_update(stream, credits))?;
+            self.write_frame(&bytes)?;
+        }
+        self.stream.flush()?; // Ensure all data is sent immediately
+        self.account();
+
+        Ok(true)
+    }
+
+    // Reads from the socket, failing once the first frame or the rest of a partial
+    // one is overdue, so silent or dribbling connections do not stay open. `None`
+    // if nothing arrived within `wait` or before it was time to check the device's queue.
+    fn read(&mut self, buffer: &mut [u8], wait: Duration) -> io::Result<Option<usize>> {
+        let first = Shared::duration(&self.shared.first_frame_micros)
+            .filter(|_| !self.first_frame)
+            .map(|deadline| (self.started + deadline, "first frame"));
+        let partial = Shared::duration(&self.shared.frame_micros)
+            .zip(self.partial_since)
+            .map(|(deadline, since)| (since + deadline, "rest of a frame"));
+        let deadline = first.into_iter().chain(partial).min();
+
+        let poll = (self.device.is_some() || self.tailing || self.subscribed)
+            .then(|| Instant::now() + DELIVERY_POLL_INTERVAL);
+        let turn = Instant::now() + wait; // Others on the shard get a turn after this one
+        let wake = deadline
+            .map(|(at, _)| at)
+            .into_iter()
+            .chain(poll)
+            .fold(turn, Instant::min);
+
+        let remaining = wake.saturating_duration_since(Instant::now());
+        // A zero timeout is rejected, and would mean none
+        self.stream
+            .set_read_timeout(Some(remaining.max(Duration::from_micros(1))))?;
+
+        match self.stream.read(buffer) {
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                match deadline {
+                    Some((at, awaited)) if Instant::now() >= at => {
+                        self.shared
+                            .counters
+                            .local()
+                            .slow_connections
+                            .fetch_add(1, Ordering::Relaxed);
+                        Err(io::Error::new(
+                            ErrorKind::TimedOut,
+                            format!("Closing connection: {} not received in time", awaited),
+                        ))
+                    }
+                    _ => Ok(None),
+                }
+            }
+            result => result.map(Some),
+        }
+    }
+
+    // Publishes the bytes buffered for this connection and returns them
+    fn account(&self) -> u64 {
+        let buffered = (self.protocol.buffered() + self.protocol.queued()) as u64;
+        self.gauges.buffered.store(buffered, Ordering::Relaxed);
+        buffered
+    }
+
+    // Sends every queued response, returning `false` if an injected fault closed the connection
+    fn transmit(&mut self) -> io::Result<bool> {
+        while let Some(bytes) = self.protocol.poll_transmit_bytes() {
+            #[cfg(feature = "fault-injection")]
+            let bytes = match self.faults.as_mut() {
+                Some(faults) => match faults.apply(bytes.into()) {
+                    Fault::Deliver(bytes, delay) => {
+                        std::thread::sleep(delay);
+                        Bytes::from(bytes)
+                    }
+                    Fault::Drop => continue,
+                    Fault::Reset => {
+                        warn!("Injected fault: closing connection");
+                        let _ = self.stream.shutdown(Shutdown::Both); // The client may already be gone
+                        return Ok(false);
+                    }
+                },
+                None => bytes,
+            };
+            self.write_frame(&bytes)?;
+        }
+        Ok(true)
+    }
+
+    // Changes the fault rules as an admin asked, answering with those in effect
+    #[cfg(feature = "fault-injection")]
+    fn change_fault_rules(&self, change: &FaultRulesRequest) -> server_message::Message {
+        let Some(injector) = self.faults.as_ref().map(ConnectionFaults::injector) else {
+            return Self::no_faults();
+        };
+        let added: Result<Vec<Rule>, String> = change.add.iter().map(Rule::try_from).collect();
+        match added {
+            Ok(added) => {
+                let rules = injector.change_rules(change.clear, added);
+                server_message::Message::FaultRulesResponse(FaultRulesResponse {
+                    rules: rules.iter().map(FaultRule::from).collect(),
+                })
+            }
+            Err(detail) => invalid("add", detail),
+        }
+    }
+
+    #[cfg(not(feature = "fault-injection"))]
+    fn change_fault_rules(&self, _change: &FaultRulesRequest) -> server_message::Message {
+        Self::no_faults()
+    }
+
+    fn no_faults() -> server_message::Message {
+        server_message::Message::ErrorResponse(ErrorResponse {
+            code: error_response::Code::Unsupported as i32,
+            detail: "no faults are injected here".to_string(),
+            ..Default::default()
+        })
+    }
+
+    // Queues the response to one request
+    fn respond(&mut self, message: ClientMessage) -> io::Result<()> {
+        let size = message.encoded_len();
+        let Some(request) = message.message else {
+            limited!(
+                LogClass::ClientError,
+                Level::Warn,
+                "Received an empty message"
+            );
+            return Ok(());
+        };
+        let kind = MessageKind::of(&request);
+        let _context = panics::request(kind.name());
+        let reply = (message.message_id, message.stream_id);
+        #[cfg(feature = "fault-injection")]
+        if let Some(faults) = self.faults.as_mut() {
+            faults.request(kind);
+        }
+
+        // Sessions belong to the connection, so the server answers these itself
+        if let client_message::Message::ResumeRequest(resume) = &request {
+            let started = Instant::now();
+            let response = self.resume(resume)?;
+            self.answer(reply, response, kind, size, started)?;
+            return self.deliver(); // Whatever was queued while the device was away
+        }
+
+        // Deliveries are the device's own, so sending them again is always allowed;
+        // they follow the response
+        if let client_message::Message::ResyncRequest(resync) = &request {
+            let started = Instant::now();
+            let (result, replayed) = self.resync(resync);
+            self.answer(reply, result, kind, size, started)?;
+            for delivery in replayed {
+                let message = ServerMessage {
+                    message: Some(server_message::Message::Delivery(delivery)),
+                    ..Default::default()
+                };
+                self.protocol.send(&message)?;
+            }
+            return Ok(());
+        }
+
+        // Checked before the dedup window, which may hold another identity's answer.
+        // Topics are checked as well as the kind of request. Admin requests are
+        // refused unless an authorizer allows them.
+        let identity = self.device.as_deref();
+        let topic = match &request {
+            client_message::Message::PublishRequest(request) => {
+                Some(Action::Publish(&request.topic))
+            }
+            client_message::Message::SubscribeRequest(request) => {
+                // A shared subscription is checked as the filter it shares
+                let shared = share::parse(&request.filter);
+                Some(Action::Subscribe(
+                    shared.map_or(&request.filter, |(_, filter)| filter),
+                ))
+            }
+            _ => None,
+        };
+        let denied = match &self.authorizer {
+            Some(authorizer) => (Some(Action::Send(kind)).into_iter().chain(topic))
+                .find(|action| !authorizer.authorize(identity, *action)),
+            None => kind.is_admin().then_some(Action::Send(kind)),
+        };
+        if let Some(action) = denied {
+            warn!(
+                target: "audit",
+                connection_id = self.info.id,
+                peer:% = self.peer_name(),
+                message_type = kind.name();
+                "Denied {} from {} ({:?}): not allowed to {}",
+                kind.name(),
+                identity.unwrap_or("unnamed client"),
+                self.peer,
+                action
+            );
+            let response = ServerMessage {
+                message: Some(server_message::Message::ErrorResponse(ErrorResponse {
+                    code: error_response::Code::Forbidden as i32,
+                    detail: format!("not allowed to {}", action),
+                    ..Default::default()
+                })),
+                message_id: message.message_id,
+                stream_id: message.stream_id,
+            };
+            self.protocol.send(&response)?;
+            self.shared
+                .counters
+                .local()
+                .denied_requests
+                .fetch_add(1, Ordering::Relaxed);
+            if let Some((_, tenant)) = &self.tenant {
+                tenant.denied_requests.fetch_add(1, Ordering::Relaxed);
+            }
+            self.denials += 1;
+            if self.denials >= self.shared.denial_limit.load(Ordering::Relaxed) {
+                return Err(io::Error::new(
+                    ErrorKind::PermissionDenied,
+                    format!("Closing connection: {} requests denied", self.denials),
+                ));
+            }
+            return Ok(());
+        }
+
+        // The log is the process's, so the server streams it itself
+        if let client_message::Message::TailLogs(tail) = &request {
+            let started = Instant::now();
+            let response = match self.log_tail {
+                Some(log_tail) => {
+                    self.tailing = true;
+                    let recent = log_tail.follow(tail, message.stream_id, self.mailbox.clone());
+                    server_message::Message::TailLogsResponse(TailLogsResponse { recent })
+                }
+                None => server_message::Message::ErrorResponse(ErrorResponse {
+                    code: error_response::Code::Unsupported as i32,
+                    detail: "logs are not streamed here".to_string(),
+                    ..Default::default()
+                }),
+            };
+            self.answer(reply, response, kind, size, started)?;
+            return Ok(());
+        }
+
+        // Only the server can check itself
+        if let client_message::Message::DiagnosticsRequest(_) = &request {
+            let started = Instant::now();
+            let report =
+                (self.shared).diagnose(self.upstream.as_deref(), self.capture_dir.as_deref());
+            let response = server_message::Message::DiagnosticsReport(report);
+            self.answer(reply, response, kind, size, started)?;
+            return Ok(());
+        }
+
+        // Only the server knows how available it has been
+        if let client_message::Message::AvailabilityRequest(_) = &request {
+            let started = Instant::now();
+            let report = AvailabilityReport::from(&self.shared.availability.snapshot());
+            let response = server_message::Message::AvailabilityReport(report);
+            self.answer(reply, response, kind, size, started)?;
+            return Ok(());
+        }
+
+        // Only the server remembers the connections that closed
+        if let client_message::Message::ConnectionHistoryRequest(query) = &request {
+            let started = Instant::now();
+            let device = Some(query.device.as_str()).filter(|device| !device.is_empty());
+            let limit = match query.limit {
+                0 => usize::MAX,
+                limit => limit as usize,
+            };
+            let history = self.shared.history.recent(device, limit);
+            let response =
+                server_message::Message::ConnectionHistoryResponse(ConnectionHistoryResponse {
+                    connections: history.iter().map(ConnectionRecord::from).collect(),
+                });
+            self.answer(reply, response, kind, size, started)?;
+            return Ok(());
+        }
+
+        // Only the server holds its fault injector
+        if let client_message::Message::FaultRulesRequest(change) = &request {
+            let started = Instant::now();
+            let response = self.change_fault_rules(change);
+            self.answer(reply, response, kind, size, started)?;
+            return Ok(());
+        }
+
+        // Only the server keeps what it could not deliver
+        if let client_message::Message::DeadLettersRequest(query) = &request {
+            let started = Instant::now();
+            let device = Some(query.device.as_str()).filter(|device| !device.is_empty());
+            let limit = match query.limit {
+                0 => usize::MAX,
+                limit => limit as usize,
+            };
+            let letters = self.shared.dead_letter_sink().map(|sink| sink.recent());
+            let letters = (letters.unwrap_or_default().iter().rev())
+                .filter(|letter| device.is_none_or(|device| letter.device == device))
+                .take(limit)
+                .map(DeadLetterRecord::from)
+                .collect();
+            let response =
+                server_message::Message::DeadLettersResponse(DeadLettersResponse { letters });
+            self.answer(reply, response, kind, size, started)?;
+            return Ok(());
+        }
+
+        // Only the server knows the rest of its cluster; a link speaks for the
+        // node it named itself as
+        if let client_message::Message::ClusterEvent(event) = &request {
+            let started = Instant::now();
+            let response = match self.device.as_deref() {
+                Some(node) if node == event.node => {
+                    self.cluster_peer = Some(event.node.clone());
+                    self.shared.clustered(self.info.id, event.clone())
+                }
+                _ => invalid(
+                    "node",
+                    format!("not the node {} named itself as", event.node),
+                ),
+            };
+            self.answer(reply, response, kind, size, started)?;
+            return Ok(());
+        }
+
+        // Usage is the device's own, so the server answers these itself
+        if let client_message::Message::QuotaRequest(_) = &request {
+            let started = Instant::now();
+            let response = server_message::Message::QuotaStatus(self.quota_status());
+            self.answer(reply, response, kind, size, started)?;
+            return Ok(());
+        }
+
+        // A retry of a request already handled gets the same response again
+        if let Some(cached) = self.dedup.get(message.message_id) {
+            let response = ServerMessage {
+                message: Some(cached.clone()),
+                message_id: message.message_id,
+                stream_id: message.stream_id,
+            };
+            self.protocol.send(&response)?;
+            self.shared
+                .counters
+                .local()
+                .duplicates
+                .fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+
+        // Refused outright while overloaded, rather than left to time out
+        if let Some(reason) = self
+            .shared
+            .overload
+            .refuse_request(self.shared.counters.pool.queued_now())
+        {
+            limited!(
+                LogClass::Refusal,
+                Level::Debug,
+                connection_id = self.info.id,
+                message_type = kind.name();
+                "Refusing {:?} request: {}", kind, reason
+            );
+            let response = ServerMessage {
+                message: Some(self.shared.overload.busy()),
+                message_id: message.message_id,
+                stream_id: message.stream_id,
+            };
+            self.protocol.send(&response)?;
+            self.shared
+                .counters
+                .local()
+                .shed_requests
+                .fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+
+        // Charged only once it is sure to be handled
+        if let Err(exceeded) = self.charge(size) {
+            limited!(
+                LogClass::Refusal,
+                Level::Debug,
+                connection_id = self.info.id,
+                message_type = kind.name();
+                "Refusing {:?} request from {:?}: {}",
+                kind, self.device, exceeded
+            );
+            let retry_after = quota::until_reset(SystemTime::now());
+            let response = ServerMessage {
+                message: Some(server_message::Message::ErrorResponse(ErrorResponse {
+                    code: error_response::Code::QuotaExceeded as i32,
+                    retry_after_ms: retry_after.as_millis().min(u32::MAX as u128) as u32,
+                    detail: exceeded.to_string(),
+                    ..Default::default()
+                })),
+                message_id: message.message_id,
+                stream_id: message.stream_id,
+            };
+            self.protocol.send(&response)?;
+            self.shared
+                .counters
+                .local()
+                .quota_refusals
+                .fetch_add(1, Ordering::Relaxed);
+            if let Some((_, tenant)) = &self.tenant {
+                tenant.quota_refusals.fetch_add(1, Ordering::Relaxed);
+            }
+            return Ok(());
+        }
+
+        let started = Instant::now();
+        let upstream = self.upstream.clone();
+        let router = self.router.clone();
+        let layers = self.layers.clone();
+        let broker = self.subscriber().map(|subscriber| {
+            let tenant = self.tenant.as_ref().map(|(name, _)| name.clone());
+            (self.shared.clone(), subscriber, self.device.clone(), tenant)
+        });
+        let request = middleware::Request {
+            message: request,
+            kind,
+            message_id: message.message_id,
+            stream_id: message.stream_id,
+            peer: self.peer,
+        };
+        let handler = move || {
+            let handle = |request: client_message::Message| {
+                // Topics are brokered here, even when relaying
+                let request = match &broker {
+                    Some((shared, subscriber, device, tenant)) => {
+                        let (device, tenant) = (device.as_deref(), tenant.as_deref());
+                        match shared.broker(subscriber, device, tenant, request) {
+                            Ok(response) => return Ok(response),
+                            Err(request) => request,
+                        }
+                    }
+                    None => request,
+                };
+                match &upstream {
+                    Some(upstream) => upstream.forward(request),
+                    None => Ok(router.handle(request)),
+                }
+            };
+            Next::new(&layers, &handle).run(request)
+        };
+        let outcome = match Shared::duration(&self.shared.handler_micros) {
+            Some(timeout) => self.shared.handlers.run(timeout, handler)?,
+            None => Handled::Done(handler()),
+        };
+        let result = match outcome {
+            Handled::Done(Ok(result)) => result,
+            Handled::Done(Err(e)) => {
+                // No answer; the device times out and retries as with any lost response
+                limited!(
+                    LogClass::SlowRequest,
+                    Level::Warn,
+                    connection_id = self.info.id,
+                    message_type = kind.name(),
+                    duration_ms = started.elapsed().as_millis() as u64;
+                    "Leaving {:?} request unanswered: {}", kind, e
+                );
+                return Ok(());
+            }
+            Handled::Refused => {
+                // Every handler thread is held by an overrunning handler
+                limited!(
+                    LogClass::Refusal,
+                    Level::Warn,
+                    connection_id = self.info.id,
+                    message_type = kind.name();
+                    "Refusing {:?} request: {} handlers overran their timeout",
+                    kind, self.shared.handlers.abandoned()
+                );
+                let response = ServerMessage {
+                    message: Some(self.shared.overload.busy()),
+                    message_id: message.message_id,
+                    stream_id: message.stream_id,
+                };
+                self.protocol.send(&response)?;
+                self.shared
+                    .counters
+                    .local()
+                    .shed_requests
+                    .fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+            Handled::Overran => {
+                // Not remembered, so a retry runs the handler again
+                let elapsed = started.elapsed();
+                limited!(
+                    LogClass::SlowRequest,
+                    Level::Warn,
+                    connection_id = self.info.id,
+                    peer:% = self.peer_name(),
+                    message_type = kind.name(),
+                    duration_ms = elapsed.as_millis() as u64;
+                    "{:?} request from {} timed out after {:?}",
+                    kind,
+                    self.peer_name(),
+                    elapsed
+                );
+                let response = ServerMessage {
+                    message: Some(server_message::Message::ErrorResponse(ErrorResponse {
+                        code: error_response::Code::Timeout as i32,
+                        ..Default::default()
+                    })),
+                    message_id: message.message_id,
+                    stream_id: message.stream_id,
+                };
+                self.protocol.send(&response)?;
+                self.shared
+                    .counters
+                    .local()
+                    .handler_timeouts
+                    .fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+        };
+        let elapsed = started.elapsed();
+        if let server_message::Message::SubscribeResponse(_) = result {
+            self.subscribed = true;
+        }
+        self.dedup.insert(message.message_id, result.clone());
+        let response = ServerMessage {
+            message: Some(result),
+            message_id: message.message_id,
+            stream_id: message.stream_id, // Answer on the stream the request came from
+        };
+        self.protocol.send(&response)?; // Queue the encoded response
+        self.finished(kind, size, elapsed);
+        Ok(())
+    }
+
+    // Resumes the session `token` names, or starts a new one if it names none
+    fn resume(&mut self, request: &ResumeRequest) -> io::Result<server_message::Message> {
+        if let Err(detail) = self.pick_host(&request.vhost) {
+            return Ok(server_message::Message::ErrorResponse(ErrorResponse {
+                code: error_response::Code::Invalid as i32,
+                field: "vhost".to_string(),
+                detail,
+                ..Default::default()
+            }));
+        }
+        if !request.device_id.is_empty() {
+            let host = self
+                .vhost
+                .as_ref()
+                .and_then(|name| self.virtual_hosts.get(name));
+            self.device = Some(match host.and_then(|host| host.tenant.as_deref()) {
+                Some(tenant) => tenant::scope(tenant, &request.device_id),
+                None => request.device_id.clone(),
+            });
+            self.join_tenant();
+            self.mailbox.listening.store(true, Ordering::Relaxed);
+            *self.gauges.device.lock().unwrap() = self.device.clone();
+            if let (Some(node), Some(device)) = (&self.shared.cluster, &self.device) {
+                if !node.is_peer(device) {
+                    node.named(device); // Its messages are queued here from now on
+                }
+            }
+        }
+        let mut sessions = self.shared.sessions.lock(&request.token);
+        let mut expired = sessions.expire(); // Their subscriptions are given back below
+        let (token, resumed) = match sessions.resume(&request.token) {
+            Some(state) if state.device == self.device => {
+                self.dedup = state.dedup;
+                (request.token.clone(), true)
+            }
+            _ => (sessions.issue()?, false),
+        };
+        drop(sessions);
+        match self.subscriber() {
+            Some(subscriber) if resumed => self.shared.resume_topics(&token, &subscriber),
+            _ => expired.push(request.token.clone()),
+        }
+        self.shared.forget_topics(&expired);
+        if resumed {
+            debug!("Resumed a session for {:?}", self.peer);
+            self.shared
+                .counters
+                .local()
+                .resumed_sessions
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        self.session = Some(token.clone());
+        Ok(server_message::Message::ResumeResponse(ResumeResponse {
+            token,
+            resumed,
+        }))
+    }
+
+    // Counts a request of `size` bytes against the device's quota, if it named one
+    fn charge(&self, size: usize) -> Result<(), quota::Exceeded> {
+        let Some(device) = &self.device else {
+            return Ok(());
+        };
+        let now = SystemTime::now();
+        let mut quotas = self.shared.quotas.lock(device);
+        quotas.check(device, size as u64, now)?;
+        if let Some((tenant, _)) = &self.tenant {
+            let mut tenants = self.shared.tenant_quotas.lock(tenant);
+            tenants.charge(tenant, size as u64, now)?;
+        }
+        quotas.charge(device, size as u64, now)
+    }
+
+    // Gives the connection the configuration of the virtual host `name`, if it
+    // names one; a connection only ever picks one
+    fn pick_host(&mut self, name: &str) -> Result<(), String> {
+        if name.is_empty() || self.vhost.as_deref() == Some(name) {
+            return Ok(());
+        }
+        if let Some(picked) = &self.vhost {
+            return Err(format!("virtual host {} already picked", picked));
+        }
+        let Some(host) = self.virtual_hosts.get(name) else {
+            return Err(format!("unknown virtual host {}", name));
+        };
+        if let Some(router) = &host.router {
+            self.router = router.clone();
+        }
+        if let Some(authorizer) = &host.authorizer {
+            self.authorizer = Some(authorizer.clone());
+        }
+        debug!("{:?} picked virtual host {}", self.peer, name);
+        self.vhost = Some(name.to_string());
+        Ok(())
+    }
+
+    // Gives the connection the virtual host its TLS handshake asked for, before
+    // its first frame. A name with no host, such as the server's own, leaves
+    // the server's configuration, which a `vhost` field can still replace.
+    fn pick_server_name(&mut self) {
+        let Some(name) = self.stream.server_name() else {
+            return;
+        };
+        if self.virtual_hosts.contains_key(&name) {
+            let _ = self.pick_host(&name); // Nothing is picked yet, so it cannot fail
+        }
+    }
+
+    // Counts the connection under the tenant of its device, instead of the one before
+    fn join_tenant(&mut self) {
+        self.leave_tenant();
+        let Some((Some(name), _)) = self.device.as_deref().map(tenant::split) else {
+            return;
+        };
+        let Some(counters) = self.shared.tenants.lock().unwrap().get(name).cloned() else {
+            return; // Not a tenant, just a device name with a separator in it
+        };
+        counters.connections.fetch_add(1, Ordering::Relaxed);
+        self.tenant = Some((name.to_string(), counters));
+    }
+
+    // The connection as its topic subscriptions know it, once it is registered
+    fn subscriber(&self) -> Option<Subscriber> {
+        let connection = self.connection?;
+        Some(Subscriber {
+            connection,
+            mailbox: self.mailbox.clone(),
+        })
+    }
+
+    // Drops the connection's topic subscriptions as it closes, and what the
+    // node it is the link of, if any, had subscribed to
+    fn leave_topics(&self) {
+        if let Some(subscriber) = self.subscriber() {
+            self.shared.leave_topics(&subscriber);
+        }
+        if let (Some(node), Some(peer)) = (&self.shared.cluster, &self.cluster_peer) {
+            node.closed(peer, self.info.id);
+        }
+    }
+
+    fn leave_tenant(&mut self) {
+        if let Some((_, counters)) = self.tenant.take() {
+            counters.connections.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    // Answers a `ResyncRequest` with the deliveries to send again after it
+    fn resync(&self, request: &ResyncRequest) -> (server_message::Message, Vec<Delivery>) {
+        let Some(device) = self.device.as_deref() else {
+            let detail = "no device named to resync".to_string();
+            return (invalid("device_id", detail), Vec::new());
+        };
+        if request.from >= request.to {
+            let detail = format!("{} is not after {}", request.to, request.from);
+            return (invalid("to", detail), Vec::new());
+        }
+        let outboxes = self.shared.outboxes.lock(device);
+        let replayed = outboxes.replay(device, request.from..request.to);
+        let response = ResyncResponse {
+            replayed: replayed.len() as u32,
+            first: replayed
+                .first()
+                .map_or(request.to, |delivery| delivery.sequence),
+        };
+        (server_message::Message::ResyncResponse(response), replayed)
+    }
+
+    fn quota_status(&self) -> QuotaStatus {
+        let device = self.device.as_deref().unwrap_or_default();
+        let queued = self.shared.outboxes.lock(device).depth(device);
+        let subscriptions = self.shared.topics.read().unwrap().held(device) as u64;
+        let mut quotas = self.shared.quotas.lock(device);
+        QuotaStatus {
+            subscriptions,
+            ..quotas.status(device, queued, SystemTime::now())
+        }
+    }
+
+    // Queues the messages waiting for the client's device, if it named one,
+    // then the frames in its mailbox
+    fn deliver(&mut self) -> io::Result<()> {
+        if let Some(device) = &self.device {
+            let (deliveries, dropped) = self.shared.outboxes.lock(device).take(device);
+            self.shared.counters.dropped(dropped);
+            for delivery in deliveries {
+                let message = ServerMessage {
+                    message: Some(server_message::Message::Delivery(delivery)),
+                    ..Default::default()
+                };
+                self.protocol.send(&message)?;
+            }
+        }
+        // Anonymous connections only get a `GoAway` here, with their next response
+        let Some(frames) = self.mailbox.take() else {
+            return Err(io::Error::other(
+                "Closing connection: too slow to take its broadcasts",
+            ));
+        };
+        for frame in frames {
+            self.protocol.send_frame(frame);
+        }
+        Ok(())
+    }
+
+    // Keeps the connection's session for its device to resume, if it has one
+    fn park(&mut self) {
+        if let Some(token) = self.session.take() {
+            let dedup = std::mem::take(&mut self.dedup);
+            let device = self.device.clone();
+            let state = SessionState { dedup, device };
+            let dropped = self.shared.sessions.lock(&token).park(token.clone(), state);
+            self.shared.forget_topics(&dropped);
+            if let Some(subscriber) = self.subscriber() {
+                self.shared.park_topics(&token, &subscriber);
+            }
+        }
+    }
+
+    // Closes the connection once `handle` has ended with `ended`: tells the client
+    // why, keeps its session and records it in the history
+    fn finish(&mut self, ended: io::Result<()>) {
+        let close = self.close_reason(ended);
+        if is_sent(close.reason()) {
+            self.close(close.clone());
+        }
+        self.park();
+        self.leave_topics();
+        self.leave_tenant();
+        let duration = self.started.elapsed();
+        self.shared.history.record(ClosedConnection {
+            id: self.info.id,
+            peer: self.peer,
+            device: self.device.clone(),
+            connected_at: self.info.connected_at,
+            duration,
+            messages: self.messages,
+            reason: close.reason(),
+            detail: close.detail,
+        });
+        self.observe(|observer, info| observer.on_disconnect(info, duration));
+        limited!(
+            LogClass::Connection,
+            Level::Info,
+            connection_id = self.info.id,
+            peer:% = self.peer_name(),
+            duration_ms = duration.as_millis() as u64;
+            "Client connection closed."
+        );
+    }
+
+    // Why the connection is closing, once `handle` has ended with `ended`
+    fn close_reason(&self, ended: io::Result<()>) -> Close {
+        if let Some(close) = self.gauges.closed_by.lock().unwrap().take() {
+            return close;
+        }
+        let (reason, detail) = match ended {
+            _ if self.shared.is_closing() => {
+                (Reason::ServerShutdown, "the server is stopping".to_string())
+            }
+            Ok(()) => (Reason::ClientClosed, String::new()),
+            Err(e) => (reason_of(&e), e.to_string()),
+        };
+        Close {
+            reason: reason as i32,
+            detail,
+        }
+    }
+
+    // Sends `close` after whatever is still queued, as far as it goes out within
+    // `CLOSE_GRACE`, and ends the server's side with a FIN rather than a reset
+    fn close(&mut self, close: Close) {
+        let message = ServerMessage {
+            message: Some(server_message::Message::Close(close)),
+            ..Default::default()
+        };
+        let _ = self.stream.set_write_timeout(Some(CLOSE_GRACE));
+        let sent = self.protocol.send(&message).is_ok()
+            && self.transmit().is_ok_and(|open| open)
+            && self.stream.flush().is_ok();
+        if !sent {
+            debug!("Could not tell {} why it is closed", self.peer_name());
+        }
+        let _ = self.stream.shutdown(Shutdown::Write); // The client may already be gone
+    }
+
+    // Calls `event` on each observer in turn
+    fn observe(&self, event: impl Fn(&dyn Observer, &ConnectionInfo)) {
+        for observer in self.observers.iter() {
+            event(observer.as_ref(), &self.info);
+        }
+    }
+
+    // Address of the client for logs
+    fn peer_name(&self) -> String {
+        peer_name(self.peer)
+    }
+
+    // Sends the response the server gave a request itself, on the stream and
+    // with the ID the request came with, and counts the request as handled
+    fn answer(
+        &mut self,
+        (message_id, stream_id): (u64, u32),
+        response: server_message::Message,
+        kind: MessageKind,
+        size: usize,
+        started: Instant,
+    ) -> io::Result<()> {
+        let response = ServerMessage {
+            message: Some(response),
+            message_id,
+            stream_id,
+        };
+        self.protocol.send(&response)?;
+        self.finished(kind, size, started.elapsed());
+        Ok(())
+    }
+
+    // Counts a handled request and logs it if it was slow
+    fn finished(&mut self, kind: MessageKind, size: usize, elapsed: Duration) {
+        self.messages += 1;
+        let counters = &self.shared.counters;
+        counters.local().requests.fetch_add(1, Ordering::Relaxed);
+        counters.record_latency(kind, elapsed);
+        if let Some((_, tenant)) = &self.tenant {
+            tenant.requests.fetch_add(1, Ordering::Relaxed);
+            tenant
+                .request_bytes
+                .fetch_add(size as u64, Ordering::Relaxed);
+        }
+        self.observe(|observer, info| observer.on_message(info, kind, elapsed));
+        if self
+            .shared
+            .slow_request_threshold()
+            .is_some_and(|threshold| elapsed >= threshold)
+        {
+            counters
+                .local()
+                .slow_requests
+                .fetch_add(1, Ordering::Relaxed);
+            limited!(
+                LogClass::SlowRequest,
+                Level::Warn,
+                connection_id = self.info.id,
+                peer:% = self.peer_name(),
+                message_type = kind.name(),
+                duration_ms = elapsed.as_millis() as u64;
+                "Slow request: {:?} of {} bytes from {} took {:?}",
+                kind,
+                size,
+                self.peer_name(),
+                elapsed
+            );
+        }
+    }
+
+    // Writes one frame once the download limit allows it
+    fn write_frame(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.throttle(Direction::Outbound, bytes.len());
+        self.stream.write_all(bytes)?;
+        self.record(Direction::Outbound, bytes);
+        Ok(())
+    }
+
+    // Waits until `bytes` fit under the limit for `direction`, if one is set
+    fn throttle(&mut self, direction: Direction, bytes: usize) {
+        let (limit, bucket) = match direction {
+            Direction::Inbound => (&self.shared.upload_limit, &mut self.upload),
+            Direction::Outbound => (&self.shared.download_limit, &mut self.download),
+        };
+        let Some(rate) = Shared::limit(limit) else {
+            return;
+        };
+        let mut wait = bucket.take(rate, bytes, Instant::now());
+        // Sleep in steps so a throttled connection does not hold up `stop()`
+        while !wait.is_zero() && !self.shared.is_closing() {
+            let step = wait.min(THROTTLE_STEP);
+            thread::sleep(step);
+            wait -= step;
+        }
+    }
+
+    // Appends to the wire log and capture file, if enabled; a failing capture is dropped rather than the client
+    fn record(&mut self, direction: Direction, bytes: &[u8]) {
+        self.shared.wire_log.log(self.peer, direction, bytes);
+        if let Some(capture) = self.capture.as_mut() {
+            if let Err(e) = capture.record(direction, bytes) {
+                warn!("Disabling capture after write error: {}", e);
+                self.capture = None;
+            }
+        }
+    }
 }
 
-impl Client {
-    // Constructor to create a new client instance
-    pub fn new(stream: TcpStream) -> Self {
-        Client { stream }
+// Threads handlers run on while a handler timeout is set, started as needed up
+// to a cap. A handler that overruns keeps its thread until it returns; once
+// every thread is taken, further requests are refused instead of waiting.
+struct HandlerPool {
+    jobs: Sender<Box<dyn FnOnce() + Send>>,
+    queue: Receiver<Box<dyn FnOnce() + Send>>, // Taken from by every thread of the pool
+    threads: Arc<Mutex<HandlerThreads>>,
+    limit: usize,              // Threads started at most
+    abandoned: Arc<AtomicU64>, // Handlers still running after their request timed out
+}
+
+#[derive(Default)]
+struct HandlerThreads {
+    started: usize,
+    idle: usize, // Waiting on the queue
+}
+
+// What became of a handler given to the pool
+enum Handled<T> {
+    Done(T),
+    Overran, // Still running, its result to be dropped
+    Refused, // Every thread was taken
+}
+
+const WAITING: u8 = 0; // The caller still waits for the handler
+const ABANDONED: u8 = 1; // The caller gave up on it
+const FINISHED: u8 = 2; // It has sent its result
+
+impl HandlerPool {
+    fn new(limit: usize) -> Self {
+        let (jobs, queue) = crossbeam_channel::unbounded();
+        HandlerPool {
+            jobs,
+            queue,
+            threads: Arc::default(),
+            limit,
+            abandoned: Arc::default(),
+        }
     }
 
-    // Handles communication with the client
-    pub fn handle(&mut self) -> io::Result<()> {
-        let mut buffer = [0; 512]; // Buffer to store incoming data
-        let bytes_read = self.stream.read(&mut buffer)?; // Read data from the client
+    // Runs `handler` on a thread of the pool and waits at most `timeout` for its
+    // result. One that panics panics the caller too, as if it had run there.
+    fn run<T: Send + 'static>(
+        &self,
+        timeout: Duration,
+        handler: impl FnOnce() -> T + Send + 'static,
+    ) -> io::Result<Handled<T>> {
+        if !self.take_thread()? {
+            return Ok(Handled::Refused);
+        }
+        let (done, result) = crossbeam_channel::bounded(1);
+        let state = Arc::new(AtomicU8::new(WAITING));
+        let context = panics::current();
+        let job = {
+            let (state, abandoned) = (state.clone(), self.abandoned.clone());
+            move || {
+                let _context = panics::resume(context);
+                let _ = done.send(panic::catch_unwind(AssertUnwindSafe(handler)));
+                if state.swap(FINISHED, Ordering::AcqRel) == ABANDONED {
+                    abandoned.fetch_sub(1, Ordering::Relaxed);
+                }
+            }
+        };
+        let _ = self.jobs.send(Box::new(job)); // The pool holds the queue too
+        let outcome = match result.recv_timeout(timeout) {
+            Ok(outcome) => outcome,
+            Err(RecvTimeoutError::Timeout) => {
+                // Counted before the handler can finish and uncount it
+                self.abandoned.fetch_add(1, Ordering::Relaxed);
+                let swapped =
+                    state.compare_exchange(WAITING, ABANDONED, Ordering::AcqRel, Ordering::Acquire);
+                if swapped.is_ok() {
+                    return Ok(Handled::Overran);
+                }
+                self.abandoned.fetch_sub(1, Ordering::Relaxed);
+                result.recv().expect("Finished handlers send their result")
+            }
+            Err(RecvTimeoutError::Disconnected) => unreachable!("Jobs send their result"),
+        };
+        match outcome {
+            Ok(result) => Ok(Handled::Done(result)),
+            Err(panic) => panic::resume_unwind(panic),
+        }
+    }
 
-        // If no data is read, the client has disconnected
-        if bytes_read == 0 {
-            info!("Client disconnected.");
-            return Ok(());
+    // Claims an idle thread, or starts one if the pool is not full yet; `false` if it is
+    fn take_thread(&self) -> io::Result<bool> {
+        let mut threads = self.threads.lock().unwrap();
+        if threads.idle > 0 {
+            threads.idle -= 1;
+            return Ok(true);
+        }
+        if threads.started >= self.limit {
+            return Ok(false);
         }
+        let (queue, pool) = (self.queue.clone(), self.threads.clone());
+        thread::Builder::new()
+            .name("handler".to_string())
+            .spawn(move || {
+                // Ends once the server is dropped along with the other end
+                while let Ok(job) = queue.recv() {
+                    job(); // Panics are caught inside
+                    pool.lock().unwrap().idle += 1;
+                }
+            })?;
+        threads.started += 1;
+        Ok(true)
+    }
 
-        // Attempt to decode the incoming data as a protobuf message
-        if let Ok(message) = EchoMessage::decode(&buffer[..bytes_read]) {
-            info!("Received: {}", message.content); // Log the received message
-                                                    // Echo the message back to the client
-            let payload = message.encode_to_vec();
-            self.stream.write_all(&payload)?; // Send the encoded message
-            self.stream.flush()?; // Ensure all data is sent immediately
-        } else {
-            error!("Failed to decode message"); // Log an error if decoding fails
+    fn abandoned(&self) -> u64 {
+        self.abandoned.load(Ordering::Relaxed)
+    }
+}
+
+// Frame of a message published on `topic`
+fn publication(topic: &str, payload: Bytes, retained: bool) -> Result<Bytes, CodecError> {
+    let message = ServerMessage {
+        message: Some(server_message::Message::Publication(Publication {
+            topic: topic.to_string(),
+            payload,
+            retained,
+        })),
+        ..Default::default()
+    };
+    Ok(Bytes::from(codec::encode(&message)?))
+}
+
+// Where the topic or filter `name` of a device of `tenant` is kept: under a first
+// level of the tenant's own, which starts with `$` so no other filter's leading
+// wildcard matches it
+fn tenant_topic(tenant: Option<&str>, name: &str) -> String {
+    match tenant {
+        Some(tenant) => format!("${}", tenant::scope(tenant, name)),
+        None => name.to_string(),
+    }
+}
+
+// Key a connection of `tenant` subscribes with under `subscription`, which may be shared
+fn tenant_subscription(tenant: Option<&str>, subscription: &str) -> String {
+    match share::parse(subscription) {
+        Some((group, filter)) => {
+            let filter = tenant_topic(tenant, filter);
+            let separator = topic::SEPARATOR;
+            format!(
+                "{}{}{}{}{}",
+                SHARE_PREFIX, separator, group, separator, filter
+            )
+        }
+        None => tenant_topic(tenant, subscription),
+    }
+}
+
+// Why a topic or filter naming the server's own topics is refused
+fn reserved() -> String {
+    format!(
+        "topics starting with {} are reserved for the server",
+        RESERVED
+    )
+}
+
+// Limits of the mailbox of a parked session, which nothing empties until it
+// is resumed: full, it drops the oldest frame rather than hold up publishers
+fn parked_limits(limits: MailboxLimits) -> MailboxLimits {
+    MailboxLimits {
+        overflow: Overflow::DropOldest,
+        ..limits
+    }
+}
+
+// Refuses a request whose `field` is not valid
+fn invalid(field: &str, detail: String) -> server_message::Message {
+    server_message::Message::ErrorResponse(ErrorResponse {
+        code: error_response::Code::Invalid as i32,
+        field: field.to_string(),
+        detail,
+        ..Default::default()
+    })
+}
+
+/// Hands a [`Listener`]'s connections to the server running it
+pub struct Incoming<'a> {
+    accepted: Sender<Box<dyn Link>>,
+    server: &'a Server,
+}
+
+impl Incoming<'_> {
+    /// Queues `link` for the server's workers, waiting while the accept queue is
+    /// full. Fails once the server has stopped accepting.
+    pub fn accept(&self, link: Box<dyn Link>) -> io::Result<()> {
+        let stopped = || io::Error::new(ErrorKind::NotConnected, "Server stopped");
+        if !self.is_running() {
+            return Err(stopped());
         }
+        limited!(
+            LogClass::Connection,
+            Level::Info,
+            peer:% = peer_name(link.peer_addr());
+            "New client connected: {}", peer_name(link.peer_addr())
+        );
+        self.accepted.send(link).map_err(|_| stopped())
+    }
 
-        Ok(())
+    /// Waits while the server has as many connections open as its overload
+    /// policy allows, so a listener can hold clients off rather than accept
+    /// connections only to have them refused. Returns whether the server is
+    /// still running.
+    pub fn wait_for_room(&self) -> bool {
+        let shared = &self.server.shared;
+        while shared
+            .overload
+            .at_connection_limit(shared.open_connections())
+        {
+            if !self.server.stop_signal.wait_timeout(THROTTLE_STEP) {
+                return false;
+            }
+        }
+        self.is_running()
     }
+
+    /// Whether the server is still accepting connections
+    pub fn is_running(&self) -> bool {
+        self.server.stop_signal.is_running()
+    }
+}
+
+// Answers a connection refused under overload with a busy response, then closes it
+fn shed(mut stream: Box<dyn Link>, shared: &Shared) {
+    shared
+        .counters
+        .local()
+        .shed_connections
+        .fetch_add(1, Ordering::Relaxed);
+    let busy = ServerMessage {
+        message: Some(shared.overload.busy()),
+        ..Default::default()
+    };
+    // Best effort: the frame fits in the socket buffer of a fresh connection
+    if let Ok(bytes) = codec::encode(&busy) {
+        let _ = stream.write_all(&bytes);
+    }
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+// Address of a client for logs
+fn peer_name(peer: Option<SocketAddr>) -> String {
+    peer.map_or_else(|| "unknown peer".to_string(), |peer| peer.to_string())
 }
 
 // The main server struct
 pub struct Server {
-    listener: TcpListener,       // Listens for incoming client connections
-    is_running: Arc<AtomicBool>, // Shared state to manage server's running status
+    listener: TcpListener,             // Listens for incoming client connections
+    socket: Option<ServerConfig>,      // Applied to each accepted connection too, if bound with one
+    stop_signal: StopSignal,           // Cleared by `drain()` or `stop()`, waking the accept loop
+    capture_dir: Option<Arc<Path>>,    // Where per-connection capture files are written
+    watchdog: Option<Watchdog>,        // Checks for a stuck accept loop or handler while running
+    shards: Option<usize>,             // Worker pools, if not one per core
+    upstream: Option<Arc<Upstream>>,   // Where requests are forwarded in relay mode
+    router: Arc<Router>,               // Application handlers, unless relaying
+    layers: Vec<Arc<dyn Middleware>>,  // Wrapped around the router or relay, outermost first
+    observers: Vec<Arc<dyn Observer>>, // Told about every connection's events
+    authorizer: Option<Arc<dyn Authorizer>>, // Asked about every request
+    log_tail: Option<&'static LogTail>, // Streams the log to operators who ask
+    virtual_hosts: HashMap<String, VirtualHost>, // Configurations picked by name
+    listeners: Vec<Box<dyn Listener>>, // Other transports, served beside TCP
+    shared: Arc<Shared>,               // Settings and counters used by every connection
+    #[cfg(feature = "fault-injection")]
+    faults: Option<FaultInjector>, // Faults injected into every connection's responses
 }
 
 impl Server {
     /// Creates a new server instance
     pub fn new(addr: &str) -> io::Result<Self> {
         let listener = TcpListener::bind(addr)?; // Bind the server to the specified address
-        let is_running = Arc::new(AtomicBool::new(false)); // Initialize the running flag
-        Ok(Server {
+        Ok(Server::from_listener(listener))
+    }
+
+    /// Creates a server whose listening socket and connections use `config`'s
+    /// backlog, buffer sizes and marking
+    pub fn with_config(addr: &str, config: ServerConfig) -> io::Result<Self> {
+        let mut server = Server::from_listener(config.bind(addr)?);
+        server.socket = Some(config);
+        Ok(server)
+    }
+
+    /// Creates a server on a listening socket that is already bound, such as one
+    /// handed over by the process being replaced or passed in by systemd
+    pub fn from_listener(listener: TcpListener) -> Self {
+        let dead_letters: Arc<dyn DeadLetterSink> = Arc::new(DeadLetters::default());
+        Server {
             listener,
-            is_running,
-        })
+            socket: None,
+            // Starts running, so a `stop()` issued before `run()` is not lost
+            stop_signal: StopSignal::new(),
+            capture_dir: None,
+            watchdog: None,
+            shards: None,
+            upstream: None,
+            router: Arc::default(),
+            layers: Vec::new(),
+            observers: Vec::new(),
+            authorizer: None,
+            log_tail: None,
+            virtual_hosts: HashMap::new(),
+            listeners: Vec::new(),
+            shared: Arc::new(Shared {
+                wire_log: WireLog::new(),
+                counters: Counters::default(),
+                slow_request_micros: AtomicU64::new(u64::MAX),
+                first_frame_micros: AtomicU64::new(DEFAULT_FIRST_FRAME_DEADLINE.as_micros() as u64),
+                frame_micros: AtomicU64::new(DEFAULT_FRAME_DEADLINE.as_micros() as u64),
+                handler_micros: AtomicU64::new(u64::MAX),
+                denial_limit: AtomicU64::new(u64::MAX),
+                overload_queued: AtomicU64::new(DEFAULT_WORKERS as u64),
+                workers: DEFAULT_WORKERS,
+                handlers: HandlerPool::new(DEFAULT_HANDLER_THREADS),
+                overload: OverloadLimits::new(OverloadPolicy::default()),
+                epoch: Instant::now(),
+                accept_beat: AtomicU64::new(0),
+                availability: Tracker::default(),
+                history: ConnectionHistory::default(),
+                upload_limit: AtomicU64::new(u64::MAX),
+                download_limit: AtomicU64::new(u64::MAX),
+                connection_memory: AtomicU64::new(u64::MAX),
+                connections: Sharded::default(),
+                mailbox_limits: Mutex::default(),
+                sessions: Sharded::new(DEFAULT_SHARDS, || {
+                    let capacity = MAX_PARKED_SESSIONS.div_ceil(DEFAULT_SHARDS);
+                    SessionStore::with_capacity(DEFAULT_SESSION_EXPIRY, capacity)
+                }),
+                outboxes: Sharded::new(DEFAULT_SHARDS, || {
+                    let mut outboxes = Outboxes::default();
+                    outboxes.set_sink(dead_letters.clone()); // One for all shards
+                    outboxes
+                }),
+                sweep_micros: AtomicU64::new(SWEEP_INTERVAL.as_micros() as u64),
+                known_devices: Mutex::default(),
+                quotas: Sharded::default(),
+                tenants: Mutex::default(),
+                tenant_quotas: Sharded::default(),
+                topics: RwLock::default(),
+                retained: RwLock::new(Retained::new(RETAINED_TOPICS)),
+                cluster: None,
+            }),
+            #[cfg(feature = "fault-injection")]
+            faults: None,
+        }
+    }
+
+    /// Creates a server that injects faults into its responses, for testing clients
+    #[cfg(feature = "fault-injection")]
+    pub fn with_fault_injector(addr: &str, faults: FaultInjector) -> io::Result<Self> {
+        let mut server = Server::new(addr)?;
+        server.faults = Some(faults);
+        Ok(server)
+    }
+
+    /// The server's fault injector, whose per-message-type rules can be changed while it runs
+    #[cfg(feature = "fault-injection")]
+    pub fn fault_injector(&self) -> Option<&FaultInjector> {
+        self.faults.as_ref()
+    }
+
+    /// Records every connection's traffic to a file in `dir`, for replay with the `replay` tool
+    pub fn capture_to(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.capture_dir = Some(Arc::from(dir.into()));
+        self
+    }
+
+    /// Runs a watchdog alongside the server that acts once it gets stuck; see [`crate::watchdog`]
+    pub fn watchdog(mut self, watchdog: Watchdog) -> Self {
+        self.watchdog = Some(watchdog);
+        self
+    }
+
+    /// Splits the connection workers into `count` shards, at most one per
+    /// worker, instead of one per core
+    pub fn shards(mut self, count: usize) -> Self {
+        self.shards = Some(count);
+        self
+    }
+
+    /// Serves connections with `count` threads, at least one, split over the
+    /// shards; 16 by default. Connections take turns on them, so any number
+    /// can be open at once.
+    pub fn workers(mut self, count: usize) -> Self {
+        let shared = Arc::get_mut(&mut self.shared).expect("Shared only once the server runs");
+        shared.workers = count.max(1);
+        self
+    }
+
+    /// Runs handlers on at most `count` threads, at least one, while a handler
+    /// timeout is set; 64 by default. Once overrunning handlers hold them all,
+    /// further requests are answered busy and counted in [`Stats::shed_requests`].
+    pub fn handler_threads(mut self, count: usize) -> Self {
+        let shared = Arc::get_mut(&mut self.shared).expect("Shared only once the server runs");
+        shared.handlers = HandlerPool::new(count.max(1));
+        self
+    }
+
+    /// Relays every request to the server at `upstream` instead of handling it here,
+    /// over at most `links` connections shared by all clients
+    pub fn relay_to(mut self, upstream: &str, links: usize) -> Self {
+        self.upstream = Some(Arc::new(Upstream::new(upstream, links)));
+        self
+    }
+
+    /// Makes the server a node of `cluster`, sharing topics and devices with
+    /// the other nodes; see [`crate::cluster`]
+    pub fn cluster(mut self, cluster: Cluster) -> Self {
+        let shared = Arc::get_mut(&mut self.shared).expect("Shared only once the server runs");
+        shared.cluster = Some(Node::new(cluster));
+        self
+    }
+
+    /// Answers requests with the handlers registered on `router`; see [`crate::router`].
+    /// Requests are relayed instead if [`Server::relay_to`] is also used.
+    pub fn router(mut self, router: Router) -> Self {
+        self.router = Arc::new(router);
+        self
+    }
+
+    /// Wraps request handling in `middleware`, inside the layers added before it;
+    /// see [`crate::middleware`]
+    pub fn layer(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.layers.push(Arc::new(middleware));
+        self
+    }
+
+    /// Asks `authorizer` whether each request may be handled; see [`crate::authz`]
+    pub fn authorizer(mut self, authorizer: impl Authorizer + 'static) -> Self {
+        self.authorizer = Some(Arc::new(authorizer));
+        self
+    }
+
+    /// Answers `TailLogs` requests by streaming `tail`'s records; see
+    /// [`crate::logtail`]. Without it they are refused as unsupported.
+    pub fn log_tail(mut self, tail: &'static LogTail) -> Self {
+        self.log_tail = Some(tail);
+        self
+    }
+
+    /// Gives the connections that pick the virtual host `name` its
+    /// configuration; see [`crate::vhost`]
+    pub fn virtual_host(mut self, name: &str, host: VirtualHost) -> Self {
+        if let Some(tenant) = &host.tenant {
+            let mut tenants = self.shared.tenants.lock().unwrap();
+            tenants.entry(tenant.clone()).or_default();
+        }
+        self.virtual_hosts.insert(name.to_string(), host);
+        self
+    }
+
+    /// Tells `observer` when connections open, answer requests, fail and close,
+    /// after the observers added before it; see [`crate::observer`]
+    pub fn observer(mut self, observer: impl Observer + 'static) -> Self {
+        self.observers.push(Arc::new(observer));
+        self
+    }
+
+    /// Also accepts the connections of `listener`, such as a named pipe, and
+    /// serves them like TCP ones; see [`crate::link`]
+    pub fn listener(mut self, listener: impl Listener + 'static) -> Self {
+        self.listeners.push(Box::new(listener));
+        self
+    }
+
+    /// Hex-dump logging of all connections, which can be enabled while the server runs
+    pub fn wire_log(&self) -> &WireLog {
+        &self.shared.wire_log
+    }
+
+    /// Rate limits and sampling of noisy log lines, by event class; shared by
+    /// every server in the process, as the logger is
+    pub fn log_limits(&self) -> &'static LogLimits {
+        loglimit::global()
+    }
+
+    /// Logs requests that take at least `threshold` to handle and counts them in
+    /// [`Stats::slow_requests`]; `None` turns this off. Takes effect immediately.
+    pub fn set_slow_request_threshold(&self, threshold: Option<Duration>) {
+        Self::store_duration(&self.shared.slow_request_micros, threshold);
+    }
+
+    /// Closes connections that have not sent a complete frame within `deadline`
+    /// of being accepted, counting them in [`Stats::slow_connections`]; `None`
+    /// turns this off. Defaults to [`DEFAULT_FIRST_FRAME_DEADLINE`]. Takes effect
+    /// immediately.
+    pub fn set_first_frame_deadline(&self, deadline: Option<Duration>) {
+        Self::store_duration(&self.shared.first_frame_micros, deadline);
+    }
+
+    /// Closes connections that take longer than `deadline` to complete a frame
+    /// once its first bytes have arrived, counting them in
+    /// [`Stats::slow_connections`]; `None` turns this off. Defaults to
+    /// [`DEFAULT_FRAME_DEADLINE`]. Takes effect immediately.
+    pub fn set_frame_deadline(&self, deadline: Option<Duration>) {
+        Self::store_duration(&self.shared.frame_micros, deadline);
+    }
+
+    /// Closes connections once `limit` of their requests have been denied by
+    /// the authorizer, telling them it was for an authorization failure; `None`
+    /// turns this off, the default. See [`crate::close`].
+    pub fn set_denial_limit(&self, limit: Option<u32>) {
+        let limit = limit.map_or(u64::MAX, u64::from);
+        self.shared.denial_limit.store(limit, Ordering::Relaxed);
+    }
+
+    /// Answers requests whose handler takes longer than `timeout` with a timeout
+    /// error, counted in [`Stats::handler_timeouts`], and moves on to the next
+    /// request; `None` turns this off. While set, handlers run on a pool of
+    /// threads (see [`Server::handler_threads`]), and one that overruns keeps its
+    /// thread until it returns, counted in [`Stats::abandoned_handlers`].
+    pub fn set_handler_timeout(&self, timeout: Option<Duration>) {
+        Self::store_duration(&self.shared.handler_micros, timeout);
+    }
+
+    /// Keeps the session of a closed connection for `expiry`, during which a
+    /// device reconnecting can resume it; see [`crate::resume`]. Defaults to
+    /// [`DEFAULT_SESSION_EXPIRY`](crate::resume::DEFAULT_SESSION_EXPIRY).
+    pub fn set_session_expiry(&self, expiry: Duration) {
+        (self.shared.sessions).for_each(|sessions| sessions.set_expiry(expiry));
+    }
+
+    /// Sends `payload` to every connected device as a `Delivery` numbered
+    /// [`BROADCAST_SEQUENCE`], returning how many it was queued for. The message
+    /// is encoded once and the frame shared by every recipient's mailbox; a
+    /// full mailbox is handled as [`Server::set_mailbox_limits`] says. Devices
+    /// that are offline do not get it later.
+    pub fn broadcast(&self, payload: impl Into<Bytes>) -> Result<usize, CodecError> {
+        let message = ServerMessage {
+            message: Some(server_message::Message::Delivery(Delivery {
+                sequence: BROADCAST_SEQUENCE,
+                payload: payload.into(),
+            })),
+            ..Default::default()
+        };
+        let frame = Bytes::from(codec::encode(&message)?);
+        Ok(self.shared.post(frame, false))
     }
 
-    /// Runs the server, listening for incoming connections
+    /// Retains the last message published on each topic `filter` matches for
+    /// new subscribers, whether or not its publisher set the retain flag; see
+    /// [`crate::retained`]
+    pub fn always_retain(&self, filter: &str) -> Result<(), TopicError> {
+        self.shared.retained.write().unwrap().always_retain(filter)
+    }
+
+    /// Caps the frames waiting in each connection's mailbox and picks what a
+    /// full one does; see [`crate::mailbox`]. Takes effect with the next broadcast.
+    pub fn set_mailbox_limits(&self, limits: MailboxLimits) {
+        *self.shared.mailbox_limits.lock().unwrap() = limits;
+    }
+
+    /// Queues `payload` for the device that names itself `device`, returning its
+    /// sequence number; see [`crate::outbox`]. Delivered as soon as the device
+    /// is connected, or when it next connects. Returns 0 for a device outside
+    /// those set with [`Server::set_known_devices`]. Pass the same [`Bytes`] to
+    /// queue one payload for many devices without copying it.
+    pub fn send_to(&self, device: &str, payload: impl Into<Bytes>) -> u64 {
+        self.queue(device, payload, None)
+    }
+
+    /// Like [`Server::send_to`], but the message is dropped, and counted in
+    /// [`Stats::expired_messages`], if it is not delivered within `ttl`
+    pub fn send_to_with_ttl(&self, device: &str, payload: impl Into<Bytes>, ttl: Duration) -> u64 {
+        self.queue(device, payload, Some(ttl))
+    }
+
+    fn queue(&self, device: &str, payload: impl Into<Bytes>, ttl: Option<Duration>) -> u64 {
+        let payload = payload.into();
+        let known = self.shared.known_devices.lock().unwrap();
+        if known.as_ref().is_some_and(|known| !known.contains(device)) {
+            drop(known);
+            if let Some(sink) = self.shared.dead_letter_sink() {
+                sink.record(DeadLetter {
+                    device: device.to_string(),
+                    sequence: 0, // Never queued, so never numbered
+                    payload: payload.to_vec(),
+                    reason: DeadReason::UnknownDevice,
+                    dropped_at: SystemTime::now(),
+                });
+            }
+            return 0;
+        }
+        drop(known);
+        if let Some(node) = &self.shared.cluster {
+            if let Some(sequence) = node.send(device, payload.clone(), ttl) {
+                return sequence; // Queued on the node the device last named itself on
+            }
+        }
+        let mut outboxes = self.shared.outboxes.lock(device);
+        let (sequence, dropped) = outboxes.push(device, payload, ttl);
+        self.shared.counters.dropped(dropped);
+        sequence
+    }
+
+    /// Messages waiting for `device`
+    pub fn queue_depth(&self, device: &str) -> usize {
+        self.shared.outboxes.lock(device).depth(device)
+    }
+
+    /// Messages waiting for each device with any
+    pub fn queue_depths(&self) -> HashMap<String, usize> {
+        let shards = self.shared.outboxes.shards();
+        shards.flat_map(|outboxes| outboxes.depths()).collect()
+    }
+
+    /// Sweeps the device queues every `interval`, from the next sweep on;
+    /// see [`crate::outbox`]
+    pub fn set_sweep_interval(&self, interval: Duration) {
+        let micros = (interval.as_micros() as u64).max(1);
+        self.shared.sweep_micros.store(micros, Ordering::Relaxed);
+    }
+
+    /// Sweeps the device queues now, without waiting for the next sweep:
+    /// drops expired messages and sessions, and forgets the queues of devices
+    /// that are gone. Returns the number of queues forgotten.
+    pub fn sweep_queues(&self) -> usize {
+        let mut expired = Vec::new();
+        (self.shared.sessions).for_each(|sessions| expired.extend(sessions.expire()));
+        self.shared.forget_topics(&expired);
+        let mut present = HashSet::new();
+        for sessions in self.shared.sessions.shards() {
+            present.extend(sessions.devices().map(str::to_string));
+        }
+        self.shared.connections.for_each(|connections| {
+            let devices = connections
+                .values()
+                .map(|open| open.gauges.device.lock().unwrap());
+            present.extend(devices.filter_map(|device| device.clone()));
+        });
+        let mut forgotten = 0;
+        self.shared.outboxes.for_each(|outboxes| {
+            let (dropped, swept) = outboxes.sweep(|device| present.contains(device));
+            self.shared.counters.dropped(dropped);
+            forgotten += swept;
+        });
+        forgotten
+    }
+
+    /// Hands the messages dropped from device queues to `sink` from now on;
+    /// see [`crate::deadletter`]
+    pub fn set_dead_letter_sink(&self, sink: impl DeadLetterSink + 'static) {
+        let sink: Arc<dyn DeadLetterSink> = Arc::new(sink);
+        self.shared
+            .outboxes
+            .for_each(|outboxes| outboxes.set_sink(sink.clone()));
+    }
+
+    /// The messages lately dropped from device queues, oldest first, as kept
+    /// by the dead-letter sink
+    pub fn dead_letters(&self) -> Vec<DeadLetter> {
+        let sink = self.shared.dead_letter_sink();
+        sink.map_or_else(Vec::new, |sink| sink.recent())
+    }
+
+    /// Limits [`Server::send_to`] to `devices`: a message for any other device
+    /// is not queued but handed to the dead-letter sink. `None`, the default,
+    /// lets any device be addressed, whether or not it has connected yet.
+    pub fn set_known_devices(&self, devices: Option<HashSet<String>>) {
+        *self.shared.known_devices.lock().unwrap() = devices;
+    }
+
+    /// Applies `quota` to the device that names itself `device`, or the default
+    /// quota again if `None`; see [`crate::quota`]
+    pub fn set_quota(&self, device: &str, quota: Option<Quota>) {
+        let max_queued = quota.map(|quota| quota.max_queued.unwrap_or(usize::MAX));
+        self.shared.quotas.lock(device).set(device, quota);
+        let mut outboxes = self.shared.outboxes.lock(device);
+        outboxes.set_device_limit(device, max_queued);
+    }
+
+    /// Applies `quota` to the devices without one of their own
+    pub fn set_default_quota(&self, quota: Quota) {
+        self.shared
+            .quotas
+            .for_each(|quotas| quotas.set_default(quota));
+        self.shared
+            .outboxes
+            .for_each(|outboxes| outboxes.set_default_device_limit(quota.max_queued));
+    }
+
+    /// Usage and limits of the quota of `device`
+    pub fn quota_status(&self, device: &str) -> QuotaStatus {
+        let queued = self.queue_depth(device);
+        let subscriptions = self.shared.topics.read().unwrap().held(device) as u64;
+        let mut quotas = self.shared.quotas.lock(device);
+        QuotaStatus {
+            subscriptions,
+            ..quotas.status(device, queued, SystemTime::now())
+        }
+    }
+
+    /// Registers the tenant `name`, whose devices together may use `quota`, or
+    /// sets its quota if it is registered already; see [`crate::tenant`]. Only
+    /// the daily limits of the quota apply to a tenant as a whole. Connections
+    /// that named one of its devices before it was registered are not counted
+    /// under it.
+    pub fn add_tenant(&self, name: &str, quota: Quota) {
+        let mut tenants = self.shared.tenants.lock().unwrap();
+        tenants.entry(name.to_string()).or_default();
+        let mut quotas = self.shared.tenant_quotas.lock(name);
+        quotas.set(name, Some(quota));
+    }
+
+    /// The server as the tenant `name` sees it
+    pub fn tenant(&self, name: &str) -> Tenant<'_> {
+        Tenant::new(self, name)
+    }
+
+    pub(crate) fn tenant_quota_status(&self, name: &str) -> QuotaStatus {
+        let tenant = Tenant::new(self, name);
+        let queued = tenant.queue_depths().values().sum();
+        let mut quotas = self.shared.tenant_quotas.lock(name);
+        quotas.status(name, queued, SystemTime::now())
+    }
+
+    pub(crate) fn tenant_stats(&self, name: &str) -> TenantStats {
+        let tenants = self.shared.tenants.lock().unwrap();
+        tenants
+            .get(name)
+            .map(|counters| counters.snapshot())
+            .unwrap_or_default()
+    }
+
+    /// Holds at most `max_queued` messages for each device, dropping the oldest
+    /// to make room, and drops those not delivered within `ttl`. Defaults to
+    /// [`DEFAULT_MAX_QUEUED`](crate::outbox::DEFAULT_MAX_QUEUED) and
+    /// [`DEFAULT_QUEUE_TTL`](crate::outbox::DEFAULT_QUEUE_TTL).
+    pub fn set_queue_limits(&self, max_queued: usize, ttl: Duration) {
+        self.shared
+            .outboxes
+            .for_each(|outboxes| outboxes.set_limits(max_queued, ttl));
+    }
+
+    fn store_duration(micros: &AtomicU64, duration: Option<Duration>) {
+        let value = duration.map_or(u64::MAX, |duration| {
+            (duration.as_micros() as u64).min(u64::MAX - 1)
+        });
+        micros.store(value, Ordering::Relaxed);
+    }
+
+    /// Counts the server as not ready while at least `queued` accepted connections
+    /// wait for a worker; `None` turns this check off. Defaults to the pool size.
+    pub fn set_overload_threshold(&self, queued: Option<u64>) {
+        self.shared
+            .overload_queued
+            .store(queued.unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+
+    /// Answers busy instead of queueing once the policy's limits are reached; see
+    /// [`crate::overload`]. Takes effect immediately.
+    pub fn set_overload_policy(&self, policy: OverloadPolicy) {
+        self.shared.overload.set(policy);
+    }
+
+    /// Whether the server is live and ready, for health probes; see [`crate::health`]
+    pub fn health(&self) -> Health {
+        let mut problems = Vec::new();
+        let running = self.stop_signal.is_running();
+        match self.shared.accept_idle() {
+            None => problems.push("Accept loop has not started".to_string()),
+            Some(idle) if running && idle >= LIVENESS_TIMEOUT => {
+                problems.push(format!("Accept loop has not run for {:?}", idle));
+            }
+            Some(_) => {}
+        }
+        let busy = self.shared.longest_busy();
+        if busy >= LIVENESS_TIMEOUT {
+            problems.push(format!("A request handler has been busy for {:?}", busy));
+        }
+        let live = problems.is_empty();
+
+        if !running {
+            problems.push("Not accepting connections".to_string());
+        }
+        problems.extend(self.shared.overload_problems());
+        if let Some(upstream) = &self.upstream {
+            if !upstream.is_reachable() {
+                problems.push("Relay upstream is unreachable".to_string());
+            }
+        }
+        Health {
+            live,
+            ready: problems.is_empty(),
+            problems,
+        }
+    }
+
+    /// Counts this start in `path`, creating it if needed, so that availability
+    /// reports include restarts; returns the starts counted before this one.
+    /// See [`crate::availability`].
+    pub fn persist_availability(&self, path: impl AsRef<Path>) -> io::Result<u64> {
+        self.shared.availability.persist(path.as_ref())
+    }
+
+    /// The last `limit` connections that closed, of the device named `device`
+    /// or of every client if `None`, newest first; see [`crate::history`]
+    pub fn connection_history(&self, device: Option<&str>, limit: usize) -> Vec<ClosedConnection> {
+        self.shared.history.recent(device, limit)
+    }
+
+    /// Closes every connection of the device named `device`, telling each it
+    /// was kicked, and returns how many there were; see [`crate::close`]
+    pub fn kick(&self, device: &str) -> usize {
+        let kicked = self.shared.kick(device);
+        let count = kicked.len();
+        self.shared.close_after_grace(kicked);
+        count
+    }
+
+    /// Keeps the last `capacity` closed connections from now on, instead of
+    /// [`DEFAULT_HISTORY`](crate::history::DEFAULT_HISTORY)
+    pub fn set_history_capacity(&self, capacity: usize) {
+        self.shared.history.set_capacity(capacity);
+    }
+
+    /// Runs the self-checks a `DiagnosticsRequest` asks for; see [`crate::diagnostics`]
+    pub fn diagnostics(&self) -> DiagnosticsReport {
+        (self.shared).diagnose(self.upstream.as_deref(), self.capture_dir.as_deref())
+    }
+
+    /// Caps the bytes per second read from each connection; `None` removes the cap.
+    /// Takes effect immediately, including on open connections.
+    pub fn set_upload_limit(&self, bytes_per_second: Option<u64>) {
+        Self::store_limit(&self.shared.upload_limit, bytes_per_second);
+    }
+
+    /// Caps the bytes per second written to each connection; `None` removes the cap.
+    /// Takes effect immediately, including on open connections.
+    pub fn set_download_limit(&self, bytes_per_second: Option<u64>) {
+        Self::store_limit(&self.shared.download_limit, bytes_per_second);
+    }
+
+    /// Caps the bytes each connection may hold in its buffers: a partial frame
+    /// received and responses not yet written. A connection whose partial frame
+    /// exceeds the cap is closed and counted in [`Stats::memory_disconnects`]; one
+    /// whose responses do has them written out before its next request is
+    /// handled. `None` removes the cap. Takes effect immediately.
+    pub fn set_connection_memory_limit(&self, bytes: Option<u64>) {
+        Self::store_limit(&self.shared.connection_memory, bytes);
+    }
+
+    fn store_limit(limit: &AtomicU64, bytes_per_second: Option<u64>) {
+        let bytes_per_second = bytes_per_second.map_or(u64::MAX, |bytes| bytes.min(u64::MAX - 1));
+        limit.store(bytes_per_second, Ordering::Relaxed);
+    }
+
+    /// Snapshot of the server's counters
+    pub fn stats(&self) -> Stats {
+        self.shared.stats()
+    }
+
+    /// [`Server::stats`] with the time they were taken, for rates between two
+    /// snapshots; see [`StatsSnapshot::since`]
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let taken = Instant::now();
+        StatsSnapshot {
+            taken,
+            stats: self.stats(),
+        }
+    }
+
+    /// Address the server is listening on, e.g. to find the port picked for `localhost:0`
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Runs the server, listening for incoming connections. Returns once the
+    /// server is stopped and every connection handler has finished.
     pub fn run(&self) -> io::Result<()> {
-        self.is_running.store(true, Ordering::SeqCst); // Mark the server as running
         info!("Server is running on {}", self.listener.local_addr()?); // Log the server address
+        self.shared.availability.start();
 
         // Enable non-blocking mode to prevent the listener from halting the server
         self.listener.set_nonblocking(true)?;
 
-        let pool = ThreadPool::new(16); // Create a thread pool with 16 threads
+        // The accept loop only accepts; the dispatcher decides what happens to each connection
+        let (accepted, queue) = crossbeam_channel::bounded(ACCEPT_QUEUE);
+        thread::scope(|scope| {
+            scope.spawn(|| self.dispatch(queue));
+            scope.spawn(|| self.sweep());
+            if let Some(watchdog) = &self.watchdog {
+                scope.spawn(move || self.watch(watchdog));
+            }
+            for listener in &self.listeners {
+                let incoming = Incoming {
+                    accepted: accepted.clone(),
+                    server: self,
+                };
+                scope.spawn(move || {
+                    info!("Also listening on {}", listener.name());
+                    if let Err(e) = listener.run(&incoming) {
+                        error!("Listener on {} failed: {}", listener.name(), e);
+                    }
+                });
+            }
+            self.accept(accepted);
+            // The dispatcher ends once every listener has let go of its queue too
+            for listener in &self.listeners {
+                listener.stop();
+            }
+        });
+
+        info!("Server stopped."); // Log server shutdown
+        Ok(())
+    }
+
+    // Sweeps the device queues until the server stops
+    fn sweep(&self) {
+        let interval = || Duration::from_micros(self.shared.sweep_micros.load(Ordering::Relaxed));
+        while self.stop_signal.wait_timeout(interval()) {
+            self.sweep_queues();
+        }
+    }
+
+    // Checks for stalls until the accept loop ends, logging and acting once per stall
+    fn watch(&self, watchdog: &Watchdog) {
+        let mut tripped = false;
+        while self.stop_signal.wait_timeout(CHECK_INTERVAL) {
+            let mut stalls = Vec::new();
+            if let Some(idle) = self.shared.accept_idle() {
+                if idle >= watchdog.accept_window {
+                    stalls.push(format!("accept loop has not run for {:?}", idle));
+                }
+            }
+            let busy = self.shared.longest_busy();
+            if busy >= watchdog.pool_window {
+                stalls.push(format!("a connection handler has been busy for {:?}", busy));
+            }
+
+            if stalls.is_empty() || tripped {
+                tripped = !stalls.is_empty();
+                continue;
+            }
+            tripped = true;
+            self.shared
+                .counters
+                .local()
+                .watchdog_trips
+                .fetch_add(1, Ordering::Relaxed);
+            error!(
+                "Watchdog: server stuck: {}\n  pool: {:?}\n{}",
+                stalls.join("; "),
+                self.stats().pool,
+                self.shared.describe_connections()
+            );
+            watchdog::act(watchdog.action);
+        }
+    }
 
-        while self.is_running.load(Ordering::SeqCst) {
+    // Accepts connections until the server is stopped; dropping `accepted` ends the dispatcher
+    fn accept(&self, accepted: Sender<Box<dyn Link>>) {
+        while self.stop_signal.is_running() {
+            let beat = self.shared.now_micros();
+            let previous = self.shared.accept_beat.swap(beat, Ordering::Relaxed);
+            let availability = &self.shared.availability;
+            if previous != 0 {
+                availability.turned_after(Duration::from_micros(beat.saturating_sub(previous)));
+            }
+            availability.overloaded(!self.shared.overload_problems().is_empty());
             match self.listener.accept() {
                 Ok((stream, addr)) => {
-                    info!("New client connected: {}", addr); // Log new connection
-                    let is_running = self.is_running.clone(); // Clone the running flag for the thread
-
-                    // Use the thread pool to handle the client
-                    pool.execute(move || {
-                        let mut client = Client::new(stream); // Create a new client instance
-                        while is_running.load(Ordering::SeqCst) {
-                            if let Err(e) = client.handle() {
-                                // Handle client communication
-                                error!("Error handling client: {}", e); // Log errors
-                                break; // Exit the loop on error
-                            }
-                        }
-                        info!("Client handler thread exiting.");
-                    });
+                    // Log new connection
+                    limited!(
+                        LogClass::Connection,
+                        Level::Info,
+                        peer:% = addr;
+                        "New client connected: {}", addr
+                    );
+                    // Accepted sockets may inherit the listener's non-blocking mode
+                    if let Err(e) = stream.set_nonblocking(false) {
+                        error!("Failed to configure client socket: {}", e);
+                        continue;
+                    }
+                    if let Some(Err(e)) = self.socket.map(|config| config.configure(&stream)) {
+                        warn!("Failed to set socket options for {}: {}", addr, e);
+                    }
+                    // Blocks while the queue is full, leaving later clients in the listen backlog
+                    if accepted.send(Box::new(stream)).is_err() {
+                        break; // The dispatcher is gone
+                    }
                 }
                 // Handle cases where no new connection is available
                 Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
-                    std::thread::sleep(Duration::from_millis(100)); // Reduce CPU usage by sleeping briefly
+                    // Wait before polling again, waking at once if the server is stopped
+                    self.stop_signal.wait_timeout(Duration::from_millis(100));
                 }
                 // Handle unexpected errors while accepting connections
                 Err(e) => {
@@ -105,15 +3312,136 @@ impl Server {
                 }
             }
         }
+    }
 
-        info!("Server stopped."); // Log server shutdown
+    // Sets up each accepted connection and hands it to a worker shard; returns once
+    // the accept loop has ended and every handler has finished
+    fn dispatch(&self, queue: Receiver<Box<dyn Link>>) {
+        let shards = Shards::new(self.shared.workers, self.shards);
+        let layers: Arc<[_]> = self.layers.iter().cloned().collect(); // Shared by every connection
+        let observers: Arc<[_]> = self.observers.iter().cloned().collect();
+        let virtual_hosts = Arc::new(self.virtual_hosts.clone());
+        // Connections are numbered from 1 for capture files and fault sequences
+        for (connection, stream) in (1u64..).zip(queue) {
+            let addr = stream.peer_addr();
+            #[cfg(feature = "fault-injection")]
+            if let Some(faults) = &self.faults {
+                while faults.connections_held()
+                    && self.stop_signal.wait_timeout(Duration::from_millis(10))
+                {}
+            }
+            let shared = self.shared.clone();
+            let refusal = shared
+                .overload
+                .refuse_connection(shared.counters.pool.queued_now(), shared.open_connections());
+            if let Some(reason) = refusal {
+                limited!(
+                    LogClass::Connection,
+                    Level::Debug,
+                    "Refusing connection from {}: {}",
+                    peer_name(addr),
+                    reason
+                );
+                shed(stream, &shared);
+                continue;
+            }
+            let registration = match shared.register(connection, stream.as_ref()) {
+                Ok(Some(registration)) => registration,
+                Ok(None) => continue, // The server is stopping; drop the connection
+                Err(e) => {
+                    error!("Failed to register client socket: {}", e);
+                    continue;
+                }
+            };
+            let capture = self.capture_dir.as_ref().and_then(|dir| {
+                // Transports without addresses are recorded as from the unspecified one
+                let peer = addr.unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 0)));
+                CaptureWriter::create(dir, connection, peer)
+                    .map_err(|e| error!("Failed to create capture file: {}", e))
+                    .ok()
+            });
+            let mut client = Client::new(stream, shared.clone()); // Create a new client instance
+            client.capture = capture;
+            client.capture_dir = self.capture_dir.clone();
+            client.upstream = self.upstream.clone();
+            client.router = self.router.clone();
+            client.layers = layers.clone();
+            client.observers = observers.clone();
+            client.authorizer = self.authorizer.clone();
+            client.log_tail = self.log_tail;
+            client.virtual_hosts = virtual_hosts.clone();
+            client.pick_server_name();
+            client.info.id = connection;
+            client.gauges = registration.gauges;
+            client.mailbox = registration.mailbox;
+            client.connection = Some(registration.id);
+            #[cfg(feature = "fault-injection")]
+            {
+                client.faults = self
+                    .faults
+                    .as_ref()
+                    .map(|faults| faults.connection(connection));
+            }
+
+            // The connection stays on its shard until it closes
+            shared.counters.pool.queued();
+            shards.place(Served { client, job: None });
+        }
+
+        shards.join(); // Handlers end promptly now that their sockets are shut down
+    }
+
+    /// Stops accepting connections but leaves open ones be; `run()` returns once
+    /// the last of them has closed. `stop()` still closes whatever remains.
+    pub fn drain(&self) {
+        if self.stop_signal.stop() {
+            info!("Draining connections.");
+        }
+    }
+
+    /// Drains like [`Server::drain`], and pushes `go_away` to every open
+    /// connection so its device moves elsewhere before the server is taken
+    /// down. Returns how many connections it was queued for. Devices that have
+    /// named themselves in a `ResumeRequest` get it within
+    /// [`DELIVERY_POLL_INTERVAL`]; other clients with their next response.
+    pub fn drain_with_go_away(&self, go_away: GoAway) -> Result<usize, CodecError> {
+        self.drain();
+        self.go_away(go_away)
+    }
+
+    /// Pushes `go_away` to every open connection, returning how many it was
+    /// queued for, and keeps serving them. Clients reconnect as it asks, which
+    /// sheds or rebalances load without draining; see [`crate::client`].
+    pub fn go_away(&self, go_away: GoAway) -> Result<usize, CodecError> {
+        let message = ServerMessage {
+            message: Some(server_message::Message::GoAway(go_away)),
+            ..Default::default()
+        };
+        let frame = Bytes::from(codec::encode(&message)?);
+        Ok(self.shared.post(frame, true))
+    }
+
+    /// Hands the listening socket to a replacement process that calls
+    /// [`handover::receive`](crate::handover::receive) on `path`, then drains.
+    /// Blocks until the replacement has connected.
+    #[cfg(all(feature = "handover", unix))]
+    pub fn hand_over(&self, path: impl AsRef<std::path::Path>) -> io::Result<()> {
+        crate::handover::offer(&self.listener, path.as_ref())?;
+        self.drain();
         Ok(())
     }
 
-    /// Stops the server by setting the running flag to false
+    /// Stops the server: the accept loop ends and every open connection is closed
     pub fn stop(&self) {
-        if self.is_running.load(Ordering::SeqCst) {
-            self.is_running.store(false, Ordering::SeqCst); // Mark the server as stopped
+        // A drained server has stopped accepting but may still have connections to close.
+        // They are closed before the signal wakes the dispatcher, which then registers
+        // no more of the connections it was handed.
+        let closing = !self.shared.is_closing();
+        if closing {
+            let numbers = self.shared.close_connections();
+            self.shared.close_after_grace(numbers);
+        }
+        if self.stop_signal.stop() || closing {
             info!("Shutdown signal sent."); // Log the shutdown signal
         } else {
             warn!("Server was already stopped or not running."); // Log a warning if the server isn't running
@@ -0,0 +1,318 @@
+//! Windows service.
+//!
+//! [`install`] registers the running executable with the service control
+//! manager (SCM), to be started at boot with the given arguments, and
+//! [`uninstall`] removes it again. [`start`] and [`stop`] ask the SCM to start
+//! or stop an installed service, as `sc start` and `sc stop` do.
+//!
+//! The executable calls [`run`] when the SCM starts it. The server runs until
+//! the SCM sends Stop, or Shutdown as the machine goes down. The server is then
+//! drained: it stops accepting, and open connections may finish for the grace
+//! period before [`Server::stop`] closes the rest. The SCM is told the service
+//! is stopping until [`Server::run`] has returned.
+
+use crate::close::CLOSE_GRACE;
+use crate::server::Server;
+use log::{error, info};
+use std::{
+    env,
+    ffi::c_void,
+    io::{self, ErrorKind},
+    os::windows::ffi::OsStrExt,
+    ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicPtr, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+use windows_sys::core::PWSTR;
+use windows_sys::Win32::{
+    Foundation::{ERROR_CALL_NOT_IMPLEMENTED, ERROR_SERVICE_SPECIFIC_ERROR, NO_ERROR},
+    Storage::FileSystem::DELETE,
+    System::Services::{
+        CloseServiceHandle, ControlService, CreateServiceW, DeleteService, OpenSCManagerW,
+        OpenServiceW, RegisterServiceCtrlHandlerExW, SetServiceStatus, StartServiceCtrlDispatcherW,
+        StartServiceW, SC_HANDLE, SC_MANAGER_CONNECT, SC_MANAGER_CREATE_SERVICE,
+        SERVICE_ACCEPT_SHUTDOWN, SERVICE_ACCEPT_STOP, SERVICE_AUTO_START,
+        SERVICE_CONTROL_INTERROGATE, SERVICE_CONTROL_SHUTDOWN, SERVICE_CONTROL_STOP,
+        SERVICE_ERROR_NORMAL, SERVICE_QUERY_STATUS, SERVICE_RUNNING, SERVICE_START,
+        SERVICE_START_PENDING, SERVICE_STATUS, SERVICE_STOP, SERVICE_STOPPED, SERVICE_STOP_PENDING,
+        SERVICE_TABLE_ENTRYW, SERVICE_WIN32_OWN_PROCESS,
+    },
+};
+
+// Time the SCM is asked to allow for starting up
+const START_HINT: Duration = Duration::from_secs(5);
+
+// The service this process runs; the SCM's callbacks carry no context of their own
+static SERVICE: Mutex<Option<Service>> = Mutex::new(None);
+
+// Where the SCM's status for the service is reported
+static STATUS: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+
+// Set once `Server::run` has returned, so a late forced stop is skipped
+static STOPPED: AtomicBool = AtomicBool::new(false);
+
+// What `run` hands to the SCM's callbacks
+struct Service {
+    name: Vec<u16>,
+    server: Arc<Server>,
+    grace: Duration,
+    result: Option<io::Result<()>>, // How `Server::run` ended
+}
+
+// Closes an SCM handle when dropped
+struct Handle(SC_HANDLE);
+
+impl Handle {
+    fn new(handle: SC_HANDLE) -> io::Result<Self> {
+        if handle.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Handle(handle))
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        unsafe { CloseServiceHandle(self.0) };
+    }
+}
+
+/// Registers the running executable as the service `name`, started at boot
+/// with `arguments`. Needs administrator rights.
+pub fn install(name: &str, display_name: &str, arguments: &[String]) -> io::Result<()> {
+    let executable = env::current_exe()?;
+    let executable = executable
+        .to_str()
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "Executable path is not UTF-8"))?;
+    let command = Some(executable)
+        .into_iter()
+        .chain(arguments.iter().map(String::as_str))
+        .map(quote)
+        .collect::<Vec<_>>()
+        .join(" ");
+    let manager = manager(SC_MANAGER_CREATE_SERVICE)?;
+    let (name, display_name, command) = (wide(name), wide(display_name), wide(&command));
+    let service = unsafe {
+        CreateServiceW(
+            manager.0,
+            name.as_ptr(),
+            display_name.as_ptr(),
+            SERVICE_QUERY_STATUS,
+            SERVICE_WIN32_OWN_PROCESS,
+            SERVICE_AUTO_START,
+            SERVICE_ERROR_NORMAL,
+            command.as_ptr(),
+            ptr::null(),
+            ptr::null_mut(),
+            ptr::null(),
+            ptr::null(), // LocalSystem
+            ptr::null(),
+        )
+    };
+    Handle::new(service)?;
+    Ok(())
+}
+
+/// Removes the service `name`; the SCM deletes it once it has stopped
+pub fn uninstall(name: &str) -> io::Result<()> {
+    let service = service(name, DELETE)?;
+    if unsafe { DeleteService(service.0) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Asks the SCM to start the service `name`
+pub fn start(name: &str) -> io::Result<()> {
+    let service = service(name, SERVICE_START)?;
+    if unsafe { StartServiceW(service.0, 0, ptr::null()) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Asks the SCM to stop the service `name`; it drains as described in the
+/// module documentation
+pub fn stop(name: &str) -> io::Result<()> {
+    let service = service(name, SERVICE_STOP)?;
+    let mut status = unsafe { std::mem::zeroed::<SERVICE_STATUS>() };
+    if unsafe { ControlService(service.0, SERVICE_CONTROL_STOP, &mut status) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Runs `server` as the service `name`, which the SCM has started, until the
+/// SCM stops it. Open connections are given `grace` to finish once it does.
+/// Fails if the process was not started by the SCM.
+pub fn run(name: &str, server: Server, grace: Duration) -> io::Result<()> {
+    let mut name = wide(name);
+    *SERVICE.lock().unwrap() = Some(Service {
+        name: name.clone(),
+        server: Arc::new(server),
+        grace,
+        result: None,
+    });
+    let table = [
+        SERVICE_TABLE_ENTRYW {
+            lpServiceName: name.as_mut_ptr(),
+            lpServiceProc: Some(service_main),
+        },
+        SERVICE_TABLE_ENTRYW {
+            lpServiceName: ptr::null_mut(),
+            lpServiceProc: None,
+        },
+    ];
+    // Returns once the service has stopped
+    let dispatched = unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) };
+    let service = SERVICE.lock().unwrap().take();
+    if dispatched == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    service.and_then(|service| service.result).unwrap_or(Ok(()))
+}
+
+// Started by the SCM on a thread of its own; runs the server until it stops
+unsafe extern "system" fn service_main(_argc: u32, _argv: *mut PWSTR) {
+    let Some((name, server)) = SERVICE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|service| (service.name.clone(), Arc::clone(&service.server)))
+    else {
+        return;
+    };
+    let status = RegisterServiceCtrlHandlerExW(name.as_ptr(), Some(control), ptr::null());
+    if status.is_null() {
+        error!(
+            "Failed to register the service control handler: {}",
+            io::Error::last_os_error()
+        );
+        return;
+    }
+    STATUS.store(status, Ordering::SeqCst);
+    report(SERVICE_START_PENDING, START_HINT, NO_ERROR);
+    report(SERVICE_RUNNING, Duration::ZERO, NO_ERROR);
+    info!("Service started.");
+
+    let result = server.run();
+    STOPPED.store(true, Ordering::SeqCst);
+    let exit_code = match &result {
+        Ok(()) => NO_ERROR,
+        Err(e) => {
+            error!("Server failed: {}", e);
+            ERROR_SERVICE_SPECIFIC_ERROR
+        }
+    };
+    if let Some(service) = SERVICE.lock().unwrap().as_mut() {
+        service.result = Some(result);
+    }
+    info!("Service stopped.");
+    report(SERVICE_STOPPED, Duration::ZERO, exit_code); // The dispatcher returns after this
+}
+
+// Called by the SCM for each control sent to the service
+unsafe extern "system" fn control(
+    control: u32,
+    _event_type: u32,
+    _event_data: *mut c_void,
+    _context: *mut c_void,
+) -> u32 {
+    match control {
+        SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+            let Some((server, grace)) = SERVICE
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(|service| (Arc::clone(&service.server), service.grace))
+            else {
+                return NO_ERROR;
+            };
+            report(SERVICE_STOP_PENDING, grace + CLOSE_GRACE * 2, NO_ERROR);
+            server.drain();
+            // Controls must be answered promptly, so the grace period is waited out elsewhere
+            thread::spawn(move || {
+                thread::sleep(grace);
+                if !STOPPED.load(Ordering::SeqCst) {
+                    server.stop();
+                }
+            });
+            NO_ERROR
+        }
+        SERVICE_CONTROL_INTERROGATE => NO_ERROR,
+        _ => ERROR_CALL_NOT_IMPLEMENTED,
+    }
+}
+
+// Tells the SCM the service is in `state`, expecting the next report within `wait`
+fn report(state: u32, wait: Duration, exit_code: u32) {
+    let accepted = match state {
+        SERVICE_RUNNING => SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN,
+        _ => 0, // Stopping or starting services take no controls
+    };
+    let status = SERVICE_STATUS {
+        dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+        dwCurrentState: state,
+        dwControlsAccepted: accepted,
+        dwWin32ExitCode: exit_code,
+        dwServiceSpecificExitCode: u32::from(exit_code == ERROR_SERVICE_SPECIFIC_ERROR),
+        dwCheckPoint: u32::from(wait > Duration::ZERO),
+        dwWaitHint: u32::try_from(wait.as_millis()).unwrap_or(u32::MAX),
+    };
+    if unsafe { SetServiceStatus(STATUS.load(Ordering::SeqCst), &status) } == 0 {
+        error!(
+            "Failed to report the service status: {}",
+            io::Error::last_os_error()
+        );
+    }
+}
+
+fn manager(access: u32) -> io::Result<Handle> {
+    Handle::new(unsafe { OpenSCManagerW(ptr::null(), ptr::null(), access) })
+}
+
+fn service(name: &str, access: u32) -> io::Result<Handle> {
+    let manager = manager(SC_MANAGER_CONNECT)?;
+    let name = wide(name);
+    Handle::new(unsafe { OpenServiceW(manager.0, name.as_ptr(), access) })
+}
+
+// Quotes one argument of the command line the SCM starts the service with, as
+// the Microsoft C runtime parses it
+fn quote(argument: &str) -> String {
+    if !argument.is_empty() && !argument.contains([' ', '\t', '"']) {
+        return argument.to_string();
+    }
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in argument.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                quoted.extend(std::iter::repeat_n('\\', backslashes * 2 + 1));
+                backslashes = 0;
+            }
+            _ => {
+                quoted.extend(std::iter::repeat_n('\\', backslashes));
+                backslashes = 0;
+            }
+        }
+        if c != '\\' {
+            quoted.push(c);
+        }
+    }
+    quoted.extend(std::iter::repeat_n('\\', backslashes * 2)); // Before the closing quote
+    quoted.push('"');
+    quoted
+}
+
+fn wide(text: &str) -> Vec<u16> {
+    std::ffi::OsStr::new(text)
+        .encode_wide()
+        .chain(Some(0))
+        .collect()
+}
@@ -0,0 +1,144 @@
+//! Transport-independent server side of one connection.
+//!
+//! [`Session`] does everything the server does with a client's bytes that does
+//! not depend on how they arrive: framing, batches, flow control, priority
+//! order, retried message IDs and the request handler. Capture replay drives it
+//! directly. Connections, whatever their transport, are served by the server
+//! instead, which runs the same steps interleaved with its router,
+//! authorization, statistics, fault injection and capture.
+
+use crate::batch;
+use crate::codec::CodecError;
+use crate::dedup::DedupWindow;
+use crate::flow::{window_update, FlowError, ReceiveWindows, FLOW_CONTROL_VERSION};
+use crate::handler::handle_message;
+use crate::message::{ClientMessage, ServerMessage};
+use crate::priority::{priority, PriorityQueue};
+use crate::protocol::{Event, ServerProtocol};
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Why a session stopped accepting requests
+#[derive(Debug)]
+pub enum SessionError {
+    /// The client's bytes could not be decoded, or a response could not be encoded
+    Codec(CodecError),
+    /// The client sent beyond its flow control window; the connection should be closed
+    Flow(FlowError),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::Codec(e) => write!(f, "{}", e),
+            SessionError::Flow(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SessionError {}
+
+impl From<CodecError> for SessionError {
+    fn from(error: CodecError) -> Self {
+        SessionError::Codec(error)
+    }
+}
+
+impl From<FlowError> for SessionError {
+    fn from(error: FlowError) -> Self {
+        SessionError::Flow(error)
+    }
+}
+
+/// Server side of one connection, without I/O
+#[derive(Debug, Default)]
+pub struct Session {
+    protocol: ServerProtocol,
+    windows: Option<ReceiveWindows>, // Once the client states it has credits
+    dedup: DedupWindow,
+}
+
+impl Session {
+    /// Creates the session for a freshly opened connection
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers the requests completed by `bytes`, queueing responses and credit grants for [`poll_transmit`](Self::poll_transmit).
+    ///
+    /// A flow control violation leaves every request of this read unanswered.
+    pub fn feed_bytes(&mut self, bytes: &[u8]) -> Result<(), SessionError> {
+        let mut result = Ok(());
+        let mut pending = PriorityQueue::new();
+        for event in self.protocol.feed_bytes(bytes) {
+            match event {
+                Event::Message(message) => {
+                    for message in batch::unpack(message) {
+                        if message.protocol_version >= FLOW_CONTROL_VERSION {
+                            self.windows.get_or_insert_with(ReceiveWindows::new);
+                        }
+                        if let Some(windows) = self.windows.as_mut() {
+                            windows.received(message.stream_id)?;
+                        }
+                        pending.push(priority(&message), message);
+                    }
+                }
+                Event::Error(e) => result = Err(e.into()),
+                Event::Closed => {}
+            }
+        }
+        while let Some(message) = pending.pop() {
+            if let Some(windows) = self.windows.as_mut() {
+                windows.completed(message.stream_id);
+            }
+            if let Err(e) = self.respond(message) {
+                result = result.and(Err(e.into()));
+            }
+        }
+        while let Some((stream, credits)) =
+            self.windows.as_mut().and_then(ReceiveWindows::poll_grant)
+        {
+            if let Err(e) = self.protocol.send(&window_update(stream, credits)) {
+                result = result.and(Err(e.into()));
+            }
+        }
+        result
+    }
+
+    /// Records that the client closed its side
+    pub fn feed_eof(&mut self) {
+        self.protocol.feed_eof();
+    }
+
+    /// Whether requests can still arrive
+    pub fn is_open(&self) -> bool {
+        self.protocol.is_open()
+    }
+
+    /// Next frame to write to the client
+    pub fn poll_transmit(&mut self) -> Option<Vec<u8>> {
+        self.protocol.poll_transmit()
+    }
+
+    // Queues the response to one request; empty messages are ignored, as by the server
+    fn respond(&mut self, message: ClientMessage) -> Result<(), CodecError> {
+        let Some(request) = message.message else {
+            return Ok(());
+        };
+        // Retried message IDs get their first response again
+        let result = match self.dedup.get(message.message_id) {
+            Some(cached) => cached.clone(),
+            None => {
+                let result = handle_message(request);
+                self.dedup.insert(message.message_id, result.clone());
+                result
+            }
+        };
+        self.protocol.send(&ServerMessage {
+            message: Some(result),
+            message_id: message.message_id,
+            stream_id: message.stream_id,
+        })
+    }
+}
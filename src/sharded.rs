@@ -0,0 +1,73 @@
+//! State split into independently locked shards.
+//!
+//! The server's device queues, quotas and connection registry are each one map
+//! that every connection handler touches. Behind a single mutex, handlers on
+//! different cores queue up for it even when they work on different devices.
+//! [`Sharded`] splits such state into [`DEFAULT_SHARDS`] parts, each behind a
+//! lock of its own, and picks the part for a key by its hash. Handlers then
+//! only contend when their keys land in the same shard.
+//!
+//! Work on a single key, such as queuing for one device, locks one shard. Work
+//! on every key, such as listing queue depths, visits the shards one at a time,
+//! so it sees each shard as of when it got there rather than all of them at
+//! one instant. Settings that every shard needs are applied to each in turn.
+
+use crate::sync::{Mutex, MutexGuard};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hash},
+};
+
+/// Shards used by [`Sharded::default`]; a few times the cores of a large server
+pub const DEFAULT_SHARDS: usize = 64;
+
+/// State of type `T` in independently locked shards
+#[cfg_attr(not(loom), derive(Debug))]
+pub struct Sharded<T> {
+    hasher: RandomState, // Keyed per instance, so clients cannot aim at one shard
+    shards: Box<[Mutex<T>]>,
+}
+
+impl<T> Sharded<T> {
+    /// Creates `count` shards, at least one, each made by `make`
+    pub fn new(count: usize, mut make: impl FnMut() -> T) -> Self {
+        Sharded {
+            hasher: RandomState::new(),
+            shards: (0..count.max(1)).map(|_| Mutex::new(make())).collect(),
+        }
+    }
+
+    /// Locks the shard holding `key`
+    pub fn lock<K: Hash + ?Sized>(&self, key: &K) -> MutexGuard<'_, T> {
+        let index = self.hasher.hash_one(key) as usize % self.shards.len();
+        self.shards[index].lock().unwrap()
+    }
+
+    /// Locks shard `index`, modulo the count, for keys that record their shard
+    pub fn lock_index(&self, index: usize) -> MutexGuard<'_, T> {
+        self.shards[index % self.shards.len()].lock().unwrap()
+    }
+
+    /// Locks each shard in turn, as the iterator reaches it
+    pub fn shards(&self) -> impl Iterator<Item = MutexGuard<'_, T>> {
+        self.shards.iter().map(|shard| shard.lock().unwrap())
+    }
+
+    /// Calls `f` on each shard in turn
+    pub fn for_each(&self, mut f: impl FnMut(&mut T)) {
+        for mut shard in self.shards() {
+            f(&mut shard);
+        }
+    }
+
+    /// Number of shards
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+}
+
+impl<T: Default> Default for Sharded<T> {
+    fn default() -> Self {
+        Self::new(DEFAULT_SHARDS, T::default)
+    }
+}
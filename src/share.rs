@@ -0,0 +1,144 @@
+//! Shared subscriptions, which split a topic's messages among a group.
+//!
+//! Several worker processes consuming one device-command topic should each
+//! handle a share of its messages, not all of them. Subscribers that join the
+//! same named group under a filter (see [`crate::topic`]) form a shared
+//! subscription: each message matching the filter goes to exactly one member.
+//! On the wire such a subscription is written as in MQTT,
+//! `$share/{group}/{filter}`, which [`parse`] splits up.
+//!
+//! [`SharedSubscriptions::round_robin`] picks the members of a group in turn;
+//! [`SharedSubscriptions::least_loaded`] picks the one with the least load as
+//! reported by the caller, such as the depth of its outbox, taking turns among
+//! equally loaded ones.
+
+use crate::topic::{self, TopicError, TopicTrie, SEPARATOR};
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// First level of a shared subscription's filter
+pub const SHARE_PREFIX: &str = "$share";
+
+/// Splits `$share/{group}/{filter}` into its group and filter; `None` if it is
+/// not a shared subscription or either part is empty
+pub fn parse(subscription: &str) -> Option<(&str, &str)> {
+    let rest = subscription
+        .strip_prefix(SHARE_PREFIX)?
+        .strip_prefix(SEPARATOR)?;
+    let (group, filter) = rest.split_once(SEPARATOR)?;
+    match group.is_empty() || filter.is_empty() {
+        true => None,
+        false => Some((group, filter)),
+    }
+}
+
+// The members of one group under one filter
+#[derive(Debug)]
+struct Group<T> {
+    members: Vec<T>,
+    next: AtomicUsize, // Member whose turn it is
+}
+
+/// Shared subscriptions by group and filter
+#[derive(Debug)]
+pub struct SharedSubscriptions<T> {
+    trie: TopicTrie<(String, String)>, // Group and filter, under the filter
+    groups: BTreeMap<(String, String), Group<T>>,
+}
+
+impl<T> SharedSubscriptions<T> {
+    /// Creates a set without subscriptions
+    pub fn new() -> Self {
+        SharedSubscriptions {
+            trie: TopicTrie::new(),
+            groups: BTreeMap::new(),
+        }
+    }
+
+    /// Adds `member` to `group`'s subscription to `filter`
+    pub fn subscribe(&mut self, group: &str, filter: &str, member: T) -> Result<(), TopicError> {
+        topic::validate_filter(filter)?;
+        let key = (group.to_string(), filter.to_string());
+        if !self.groups.contains_key(&key) {
+            self.trie.insert(filter, key.clone())?;
+        }
+        let group = self.groups.entry(key).or_insert_with(|| Group {
+            members: Vec::new(),
+            next: AtomicUsize::new(0),
+        });
+        group.members.push(member);
+        Ok(())
+    }
+
+    /// Removes `member` from `group`'s subscription to `filter`; whether it was
+    /// a member. The subscription ends with its last member.
+    pub fn unsubscribe(&mut self, group: &str, filter: &str, member: &T) -> bool
+    where
+        T: PartialEq,
+    {
+        let key = (group.to_string(), filter.to_string());
+        let Some(shared) = self.groups.get_mut(&key) else {
+            return false;
+        };
+        let Some(at) = shared.members.iter().position(|m| m == member) else {
+            return false;
+        };
+        shared.members.remove(at);
+        let next = shared.next.get_mut();
+        if at < *next {
+            *next -= 1; // The same member keeps its turn
+        }
+        if shared.members.is_empty() {
+            self.groups.remove(&key);
+            self.trie.remove(filter, &key);
+        }
+        true
+    }
+
+    /// The member of each subscription matching `topic` whose turn it is
+    pub fn round_robin(&self, topic: &str) -> Vec<&T> {
+        self.least_loaded(topic, |_| 0)
+    }
+
+    /// The member of each subscription matching `topic` with the least `load`,
+    /// taking turns among those with the same load. Only reads the set, so
+    /// callers may share it; two at once may then both pick the same member.
+    pub fn least_loaded(&self, topic: &str, load: impl Fn(&T) -> usize) -> Vec<&T> {
+        let mut picked = Vec::new();
+        for key in self.trie.matches(topic) {
+            let Some(group) = self.groups.get(key) else {
+                continue;
+            };
+            let count = group.members.len();
+            let next = group.next.load(Ordering::Relaxed);
+            let chosen = (0..count)
+                .map(|offset| (next + offset) % count)
+                .min_by_key(|&at| load(&group.members[at]));
+            if let Some(at) = chosen {
+                group.next.store((at + 1) % count, Ordering::Relaxed);
+                picked.push(&group.members[at]);
+            }
+        }
+        picked
+    }
+
+    /// Number of shared subscriptions, counting each group and filter once
+    pub fn len(&self) -> usize {
+        self.groups.len()
+    }
+
+    /// Whether there are no shared subscriptions
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+}
+
+impl<T> Default for SharedSubscriptions<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
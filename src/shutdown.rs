@@ -0,0 +1,131 @@
+//! The server's stop flag and its registry of open connections.
+//!
+//! Stopping a server races with the threads it has to stop. The accept loop
+//! may be about to wait for its next poll when [`Server::stop`] clears the
+//! running flag, and a connection may be about to register when `stop` walks
+//! the registry to close every socket. Either miss leaves a thread waiting
+//! for a wakeup that already happened, and `run()` never returns.
+//!
+//! [`StopSignal`] keeps the flag under the same lock its waiters sleep on, so
+//! a stop is either seen before waiting or wakes the waiter. [`Registry`]
+//! refuses entries once it is closed; kept behind a lock, an entry is then
+//! either refused or visited by whoever closed it. Both are built on
+//! [`crate::sync`] and tested under loom in `tests/loom_test.rs`.
+//!
+//! [`Server::stop`]: crate::server::Server::stop
+
+use crate::slab::{Key, Slab};
+use crate::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+/// Whether a server is still running, with a wait that ends as soon as it stops
+#[derive(Default)]
+pub struct StopSignal {
+    stopped: Mutex<bool>,
+    woken: Condvar, // Signalled once, on the first `stop`
+}
+
+impl StopSignal {
+    /// Creates a signal in the running state
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `stop` has not been called yet
+    pub fn is_running(&self) -> bool {
+        !*self.stopped.lock().unwrap()
+    }
+
+    /// Stops, waking every waiter; returns whether this call did it
+    pub fn stop(&self) -> bool {
+        let mut stopped = self.stopped.lock().unwrap();
+        let was_running = !*stopped;
+        *stopped = true;
+        self.woken.notify_all();
+        was_running
+    }
+
+    /// Waits up to `timeout`, returning at once if stopped; returns whether
+    /// still running. May return early without being stopped.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let stopped = self.stopped.lock().unwrap();
+        if *stopped {
+            return false;
+        }
+        let (stopped, _) = self.woken.wait_timeout(stopped, timeout).unwrap();
+        !*stopped
+    }
+}
+
+/// Entries in slots reused as they leave, refused once closed
+#[derive(Debug)]
+pub struct Registry<T> {
+    open: Slab<T>,
+    closed: bool,
+}
+
+impl<T> Registry<T> {
+    /// Creates an empty, open registry
+    pub fn new() -> Self {
+        Registry {
+            open: Slab::new(),
+            closed: false,
+        }
+    }
+
+    /// Adds `value`, or hands it back if the registry is closed
+    pub fn insert(&mut self, value: T) -> Result<Key, T> {
+        if self.closed {
+            return Err(value);
+        }
+        Ok(self.open.insert(value))
+    }
+
+    /// Removes the entry under `key`
+    pub fn remove(&mut self, key: Key) -> Option<T> {
+        self.open.remove(key)
+    }
+
+    /// The entry under `key`
+    pub fn get(&self, key: Key) -> Option<&T> {
+        self.open.get(key)
+    }
+
+    /// Refuses further entries; returns the ones still open, which the caller
+    /// should tell to leave
+    pub fn close(&mut self) -> impl Iterator<Item = &T> {
+        self.closed = true;
+        self.open.values()
+    }
+
+    /// Whether `close` has been called
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Open entries with their keys
+    pub fn iter(&self) -> impl Iterator<Item = (Key, &T)> {
+        self.open.iter()
+    }
+
+    /// Open entries
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.open.values()
+    }
+
+    /// Number of open entries
+    pub fn len(&self) -> usize {
+        self.open.len()
+    }
+
+    /// Whether no entries are open
+    pub fn is_empty(&self) -> bool {
+        self.open.is_empty()
+    }
+}
+
+impl<T> Default for Registry<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
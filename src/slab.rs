@@ -0,0 +1,202 @@
+//! Slots for values that come and go, addressed by small stable keys.
+//!
+//! A reconnect storm opens and closes thousands of connections a second.
+//! Keeping each one's state in a map hashes it in and out and lets its entry
+//! wander the heap; [`Slab`] keeps the values in one vector and hands the slot
+//! of a removed value to the next one, so once it has grown to the peak number
+//! of values, inserting allocates nothing.
+//!
+//! A [`Key`] is a slot's index together with the slot's generation, which goes
+//! up each time the slot is freed. A key that outlives its value stops
+//! matching instead of reaching whatever took the slot next, so keys can be
+//! handed around freely as cheap handles. They pack into a `u64` for logs and
+//! maps. Like the codec, this module only depends on `core` and `alloc`.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Handle to a value in a [`Slab`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Key {
+    index: u32,
+    generation: u32,
+}
+
+impl Key {
+    /// Slot of the value, below the largest number of values the slab has held
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+
+    /// The key as one number: the generation above the index
+    pub fn to_bits(self) -> u64 {
+        (self.generation as u64) << 32 | self.index as u64
+    }
+
+    /// The key [`to_bits`](Key::to_bits) made `bits` from
+    pub fn from_bits(bits: u64) -> Key {
+        Key {
+            index: bits as u32,
+            generation: (bits >> 32) as u32,
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.index, self.generation)
+    }
+}
+
+// A slot and the generation of the keys that reach it
+#[derive(Debug)]
+struct Entry<T> {
+    generation: u32,
+    slot: Slot<T>,
+}
+
+#[derive(Debug)]
+enum Slot<T> {
+    Occupied(T),
+    Vacant(Option<u32>), // Next free slot
+}
+
+/// Values in reusable slots of one vector
+#[derive(Debug)]
+pub struct Slab<T> {
+    entries: Vec<Entry<T>>,
+    free: Option<u32>, // Most recently freed slot, heading the list of free ones
+    len: usize,
+}
+
+impl<T> Slab<T> {
+    /// Creates an empty slab
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    /// Creates an empty slab with room for `capacity` values before it grows
+    pub fn with_capacity(capacity: usize) -> Self {
+        Slab {
+            entries: Vec::with_capacity(capacity),
+            free: None,
+            len: 0,
+        }
+    }
+
+    /// Stores `value` in a free slot, or a new one if none is free
+    pub fn insert(&mut self, value: T) -> Key {
+        self.len += 1;
+        if let Some(index) = self.free {
+            let entry = &mut self.entries[index as usize];
+            if let Slot::Vacant(next) = entry.slot {
+                self.free = next;
+            }
+            entry.slot = Slot::Occupied(value);
+            return Key {
+                index,
+                generation: entry.generation,
+            };
+        }
+        let index = u32::try_from(self.entries.len()).expect("More than u32::MAX slots");
+        self.entries.push(Entry {
+            generation: 0,
+            slot: Slot::Occupied(value),
+        });
+        Key {
+            index,
+            generation: 0,
+        }
+    }
+
+    /// The value `key` was handed out for, unless it has been removed
+    pub fn get(&self, key: Key) -> Option<&T> {
+        match self.entries.get(key.index as usize) {
+            Some(Entry {
+                generation,
+                slot: Slot::Occupied(value),
+            }) if *generation == key.generation => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The value `key` was handed out for, to change it
+    pub fn get_mut(&mut self, key: Key) -> Option<&mut T> {
+        match self.entries.get_mut(key.index as usize) {
+            Some(Entry {
+                generation,
+                slot: Slot::Occupied(value),
+            }) if *generation == key.generation => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Whether `key` still reaches a value
+    pub fn contains(&self, key: Key) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Takes the value out, freeing its slot for the next insert; `None` if
+    /// `key` no longer reaches one
+    pub fn remove(&mut self, key: Key) -> Option<T> {
+        let entry = self.entries.get_mut(key.index as usize)?;
+        if entry.generation != key.generation {
+            return None;
+        }
+        match core::mem::replace(&mut entry.slot, Slot::Vacant(self.free)) {
+            Slot::Occupied(value) => {
+                entry.generation = entry.generation.wrapping_add(1); // Older keys stop matching
+                self.free = Some(key.index);
+                self.len -= 1;
+                Some(value)
+            }
+            vacant => {
+                entry.slot = vacant; // Already free; keep its place in the list
+                None
+            }
+        }
+    }
+
+    /// The values with their keys, in slot order
+    pub fn iter(&self) -> impl Iterator<Item = (Key, &T)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entry)| match &entry.slot {
+                Slot::Occupied(value) => Some((
+                    Key {
+                        index: index as u32,
+                        generation: entry.generation,
+                    },
+                    value,
+                )),
+                Slot::Vacant(_) => None,
+            })
+    }
+
+    /// The values, in slot order
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.iter().map(|(_, value)| value)
+    }
+
+    /// Number of values held
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether no values are held
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of slots, free or not; the most values held at once
+    pub fn slots(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+impl<T> Default for Slab<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
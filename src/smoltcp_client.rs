@@ -0,0 +1,189 @@
+//! Client state machine driven by a caller-owned smoltcp TCP socket.
+//!
+//! Bare-metal firmware owns its interface, socket set and poll loop, so this
+//! client never touches the network itself. Requests are queued with
+//! [`SmoltcpClient::send`] and each call to [`SmoltcpClient::poll`] moves queued
+//! bytes into the socket and returns a response once a full frame has arrived:
+//!
+//! ```ignore
+//! loop {
+//!     iface.poll(now, &mut device, &mut sockets);
+//!     let socket = sockets.get_mut::<tcp::Socket>(handle);
+//!     if let Some(response) = client.poll(socket) {
+//!         // handle the response
+//!     }
+//! }
+//! ```
+
+use crate::codec::{CodecError, MAX_FRAME_SIZE, PROTOCOL_VERSION};
+use crate::flow::SendWindows;
+use crate::fmt::{log_debug, log_trace, log_warn};
+use crate::message::{client_message, server_message, ClientMessage, ServerMessage};
+use crate::protocol::{ClientProtocol, Event};
+use alloc::{collections::VecDeque, vec::Vec};
+use prost::Message;
+use smoltcp::socket::tcp;
+
+/// Connection state as observed from the socket
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ClientState {
+    /// Waiting for the socket to finish its handshake
+    Connecting,
+    /// The socket is established and frames are being exchanged
+    Established,
+    /// The socket was closed by either side
+    Closed,
+    /// The server sent a frame that could not be decoded; the socket was aborted
+    Failed(CodecError),
+}
+
+/// Protocol client polled from the firmware's network loop
+#[derive(Debug)]
+pub struct SmoltcpClient {
+    state: ClientState,
+    protocol: ClientProtocol,
+    outgoing: Vec<u8>,             // Encoded frames not yet accepted by the socket
+    held: VecDeque<ClientMessage>, // Requests waiting for flow control credits
+    windows: SendWindows,
+    events: VecDeque<Event<ServerMessage>>, // Events not yet returned by `poll`
+    stated: bool, // Whether the protocol version went out, on the first request
+}
+
+impl SmoltcpClient {
+    /// Creates a client for a socket that is connecting or already connected
+    pub fn new() -> Self {
+        SmoltcpClient {
+            state: ClientState::Connecting,
+            protocol: ClientProtocol::new(),
+            outgoing: Vec::new(),
+            held: VecDeque::new(),
+            windows: SendWindows::new(),
+            events: VecDeque::new(),
+            stated: false,
+        }
+    }
+
+    /// Current connection state
+    pub fn state(&self) -> &ClientState {
+        &self.state
+    }
+
+    /// Number of encoded bytes waiting for room in the socket's transmit buffer
+    pub fn pending(&self) -> usize {
+        self.outgoing.len()
+    }
+
+    /// Number of requests held back until the server grants flow control credits
+    pub fn held(&self) -> usize {
+        self.held.len()
+    }
+
+    /// Queues a request; it is written to the socket on the following polls
+    pub fn send(&mut self, message: client_message::Message) -> Result<(), CodecError> {
+        let message = ClientMessage {
+            message: Some(message),
+            message_id: 0,
+            stream_id: 0,
+            protocol_version: if self.stated { 0 } else { PROTOCOL_VERSION },
+        };
+        self.stated = true;
+        if !self.held.is_empty() || !self.windows.consume(0) {
+            // Checked now so a held request cannot fail when it is released
+            let len = message.encoded_len();
+            if len > MAX_FRAME_SIZE {
+                return Err(CodecError::FrameTooLarge(len));
+            }
+            log_debug!("Out of credits, holding request");
+            self.held.push_back(message);
+            return Ok(());
+        }
+        self.encode(&message)
+    }
+
+    fn encode(&mut self, message: &ClientMessage) -> Result<(), CodecError> {
+        self.protocol.send(message)?;
+        while let Some(frame) = self.protocol.poll_transmit() {
+            log_trace!("Queued frame of {} bytes", frame.len());
+            self.outgoing.extend_from_slice(&frame);
+        }
+        Ok(())
+    }
+
+    // Applies credit grants, releasing held requests, and queues the other events for `poll`
+    fn absorb(&mut self, events: Vec<Event<ServerMessage>>) {
+        for event in events {
+            match event {
+                Event::Message(ServerMessage {
+                    message: Some(server_message::Message::WindowUpdate(update)),
+                    stream_id,
+                    ..
+                }) => self.windows.grant(stream_id, update.credits),
+                event => self.events.push_back(event),
+            }
+        }
+        while !self.held.is_empty() && self.windows.consume(0) {
+            if let Some(message) = self.held.pop_front() {
+                let _ = self.encode(&message); // Its size was checked by `send`
+            }
+        }
+    }
+
+    /// Exchanges bytes with the socket, returning the next complete response if any
+    pub fn poll(&mut self, socket: &mut tcp::Socket<'_>) -> Option<ServerMessage> {
+        match self.state {
+            ClientState::Closed | ClientState::Failed(_) => return None,
+            ClientState::Connecting if socket.may_send() => {
+                log_debug!("Connection established");
+                self.state = ClientState::Established;
+            }
+            ClientState::Connecting => return None,
+            ClientState::Established => {}
+        }
+
+        // Hand as many queued bytes to the socket as its transmit buffer accepts
+        if !self.outgoing.is_empty() && socket.can_send() {
+            if let Ok(written) = socket.send_slice(&self.outgoing) {
+                self.outgoing.drain(..written);
+            }
+        }
+
+        if socket.can_recv() {
+            let protocol = &mut self.protocol;
+            if let Ok(events) = socket.recv(|bytes| (bytes.len(), protocol.feed_bytes(bytes))) {
+                self.absorb(events);
+            }
+        } else if !socket.may_recv() {
+            let events = self.protocol.feed_eof();
+            self.absorb(events);
+        }
+
+        match self.events.pop_front()? {
+            Event::Message(message) => {
+                log_trace!(
+                    "Received response, {} bytes still buffered",
+                    self.protocol.buffered()
+                );
+                Some(message)
+            }
+            Event::Error(e) => {
+                log_warn!("Failed to decode frame from server: {}", e);
+                socket.abort();
+                self.state = ClientState::Failed(e);
+                None
+            }
+            Event::Closed => {
+                // Only reported once every response received before the close was returned
+                log_debug!("Connection closed");
+                self.state = ClientState::Closed;
+                None
+            }
+        }
+    }
+}
+
+impl Default for SmoltcpClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,103 @@
+//! Options for the listening socket and the connections it accepts.
+//!
+//! [`Server::new`] binds with the operating system's defaults. A reconnect
+//! storm can overflow the default listen backlog of 128, after which the
+//! kernel drops the handshakes of further devices and they back off for
+//! seconds. [`Server::with_config`] binds with a [`ServerConfig`] instead,
+//! which sets the backlog, the receive and send buffer sizes, and the IP
+//! type-of-service byte, so network equipment can prioritise device traffic
+//! by its DSCP marking. Buffer sizes and marking are set on the listener
+//! before it listens and again on each accepted connection, since not every
+//! platform passes them on.
+//!
+//! [`Server::new`]: crate::server::Server::new
+//! [`Server::with_config`]: crate::server::Server::with_config
+
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use std::{
+    io,
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+};
+
+/// Listen backlog used when none is configured, as in the standard library
+pub const DEFAULT_BACKLOG: i32 = 128;
+
+/// Socket options the server binds and accepts with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerConfig {
+    /// Connections the kernel completes and queues before the server accepts
+    /// them; capped by the system (`net.core.somaxconn` on Linux)
+    pub backlog: i32,
+    /// `SO_RCVBUF` in bytes; the system default when `None`
+    pub recv_buffer: Option<usize>,
+    /// `SO_SNDBUF` in bytes; the system default when `None`
+    pub send_buffer: Option<usize>,
+    /// Type-of-service byte (traffic class on IPv6) of every packet sent,
+    /// such as [`ServerConfig::dscp`] makes; unmarked when `None`
+    pub tos: Option<u32>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            backlog: DEFAULT_BACKLOG,
+            recv_buffer: None,
+            send_buffer: None,
+            tos: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Marks packets with a DSCP code point, such as 46 for expedited
+    /// forwarding, leaving the ECN bits clear
+    pub fn dscp(mut self, code_point: u8) -> Self {
+        self.tos = Some(u32::from(code_point & 0x3f) << 2);
+        self
+    }
+
+    // Binds to the first of `addr`'s addresses that accepts, like `TcpListener::bind`
+    pub(crate) fn bind(&self, addr: &str) -> io::Result<TcpListener> {
+        let mut last_error = io::Error::new(io::ErrorKind::InvalidInput, "No address to bind");
+        for addr in addr.to_socket_addrs()? {
+            match self.bind_one(addr) {
+                Ok(listener) => return Ok(listener),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    fn bind_one(&self, addr: SocketAddr) -> io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?; // As the standard library does, for quick restarts
+        self.apply(&socket, addr.is_ipv6())?;
+        socket.bind(&addr.into())?;
+        socket.listen(self.backlog)?;
+        Ok(socket.into())
+    }
+
+    // Sets the buffer sizes and marking on an accepted connection
+    pub(crate) fn configure(&self, stream: &TcpStream) -> io::Result<()> {
+        let ipv6 = stream.local_addr()?.is_ipv6();
+        self.apply(&SockRef::from(stream), ipv6)
+    }
+
+    fn apply(&self, socket: &Socket, ipv6: bool) -> io::Result<()> {
+        if let Some(size) = self.recv_buffer {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(size) = self.send_buffer {
+            socket.set_send_buffer_size(size)?;
+        }
+        match self.tos {
+            #[cfg(unix)]
+            Some(tos) if ipv6 => socket.set_tclass_v6(tos),
+            #[cfg(not(unix))]
+            Some(_) if ipv6 => Ok(()), // Not settable for IPv6 on this platform
+            Some(tos) => socket.set_tos(tos),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Messages kept while the client is disconnected, sent once it reconnects.
+//!
+//! Telemetry a device produces during an outage should reach the server
+//! afterwards rather than be lost. A [`Client`] given a [`Spool`] with
+//! [`Client::set_spool`] keeps the messages it cannot send, because it is not
+//! connected or the connection broke under them, and sends them in order as
+//! soon as it is connected again, ahead of any newer ones. Their responses
+//! arrive like any others; give the messages IDs to tell them apart.
+//!
+//! A spool holds at most its capacity in messages. When it is full, the
+//! oldest is dropped to make room and handed to the callback set with
+//! [`Spool::on_drop`]. A spool made with [`Spool::open`] also keeps its
+//! messages in a small file, so they survive a restart of the device; the
+//! file is appended to as messages are kept and rewritten as they leave.
+//!
+//! [`Client`]: crate::client::Client
+//! [`Client::set_spool`]: crate::client::Client::set_spool
+
+use crate::codec;
+use crate::message::ClientMessage;
+use log::warn;
+use std::{
+    collections::VecDeque,
+    fmt, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// Messages a spool holds by default
+pub const DEFAULT_SPOOL_CAPACITY: usize = 1000;
+
+/// Outgoing messages waiting for a connection, oldest first
+pub struct Spool {
+    messages: VecDeque<ClientMessage>,
+    capacity: usize,
+    file: Option<PathBuf>,        // Mirror of `messages`, one frame each
+    on_drop: Option<DropHandler>, // Told about each message dropped to make room
+}
+
+type DropHandler = Box<dyn FnMut(ClientMessage) + Send>;
+
+impl Spool {
+    /// Creates an empty spool in memory holding up to `capacity` messages
+    pub fn new(capacity: usize) -> Self {
+        Spool {
+            messages: VecDeque::new(),
+            capacity: capacity.max(1),
+            file: None,
+            on_drop: None,
+        }
+    }
+
+    /// Creates a spool kept in the file at `path`, starting with the messages
+    /// already in it, or the newest `capacity` of them. A damaged end of the
+    /// file, such as a frame cut short by a power cut, is discarded.
+    pub fn open(path: impl AsRef<Path>, capacity: usize) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut spool = Spool::new(capacity);
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let mut rest = &bytes[..];
+        while let Ok(Some((message, used))) = codec::decode_frame::<ClientMessage>(rest) {
+            spool.messages.push_back(message);
+            rest = &rest[used..];
+        }
+        if !rest.is_empty() {
+            warn!(
+                "Discarding {} damaged bytes at the end of the spool",
+                rest.len()
+            );
+        }
+        let excess = spool.messages.len().saturating_sub(spool.capacity);
+        spool.messages.drain(..excess);
+        spool.file = Some(path.to_path_buf());
+        spool.save();
+        Ok(spool)
+    }
+
+    /// Calls `handler` with each message dropped from a full spool
+    pub fn on_drop(mut self, handler: impl FnMut(ClientMessage) + Send + 'static) -> Self {
+        self.on_drop = Some(Box::new(handler));
+        self
+    }
+
+    /// Most messages held at once
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of messages waiting
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Whether no messages are waiting
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    // Keeps `message` behind the others, dropping the oldest if full
+    pub(crate) fn push(&mut self, message: ClientMessage) {
+        let mut dropped = false;
+        while self.messages.len() >= self.capacity {
+            let Some(oldest) = self.messages.pop_front() else {
+                break;
+            };
+            warn!("Spool full; dropping its oldest message");
+            if let Some(on_drop) = self.on_drop.as_mut() {
+                on_drop(oldest);
+            }
+            dropped = true;
+        }
+        if dropped {
+            self.messages.push_back(message);
+            self.save();
+        } else {
+            self.append(&message);
+            self.messages.push_back(message);
+        }
+    }
+
+    pub(crate) fn front(&self) -> Option<&ClientMessage> {
+        self.messages.front()
+    }
+
+    // Removes the oldest message once sent; `save` brings the file up to date
+    pub(crate) fn pop_front(&mut self) -> Option<ClientMessage> {
+        self.messages.pop_front()
+    }
+
+    // Adds one message to the end of the file
+    fn append(&self, message: &ClientMessage) {
+        let Some(path) = &self.file else {
+            return;
+        };
+        let result = codec::encode(message)
+            .map_err(io::Error::from)
+            .and_then(|frame| {
+                let mut file = fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)?;
+                file.write_all(&frame)
+            });
+        if let Err(e) = result {
+            warn!(
+                "Failed to write to the spool file {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+
+    // Rewrites the file with the messages now waiting
+    pub(crate) fn save(&self) {
+        let Some(path) = &self.file else {
+            return;
+        };
+        let mut bytes = Vec::new();
+        for message in &self.messages {
+            match codec::encode(message) {
+                Ok(frame) => bytes.extend_from_slice(&frame),
+                Err(e) => warn!("Failed to encode a spooled message: {}", e),
+            }
+        }
+        if let Err(e) = fs::write(path, bytes) {
+            warn!("Failed to write the spool file {}: {}", path.display(), e);
+        }
+    }
+}
+
+impl Default for Spool {
+    fn default() -> Self {
+        Self::new(DEFAULT_SPOOL_CAPACITY)
+    }
+}
+
+impl fmt::Debug for Spool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Spool")
+            .field("len", &self.messages.len())
+            .field("capacity", &self.capacity)
+            .field("file", &self.file)
+            .finish()
+    }
+}
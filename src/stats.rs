@@ -0,0 +1,553 @@
+//! Server statistics.
+//!
+//! Connection handlers update atomic counters that are split into stripes, one
+//! per thread up to [`STRIPES`], each on its own cache lines: counting a request
+//! never takes a lock and threads on different cores do not fight over the same
+//! line. [`Server::stats`] adds the stripes up into a [`Stats`] snapshot.
+//!
+//! A metrics exporter scraping at intervals wants rates rather than totals:
+//! [`Server::snapshot`] returns the stats with the time they were taken as a
+//! [`StatsSnapshot`], and [`StatsSnapshot::since`] an earlier one gives what
+//! happened in between as a [`StatsDelta`].
+//!
+//! Handler latency is kept per message type in HDR-style histograms: values
+//! are grouped by powers of two, each split into 16 linear sub-buckets, which
+//! bounds the error of any percentile to about 6% from 1 µs up to many hours
+//! while using a fixed, small amount of memory.
+//!
+//! [`Server::stats`]: crate::server::Server::stats
+//! [`Server::snapshot`]: crate::server::Server::snapshot
+
+use crate::availability::Availability;
+use crate::handler::MessageKind;
+use crate::outbox::Dropped;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Stripes the hot counters are split into; threads beyond this share them
+pub const STRIPES: usize = 16;
+
+const SUB_BUCKET_BITS: u32 = 4;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+const MAX_MICROS: u64 = (1 << 36) - 1; // About 19 hours; longer values are clamped
+const BUCKETS: usize = (36 - SUB_BUCKET_BITS as usize + 1) * SUB_BUCKETS;
+
+/// Point-in-time copy of the server's counters
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Requests handled since the server started
+    pub requests: u64,
+    /// Requests that took at least the slow-request threshold
+    pub slow_requests: u64,
+    /// Retried requests answered from the dedup window without being handled
+    pub duplicates: u64,
+    /// Sessions resumed by a reconnecting device
+    pub resumed_sessions: u64,
+    /// Messages for devices dropped for waiting longer than their or the queue's time to live
+    pub expired_messages: u64,
+    /// Messages for devices dropped to make room in a full queue
+    pub overflowed_messages: u64,
+    /// Times the watchdog found the server stuck
+    pub watchdog_trips: u64,
+    /// New connections refused as busy by the overload policy
+    pub shed_connections: u64,
+    /// Requests refused as busy by the overload policy
+    pub shed_requests: u64,
+    /// Requests refused by the authorizer
+    pub denied_requests: u64,
+    /// Requests refused for going over the device's daily quota
+    pub quota_refusals: u64,
+    /// Connections closed for buffering more than the connection memory limit
+    pub memory_disconnects: u64,
+    /// Connections closed for not sending their first frame, or the rest of a frame, in time
+    pub slow_connections: u64,
+    /// Requests answered with a timeout because their handler overran the handler timeout
+    pub handler_timeouts: u64,
+    /// Handlers still running after their request was answered with a timeout
+    pub abandoned_handlers: u64,
+    /// Bytes held in the buffers of all open connections
+    pub buffered_bytes: u64,
+    /// Broadcast frames waiting in the mailboxes of all open connections
+    pub mailbox_frames: u64,
+    /// Frames waiting in the fullest mailbox
+    pub deepest_mailbox: u64,
+    /// Broadcast frames dropped from full mailboxes; see [`crate::mailbox`]
+    pub mailbox_drops: u64,
+    /// Connections closed for letting their mailbox overflow
+    pub slow_consumer_disconnects: u64,
+    /// Handler latency for each message type that has been handled
+    pub latency: HashMap<MessageKind, Histogram>,
+    /// Load on the threads serving connections
+    pub pool: PoolStats,
+    /// Activity of each registered tenant's devices
+    pub tenants: HashMap<String, TenantStats>,
+    /// Uptime, stalls and overload since the server started
+    pub availability: Availability,
+}
+
+/// Activity of one tenant's devices; see [`crate::tenant`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TenantStats {
+    /// Connections of its devices open now
+    pub connections: u64,
+    /// Requests its devices sent that were handled
+    pub requests: u64,
+    /// Bytes of those requests
+    pub request_bytes: u64,
+    /// Requests of its devices refused by the authorizer
+    pub denied_requests: u64,
+    /// Requests of its devices refused for going over a quota
+    pub quota_refusals: u64,
+}
+
+impl Stats {
+    /// What was counted since `earlier`: counters are subtracted, while gauges
+    /// (`abandoned_handlers`, `buffered_bytes`, `mailbox_frames`,
+    /// `deepest_mailbox`, pool `workers`, `active` and `queued`, tenant
+    /// `connections`) and `availability` keep their current value
+    pub fn since(&self, earlier: &Stats) -> Stats {
+        let latency = self
+            .latency
+            .iter()
+            .map(|(kind, histogram)| match earlier.latency.get(kind) {
+                Some(before) => (*kind, histogram.since(before)),
+                None => (*kind, histogram.clone()),
+            })
+            .filter(|(_, histogram)| histogram.count > 0)
+            .collect();
+        let tenants = self
+            .tenants
+            .iter()
+            .map(|(name, tenant)| {
+                let before = earlier.tenants.get(name).cloned().unwrap_or_default();
+                let delta = TenantStats {
+                    connections: tenant.connections,
+                    requests: tenant.requests.saturating_sub(before.requests),
+                    request_bytes: tenant.request_bytes.saturating_sub(before.request_bytes),
+                    denied_requests: tenant
+                        .denied_requests
+                        .saturating_sub(before.denied_requests),
+                    quota_refusals: tenant.quota_refusals.saturating_sub(before.quota_refusals),
+                };
+                (name.clone(), delta)
+            })
+            .collect();
+        Stats {
+            requests: self.requests.saturating_sub(earlier.requests),
+            slow_requests: self.slow_requests.saturating_sub(earlier.slow_requests),
+            duplicates: self.duplicates.saturating_sub(earlier.duplicates),
+            resumed_sessions: self
+                .resumed_sessions
+                .saturating_sub(earlier.resumed_sessions),
+            expired_messages: self
+                .expired_messages
+                .saturating_sub(earlier.expired_messages),
+            overflowed_messages: self
+                .overflowed_messages
+                .saturating_sub(earlier.overflowed_messages),
+            watchdog_trips: self.watchdog_trips.saturating_sub(earlier.watchdog_trips),
+            shed_connections: self
+                .shed_connections
+                .saturating_sub(earlier.shed_connections),
+            shed_requests: self.shed_requests.saturating_sub(earlier.shed_requests),
+            denied_requests: self.denied_requests.saturating_sub(earlier.denied_requests),
+            quota_refusals: self.quota_refusals.saturating_sub(earlier.quota_refusals),
+            memory_disconnects: self
+                .memory_disconnects
+                .saturating_sub(earlier.memory_disconnects),
+            slow_connections: self
+                .slow_connections
+                .saturating_sub(earlier.slow_connections),
+            handler_timeouts: self
+                .handler_timeouts
+                .saturating_sub(earlier.handler_timeouts),
+            abandoned_handlers: self.abandoned_handlers,
+            buffered_bytes: self.buffered_bytes,
+            mailbox_frames: self.mailbox_frames,
+            deepest_mailbox: self.deepest_mailbox,
+            mailbox_drops: self.mailbox_drops.saturating_sub(earlier.mailbox_drops),
+            slow_consumer_disconnects: self
+                .slow_consumer_disconnects
+                .saturating_sub(earlier.slow_consumer_disconnects),
+            latency,
+            pool: PoolStats {
+                workers: self.pool.workers,
+                active: self.pool.active,
+                queued: self.pool.queued,
+                completed: self.pool.completed.saturating_sub(earlier.pool.completed),
+                panicked: self.pool.panicked.saturating_sub(earlier.pool.panicked),
+            },
+            tenants,
+            availability: self.availability.clone(),
+        }
+    }
+}
+
+/// [`Stats`] with the time they were taken, so that two can be compared
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatsSnapshot {
+    /// When the counters were read
+    pub taken: Instant,
+    /// The counters
+    pub stats: Stats,
+}
+
+impl StatsSnapshot {
+    /// What happened between `earlier` and this snapshot
+    pub fn since(&self, earlier: &StatsSnapshot) -> StatsDelta {
+        StatsDelta {
+            elapsed: self.taken.saturating_duration_since(earlier.taken),
+            stats: self.stats.since(&earlier.stats),
+        }
+    }
+}
+
+/// Counts between two [`StatsSnapshot`]s and the time between them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatsDelta {
+    /// Time between the snapshots
+    pub elapsed: Duration,
+    /// What was counted in that time; see [`Stats::since`]
+    pub stats: Stats,
+}
+
+impl StatsDelta {
+    /// Average rate per second of the count `counter` picks, e.g.
+    /// `delta.per_second(|stats| stats.requests)`; zero over no time
+    pub fn per_second(&self, counter: impl Fn(&Stats) -> u64) -> f64 {
+        match self.elapsed.is_zero() {
+            true => 0.0,
+            false => counter(&self.stats) as f64 / self.elapsed.as_secs_f64(),
+        }
+    }
+}
+
+/// Worker load. Open connections take turns on the workers, so `active` may be
+/// far above `workers`; a growing `queued` means the workers cannot keep up.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Threads serving connections
+    pub workers: usize,
+    /// Connections being served
+    pub active: u64,
+    /// Accepted connections waiting for their first turn on a worker
+    pub queued: u64,
+    /// Connections handled to the end
+    pub completed: u64,
+    /// Connection handlers that panicked
+    pub panicked: u64,
+}
+
+/// Snapshot of a latency histogram
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    counts: Vec<u64>, // Per bucket, see `bucket`
+    count: u64,
+    sum_micros: u64,
+    max_micros: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            counts: vec![0; BUCKETS],
+            count: 0,
+            sum_micros: 0,
+            max_micros: 0,
+        }
+    }
+}
+
+impl Histogram {
+    /// Values recorded since `earlier`, a snapshot of the same histogram. The
+    /// largest value cannot be split up, so [`max`](Histogram::max) stays the
+    /// largest since the start, capped by the remaining percentiles.
+    pub fn since(&self, earlier: &Histogram) -> Histogram {
+        Histogram {
+            counts: (self.counts.iter())
+                .zip(&earlier.counts)
+                .map(|(now, before)| now.saturating_sub(*before))
+                .collect(),
+            count: self.count.saturating_sub(earlier.count),
+            sum_micros: self.sum_micros.saturating_sub(earlier.sum_micros),
+            max_micros: self.max_micros,
+        }
+    }
+
+    /// Number of recorded values
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Largest recorded value
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.max_micros)
+    }
+
+    /// Average of the recorded values
+    pub fn mean(&self) -> Duration {
+        Duration::from_micros(self.sum_micros.checked_div(self.count).unwrap_or(0))
+    }
+
+    /// Value below which `percentile` percent of the recorded values fall, e.g. `percentile(99.0)`
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        let rank = ((percentile / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_micros(bucket_upper(bucket).min(self.max_micros));
+            }
+        }
+        self.max()
+    }
+}
+
+// Live counters shared by all connection handlers, striped by thread
+#[derive(Debug)]
+pub(crate) struct Counters {
+    stripes: Box<[Stripe]>,
+    pub(crate) pool: PoolCounters, // Read on every request, so kept whole
+}
+
+// One thread's share of the hot counters; aligned so that no two stripes share a cache line
+#[derive(Debug, Default)]
+#[repr(align(128))]
+pub(crate) struct Stripe {
+    pub(crate) requests: AtomicU64,
+    pub(crate) slow_requests: AtomicU64,
+    pub(crate) duplicates: AtomicU64,
+    pub(crate) resumed_sessions: AtomicU64,
+    pub(crate) expired_messages: AtomicU64,
+    pub(crate) overflowed_messages: AtomicU64,
+    pub(crate) watchdog_trips: AtomicU64,
+    pub(crate) shed_connections: AtomicU64,
+    pub(crate) shed_requests: AtomicU64,
+    pub(crate) denied_requests: AtomicU64,
+    pub(crate) quota_refusals: AtomicU64,
+    pub(crate) memory_disconnects: AtomicU64,
+    pub(crate) slow_connections: AtomicU64,
+    pub(crate) handler_timeouts: AtomicU64,
+    pub(crate) mailbox_drops: AtomicU64,
+    pub(crate) slow_consumer_disconnects: AtomicU64,
+    latency: [AtomicHistogram; MessageKind::ALL.len()],
+}
+
+// Stripe of the calling thread, handed out to threads in turn
+fn stripe_index() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    thread_local! {
+        static STRIPE: usize = NEXT.fetch_add(1, Ordering::Relaxed) % STRIPES;
+    }
+    STRIPE.with(|stripe| *stripe)
+}
+
+// Live counters of one tenant, shared by the connections of its devices
+#[derive(Debug, Default)]
+pub(crate) struct TenantCounters {
+    pub(crate) connections: AtomicU64,
+    pub(crate) requests: AtomicU64,
+    pub(crate) request_bytes: AtomicU64,
+    pub(crate) denied_requests: AtomicU64,
+    pub(crate) quota_refusals: AtomicU64,
+}
+
+impl TenantCounters {
+    pub(crate) fn snapshot(&self) -> TenantStats {
+        TenantStats {
+            connections: self.connections.load(Ordering::Relaxed),
+            requests: self.requests.load(Ordering::Relaxed),
+            request_bytes: self.request_bytes.load(Ordering::Relaxed),
+            denied_requests: self.denied_requests.load(Ordering::Relaxed),
+            quota_refusals: self.quota_refusals.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// Live worker counters; `workers` is filled in by the server
+#[derive(Debug, Default)]
+pub(crate) struct PoolCounters {
+    active: AtomicU64,
+    queued: AtomicU64,
+    completed: AtomicU64,
+    panicked: AtomicU64,
+}
+
+impl PoolCounters {
+    // A connection was queued on a shard
+    pub(crate) fn queued(&self) {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // A worker gave the connection its first turn
+    pub(crate) fn started(&self) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        self.active.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // The connection closed, or its handler unwound
+    pub(crate) fn finished(&self, panicked: bool) {
+        self.active.fetch_sub(1, Ordering::Relaxed);
+        match panicked {
+            true => self.panicked.fetch_add(1, Ordering::Relaxed),
+            false => self.completed.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    // Connections waiting for a worker
+    pub(crate) fn queued_now(&self) -> u64 {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    fn snapshot(&self) -> PoolStats {
+        PoolStats {
+            workers: 0,
+            active: self.active.load(Ordering::Relaxed),
+            queued: self.queued.load(Ordering::Relaxed),
+            completed: self.completed.load(Ordering::Relaxed),
+            panicked: self.panicked.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Counters {
+            stripes: (0..STRIPES).map(|_| Stripe::default()).collect(),
+            pool: PoolCounters::default(),
+        }
+    }
+}
+
+impl Counters {
+    // The calling thread's stripe, to count in
+    pub(crate) fn local(&self) -> &Stripe {
+        &self.stripes[stripe_index()]
+    }
+
+    pub(crate) fn record_latency(&self, kind: MessageKind, elapsed: Duration) {
+        self.local().latency[kind as usize].record(elapsed);
+    }
+
+    // Counts messages for devices that were dropped instead of delivered
+    pub(crate) fn dropped(&self, dropped: Dropped) {
+        let stripe = self.local();
+        stripe
+            .expired_messages
+            .fetch_add(dropped.expired as u64, Ordering::Relaxed);
+        stripe
+            .overflowed_messages
+            .fetch_add(dropped.overflowed as u64, Ordering::Relaxed);
+    }
+
+    // Adds up the stripes
+    pub(crate) fn snapshot(&self) -> Stats {
+        let sum = |counter: fn(&Stripe) -> &AtomicU64| -> u64 {
+            self.stripes
+                .iter()
+                .map(|stripe| counter(stripe).load(Ordering::Relaxed))
+                .sum()
+        };
+        Stats {
+            requests: sum(|s| &s.requests),
+            slow_requests: sum(|s| &s.slow_requests),
+            duplicates: sum(|s| &s.duplicates),
+            resumed_sessions: sum(|s| &s.resumed_sessions),
+            expired_messages: sum(|s| &s.expired_messages),
+            overflowed_messages: sum(|s| &s.overflowed_messages),
+            watchdog_trips: sum(|s| &s.watchdog_trips),
+            shed_connections: sum(|s| &s.shed_connections),
+            shed_requests: sum(|s| &s.shed_requests),
+            denied_requests: sum(|s| &s.denied_requests),
+            quota_refusals: sum(|s| &s.quota_refusals),
+            memory_disconnects: sum(|s| &s.memory_disconnects),
+            slow_connections: sum(|s| &s.slow_connections),
+            handler_timeouts: sum(|s| &s.handler_timeouts),
+            abandoned_handlers: 0, // Filled in by the server from its handler threads
+            buffered_bytes: 0,     // Filled in by the server from the open connections
+            mailbox_frames: 0,     // Likewise
+            deepest_mailbox: 0,
+            mailbox_drops: sum(|s| &s.mailbox_drops),
+            slow_consumer_disconnects: sum(|s| &s.slow_consumer_disconnects),
+            latency: MessageKind::ALL
+                .iter()
+                .map(|&kind| {
+                    let mut histogram = Histogram::default();
+                    for stripe in self.stripes.iter() {
+                        stripe.latency[kind as usize].add_to(&mut histogram);
+                    }
+                    (kind, histogram)
+                })
+                .filter(|(_, histogram)| histogram.count > 0)
+                .collect(),
+            pool: self.pool.snapshot(),
+            tenants: HashMap::new(), // Filled in by the server from its tenants
+            availability: Availability::default(), // And from its tracker
+        }
+    }
+}
+
+// Histogram updated concurrently without locks
+#[derive(Debug)]
+struct AtomicHistogram {
+    counts: Box<[AtomicU64]>,
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+    max_micros: AtomicU64,
+}
+
+impl Default for AtomicHistogram {
+    fn default() -> Self {
+        AtomicHistogram {
+            counts: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+            max_micros: AtomicU64::new(0),
+        }
+    }
+}
+
+impl AtomicHistogram {
+    fn record(&self, elapsed: Duration) {
+        let micros = (elapsed.as_micros() as u64).min(MAX_MICROS);
+        self.counts[bucket(micros)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    // Adds this stripe's values to `histogram`
+    fn add_to(&self, histogram: &mut Histogram) {
+        for (total, count) in histogram.counts.iter_mut().zip(self.counts.iter()) {
+            *total += count.load(Ordering::Relaxed);
+        }
+        histogram.count += self.count.load(Ordering::Relaxed);
+        histogram.sum_micros += self.sum_micros.load(Ordering::Relaxed);
+        histogram.max_micros = histogram
+            .max_micros
+            .max(self.max_micros.load(Ordering::Relaxed));
+    }
+}
+
+// Values below 16 get a bucket each; above that, each power of two is split into 16 buckets
+fn bucket(micros: u64) -> usize {
+    if micros < SUB_BUCKETS as u64 {
+        return micros as usize;
+    }
+    let magnitude = 63 - micros.leading_zeros(); // Position of the highest set bit, at least 4
+    let sub_bucket = (micros >> (magnitude - SUB_BUCKET_BITS)) as usize & (SUB_BUCKETS - 1);
+    (magnitude - SUB_BUCKET_BITS + 1) as usize * SUB_BUCKETS + sub_bucket
+}
+
+// Largest value that falls into `bucket`
+fn bucket_upper(bucket: usize) -> u64 {
+    if bucket < SUB_BUCKETS {
+        return bucket as u64;
+    }
+    let shift = (bucket / SUB_BUCKETS - 1) as u32;
+    let lower = ((SUB_BUCKETS + bucket % SUB_BUCKETS) as u64) << shift;
+    lower + (1 << shift) - 1
+}
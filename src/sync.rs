@@ -0,0 +1,23 @@
+//! Synchronisation types, from `loom` when built with `--cfg loom`.
+//!
+//! The shutdown flag, the connection registry and the mailboxes take their
+//! locks, condition variables and atomics from here, so `tests/loom_test.rs`
+//! can run them under loom's scheduler, which tries every interleaving of
+//! their threads. Everything else keeps using `std::sync` directly. Only the
+//! locks are used outside the server.
+
+#[cfg(all(loom, feature = "server"))]
+pub(crate) use loom::sync::{
+    atomic::{AtomicBool, Ordering},
+    Condvar,
+};
+#[cfg(loom)]
+pub(crate) use loom::sync::{Mutex, MutexGuard};
+
+#[cfg(all(not(loom), feature = "server"))]
+pub(crate) use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Condvar,
+};
+#[cfg(not(loom))]
+pub(crate) use std::sync::{Mutex, MutexGuard};
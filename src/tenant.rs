@@ -0,0 +1,141 @@
+//! Tenants, so one server can serve several customers.
+//!
+//! A tenant is registered with
+//! [`Server::add_tenant`](crate::server::Server::add_tenant). Its devices name
+//! themselves `{tenant}/{device}` in the `device_id` of their `ResumeRequest`,
+//! so `acme/thermo-1` is device `thermo-1` of tenant `acme`. The prefix of an
+//! ID is only a tenant once one of that name is registered; until then it is
+//! part of the device's name.
+//!
+//! [`Server::tenant`](crate::server::Server::tenant) hands out a [`Tenant`],
+//! the server as one customer sees it. Device names given to it and reported by
+//! it are the tenant's own, without the prefix, so code acting for a customer
+//! cannot reach or list another customer's devices. A tenant has a
+//! [`Quota`] of its own, counting the requests and bytes of all its devices
+//! together, and its activity is reported in
+//! [`Stats::tenants`](crate::stats::Stats::tenants).
+//!
+//! Topics are scoped the same way with [`Tenant::topic`]. The server's broker
+//! keeps each tenant's topics apart itself: its devices publish and subscribe
+//! within its namespace, which no other device's filters reach.
+
+use crate::deadletter::DeadLetter;
+use crate::message::QuotaStatus;
+use crate::quota::Quota;
+use crate::server::Server;
+use crate::stats::TenantStats;
+use prost::bytes::Bytes;
+use std::{collections::HashMap, time::Duration};
+
+/// Separates a tenant's name from the rest of a device ID or topic
+pub const TENANT_SEPARATOR: char = '/';
+
+/// Splits `identity` into the tenant it names and the rest; no tenant if it
+/// has no separator or nothing before it
+pub fn split(identity: &str) -> (Option<&str>, &str) {
+    match identity.split_once(TENANT_SEPARATOR) {
+        Some((tenant, rest)) if !tenant.is_empty() => (Some(tenant), rest),
+        _ => (None, identity),
+    }
+}
+
+/// `name` in `tenant`'s namespace
+pub fn scope(tenant: &str, name: &str) -> String {
+    format!("{}{}{}", tenant, TENANT_SEPARATOR, name)
+}
+
+// `name` without `tenant`'s prefix; `None` if it is in another namespace
+fn unscope<'a>(tenant: &str, name: &'a str) -> Option<&'a str> {
+    match split(name) {
+        (Some(owner), rest) if owner == tenant => Some(rest),
+        _ => None,
+    }
+}
+
+/// The server as one tenant sees it
+pub struct Tenant<'a> {
+    server: &'a Server,
+    name: String,
+}
+
+impl<'a> Tenant<'a> {
+    pub(crate) fn new(server: &'a Server, name: &str) -> Self {
+        Tenant {
+            server,
+            name: name.to_string(),
+        }
+    }
+
+    /// Name of the tenant
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// ID the tenant's `device` names itself with
+    pub fn device_id(&self, device: &str) -> String {
+        scope(&self.name, device)
+    }
+
+    /// `topic` in the tenant's namespace
+    pub fn topic(&self, topic: &str) -> String {
+        scope(&self.name, topic)
+    }
+
+    /// Queues `payload` for the tenant's `device`; see [`Server::send_to`]
+    pub fn send_to(&self, device: &str, payload: impl Into<Bytes>) -> u64 {
+        self.server.send_to(&self.device_id(device), payload)
+    }
+
+    /// Queues `payload` for the tenant's `device` until `ttl` passes; see
+    /// [`Server::send_to_with_ttl`]
+    pub fn send_to_with_ttl(&self, device: &str, payload: impl Into<Bytes>, ttl: Duration) -> u64 {
+        self.server
+            .send_to_with_ttl(&self.device_id(device), payload, ttl)
+    }
+
+    /// Messages waiting for the tenant's `device`
+    pub fn queue_depth(&self, device: &str) -> usize {
+        self.server.queue_depth(&self.device_id(device))
+    }
+
+    /// Messages waiting for each of the tenant's devices with any
+    pub fn queue_depths(&self) -> HashMap<String, usize> {
+        self.server
+            .queue_depths()
+            .into_iter()
+            .filter_map(|(id, depth)| Some((unscope(&self.name, &id)?.to_string(), depth)))
+            .collect()
+    }
+
+    /// The messages lately dropped from the tenant's device queues
+    pub fn dead_letters(&self) -> Vec<DeadLetter> {
+        self.server
+            .dead_letters()
+            .into_iter()
+            .filter_map(|mut letter| {
+                letter.device = unscope(&self.name, &letter.device)?.to_string();
+                Some(letter)
+            })
+            .collect()
+    }
+
+    /// Applies `quota` to the tenant's `device`, or the default quota again if `None`
+    pub fn set_device_quota(&self, device: &str, quota: Option<Quota>) {
+        self.server.set_quota(&self.device_id(device), quota);
+    }
+
+    /// Usage and limits of the quota of the tenant's `device`
+    pub fn device_quota_status(&self, device: &str) -> QuotaStatus {
+        self.server.quota_status(&self.device_id(device))
+    }
+
+    /// Usage and limits of the tenant's own quota, over all its devices
+    pub fn quota_status(&self) -> QuotaStatus {
+        self.server.tenant_quota_status(&self.name)
+    }
+
+    /// Activity of the tenant's devices
+    pub fn stats(&self) -> TenantStats {
+        self.server.tenant_stats(&self.name)
+    }
+}
@@ -0,0 +1,327 @@
+//! Test doubles for applications built on the client.
+//!
+//! Only built with the `testing` feature, which belongs in an application's
+//! `[dev-dependencies]`. [`MockServer`] listens on an ephemeral port and
+//! speaks the framing, batches and flow control of the real server, but none
+//! of its request handling: it records every request it receives, each request
+//! of a batch on its own, and answers with the [`Reply`]s a test scripts. Replies can carry a delay, an
+//! `ErrorResponse`, no answer at all, or the closing of the connection, so
+//! timeouts and refusals can be tested without a misbehaving real server.
+//!
+//! Scripted replies answer requests in the order they arrive, whichever
+//! connection they come from. Requests beyond the script are answered by the
+//! function given to [`MockServer::respond_with`], which by default refuses
+//! them as unsupported. [`MockServer::push`] sends a message unasked to every
+//! open connection.
+//!
+//! [`fixtures`] builds messages for tests and draws them for property tests.
+
+pub mod fixtures;
+
+use crate::batch;
+use crate::flow::{window_update, ReceiveWindows, FLOW_CONTROL_VERSION};
+use crate::message::{
+    client_message, error_response, server_message, ClientMessage, ErrorResponse, ServerMessage,
+};
+use crate::protocol::{Event, ServerProtocol};
+use log::{info, warn};
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How the mock server answers one request
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reply {
+    message: Option<server_message::Message>, // Nothing is sent when `None`
+    delay: Duration,
+    close: bool, // Close the connection instead of answering
+}
+
+impl Reply {
+    /// Answers with `message`, tagged with the request's message and stream IDs
+    pub fn with(message: server_message::Message) -> Self {
+        Reply {
+            message: Some(message),
+            delay: Duration::ZERO,
+            close: false,
+        }
+    }
+
+    /// Refuses the request with an `ErrorResponse`
+    pub fn error(code: error_response::Code, detail: &str) -> Self {
+        Self::with(server_message::Message::ErrorResponse(ErrorResponse {
+            code: code as i32,
+            detail: detail.to_string(),
+            ..Default::default()
+        }))
+    }
+
+    /// Leaves the request unanswered, as if the response were lost
+    pub fn silence() -> Self {
+        Reply {
+            message: None,
+            delay: Duration::ZERO,
+            close: false,
+        }
+    }
+
+    /// Closes the connection without answering
+    pub fn close() -> Self {
+        Reply {
+            close: true,
+            ..Self::silence()
+        }
+    }
+
+    /// Waits `delay` before answering; later requests on the same connection wait too
+    pub fn after(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+}
+
+type Responder = Box<dyn FnMut(&client_message::Message) -> Reply + Send>;
+
+/// A server that records requests and answers them from a script
+pub struct MockServer {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    accepter: Option<thread::JoinHandle<()>>,
+}
+
+struct Shared {
+    script: Mutex<VecDeque<Reply>>,
+    responder: Mutex<Responder>,
+    received: Mutex<Vec<ClientMessage>>,
+    arrived: Condvar, // Signalled as requests are recorded
+    connections: Mutex<Vec<Arc<Mutex<TcpStream>>>>, // Writers of the open connections
+    stopped: AtomicBool,
+}
+
+impl MockServer {
+    /// Starts a mock server on an ephemeral port of the loopback interface
+    pub fn start() -> io::Result<Self> {
+        Self::bind("127.0.0.1:0")
+    }
+
+    /// Starts a mock server on `addr`
+    pub fn bind(addr: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            script: Mutex::new(VecDeque::new()),
+            responder: Mutex::new(Box::new(|_| {
+                Reply::error(error_response::Code::Unsupported, "no scripted reply")
+            })),
+            received: Mutex::new(Vec::new()),
+            arrived: Condvar::new(),
+            connections: Mutex::new(Vec::new()),
+            stopped: AtomicBool::new(false),
+        });
+        let accepter = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || accept(listener, shared))
+        };
+        info!("Mock server listening on {}", addr);
+        Ok(MockServer {
+            addr,
+            shared,
+            accepter: Some(accepter),
+        })
+    }
+
+    /// Address the server listens on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Port the server listens on, for `Client::new`
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// Answers the next request not yet answered with `reply`
+    pub fn reply(&self, reply: Reply) {
+        self.shared.script.lock().unwrap().push_back(reply);
+    }
+
+    /// Answers requests beyond the scripted replies with `responder`
+    pub fn respond_with(
+        &self,
+        responder: impl FnMut(&client_message::Message) -> Reply + Send + 'static,
+    ) {
+        *self.shared.responder.lock().unwrap() = Box::new(responder);
+    }
+
+    /// Sends `message` unasked to every open connection, returning how many it reached
+    pub fn push(&self, message: server_message::Message) -> usize {
+        let message = ServerMessage {
+            message: Some(message),
+            ..Default::default()
+        };
+        let mut protocol = ServerProtocol::new();
+        if let Err(e) = protocol.send(&message) {
+            warn!("Failed to encode push: {}", e);
+            return 0;
+        }
+        let frame = protocol.poll_transmit().unwrap_or_default();
+        let connections = self.shared.connections.lock().unwrap();
+        connections
+            .iter()
+            .filter(|writer| writer.lock().unwrap().write_all(&frame).is_ok())
+            .count()
+    }
+
+    /// Requests received so far, in order of arrival
+    pub fn received(&self) -> Vec<ClientMessage> {
+        self.shared.received.lock().unwrap().clone()
+    }
+
+    /// Waits until at least `count` requests have arrived or `timeout` has passed,
+    /// returning those received by then
+    pub fn wait_for(&self, count: usize, timeout: Duration) -> Vec<ClientMessage> {
+        let deadline = Instant::now() + timeout;
+        let mut received = self.shared.received.lock().unwrap();
+        while received.len() < count {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            received = self.shared.arrived.wait_timeout(received, left).unwrap().0;
+        }
+        received.clone()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::SeqCst);
+        let _ = TcpStream::connect(self.addr); // Wakes the accepting thread
+        for writer in self.shared.connections.lock().unwrap().iter() {
+            let _ = writer.lock().unwrap().shutdown(Shutdown::Both);
+        }
+        if let Some(accepter) = self.accepter.take() {
+            let _ = accepter.join();
+        }
+    }
+}
+
+impl fmt::Debug for MockServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockServer")
+            .field("addr", &self.addr)
+            .field("received", &self.shared.received.lock().unwrap().len())
+            .finish()
+    }
+}
+
+fn accept(listener: TcpListener, shared: Arc<Shared>) {
+    for stream in listener.incoming() {
+        if shared.stopped.load(Ordering::SeqCst) {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Mock server failed to accept: {}", e);
+                continue;
+            }
+        };
+        let writer = match stream.try_clone() {
+            Ok(writer) => Arc::new(Mutex::new(writer)),
+            Err(e) => {
+                warn!("Mock server failed to clone a connection: {}", e);
+                continue;
+            }
+        };
+        shared.connections.lock().unwrap().push(Arc::clone(&writer));
+        let shared = Arc::clone(&shared);
+        thread::spawn(move || {
+            if let Err(e) = serve(stream, &writer, &shared) {
+                info!("Mock server connection ended: {}", e);
+            }
+            let mut connections = shared.connections.lock().unwrap();
+            connections.retain(|open| !Arc::ptr_eq(open, &writer));
+        });
+    }
+}
+
+// Records and answers the requests of one connection until it closes
+fn serve(mut stream: TcpStream, writer: &Mutex<TcpStream>, shared: &Shared) -> io::Result<()> {
+    let mut protocol = ServerProtocol::new();
+    let mut windows: Option<ReceiveWindows> = None; // Once the client states it has credits
+    let mut buffer = [0u8; 1024];
+    loop {
+        let read = stream.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(());
+        }
+        for event in protocol.feed_bytes(&buffer[..read]) {
+            let requests = match event {
+                Event::Message(request) => batch::unpack(request),
+                Event::Error(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+                Event::Closed => return Ok(()),
+            };
+            for request in requests {
+                if request.protocol_version >= FLOW_CONTROL_VERSION {
+                    windows.get_or_insert_with(ReceiveWindows::new);
+                }
+                if let Some(windows) = windows.as_mut() {
+                    windows
+                        .received(request.stream_id)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                }
+                let reply = shared.reply_to(&request);
+                thread::sleep(reply.delay);
+                if reply.close {
+                    return stream.shutdown(Shutdown::Both);
+                }
+                if let Some(message) = reply.message {
+                    let response = ServerMessage {
+                        message: Some(message),
+                        message_id: request.message_id,
+                        stream_id: request.stream_id,
+                    };
+                    protocol.send(&response).map_err(io::Error::from)?;
+                }
+                if let Some(windows) = windows.as_mut() {
+                    windows.completed(request.stream_id);
+                }
+                while let Some((stream, credits)) =
+                    windows.as_mut().and_then(ReceiveWindows::poll_grant)
+                {
+                    protocol
+                        .send(&window_update(stream, credits))
+                        .map_err(io::Error::from)?;
+                }
+                while let Some(frame) = protocol.poll_transmit() {
+                    writer.lock().unwrap().write_all(&frame)?;
+                }
+            }
+        }
+    }
+}
+
+impl Shared {
+    // Records `request` and picks its reply
+    fn reply_to(&self, request: &ClientMessage) -> Reply {
+        self.received.lock().unwrap().push(request.clone());
+        self.arrived.notify_all();
+        if let Some(reply) = self.script.lock().unwrap().pop_front() {
+            return reply;
+        }
+        match &request.message {
+            Some(message) => (self.responder.lock().unwrap())(message),
+            None => Reply::silence(),
+        }
+    }
+}
@@ -0,0 +1,712 @@
+//! Builders and proptest strategies for the protobuf messages.
+//!
+//! The functions named after requests, such as [`echo`] and [`add`], build the
+//! `oneof` variant a test would otherwise spell out field by field, and
+//! [`request`] and [`response`] wrap one in its envelope with a message ID
+//! and stream:
+//!
+//! ```
+//! use embedded_recruitment_task::testing::fixtures::{add, request};
+//!
+//! let message = request(add(2, 3)).id(7).stream(1).build();
+//! assert_eq!(message.message_id, 7);
+//! ```
+//!
+//! Every message type also implements proptest's [`Arbitrary`], so property
+//! tests can draw them with `any::<ClientMessage>()`. Integers favour their
+//! boundary values (zero, one, the minimum and the maximum), strings range
+//! from empty to several hundred characters of any script, and floats stay
+//! finite so that round trips compare equal. Enum fields are mostly valid
+//! values, sometimes unknown ones.
+
+use crate::message::{
+    client_message, close, cluster_event, dead_letter_record, diagnostic_check, fault_rule,
+    go_away, log_event, server_message, transform_request, AddRequest, AddResponse,
+    AvailabilityReport, AvailabilityRequest, Batch, CalcRequest, CalcResponse, ClientMessage,
+    Close, ClusterAck, ClusterDevice, ClusterEvent, ClusterInterest, ClusterPublish, ClusterSend,
+    ClusterSync, ConnectionHistoryRequest, ConnectionHistoryResponse, ConnectionRecord,
+    DeadLetterRecord, DeadLettersRequest, DeadLettersResponse, Delivery, DescribeRequest,
+    DescribeResponse, DiagnosticCheck, DiagnosticsReport, DiagnosticsRequest, EchoBytes,
+    EchoMessage, ErrorResponse, FaultRule, FaultRulesRequest, FaultRulesResponse, GoAway, LogEvent,
+    LogField, PingRequest, PingResponse, Publication, PublishRequest, PublishResponse,
+    QuotaRequest, QuotaStatus, RandomRequest, RandomResponse, ResumeRequest, ResumeResponse,
+    ResyncRequest, ResyncResponse, ServerMessage, SubscribeRequest, SubscribeResponse, TailLogs,
+    TailLogsResponse, TelemetryAck, TelemetryReport, TransformRequest, TransformResponse,
+    UnsubscribeRequest, UnsubscribeResponse, WindowUpdate,
+};
+use proptest::prelude::*;
+
+/// An echo request
+pub fn echo(content: &str) -> client_message::Message {
+    client_message::Message::EchoMessage(EchoMessage {
+        content: content.to_string(),
+    })
+}
+
+/// A binary echo request
+pub fn echo_bytes(data: &[u8]) -> client_message::Message {
+    client_message::Message::EchoBytes(EchoBytes {
+        data: data.to_vec(),
+    })
+}
+
+/// An addition request
+pub fn add(a: i32, b: i32) -> client_message::Message {
+    client_message::Message::AddRequest(AddRequest { a, b })
+}
+
+/// A ping carrying `timestamp`
+pub fn ping(timestamp: u64) -> client_message::Message {
+    client_message::Message::PingRequest(PingRequest { timestamp })
+}
+
+/// A sensor reading
+pub fn report(sensor_id: u32, value: f32, timestamp: u64) -> client_message::Message {
+    client_message::Message::TelemetryReport(TelemetryReport {
+        sensor_id,
+        value,
+        timestamp,
+    })
+}
+
+/// A request to apply `op` to `content`
+pub fn transform(content: &str, op: transform_request::Op) -> client_message::Message {
+    client_message::Message::TransformRequest(TransformRequest {
+        content: content.to_string(),
+        op: op as i32,
+    })
+}
+
+/// A request for `num_bytes` random bytes
+pub fn random(num_bytes: u32) -> client_message::Message {
+    client_message::Message::RandomRequest(RandomRequest { num_bytes })
+}
+
+/// A request to evaluate `expression`
+pub fn calc(expression: &str) -> client_message::Message {
+    client_message::Message::CalcRequest(CalcRequest {
+        expression: expression.to_string(),
+    })
+}
+
+/// A request for what the server supports
+pub fn describe() -> client_message::Message {
+    client_message::Message::DescribeRequest(DescribeRequest {})
+}
+
+/// A device naming itself, without a session to resume
+pub fn resume(device_id: &str) -> client_message::Message {
+    client_message::Message::ResumeRequest(ResumeRequest {
+        device_id: device_id.to_string(),
+        ..Default::default()
+    })
+}
+
+/// A request for the device's quota usage
+pub fn quota() -> client_message::Message {
+    client_message::Message::QuotaRequest(QuotaRequest {})
+}
+
+/// A request to stream log records of `level` and above whose target or
+/// message contains `filter`
+pub fn tail_logs(level: log_event::Level, filter: &str) -> client_message::Message {
+    client_message::Message::TailLogs(TailLogs {
+        level: level as i32,
+        filter: filter.to_string(),
+    })
+}
+
+/// A request for the server's self-checks
+pub fn diagnostics() -> client_message::Message {
+    client_message::Message::DiagnosticsRequest(DiagnosticsRequest {})
+}
+
+/// A request for the server's uptime and availability
+pub fn availability() -> client_message::Message {
+    client_message::Message::AvailabilityRequest(AvailabilityRequest {})
+}
+
+/// A request for the last `limit` closed connections of `device`, or of every
+/// client if empty
+pub fn connection_history(device: &str, limit: u32) -> client_message::Message {
+    client_message::Message::ConnectionHistoryRequest(ConnectionHistoryRequest {
+        device: device.to_string(),
+        limit,
+    })
+}
+
+/// A request for the last `limit` messages the server could not deliver to
+/// `device`, or to any device if empty
+pub fn dead_letters(device: &str, limit: u32) -> client_message::Message {
+    client_message::Message::DeadLettersRequest(DeadLettersRequest {
+        device: device.to_string(),
+        limit,
+    })
+}
+
+/// A request that removes every fault rule if `clear`, then adds `add`
+pub fn fault_rules(clear: bool, add: Vec<FaultRule>) -> client_message::Message {
+    client_message::Message::FaultRulesRequest(FaultRulesRequest { clear, add })
+}
+
+/// A request for the messages published on topics `filter` matches
+pub fn subscribe(filter: &str) -> client_message::Message {
+    client_message::Message::SubscribeRequest(SubscribeRequest {
+        filter: filter.to_string(),
+    })
+}
+
+/// A request to stop receiving the messages `filter` matches
+pub fn unsubscribe(filter: &str) -> client_message::Message {
+    client_message::Message::UnsubscribeRequest(UnsubscribeRequest {
+        filter: filter.to_string(),
+    })
+}
+
+/// A request to publish `payload` on `topic`, retained if `retain` is set
+pub fn publish(topic: &str, payload: &[u8], retain: bool) -> client_message::Message {
+    client_message::Message::PublishRequest(PublishRequest {
+        topic: topic.to_string(),
+        payload: payload.to_vec().into(),
+        retain,
+    })
+}
+
+/// A request for the deliveries numbered `from` up to `to` again
+pub fn resync(from: u64, to: u64) -> client_message::Message {
+    client_message::Message::ResyncRequest(ResyncRequest { from, to })
+}
+
+/// Several requests, built with [`request`], sent in one frame
+pub fn batch(messages: Vec<ClientMessage>) -> client_message::Message {
+    client_message::Message::Batch(Batch { messages })
+}
+
+/// Starts a [`ClientMessage`] around `message`, with message ID and stream 0
+pub fn request(message: client_message::Message) -> RequestBuilder {
+    RequestBuilder(ClientMessage {
+        message: Some(message),
+        ..Default::default()
+    })
+}
+
+/// Starts a [`ServerMessage`] around `message`, with message ID and stream 0
+pub fn response(message: server_message::Message) -> ResponseBuilder {
+    ResponseBuilder(ServerMessage {
+        message: Some(message),
+        message_id: 0,
+        stream_id: 0,
+    })
+}
+
+/// A [`ClientMessage`] being built; see [`request`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestBuilder(ClientMessage);
+
+impl RequestBuilder {
+    /// Sets the message ID
+    pub fn id(mut self, message_id: u64) -> Self {
+        self.0.message_id = message_id;
+        self
+    }
+
+    /// Sets the stream
+    pub fn stream(mut self, stream_id: u32) -> Self {
+        self.0.stream_id = stream_id;
+        self
+    }
+
+    /// The finished envelope
+    pub fn build(self) -> ClientMessage {
+        self.0
+    }
+}
+
+impl From<RequestBuilder> for ClientMessage {
+    fn from(builder: RequestBuilder) -> Self {
+        builder.0
+    }
+}
+
+/// A [`ServerMessage`] being built; see [`response`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResponseBuilder(ServerMessage);
+
+impl ResponseBuilder {
+    /// Sets the message ID, as the server copies it from the request
+    pub fn id(mut self, message_id: u64) -> Self {
+        self.0.message_id = message_id;
+        self
+    }
+
+    /// Sets the stream
+    pub fn stream(mut self, stream_id: u32) -> Self {
+        self.0.stream_id = stream_id;
+        self
+    }
+
+    /// The finished envelope
+    pub fn build(self) -> ServerMessage {
+        self.0
+    }
+}
+
+impl From<ResponseBuilder> for ServerMessage {
+    fn from(builder: ResponseBuilder) -> Self {
+        builder.0
+    }
+}
+
+/// Any `i32`, with its boundary values drawn often
+pub fn boundary_i32() -> impl Strategy<Value = i32> {
+    prop_oneof![
+        1 => prop_oneof![Just(i32::MIN), Just(-1), Just(0), Just(1), Just(i32::MAX)],
+        3 => any::<i32>(),
+    ]
+}
+
+/// Any `u32`, with its boundary values drawn often
+pub fn boundary_u32() -> impl Strategy<Value = u32> {
+    prop_oneof![
+        1 => prop_oneof![Just(0), Just(1), Just(u32::MAX)],
+        3 => any::<u32>(),
+    ]
+}
+
+/// Any `u64`, with its boundary values drawn often
+pub fn boundary_u64() -> impl Strategy<Value = u64> {
+    prop_oneof![
+        1 => prop_oneof![Just(0), Just(1), Just(u64::MAX)],
+        3 => any::<u64>(),
+    ]
+}
+
+/// Text from empty to long enough to need multi-byte length prefixes
+pub fn text() -> impl Strategy<Value = String> {
+    prop_oneof![
+        1 => Just(String::new()),
+        4 => ".{0,400}",
+    ]
+}
+
+/// Bytes from none to several hundred
+pub fn bytes() -> impl Strategy<Value = Vec<u8>> {
+    proptest::collection::vec(any::<u8>(), 0..400)
+}
+
+// An enum field: mostly one of the `count` known values, sometimes unknown
+fn enumeration(count: i32) -> impl Strategy<Value = i32> {
+    prop_oneof![
+        4 => 0..count,
+        1 => boundary_i32(),
+    ]
+}
+
+// Finite, so a decoded copy compares equal
+fn finite_f32() -> impl Strategy<Value = f32> {
+    prop_oneof![
+        1 => prop_oneof![Just(0.0), Just(f32::MIN), Just(f32::MAX), Just(f32::EPSILON)],
+        3 => -1e9f32..1e9f32,
+    ]
+}
+
+fn finite_f64() -> impl Strategy<Value = f64> {
+    prop_oneof![
+        1 => prop_oneof![Just(0.0), Just(f64::MIN), Just(f64::MAX), Just(f64::EPSILON)],
+        3 => -1e18f64..1e18f64,
+    ]
+}
+
+// A request around `message`, with an empty envelope now and then, as a peer may send
+fn envelope(
+    message: impl Strategy<Value = client_message::Message>,
+) -> impl Strategy<Value = ClientMessage> {
+    (
+        proptest::option::weighted(0.95, message),
+        boundary_u64(),
+        boundary_u32(),
+        boundary_u32(),
+    )
+        .prop_map(
+            |(message, message_id, stream_id, protocol_version)| ClientMessage {
+                message,
+                message_id,
+                stream_id,
+                protocol_version,
+            },
+        )
+}
+
+macro_rules! arbitrary {
+    ($($ty:ty => $strategy:expr;)*) => {
+        $(
+            impl Arbitrary for $ty {
+                type Parameters = ();
+                type Strategy = BoxedStrategy<Self>;
+
+                fn arbitrary_with(_: ()) -> Self::Strategy {
+                    $strategy.boxed()
+                }
+            }
+        )*
+    };
+}
+
+arbitrary! {
+    EchoMessage => text().prop_map(|content| EchoMessage { content });
+    EchoBytes => bytes().prop_map(|data| EchoBytes { data });
+    TransformRequest => (text(), enumeration(5))
+        .prop_map(|(content, op)| TransformRequest { content, op });
+    TransformResponse => text().prop_map(|content| TransformResponse { content });
+    RandomRequest => boundary_u32().prop_map(|num_bytes| RandomRequest { num_bytes });
+    RandomResponse => bytes().prop_map(|data| RandomResponse { data });
+    CalcRequest => text().prop_map(|expression| CalcRequest { expression });
+    CalcResponse => finite_f64().prop_map(|result| CalcResponse { result });
+    DescribeRequest => Just(DescribeRequest {});
+    DescribeResponse => (
+        boundary_u32(),
+        proptest::collection::vec(text(), 0..8),
+        proptest::collection::vec(text(), 0..8),
+        boundary_u32(),
+        text(),
+    )
+        .prop_map(
+            |(protocol_version, message_types, features, max_frame_size, server_version)| {
+                DescribeResponse {
+                    protocol_version,
+                    message_types,
+                    features,
+                    max_frame_size,
+                    server_version,
+                }
+            },
+        );
+    ResumeRequest => (bytes(), text(), text())
+        .prop_map(|(token, device_id, vhost)| ResumeRequest { token, device_id, vhost });
+    ResumeResponse => (bytes(), any::<bool>())
+        .prop_map(|(token, resumed)| ResumeResponse { token, resumed });
+    Delivery => (boundary_u64(), bytes()).prop_map(|(sequence, payload)| Delivery {
+        sequence,
+        payload: payload.into(),
+    });
+    GoAway => (boundary_u64(), text(), enumeration(go_away::Reason::Rebalance as i32 + 1))
+        .prop_map(|(reconnect_after_ms, alternate_server, reason)| GoAway {
+            reconnect_after_ms,
+            alternate_server,
+            reason,
+        });
+    QuotaRequest => Just(QuotaRequest {});
+    QuotaStatus => (
+        boundary_u64(),
+        boundary_u64(),
+        boundary_u64(),
+        boundary_u64(),
+        boundary_u64(),
+        boundary_u64(),
+        boundary_u64(),
+        boundary_u64(),
+        boundary_u64(),
+    )
+        .prop_map(
+            |(
+                messages,
+                max_messages,
+                bytes,
+                max_bytes,
+                queued,
+                max_queued,
+                subscriptions,
+                max_subscriptions,
+                resets_in_ms,
+            )| QuotaStatus {
+                messages,
+                max_messages,
+                bytes,
+                max_bytes,
+                queued,
+                max_queued,
+                subscriptions,
+                max_subscriptions,
+                resets_in_ms,
+            },
+        );
+    TailLogs => (enumeration(log_event::Level::Trace as i32 + 1), text())
+        .prop_map(|(level, filter)| TailLogs { level, filter });
+    TailLogsResponse => boundary_u32().prop_map(|recent| TailLogsResponse { recent });
+    LogField => (text(), text()).prop_map(|(key, value)| LogField { key, value });
+    LogEvent => (
+        boundary_u64(),
+        enumeration(log_event::Level::Trace as i32 + 1),
+        text(),
+        text(),
+        proptest::collection::vec(any::<LogField>(), 0..4),
+    )
+        .prop_map(|(time_ms, level, target, message, fields)| LogEvent {
+            time_ms,
+            level,
+            target,
+            message,
+            fields,
+        });
+    DiagnosticsRequest => Just(DiagnosticsRequest {});
+    DiagnosticCheck => (
+        text(),
+        enumeration(diagnostic_check::Status::Skipped as i32 + 1),
+        text(),
+    )
+        .prop_map(|(name, status, detail)| DiagnosticCheck {
+            name,
+            status,
+            detail,
+        });
+    DiagnosticsReport => (any::<bool>(), proptest::collection::vec(any::<DiagnosticCheck>(), 0..6))
+        .prop_map(|(healthy, checks)| DiagnosticsReport { healthy, checks });
+    AvailabilityRequest => Just(AvailabilityRequest {});
+    AvailabilityReport => (
+        (boundary_u64(), boundary_u64(), boundary_u64(), boundary_u64()),
+        (boundary_u64(), boundary_u64(), any::<bool>(), boundary_u64()),
+        finite_f64(),
+    )
+        .prop_map(
+            |(
+                (uptime_ms, started_at_ms, accept_stalls, stalled_ms),
+                (overload_periods, overloaded_ms, persisted, restarts),
+                availability,
+            )| AvailabilityReport {
+                uptime_ms,
+                started_at_ms,
+                accept_stalls,
+                stalled_ms,
+                overload_periods,
+                overloaded_ms,
+                persisted,
+                restarts,
+                availability,
+            },
+        );
+    ConnectionHistoryRequest => (text(), boundary_u32())
+        .prop_map(|(device, limit)| ConnectionHistoryRequest { device, limit });
+    ConnectionRecord => (
+        (boundary_u64(), text(), text(), boundary_u64()),
+        (
+            boundary_u64(),
+            boundary_u64(),
+            enumeration(close::Reason::ResourceLimit as i32 + 1),
+            text(),
+        ),
+    )
+        .prop_map(
+            |(
+                (connection_id, peer, device, connected_at_ms),
+                (duration_ms, messages, reason, detail),
+            )| ConnectionRecord {
+                connection_id,
+                peer,
+                device,
+                connected_at_ms,
+                duration_ms,
+                messages,
+                reason,
+                detail,
+            },
+        );
+    Close => (enumeration(close::Reason::ResourceLimit as i32 + 1), text())
+        .prop_map(|(reason, detail)| Close { reason, detail });
+    ConnectionHistoryResponse => proptest::collection::vec(any::<ConnectionRecord>(), 0..4)
+        .prop_map(|connections| ConnectionHistoryResponse { connections });
+    DeadLettersRequest => (text(), boundary_u32())
+        .prop_map(|(device, limit)| DeadLettersRequest { device, limit });
+    DeadLetterRecord => (
+        text(),
+        boundary_u64(),
+        bytes(),
+        enumeration(dead_letter_record::Reason::UnknownDevice as i32 + 1),
+        boundary_u64(),
+    )
+        .prop_map(
+            |(device, sequence, payload, reason, dropped_at_ms)| DeadLetterRecord {
+                device,
+                sequence,
+                payload,
+                reason,
+                dropped_at_ms,
+            },
+        );
+    DeadLettersResponse => proptest::collection::vec(any::<DeadLetterRecord>(), 0..4)
+        .prop_map(|letters| DeadLettersResponse { letters });
+    FaultRule => (
+        text(),
+        finite_f64(),
+        enumeration(fault_rule::Action::Reset as i32 + 1),
+        boundary_u64(),
+    )
+        .prop_map(|(message_type, probability, action, delay_ms)| FaultRule {
+            message_type,
+            probability,
+            action,
+            delay_ms,
+        });
+    FaultRulesRequest => (any::<bool>(), proptest::collection::vec(any::<FaultRule>(), 0..4))
+        .prop_map(|(clear, add)| FaultRulesRequest { clear, add });
+    FaultRulesResponse => proptest::collection::vec(any::<FaultRule>(), 0..4)
+        .prop_map(|rules| FaultRulesResponse { rules });
+    SubscribeRequest => text().prop_map(|filter| SubscribeRequest { filter });
+    SubscribeResponse => boundary_u32().prop_map(|retained| SubscribeResponse { retained });
+    UnsubscribeRequest => text().prop_map(|filter| UnsubscribeRequest { filter });
+    UnsubscribeResponse => any::<bool>().prop_map(|subscribed| UnsubscribeResponse { subscribed });
+    PublishRequest => (text(), bytes(), any::<bool>()).prop_map(|(topic, payload, retain)| {
+        PublishRequest {
+            topic,
+            payload: payload.into(),
+            retain,
+        }
+    });
+    PublishResponse => boundary_u32().prop_map(|subscribers| PublishResponse { subscribers });
+    Publication => (text(), bytes(), any::<bool>()).prop_map(|(topic, payload, retained)| {
+        Publication {
+            topic,
+            payload: payload.into(),
+            retained,
+        }
+    });
+    ResyncRequest => (boundary_u64(), boundary_u64()).prop_map(|(from, to)| ResyncRequest { from, to });
+    ResyncResponse => (boundary_u32(), boundary_u64())
+        .prop_map(|(replayed, first)| ResyncResponse { replayed, first });
+    ClusterInterest => (text(), any::<bool>())
+        .prop_map(|(filter, subscribed)| ClusterInterest { filter, subscribed });
+    ClusterPublish => (text(), text(), bytes(), any::<bool>()).prop_map(
+        |(topic, name, payload, retain)| ClusterPublish {
+            topic,
+            name,
+            payload: payload.into(),
+            retain,
+        },
+    );
+    ClusterDevice => (text(), boundary_u64())
+        .prop_map(|(device, named_at_ms)| ClusterDevice { device, named_at_ms });
+    ClusterSync => (
+        proptest::collection::vec(text(), 0..4),
+        proptest::collection::vec(any::<ClusterDevice>(), 0..4),
+    )
+        .prop_map(|(filters, devices)| ClusterSync { filters, devices });
+    ClusterSend => (text(), bytes(), boundary_u32()).prop_map(|(device, payload, ttl_ms)| {
+        ClusterSend {
+            device,
+            payload: payload.into(),
+            ttl_ms,
+        }
+    });
+    ClusterEvent => {
+        use cluster_event::Event;
+        let event = prop_oneof![
+            any::<ClusterSync>().prop_map(Event::Sync),
+            any::<ClusterInterest>().prop_map(Event::Interest),
+            any::<ClusterPublish>().prop_map(Event::Publish),
+            any::<ClusterDevice>().prop_map(Event::Device),
+            any::<ClusterSend>().prop_map(Event::Send),
+        ];
+        (text(), proptest::option::of(event)).prop_map(|(node, event)| ClusterEvent { node, event })
+    };
+    ClusterAck => boundary_u64().prop_map(|sequence| ClusterAck { sequence });
+    AddRequest => (boundary_i32(), boundary_i32()).prop_map(|(a, b)| AddRequest { a, b });
+    AddResponse => boundary_i32().prop_map(|result| AddResponse { result });
+    PingRequest => boundary_u64().prop_map(|timestamp| PingRequest { timestamp });
+    PingResponse => boundary_u64().prop_map(|timestamp| PingResponse { timestamp });
+    TelemetryReport => (boundary_u32(), finite_f32(), boundary_u64()).prop_map(
+        |(sensor_id, value, timestamp)| TelemetryReport {
+            sensor_id,
+            value,
+            timestamp,
+        },
+    );
+    TelemetryAck => (boundary_u32(), boundary_u64())
+        .prop_map(|(sensor_id, timestamp)| TelemetryAck { sensor_id, timestamp });
+    WindowUpdate => boundary_u32().prop_map(|credits| WindowUpdate { credits });
+    ErrorResponse => (enumeration(7), boundary_u32(), text(), text()).prop_map(
+        |(code, retry_after_ms, field, detail)| ErrorResponse {
+            code,
+            retry_after_ms,
+            field,
+            detail,
+        },
+    );
+    client_message::Message => {
+        use client_message::Message;
+        let single = prop_oneof![
+            any::<EchoMessage>().prop_map(Message::EchoMessage),
+            any::<AddRequest>().prop_map(Message::AddRequest),
+            any::<PingRequest>().prop_map(Message::PingRequest),
+            any::<TelemetryReport>().prop_map(Message::TelemetryReport),
+            any::<EchoBytes>().prop_map(Message::EchoBytes),
+            any::<TransformRequest>().prop_map(Message::TransformRequest),
+            any::<RandomRequest>().prop_map(Message::RandomRequest),
+            any::<CalcRequest>().prop_map(Message::CalcRequest),
+            any::<DescribeRequest>().prop_map(Message::DescribeRequest),
+            any::<ResumeRequest>().prop_map(Message::ResumeRequest),
+            any::<QuotaRequest>().prop_map(Message::QuotaRequest),
+            any::<TailLogs>().prop_map(Message::TailLogs),
+            any::<DiagnosticsRequest>().prop_map(Message::DiagnosticsRequest),
+            any::<AvailabilityRequest>().prop_map(Message::AvailabilityRequest),
+            any::<ConnectionHistoryRequest>().prop_map(Message::ConnectionHistoryRequest),
+            any::<SubscribeRequest>().prop_map(Message::SubscribeRequest),
+            any::<UnsubscribeRequest>().prop_map(Message::UnsubscribeRequest),
+            any::<PublishRequest>().prop_map(Message::PublishRequest),
+            any::<ResyncRequest>().prop_map(Message::ResyncRequest),
+            any::<ClusterEvent>().prop_map(Message::ClusterEvent),
+            any::<DeadLettersRequest>().prop_map(Message::DeadLettersRequest),
+            any::<FaultRulesRequest>().prop_map(Message::FaultRulesRequest),
+        ]
+        .boxed();
+        // Now and then several in a batch, which never holds another
+        let batch = proptest::collection::vec(envelope(single.clone()), 0..4)
+            .prop_map(|messages| Message::Batch(Batch { messages }));
+        prop_oneof![20 => single, 1 => batch]
+    };
+    server_message::Message => {
+        use server_message::Message;
+        prop_oneof![
+            any::<EchoMessage>().prop_map(Message::EchoMessage),
+            any::<AddResponse>().prop_map(Message::AddResponse),
+            any::<PingResponse>().prop_map(Message::PingResponse),
+            any::<TelemetryAck>().prop_map(Message::TelemetryAck),
+            any::<WindowUpdate>().prop_map(Message::WindowUpdate),
+            any::<ErrorResponse>().prop_map(Message::ErrorResponse),
+            any::<EchoBytes>().prop_map(Message::EchoBytes),
+            any::<TransformResponse>().prop_map(Message::TransformResponse),
+            any::<RandomResponse>().prop_map(Message::RandomResponse),
+            any::<CalcResponse>().prop_map(Message::CalcResponse),
+            any::<DescribeResponse>().prop_map(Message::DescribeResponse),
+            any::<ResumeResponse>().prop_map(Message::ResumeResponse),
+            any::<Delivery>().prop_map(Message::Delivery),
+            any::<GoAway>().prop_map(Message::GoAway),
+            any::<QuotaStatus>().prop_map(Message::QuotaStatus),
+            any::<TailLogsResponse>().prop_map(Message::TailLogsResponse),
+            any::<LogEvent>().prop_map(Message::LogEvent),
+            any::<DiagnosticsReport>().prop_map(Message::DiagnosticsReport),
+            any::<AvailabilityReport>().prop_map(Message::AvailabilityReport),
+            any::<ConnectionHistoryResponse>().prop_map(Message::ConnectionHistoryResponse),
+            any::<Close>().prop_map(Message::Close),
+            any::<SubscribeResponse>().prop_map(Message::SubscribeResponse),
+            any::<UnsubscribeResponse>().prop_map(Message::UnsubscribeResponse),
+            any::<PublishResponse>().prop_map(Message::PublishResponse),
+            any::<Publication>().prop_map(Message::Publication),
+            any::<ResyncResponse>().prop_map(Message::ResyncResponse),
+            any::<ClusterAck>().prop_map(Message::ClusterAck),
+            any::<DeadLettersResponse>().prop_map(Message::DeadLettersResponse),
+            any::<FaultRulesResponse>().prop_map(Message::FaultRulesResponse),
+        ]
+    };
+    ClientMessage => envelope(any::<client_message::Message>());
+    Batch => proptest::collection::vec(any::<ClientMessage>(), 0..4)
+        .prop_map(|messages| Batch { messages });
+    ServerMessage => (
+        proptest::option::weighted(0.95, any::<server_message::Message>()),
+        boundary_u64(),
+        boundary_u32(),
+    )
+        .prop_map(|(message, message_id, stream_id)| ServerMessage {
+            message,
+            message_id,
+            stream_id,
+        });
+}
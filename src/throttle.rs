@@ -0,0 +1,47 @@
+//! Per-connection bandwidth limits.
+//!
+//! [`Server::set_upload_limit`] and [`Server::set_download_limit`] cap the
+//! bytes per second each connection may send and receive. Every connection
+//! keeps a [`TokenBucket`] per direction: bytes spend tokens, tokens refill at
+//! the configured rate, and a connection that has run out waits before its next
+//! read or write. Holding off reads lets TCP push back on the sender, so one
+//! device streaming a large transfer cannot crowd out the others on the link.
+//!
+//! Buckets hold at most one second's worth of tokens, so an idle connection can
+//! burst up to the limit. A frame larger than that is still sent whole; the
+//! connection then waits until the overdraft is paid back.
+//!
+//! [`Server::set_upload_limit`]: crate::server::Server::set_upload_limit
+//! [`Server::set_download_limit`]: crate::server::Server::set_download_limit
+
+use std::time::{Duration, Instant};
+
+/// Token bucket metering one direction of one connection
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    tokens: f64, // Bytes that may pass now; negative while overdrawn
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket
+    pub fn new(now: Instant) -> Self {
+        TokenBucket {
+            tokens: f64::MAX, // Capped at the rate on first use
+            updated: now,
+        }
+    }
+
+    /// Spends `bytes` at `rate` bytes per second, returning how long to wait before they may pass
+    pub fn take(&mut self, rate: u64, bytes: usize, now: Instant) -> Duration {
+        let rate = rate.max(1) as f64;
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(rate) - bytes as f64;
+        self.updated = now;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / rate)
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! Hierarchical topic names and MQTT-style subscription filters.
+//!
+//! A topic name is a path of levels separated by `/`, such as
+//! `site/room/device/metric`. A filter may replace whole levels with
+//! wildcards: `+` matches exactly one level and `#`, only allowed last,
+//! matches any number of levels, including none. So `site/+/thermo-1/#`
+//! matches `site/kitchen/thermo-1/temperature` and `site/hall/thermo-1`. As in
+//! MQTT, a wildcard at the start of a filter does not match topics whose first
+//! level starts with `$`, which are reserved for the server.
+//!
+//! [`TopicTrie`] keeps values, such as subscribers, under filters and finds
+//! the ones matching a topic by walking one level at a time, so the cost of a
+//! lookup grows with the depth of the topic rather than the number of filters.
+//! Levels are capped at [`MAX_LEVELS`], so names from untrusted devices cannot
+//! make a lookup recurse without bound. Like the codec, this module only
+//! depends on `core` and `alloc`.
+
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::fmt;
+
+/// Most levels a topic name or filter may have
+pub const MAX_LEVELS: usize = 32;
+
+/// Separates the levels of a topic
+pub const SEPARATOR: char = '/';
+
+/// Matches exactly one level of a topic
+pub const SINGLE_LEVEL: &str = "+";
+
+/// Matches the remaining levels of a topic, if any
+pub const MULTI_LEVEL: &str = "#";
+
+/// Why a topic name or filter was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TopicError {
+    /// The name or filter is empty
+    Empty,
+    /// More levels than [`MAX_LEVELS`]
+    TooDeep,
+    /// A wildcard in a topic name, where only filters may have them
+    Wildcard,
+    /// A wildcard sharing its level with other characters, or `#` before the last level
+    MisplacedWildcard,
+}
+
+impl fmt::Display for TopicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TopicError::Empty => write!(f, "empty topic"),
+            TopicError::TooDeep => write!(f, "more than {} levels", MAX_LEVELS),
+            TopicError::Wildcard => write!(f, "wildcard in a topic name"),
+            TopicError::MisplacedWildcard => write!(f, "misplaced wildcard"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TopicError {}
+
+/// Checks a topic name that messages are published to
+pub fn validate_topic(topic: &str) -> Result<(), TopicError> {
+    check_depth(topic)?;
+    match topic.contains(['+', '#']) {
+        true => Err(TopicError::Wildcard),
+        false => Ok(()),
+    }
+}
+
+/// Checks a subscription filter
+pub fn validate_filter(filter: &str) -> Result<(), TopicError> {
+    check_depth(filter)?;
+    let mut levels = filter.split(SEPARATOR).peekable();
+    while let Some(level) = levels.next() {
+        let last = levels.peek().is_none();
+        match level {
+            SINGLE_LEVEL => {}
+            MULTI_LEVEL if last => {}
+            _ if level.contains(['+', '#']) => return Err(TopicError::MisplacedWildcard),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn check_depth(name: &str) -> Result<(), TopicError> {
+    if name.is_empty() {
+        return Err(TopicError::Empty);
+    }
+    match name.split(SEPARATOR).count() > MAX_LEVELS {
+        true => Err(TopicError::TooDeep),
+        false => Ok(()),
+    }
+}
+
+/// Whether `filter` matches `topic`; invalid filters and names match nothing
+pub fn matches(filter: &str, topic: &str) -> bool {
+    if validate_filter(filter).is_err() || validate_topic(topic).is_err() {
+        return false;
+    }
+    if topic.starts_with('$') && filter.starts_with(['+', '#']) {
+        return false;
+    }
+    let mut levels = topic.split(SEPARATOR);
+    for level in filter.split(SEPARATOR) {
+        if level == MULTI_LEVEL {
+            return true;
+        }
+        match levels.next() {
+            Some(topic_level) if level == SINGLE_LEVEL || level == topic_level => {}
+            _ => return false,
+        }
+    }
+    levels.next().is_none()
+}
+
+/// Values kept under subscription filters, found by the topics they match
+#[derive(Debug)]
+pub struct TopicTrie<T> {
+    root: Node<T>,
+    len: usize, // Values in all nodes
+}
+
+// One level of the filters inserted so far
+#[derive(Debug)]
+struct Node<T> {
+    children: BTreeMap<String, Node<T>>, // By level, wildcards included
+    values: Vec<T>,                      // Of filters ending at this level
+}
+
+impl<T> Node<T> {
+    fn new() -> Self {
+        Node {
+            children: BTreeMap::new(),
+            values: Vec::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.children.is_empty() && self.values.is_empty()
+    }
+
+    // Collects the values of filters matching `levels`, which follow this node
+    fn collect<'a>(&'a self, levels: &[&str], first: bool, found: &mut Vec<&'a T>) {
+        // Wildcards at the start of a filter skip the server's `$` topics
+        let reserved = first && levels.first().is_some_and(|l| l.starts_with('$'));
+        if !reserved {
+            if let Some(rest) = self.children.get(MULTI_LEVEL) {
+                found.extend(&rest.values);
+            }
+        }
+        let Some((level, rest)) = levels.split_first() else {
+            found.extend(&self.values);
+            return;
+        };
+        if let Some(child) = self.children.get(*level) {
+            child.collect(rest, false, found);
+        }
+        if !reserved {
+            if let Some(child) = self.children.get(SINGLE_LEVEL) {
+                child.collect(rest, false, found);
+            }
+        }
+    }
+
+    // Removes the first value equal to `value` under `levels`; whether there was one
+    fn remove(&mut self, levels: &[&str], value: &T) -> bool
+    where
+        T: PartialEq,
+    {
+        let Some((level, rest)) = levels.split_first() else {
+            let position = self.values.iter().position(|v| v == value);
+            return position.map(|at| self.values.remove(at)).is_some();
+        };
+        let Some(child) = self.children.get_mut(*level) else {
+            return false;
+        };
+        let removed = child.remove(rest, value);
+        if child.is_empty() {
+            self.children.remove(*level); // Nothing left under it to match
+        }
+        removed
+    }
+}
+
+impl<T> TopicTrie<T> {
+    /// Creates an empty trie
+    pub fn new() -> Self {
+        TopicTrie {
+            root: Node::new(),
+            len: 0,
+        }
+    }
+
+    /// Keeps `value` under `filter`; a filter may hold several values
+    pub fn insert(&mut self, filter: &str, value: T) -> Result<(), TopicError> {
+        validate_filter(filter)?;
+        let mut node = &mut self.root;
+        for level in filter.split(SEPARATOR) {
+            node = node.children.entry(level.into()).or_insert_with(Node::new);
+        }
+        node.values.push(value);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Removes `value` from under `filter`; whether it was there
+    pub fn remove(&mut self, filter: &str, value: &T) -> bool
+    where
+        T: PartialEq,
+    {
+        let levels: Vec<&str> = filter.split(SEPARATOR).collect();
+        let removed = self.root.remove(&levels, value);
+        if removed {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    /// Values under every filter matching `topic`; none if it is not a valid name
+    pub fn matches(&self, topic: &str) -> Vec<&T> {
+        let mut found = Vec::new();
+        if validate_topic(topic).is_ok() {
+            let levels: Vec<&str> = topic.split(SEPARATOR).collect();
+            self.root.collect(&levels, true, &mut found);
+        }
+        found
+    }
+
+    /// Number of values kept
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether no values are kept
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<T> Default for TopicTrie<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,502 @@
+//! Byte transports the protocol can run over.
+//!
+//! [`Transport`] is the minimal blocking I/O interface the client needs. It is
+//! implemented for `TcpStream` on std targets and, behind the `embedded-io`
+//! feature, for any `embedded_io` reader/writer through [`EmbeddedIo`], so the
+//! same [`Connection`] logic drives std sockets, serial ports and network stacks.
+//!
+//! Async firmware (e.g. Embassy tasks) uses [`AsyncConnection`] instead, which
+//! awaits `embedded_io_async` I/O so it never blocks the executor.
+
+#[cfg(feature = "message")]
+use crate::batch;
+#[cfg(feature = "message")]
+use crate::codec::{CodecError, PROTOCOL_VERSION};
+#[cfg(feature = "message")]
+use crate::flow::SendWindows;
+#[cfg(feature = "message")]
+use crate::fmt::{log_debug, log_trace, log_warn};
+#[cfg(feature = "message")]
+use crate::message::{client_message, server_message, ClientMessage, ServerMessage};
+#[cfg(feature = "message")]
+use crate::protocol::{ClientProtocol, Event};
+#[cfg(feature = "message")]
+use alloc::{collections::VecDeque, vec::Vec};
+use core::fmt;
+
+/// Blocking byte stream used to carry frames
+pub trait Transport {
+    /// Error reported by the underlying I/O
+    type Error;
+
+    /// Reads available bytes into `buf`, returning 0 once the peer has closed the stream
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error>;
+
+    /// Writes the whole buffer
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error>;
+
+    /// Flushes buffered output
+    fn flush(&mut self) -> Result<(), Self::Error>;
+}
+
+#[cfg(feature = "std")]
+impl Transport for std::net::TcpStream {
+    type Error = std::io::Error;
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        std::io::Read::read(self, buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        std::io::Write::write_all(self, buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        std::io::Write::flush(self)
+    }
+}
+
+/// Adapts an `embedded_io` reader/writer (UART, TCP socket driver, ...) to [`Transport`]
+#[cfg(feature = "embedded-io")]
+#[derive(Debug)]
+pub struct EmbeddedIo<T>(pub T);
+
+#[cfg(feature = "embedded-io")]
+impl<T: embedded_io::Read + embedded_io::Write> Transport for EmbeddedIo<T> {
+    type Error = T::Error;
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.0.read(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        self.0.write_all(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.0.flush()
+    }
+}
+
+/// Errors produced while exchanging messages over a transport
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The underlying transport failed
+    Transport(E),
+    /// A frame could not be encoded or decoded
+    #[cfg(feature = "message")]
+    Codec(CodecError),
+    /// The peer closed the stream
+    Closed,
+}
+
+impl<E: fmt::Debug> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Transport(e) => write!(f, "transport error: {:?}", e),
+            #[cfg(feature = "message")]
+            Error::Codec(e) => write!(f, "{}", e),
+            Error::Closed => write!(f, "connection closed by peer"),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<E: defmt::Format> defmt::Format for Error<E> {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Error::Transport(e) => defmt::write!(f, "Transport({})", e),
+            #[cfg(feature = "message")]
+            Error::Codec(e) => defmt::write!(f, "Codec({})", e),
+            Error::Closed => defmt::write!(f, "Closed"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: fmt::Debug> std::error::Error for Error<E> {}
+
+#[cfg(feature = "std")]
+impl From<Error<std::io::Error>> for std::io::Error {
+    fn from(error: Error<std::io::Error>) -> Self {
+        match error {
+            Error::Transport(e) => e,
+            Error::Codec(e) => e.into(),
+            Error::Closed => {
+                std::io::Error::new(std::io::ErrorKind::ConnectionAborted, "Server disconnected")
+            }
+        }
+    }
+}
+
+// Protocol state shared by the blocking and async connections
+#[cfg(feature = "message")]
+#[derive(Debug, Default)]
+struct ClientDriver {
+    protocol: ClientProtocol,
+    events: VecDeque<Event<ServerMessage>>, // Events not yet returned by `receive`
+    parked: VecDeque<ServerMessage>,        // Responses received while waiting on another stream
+    windows: SendWindows,                   // Flow control credits left per stream
+    stated: bool,   // Whether the protocol version went out, on the first message
+    envelope: bool, // Whether frames written together go out as one `Batch`
+}
+
+#[cfg(feature = "message")]
+impl ClientDriver {
+    // Whether a request may be sent on `stream` now; if not, read until the server grants credits
+    fn may_send<E>(&self, stream: u32) -> Result<bool, Error<E>> {
+        if self.windows.available(stream) > 0 {
+            return Ok(true);
+        }
+        if !self.protocol.is_open() {
+            return Err(Error::Closed); // No credits can arrive any more
+        }
+        log_debug!("Stream {} is out of credits", stream);
+        Ok(false)
+    }
+
+    fn queue(
+        &mut self,
+        stream: u32,
+        message_id: u64,
+        message: client_message::Message,
+    ) -> Result<(), CodecError> {
+        self.windows.consume(stream);
+        let protocol_version = if self.stated { 0 } else { PROTOCOL_VERSION };
+        self.stated = true;
+        self.protocol.send(&ClientMessage {
+            message: Some(message),
+            message_id,
+            stream_id: stream,
+            protocol_version,
+        })
+    }
+
+    fn poll_transmit(&mut self) -> Option<Vec<u8>> {
+        let frame = self.protocol.poll_transmit()?;
+        log_trace!("Sending frame of {} bytes", frame.len());
+        Some(frame)
+    }
+
+    // Every queued frame, back to back, so they go out in one write; with the
+    // envelope, several are packed into one `Batch` frame if it fits
+    fn take_transmit(&mut self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.protocol.queued());
+        let mut frames = 0;
+        while let Some(frame) = self.poll_transmit() {
+            bytes.extend_from_slice(&frame);
+            frames += 1;
+        }
+        if self.envelope && frames > 1 {
+            match batch::pack(&bytes) {
+                Ok(packed) => {
+                    log_trace!(
+                        "Packed {} frames into a batch of {} bytes",
+                        frames,
+                        packed.len()
+                    );
+                    return packed;
+                }
+                Err(e) => log_debug!("Sending {} frames unbatched: {}", frames, e),
+            }
+        }
+        bytes
+    }
+
+    // Hands the result of one read to the protocol; an empty read means end of stream
+    fn received(&mut self, bytes: &[u8]) {
+        let events = if bytes.is_empty() {
+            self.protocol.feed_eof()
+        } else {
+            log_trace!("Read {} bytes", bytes.len());
+            self.protocol.feed_bytes(bytes)
+        };
+        for event in events {
+            match event {
+                // Credits are applied at once and never returned by `receive`
+                Event::Message(ServerMessage {
+                    message: Some(server_message::Message::WindowUpdate(update)),
+                    stream_id,
+                    ..
+                }) => self.windows.grant(stream_id, update.credits),
+                event => self.events.push_back(event),
+            }
+        }
+    }
+
+    // Next result for `receive` on `stream`, or on any stream if `None`; `None`
+    // if more bytes have to be read first. Responses for other streams are
+    // parked until asked for. The server's `Close` ends every stream.
+    fn next_response<E>(&mut self, stream: Option<u32>) -> Option<Result<ServerMessage, Error<E>>> {
+        let wanted = |message: &ServerMessage| {
+            stream.is_none_or(|id| message.stream_id == id)
+                || matches!(message.message, Some(server_message::Message::Close(_)))
+        };
+        if let Some(i) = self.parked.iter().position(wanted) {
+            return self.parked.remove(i).map(Ok);
+        }
+
+        loop {
+            match self.events.pop_front() {
+                Some(Event::Message(message)) if wanted(&message) => return Some(Ok(message)),
+                Some(Event::Message(message)) => self.parked.push_back(message),
+                Some(Event::Error(e)) => {
+                    log_warn!("Failed to decode frame from server: {}", e);
+                    return Some(Err(Error::Codec(e)));
+                }
+                Some(Event::Closed) => {
+                    log_debug!("Connection closed by server");
+                    return Some(Err(Error::Closed));
+                }
+                None if !self.protocol.is_open() => return Some(Err(Error::Closed)),
+                None => return None,
+            }
+        }
+    }
+}
+
+/// Client side of the protocol running over any [`Transport`]
+#[cfg(feature = "message")]
+#[derive(Debug)]
+pub struct Connection<T> {
+    transport: T,
+    driver: ClientDriver,
+    batch_bytes: usize, // Frames are held until this many bytes are waiting
+}
+
+#[cfg(feature = "message")]
+impl<T: Transport> Connection<T> {
+    /// Wraps a connected transport
+    pub fn new(transport: T) -> Self {
+        Connection {
+            transport,
+            driver: ClientDriver::default(),
+            batch_bytes: 0,
+        }
+    }
+
+    /// Holds sent requests until `bytes` of frames are waiting, then writes
+    /// them in one go, so a radio wakes once for several small messages. The
+    /// default of 0 writes each request as it is sent. Held requests are also
+    /// written by [`flush`](Self::flush) and before any read.
+    pub fn set_batch_bytes(&mut self, bytes: usize) {
+        self.batch_bytes = bytes;
+    }
+
+    /// Sends the requests written together as one `Batch` frame rather than a
+    /// frame each. Servers built before the batch envelope drop it unanswered,
+    /// so it is off by default. Batches too large for one frame go out as
+    /// separate frames.
+    pub fn set_batch_envelope(&mut self, envelope: bool) {
+        self.driver.envelope = envelope;
+    }
+
+    /// Bytes of requests sent but held back for a batch
+    pub fn held(&self) -> usize {
+        self.driver.protocol.queued()
+    }
+
+    /// Writes the requests held back for a batch
+    pub fn flush(&mut self) -> Result<(), Error<T::Error>> {
+        let bytes = self.driver.take_transmit();
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        self.transport.write_all(&bytes).map_err(Error::Transport)?;
+        self.transport.flush().map_err(Error::Transport)
+    }
+
+    /// Encodes and sends one request on the default stream
+    pub fn send(&mut self, message: client_message::Message) -> Result<(), Error<T::Error>> {
+        self.send_on(0, message)
+    }
+
+    /// Encodes and sends one request on a logical stream; its response carries the same stream.
+    ///
+    /// If the stream has no flow control credits left, reads until the server grants more.
+    pub fn send_on(
+        &mut self,
+        stream: u32,
+        message: client_message::Message,
+    ) -> Result<(), Error<T::Error>> {
+        self.send_with_id(stream, 0, message)
+    }
+
+    /// Like [`send_on`](Self::send_on), tagging the request with a message ID.
+    ///
+    /// A retry sent with the same nonzero ID is answered with the response to the
+    /// first attempt instead of being handled again; see [`crate::dedup`].
+    pub fn send_with_id(
+        &mut self,
+        stream: u32,
+        message_id: u64,
+        message: client_message::Message,
+    ) -> Result<(), Error<T::Error>> {
+        let mut buffer = [0u8; 256];
+        while !self.driver.may_send(stream)? {
+            self.flush()?; // Credits only come back for requests the server has seen
+            let bytes_read = self.transport.read(&mut buffer).map_err(Error::Transport)?;
+            self.driver.received(&buffer[..bytes_read]);
+        }
+
+        self.driver
+            .queue(stream, message_id, message)
+            .map_err(Error::Codec)?;
+        if self.held() >= self.batch_bytes {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Blocks until a complete response has been received, on any stream
+    pub fn receive(&mut self) -> Result<ServerMessage, Error<T::Error>> {
+        self.receive_from(None)
+    }
+
+    /// Blocks until a response arrives on `stream`, keeping those for other streams
+    pub fn receive_on(&mut self, stream: u32) -> Result<ServerMessage, Error<T::Error>> {
+        self.receive_from(Some(stream))
+    }
+
+    fn receive_from(&mut self, stream: Option<u32>) -> Result<ServerMessage, Error<T::Error>> {
+        let mut buffer = [0u8; 256];
+        self.flush()?; // The response may be to a request held back
+        loop {
+            if let Some(result) = self.driver.next_response(stream) {
+                return result;
+            }
+
+            let bytes_read = self.transport.read(&mut buffer).map_err(Error::Transport)?;
+            self.driver.received(&buffer[..bytes_read]);
+        }
+    }
+
+    /// The underlying transport
+    pub fn get_ref(&self) -> &T {
+        &self.transport
+    }
+
+    /// The underlying transport, mutably
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Unwraps the transport, discarding any partially received frame
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+}
+
+/// Client side of the protocol over an `embedded_io_async` reader/writer.
+///
+/// Does not depend on any particular executor, so it runs under Embassy as well
+/// as any other async runtime.
+#[cfg(all(feature = "message", feature = "embedded-io-async"))]
+#[derive(Debug)]
+pub struct AsyncConnection<T> {
+    transport: T,
+    driver: ClientDriver,
+}
+
+#[cfg(all(feature = "message", feature = "embedded-io-async"))]
+impl<T: embedded_io_async::Read + embedded_io_async::Write> AsyncConnection<T> {
+    /// Wraps a connected transport
+    pub fn new(transport: T) -> Self {
+        AsyncConnection {
+            transport,
+            driver: ClientDriver::default(),
+        }
+    }
+
+    /// Encodes and sends one request on the default stream
+    pub async fn send(&mut self, message: client_message::Message) -> Result<(), Error<T::Error>> {
+        self.send_on(0, message).await
+    }
+
+    /// Encodes and sends one request on a logical stream; its response carries the same stream.
+    ///
+    /// If the stream has no flow control credits left, reads until the server grants more.
+    pub async fn send_on(
+        &mut self,
+        stream: u32,
+        message: client_message::Message,
+    ) -> Result<(), Error<T::Error>> {
+        self.send_with_id(stream, 0, message).await
+    }
+
+    /// Like [`send_on`](Self::send_on), tagging the request with a message ID.
+    ///
+    /// A retry sent with the same nonzero ID is answered with the response to the
+    /// first attempt instead of being handled again; see [`crate::dedup`].
+    pub async fn send_with_id(
+        &mut self,
+        stream: u32,
+        message_id: u64,
+        message: client_message::Message,
+    ) -> Result<(), Error<T::Error>> {
+        let mut buffer = [0u8; 256];
+        while !self.driver.may_send(stream)? {
+            let bytes_read = self
+                .transport
+                .read(&mut buffer)
+                .await
+                .map_err(Error::Transport)?;
+            self.driver.received(&buffer[..bytes_read]);
+        }
+
+        self.driver
+            .queue(stream, message_id, message)
+            .map_err(Error::Codec)?;
+        while let Some(frame) = self.driver.poll_transmit() {
+            self.transport
+                .write_all(&frame)
+                .await
+                .map_err(Error::Transport)?;
+        }
+        self.transport.flush().await.map_err(Error::Transport)
+    }
+
+    /// Waits until a complete response has been received, on any stream
+    pub async fn receive(&mut self) -> Result<ServerMessage, Error<T::Error>> {
+        self.receive_from(None).await
+    }
+
+    /// Waits until a response arrives on `stream`, keeping those for other streams
+    pub async fn receive_on(&mut self, stream: u32) -> Result<ServerMessage, Error<T::Error>> {
+        self.receive_from(Some(stream)).await
+    }
+
+    async fn receive_from(
+        &mut self,
+        stream: Option<u32>,
+    ) -> Result<ServerMessage, Error<T::Error>> {
+        let mut buffer = [0u8; 256];
+        loop {
+            if let Some(result) = self.driver.next_response(stream) {
+                return result;
+            }
+
+            let bytes_read = self
+                .transport
+                .read(&mut buffer)
+                .await
+                .map_err(Error::Transport)?;
+            self.driver.received(&buffer[..bytes_read]);
+        }
+    }
+
+    /// The underlying transport
+    pub fn get_ref(&self) -> &T {
+        &self.transport
+    }
+
+    /// The underlying transport, mutably
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Unwraps the transport, discarding any partially received frame
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+}
@@ -0,0 +1,248 @@
+//! Checking requests before they are handled.
+//!
+//! A [`Validator`] is middleware (see [`crate::middleware`]) that checks each
+//! request against rules for its message type before passing it on. A request
+//! that breaks a rule is answered with an `ErrorResponse` whose code is
+//! `INVALID`, naming the field at fault and what was wrong, and is not handled:
+//!
+//! ```
+//! # use embedded_recruitment_task::{server::Server, validation::Validator};
+//! let server = Server::new("localhost:0")?
+//!     .layer(Validator::new().max_string_len(256).add_operands(-1000..=1000));
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! The built-in rules cover string lengths and numeric ranges. Strings need no
+//! UTF-8 check here: a frame whose string is not valid UTF-8 already fails to
+//! decode, which closes the connection. [`Validator::rule`] replaces the
+//! built-in rules of one message type with a check of the application's own.
+
+use crate::handler::MessageKind;
+use crate::message::{client_message, error_response, server_message, ErrorResponse};
+use crate::middleware::{Middleware, Next, Request, Response};
+use log::debug;
+use std::{collections::HashMap, ops::RangeInclusive};
+
+/// Longest string accepted by default, in bytes
+pub const DEFAULT_MAX_STRING_LEN: usize = 4096;
+
+/// Longest byte payload accepted by default
+pub const DEFAULT_MAX_BYTES_LEN: usize = 16 * 1024;
+
+/// A broken rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// Field of the request at fault
+    pub field: String,
+    /// What was wrong with it
+    pub detail: String,
+}
+
+impl Violation {
+    /// A violation of a rule on `field`
+    pub fn new(field: &str, detail: impl Into<String>) -> Self {
+        Violation {
+            field: field.to_string(),
+            detail: detail.into(),
+        }
+    }
+}
+
+type Rule = Box<dyn Fn(&client_message::Message) -> Result<(), Violation> + Send + Sync>;
+
+/// Rules for each message type
+pub struct Validator {
+    max_string_len: usize,
+    max_bytes_len: usize,
+    add_operands: Option<RangeInclusive<i32>>,
+    telemetry_values: Option<RangeInclusive<f32>>,
+    max_random_bytes: Option<u32>,
+    rules: HashMap<MessageKind, Rule>, // Replace the built-in rules of their type
+}
+
+impl Validator {
+    /// Built-in rules only: strings of at most [`DEFAULT_MAX_STRING_LEN`] bytes,
+    /// byte payloads of at most [`DEFAULT_MAX_BYTES_LEN`] and finite telemetry values
+    pub fn new() -> Self {
+        Validator {
+            max_string_len: DEFAULT_MAX_STRING_LEN,
+            max_bytes_len: DEFAULT_MAX_BYTES_LEN,
+            add_operands: None,
+            telemetry_values: None,
+            max_random_bytes: None,
+            rules: HashMap::new(),
+        }
+    }
+
+    /// Rejects strings longer than `bytes`
+    pub fn max_string_len(mut self, bytes: usize) -> Self {
+        self.max_string_len = bytes;
+        self
+    }
+
+    /// Rejects byte payloads longer than `bytes`
+    pub fn max_bytes_len(mut self, bytes: usize) -> Self {
+        self.max_bytes_len = bytes;
+        self
+    }
+
+    /// Rejects `AddRequest`s with an operand outside `range`
+    pub fn add_operands(mut self, range: RangeInclusive<i32>) -> Self {
+        self.add_operands = Some(range);
+        self
+    }
+
+    /// Rejects `TelemetryReport`s with a value outside `range`
+    pub fn telemetry_values(mut self, range: RangeInclusive<f32>) -> Self {
+        self.telemetry_values = Some(range);
+        self
+    }
+
+    /// Rejects `RandomRequest`s for more than `bytes`, on top of the handler's
+    /// own limit of [`MAX_RANDOM_BYTES`](crate::handler::MAX_RANDOM_BYTES)
+    pub fn max_random_bytes(mut self, bytes: u32) -> Self {
+        self.max_random_bytes = Some(bytes);
+        self
+    }
+
+    /// Checks requests of type `kind` with `rule` instead of the built-in rules
+    pub fn rule(
+        mut self,
+        kind: MessageKind,
+        rule: impl Fn(&client_message::Message) -> Result<(), Violation> + Send + Sync + 'static,
+    ) -> Self {
+        self.rules.insert(kind, Box::new(rule));
+        self
+    }
+
+    /// Checks `request` against the rules for its type
+    pub fn validate(&self, request: &client_message::Message) -> Result<(), Violation> {
+        if let Some(rule) = self.rules.get(&MessageKind::of(request)) {
+            return rule(request);
+        }
+        match request {
+            client_message::Message::EchoMessage(echo) => {
+                check_len("content", echo.content.len(), self.max_string_len)?;
+            }
+            client_message::Message::EchoBytes(echo) => {
+                check_len("data", echo.data.len(), self.max_bytes_len)?;
+            }
+            client_message::Message::TransformRequest(transform) => {
+                check_len("content", transform.content.len(), self.max_string_len)?;
+            }
+            client_message::Message::CalcRequest(calc) => {
+                check_len("expression", calc.expression.len(), self.max_string_len)?;
+            }
+            client_message::Message::TailLogs(tail) => {
+                check_len("filter", tail.filter.len(), self.max_string_len)?;
+            }
+            client_message::Message::ConnectionHistoryRequest(request) => {
+                check_len("device", request.device.len(), self.max_string_len)?;
+            }
+            client_message::Message::DeadLettersRequest(request) => {
+                check_len("device", request.device.len(), self.max_string_len)?;
+            }
+            client_message::Message::FaultRulesRequest(request) => {
+                for rule in &request.add {
+                    check_len("message_type", rule.message_type.len(), self.max_string_len)?;
+                }
+            }
+            client_message::Message::SubscribeRequest(request) => {
+                check_len("filter", request.filter.len(), self.max_string_len)?;
+            }
+            client_message::Message::UnsubscribeRequest(request) => {
+                check_len("filter", request.filter.len(), self.max_string_len)?;
+            }
+            client_message::Message::PublishRequest(request) => {
+                check_len("topic", request.topic.len(), self.max_string_len)?;
+                check_len("payload", request.payload.len(), self.max_bytes_len)?;
+            }
+            client_message::Message::AddRequest(add) => {
+                if let Some(range) = &self.add_operands {
+                    for (field, operand) in [("a", add.a), ("b", add.b)] {
+                        if !range.contains(&operand) {
+                            return Err(Violation::new(
+                                field,
+                                format!("{} is outside {:?}", operand, range),
+                            ));
+                        }
+                    }
+                }
+            }
+            client_message::Message::RandomRequest(random) => {
+                if let Some(max) = self.max_random_bytes {
+                    if random.num_bytes > max {
+                        return Err(Violation::new(
+                            "num_bytes",
+                            format!("{} is more than the limit of {}", random.num_bytes, max),
+                        ));
+                    }
+                }
+            }
+            client_message::Message::PingRequest(_)
+            | client_message::Message::DescribeRequest(_)
+            | client_message::Message::ResumeRequest(_)
+            | client_message::Message::QuotaRequest(_)
+            | client_message::Message::ResyncRequest(_)
+            | client_message::Message::DiagnosticsRequest(_)
+            | client_message::Message::AvailabilityRequest(_)
+            // Checked by the node its requests came from
+            | client_message::Message::ClusterEvent(_)
+            // Its requests are checked one by one once unpacked
+            | client_message::Message::Batch(_) => {}
+            client_message::Message::TelemetryReport(report) => {
+                if !report.value.is_finite() {
+                    return Err(Violation::new(
+                        "value",
+                        format!("{} is not finite", report.value),
+                    ));
+                }
+                if let Some(range) = &self.telemetry_values {
+                    if !range.contains(&report.value) {
+                        return Err(Violation::new(
+                            "value",
+                            format!("{} is outside {:?}", report.value, range),
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_len(field: &str, len: usize, max: usize) -> Result<(), Violation> {
+    match len > max {
+        true => Err(Violation::new(
+            field,
+            format!("{} bytes, more than the limit of {}", len, max),
+        )),
+        false => Ok(()),
+    }
+}
+
+impl Default for Validator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Middleware for Validator {
+    fn call(&self, request: Request, next: Next<'_>) -> Response {
+        match self.validate(&request.message) {
+            Ok(()) => next.run(request),
+            Err(violation) => {
+                debug!(
+                    "Rejecting {:?} request: {}: {}",
+                    request.kind, violation.field, violation.detail
+                );
+                Ok(server_message::Message::ErrorResponse(ErrorResponse {
+                    code: error_response::Code::Invalid as i32,
+                    field: violation.field,
+                    detail: violation.detail,
+                    ..Default::default()
+                }))
+            }
+        }
+    }
+}
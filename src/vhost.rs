@@ -0,0 +1,53 @@
+//! Virtual hosts, so one listener can serve differently configured customers.
+//!
+//! A [`VirtualHost`] added with
+//! [`Server::virtual_host`](crate::server::Server::virtual_host) is picked by
+//! a connection whose first `ResumeRequest` names it in `vhost`, or by one
+//! whose TLS handshake asked for its name (SNI), as over QUIC. The host can
+//! replace the server's router, so its requests reach other handlers, and its
+//! authorizer, so its devices are checked against another policy. It can also
+//! place its devices in a tenant (see [`crate::tenant`]): a device that names
+//! itself `thermo-1` on the host of tenant `acme` is `acme/thermo-1`, under
+//! that tenant's quota and statistics, whatever ID it gives.
+//!
+//! Connections that name no host get the server's own configuration. One that
+//! names an unknown host, or a second host, is answered with an `INVALID`
+//! error and keeps the configuration it had.
+
+use crate::authz::Authorizer;
+use crate::router::Router;
+use std::sync::Arc;
+
+/// Configuration of the connections that pick one host
+#[derive(Clone, Default)]
+pub struct VirtualHost {
+    pub(crate) tenant: Option<String>,
+    pub(crate) router: Option<Arc<Router>>, // The server's own if `None`
+    pub(crate) authorizer: Option<Arc<dyn Authorizer>>, // The server's own if `None`
+}
+
+impl VirtualHost {
+    /// Creates a host with the server's own configuration
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Places the host's devices in the tenant `name`, registering it if it
+    /// is not registered yet
+    pub fn tenant(mut self, name: &str) -> Self {
+        self.tenant = Some(name.to_string());
+        self
+    }
+
+    /// Answers the host's requests with the handlers registered on `router`
+    pub fn router(mut self, router: Router) -> Self {
+        self.router = Some(Arc::new(router));
+        self
+    }
+
+    /// Asks `authorizer` about the host's requests instead of the server's authorizer
+    pub fn authorizer(mut self, authorizer: impl Authorizer + 'static) -> Self {
+        self.authorizer = Some(Arc::new(authorizer));
+        self
+    }
+}
@@ -0,0 +1,253 @@
+//! Handlers loaded as WebAssembly plugins.
+//!
+//! A [`Plugin`] is middleware (see [`crate::middleware`]) running a WASM
+//! module under [wasmtime](https://wasmtime.dev), so customer-specific
+//! message processing can ship as a module instead of a change to the server,
+//! sandboxed from the rest of the process. Modules are compiled once, when
+//! loaded at startup:
+//!
+//! ```no_run
+//! # use embedded_recruitment_task::{server::Server, wasm::Plugin};
+//! let server = Server::new("localhost:0")?.layer(Plugin::load("plugins/acme.wasm")?);
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! # Guest ABI
+//!
+//! Messages cross the boundary as protobuf bytes. The module exports:
+//!
+//! - `memory`, its linear memory.
+//! - `alloc(len: i32) -> i32`, returning where the host may write `len` bytes.
+//! - `handle(ptr: i32, len: i32) -> i64`, given an encoded `ClientMessage` at
+//!   `ptr`. It returns the address of an encoded `ServerMessage` in the high 32
+//!   bits and its length in the low 32 bits, or 0 to pass the request on.
+//!
+//! The module may import, from the `host` module:
+//!
+//! - `log(level: i32, ptr: i32, len: i32)`, logging the UTF-8 text at `ptr`;
+//!   `level` runs from 1 (error) to 5 (trace).
+//! - `kv_get(key_ptr: i32, key_len: i32, value_ptr: i32, value_cap: i32) -> i32`,
+//!   copying the value stored under the key to `value_ptr` if it fits in
+//!   `value_cap` bytes. It returns the value's length, or -1 if there is none.
+//! - `kv_set(key_ptr: i32, key_len: i32, value_ptr: i32, value_len: i32)`.
+//!
+//! Every request runs in a fresh instance, so a plugin keeps what must outlive
+//! a request in the key-value store, which is shared by all requests to the
+//! plugin. A request runs out of fuel after [`MAX_FUEL`] units, and memory is
+//! capped at [`MAX_MEMORY`]. A plugin that traps, runs out of fuel or returns
+//! bytes that do not decode is logged and the request passed on.
+
+use crate::handler::MessageKind;
+use crate::message::{client_message, server_message, ClientMessage, ServerMessage};
+use crate::middleware::{Middleware, Next, Request, Response};
+use log::{log, warn, Level};
+use prost::Message;
+use std::{
+    collections::HashMap,
+    io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+use wasmtime::{
+    Caller, Config, Engine, Extern, InstancePre, Linker, Module, Store, StoreLimits,
+    StoreLimitsBuilder,
+};
+
+/// Fuel a plugin may burn per request, roughly one unit per instruction
+pub const MAX_FUEL: u64 = 10_000_000;
+
+/// Most linear memory a plugin instance may grow to, in bytes
+pub const MAX_MEMORY: usize = 16 * 1024 * 1024;
+
+type Kv = Mutex<HashMap<Vec<u8>, Vec<u8>>>;
+
+// What host functions see of the plugin while it runs
+struct Host {
+    name: Arc<str>, // Logged with whatever the plugin logs
+    kv: Arc<Kv>,
+    limits: StoreLimits,
+}
+
+/// A WASM module answering requests
+pub struct Plugin {
+    path: PathBuf,
+    engine: Engine,
+    instance: InstancePre<Host>,
+    kinds: Option<Vec<MessageKind>>, // `None` runs the plugin for every type
+    kv: Arc<Kv>,
+}
+
+impl Plugin {
+    /// Compiles the module at `path`, a `.wasm` binary or `.wat` text; an error
+    /// if it cannot be read or compiled, or does not follow the guest ABI
+    pub fn load(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let invalid = |e: wasmtime::Error| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {:#}", path.display(), e),
+            )
+        };
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).map_err(invalid)?;
+        let module = Module::from_file(&engine, &path).map_err(invalid)?;
+        for export in ["memory", "alloc", "handle"] {
+            if module.get_export(export).is_none() {
+                return Err(invalid(wasmtime::Error::msg(format!(
+                    "missing export `{}`",
+                    export
+                ))));
+            }
+        }
+        let mut linker = Linker::new(&engine);
+        link_host(&mut linker).map_err(invalid)?;
+        let instance = linker.instantiate_pre(&module).map_err(invalid)?;
+        Ok(Plugin {
+            path,
+            engine,
+            instance,
+            kinds: None,
+            kv: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// Runs the plugin only for requests of these types; the rest pass on
+    pub fn only(mut self, kinds: &[MessageKind]) -> Self {
+        self.kinds = Some(kinds.to_vec());
+        self
+    }
+
+    /// The plugin's response to `request`; `None` if it passes the request on
+    /// or fails
+    pub fn handle(&self, request: &client_message::Message) -> Option<server_message::Message> {
+        let request = ClientMessage {
+            message: Some(request.clone()),
+            ..Default::default()
+        };
+        match self.call(&request.encode_to_vec()) {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("Plugin {}: {:#}", self.path.display(), e);
+                None
+            }
+        }
+    }
+
+    // Runs `handle` on the encoded request in a fresh instance
+    fn call(&self, request: &[u8]) -> wasmtime::Result<Option<server_message::Message>> {
+        let host = Host {
+            name: self.path.display().to_string().into(),
+            kv: self.kv.clone(),
+            limits: StoreLimitsBuilder::new().memory_size(MAX_MEMORY).build(),
+        };
+        let mut store = Store::new(&self.engine, host);
+        store.limiter(|host| &mut host.limits);
+        store.set_fuel(MAX_FUEL)?;
+        let instance = self.instance.instantiate(&mut store)?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| wasmtime::Error::msg("`memory` is not a memory"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        let handle = instance.get_typed_func::<(i32, i32), i64>(&mut store, "handle")?;
+
+        let len = i32::try_from(request.len())?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory.write(&mut store, ptr as u32 as usize, request)?;
+        let result = handle.call(&mut store, (ptr, len))? as u64;
+        if result == 0 {
+            return Ok(None);
+        }
+        let (ptr, len) = ((result >> 32) as usize, (result as u32) as usize);
+        let bytes = memory
+            .data(&store)
+            .get(ptr..ptr.saturating_add(len))
+            .ok_or_else(|| wasmtime::Error::msg("response outside the plugin's memory"))?;
+        match ServerMessage::decode(bytes)?.message {
+            Some(response) => Ok(Some(response)),
+            None => Err(wasmtime::Error::msg("response without a message")),
+        }
+    }
+}
+
+impl Middleware for Plugin {
+    fn call(&self, request: Request, next: Next<'_>) -> Response {
+        if self
+            .kinds
+            .as_ref()
+            .is_some_and(|k| !k.contains(&request.kind))
+        {
+            return next.run(request);
+        }
+        match self.handle(&request.message) {
+            Some(response) => Ok(response),
+            None => next.run(request),
+        }
+    }
+}
+
+// Defines the `host` functions plugins may import
+fn link_host(linker: &mut Linker<Host>) -> wasmtime::Result<()> {
+    linker.func_wrap(
+        "host",
+        "log",
+        |mut caller: Caller<'_, Host>, level: i32, ptr: i32, len: i32| {
+            let text = read(&mut caller, ptr, len)?;
+            let level = match level {
+                1 => Level::Error,
+                2 => Level::Warn,
+                3 => Level::Info,
+                4 => Level::Debug,
+                _ => Level::Trace,
+            };
+            let name = caller.data().name.clone();
+            log!(level, "Plugin {}: {}", name, String::from_utf8_lossy(&text));
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "host",
+        "kv_get",
+        |mut caller: Caller<'_, Host>, key_ptr: i32, key_len: i32, ptr: i32, cap: i32| {
+            let key = read(&mut caller, key_ptr, key_len)?;
+            let value = caller.data().kv.lock().unwrap().get(&key).cloned();
+            let Some(value) = value else {
+                return Ok(-1);
+            };
+            if value.len() <= cap as u32 as usize {
+                write(&mut caller, ptr, &value)?;
+            }
+            Ok(i32::try_from(value.len())?)
+        },
+    )?;
+    linker.func_wrap(
+        "host",
+        "kv_set",
+        |mut caller: Caller<'_, Host>, key_ptr: i32, key_len: i32, ptr: i32, len: i32| {
+            let key = read(&mut caller, key_ptr, key_len)?;
+            let value = read(&mut caller, ptr, len)?;
+            caller.data().kv.lock().unwrap().insert(key, value);
+            Ok(())
+        },
+    )?;
+    Ok(())
+}
+
+fn memory(caller: &mut Caller<'_, Host>) -> wasmtime::Result<wasmtime::Memory> {
+    match caller.get_export("memory") {
+        Some(Extern::Memory(memory)) => Ok(memory),
+        _ => Err(wasmtime::Error::msg("`memory` is not a memory")),
+    }
+}
+
+// Copies `len` bytes at `ptr` out of the plugin's memory
+fn read(caller: &mut Caller<'_, Host>, ptr: i32, len: i32) -> wasmtime::Result<Vec<u8>> {
+    let mut bytes = vec![0; len as u32 as usize];
+    memory(caller)?.read(&*caller, ptr as u32 as usize, &mut bytes)?;
+    Ok(bytes)
+}
+
+fn write(caller: &mut Caller<'_, Host>, ptr: i32, bytes: &[u8]) -> wasmtime::Result<()> {
+    memory(caller)?.write(&mut *caller, ptr as u32 as usize, bytes)?;
+    Ok(())
+}
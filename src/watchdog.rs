@@ -0,0 +1,78 @@
+//! Detecting a server that has stopped making progress.
+//!
+//! A server built with [`Server::watchdog`](crate::server::Server::watchdog)
+//! runs a thread next to its accept loop that checks, every
+//! [`CHECK_INTERVAL`], that the accept loop has turned within
+//! [`Watchdog::accept_window`] and that no connection handler has spent longer
+//! than [`Watchdog::pool_window`] on the requests of one read. When either
+//! check fails, it logs the server's internal state (pool counters and what
+//! every connection handler is doing), counts the trip in
+//! [`Stats::watchdog_trips`](crate::stats::Stats::watchdog_trips), and takes
+//! its [`WatchdogAction`].
+//!
+//! Unlike [`Server::health`](crate::server::Server::health), which waits to be
+//! asked, the watchdog acts on its own, for deployments without a prober.
+
+use log::error;
+use std::time::Duration;
+
+/// How often the watchdog checks the server
+pub const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What the watchdog does once the server is stuck
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchdogAction {
+    /// Only log the diagnostics, once per stall
+    Log,
+    /// Exit the process with this status, for a supervisor to restart it
+    Exit(i32),
+    /// Replace the process with a fresh copy of itself, with the same arguments
+    #[cfg(unix)]
+    Restart,
+}
+
+/// Stall windows and the action taken when one is exceeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchdog {
+    /// Longest the accept loop may go without turning
+    pub accept_window: Duration,
+    /// Longest a connection handler may spend on the requests of one read
+    pub pool_window: Duration,
+    /// What to do once either window is exceeded
+    pub action: WatchdogAction,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Watchdog {
+            accept_window: Duration::from_secs(30),
+            pool_window: Duration::from_secs(30),
+            action: WatchdogAction::Log,
+        }
+    }
+}
+
+// Takes the configured action once the diagnostics have been logged
+pub(crate) fn act(action: WatchdogAction) {
+    match action {
+        WatchdogAction::Log => {}
+        WatchdogAction::Exit(status) => {
+            error!("Watchdog: exiting with status {}", status);
+            std::process::exit(status);
+        }
+        #[cfg(unix)]
+        WatchdogAction::Restart => {
+            use std::os::unix::process::CommandExt;
+            error!("Watchdog: restarting");
+            // Only returns on failure; the listening socket is closed on exec
+            let error = match std::env::current_exe() {
+                Ok(exe) => std::process::Command::new(exe)
+                    .args(std::env::args_os().skip(1))
+                    .exec(),
+                Err(e) => e,
+            };
+            error!("Watchdog: failed to restart: {}", error);
+            std::process::exit(1);
+        }
+    }
+}
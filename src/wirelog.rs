@@ -0,0 +1,150 @@
+//! Hex-dump logging of wire traffic for diagnosing framing problems.
+//!
+//! Off by default. [`Server::wire_log`] returns the server's [`WireLog`], which
+//! can be switched on and off while the server runs. When enabled, every read
+//! from a client and every frame written back is logged at debug level under
+//! the `wire` target, with the peer address, the length and a hex dump. A
+//! per-second limit keeps a busy server from flooding the log; frames over the
+//! limit are counted and reported with the next logged one.
+//!
+//! [`Server::wire_log`]: crate::server::Server::wire_log
+
+use crate::capture::Direction;
+use log::debug;
+use std::{
+    fmt::Write,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Frames logged per second unless changed with [`WireLog::set_rate_limit`]
+pub const DEFAULT_RATE_LIMIT: u32 = 100;
+
+/// Bytes of each frame included in the dump; the rest is only counted
+pub const MAX_DUMP_BYTES: usize = 256;
+
+/// Runtime switch and rate limiter for wire logging
+#[derive(Debug)]
+pub struct WireLog {
+    enabled: AtomicBool,
+    rate_limit: AtomicU32, // Frames per second
+    window: Mutex<Window>,
+}
+
+// Frames logged and suppressed in the current one-second window
+#[derive(Debug)]
+struct Window {
+    start: Instant,
+    logged: u32,
+    suppressed: u64,
+}
+
+impl WireLog {
+    /// Creates a disabled wire log
+    pub fn new() -> Self {
+        WireLog {
+            enabled: AtomicBool::new(false),
+            rate_limit: AtomicU32::new(DEFAULT_RATE_LIMIT),
+            window: Mutex::new(Window {
+                start: Instant::now(),
+                logged: 0,
+                suppressed: 0,
+            }),
+        }
+    }
+
+    /// Starts logging frames
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    /// Stops logging frames
+    pub fn disable(&self) {
+        self.enabled.store(false, Ordering::Relaxed);
+    }
+
+    /// Whether frames are being logged
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Sets how many frames are logged per second at most
+    pub fn set_rate_limit(&self, frames_per_second: u32) {
+        self.rate_limit.store(frames_per_second, Ordering::Relaxed);
+    }
+
+    /// Logs one read or frame, returning whether it was logged
+    pub fn log(&self, peer: Option<SocketAddr>, direction: Direction, bytes: &[u8]) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+
+        let suppressed = {
+            let mut window = self.window.lock().unwrap();
+            if window.start.elapsed() >= Duration::from_secs(1) {
+                window.start = Instant::now();
+                window.logged = 0;
+            }
+            if window.logged >= self.rate_limit.load(Ordering::Relaxed) {
+                window.suppressed += 1;
+                return false;
+            }
+            window.logged += 1;
+            std::mem::take(&mut window.suppressed)
+        };
+
+        if suppressed > 0 {
+            debug!(target: "wire", "{} frames not logged (rate limit)", suppressed);
+        }
+        let peer = peer.map_or_else(|| "unknown peer".to_string(), |peer| peer.to_string());
+        let arrow = match direction {
+            Direction::Inbound => "<-",
+            Direction::Outbound => "->",
+        };
+        debug!(target: "wire", "{} {} {} bytes\n{}", arrow, peer, bytes.len(), hex_dump(bytes));
+        true
+    }
+}
+
+impl Default for WireLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Formats bytes as offset, hex and ASCII columns, 16 bytes per line
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (line, chunk) in bytes[..bytes.len().min(MAX_DUMP_BYTES)]
+        .chunks(16)
+        .enumerate()
+    {
+        let _ = write!(dump, "{:04x} ", line * 16);
+        for i in 0..16 {
+            match chunk.get(i) {
+                Some(byte) => {
+                    let _ = write!(dump, " {:02x}", byte);
+                }
+                None => dump.push_str("   "),
+            }
+        }
+        dump.push_str("  |");
+        dump.extend(chunk.iter().map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            }
+        }));
+        dump.push_str("|\n");
+    }
+    if bytes.len() > MAX_DUMP_BYTES {
+        let _ = writeln!(dump, "... {} more bytes", bytes.len() - MAX_DUMP_BYTES);
+    }
+    dump.pop(); // No trailing newline; the logger adds its own
+    dump
+}
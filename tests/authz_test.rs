@@ -0,0 +1,78 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::{echo, start};
+use embedded_recruitment_task::authz::{Action, Authorizer, Grant, StaticPolicy};
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::handler::MessageKind;
+use embedded_recruitment_task::message::{
+    client_message, error_response, server_message, PingRequest, ResumeRequest,
+};
+use embedded_recruitment_task::server::Server;
+
+// Sends `request`, returning the code of the `ErrorResponse` it got, if any
+fn refusal(client: &mut Client, request: client_message::Message) -> Option<i32> {
+    client.send(request).expect("Failed to send message");
+    match client
+        .receive()
+        .expect("Failed to receive response")
+        .message
+    {
+        Some(server_message::Message::ErrorResponse(error)) => Some(error.code),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_requests_are_checked_against_the_identity() {
+    let policy = StaticPolicy::new()
+        .everyone(Grant::new().send(MessageKind::Ping))
+        .grant("sensor-1", Grant::new().send(MessageKind::Echo));
+    let (server, handle, port) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .authorizer(policy),
+    );
+    let forbidden = Some(error_response::Code::Forbidden as i32);
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+
+    assert_eq!(refusal(&mut client, echo("hello")), forbidden);
+    let ping = client_message::Message::PingRequest(PingRequest::default());
+    assert_eq!(refusal(&mut client, ping), None);
+
+    let resume = client_message::Message::ResumeRequest(ResumeRequest {
+        device_id: "sensor-1".to_string(),
+        ..Default::default()
+    });
+    assert_eq!(refusal(&mut client, resume), None);
+    assert_eq!(refusal(&mut client, echo("hello")), None);
+    client.disconnect().expect("Failed to disconnect");
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+    assert_eq!(server.stats().denied_requests, 1);
+}
+
+#[test]
+fn test_policy_topics() {
+    let policy = StaticPolicy::new().grant(
+        "thermo-1",
+        Grant::all_requests()
+            .publish("site/+/thermo-1/#")
+            .subscribe("site/commands/#"),
+    );
+    let thermo = Some("thermo-1");
+
+    assert!(policy.authorize(thermo, Action::Send(MessageKind::Calc)));
+    assert!(!policy.authorize(None, Action::Send(MessageKind::Calc)));
+    assert!(policy.authorize(thermo, Action::Publish("site/hall/thermo-1/temperature")));
+    assert!(!policy.authorize(thermo, Action::Publish("site/hall/thermo-2/temperature")));
+    assert!(!policy.authorize(Some("thermo-2"), Action::Publish("site/hall/thermo-1")));
+
+    assert!(policy.authorize(thermo, Action::Subscribe("site/commands/thermo-1")));
+    assert!(policy.authorize(thermo, Action::Subscribe("site/commands/+/urgent")));
+    assert!(!policy.authorize(thermo, Action::Subscribe("site/+/thermo-1")));
+    assert!(!policy.authorize(thermo, Action::Subscribe("site/#")));
+}
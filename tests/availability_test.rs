@@ -0,0 +1,79 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::start;
+use embedded_recruitment_task::availability::Availability;
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::server::Server;
+use std::{env, fs, process, thread, time::Duration};
+
+#[test]
+fn test_ratio_counts_lost_time_once() {
+    let availability = Availability {
+        uptime: Duration::from_secs(100),
+        stalled: Duration::from_secs(1),
+        overloaded: Duration::from_secs(4),
+        ..Default::default()
+    };
+    assert!((availability.ratio() - 0.96).abs() < 1e-9);
+    assert_eq!(Availability::default().ratio(), 1.0);
+}
+
+#[test]
+fn test_restarts_are_counted_in_the_file() {
+    let path = env::temp_dir().join(format!("availability_test_{}", process::id()));
+    let _ = fs::remove_file(&path);
+    let earlier = Server::new("localhost:0").expect("Failed to start server");
+    assert_eq!(earlier.persist_availability(&path).unwrap(), 0);
+    assert_eq!(earlier.stats().availability.uptime, Duration::ZERO);
+
+    let server = Server::new("localhost:0").expect("Failed to start server");
+    assert_eq!(server.persist_availability(&path).unwrap(), 1);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "2\n");
+    let (server, handle, port) = start(server);
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    thread::sleep(Duration::from_millis(20));
+
+    let report = client.availability().expect("Availability failed");
+    assert!(report.persisted);
+    assert_eq!(report.restarts, 1);
+    assert!(report.uptime_ms >= 20, "{:?}", report);
+    assert!(report.started_at_ms > 0);
+    assert!(report.availability > 0.0 && report.availability <= 1.0);
+    assert_eq!(server.stats().availability.restarts, Some(1));
+
+    server.stop();
+    handle.join().unwrap();
+    fs::write(&path, "not a number").unwrap();
+    assert!(Server::new("localhost:0")
+        .unwrap()
+        .persist_availability(&path)
+        .is_err());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_overload_periods_are_timed() {
+    let (server, handle, _) = start(Server::new("localhost:0").expect("Failed to start server"));
+    // Every server has at least no connections waiting
+    server.set_overload_threshold(Some(0));
+    thread::sleep(Duration::from_millis(400));
+    let overloaded = server.stats().availability;
+    assert_eq!(overloaded.overload_periods, 1);
+    assert!(overloaded.overloaded > Duration::ZERO);
+    assert!(overloaded.ratio() < 1.0);
+
+    server.set_overload_threshold(None);
+    thread::sleep(Duration::from_millis(300));
+    let ended = server.stats().availability.overloaded;
+    thread::sleep(Duration::from_millis(200));
+    let availability = server.stats().availability;
+    assert_eq!(availability.overload_periods, 1);
+    assert_eq!(availability.overloaded, ended);
+    assert_eq!(availability.restarts, None);
+
+    server.stop();
+    handle.join().unwrap();
+}
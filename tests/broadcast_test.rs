@@ -0,0 +1,101 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::start;
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::codec::{Bytes, CodecError, MAX_FRAME_SIZE};
+use embedded_recruitment_task::message::{
+    client_message, server_message, Delivery, EchoMessage, ResumeRequest,
+};
+use embedded_recruitment_task::outbox::BROADCAST_SEQUENCE;
+use embedded_recruitment_task::server::Server;
+
+// Connects and names the device, returning the client once the resume is answered
+fn connect_as(port: u16, device: &str) -> Client {
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    client
+        .send(client_message::Message::ResumeRequest(ResumeRequest {
+            device_id: device.to_string(),
+            ..Default::default()
+        }))
+        .expect("Failed to send message");
+    let response = client.receive().expect("Failed to receive response");
+    assert!(matches!(
+        response.message,
+        Some(server_message::Message::ResumeResponse(_))
+    ));
+    client
+}
+
+fn receive_delivery(client: &mut Client) -> Delivery {
+    match client
+        .receive()
+        .expect("Failed to receive delivery")
+        .message
+    {
+        Some(server_message::Message::Delivery(delivery)) => delivery,
+        other => panic!("Expected a Delivery, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_broadcast_reaches_every_connected_device() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let mut devices: Vec<Client> = (1..=3)
+        .map(|device| connect_as(port, &format!("sensor-{}", device)))
+        .collect();
+    // Without a device, a client is not sent broadcasts
+    let mut anonymous = Client::new("localhost", port.into(), 1000);
+    anonymous
+        .connect()
+        .expect("Failed to connect to the server");
+
+    let payload = Bytes::from_static(b"firmware 2.1 available");
+    assert_eq!(server.broadcast(payload.clone()), Ok(3));
+    for client in &mut devices {
+        let delivery = receive_delivery(client);
+        assert_eq!(delivery.sequence, BROADCAST_SEQUENCE);
+        assert_eq!(delivery.payload, payload);
+    }
+    server.send_to("sensor-1", b"queued".to_vec());
+    assert_eq!(receive_delivery(&mut devices[0]).sequence, 1);
+
+    anonymous
+        .send(client_message::Message::EchoMessage(EchoMessage {
+            content: "still answered".to_string(),
+        }))
+        .expect("Failed to send message");
+    assert!(matches!(
+        anonymous
+            .receive()
+            .expect("Failed to receive response")
+            .message,
+        Some(server_message::Message::EchoMessage(_))
+    ));
+
+    for client in devices.iter_mut().chain([&mut anonymous]) {
+        client.disconnect().expect("Failed to disconnect");
+    }
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_broadcast_is_not_kept_for_offline_devices() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    assert_eq!(server.broadcast(b"nobody".to_vec()), Ok(0));
+    assert!(matches!(
+        server.broadcast(vec![0; MAX_FRAME_SIZE]),
+        Err(CodecError::FrameTooLarge(_))
+    ));
+
+    let mut client = connect_as(port, "sensor-1");
+    server.send_to("sensor-1", b"queued".to_vec());
+    assert_eq!(receive_delivery(&mut client).payload, &b"queued"[..]);
+    client.disconnect().expect("Failed to disconnect");
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
@@ -0,0 +1,45 @@
+#![cfg(feature = "message")]
+
+use embedded_recruitment_task::calc::{evaluate, CalcError, MAX_DEPTH};
+
+#[test]
+fn test_arithmetic_with_precedence() {
+    assert_eq!(evaluate("1 + 2 * 3"), Ok(7.0));
+    assert_eq!(evaluate("(1 + 2) * 3"), Ok(9.0));
+    assert_eq!(evaluate("10 - 4 - 3"), Ok(3.0));
+    assert_eq!(evaluate("7 / 2"), Ok(3.5));
+    assert_eq!(evaluate("7 % 4"), Ok(3.0));
+    assert_eq!(evaluate("-(2.5 + 0.5) * -2"), Ok(6.0));
+    assert_eq!(evaluate(" 42 "), Ok(42.0));
+}
+
+#[test]
+fn test_parse_errors() {
+    assert_eq!(evaluate(""), Err(CalcError::UnexpectedEnd));
+    assert_eq!(evaluate("1 +"), Err(CalcError::UnexpectedEnd));
+    assert_eq!(evaluate("(1 + 2"), Err(CalcError::UnexpectedEnd));
+    assert_eq!(evaluate("1 + 2)"), Err(CalcError::Unexpected(5)));
+    assert_eq!(evaluate("2 x 3"), Err(CalcError::Unexpected(2)));
+    assert_eq!(evaluate("1.2.3"), Err(CalcError::Unexpected(0)));
+    assert_eq!(evaluate("sqrt(4)"), Err(CalcError::Unexpected(0)));
+}
+
+#[test]
+fn test_evaluation_errors() {
+    assert_eq!(evaluate("1 / (2 - 2)"), Err(CalcError::DivisionByZero));
+    assert_eq!(evaluate("1 % 0"), Err(CalcError::DivisionByZero));
+    let huge = format!("1{} * 1{}", "0".repeat(300), "0".repeat(300));
+    assert_eq!(evaluate(&huge), Err(CalcError::Overflow));
+}
+
+#[test]
+fn test_nesting_is_bounded() {
+    let nested = |depth| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+    assert_eq!(evaluate(&nested(MAX_DEPTH - 1)), Ok(1.0));
+    assert_eq!(evaluate(&nested(MAX_DEPTH + 1)), Err(CalcError::TooDeep));
+    assert_eq!(
+        evaluate(&"-".repeat(100_000)),
+        Err(CalcError::TooDeep),
+        "Unary operators count towards the depth too"
+    );
+}
@@ -0,0 +1,70 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+use embedded_recruitment_task::capture::{self, CaptureReader, Direction};
+use embedded_recruitment_task::client;
+use embedded_recruitment_task::message::{client_message, AddRequest, EchoMessage};
+use embedded_recruitment_task::server::Server;
+use std::{fs, sync::Arc, thread, time::Duration};
+
+#[test]
+fn test_capture_and_replay_through_handler() {
+    let dir = std::env::temp_dir().join(format!("capture-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let server = Arc::new(
+        Server::new("localhost:8092")
+            .expect("Failed to start server")
+            .capture_to(&dir),
+    );
+    let server_clone = Arc::clone(&server);
+    let handle = thread::spawn(move || server_clone.run().expect("Server encountered an error"));
+
+    let mut client = client::Client::new("localhost", 8092, 1000);
+    client.connect().expect("Failed to connect to the server");
+    let requests = [
+        client_message::Message::AddRequest(AddRequest { a: 2, b: 3 }),
+        client_message::Message::EchoMessage(EchoMessage {
+            content: "Hello, World!".to_string(),
+        }),
+    ];
+    for request in requests {
+        client.send(request).expect("Failed to send message");
+        client.receive().expect("Failed to receive response");
+    }
+    client.disconnect().expect("Failed to disconnect");
+    // Stopping first would have the handler tell the client the server is stopping
+    while server.connection_history(None, 1).is_empty() {
+        thread::sleep(Duration::from_millis(10));
+    }
+    server.stop();
+    handle.join().expect("Server thread panicked");
+
+    let files: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    assert_eq!(files.len(), 1, "Expected one capture file per connection");
+
+    let reader = CaptureReader::open(&files[0]).expect("Failed to open capture");
+    assert!(reader.peer().starts_with("127.0.0.1:") || reader.peer().starts_with("[::1]:"));
+    let records: Vec<_> = reader.map(Result::unwrap).collect();
+    let outbound: Vec<_> = records
+        .iter()
+        .filter(|record| record.direction == Direction::Outbound)
+        .map(|record| record.bytes.clone())
+        .collect();
+    assert_eq!(
+        outbound.len(),
+        2,
+        "Expected one outbound record per response"
+    );
+    assert!(records
+        .windows(2)
+        .all(|pair| pair[0].timestamp <= pair[1].timestamp));
+
+    let replay = capture::replay(&records);
+    assert_eq!(replay.error, None);
+    assert_eq!(replay.responses, outbound, "Replayed responses differ");
+
+    fs::remove_dir_all(&dir).ok();
+}
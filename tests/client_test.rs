@@ -1,12 +1,26 @@
-use embedded_recruitment_task::message::{client_message, server_message, AddRequest, EchoMessage};
+#![cfg(all(feature = "client", feature = "server"))]
+// The baseline tests build their messages field by field
+#![allow(clippy::field_reassign_with_default, clippy::clone_on_copy)]
+
+use embedded_recruitment_task::client;
+use embedded_recruitment_task::codec::{self, FrameDecoder};
+use embedded_recruitment_task::compat::v1;
+use embedded_recruitment_task::flow::INITIAL_WINDOW;
+use embedded_recruitment_task::message::{
+    client_message, error_response, server_message, transform_request, AddRequest, AddResponse,
+    Batch, CalcRequest, CalcResponse, ClientMessage, DescribeRequest, EchoBytes, EchoMessage,
+    ErrorResponse, PingRequest, PingResponse, RandomRequest, ServerMessage, TelemetryReport,
+    TransformRequest, TransformResponse,
+};
 use embedded_recruitment_task::server::Server;
 use std::{
+    io::{Read, Write},
+    net::TcpStream,
     sync::Arc,
     thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
-mod client;
-
 struct ServerHandle {
     server: Arc<Server>,
     handle: JoinHandle<()>,
@@ -234,3 +248,695 @@ fn test_client_add_request() {
 
     server_handle.stop();
 }
+
+#[test]
+fn test_client_echo_bytes() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let port = server.local_addr().expect("No local address").port();
+    let server_handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::new("localhost", port.into(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    // Not valid UTF-8, so it could not be sent as an `EchoMessage`
+    let data = vec![0x00, 0xff, 0xfe, 0x80, b'\n', 0x00];
+    let message = client_message::Message::EchoBytes(EchoBytes { data: data.clone() });
+    assert!(client.send(message).is_ok(), "Failed to send message");
+
+    let response = client.receive().expect("Failed to receive response");
+    assert_eq!(
+        response.message,
+        Some(server_message::Message::EchoBytes(EchoBytes { data })),
+        "Echoed bytes do not match"
+    );
+
+    assert!(
+        client.disconnect().is_ok(),
+        "Failed to disconnect from the server"
+    );
+
+    server_handle.stop();
+}
+
+#[test]
+fn test_client_transform_request() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let port = server.local_addr().expect("No local address").port();
+    let server_handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::new("localhost", port.into(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    let mut transform = |content: &str, op: i32| {
+        let request = TransformRequest {
+            content: content.to_string(),
+            op,
+        };
+        client
+            .send(client_message::Message::TransformRequest(request))
+            .expect("Failed to send message");
+        client
+            .receive()
+            .expect("Failed to receive response")
+            .message
+    };
+    let transformed = |content: &str| {
+        Some(server_message::Message::TransformResponse(
+            TransformResponse {
+                content: content.to_string(),
+            },
+        ))
+    };
+    use transform_request::Op;
+    assert_eq!(
+        transform("Grüße", Op::Uppercase as i32),
+        transformed("GRÜSSE")
+    );
+    assert_eq!(transform("abc", Op::Reverse as i32), transformed("cba"));
+    assert_eq!(transform("Grüße", Op::Length as i32), transformed("7"));
+    assert_eq!(
+        transform("abc", Op::Sha256 as i32),
+        transformed("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+    );
+
+    // An operation the server does not know
+    assert_eq!(
+        transform("abc", 99),
+        Some(server_message::Message::ErrorResponse(ErrorResponse {
+            code: error_response::Code::Invalid as i32,
+            field: "op".to_string(),
+            detail: "unknown operation 99".to_string(),
+            ..Default::default()
+        }))
+    );
+
+    assert!(
+        client.disconnect().is_ok(),
+        "Failed to disconnect from the server"
+    );
+
+    server_handle.stop();
+}
+
+#[test]
+fn test_client_calc_request() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let port = server.local_addr().expect("No local address").port();
+    let server_handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::new("localhost", port.into(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    let mut calc = |expression: &str| {
+        client
+            .send(client_message::Message::CalcRequest(CalcRequest {
+                expression: expression.to_string(),
+            }))
+            .expect("Failed to send message");
+        client
+            .receive()
+            .expect("Failed to receive response")
+            .message
+    };
+    assert_eq!(
+        calc("2 * (3 + 4) / -5"),
+        Some(server_message::Message::CalcResponse(CalcResponse {
+            result: -2.8
+        }))
+    );
+
+    let invalid = |detail: &str| {
+        Some(server_message::Message::ErrorResponse(ErrorResponse {
+            code: error_response::Code::Invalid as i32,
+            field: "expression".to_string(),
+            detail: detail.to_string(),
+            ..Default::default()
+        }))
+    };
+    assert_eq!(calc("1 +"), invalid("unexpected end of expression"));
+    assert_eq!(calc("1 / 0"), invalid("division by zero"));
+
+    assert!(
+        client.disconnect().is_ok(),
+        "Failed to disconnect from the server"
+    );
+
+    server_handle.stop();
+}
+
+#[test]
+fn test_client_describe_request() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let port = server.local_addr().expect("No local address").port();
+    let server_handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::new("localhost", port.into(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    client
+        .send(client_message::Message::DescribeRequest(DescribeRequest {}))
+        .expect("Failed to send message");
+    let description = match client
+        .receive()
+        .expect("Failed to receive response")
+        .message
+    {
+        Some(server_message::Message::DescribeResponse(description)) => description,
+        other => panic!("Expected a DescribeResponse, got {:?}", other),
+    };
+    assert_eq!(description.protocol_version, codec::PROTOCOL_VERSION);
+    assert_eq!(description.max_frame_size as usize, codec::MAX_FRAME_SIZE);
+    assert_eq!(description.server_version, env!("CARGO_PKG_VERSION"));
+    for name in [
+        "EchoMessage",
+        "AddRequest",
+        "CalcRequest",
+        "DescribeRequest",
+    ] {
+        assert!(
+            description.message_types.iter().any(|t| t == name),
+            "{} missing from {:?}",
+            name,
+            description.message_types
+        );
+    }
+    assert!(description.features.iter().any(|f| f == "server"));
+
+    assert!(
+        client.disconnect().is_ok(),
+        "Failed to disconnect from the server"
+    );
+
+    server_handle.stop();
+}
+
+#[test]
+fn test_client_random_request() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let port = server.local_addr().expect("No local address").port();
+    let server_handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::new("localhost", port.into(), 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    let mut random = |num_bytes| {
+        client
+            .send(client_message::Message::RandomRequest(RandomRequest {
+                num_bytes,
+            }))
+            .expect("Failed to send message");
+        client
+            .receive()
+            .expect("Failed to receive response")
+            .message
+    };
+    let data = |response| match response {
+        Some(server_message::Message::RandomResponse(response)) => response.data,
+        other => panic!("Expected a RandomResponse, got {:?}", other),
+    };
+    let first = data(random(32));
+    let second = data(random(32));
+    assert_eq!(first.len(), 32);
+    assert_eq!(second.len(), 32);
+    assert_ne!(first, second, "Two draws of 32 random bytes should differ");
+    assert!(data(random(0)).is_empty());
+    assert_eq!(data(random(1024)).len(), 1024);
+
+    // More than the server hands out at once
+    assert_eq!(
+        random(1025),
+        Some(server_message::Message::ErrorResponse(ErrorResponse {
+            code: error_response::Code::Invalid as i32,
+            field: "num_bytes".to_string(),
+            detail: "more than the limit of 1024".to_string(),
+            ..Default::default()
+        }))
+    );
+
+    assert!(
+        client.disconnect().is_ok(),
+        "Failed to disconnect from the server"
+    );
+
+    server_handle.stop();
+}
+
+#[test]
+fn test_client_ping_and_telemetry() {
+    let server = create_server(8086); // Unique port for this test
+    let server_handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::new("localhost", 8086, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    let ping = PingRequest { timestamp: 1234 };
+    assert!(
+        client
+            .send(client_message::Message::PingRequest(ping))
+            .is_ok(),
+        "Failed to send message"
+    );
+    match client
+        .receive()
+        .expect("Failed to receive response")
+        .message
+    {
+        Some(server_message::Message::PingResponse(pong)) => {
+            assert_eq!(
+                pong.timestamp, ping.timestamp,
+                "Ping timestamp does not match"
+            );
+        }
+        _ => panic!("Expected PingResponse, but received a different message"),
+    }
+
+    let report = TelemetryReport {
+        sensor_id: 3,
+        value: 21.5,
+        timestamp: 5678,
+    };
+    assert!(
+        client
+            .send(client_message::Message::TelemetryReport(report))
+            .is_ok(),
+        "Failed to send message"
+    );
+    match client
+        .receive()
+        .expect("Failed to receive response")
+        .message
+    {
+        Some(server_message::Message::TelemetryAck(ack)) => {
+            assert_eq!(
+                ack.sensor_id, report.sensor_id,
+                "Acked sensor does not match"
+            );
+            assert_eq!(
+                ack.timestamp, report.timestamp,
+                "Acked timestamp does not match"
+            );
+        }
+        _ => panic!("Expected TelemetryAck, but received a different message"),
+    }
+
+    assert!(
+        client.disconnect().is_ok(),
+        "Failed to disconnect from the server"
+    );
+
+    server_handle.stop();
+}
+
+#[test]
+fn test_stop_closes_idle_connections() {
+    let server = create_server(8094); // Unique port for this test
+    let server_handle = setup_server_thread(server.clone());
+
+    // Handlers of idle clients are blocked in `read` until the server closes their sockets
+    let mut clients: Vec<_> = (0..3)
+        .map(|_| {
+            let mut client = client::Client::new("localhost", 8094, 5000);
+            client.connect().expect("Failed to connect to the server");
+            client
+                .send(client_message::Message::PingRequest(PingRequest {
+                    timestamp: 1,
+                }))
+                .expect("Failed to send message");
+            client.receive().expect("Failed to receive response");
+            client
+        })
+        .collect();
+
+    let start = Instant::now();
+    server_handle.stop(); // Waits for `run()`, which waits for every handler
+    assert!(
+        start.elapsed() < Duration::from_secs(1),
+        "Stopping took {:?}",
+        start.elapsed()
+    );
+    assert_eq!(server.stats().pool.active, 0);
+
+    // Closed, not merely silent until the 5 s read timeout
+    let start = Instant::now();
+    for client in &mut clients {
+        assert!(
+            client.receive().is_err(),
+            "Connection should have been closed"
+        );
+    }
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn test_ping_overtakes_queued_telemetry() {
+    let server = create_server(8095); // Unique port for this test
+    let server_handle = setup_server_thread(server.clone());
+
+    // Several telemetry reports followed by a ping, all arriving in one read
+    let mut bytes = Vec::new();
+    for sensor_id in 0..4 {
+        bytes.extend(
+            codec::encode(&ClientMessage {
+                message: Some(client_message::Message::TelemetryReport(TelemetryReport {
+                    sensor_id,
+                    value: 1.0,
+                    timestamp: 1,
+                })),
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+    }
+    bytes.extend(
+        codec::encode(&ClientMessage {
+            message: Some(client_message::Message::PingRequest(PingRequest {
+                timestamp: 42,
+            })),
+            ..Default::default()
+        })
+        .unwrap(),
+    );
+    let mut stream = TcpStream::connect("localhost:8095").expect("Failed to connect");
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream.write_all(&bytes).expect("Failed to send requests");
+
+    let mut decoder = FrameDecoder::new();
+    let mut responses = Vec::new();
+    let mut buffer = [0; 512];
+    while responses.len() < 5 {
+        let n = stream.read(&mut buffer).expect("Failed to read responses");
+        assert!(n > 0, "Server closed the connection");
+        decoder.extend(&buffer[..n]);
+        while let Some(response) = decoder.next_message::<ServerMessage>().unwrap() {
+            responses.push(response.message.unwrap());
+        }
+    }
+
+    assert!(matches!(
+        responses[0],
+        server_message::Message::PingResponse(PingResponse { timestamp: 42 })
+    ));
+    for (sensor_id, response) in (0..).zip(&responses[1..]) {
+        match response {
+            server_message::Message::TelemetryAck(ack) => assert_eq!(ack.sensor_id, sensor_id),
+            other => panic!("Unexpected response {:?}", other),
+        }
+    }
+
+    server_handle.stop();
+}
+
+#[test]
+fn test_responses_are_matched_to_their_streams() {
+    let server = create_server(8096); // Unique port for this test
+    let server_handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::new("localhost", 8096, 1000);
+    client.connect().expect("Failed to connect to the server");
+
+    // Three exchanges in flight at once on one connection
+    client
+        .send_on(
+            1,
+            client_message::Message::EchoMessage(EchoMessage {
+                content: "first".to_string(),
+            }),
+        )
+        .expect("Failed to send message");
+    client
+        .send_on(
+            2,
+            client_message::Message::AddRequest(AddRequest { a: 2, b: 3 }),
+        )
+        .expect("Failed to send message");
+    client
+        .send(client_message::Message::PingRequest(PingRequest {
+            timestamp: 9,
+        }))
+        .expect("Failed to send message");
+
+    // Collected in a different order than sent
+    let add = client.receive_on(2).expect("Failed to receive response");
+    assert_eq!(add.stream_id, 2);
+    assert!(matches!(
+        add.message,
+        Some(server_message::Message::AddResponse(AddResponse {
+            result: 5
+        }))
+    ));
+
+    let ping = client.receive_on(0).expect("Failed to receive response");
+    assert!(matches!(
+        ping.message,
+        Some(server_message::Message::PingResponse(PingResponse {
+            timestamp: 9
+        }))
+    ));
+
+    let echo = client.receive().expect("Failed to receive response");
+    assert_eq!(echo.stream_id, 1);
+    match echo.message {
+        Some(server_message::Message::EchoMessage(echo)) => assert_eq!(echo.content, "first"),
+        other => panic!("Unexpected response {:?}", other),
+    }
+
+    assert!(client.disconnect().is_ok());
+    server_handle.stop();
+}
+
+#[test]
+fn test_pipelining_beyond_the_window_waits_for_credits() {
+    let server = create_server(8097); // Unique port for this test
+    let server_handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::new("localhost", 8097, 1000);
+    client.connect().expect("Failed to connect to the server");
+
+    // Far more requests than one window; sending blocks on credits instead of failing
+    let requests = 5 * INITIAL_WINDOW as i32;
+    for i in 0..requests {
+        client
+            .send_on(
+                4,
+                client_message::Message::AddRequest(AddRequest { a: i, b: 1 }),
+            )
+            .expect("Failed to send message");
+    }
+    for i in 0..requests {
+        let response = client.receive_on(4).expect("Failed to receive response");
+        assert!(
+            matches!(
+                response.message,
+                Some(server_message::Message::AddResponse(AddResponse { result })) if result == i + 1
+            ),
+            "Unexpected response {:?}",
+            response
+        );
+    }
+
+    assert!(client.disconnect().is_ok());
+    server_handle.stop();
+}
+
+#[test]
+fn test_exceeding_the_window_closes_the_connection() {
+    let server = create_server(8098); // Unique port for this test
+    let server_handle = setup_server_thread(server.clone());
+
+    // A raw client that ignores flow control, though it states a version that has it
+    let mut bytes = Vec::new();
+    for _ in 0..=INITIAL_WINDOW {
+        bytes.extend(
+            codec::encode(&ClientMessage {
+                message: Some(client_message::Message::PingRequest(PingRequest {
+                    timestamp: 1,
+                })),
+                protocol_version: codec::PROTOCOL_VERSION,
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+    }
+    let mut stream = TcpStream::connect("localhost:8098").expect("Failed to connect");
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream.write_all(&bytes).expect("Failed to send requests");
+
+    // Closed by the server, either cleanly or with a reset if requests were left unread
+    let mut received = Vec::new();
+    if let Err(e) = stream.read_to_end(&mut received) {
+        assert_eq!(
+            e.kind(),
+            std::io::ErrorKind::ConnectionReset,
+            "Connection should be closed, not time out"
+        );
+    }
+
+    server_handle.stop();
+}
+
+#[test]
+fn test_first_revision_clients_get_no_credits() {
+    let server = create_server(8087); // Unique port for this test
+    let server_handle = setup_server_thread(server.clone());
+    let mut stream = TcpStream::connect("localhost:8087").expect("Failed to connect");
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let add = |a| {
+        codec::encode(&v1::ClientMessage {
+            message: Some(v1::client_message::Message::AddRequest(v1::AddRequest {
+                a,
+                b: 1,
+            })),
+        })
+        .unwrap()
+    };
+    let mut decoder = FrameDecoder::new();
+    let mut buffer = [0; 512];
+    let mut next_response = |stream: &mut TcpStream| loop {
+        if let Some(response) = decoder.next_message::<v1::ServerMessage>().unwrap() {
+            return response.message;
+        }
+        let n = stream.read(&mut buffer).expect("Failed to read response");
+        assert!(n > 0, "Server closed the connection");
+        decoder.extend(&buffer[..n]);
+    };
+
+    // Each response is the next frame, however many requests went before
+    for a in 0..3 * INITIAL_WINDOW as i32 {
+        stream.write_all(&add(a)).expect("Failed to send request");
+        assert_eq!(
+            next_response(&mut stream),
+            Some(v1::server_message::Message::AddResponse(v1::AddResponse {
+                result: a + 1
+            }))
+        );
+    }
+    // Nor is the client held to a window it never heard of
+    let pipelined: Vec<u8> = (0..2 * INITIAL_WINDOW as i32).flat_map(add).collect();
+    stream
+        .write_all(&pipelined)
+        .expect("Failed to send requests");
+    for a in 0..2 * INITIAL_WINDOW as i32 {
+        assert_eq!(
+            next_response(&mut stream),
+            Some(v1::server_message::Message::AddResponse(v1::AddResponse {
+                result: a + 1
+            }))
+        );
+    }
+
+    server_handle.stop();
+}
+
+#[test]
+fn test_batched_requests_wait_for_the_window() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let port = server.local_addr().expect("No local address").port();
+    let server_handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::new("localhost", port.into(), 1000);
+    client
+        .set_batching(Some(client::Batching {
+            max_bytes: 4096,
+            max_delay: Duration::from_millis(200),
+            envelope: false,
+        }))
+        .expect("Failed to set batching");
+    client.connect().expect("Failed to connect to the server");
+    let report = |sensor_id| {
+        client_message::Message::TelemetryReport(TelemetryReport {
+            sensor_id,
+            value: 1.0,
+            timestamp: 1,
+        })
+    };
+    client.send(report(1)).expect("Failed to send message");
+    client.send(report(2)).expect("Failed to send message");
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(server.stats().requests, 0, "Requests should be held back");
+
+    // Past the window, the next send writes all three
+    thread::sleep(Duration::from_millis(150));
+    client.send(report(3)).expect("Failed to send message");
+    for sensor_id in 1..=3 {
+        match client
+            .receive()
+            .expect("Failed to receive response")
+            .message
+        {
+            Some(server_message::Message::TelemetryAck(ack)) => {
+                assert_eq!(ack.sensor_id, sensor_id)
+            }
+            other => panic!("Expected TelemetryAck, got {:?}", other),
+        }
+    }
+
+    client.send(report(4)).expect("Failed to send message");
+    client.flush().expect("Failed to flush");
+    assert!(client.receive().is_ok());
+    assert!(client.disconnect().is_ok());
+    server_handle.stop();
+}
+
+#[test]
+fn test_batch_envelope_requests_are_answered_one_by_one() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let port = server.local_addr().expect("No local address").port();
+    let server_handle = setup_server_thread(server.clone());
+
+    let mut client = client::Client::new("localhost", port.into(), 1000);
+    client
+        .set_batching(Some(client::Batching {
+            max_bytes: 4096,
+            max_delay: Duration::from_secs(10),
+            envelope: true,
+        }))
+        .expect("Failed to set batching");
+    client.connect().expect("Failed to connect to the server");
+    let mut echo = EchoMessage::default();
+    echo.content = "batched".to_string();
+    client
+        .send(client_message::Message::AddRequest(AddRequest {
+            a: 2,
+            b: 3,
+        }))
+        .expect("Failed to send message");
+    client
+        .send(client_message::Message::EchoMessage(echo.clone()))
+        .expect("Failed to send message");
+    client.flush().expect("Failed to flush");
+    assert_eq!(
+        client.receive().expect("Failed to receive").message,
+        Some(server_message::Message::AddResponse(AddResponse {
+            result: 5
+        }))
+    );
+    assert_eq!(
+        client.receive().expect("Failed to receive").message,
+        Some(server_message::Message::EchoMessage(echo))
+    );
+    assert_eq!(server.stats().requests, 2);
+
+    // A batch inside a batch is refused
+    let nested = client_message::Message::Batch(Batch {
+        messages: vec![ClientMessage {
+            message: Some(client_message::Message::Batch(Batch::default())),
+            ..Default::default()
+        }],
+    });
+    client.send(nested).expect("Failed to send message");
+    match client.receive().expect("Failed to receive").message {
+        Some(server_message::Message::ErrorResponse(error)) => {
+            assert_eq!(error.code, error_response::Code::Unsupported as i32)
+        }
+        other => panic!("Expected ErrorResponse, got {:?}", other),
+    }
+
+    assert!(client.disconnect().is_ok());
+    server_handle.stop();
+}
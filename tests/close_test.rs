@@ -0,0 +1,105 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::start;
+use embedded_recruitment_task::authz::StaticPolicy;
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::close::Disconnected;
+use embedded_recruitment_task::message::{close::Reason, server_message, ServerMessage};
+use embedded_recruitment_task::server::Server;
+use prost::Message;
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+// The reason the server gave in the error `result` ended with
+fn reason<T: std::fmt::Debug>(result: io::Result<T>) -> Reason {
+    let e = result.expect_err("Not closed");
+    assert_eq!(e.kind(), io::ErrorKind::ConnectionAborted, "{}", e);
+    Disconnected::of(&e).expect("No reason given").reason
+}
+
+#[test]
+fn test_idle_and_denied_clients_are_told() {
+    let (server, handle, port) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .authorizer(StaticPolicy::new()),
+    );
+    server.set_first_frame_deadline(Some(Duration::from_millis(100)));
+    let mut idle = Client::new("localhost", port.into(), 2000);
+    idle.connect().expect("Failed to connect to the server");
+    assert_eq!(reason(idle.receive()), Reason::IdleTimeout);
+    let disconnected = idle.disconnect_reason().expect("No reason kept");
+    assert!(
+        disconnected.detail.contains("first frame"),
+        "{}",
+        disconnected
+    );
+
+    server.set_first_frame_deadline(None);
+    server.set_denial_limit(Some(2));
+    let mut denied = Client::new("localhost", port.into(), 2000);
+    denied.connect().expect("Failed to connect to the server");
+    for _ in 0..2 {
+        let refused = denied.echo("hello").unwrap_err();
+        assert_eq!(refused.kind(), io::ErrorKind::PermissionDenied);
+    }
+    assert_eq!(reason(denied.receive()), Reason::AuthFailure);
+
+    server.stop();
+    handle.join().unwrap();
+    let history = server.connection_history(None, 2);
+    assert_eq!(history[0].reason, Reason::AuthFailure);
+    assert_eq!(history[1].reason, Reason::IdleTimeout);
+}
+
+#[test]
+fn test_protocol_errors_are_named() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let mut stream = TcpStream::connect(("localhost", port)).expect("Failed to connect");
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .expect("Failed to set timeout");
+    // A three byte frame that is not a message
+    stream.write_all(&[3, 0xff, 0xff, 0xff]).unwrap();
+    let mut received = Vec::new();
+    stream
+        .read_to_end(&mut received)
+        .expect("Not closed cleanly");
+    let last = ServerMessage::decode_length_delimited(received.as_slice()).expect("Not a frame");
+    let Some(server_message::Message::Close(close)) = last.message else {
+        panic!("Unexpected response {:?}", last);
+    };
+    assert_eq!(close.reason(), Reason::ProtocolError);
+    assert!(!close.detail.is_empty());
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+#[test]
+fn test_kicked_and_shut_down_clients_are_told() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let mut device = Client::new("localhost", port.into(), 2000);
+    device.connect().expect("Failed to connect to the server");
+    device.subscribe("thermo-1").expect("Failed to subscribe");
+    let mut other = Client::new("localhost", port.into(), 2000);
+    other.connect().expect("Failed to connect to the server");
+    assert_eq!(other.add(1, 2).expect("Add failed"), 3);
+
+    assert_eq!(server.kick("thermo-2"), 0);
+    assert_eq!(server.kick("thermo-1"), 1);
+    assert_eq!(reason(device.next_push()), Reason::Kicked);
+    // Others stay connected
+    assert_eq!(other.add(2, 3).expect("Add failed"), 5);
+
+    server.stop();
+    assert_eq!(reason(other.receive()), Reason::ServerShutdown);
+    handle.join().unwrap();
+    let history = server.connection_history(Some("thermo-1"), 1);
+    assert_eq!(history[0].reason, Reason::Kicked);
+}
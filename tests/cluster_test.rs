@@ -0,0 +1,224 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::start;
+use embedded_recruitment_task::authz::{Grant, StaticPolicy};
+use embedded_recruitment_task::client::{Client, Push};
+use embedded_recruitment_task::cluster::Cluster;
+use embedded_recruitment_task::handler::MessageKind;
+use embedded_recruitment_task::message::{
+    client_message, server_message, ClusterEvent, Delivery, ResumeRequest,
+};
+use embedded_recruitment_task::server::Server;
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+// Devices may do anything but admin requests; only the nodes may send cluster events
+fn policy() -> StaticPolicy {
+    let node = || {
+        Grant::new()
+            .send(MessageKind::Resume)
+            .send(MessageKind::Cluster)
+    };
+    StaticPolicy::new()
+        .everyone(Grant::all_requests().publish("#").subscribe("#"))
+        .grant("a", node())
+        .grant("b", node())
+}
+
+// Two servers clustered as the nodes "a" and "b"; returns them with their ports
+fn pair() -> Vec<(Arc<Server>, thread::JoinHandle<()>, u16)> {
+    let a = Server::new("localhost:0").expect("Failed to start server");
+    let b = Server::new("localhost:0").expect("Failed to start server");
+    let (addr_a, addr_b) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+    vec![
+        start(
+            a.authorizer(policy())
+                .cluster(Cluster::new("a").peer("b", &addr_b.to_string())),
+        ),
+        start(
+            b.authorizer(policy())
+                .cluster(Cluster::new("b").peer("a", &addr_a.to_string())),
+        ),
+    ]
+}
+
+fn connect(port: u16) -> Client {
+    connect_with(port, 2000)
+}
+
+fn connect_with(port: u16, timeout_ms: u64) -> Client {
+    let mut client = Client::new("localhost", port.into(), timeout_ms);
+    client.connect().expect("Failed to connect to the server");
+    client
+}
+
+// Connects and names the device, returning the client once the resume is answered
+fn connect_as(port: u16, device: &str) -> Client {
+    let mut client = connect(port);
+    client
+        .send(client_message::Message::ResumeRequest(ResumeRequest {
+            device_id: device.to_string(),
+            ..Default::default()
+        }))
+        .expect("Failed to send message");
+    let response = client.receive().expect("Failed to receive response");
+    assert!(matches!(
+        response.message,
+        Some(server_message::Message::ResumeResponse(_))
+    ));
+    client
+}
+
+fn receive_delivery(client: &mut Client) -> Delivery {
+    match client.next_push().expect("No delivery") {
+        Push::Delivery(delivery) => delivery,
+        other => panic!("Expected a Delivery, got {:?}", other),
+    }
+}
+
+// Repeats `attempt` until it holds, as what one node tells another arrives on its own time
+fn eventually(what: &str, mut attempt: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !attempt() {
+        assert!(Instant::now() < deadline, "{}", what);
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+fn stop(nodes: Vec<(Arc<Server>, thread::JoinHandle<()>, u16)>) {
+    for (server, handle, _) in nodes {
+        server.stop();
+        handle.join().unwrap();
+    }
+}
+
+#[test]
+fn test_publications_reach_subscribers_on_other_nodes() {
+    let nodes = pair();
+    let (port_a, port_b) = (nodes[0].2, nodes[1].2);
+    let mut subscriber = connect_with(port_b, 300); // Publications sent too early are lost
+    subscriber.subscribe_to("site/#").expect("Subscribe failed");
+    let mut publisher = connect(port_a);
+
+    // Counted on the node it was published on, which has no subscribers itself
+    eventually("Subscription not shared", || {
+        assert_eq!(publisher.publish("site/a/temp", &b"21"[..]).unwrap(), 0);
+        matches!(
+            subscriber.next_push(),
+            Ok(Push::Publication(publication)) if publication.topic == "site/a/temp"
+        )
+    });
+
+    // Subscribers on the publishing node still get it once
+    let mut local = connect(port_a);
+    local.subscribe_to("site/+/temp").expect("Subscribe failed");
+    assert_eq!(publisher.publish("site/b/temp", &b"19"[..]).unwrap(), 1);
+    for client in [&mut local, &mut subscriber] {
+        match client.next_push().expect("No publication") {
+            Push::Publication(publication) => assert_eq!(&publication.payload[..], b"19"),
+            other => panic!("Unexpected push {:?}", other),
+        }
+    }
+
+    // Once the last subscription ends the node is not sent any more
+    assert!(subscriber.unsubscribe_from("site/#").unwrap());
+    let requests = || nodes[1].0.stats().requests;
+    eventually("Unsubscription not shared", || {
+        let before = requests();
+        publisher.publish("site/c/temp", &b""[..]).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        requests() == before
+    });
+
+    stop(nodes);
+}
+
+#[test]
+fn test_retained_messages_reach_every_node() {
+    let nodes = pair();
+    let (port_a, port_b) = (nodes[0].2, nodes[1].2);
+    let mut publisher = connect(port_a);
+    publisher
+        .publish_retained("site/hall/temp", &b"20"[..])
+        .unwrap();
+
+    eventually("Retained message not shared", || {
+        let mut subscriber = connect_with(port_b, 300);
+        subscriber
+            .subscribe_to("site/+/temp")
+            .expect("Subscribe failed");
+        matches!(
+            subscriber.next_push(),
+            Ok(Push::Publication(publication)) if &publication.payload[..] == b"20"
+        )
+    });
+
+    stop(nodes);
+}
+
+#[test]
+fn test_messages_for_a_device_are_queued_on_its_node() {
+    let nodes = pair();
+    let (server_a, port_b) = (Arc::clone(&nodes[0].0), nodes[1].2);
+
+    // Queued where it was sent while the device has named itself nowhere
+    server_a.send_to("valve-1", b"early".to_vec());
+    assert_eq!(server_a.queue_depth("valve-1"), 1);
+
+    // Handed over once it names itself on the other node, and numbered there
+    let mut device = connect_as(port_b, "valve-1");
+    let early = receive_delivery(&mut device);
+    assert_eq!((early.sequence, &early.payload[..]), (1, &b"early"[..]));
+    assert_eq!(server_a.queue_depth("valve-1"), 0);
+
+    // Later messages go straight to its node
+    server_a.send_to("valve-1", b"late".to_vec());
+    assert_eq!(server_a.queue_depth("valve-1"), 0);
+    let late = receive_delivery(&mut device);
+    assert_eq!((late.sequence, &late.payload[..]), (2, &b"late"[..]));
+
+    stop(nodes);
+}
+
+#[test]
+fn test_cluster_events_only_from_the_node_the_link_named() {
+    let nodes = pair();
+    let port_a = nodes[0].2;
+    let event = |node: &str| {
+        client_message::Message::ClusterEvent(ClusterEvent {
+            node: node.to_string(),
+            event: None,
+        })
+    };
+
+    // A device is not a node
+    let mut device = connect_as(port_a, "sensor-1");
+    device.send(event("sensor-1")).expect("Failed to send");
+    let response = device.receive().expect("Failed to receive");
+    assert!(matches!(
+        response.message,
+        Some(server_message::Message::ErrorResponse(_))
+    ));
+
+    // Nor may a node speak for another
+    let mut node = connect_as(port_a, "b");
+    node.send(event("c")).expect("Failed to send");
+    let response = node.receive().expect("Failed to receive");
+    assert!(matches!(
+        response.message,
+        Some(server_message::Message::ErrorResponse(_))
+    ));
+    node.send(event("b")).expect("Failed to send");
+    let response = node.receive().expect("Failed to receive");
+    assert!(matches!(
+        response.message,
+        Some(server_message::Message::ClusterAck(_))
+    ));
+
+    stop(nodes);
+}
@@ -0,0 +1,102 @@
+#![cfg(feature = "message")]
+
+mod common;
+
+use common::echo;
+use embedded_recruitment_task::codec::{self, CodecError, FrameDecoder, MAX_FRAME_SIZE};
+use embedded_recruitment_task::message::{client_message, ClientMessage, Delivery};
+
+fn request(message: client_message::Message) -> ClientMessage {
+    ClientMessage {
+        message: Some(message),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_frame_split_across_reads() {
+    let message = request(echo("Hello, World!"));
+    let frame = codec::encode(&message).expect("Failed to encode message");
+
+    let mut decoder = FrameDecoder::new();
+    for byte in &frame[..frame.len() - 1] {
+        decoder.extend(&[*byte]);
+        assert_eq!(
+            decoder.next_message::<ClientMessage>(),
+            Ok(None),
+            "Decoder returned a message before the frame was complete"
+        );
+    }
+
+    decoder.extend(&frame[frame.len() - 1..]);
+    assert_eq!(decoder.next_message(), Ok(Some(message)));
+    assert_eq!(decoder.buffered(), 0, "Decoder kept bytes after the frame");
+}
+
+#[test]
+fn test_multiple_frames_in_one_read() {
+    let messages = vec![
+        request(echo("Hello, World!")),
+        request(echo("How are you?")),
+        request(echo("")),
+    ];
+
+    let mut bytes = Vec::new();
+    for message in &messages {
+        bytes.extend(codec::encode(message).expect("Failed to encode message"));
+    }
+
+    let mut decoder = FrameDecoder::new();
+    decoder.extend(&bytes);
+    for message in messages {
+        assert_eq!(decoder.next_message(), Ok(Some(message)));
+    }
+    assert_eq!(decoder.next_message::<ClientMessage>(), Ok(None));
+}
+
+#[test]
+fn test_oversized_frames_are_rejected() {
+    let message = request(echo(&"x".repeat(MAX_FRAME_SIZE)));
+    assert!(
+        matches!(codec::encode(&message), Err(CodecError::FrameTooLarge(_))),
+        "Encoder accepted a frame above the size limit"
+    );
+
+    // A length prefix announcing more than MAX_FRAME_SIZE bytes
+    let mut decoder = FrameDecoder::new();
+    decoder.extend(&[0x81, 0x80, 0x08]);
+    assert!(matches!(
+        decoder.next_frame(),
+        Err(CodecError::FrameTooLarge(_))
+    ));
+
+    // A length prefix that never terminates
+    let mut decoder = FrameDecoder::new();
+    decoder.extend(&[0xff, 0xff, 0xff, 0xff]);
+    assert_eq!(decoder.next_frame(), Err(CodecError::InvalidLength));
+}
+
+#[test]
+fn test_shared_frames_are_not_copied() {
+    let delivery = Delivery {
+        sequence: 7,
+        payload: b"sensor reading".to_vec().into(),
+    };
+    let mut stream = codec::encode(&delivery).expect("Failed to encode message");
+    stream.extend(codec::encode(&request(echo("next"))).expect("Failed to encode message"));
+
+    let mut decoder = FrameDecoder::new();
+    decoder.extend(&stream);
+    let body = (decoder.next_frame_bytes())
+        .expect("Valid frame")
+        .expect("Complete frame");
+    let decoded: Delivery = codec::decode_bytes(body.clone()).expect("Failed to decode");
+    assert_eq!(decoded, delivery);
+    assert!(body.as_ptr_range().contains(&decoded.payload.as_ptr()));
+
+    let next: ClientMessage = (decoder.next_message())
+        .expect("Valid frame")
+        .expect("Complete frame");
+    assert_eq!(next, request(echo("next")));
+    assert_eq!(decoder.buffered(), 0);
+}
@@ -0,0 +1,75 @@
+//! Helpers shared by the tests: message builders, and running a server on a
+//! thread of its own.
+
+#![allow(dead_code)] // Not every test binary uses every helper
+
+#[cfg(feature = "client")]
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::message::{
+    client_message, server_message, AddRequest, AddResponse, EchoMessage,
+};
+#[cfg(feature = "server")]
+use embedded_recruitment_task::server::Server;
+#[cfg(feature = "server")]
+use std::{sync::Arc, thread};
+
+/// An echo request carrying `content`
+pub fn echo(content: &str) -> client_message::Message {
+    client_message::Message::EchoMessage(EchoMessage {
+        content: content.to_string(),
+    })
+}
+
+/// An addition request
+pub fn add(a: i32, b: i32) -> client_message::Message {
+    client_message::Message::AddRequest(AddRequest { a, b })
+}
+
+/// The response to an addition that came to `result`
+pub fn sum(result: i32) -> server_message::Message {
+    server_message::Message::AddResponse(AddResponse { result })
+}
+
+/// Sends `message` and returns what the server answered with
+#[cfg(feature = "client")]
+pub fn call(
+    client: &mut Client,
+    message: client_message::Message,
+) -> Option<server_message::Message> {
+    call_with_id(client, 0, message)
+}
+
+/// Like [`call`], tagging the request with `message_id`
+#[cfg(feature = "client")]
+pub fn call_with_id(
+    client: &mut Client,
+    message_id: u64,
+    message: client_message::Message,
+) -> Option<server_message::Message> {
+    client
+        .send_with_id(0, message_id, message)
+        .expect("Failed to send message");
+    client
+        .receive()
+        .expect("Failed to receive response")
+        .message
+}
+
+/// Runs `server` on a thread of its own; returns it, the thread and the port it listens on
+#[cfg(feature = "server")]
+pub fn start(server: Server) -> (Arc<Server>, thread::JoinHandle<()>, u16) {
+    let server = Arc::new(server);
+    let port = server.local_addr().expect("No local address").port();
+    let server_clone = Arc::clone(&server);
+    let handle = thread::spawn(move || server_clone.run().expect("Server encountered an error"));
+    (server, handle, port)
+}
+
+/// Like [`start`], with a client already connected to the server
+#[cfg(all(feature = "client", feature = "server"))]
+pub fn start_connected(server: Server) -> (Arc<Server>, thread::JoinHandle<()>, Client) {
+    let (server, handle, port) = start(server);
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    (server, handle, client)
+}
@@ -0,0 +1,119 @@
+#![cfg(feature = "message")]
+
+use embedded_recruitment_task::compat::{convert, v1};
+use embedded_recruitment_task::message::{
+    client_message, server_message, AddRequest, AddResponse, ClientMessage, EchoMessage,
+    PingResponse, ServerMessage,
+};
+use prost::Message;
+
+// Frames as encoded by firmware built against the first revision
+const V1_ECHO: &[u8] = &[0x0a, 0x04, 0x0a, 0x02, b'h', b'i'];
+const V1_ADD_REQUEST: &[u8] = &[
+    0x12, 0x0d, 0x08, 0x02, 0x10, 0xfd, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01,
+];
+const V1_ADD_RESPONSE: &[u8] = &[
+    0x12, 0x0b, 0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01,
+];
+
+fn v1_echo() -> v1::ClientMessage {
+    v1::ClientMessage {
+        message: Some(v1::client_message::Message::EchoMessage(v1::EchoMessage {
+            content: "hi".to_string(),
+        })),
+    }
+}
+
+fn v1_add() -> v1::ClientMessage {
+    v1::ClientMessage {
+        message: Some(v1::client_message::Message::AddRequest(v1::AddRequest {
+            a: 2,
+            b: -3,
+        })),
+    }
+}
+
+#[test]
+fn test_golden_bytes_match_the_first_revision() {
+    assert_eq!(v1_echo().encode_to_vec(), V1_ECHO);
+    assert_eq!(v1_add().encode_to_vec(), V1_ADD_REQUEST);
+    let response = v1::ServerMessage {
+        message: Some(v1::server_message::Message::AddResponse(v1::AddResponse {
+            result: -1,
+        })),
+    };
+    assert_eq!(response.encode_to_vec(), V1_ADD_RESPONSE);
+}
+
+#[test]
+fn test_current_code_decodes_first_revision_requests() {
+    let echo = ClientMessage::decode(V1_ECHO).expect("Failed to decode echo");
+    assert_eq!(
+        echo,
+        ClientMessage {
+            message: Some(client_message::Message::EchoMessage(EchoMessage {
+                content: "hi".to_string()
+            })),
+            ..Default::default()
+        },
+        "Fields added since default to 0"
+    );
+    let add = ClientMessage::decode(V1_ADD_REQUEST).expect("Failed to decode add");
+    assert_eq!(
+        add.message,
+        Some(client_message::Message::AddRequest(AddRequest {
+            a: 2,
+            b: -3
+        }))
+    );
+    assert_eq!(add.message_id, 0);
+    assert_eq!(add.stream_id, 0);
+}
+
+#[test]
+fn test_first_revision_decodes_current_responses() {
+    let response = ServerMessage {
+        message: Some(server_message::Message::AddResponse(AddResponse {
+            result: -1,
+        })),
+        message_id: 7,
+        stream_id: 3,
+    };
+    let old: v1::ServerMessage = convert(&response).expect("Failed to decode as v1");
+    assert_eq!(
+        old.message,
+        Some(v1::server_message::Message::AddResponse(v1::AddResponse {
+            result: -1
+        })),
+        "Unknown envelope fields are skipped"
+    );
+
+    // Without the envelope fields, the bytes are exactly what v1 sent
+    let bare = ServerMessage {
+        message_id: 0,
+        stream_id: 0,
+        ..response
+    };
+    assert_eq!(bare.encode_to_vec(), V1_ADD_RESPONSE);
+}
+
+#[test]
+fn test_first_revision_skips_messages_added_since() {
+    let response = ServerMessage {
+        message: Some(server_message::Message::PingResponse(PingResponse {
+            timestamp: 42,
+        })),
+        ..Default::default()
+    };
+    let old: v1::ServerMessage = convert(&response).expect("Failed to decode as v1");
+    assert_eq!(old.message, None, "An unknown oneof case decodes as empty");
+}
+
+#[test]
+fn test_round_trip_through_the_first_revision() {
+    for request in [v1_echo(), v1_add()] {
+        let current: ClientMessage = convert(&request).expect("Failed to decode as current");
+        let back: v1::ClientMessage = convert(&current).expect("Failed to decode as v1");
+        assert_eq!(back, request);
+    }
+}
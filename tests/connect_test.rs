@@ -0,0 +1,68 @@
+#![cfg(feature = "client")]
+
+use embedded_recruitment_task::connect::{attempt_order, connect, CONNECTION_ATTEMPT_DELAY};
+use std::{
+    net::{SocketAddr, TcpListener},
+    time::{Duration, Instant},
+};
+
+fn addr(s: &str) -> SocketAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn test_families_are_interleaved() {
+    let addrs = [
+        addr("[::1]:1"),
+        addr("[::2]:1"),
+        addr("[::3]:1"),
+        addr("10.0.0.1:1"),
+        addr("10.0.0.2:1"),
+    ];
+    assert_eq!(
+        attempt_order(&addrs),
+        [
+            addr("[::1]:1"),
+            addr("10.0.0.1:1"),
+            addr("[::2]:1"),
+            addr("10.0.0.2:1"),
+            addr("[::3]:1"),
+        ]
+    );
+    assert!(attempt_order(&[]).is_empty());
+}
+
+#[test]
+fn test_unreachable_addresses_are_skipped() {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind");
+    let good = listener.local_addr().unwrap();
+    // Closed ports refuse, so the attempts behind them start without waiting out a head start
+    let closed = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let also_closed = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let start = Instant::now();
+    let stream = connect(&[closed, also_closed, good], Duration::from_secs(5))
+        .expect("Should reach the listening address");
+    assert_eq!(stream.peer_addr().unwrap(), good);
+    assert!(
+        start.elapsed() < CONNECTION_ATTEMPT_DELAY,
+        "Waited out a head start: {:?}",
+        start.elapsed()
+    );
+}
+
+#[test]
+fn test_all_unreachable_reports_the_error() {
+    let closed = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    assert!(connect(&[closed], Duration::from_secs(1)).is_err());
+    assert!(connect(&[], Duration::from_secs(1)).is_err());
+}
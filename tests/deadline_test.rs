@@ -0,0 +1,128 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::start;
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::message::{
+    client_message, close::Reason, server_message, AddRequest, ServerMessage,
+};
+use embedded_recruitment_task::server::{Server, DEFAULT_FIRST_FRAME_DEADLINE};
+use prost::Message;
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+
+// Waits up to `limit` for the server to close the connection, returning how long
+// that took and the reason it gave, unless the connection was reset before it arrived
+fn wait_for_close(stream: &mut TcpStream, limit: Duration) -> (Duration, Option<Reason>) {
+    stream
+        .set_read_timeout(Some(limit))
+        .expect("Failed to set timeout");
+    let start = Instant::now();
+    let mut received = Vec::new();
+    if let Err(e) = stream.read_to_end(&mut received) {
+        assert_ne!(e.kind(), std::io::ErrorKind::WouldBlock, "Not closed");
+    }
+    let waited = start.elapsed();
+    if received.is_empty() {
+        return (waited, None);
+    }
+    let last = ServerMessage::decode_length_delimited(received.as_slice()).expect("Not a frame");
+    match last.message {
+        Some(server_message::Message::Close(close)) => (waited, Some(close.reason())),
+        other => panic!("Unexpected response {:?}", other),
+    }
+}
+
+#[test]
+fn test_silent_connections_are_closed() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    server.set_first_frame_deadline(Some(Duration::from_millis(200)));
+
+    let mut stream = TcpStream::connect(("localhost", port)).expect("Failed to connect");
+    let (waited, reason) = wait_for_close(&mut stream, Duration::from_secs(5));
+    assert_eq!(reason, Some(Reason::IdleTimeout));
+    assert!(
+        waited >= Duration::from_millis(100),
+        "Closed after {:?}",
+        waited
+    );
+    assert_eq!(server.stats().slow_connections, 1);
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_silent_connections_are_closed_by_default() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+
+    let mut stream = TcpStream::connect(("localhost", port)).expect("Failed to connect");
+    let limit = DEFAULT_FIRST_FRAME_DEADLINE + Duration::from_secs(5);
+    let (waited, reason) = wait_for_close(&mut stream, limit);
+    assert_eq!(reason, Some(Reason::IdleTimeout));
+    assert!(
+        waited >= DEFAULT_FIRST_FRAME_DEADLINE - Duration::from_secs(1),
+        "Closed after {:?}",
+        waited
+    );
+    assert_eq!(server.stats().slow_connections, 1);
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_dribbled_frames_are_closed() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    server.set_frame_deadline(Some(Duration::from_millis(300)));
+
+    // The length prefix of a 10 byte frame, then one byte of it every 100 ms
+    let mut stream = TcpStream::connect(("localhost", port)).expect("Failed to connect");
+    stream.write_all(&[10]).unwrap();
+    for _ in 0..5 {
+        thread::sleep(Duration::from_millis(100));
+        if stream.write_all(&[0]).is_err() {
+            break; // Already closed
+        }
+    }
+    wait_for_close(&mut stream, Duration::from_secs(5));
+    assert_eq!(server.stats().slow_connections, 1);
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_prompt_clients_are_not_affected() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    server.set_first_frame_deadline(Some(Duration::from_millis(200)));
+    server.set_frame_deadline(Some(Duration::from_millis(200)));
+
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    // Idle between complete frames is fine once the first one has arrived
+    for _ in 0..3 {
+        client
+            .send(client_message::Message::AddRequest(AddRequest {
+                a: 1,
+                b: 2,
+            }))
+            .expect("Failed to send message");
+        let response = client.receive().expect("Failed to receive response");
+        assert!(matches!(
+            response.message,
+            Some(server_message::Message::AddResponse(_))
+        ));
+        thread::sleep(Duration::from_millis(300));
+    }
+    assert_eq!(server.stats().slow_connections, 0);
+
+    client.disconnect().ok();
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
@@ -0,0 +1,94 @@
+#![cfg(feature = "message")]
+
+mod common;
+
+use common::sum;
+use embedded_recruitment_task::dedup::DedupWindow;
+
+#[test]
+fn test_remembers_responses_by_id() {
+    let mut window = DedupWindow::new(4);
+    assert_eq!(window.get(7), None);
+
+    window.insert(7, sum(3));
+    assert_eq!(window.get(7), Some(&sum(3)));
+    assert_eq!(window.get(8), None);
+}
+
+#[test]
+fn test_id_zero_is_never_remembered() {
+    let mut window = DedupWindow::new(4);
+    window.insert(0, sum(3));
+    assert_eq!(window.get(0), None);
+    assert!(window.is_empty());
+}
+
+#[test]
+fn test_oldest_ids_are_evicted() {
+    let mut window = DedupWindow::new(3);
+    for id in 1..=4 {
+        window.insert(id, sum(id as i32));
+    }
+
+    assert_eq!(window.len(), 3);
+    assert_eq!(window.get(1), None, "Oldest ID should have been evicted");
+    for id in 2..=4 {
+        assert_eq!(window.get(id), Some(&sum(id as i32)));
+    }
+
+    // Reinserting a remembered ID does not refresh or duplicate it
+    window.insert(2, sum(2));
+    window.insert(5, sum(5));
+    assert_eq!(window.len(), 3);
+    assert_eq!(window.get(2), None);
+}
+
+#[cfg(all(feature = "client", feature = "server"))]
+mod server {
+    use embedded_recruitment_task::client;
+    use embedded_recruitment_task::message::{client_message, server_message, AddRequest};
+    use embedded_recruitment_task::server::Server;
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn test_retried_message_id_is_not_handled_twice() {
+        let server = Arc::new(Server::new("localhost:8099").expect("Failed to start server"));
+        let server_clone = Arc::clone(&server);
+        let handle =
+            thread::spawn(move || server_clone.run().expect("Server encountered an error"));
+
+        let mut client = client::Client::new("localhost", 8099, 1000);
+        client.connect().expect("Failed to connect to the server");
+        let mut add = |message_id: u64, a: i32| {
+            client
+                .send_with_id(
+                    0,
+                    message_id,
+                    client_message::Message::AddRequest(AddRequest { a, b: 1 }),
+                )
+                .expect("Failed to send message");
+            let response = client.receive().expect("Failed to receive response");
+            assert_eq!(response.message_id, message_id);
+            match response.message {
+                Some(server_message::Message::AddResponse(add)) => add.result,
+                other => panic!("Unexpected response: {:?}", other),
+            }
+        };
+
+        assert_eq!(add(42, 1), 2);
+        // The retry is answered from the window, so its changed operand is never seen
+        assert_eq!(add(42, 100), 2);
+        assert_eq!(add(43, 100), 101);
+        // Requests without an ID are always handled
+        assert_eq!(add(0, 5), 6);
+        assert_eq!(add(0, 6), 7);
+
+        let stats = server.stats();
+        assert_eq!(stats.requests, 4);
+        assert_eq!(stats.duplicates, 1);
+
+        client.disconnect().ok();
+        server.stop();
+        handle.join().expect("Server thread panicked");
+    }
+}
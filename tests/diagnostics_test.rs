@@ -0,0 +1,90 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::start;
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::diagnostics;
+use embedded_recruitment_task::message::{diagnostic_check::Status, DiagnosticsReport};
+use embedded_recruitment_task::server::Server;
+use std::{env, time::Duration};
+
+fn status(report: &DiagnosticsReport, name: &str) -> Status {
+    diagnostics::find(report, name)
+        .unwrap_or_else(|| panic!("No {} check", name))
+        .status()
+}
+
+#[test]
+fn test_report_over_the_protocol() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+
+    let report = client.diagnostics().expect("Diagnostics failed");
+    assert!(report.healthy, "{:?}", report);
+    let names: Vec<_> = report
+        .checks
+        .iter()
+        .map(|check| check.name.as_str())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "listener",
+            "worker_pool",
+            "memory",
+            "queues",
+            "upstream",
+            "persistence"
+        ]
+    );
+    assert_eq!(status(&report, "listener"), Status::Ok);
+    assert_eq!(status(&report, "worker_pool"), Status::Ok);
+    assert_eq!(status(&report, "upstream"), Status::Skipped);
+    assert_eq!(status(&report, "persistence"), Status::Skipped);
+    let pool = diagnostics::find(&report, "worker_pool").unwrap();
+    assert!(
+        pool.detail.starts_with("1 connections on "),
+        "{}",
+        pool.detail
+    );
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+#[test]
+fn test_problems_are_reported() {
+    let missing = env::temp_dir().join("diagnostics_test_missing_dir");
+    let server = Server::new("localhost:0")
+        .expect("Failed to start server")
+        .capture_to(&missing);
+    // Before `run()`, nothing accepts connections
+    let report = server.diagnostics();
+    assert!(!report.healthy);
+    assert_eq!(status(&report, "listener"), Status::Failed);
+    assert_eq!(status(&report, "persistence"), Status::Failed);
+
+    drop(server);
+
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    server.set_queue_limits(1, Duration::from_secs(60));
+    server.send_to("thermo-1", "first");
+    server.send_to("thermo-1", "second");
+    let report = client.diagnostics().expect("Diagnostics failed");
+    assert_eq!(status(&report, "queues"), Status::Warning);
+    let queues = diagnostics::find(&report, "queues").unwrap();
+    assert!(
+        queues.detail.ends_with("1 device queues full"),
+        "{}",
+        queues.detail
+    );
+    // A warning alone leaves the server healthy
+    assert!(report.healthy);
+
+    server.stop();
+    handle.join().unwrap();
+}
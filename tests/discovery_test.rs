@@ -0,0 +1,29 @@
+#![cfg(all(feature = "discovery", feature = "server"))]
+
+use embedded_recruitment_task::discovery::{discover, Advertisement};
+use embedded_recruitment_task::server::Server;
+use std::{sync::Arc, thread, time::Duration};
+
+#[test]
+fn test_advertised_server_is_discovered() {
+    // Reachable on the LAN addresses the advertisement announces
+    let server = Arc::new(Server::new("0.0.0.0:0").expect("Failed to start server"));
+    let port = server.local_addr().expect("No local address").port();
+    let server_clone = Arc::clone(&server);
+    let handle = thread::spawn(move || server_clone.run().expect("Server encountered an error"));
+
+    let name = format!("discovery-test-{}", port);
+    let advertisement = Advertisement::new(&name, port).expect("Failed to advertise");
+
+    let servers = discover(Duration::from_secs(3)).expect("Failed to browse");
+    let found = servers
+        .iter()
+        .find(|server| server.name == advertisement.fullname())
+        .unwrap_or_else(|| panic!("{} not among {:?}", name, servers));
+    assert_eq!(found.port, port);
+    assert!(found.socket_addrs().next().is_some());
+
+    drop(advertisement);
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
@@ -0,0 +1,92 @@
+#![cfg(all(feature = "client", feature = "fault-injection"))]
+
+mod common;
+
+use common::start;
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::fault::FaultInjector;
+use embedded_recruitment_task::server::Server;
+use std::{
+    net::{SocketAddr, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+const MAX_CONNECTIONS: usize = 1000;
+
+// Opens up to `count` connections, stopping at the first the server does not
+// take. Paced so that an accept loop that is still running keeps the listen
+// backlog from filling.
+fn connect(port: u16, count: usize) -> Vec<TcpStream> {
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let mut streams = Vec::new();
+    while streams.len() < count {
+        match TcpStream::connect_timeout(&addr, Duration::from_millis(500)) {
+            Ok(stream) => streams.push(stream),
+            Err(_) => break,
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    streams
+}
+
+// Waits until `done` holds
+fn wait_until(what: &str, mut done: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !done() {
+        assert!(Instant::now() < deadline, "{}", what);
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn test_full_accept_queue_leaves_clients_in_the_backlog() {
+    let (server, handle, port) = start(
+        Server::with_fault_injector("127.0.0.1:0", FaultInjector::new(1))
+            .expect("Failed to start server"),
+    );
+    let faults = server.fault_injector().unwrap();
+    faults.hold_connections(true);
+
+    // The accept queue, then the listen backlog, fill and the server stops
+    // completing handshakes
+    let streams = connect(port, MAX_CONNECTIONS);
+    assert!(
+        streams.len() < MAX_CONNECTIONS,
+        "Every connection was accepted"
+    );
+    // The 64 the accept queue holds, one each in the dispatcher and the accept
+    // loop, and the listen backlog of 128; Linux takes one more than that
+    assert!(streams.len() > 64 + 128, "{} connections", streams.len());
+    assert_eq!(server.stats().pool.active + server.stats().pool.queued, 0);
+
+    faults.hold_connections(false);
+    wait_until("Connections not handed to workers", || {
+        let pool = server.stats().pool;
+        pool.active + pool.queued == streams.len() as u64
+    });
+    drop(streams);
+    let mut client = Client::new("localhost", port.into(), 5000);
+    client.connect().expect("Failed to connect to the server");
+    assert_eq!(client.add(1, 2).expect("Add failed"), 3);
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+#[test]
+fn test_stop_returns_while_connections_are_held() {
+    let (server, handle, port) = start(
+        Server::with_fault_injector("127.0.0.1:0", FaultInjector::new(1))
+            .expect("Failed to start server"),
+    );
+    server.fault_injector().unwrap().hold_connections(true);
+    // More than the accept queue holds, so the accept loop is blocked on it
+    let streams = connect(port, 100);
+    assert_eq!(streams.len(), 100);
+
+    server.stop();
+    wait_until("run() did not return", || handle.is_finished());
+    handle.join().unwrap();
+    assert_eq!(server.stats().pool.completed, 0);
+}
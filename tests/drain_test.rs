@@ -0,0 +1,78 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::{echo, start};
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::message::{
+    client_message, go_away, server_message, GoAway, ResumeRequest,
+};
+use embedded_recruitment_task::server::Server;
+
+// Connects and names the device, returning the client once the resume is answered
+fn connect_as(port: u16, device: &str) -> Client {
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    client
+        .send(client_message::Message::ResumeRequest(ResumeRequest {
+            device_id: device.to_string(),
+            ..Default::default()
+        }))
+        .expect("Failed to send message");
+    let response = client.receive().expect("Failed to receive response");
+    assert!(matches!(
+        response.message,
+        Some(server_message::Message::ResumeResponse(_))
+    ));
+    client
+}
+
+#[test]
+fn test_drain_tells_every_connection_to_go_away() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let mut device = connect_as(port, "sensor-1");
+    let mut anonymous = Client::new("localhost", port.into(), 1000);
+    anonymous
+        .connect()
+        .expect("Failed to connect to the server");
+    anonymous
+        .send(echo("first"))
+        .expect("Failed to send message");
+    anonymous.receive().expect("Failed to receive response");
+
+    let go_away = GoAway {
+        reconnect_after_ms: 5000,
+        alternate_server: "10.0.0.2:8080".to_string(),
+        reason: go_away::Reason::Maintenance as i32,
+    };
+    assert_eq!(server.drain_with_go_away(go_away.clone()), Ok(2));
+
+    // A named device hears at once; an anonymous client after its next response
+    let pushed = device.receive().expect("Failed to receive GoAway");
+    assert_eq!(
+        pushed.message,
+        Some(server_message::Message::GoAway(go_away.clone()))
+    );
+    anonymous
+        .send(echo("second"))
+        .expect("Failed to send message");
+    let answered = anonymous.receive().expect("Failed to receive response");
+    assert!(matches!(
+        answered.message,
+        Some(server_message::Message::EchoMessage(_))
+    ));
+    let pushed = anonymous.receive().expect("Failed to receive GoAway");
+    assert_eq!(
+        pushed.message,
+        Some(server_message::Message::GoAway(go_away))
+    );
+
+    // Still served while draining; `run()` returns once both have left
+    device
+        .send(echo("still here"))
+        .expect("Failed to send message");
+    assert!(device.receive().is_ok());
+    device.disconnect().expect("Failed to disconnect");
+    anonymous.disconnect().expect("Failed to disconnect");
+    handle.join().expect("Server thread panicked");
+}
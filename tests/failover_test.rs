@@ -0,0 +1,226 @@
+#![cfg(feature = "client")]
+
+use embedded_recruitment_task::failover::{Endpoint, EndpointSet};
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_candidates_follow_priority_and_health() {
+    let mut set = EndpointSet::new(vec![
+        Endpoint::new("backup", 1).with_priority(1),
+        Endpoint::new("primary", 1),
+    ]);
+    assert_eq!(set.candidates(), [1, 0]);
+
+    // A failed endpoint is only tried as a last resort
+    let now = Instant::now();
+    set.mark_down(1, now);
+    assert_eq!(set.candidates(), [0, 1]);
+    set.mark_up(1);
+    assert_eq!(set.candidates(), [1, 0]);
+
+    assert_eq!(set.better_than(0), [1]);
+    assert!(set.better_than(1).is_empty());
+}
+
+#[test]
+fn test_down_endpoints_are_retried_longest_failed_first() {
+    let mut set = EndpointSet::new(vec![
+        Endpoint::new("a", 1),
+        Endpoint::new("b", 1).with_priority(1),
+    ]);
+    let now = Instant::now();
+    set.mark_down(1, now);
+    set.mark_down(0, now + Duration::from_secs(1));
+    assert_eq!(set.candidates(), [1, 0]);
+}
+
+#[test]
+fn test_equal_priorities_are_shared_by_weight() {
+    let mut set = EndpointSet::new(vec![
+        Endpoint::new("a", 1).with_weight(3),
+        Endpoint::new("b", 1).with_weight(1),
+    ]);
+    let firsts: Vec<usize> = (0..8).map(|_| set.candidates()[0]).collect();
+    assert_eq!(firsts.iter().filter(|&&i| i == 0).count(), 6);
+    assert_eq!(firsts.iter().filter(|&&i| i == 1).count(), 2);
+}
+
+#[test]
+fn test_endpoints_parse_and_insert_once() {
+    assert_eq!(
+        Endpoint::parse("[::1]:8080"),
+        Some(Endpoint::new("::1", 8080))
+    );
+    assert_eq!(
+        Endpoint::parse("gateway:1"),
+        Some(Endpoint::new("gateway", 1))
+    );
+    assert_eq!(Endpoint::parse("gateway"), None);
+    assert_eq!(Endpoint::parse(":1"), None);
+
+    let mut set = EndpointSet::new(vec![Endpoint::new("a", 1)]);
+    assert_eq!(set.insert(Endpoint::new("b", 1)), 1);
+    assert_eq!(set.insert(Endpoint::new("a", 1).with_priority(5)), 0);
+    assert_eq!(set.len(), 2);
+    assert_eq!(set.candidates(), [0, 1]);
+}
+
+#[cfg(feature = "server")]
+mod server {
+    use embedded_recruitment_task::client::Client;
+    use embedded_recruitment_task::failover::Endpoint;
+    use embedded_recruitment_task::message::{
+        client_message, go_away, server_message, AddRequest, GoAway,
+    };
+    use embedded_recruitment_task::server::Server;
+    use std::{sync::Arc, thread, time::Duration};
+
+    struct Running {
+        server: Arc<Server>,
+        handle: thread::JoinHandle<()>,
+    }
+
+    fn start(addr: &str) -> Running {
+        let server = Arc::new(Server::new(addr).expect("Failed to start server"));
+        let server_clone = Arc::clone(&server);
+        let handle =
+            thread::spawn(move || server_clone.run().expect("Server encountered an error"));
+        Running { server, handle }
+    }
+
+    impl Running {
+        fn port(&self) -> u32 {
+            self.server
+                .local_addr()
+                .expect("No local address")
+                .port()
+                .into()
+        }
+
+        fn stop(self) {
+            self.server.stop();
+            self.handle.join().expect("Server thread panicked");
+        }
+    }
+
+    // Has `running` push a `GoAway` and waits for the client to receive it
+    fn go_away(running: &Running, client: &mut Client, alternate_server: String) {
+        let go_away = GoAway {
+            reconnect_after_ms: 0,
+            alternate_server,
+            reason: go_away::Reason::Rebalance as i32,
+        };
+        add(client).expect("Server should answer"); // Registered by now
+        assert_eq!(running.server.go_away(go_away), Ok(1));
+        client
+            .send(client_message::Message::AddRequest(AddRequest::default()))
+            .unwrap();
+        assert!(client.receive().is_ok()); // The answer, followed by the push
+        assert!(matches!(
+            client.receive().expect("Failed to receive GoAway").message,
+            Some(server_message::Message::GoAway(_))
+        ));
+    }
+
+    fn add(client: &mut Client) -> std::io::Result<i32> {
+        client.send(client_message::Message::AddRequest(AddRequest {
+            a: 1,
+            b: 2,
+        }))?;
+        match client.receive()?.message {
+            Some(server_message::Message::AddResponse(add)) => Ok(add.result),
+            other => panic!("Unexpected response: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_fails_over_and_back() {
+        let primary = start("localhost:0");
+        let backup = start("localhost:0");
+        let primary_port = primary.port();
+        let backup_port = backup.port();
+
+        let mut client = Client::with_endpoints(
+            vec![
+                Endpoint::new("localhost", backup_port).with_priority(1),
+                Endpoint::new("localhost", primary_port),
+            ],
+            1000,
+        );
+        client.connect().expect("Failed to connect to the server");
+        assert_eq!(client.endpoint().map(|e| e.port), Some(primary_port));
+        assert_eq!(add(&mut client).expect("Primary should answer"), 3);
+
+        // The request in flight is lost, and the client moves to the backup
+        primary.stop();
+        assert!(add(&mut client).is_err());
+        assert_eq!(client.endpoint().map(|e| e.port), Some(backup_port));
+        assert_eq!(add(&mut client).expect("Backup should answer"), 3);
+
+        // Once the primary is back, the next request between exchanges returns to it
+        let primary = start(&format!("localhost:{}", primary_port));
+        client.set_health_check_interval(Duration::ZERO);
+        assert_eq!(add(&mut client).expect("Primary should answer"), 3);
+        assert_eq!(client.endpoint().map(|e| e.port), Some(primary_port));
+
+        client.disconnect().ok();
+        primary.stop();
+        backup.stop();
+    }
+
+    #[test]
+    fn test_go_away_moves_to_the_alternate_server() {
+        let old = start("localhost:0");
+        let new = start("localhost:0");
+        let mut client = Client::new("localhost", old.port(), 1000);
+        client.connect().expect("Failed to connect to the server");
+
+        go_away(&old, &mut client, format!("localhost:{}", new.port()));
+        assert_eq!(add(&mut client).expect("Alternate should answer"), 3);
+        assert_eq!(client.endpoint().map(|e| e.port), Some(new.port()));
+
+        client.disconnect().ok();
+        old.stop();
+        new.stop();
+    }
+
+    #[test]
+    fn test_go_away_without_alternate_moves_along_the_list() {
+        let first = start("localhost:0");
+        let second = start("localhost:0");
+        let mut client = Client::with_endpoints(
+            vec![
+                Endpoint::new("localhost", first.port()),
+                Endpoint::new("localhost", second.port()).with_priority(1),
+            ],
+            1000,
+        );
+        client.connect().expect("Failed to connect to the server");
+        assert_eq!(client.endpoint().map(|e| e.port), Some(first.port()));
+
+        go_away(&first, &mut client, String::new());
+        assert_eq!(add(&mut client).expect("Second should answer"), 3);
+        assert_eq!(client.endpoint().map(|e| e.port), Some(second.port()));
+
+        client.disconnect().ok();
+        first.stop();
+        second.stop();
+    }
+
+    #[test]
+    fn test_go_away_stays_put_while_nothing_else_answers() {
+        let only = start("localhost:0");
+        let closed = start("localhost:0");
+        let closed_port = closed.port();
+        closed.stop();
+        let mut client = Client::new("localhost", only.port(), 1000);
+        client.connect().expect("Failed to connect to the server");
+
+        go_away(&only, &mut client, format!("localhost:{}", closed_port));
+        assert_eq!(add(&mut client).expect("Old server should answer"), 3);
+        assert_eq!(client.endpoint().map(|e| e.port), Some(only.port()));
+
+        client.disconnect().ok();
+        only.stop();
+    }
+}
@@ -0,0 +1,218 @@
+#![cfg(all(feature = "client", feature = "fault-injection"))]
+
+mod common;
+
+use common::add;
+use embedded_recruitment_task::authz::{Grant, StaticPolicy};
+use embedded_recruitment_task::client;
+use embedded_recruitment_task::fault::{Action, Delay, FaultInjector, Rule};
+use embedded_recruitment_task::handler::MessageKind;
+use embedded_recruitment_task::message::{
+    client_message, fault_rule, server_message, AddResponse, EchoMessage, FaultRule,
+};
+use embedded_recruitment_task::server::Server;
+use std::{
+    io::ErrorKind,
+    sync::Arc,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+fn start_server(port: u16, faults: FaultInjector) -> (Arc<Server>, JoinHandle<()>) {
+    let server = Server::with_fault_injector(&format!("localhost:{}", port), faults)
+        .expect("Failed to start server");
+    let (server, handle, _) = common::start(server);
+    (server, handle)
+}
+
+#[test]
+fn test_dropped_responses_time_out() {
+    let (server, handle) = start_server(8087, FaultInjector::new(1).drop_responses(1.0));
+
+    let mut client = client::Client::new("localhost", 8087, 300);
+    client.connect().expect("Failed to connect to the server");
+    client.send(add(1, 2)).expect("Failed to send message");
+
+    let error = client.receive().expect_err("Received a dropped response");
+    assert!(
+        matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut),
+        "Unexpected error: {:?}",
+        error
+    );
+
+    client.disconnect().ok();
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_corrupted_responses_fail_to_decode() {
+    let (server, handle) = start_server(8088, FaultInjector::new(1).corrupt_frames(1.0));
+
+    let mut client = client::Client::new("localhost", 8088, 1000);
+    client.connect().expect("Failed to connect to the server");
+    client.send(add(1, 2)).expect("Failed to send message");
+
+    let error = client.receive().expect_err("Decoded a corrupted response");
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+    client.disconnect().ok();
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_connection_reset_on_schedule() {
+    let (server, handle) = start_server(8089, FaultInjector::new(1).reset_after(2));
+
+    let mut client = client::Client::new("localhost", 8089, 1000);
+    client.connect().expect("Failed to connect to the server");
+    for i in 0..2 {
+        client.send(add(i, 1)).expect("Failed to send message");
+        assert_eq!(
+            client
+                .receive()
+                .expect("Failed to receive response")
+                .message,
+            Some(server_message::Message::AddResponse(AddResponse {
+                result: i + 1
+            }))
+        );
+    }
+
+    client.send(add(2, 1)).expect("Failed to send message");
+    let error = client.receive().expect_err("Connection was not reset");
+    assert_eq!(error.kind(), ErrorKind::ConnectionAborted);
+
+    // A new connection gets a fresh schedule
+    client.connect().expect("Failed to reconnect to the server");
+    client.send(add(3, 1)).expect("Failed to send message");
+    assert!(
+        client.receive().is_ok(),
+        "Reconnected client got no response"
+    );
+
+    client.disconnect().ok();
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_delayed_responses() {
+    let delay = Delay::Uniform {
+        min: Duration::from_millis(100),
+        max: Duration::from_millis(150),
+    };
+    let (server, handle) = start_server(8090, FaultInjector::new(1).delay_responses(delay));
+
+    let mut client = client::Client::new("localhost", 8090, 1000);
+    client.connect().expect("Failed to connect to the server");
+
+    let start = Instant::now();
+    client.send(add(1, 2)).expect("Failed to send message");
+    client.receive().expect("Failed to receive response");
+    assert!(
+        start.elapsed() >= Duration::from_millis(100),
+        "Response was not delayed"
+    );
+
+    client.disconnect().ok();
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_per_message_type_rules_change_at_runtime() {
+    let faults = FaultInjector::new(1).rule(Rule::new(MessageKind::Echo, 1.0, Action::Corrupt));
+    let (server, handle) = start_server(8091, faults);
+
+    let mut client = client::Client::new("localhost", 8091, 1000);
+    client.connect().expect("Failed to connect to the server");
+
+    // Only echoes are affected
+    client.send(add(1, 2)).expect("Failed to send message");
+    assert!(client.receive().is_ok(), "Add response was not delivered");
+    let echo = client_message::Message::EchoMessage(EchoMessage {
+        content: "Hello".to_string(),
+    });
+    client.send(echo.clone()).expect("Failed to send message");
+    assert_eq!(
+        client.receive().expect_err("Echo was not corrupted").kind(),
+        ErrorKind::InvalidData
+    );
+
+    // Clearing the rules takes effect without restarting the server
+    server
+        .fault_injector()
+        .expect("Server has no fault injector")
+        .set_rules(Vec::new());
+    client.connect().expect("Failed to reconnect to the server");
+    client.send(echo).expect("Failed to send message");
+    assert!(client.receive().is_ok(), "Echo was still corrupted");
+
+    client.disconnect().ok();
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_rules_changed_over_the_admin_interface() {
+    let policy = StaticPolicy::new().everyone(Grant::all_requests().send(MessageKind::FaultRules));
+    let server = Server::with_fault_injector("localhost:0", FaultInjector::new(1))
+        .expect("Failed to start server")
+        .authorizer(policy);
+    let (server, handle, port) = common::start(server);
+    let mut client = client::Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    let echo = client_message::Message::EchoMessage(EchoMessage {
+        content: "Hello".to_string(),
+    });
+
+    // Added rules are listed, and apply from the next request
+    let corrupt_echoes = FaultRule {
+        message_type: "EchoMessage".to_string(),
+        probability: 1.0,
+        action: fault_rule::Action::Corrupt as i32,
+        delay_ms: 0,
+    };
+    let rules = client
+        .fault_rules(false, vec![corrupt_echoes.clone()])
+        .expect("Failed to add a rule");
+    assert_eq!(rules, std::slice::from_ref(&corrupt_echoes));
+    let injector = server
+        .fault_injector()
+        .expect("Server has no fault injector");
+    assert_eq!(
+        injector.rules(),
+        [Rule::new(MessageKind::Echo, 1.0, Action::Corrupt)]
+    );
+    client.send(echo.clone()).expect("Failed to send message");
+    assert_eq!(
+        client.receive().expect_err("Echo was not corrupted").kind(),
+        ErrorKind::InvalidData
+    );
+
+    // A rule for an unknown message type is refused, and nothing changes
+    client.connect().expect("Failed to reconnect to the server");
+    let typo = FaultRule {
+        message_type: "EchoMesage".to_string(),
+        ..corrupt_echoes
+    };
+    let refused = client
+        .fault_rules(true, vec![typo])
+        .expect_err("Unknown message type accepted");
+    assert_eq!(refused.kind(), ErrorKind::InvalidInput);
+    assert_eq!(injector.rules().len(), 1);
+
+    // Clearing them ends the faults
+    assert!(client
+        .fault_rules(true, Vec::new())
+        .expect("Failed to clear the rules")
+        .is_empty());
+    client.send(echo).expect("Failed to send message");
+    assert!(client.receive().is_ok(), "Echo was still corrupted");
+
+    client.disconnect().ok();
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
@@ -0,0 +1,75 @@
+use std::path::Path;
+use std::process::Command;
+
+// Runs cargo against this crate with a separate target dir so it doesn't contend
+// with the build lock held by the running test. The protoc the test was built
+// with, if any, builds the messages there too.
+fn cargo(args: &[&str]) -> std::process::Output {
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let mut command = Command::new(env!("CARGO"));
+    command.args(args).current_dir(manifest_dir).env(
+        "CARGO_TARGET_DIR",
+        Path::new(manifest_dir).join("target/feature-check"),
+    );
+    if let Some(protoc) = std::env::var_os("PROTOC") {
+        command.env("PROTOC", protoc);
+    }
+    command.output().expect("Failed to run cargo")
+}
+
+// Builds the library with only `features`, failing on any warning, since a
+// lint that is only hit without the default features would otherwise go unseen.
+// The bundled protoc is enabled, so machines without one can build too.
+fn assert_builds(features: &str) {
+    let features = format!("{},vendored-protoc", features);
+    let output = cargo(&[
+        "clippy",
+        "--lib",
+        "--no-default-features",
+        "--features",
+        &features,
+        "--",
+        "-D",
+        "warnings",
+    ]);
+    assert!(
+        output.status.success(),
+        "Build with features `{}` failed:\n{}",
+        features,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn test_client_only_build() {
+    assert_builds("client");
+
+    let output = cargo(&[
+        "tree",
+        "--no-default-features",
+        "--features",
+        "client",
+        "--edges",
+        "normal",
+    ]);
+    assert!(output.status.success(), "cargo tree failed");
+    assert!(
+        !String::from_utf8_lossy(&output.stdout).contains("crossbeam-channel"),
+        "Client-only build should not depend on crossbeam-channel"
+    );
+}
+
+#[test]
+fn test_server_only_build() {
+    assert_builds("server");
+}
+
+#[test]
+fn test_message_only_build() {
+    assert_builds("message");
+}
+
+#[test]
+fn test_testing_only_build() {
+    assert_builds("testing");
+}
@@ -0,0 +1,210 @@
+#![cfg(feature = "message")]
+
+use embedded_recruitment_task::codec;
+use embedded_recruitment_task::fixed::{
+    FixedError, FrameBuf, Request, Response, ERROR_BUSY, ERROR_INVALID,
+};
+use embedded_recruitment_task::message::{
+    client_message, error_response, server_message, AddRequest, AddResponse, ClientMessage,
+    EchoBytes, EchoMessage, ErrorResponse, PingRequest, ServerMessage, TelemetryAck,
+    TelemetryReport, WindowUpdate,
+};
+
+#[test]
+fn test_requests_match_prost_encoding() {
+    let cases = vec![
+        (
+            Request::Echo("Hello, World!"),
+            client_message::Message::EchoMessage(EchoMessage {
+                content: "Hello, World!".to_string(),
+            }),
+        ),
+        (
+            Request::EchoBytes(&[0x00, 0xff, 0x80]),
+            client_message::Message::EchoBytes(EchoBytes {
+                data: vec![0x00, 0xff, 0x80],
+            }),
+        ),
+        (
+            Request::Add { a: -7, b: i32::MAX },
+            client_message::Message::AddRequest(AddRequest { a: -7, b: i32::MAX }),
+        ),
+        (
+            Request::Ping { timestamp: 0 },
+            client_message::Message::PingRequest(PingRequest { timestamp: 0 }),
+        ),
+        (
+            Request::Telemetry {
+                sensor_id: 42,
+                value: -1.5,
+                timestamp: 1_700_000_000_000,
+            },
+            client_message::Message::TelemetryReport(TelemetryReport {
+                sensor_id: 42,
+                value: -1.5,
+                timestamp: 1_700_000_000_000,
+            }),
+        ),
+    ];
+
+    for (request, expected) in cases {
+        let expected = codec::encode(&ClientMessage {
+            message: Some(expected),
+            ..Default::default()
+        })
+        .expect("Failed to encode message");
+
+        let mut buf = FrameBuf::<64>::new();
+        let encoded = buf
+            .encode_request(&request)
+            .expect("Failed to encode request");
+        assert_eq!(encoded, &expected[..], "Encoding differs for {:?}", request);
+
+        assert_eq!(
+            Request::decode(&expected),
+            Ok(Some((request, expected.len()))),
+            "Decoding differs for {:?}",
+            request
+        );
+    }
+}
+
+#[test]
+fn test_responses_decode_prost_frames() {
+    let cases = vec![
+        (
+            server_message::Message::AddResponse(AddResponse { result: -30 }),
+            Response::Add { result: -30 },
+        ),
+        (
+            server_message::Message::EchoBytes(EchoBytes {
+                data: vec![0xde, 0xad],
+            }),
+            Response::EchoBytes(&[0xde, 0xad]),
+        ),
+        (
+            server_message::Message::TelemetryAck(TelemetryAck {
+                sensor_id: 7,
+                timestamp: 99,
+            }),
+            Response::TelemetryAck {
+                sensor_id: 7,
+                timestamp: 99,
+            },
+        ),
+        (
+            server_message::Message::WindowUpdate(WindowUpdate { credits: 16 }),
+            Response::WindowUpdate { credits: 16 },
+        ),
+        (
+            server_message::Message::ErrorResponse(ErrorResponse {
+                code: error_response::Code::Busy as i32,
+                retry_after_ms: 1500,
+                ..Default::default()
+            }),
+            Response::Error {
+                code: ERROR_BUSY,
+                retry_after_ms: 1500,
+            },
+        ),
+        (
+            server_message::Message::ErrorResponse(ErrorResponse {
+                code: error_response::Code::Invalid as i32,
+                field: "content".to_string(),
+                detail: "too long".to_string(),
+                ..Default::default()
+            }),
+            Response::Error {
+                code: ERROR_INVALID,
+                retry_after_ms: 0,
+            },
+        ),
+    ];
+
+    for (message, expected) in cases {
+        let mut frame = codec::encode(&ServerMessage {
+            message: Some(message),
+            ..Default::default()
+        })
+        .expect("Failed to encode message");
+        let len = frame.len();
+        frame.extend_from_slice(&[0x00, 0x01]); // Start of the next frame is left untouched
+
+        assert_eq!(Response::decode(&frame), Ok(Some((expected, len))));
+        assert_eq!(
+            Response::decode(&frame[..len - 1]),
+            Ok(None),
+            "Truncated frame should wait for more bytes"
+        );
+    }
+}
+
+#[test]
+fn test_stream_ids_match_prost_encoding() {
+    for stream_id in [0, 1, 300, u32::MAX] {
+        let expected = codec::encode(&ClientMessage {
+            message: Some(client_message::Message::AddRequest(AddRequest {
+                a: 1,
+                b: 2,
+            })),
+            stream_id,
+            ..Default::default()
+        })
+        .expect("Failed to encode message");
+
+        let mut buf = FrameBuf::<64>::new();
+        let request = Request::Add { a: 1, b: 2 };
+        let encoded = buf
+            .encode_request_on(stream_id, &request)
+            .expect("Failed to encode request");
+        assert_eq!(
+            encoded,
+            &expected[..],
+            "Encoding differs on stream {}",
+            stream_id
+        );
+        assert_eq!(
+            Request::decode_with_stream(&expected),
+            Ok(Some((request, stream_id, expected.len())))
+        );
+
+        let response = codec::encode(&ServerMessage {
+            message: Some(server_message::Message::AddResponse(AddResponse {
+                result: 3,
+            })),
+            stream_id,
+            ..Default::default()
+        })
+        .expect("Failed to encode message");
+        let mut encoded = [0u8; 64];
+        let len = Response::Add { result: 3 }
+            .encode_on(stream_id, &mut encoded)
+            .expect("Failed to encode response");
+        assert_eq!(&encoded[..len], &response[..]);
+        assert_eq!(
+            Response::decode_with_stream(&response),
+            Ok(Some((
+                Response::Add { result: 3 },
+                stream_id,
+                response.len()
+            )))
+        );
+    }
+}
+
+#[test]
+fn test_buffer_too_small() {
+    let request = Request::Echo("Hello, World!");
+    let needed = request
+        .encode(&mut [0u8; 64])
+        .expect("Failed to encode request");
+
+    let mut buf = FrameBuf::<8>::new();
+    assert_eq!(
+        buf.encode_request(&request),
+        Err(FixedError::BufferTooSmall {
+            needed,
+            available: 8,
+        })
+    );
+}
@@ -0,0 +1,52 @@
+#![cfg(feature = "testing")]
+
+use embedded_recruitment_task::codec::{self, decode_frame};
+use embedded_recruitment_task::message::{
+    client_message, server_message, transform_request, AddRequest, AddResponse, ClientMessage,
+    ServerMessage, TransformRequest,
+};
+use embedded_recruitment_task::testing::fixtures::{add, request, response, transform};
+use proptest::prelude::*;
+
+#[test]
+fn test_builders_fill_the_envelope() {
+    assert_eq!(
+        request(add(2, 3)).id(7).stream(1).build(),
+        ClientMessage {
+            message: Some(client_message::Message::AddRequest(AddRequest {
+                a: 2,
+                b: 3
+            })),
+            message_id: 7,
+            stream_id: 1,
+            ..Default::default()
+        }
+    );
+    assert_eq!(
+        transform("abc", transform_request::Op::Reverse),
+        client_message::Message::TransformRequest(TransformRequest {
+            content: "abc".to_string(),
+            op: transform_request::Op::Reverse as i32,
+        })
+    );
+    let built: ServerMessage = response(server_message::Message::AddResponse(AddResponse {
+        result: 5,
+    }))
+    .id(7)
+    .into();
+    assert_eq!((built.message_id, built.stream_id), (7, 0));
+}
+
+proptest! {
+    #[test]
+    fn prop_client_messages_round_trip(message in any::<ClientMessage>()) {
+        let frame = codec::encode(&message).unwrap();
+        prop_assert_eq!(decode_frame(&frame), Ok(Some((message, frame.len()))));
+    }
+
+    #[test]
+    fn prop_server_messages_round_trip(message in any::<ServerMessage>()) {
+        let frame = codec::encode(&message).unwrap();
+        prop_assert_eq!(decode_frame(&frame), Ok(Some((message, frame.len()))));
+    }
+}
@@ -0,0 +1,71 @@
+#![cfg(feature = "message")]
+
+use embedded_recruitment_task::flow::{FlowError, ReceiveWindows, SendWindows, INITIAL_WINDOW};
+
+#[test]
+fn test_send_window_runs_dry_and_refills() {
+    let mut windows = SendWindows::new();
+    for _ in 0..INITIAL_WINDOW {
+        assert!(windows.consume(1));
+    }
+    assert!(!windows.consume(1), "Stream 1 should be out of credits");
+    assert_eq!(
+        windows.available(2),
+        INITIAL_WINDOW,
+        "Other streams are unaffected"
+    );
+
+    windows.grant(1, 3);
+    assert_eq!(windows.available(1), 3);
+    assert!(windows.consume(1));
+}
+
+#[test]
+fn test_credits_are_granted_in_batches_after_completion() {
+    let mut windows = ReceiveWindows::new();
+
+    // Answered requests are owed back, but not granted while the client has plenty left
+    for _ in 0..INITIAL_WINDOW / 2 - 1 {
+        windows.received(7).unwrap();
+        windows.completed(7);
+    }
+    assert_eq!(windows.poll_grant(), None);
+
+    // Down to half the window: everything owed goes back at once
+    windows.received(7).unwrap();
+    windows.completed(7);
+    assert_eq!(windows.poll_grant(), Some((7, INITIAL_WINDOW / 2)));
+    assert_eq!(windows.poll_grant(), None);
+}
+
+#[test]
+fn test_unanswered_requests_keep_their_credits() {
+    let mut windows = ReceiveWindows::new();
+    for _ in 0..INITIAL_WINDOW {
+        windows.received(0).unwrap();
+    }
+    assert_eq!(windows.poll_grant(), None, "Nothing answered yet");
+    assert_eq!(windows.received(0), Err(FlowError { stream_id: 0 }));
+
+    windows.completed(0);
+    assert_eq!(windows.poll_grant(), Some((0, 1)));
+    assert!(windows.received(0).is_ok());
+}
+
+#[test]
+fn test_client_and_server_agree_on_credits() {
+    let mut client = SendWindows::new();
+    let mut server = ReceiveWindows::new();
+
+    // A client that never waits for responses stays within its window
+    for request in 0..10 * INITIAL_WINDOW {
+        if !client.consume(3) {
+            while let Some((stream, credits)) = server.poll_grant() {
+                client.grant(stream, credits);
+            }
+            assert!(client.consume(3), "No credits after request {}", request);
+        }
+        server.received(3).unwrap();
+        server.completed(3);
+    }
+}
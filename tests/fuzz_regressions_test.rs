@@ -0,0 +1,132 @@
+#![cfg(feature = "message")]
+
+// Inputs kept in `fuzz/regressions`, replayed through every decode path the
+// fuzz targets exercise. Minimized crashes found by `cargo fuzz` go there too.
+
+use embedded_recruitment_task::codec::{decode_frame, CodecError, FrameDecoder};
+use embedded_recruitment_task::fixed::{Request, Response};
+use embedded_recruitment_task::handler::handle_message;
+use embedded_recruitment_task::message::{ClientMessage, ServerMessage};
+use embedded_recruitment_task::protocol::{Event, ServerProtocol};
+use std::fs;
+use std::path::PathBuf;
+
+fn corpus() -> Vec<(String, Vec<u8>)> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fuzz/regressions");
+    let mut inputs: Vec<_> = fs::read_dir(&dir)
+        .expect("Failed to read the regression corpus")
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, fs::read(&path).unwrap())
+        })
+        .collect();
+    inputs.sort();
+    inputs
+}
+
+fn input(name: &str) -> Vec<u8> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("fuzz/regressions")
+        .join(name);
+    fs::read(path).expect("Missing regression input")
+}
+
+#[test]
+fn test_corpus_through_every_decoder() {
+    let inputs = corpus();
+    assert!(!inputs.is_empty(), "Regression corpus is empty");
+
+    for (name, data) in inputs {
+        if let Ok(Some((_, used))) = decode_frame::<ClientMessage>(&data) {
+            assert!(used <= data.len(), "{}", name);
+        }
+        if let Ok(Some((_, used))) = decode_frame::<ServerMessage>(&data) {
+            assert!(used <= data.len(), "{}", name);
+        }
+        if let Ok(Some((_, used))) = Request::decode(&data) {
+            assert!(used <= data.len(), "{}", name);
+        }
+        if let Ok(Some((_, used))) = Response::decode(&data) {
+            assert!(used <= data.len(), "{}", name);
+        }
+
+        // Byte by byte through the server's state machine, as a slow client would send it
+        let mut protocol = ServerProtocol::new();
+        for byte in &data {
+            for event in protocol.feed_bytes(&[*byte]) {
+                if let Event::Message(ClientMessage {
+                    message: Some(request),
+                    ..
+                }) = event
+                {
+                    let response = ServerMessage {
+                        message: Some(handle_message(request)),
+                        ..Default::default()
+                    };
+                    protocol.send(&response).unwrap();
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_adversarial_length_prefixes() {
+    assert_eq!(
+        decode_frame::<ClientMessage>(&input("length_prefix_too_large")),
+        Err(CodecError::FrameTooLarge(0x1fffff))
+    );
+    for name in [
+        "length_prefix_unterminated",
+        "length_prefix_three_continuations",
+    ] {
+        assert_eq!(
+            decode_frame::<ClientMessage>(&input(name)),
+            Err(CodecError::InvalidLength),
+            "{}",
+            name
+        );
+    }
+
+    // A redundant continuation byte still encodes a valid length
+    assert_eq!(
+        decode_frame::<ClientMessage>(&input("non_canonical_length_prefix")),
+        Ok(Some((ClientMessage::default(), 2)))
+    );
+}
+
+#[test]
+fn test_truncated_input_waits_for_more_bytes() {
+    for name in ["length_prefix_truncated", "body_truncated"] {
+        let data = input(name);
+        assert_eq!(decode_frame::<ClientMessage>(&data), Ok(None), "{}", name);
+
+        let mut decoder = FrameDecoder::new();
+        decoder.extend(&data);
+        assert_eq!(decoder.next_frame(), Ok(None), "{}", name);
+        assert_eq!(decoder.buffered(), data.len(), "{}", name);
+    }
+}
+
+#[test]
+fn test_garbage_bodies_are_decode_errors() {
+    for name in [
+        "garbage_body",
+        "nested_length_overruns_body",
+        "echo_invalid_utf8",
+    ] {
+        assert!(
+            matches!(
+                decode_frame::<ClientMessage>(&input(name)),
+                Err(CodecError::Decode(_))
+            ),
+            "{}",
+            name
+        );
+    }
+    assert_eq!(
+        decode_frame::<ClientMessage>(&input("empty_frame")),
+        Ok(Some((ClientMessage::default(), 1)))
+    );
+}
@@ -0,0 +1,122 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::{add, call, start};
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::message::{
+    error_response, server_message, AddResponse, ErrorResponse,
+};
+use embedded_recruitment_task::router::Router;
+use embedded_recruitment_task::server::Server;
+use std::{
+    net::TcpListener,
+    sync::{mpsc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[test]
+fn test_stuck_handlers_answer_with_a_timeout() {
+    // An upstream that accepts but never answers keeps the relay handler waiting
+    let upstream = TcpListener::bind("localhost:0").expect("Failed to bind upstream");
+    let upstream_addr = upstream.local_addr().expect("No local address");
+    let (server, handle, port) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .relay_to(&upstream_addr.to_string(), 1),
+    );
+    server.set_handler_timeout(Some(Duration::from_millis(200)));
+
+    let mut client = Client::new("localhost", port.into(), 2000);
+    client.connect().expect("Failed to connect to the server");
+    assert_eq!(
+        call(&mut client, add(1, 2)),
+        Some(server_message::Message::ErrorResponse(ErrorResponse {
+            code: error_response::Code::Timeout as i32,
+            ..Default::default()
+        }))
+    );
+    let stats = server.stats();
+    assert_eq!(stats.handler_timeouts, 1);
+    assert_eq!(stats.requests, 0);
+
+    client.disconnect().ok();
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_overrunning_handlers_are_capped() {
+    // Each handler holds its thread until the sender is dropped
+    let (release, released) = mpsc::channel::<()>();
+    let released = Mutex::new(released);
+    let router = Router::new().on_add(move |add| {
+        let _ = released.lock().unwrap().recv();
+        server_message::Message::AddResponse(AddResponse {
+            result: add.a + add.b,
+        })
+    });
+    let (server, handle, port) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .handler_threads(1)
+            .router(router),
+    );
+    server.set_handler_timeout(Some(Duration::from_millis(200)));
+
+    let mut client = Client::new("localhost", port.into(), 2000);
+    client.connect().expect("Failed to connect to the server");
+    assert!(matches!(
+        call(&mut client, add(1, 2)),
+        Some(server_message::Message::ErrorResponse(ErrorResponse { code, .. }))
+            if code == error_response::Code::Timeout as i32
+    ));
+    assert_eq!(server.stats().abandoned_handlers, 1);
+
+    // The only thread is still held, so the next request is refused at once
+    assert!(matches!(
+        call(&mut client, add(1, 2)),
+        Some(server_message::Message::ErrorResponse(ErrorResponse { code, .. }))
+            if code == error_response::Code::Busy as i32
+    ));
+    assert_eq!(server.stats().shed_requests, 1);
+
+    // Once the handler returns its thread is reused
+    drop(release);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while server.stats().abandoned_handlers != 0 {
+        assert!(Instant::now() < deadline, "Handler not finished");
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(matches!(
+        call(&mut client, add(1, 2)),
+        Some(server_message::Message::AddResponse(_))
+    ));
+
+    client.disconnect().ok();
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_prompt_handlers_are_answered_normally() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    server.set_handler_timeout(Some(Duration::from_secs(5)));
+
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    for _ in 0..3 {
+        assert!(matches!(
+            call(&mut client, add(1, 2)),
+            Some(server_message::Message::AddResponse(_))
+        ));
+    }
+    let stats = server.stats();
+    assert_eq!(stats.handler_timeouts, 0);
+    assert_eq!(stats.requests, 3);
+
+    client.disconnect().ok();
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
@@ -0,0 +1,56 @@
+#![cfg(all(feature = "client", feature = "handover", unix))]
+
+mod common;
+
+use common::{add, call, start, sum};
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::handover;
+use embedded_recruitment_task::server::Server;
+use std::{sync::Arc, thread, time::Duration};
+
+#[test]
+fn test_listener_is_handed_over_and_old_server_drains() {
+    let path = std::env::temp_dir().join(format!("handover-test-{}.sock", std::process::id()));
+    let (old, old_handle, _) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let port = old.local_addr().expect("No local address").port();
+
+    let mut existing = Client::new("localhost", port.into(), 1000);
+    existing.connect().expect("Failed to connect to the server");
+    assert_eq!(call(&mut existing, add(1, 2)), Some(sum(3)));
+
+    // The old process offers its listener; the new one picks it up
+    let old_clone = Arc::clone(&old);
+    let path_clone = path.clone();
+    let offering = thread::spawn(move || old_clone.hand_over(&path_clone));
+    let listener = (0..100)
+        .find_map(|_| {
+            handover::receive(&path)
+                .map_err(|_| thread::sleep(Duration::from_millis(10)))
+                .ok()
+        })
+        .expect("Failed to receive the listener");
+    offering
+        .join()
+        .expect("Handover thread panicked")
+        .expect("Failed to hand the listener over");
+    let (new, new_handle, _) = start(Server::from_listener(listener));
+    assert_eq!(new.local_addr().expect("No local address").port(), port);
+
+    // New connections go to the new server, on the same port
+    let mut fresh = Client::new("localhost", port.into(), 1000);
+    fresh
+        .connect()
+        .expect("Failed to connect after the handover");
+    assert_eq!(call(&mut fresh, add(1, 2)), Some(sum(3)));
+    assert_eq!(new.stats().requests, 1);
+
+    // The old server keeps serving its device until it leaves, then exits
+    assert_eq!(call(&mut existing, add(1, 2)), Some(sum(3)));
+    assert_eq!(old.stats().requests, 2);
+    existing.disconnect().ok();
+    old_handle.join().expect("Old server thread panicked");
+
+    fresh.disconnect().ok();
+    new.stop();
+    new_handle.join().expect("New server thread panicked");
+}
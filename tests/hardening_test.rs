@@ -0,0 +1,65 @@
+#![cfg(all(feature = "client", feature = "hardening", target_os = "linux"))]
+
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::hardening::Sandbox;
+use embedded_recruitment_task::message::{client_message, server_message, AddRequest};
+use embedded_recruitment_task::server::Server;
+use std::{io::ErrorKind, process::Command, sync::Arc, thread};
+
+// Set in the child process that sandboxes itself
+const CHILD: &str = "HARDENING_TEST_CHILD";
+
+// Runs in a child process, so the rest of the suite stays unrestricted
+#[test]
+fn serve_in_sandbox() {
+    if std::env::var_os(CHILD).is_none() {
+        return; // Only meaningful in the child
+    }
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let port = server.local_addr().expect("No local address").port();
+    let status = Sandbox::new().apply().expect("Failed to apply the sandbox");
+
+    let server_clone = Arc::clone(&server);
+    let handle = thread::spawn(move || server_clone.run().expect("Server encountered an error"));
+    let mut client = Client::new("127.0.0.1", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    client
+        .send(client_message::Message::AddRequest(AddRequest {
+            a: 1,
+            b: 2,
+        }))
+        .expect("Failed to send message");
+    match client
+        .receive()
+        .expect("Failed to receive response")
+        .message
+    {
+        Some(server_message::Message::AddResponse(add)) => assert_eq!(add.result, 3),
+        other => panic!("Unexpected response: {:?}", other),
+    }
+
+    // Starting programs is not among the allowed system calls
+    assert!(Command::new("true").status().is_err());
+    if status.filesystem_restricted {
+        let error = std::fs::read("/etc/hostname").expect_err("Filesystem should be closed off");
+        assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+    }
+
+    client.disconnect().ok();
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_server_runs_in_sandbox() {
+    let output = Command::new(std::env::current_exe().expect("No test binary"))
+        .args(["--exact", "serve_in_sandbox", "--nocapture"])
+        .env(CHILD, "1")
+        .output()
+        .expect("Failed to run the child");
+    assert!(
+        output.status.success(),
+        "Sandboxed server failed:\n{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
@@ -0,0 +1,138 @@
+#![cfg(feature = "server")]
+
+mod common;
+
+use embedded_recruitment_task::health::{Health, HealthEndpoint};
+use embedded_recruitment_task::server::Server;
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+fn start(server: Server) -> (Arc<Server>, thread::JoinHandle<()>) {
+    let (server, handle, _) = common::start(server);
+    // Wait for the accept loop's first turn
+    while server
+        .health()
+        .problems
+        .contains(&"Accept loop has not started".to_string())
+    {
+        thread::sleep(Duration::from_millis(10));
+    }
+    (server, handle)
+}
+
+#[test]
+fn test_live_and_ready_follow_the_lifecycle() {
+    let server = Server::new("localhost:0").expect("Failed to start server");
+    let health = server.health();
+    assert!(!health.live && !health.ready, "Not live before run()");
+
+    let (server, handle) = start(server);
+    let health = server.health();
+    assert!(
+        health.live && health.ready,
+        "Unexpected problems: {:?}",
+        health.problems
+    );
+
+    // Draining: still live, but no longer taking devices
+    server.drain();
+    handle.join().expect("Server thread panicked");
+    let health = server.health();
+    assert!(health.live);
+    assert!(!health.ready);
+    assert_eq!(health.problems, ["Not accepting connections"]);
+}
+
+#[test]
+fn test_overload_makes_server_unready() {
+    let (server, handle) = start(Server::new("localhost:0").expect("Failed to start server"));
+    server.set_overload_threshold(Some(0));
+    let health = server.health();
+    assert!(health.live && !health.ready);
+    assert_eq!(health.problems, ["0 connections waiting for a worker"]);
+
+    server.set_overload_threshold(None);
+    assert!(server.health().ready);
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[cfg(feature = "client")]
+#[test]
+fn test_unreachable_upstream_makes_relay_unready() {
+    use embedded_recruitment_task::client::Client;
+    use embedded_recruitment_task::message::{client_message, AddRequest};
+
+    // Nothing listens on the upstream address once the listener is dropped
+    let upstream_addr = std::net::TcpListener::bind("localhost:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("Failed to reserve a port");
+    let (edge, handle) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start edge server")
+            .relay_to(&upstream_addr.to_string(), 1),
+    );
+    assert!(
+        edge.health().ready,
+        "Upstream is assumed reachable at first"
+    );
+
+    let port = edge.local_addr().expect("No local address").port();
+    let mut client = Client::new("localhost", port.into(), 300);
+    client.connect().expect("Failed to connect to the edge");
+    client
+        .send(client_message::Message::AddRequest(AddRequest {
+            a: 1,
+            b: 2,
+        }))
+        .expect("Failed to send message");
+    assert!(client.receive().is_err(), "Request should go unanswered");
+
+    let health = edge.health();
+    assert!(health.live && !health.ready);
+    assert_eq!(health.problems, ["Relay upstream is unreachable"]);
+
+    client.disconnect().ok();
+    edge.stop();
+    handle.join().expect("Edge thread panicked");
+}
+
+fn get(endpoint: &HealthEndpoint, path: &str) -> String {
+    let mut stream = TcpStream::connect(endpoint.local_addr()).expect("Failed to connect");
+    write!(stream, "GET {} HTTP/1.1\r\nHost: probe\r\n\r\n", path).expect("Failed to send");
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .expect("Failed to read response");
+    response
+}
+
+#[test]
+fn test_endpoint_answers_probes() {
+    let endpoint = HealthEndpoint::start("localhost:0", || Health {
+        live: true,
+        ready: false,
+        problems: vec!["Relay upstream is unreachable".to_string()],
+    })
+    .expect("Failed to start endpoint");
+
+    let live = get(&endpoint, "/livez");
+    assert!(live.starts_with("HTTP/1.1 200 OK\r\n"), "{}", live);
+    assert!(live.ends_with("\r\n\r\nok\n"), "{}", live);
+
+    let ready = get(&endpoint, "/readyz");
+    assert!(ready.starts_with("HTTP/1.1 503 "), "{}", ready);
+    assert!(
+        ready.ends_with("\r\n\r\nRelay upstream is unreachable\n"),
+        "{}",
+        ready
+    );
+
+    assert!(get(&endpoint, "/metrics").starts_with("HTTP/1.1 404 "));
+}
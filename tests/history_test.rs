@@ -0,0 +1,98 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::start;
+use embedded_recruitment_task::authz::{Grant, StaticPolicy};
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::handler::MessageKind;
+use embedded_recruitment_task::message::{client_message, close::Reason, ResumeRequest};
+use embedded_recruitment_task::server::Server;
+use std::{
+    io, thread,
+    time::{Duration, Instant},
+};
+
+fn admin_policy() -> StaticPolicy {
+    StaticPolicy::new().everyone(Grant::all_requests().send(MessageKind::ConnectionHistory))
+}
+
+// Waits for the handlers of `closed` connections to finish
+fn wait_for_history(server: &Server, closed: usize) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while server.connection_history(None, usize::MAX).len() < closed {
+        assert!(Instant::now() < deadline, "Connections not recorded");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn test_closed_connections_are_listed() {
+    let (server, handle, port) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .authorizer(admin_policy()),
+    );
+    let mut device = Client::new("localhost", port.into(), 1000);
+    device.connect().expect("Failed to connect to the server");
+    let resume = client_message::Message::ResumeRequest(ResumeRequest {
+        device_id: "thermo-1".to_string(),
+        ..Default::default()
+    });
+    device.send(resume).expect("Failed to send message");
+    device.receive().expect("Failed to receive response");
+    assert_eq!(device.add(1, 2).expect("Add failed"), 3);
+    device.disconnect().expect("Failed to disconnect");
+    let mut other = Client::new("localhost", port.into(), 1000);
+    other.connect().expect("Failed to connect to the server");
+    other.disconnect().expect("Failed to disconnect");
+    wait_for_history(&server, 2);
+
+    let mut admin = Client::new("localhost", port.into(), 1000);
+    admin.connect().expect("Failed to connect to the server");
+    let all = admin.connection_history("", 0).expect("History failed");
+    assert_eq!(all.len(), 2);
+    // Newest first
+    assert_eq!(all[0].device, "");
+    assert_eq!(all[0].messages, 0);
+    assert!(all[0].connection_id > all[1].connection_id);
+
+    let devices = admin
+        .connection_history("thermo-1", 0)
+        .expect("History failed");
+    assert_eq!(devices, all[1..]);
+    let record = &devices[0];
+    assert_eq!(record.messages, 2);
+    assert_eq!(record.reason(), Reason::ClientClosed);
+    assert!(record.peer.starts_with("127.0.0.1:") || record.peer.starts_with("[::1]:"));
+    assert!(record.connected_at_ms > 0);
+    assert_eq!(
+        admin
+            .connection_history("", 1)
+            .expect("History failed")
+            .len(),
+        1
+    );
+
+    server.set_history_capacity(1);
+    // The oldest, of the device, is dropped
+    assert_eq!(server.connection_history(None, usize::MAX).len(), 1);
+    assert!(server.connection_history(Some("thermo-1"), 10).is_empty());
+
+    server.stop();
+    handle.join().unwrap();
+    let stopped = server.connection_history(None, 1);
+    assert_eq!(stopped[0].reason, Reason::ServerShutdown);
+    assert_eq!(stopped[0].messages, 3);
+}
+
+#[test]
+fn test_history_is_an_admin_request() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    let refused = client.connection_history("", 0).unwrap_err();
+    assert_eq!(refused.kind(), io::ErrorKind::PermissionDenied);
+    server.stop();
+    handle.join().unwrap();
+}
@@ -0,0 +1,94 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::{echo, start};
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::message::server_message;
+use embedded_recruitment_task::server::Server;
+use std::{
+    io,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+#[test]
+fn test_incoming_yields_responses_and_pushes_in_turn() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    // A short read timeout, which `incoming` waits past
+    let mut client = Client::new("localhost", port.into(), 50);
+    // Confirmed on connecting, while `incoming` reads
+    client.subscribe("sensor-1").expect("Failed to subscribe");
+    client.connect().expect("Failed to connect to the server");
+
+    client.send(echo("hello")).expect("Failed to send message");
+    let server_clone = Arc::clone(&server);
+    let sender = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(200));
+        server_clone.send_to("sensor-1", b"command".to_vec());
+    });
+    let mut kinds = Vec::new();
+    for message in client.incoming() {
+        match message.expect("Failed to receive message").message {
+            Some(server_message::Message::EchoMessage(echo)) => kinds.push(echo.content),
+            Some(server_message::Message::Delivery(delivery)) => {
+                kinds.push(String::from_utf8_lossy(&delivery.payload).into_owned());
+                break;
+            }
+            other => panic!("Unexpected message {:?}", other),
+        }
+    }
+    assert_eq!(kinds, ["hello", "command"]);
+    sender.join().expect("Sender thread panicked");
+    client.disconnect().expect("Failed to disconnect");
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_incoming_timeout_returns_to_the_loop() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let mut client = Client::new("localhost", port.into(), 5000);
+    client.connect().expect("Failed to connect to the server");
+    client.subscribe("sensor-1").expect("Failed to subscribe");
+
+    let mut incoming = client.incoming_timeout(Duration::from_millis(100));
+    let started = Instant::now();
+    let idle = incoming.next().expect("Iterator ended");
+    assert_eq!(
+        idle.map_err(|e| e.kind()).err(),
+        Some(io::ErrorKind::TimedOut)
+    );
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    server.send_to("sensor-1", b"wake".to_vec());
+    let message = incoming
+        .next()
+        .expect("Iterator ended")
+        .expect("Failed to receive message");
+    assert!(matches!(
+        message.message,
+        Some(server_message::Message::Delivery(_))
+    ));
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+    // The closed connection ends the loop
+    let errors = incoming.take_while(|message| message.is_err()).count();
+    assert!(errors >= 1);
+}
+
+#[test]
+fn test_incoming_without_connection_ends_at_once() {
+    let mut client = Client::new("localhost", 1, 1000);
+    let mut incoming = client.incoming();
+    assert_eq!(
+        incoming
+            .next()
+            .map(|message| message.map_err(|e| e.kind()).err()),
+        Some(Some(io::ErrorKind::NotConnected))
+    );
+    assert!(incoming.next().is_none());
+}
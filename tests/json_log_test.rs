@@ -0,0 +1,124 @@
+#![cfg(all(feature = "json-log", feature = "client", feature = "server"))]
+
+mod common;
+
+use common::start;
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::logging::JsonLogger;
+use embedded_recruitment_task::server::Server;
+use log::{LevelFilter, Log, Record};
+use serde_json::{json, Value};
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+// Collects what the logger writes
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Captured {
+    fn lines(&self) -> Vec<Value> {
+        let bytes = self.0.lock().unwrap();
+        String::from_utf8_lossy(&bytes)
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("Log line is not JSON"))
+            .collect()
+    }
+}
+
+impl Write for Captured {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_records_become_json_objects_with_their_fields() {
+    let captured = Captured::default();
+    let logger = JsonLogger::new(LevelFilter::Info).with_writer(captured.clone());
+    let fields: &[(&str, &dyn log::kv::ToValue)] = &[
+        ("connection_id", &7u64),
+        ("peer", &"10.0.0.7:50412"),
+        ("duration_ms", &250u64),
+        ("healthy", &true),
+    ];
+    logger.log(
+        &Record::builder()
+            .level(log::Level::Warn)
+            .target("server")
+            .args(format_args!("Slow request: {}", "add"))
+            .key_values(&fields)
+            .build(),
+    );
+    // Above the logger's level, so not written
+    logger.log(
+        &Record::builder()
+            .level(log::Level::Debug)
+            .args(format_args!("hidden"))
+            .build(),
+    );
+
+    let lines = captured.lines();
+    assert_eq!(lines.len(), 1);
+    let line = &lines[0];
+    assert!(line["time_ms"].is_u64());
+    assert_eq!(line["level"], "WARN");
+    assert_eq!(line["target"], "server");
+    assert_eq!(line["message"], "Slow request: add");
+    assert_eq!(line["connection_id"], 7);
+    assert_eq!(line["peer"], "10.0.0.7:50412");
+    assert_eq!(line["duration_ms"], 250);
+    assert_eq!(line["healthy"], true);
+}
+
+// The only test installing the logger, which can happen once per process
+#[test]
+fn test_server_events_carry_connection_fields() {
+    let captured = Captured::default();
+    JsonLogger::new(LevelFilter::Info)
+        .with_writer(captured.clone())
+        .init()
+        .expect("Failed to install the logger");
+
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    server.set_slow_request_threshold(Some(Duration::ZERO));
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    assert_eq!(client.add(1, 2).expect("Add failed"), 3);
+    client.disconnect().expect("Failed to disconnect");
+
+    // The handler logs the disconnect after the client has gone
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let find = |message: &str| {
+        captured
+            .lines()
+            .into_iter()
+            .find(|line| line["message"].as_str().unwrap().starts_with(message))
+    };
+    while find("Client disconnected").is_none() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+    server.stop();
+    handle.join().unwrap();
+
+    let connected = find("New client connected").expect("No connect event");
+    let peer = connected["peer"].as_str().expect("No peer").to_string();
+    assert_ne!(peer, "unknown peer");
+    let slow = find("Slow request").expect("No slow request event");
+    assert_eq!(slow["level"], "WARN");
+    assert_eq!(slow["connection_id"], 1);
+    assert_eq!(slow["peer"], json!(peer));
+    assert_eq!(slow["message_type"], "AddRequest");
+    assert!(slow["duration_ms"].is_u64());
+    let disconnected = find("Client disconnected").expect("No disconnect event");
+    assert_eq!(disconnected["connection_id"], 1);
+    assert_eq!(disconnected["peer"], json!(peer));
+}
@@ -0,0 +1,122 @@
+#![cfg(feature = "json")]
+
+use embedded_recruitment_task::json::{from_json, to_json};
+use embedded_recruitment_task::message::{
+    client_message, error_response, server_message, transform_request, AddRequest, ClientMessage,
+    EchoBytes, ErrorResponse, ServerMessage, TelemetryReport, TransformRequest,
+};
+use serde_json::json;
+
+#[test]
+fn test_requests_follow_the_proto3_json_mapping() {
+    let cases = [
+        (
+            ClientMessage {
+                message: Some(client_message::Message::TelemetryReport(TelemetryReport {
+                    sensor_id: 3,
+                    value: 21.5,
+                    timestamp: 1_700_000_000_000,
+                })),
+                stream_id: 2,
+                ..Default::default()
+            },
+            json!({
+                "telemetryReport": { "sensorId": 3, "value": 21.5, "timestamp": "1700000000000" },
+                "streamId": 2
+            }),
+        ),
+        (
+            ClientMessage {
+                message: Some(client_message::Message::EchoBytes(EchoBytes {
+                    data: vec![0, 1, 0xff],
+                })),
+                ..Default::default()
+            },
+            json!({ "echoBytes": { "data": "AAH/" } }),
+        ),
+        (
+            ClientMessage {
+                message: Some(client_message::Message::TransformRequest(
+                    TransformRequest {
+                        content: "abc".to_string(),
+                        op: transform_request::Op::Sha256 as i32,
+                    },
+                )),
+                ..Default::default()
+            },
+            json!({ "transformRequest": { "content": "abc", "op": "SHA256" } }),
+        ),
+        (ClientMessage::default(), json!({})),
+    ];
+    for (message, value) in cases {
+        assert_eq!(to_json(&message).expect("Failed to convert"), value);
+        assert_eq!(
+            from_json::<ClientMessage>(value).expect("Failed to parse"),
+            message
+        );
+    }
+}
+
+#[test]
+fn test_responses_round_trip() {
+    let response = ServerMessage {
+        message: Some(server_message::Message::ErrorResponse(ErrorResponse {
+            code: error_response::Code::Invalid as i32,
+            field: "b".to_string(),
+            detail: "must not be 0".to_string(),
+            ..Default::default()
+        })),
+        message_id: 9,
+        stream_id: 0,
+    };
+    let value = to_json(&response).expect("Failed to convert");
+    assert_eq!(
+        value,
+        json!({
+            "errorResponse": { "code": "INVALID", "field": "b", "detail": "must not be 0" },
+            "messageId": "9"
+        })
+    );
+    assert_eq!(
+        from_json::<ServerMessage>(value).expect("Failed to parse"),
+        response
+    );
+}
+
+#[test]
+fn test_parsing_accepts_proto_names_and_numbers() {
+    let parsed: ClientMessage = from_json(json!({
+        "add_request": { "a": 1, "b": "2" },
+        "message_id": 5
+    }))
+    .expect("Failed to parse");
+    assert_eq!(
+        parsed.message,
+        Some(client_message::Message::AddRequest(AddRequest {
+            a: 1,
+            b: 2
+        }))
+    );
+    assert_eq!(parsed.message_id, 5);
+}
+
+#[test]
+fn test_invalid_json_is_rejected() {
+    assert!(from_json::<ClientMessage>(json!({ "addRequest": { "a": "one" } })).is_err());
+    assert!(from_json::<ClientMessage>(json!({
+        "echoMessage": { "content": "x" },
+        "addRequest": {}
+    }))
+    .is_err());
+    let unknown = ServerMessage {
+        message: Some(server_message::Message::ErrorResponse(ErrorResponse {
+            code: 99,
+            ..Default::default()
+        })),
+        ..Default::default()
+    };
+    assert!(
+        to_json(&unknown).is_err(),
+        "Unknown enum values have no name"
+    );
+}
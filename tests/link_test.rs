@@ -0,0 +1,174 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::{echo, start};
+use embedded_recruitment_task::authz::{Grant, StaticPolicy};
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::handler::MessageKind;
+use embedded_recruitment_task::link::{Link, Listener};
+use embedded_recruitment_task::message::{error_response, server_message};
+use embedded_recruitment_task::overload::OverloadPolicy;
+use embedded_recruitment_task::server::{Incoming, Server};
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+// A transport of its own: TCP on a second port, whose links have no address
+struct SideDoor {
+    listener: TcpListener,
+    stopped: AtomicBool,
+}
+
+impl SideDoor {
+    fn new() -> (Self, u16) {
+        let listener = TcpListener::bind("localhost:0").expect("Failed to bind");
+        let port = listener.local_addr().unwrap().port();
+        listener.set_nonblocking(true).unwrap();
+        let door = SideDoor {
+            listener,
+            stopped: AtomicBool::new(false),
+        };
+        (door, port)
+    }
+}
+
+impl Listener for SideDoor {
+    fn run(&self, incoming: &Incoming) -> io::Result<()> {
+        while incoming.wait_for_room() && !self.stopped.load(Ordering::SeqCst) {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(false)?;
+                    if incoming.accept(Box::new(Anonymous(stream))).is_err() {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+
+    fn name(&self) -> String {
+        "side door".to_string()
+    }
+}
+
+struct Anonymous(TcpStream);
+
+impl Read for Anonymous {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for Anonymous {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Link for Anonymous {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.0.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.0.set_write_timeout(timeout)
+    }
+
+    fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        Link::peek(&self.0, buf)
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.0.shutdown(how)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Link>> {
+        Ok(Box::new(Anonymous(self.0.try_clone()?)))
+    }
+}
+
+#[test]
+fn test_listener_connections_take_the_server_pipeline() {
+    let (door, door_port) = SideDoor::new();
+    let policy = StaticPolicy::new().everyone(Grant::new().send(MessageKind::Add));
+    let (server, handle, _) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .authorizer(policy)
+            .listener(door),
+    );
+    let mut client = Client::new("localhost", door_port.into(), 1000);
+    client
+        .connect()
+        .expect("Failed to connect to the side door");
+    assert_eq!(client.add(2, 3).expect("Add failed"), 5);
+
+    // The server's authorizer is asked, as for TCP connections
+    client.send(echo("hello")).expect("Failed to send");
+    let response = client.receive().expect("Failed to receive");
+    let Some(server_message::Message::ErrorResponse(error)) = response.message else {
+        panic!("Echo answered: {:?}", response);
+    };
+    assert_eq!(error.code, error_response::Code::Forbidden as i32);
+    let stats = server.stats();
+    assert_eq!((stats.requests, stats.denied_requests), (1, 1));
+
+    // Stopping closes the listener's connections too
+    server.stop();
+    handle.join().unwrap();
+    assert!(client.add(1, 1).is_err());
+}
+
+#[test]
+fn test_listener_waits_for_room_under_the_connection_limit() {
+    let (door, door_port) = SideDoor::new();
+    let (server, handle, _) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .listener(door),
+    );
+    server.set_overload_policy(OverloadPolicy {
+        max_connections: Some(1),
+        ..Default::default()
+    });
+    let mut first = Client::new("localhost", door_port.into(), 1000);
+    first.connect().expect("Failed to connect");
+    assert_eq!(first.add(1, 2).expect("Add failed"), 3);
+
+    // Left in the backlog rather than accepted and refused
+    let mut second = Client::new("localhost", door_port.into(), 300);
+    second.connect().expect("Failed to connect");
+    assert!(second.add(3, 4).is_err(), "Served over the limit");
+    assert_eq!(server.stats().shed_connections, 0);
+
+    first.disconnect().expect("Failed to disconnect");
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while second.add(5, 6).is_err() {
+        assert!(Instant::now() < deadline, "Not served once there was room");
+    }
+
+    server.stop();
+    handle.join().unwrap();
+}
@@ -0,0 +1,103 @@
+#![cfg(feature = "server")]
+
+mod common;
+
+use common::start;
+use embedded_recruitment_task::loglimit::{LogClass, LogLimits};
+use embedded_recruitment_task::server::Server;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::{
+    fs,
+    io::Write,
+    net::TcpStream,
+    path::PathBuf,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+// Keeps the messages of everything logged
+struct Collector(Mutex<Vec<String>>);
+
+impl Log for Collector {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        self.0.lock().unwrap().push(record.args().to_string());
+    }
+
+    fn flush(&self) {}
+}
+
+#[test]
+fn test_rate_limit_counts_what_it_drops() {
+    let limits = LogLimits::new();
+    limits.set_rate_limit(LogClass::ClientError, 3);
+
+    let admitted = (0..10)
+        .filter(|_| limits.admit(LogClass::ClientError, Level::Warn))
+        .count();
+    assert_eq!(admitted, 3);
+    assert_eq!(limits.suppressed(LogClass::ClientError), 7);
+    // Other classes keep their own allowance
+    assert!(limits.admit(LogClass::Refusal, Level::Warn));
+    assert_eq!(limits.suppressed(LogClass::Refusal), 0);
+
+    // A new window starts a second later
+    thread::sleep(Duration::from_millis(1100));
+    assert!(limits.admit(LogClass::ClientError, Level::Warn));
+}
+
+#[test]
+fn test_sampling_considers_one_event_in_n() {
+    let limits = LogLimits::new();
+    limits.set_rate_limit(LogClass::Message, u32::MAX);
+    limits.set_sample_rate(LogClass::Message, 4);
+
+    let admitted = (0..20)
+        .filter(|_| limits.admit(LogClass::Message, Level::Info))
+        .count();
+    assert_eq!(admitted, 5);
+    assert_eq!(limits.suppressed(LogClass::Message), 15);
+
+    limits.set_rate_limit(LogClass::Message, 0);
+    limits.set_sample_rate(LogClass::Message, 1);
+    assert!(!limits.admit(LogClass::Message, Level::Info));
+}
+
+// The only test installing the logger, which can happen once per process
+#[test]
+fn test_garbage_from_many_connections_is_logged_within_the_limit() {
+    let collector: &'static Collector = Box::leak(Box::new(Collector(Mutex::new(Vec::new()))));
+    log::set_logger(collector).expect("Failed to install the logger");
+    log::set_max_level(LevelFilter::Info);
+
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    server.log_limits().set_rate_limit(LogClass::ClientError, 2);
+    let garbage =
+        fs::read(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fuzz/regressions/garbage_body"))
+            .expect("Missing regression input");
+    for _ in 0..10 {
+        let mut stream = TcpStream::connect(("localhost", port)).expect("Failed to connect");
+        stream.write_all(&garbage).expect("Failed to send");
+    }
+
+    let errors = || {
+        (collector.0.lock().unwrap().iter())
+            .filter(|message| message.starts_with("Error handling client"))
+            .count() as u64
+    };
+    let suppressed = || server.log_limits().suppressed(LogClass::ClientError);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while errors() + suppressed() < 10 && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+    server.stop();
+    handle.join().unwrap();
+
+    assert_eq!(errors() + suppressed(), 10);
+    // Two per second; a slow machine may have taken a second window
+    assert!(errors() <= 4, "{} errors logged", errors());
+}
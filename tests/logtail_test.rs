@@ -0,0 +1,106 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::start;
+use embedded_recruitment_task::authz::{Grant, StaticPolicy};
+use embedded_recruitment_task::client::{Client, Push};
+use embedded_recruitment_task::handler::MessageKind;
+use embedded_recruitment_task::logtail::LogTail;
+use embedded_recruitment_task::message::{log_event, LogEvent};
+use embedded_recruitment_task::server::Server;
+use log::LevelFilter;
+use std::{io, sync::OnceLock};
+
+// The tail, installed once for the whole process; records are logged at warn
+// so the client's own info lines about the events it receives stay out of it
+fn tail() -> &'static LogTail {
+    static TAIL: OnceLock<&'static LogTail> = OnceLock::new();
+    TAIL.get_or_init(|| {
+        LogTail::new(LevelFilter::Warn)
+            .capacity(8)
+            .install()
+            .expect("Failed to install the logger")
+    })
+}
+
+fn admin_policy() -> StaticPolicy {
+    StaticPolicy::new().everyone(Grant::all_requests().send(MessageKind::TailLogs))
+}
+
+fn next_log(client: &mut Client) -> LogEvent {
+    loop {
+        if let Push::Log(event) = client.next_push().expect("No log event") {
+            return event;
+        }
+    }
+}
+
+#[test]
+fn test_recent_then_live_records_are_streamed() {
+    let tail = tail();
+    log::warn!(target: "logtail_test::recent", "before anyone tails");
+    log::warn!(target: "logtail_test::other", "not asked for");
+    let (server, handle, port) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .authorizer(admin_policy())
+            .log_tail(tail),
+    );
+    let mut client = Client::new("localhost", port.into(), 5000);
+    client.connect().expect("Failed to connect to the server");
+
+    let recent = (client.tail_logs(log_event::Level::Warn, "logtail_test::recent"))
+        .expect("TailLogs failed");
+    assert_eq!(recent, 1);
+    let event = next_log(&mut client);
+    assert_eq!(event.message, "before anyone tails");
+    assert_eq!(event.level, log_event::Level::Warn as i32);
+    assert!(event.time_ms > 0);
+    assert_eq!(tail.followers(), 1);
+
+    log::error!(target: "logtail_test::other", "still not asked for");
+    log::warn!(target: "logtail_test::recent", device = "thermo-1"; "live record");
+    let event = next_log(&mut client);
+    assert_eq!(event.target, "logtail_test::recent");
+    assert_eq!(event.message, "live record");
+    assert_eq!(event.fields.len(), 1);
+    assert_eq!(event.fields[0].key, "device");
+    assert_eq!(event.fields[0].value, "thermo-1");
+
+    // A follower is dropped with its connection, at the next record it wants
+    drop(client);
+    server.stop();
+    handle.join().unwrap();
+    log::warn!(target: "logtail_test::recent", "nobody is listening");
+    assert_eq!(tail.followers(), 0);
+}
+
+#[test]
+fn test_tailing_is_an_admin_request() {
+    let (server, handle, port) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .log_tail(tail()),
+    );
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    let refused = client.tail_logs(log_event::Level::Info, "").unwrap_err();
+    assert_eq!(refused.kind(), io::ErrorKind::PermissionDenied);
+    // Other requests are allowed as before
+    assert_eq!(client.add(1, 2).expect("Add failed"), 3);
+    server.stop();
+    handle.join().unwrap();
+
+    let (server, handle, port) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .authorizer(admin_policy()),
+    );
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    let refused = client.tail_logs(log_event::Level::Info, "").unwrap_err();
+    assert_eq!(refused.kind(), io::ErrorKind::Unsupported);
+    server.stop();
+    handle.join().unwrap();
+}
@@ -0,0 +1,129 @@
+//! Runs the server's shutdown primitives under loom, which tries every
+//! interleaving of their threads and fails on a deadlock or lost wakeup:
+//!
+//! RUSTFLAGS="--cfg loom" cargo test --release --test loom_test
+#![cfg(all(loom, feature = "server"))]
+
+use embedded_recruitment_task::codec::Bytes;
+use embedded_recruitment_task::mailbox::{Mailbox, MailboxLimits, Overflow, Posted};
+use embedded_recruitment_task::sharded::Sharded;
+use embedded_recruitment_task::shutdown::{Registry, StopSignal};
+use loom::{sync::Arc, thread};
+use std::time::Duration;
+
+// Long enough never to run out within a test; loom ignores it anyway
+const WAIT: Duration = Duration::from_secs(3600);
+
+#[test]
+fn loom_stop_wakes_the_accept_loop() {
+    loom::model(|| {
+        let signal = Arc::new(StopSignal::new());
+        let accept = {
+            let signal = signal.clone();
+            thread::spawn(move || while signal.wait_timeout(WAIT) {})
+        };
+        assert!(signal.stop());
+        accept.join().unwrap();
+        assert!(!signal.is_running());
+    });
+}
+
+#[test]
+fn loom_only_one_stop_stops() {
+    loom::model(|| {
+        let signal = Arc::new(StopSignal::new());
+        let other = {
+            let signal = signal.clone();
+            thread::spawn(move || signal.stop())
+        };
+        let stopped_here = signal.stop();
+        let stopped_there = other.join().unwrap();
+        assert!(stopped_here != stopped_there);
+    });
+}
+
+// A connection registering while the server stops is either refused or
+// closed; its handler never waits for a close that has already happened
+#[test]
+fn loom_stop_closes_connections_registering_meanwhile() {
+    loom::model(|| {
+        let registry = Arc::new(Sharded::new(2, Registry::<Arc<StopSignal>>::new));
+        let handler = {
+            let registry = registry.clone();
+            thread::spawn(move || {
+                let socket = Arc::new(StopSignal::new());
+                let inserted = registry.lock_index(1).insert(socket.clone());
+                if let Ok(key) = inserted {
+                    while socket.wait_timeout(WAIT) {} // A blocked read
+                    registry.lock_index(1).remove(key);
+                }
+            })
+        };
+        registry.for_each(|connections| {
+            for socket in connections.close() {
+                socket.stop();
+            }
+        });
+        handler.join().unwrap();
+        assert!(registry.shards().all(|connections| connections.is_empty()));
+    });
+}
+
+#[test]
+fn loom_registry_insert_and_remove_race() {
+    loom::model(|| {
+        let registry = Arc::new(Sharded::new(1, Registry::<u32>::new));
+        let handlers: Vec<_> = (0..2)
+            .map(|n| {
+                let registry = registry.clone();
+                thread::spawn(move || {
+                    let key = registry.lock_index(0).insert(n).unwrap();
+                    assert_eq!(registry.lock_index(0).get(key), Some(&n));
+                    assert_eq!(registry.lock_index(0).remove(key), Some(n));
+                })
+            })
+            .collect();
+        for handler in handlers {
+            handler.join().unwrap();
+        }
+        assert!(registry.lock_index(0).is_empty());
+    });
+}
+
+fn blocking(capacity: usize) -> MailboxLimits {
+    MailboxLimits {
+        capacity,
+        overflow: Overflow::Block(WAIT),
+    }
+}
+
+#[test]
+fn loom_closing_releases_a_blocked_publisher() {
+    loom::model(|| {
+        let mailbox = Arc::new(Mailbox::default());
+        let frame = Bytes::from_static(b"frame");
+        assert_eq!(mailbox.post(frame.clone(), blocking(1)), Posted::Queued);
+        let publisher = {
+            let mailbox = mailbox.clone();
+            thread::spawn(move || mailbox.post(frame, blocking(1)))
+        };
+        mailbox.close();
+        assert_eq!(publisher.join().unwrap(), Posted::Dropped);
+    });
+}
+
+#[test]
+fn loom_taking_makes_room_for_a_blocked_publisher() {
+    loom::model(|| {
+        let mailbox = Arc::new(Mailbox::default());
+        let frame = Bytes::from_static(b"frame");
+        assert_eq!(mailbox.post(frame.clone(), blocking(1)), Posted::Queued);
+        let publisher = {
+            let mailbox = mailbox.clone();
+            thread::spawn(move || mailbox.post(frame, blocking(1)))
+        };
+        let taken = mailbox.take().unwrap().len();
+        assert_eq!(publisher.join().unwrap(), Posted::Queued);
+        assert_eq!(taken + mailbox.depth(), 2);
+    });
+}
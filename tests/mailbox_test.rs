@@ -0,0 +1,135 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::mailbox::{MailboxLimits, Overflow};
+use embedded_recruitment_task::message::{
+    client_message, server_message, Delivery, EchoMessage, ResumeRequest,
+};
+use embedded_recruitment_task::router::Router;
+use embedded_recruitment_task::server::Server;
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+const BUSY: Duration = Duration::from_millis(500); // How long each echo keeps the handler
+
+// Starts a server whose echoes keep the handler busy, so broadcasts pile up meanwhile
+fn start(limits: MailboxLimits) -> (Arc<Server>, thread::JoinHandle<()>, u16) {
+    let router = Router::new().on_echo(|echo| {
+        thread::sleep(BUSY);
+        server_message::Message::EchoMessage(echo)
+    });
+    let server = Server::new("localhost:0")
+        .expect("Failed to start server")
+        .router(router);
+    server.set_mailbox_limits(limits);
+    common::start(server)
+}
+
+// Connects as a device, then keeps its handler busy with an echo
+fn busy_device(port: u16) -> Client {
+    let mut client = Client::new("localhost", port.into(), 2000);
+    client.connect().expect("Failed to connect to the server");
+    client
+        .send(client_message::Message::ResumeRequest(ResumeRequest {
+            device_id: "sensor-1".to_string(),
+            ..Default::default()
+        }))
+        .expect("Failed to send message");
+    client.receive().expect("Failed to receive response");
+    client
+        .send(client_message::Message::EchoMessage(EchoMessage {
+            content: "busy".to_string(),
+        }))
+        .expect("Failed to send message");
+    thread::sleep(Duration::from_millis(100)); // Let the handler pick the echo up
+    client
+}
+
+fn receive_delivery(client: &mut Client) -> Delivery {
+    loop {
+        match client.receive().expect("Failed to receive").message {
+            Some(server_message::Message::Delivery(delivery)) => return delivery,
+            Some(server_message::Message::EchoMessage(_)) => continue,
+            other => panic!("Expected a Delivery, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn test_full_mailbox_drops_the_oldest() {
+    let (server, handle, port) = start(MailboxLimits {
+        capacity: 2,
+        overflow: Overflow::DropOldest,
+    });
+    let mut client = busy_device(port);
+    for payload in 1..=5u8 {
+        assert_eq!(server.broadcast(vec![payload]), Ok(1));
+    }
+    let stats = server.stats();
+    assert_eq!((stats.mailbox_frames, stats.deepest_mailbox), (2, 2));
+    assert_eq!(stats.mailbox_drops, 3);
+
+    assert_eq!(receive_delivery(&mut client).payload, &[4][..]);
+    assert_eq!(receive_delivery(&mut client).payload, &[5][..]);
+    assert_eq!(server.stats().mailbox_frames, 0);
+    client.disconnect().expect("Failed to disconnect");
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_slow_consumer_is_disconnected() {
+    let (server, handle, port) = start(MailboxLimits {
+        capacity: 1,
+        overflow: Overflow::Disconnect,
+    });
+    let mut client = busy_device(port);
+    assert_eq!(server.broadcast(b"first".to_vec()), Ok(1));
+    assert_eq!(server.broadcast(b"second".to_vec()), Ok(0));
+    assert_eq!(server.broadcast(b"third".to_vec()), Ok(0));
+
+    assert!(client.receive().is_err());
+    let stats = server.stats();
+    assert_eq!(stats.slow_consumer_disconnects, 1);
+    assert_eq!(stats.mailbox_drops, 1);
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_publisher_waits_for_room() {
+    let (server, handle, port) = start(MailboxLimits {
+        capacity: 1,
+        overflow: Overflow::Block(Duration::from_secs(5)),
+    });
+    let mut client = busy_device(port);
+    assert_eq!(server.broadcast(b"first".to_vec()), Ok(1));
+    let started = Instant::now();
+    assert_eq!(server.broadcast(b"second".to_vec()), Ok(1));
+    assert!(started.elapsed() >= Duration::from_millis(200));
+
+    assert_eq!(receive_delivery(&mut client).payload, &b"first"[..]);
+    assert_eq!(receive_delivery(&mut client).payload, &b"second"[..]);
+    client.disconnect().expect("Failed to disconnect");
+
+    server.set_mailbox_limits(MailboxLimits {
+        capacity: 1,
+        overflow: Overflow::Block(Duration::from_millis(50)),
+    });
+    let mut client = busy_device(port);
+    server.broadcast(b"kept".to_vec()).expect("Frame fits");
+    assert_eq!(server.broadcast(b"timed out".to_vec()), Ok(0));
+    assert_eq!(server.stats().mailbox_drops, 1);
+    assert_eq!(receive_delivery(&mut client).payload, &b"kept"[..]);
+    client.disconnect().expect("Failed to disconnect");
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
@@ -0,0 +1,128 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use embedded_recruitment_task::codec;
+use embedded_recruitment_task::message::{
+    client_message, close::Reason, server_message, ClientMessage, EchoMessage,
+};
+use embedded_recruitment_task::protocol::{ClientProtocol, Event};
+use embedded_recruitment_task::server::Server;
+use embedded_recruitment_task::stats::Stats;
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+fn start() -> (Arc<Server>, thread::JoinHandle<()>, TcpStream) {
+    let (server, handle, port) =
+        common::start(Server::new("localhost:0").expect("Failed to start server"));
+    let stream = TcpStream::connect(("localhost", port)).expect("Failed to connect to the server");
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .expect("Failed to set timeout");
+    (server, handle, stream)
+}
+
+// Polls the server's stats until `done` holds, failing after five seconds
+fn wait_for(server: &Server, done: impl Fn(&Stats) -> bool) -> Stats {
+    let start = Instant::now();
+    loop {
+        let stats = server.stats();
+        if done(&stats) {
+            return stats;
+        }
+        assert!(start.elapsed() < Duration::from_secs(5), "Timed out");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+// The length prefix of a 1000 byte frame, and the first `body` bytes of it
+fn partial_frame(body: usize) -> Vec<u8> {
+    let mut bytes = vec![0xe8, 0x07];
+    bytes.resize(2 + body, 0);
+    bytes
+}
+
+#[test]
+fn test_partial_frames_are_counted_as_buffered() {
+    let (server, handle, mut stream) = start();
+    stream.write_all(&partial_frame(100)).unwrap();
+
+    wait_for(&server, |stats| stats.buffered_bytes == 102);
+    drop(stream);
+    wait_for(&server, |stats| stats.buffered_bytes == 0);
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_partial_frame_over_the_limit_closes_the_connection() {
+    let (server, handle, mut stream) = start();
+    server.set_connection_memory_limit(Some(256));
+    stream.write_all(&partial_frame(300)).unwrap();
+
+    // The server says why it closes, unless the unread bytes reset the connection first
+    let mut received = Vec::new();
+    let _ = stream.read_to_end(&mut received);
+    for event in ClientProtocol::new().feed_bytes(&received) {
+        match event {
+            Event::Message(message) => match message.message {
+                Some(server_message::Message::Close(close)) => {
+                    assert_eq!(close.reason(), Reason::ResourceLimit)
+                }
+                other => panic!("Unexpected response {:?}", other),
+            },
+            event => panic!("Unexpected event {:?}", event),
+        }
+    }
+    wait_for(&server, |stats| {
+        stats.memory_disconnects == 1 && stats.buffered_bytes == 0
+    });
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_responses_over_the_limit_are_all_delivered() {
+    let (server, handle, mut stream) = start();
+    server.set_connection_memory_limit(Some(64));
+
+    // Ten requests in one write, whose responses together exceed the limit
+    let mut bytes = Vec::new();
+    for message_id in 1..=10 {
+        let request = ClientMessage {
+            message: Some(client_message::Message::EchoMessage(EchoMessage {
+                content: "0123456789abcdef".to_string(),
+            })),
+            message_id,
+            ..Default::default()
+        };
+        bytes.extend(codec::encode(&request).unwrap());
+    }
+    stream.write_all(&bytes).unwrap();
+
+    let mut protocol = ClientProtocol::new();
+    let mut responses = Vec::new();
+    let mut buffer = [0; 512];
+    while responses.len() < 10 {
+        let read = stream.read(&mut buffer).expect("Failed to read responses");
+        assert!(read > 0, "Connection closed early");
+        for event in protocol.feed_bytes(&buffer[..read]) {
+            if let Event::Message(response) = event {
+                responses.push(response.message_id);
+            }
+        }
+    }
+    assert_eq!(responses, (1..=10).collect::<Vec<_>>());
+    assert_eq!(server.stats().memory_disconnects, 0);
+
+    drop(stream);
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
@@ -0,0 +1,131 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::start_connected;
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::handler::MessageKind;
+use embedded_recruitment_task::message::{
+    client_message, server_message, AddRequest, EchoMessage, PingRequest, PingResponse,
+};
+use embedded_recruitment_task::middleware;
+use embedded_recruitment_task::router::Router;
+use embedded_recruitment_task::server::Server;
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+};
+
+fn stop(server: Arc<Server>, handle: thread::JoinHandle<()>, mut client: Client) {
+    client.disconnect().ok();
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_layers_run_outermost_first() {
+    let trace = Arc::new(Mutex::new(Vec::new()));
+    let layer = |name: &'static str| {
+        let trace = trace.clone();
+        middleware::from_fn(move |request, next| {
+            trace.lock().unwrap().push(format!("{} before", name));
+            let response = next.run(request);
+            trace.lock().unwrap().push(format!("{} after", name));
+            response
+        })
+    };
+    let router_trace = trace.clone();
+    let router = Router::new().on_ping(move |ping| {
+        router_trace.lock().unwrap().push("router".to_string());
+        server_message::Message::PingResponse(PingResponse {
+            timestamp: ping.timestamp,
+        })
+    });
+    let (server, handle, mut client) = start_connected(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .router(router)
+            .layer(layer("outer"))
+            .layer(layer("inner")),
+    );
+
+    client
+        .send(client_message::Message::PingRequest(PingRequest {
+            timestamp: 5,
+        }))
+        .expect("Failed to send message");
+    client.receive().expect("Failed to receive response");
+    assert_eq!(
+        *trace.lock().unwrap(),
+        [
+            "outer before",
+            "inner before",
+            "router",
+            "inner after",
+            "outer after"
+        ]
+    );
+
+    stop(server, handle, client);
+}
+
+#[test]
+fn test_layers_can_answer_without_the_handler() {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let seen_clone = seen.clone();
+    // Refuses additions, passes everything else on
+    let deny_add = middleware::from_fn(move |request, next| {
+        seen_clone
+            .lock()
+            .unwrap()
+            .push((request.kind, request.message_id, request.peer.is_some()));
+        match request.kind {
+            MessageKind::Add => Ok(server_message::Message::EchoMessage(EchoMessage {
+                content: "denied".to_string(),
+            })),
+            _ => next.run(request),
+        }
+    });
+    let (server, handle, mut client) = start_connected(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .layer(deny_add),
+    );
+
+    client
+        .send_with_id(
+            0,
+            9,
+            client_message::Message::AddRequest(AddRequest { a: 1, b: 2 }),
+        )
+        .expect("Failed to send message");
+    assert_eq!(
+        client
+            .receive()
+            .expect("Failed to receive response")
+            .message,
+        Some(server_message::Message::EchoMessage(EchoMessage {
+            content: "denied".to_string()
+        }))
+    );
+    client
+        .send(client_message::Message::EchoMessage(EchoMessage {
+            content: "hello".to_string(),
+        }))
+        .expect("Failed to send message");
+    assert_eq!(
+        client
+            .receive()
+            .expect("Failed to receive response")
+            .message,
+        Some(server_message::Message::EchoMessage(EchoMessage {
+            content: "hello".to_string()
+        }))
+    );
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [(MessageKind::Add, 9, true), (MessageKind::Echo, 0, true)]
+    );
+
+    stop(server, handle, client);
+}
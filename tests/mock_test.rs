@@ -0,0 +1,215 @@
+#![cfg(all(feature = "client", feature = "testing"))]
+
+use embedded_recruitment_task::client::{Client, Push};
+use embedded_recruitment_task::message::{
+    client_message, error_response, server_message, AddRequest, AddResponse, Delivery, EchoMessage,
+    ResumeResponse, ResyncRequest, ResyncResponse,
+};
+use embedded_recruitment_task::testing::{MockServer, Reply};
+use std::{io, thread, time::Duration};
+
+fn connect(mock: &MockServer, timeout_ms: u64) -> Client {
+    let mut client = Client::new("127.0.0.1", mock.port().into(), timeout_ms);
+    client
+        .connect()
+        .expect("Failed to connect to the mock server");
+    client
+}
+
+#[test]
+fn test_scripted_replies_answer_in_order() {
+    let mock = MockServer::start().expect("Failed to start mock server");
+    mock.reply(Reply::with(server_message::Message::AddResponse(
+        AddResponse { result: 5 },
+    )));
+    mock.reply(Reply::error(error_response::Code::Busy, "try later"));
+    let mut client = connect(&mock, 1000);
+
+    assert_eq!(client.add(2, 2).expect("Add failed"), 5);
+    let refused = client.add(1, 1).expect_err("Busy reply accepted");
+    assert_eq!(refused.kind(), io::ErrorKind::ResourceBusy);
+    // Beyond the script, requests are refused as unsupported
+    let refused = client
+        .echo("anyone?")
+        .expect_err("Unscripted request answered");
+    assert_eq!(refused.kind(), io::ErrorKind::Unsupported);
+
+    let received = mock.received();
+    assert_eq!(received.len(), 3);
+    assert_eq!(
+        received[0].message,
+        Some(client_message::Message::AddRequest(AddRequest {
+            a: 2,
+            b: 2
+        }))
+    );
+}
+
+#[test]
+fn test_responder_answers_unscripted_requests() {
+    let mock = MockServer::start().expect("Failed to start mock server");
+    mock.respond_with(|request| match request {
+        client_message::Message::EchoMessage(echo) => {
+            Reply::with(server_message::Message::EchoMessage(EchoMessage {
+                content: echo.content.to_uppercase(),
+            }))
+        }
+        _ => Reply::silence(),
+    });
+    let mut client = connect(&mock, 1000);
+    // More requests than the flow control window, so credits must come back
+    for n in 0..40 {
+        let content = format!("request {}", n);
+        assert_eq!(
+            client.echo(&content).expect("Echo failed"),
+            content.to_uppercase()
+        );
+    }
+    assert_eq!(mock.wait_for(40, Duration::from_secs(1)).len(), 40);
+}
+
+#[test]
+fn test_delayed_silent_and_closing_replies() {
+    let mock = MockServer::start().expect("Failed to start mock server");
+    mock.reply(
+        Reply::with(server_message::Message::AddResponse(AddResponse {
+            result: 1,
+        }))
+        .after(Duration::from_millis(400)),
+    );
+    mock.reply(Reply::silence());
+    mock.reply(Reply::close());
+    let mut client = connect(&mock, 100);
+
+    let late = client.add(0, 1).expect_err("Late reply arrived in time");
+    assert!(matches!(
+        late.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    ));
+    thread::sleep(Duration::from_millis(400));
+    client.receive().expect("Delayed reply never arrived");
+
+    assert!(client.echo("lost").is_err());
+    assert!(client.echo("closed").is_err());
+    assert_eq!(mock.received().len(), 3);
+}
+
+#[test]
+fn test_late_reply_is_not_taken_for_the_next_call() {
+    let mock = MockServer::start().expect("Failed to start mock server");
+    mock.reply(
+        Reply::with(server_message::Message::EchoMessage(EchoMessage {
+            content: "first".to_string(),
+        }))
+        .after(Duration::from_millis(400)),
+    );
+    mock.respond_with(|request| match request {
+        client_message::Message::EchoMessage(echo) => {
+            Reply::with(server_message::Message::EchoMessage(echo.clone()))
+        }
+        _ => Reply::silence(),
+    });
+    let mut client = connect(&mock, 300);
+
+    let late = client
+        .echo("first")
+        .expect_err("Late reply arrived in time");
+    assert_eq!(late.kind(), io::ErrorKind::WouldBlock);
+    assert_eq!(client.echo("second").expect("Echo failed"), "second");
+    assert_eq!(client.echo("third").expect("Echo failed"), "third");
+    let ids: Vec<u64> = mock.received().iter().map(|r| r.message_id).collect();
+    assert!(
+        ids[0] != ids[1] && ids[1] != ids[2],
+        "IDs reused: {:?}",
+        ids
+    );
+}
+
+#[test]
+fn test_push_reaches_open_connections() {
+    let mock = MockServer::start().expect("Failed to start mock server");
+    let mut client = connect(&mock, 1000);
+    mock.reply(Reply::with(server_message::Message::AddResponse(
+        AddResponse { result: 3 },
+    )));
+    // Once answered, the connection is known to the mock
+    assert_eq!(client.add(1, 2).expect("Add failed"), 3);
+
+    let delivery = Delivery {
+        sequence: 1,
+        payload: b"hello".to_vec().into(),
+    };
+    assert_eq!(
+        mock.push(server_message::Message::Delivery(delivery.clone())),
+        1
+    );
+    assert_eq!(
+        client.next_push().expect("Failed to receive push"),
+        Push::Delivery(delivery)
+    );
+}
+
+fn delivery(sequence: u64) -> server_message::Message {
+    server_message::Message::Delivery(Delivery {
+        sequence,
+        payload: vec![sequence as u8].into(),
+    })
+}
+
+fn sequence(push: io::Result<Push>) -> u64 {
+    match push.expect("Failed to receive push") {
+        Push::Delivery(delivery) => delivery.sequence,
+        other => panic!("Expected a Delivery, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_missing_deliveries_are_asked_for_again() {
+    let mock = MockServer::start().expect("Failed to start mock server");
+    mock.reply(Reply::with(server_message::Message::ResumeResponse(
+        ResumeResponse::default(),
+    )));
+    mock.reply(Reply::with(server_message::Message::ResyncResponse(
+        ResyncResponse {
+            replayed: 1,
+            first: 2,
+        },
+    )));
+    mock.reply(Reply::with(server_message::Message::ResyncResponse(
+        ResyncResponse {
+            replayed: 0,
+            first: 5,
+        },
+    )));
+    let mut client = connect(&mock, 300);
+    client.subscribe("sensor-1").expect("Failed to subscribe");
+
+    mock.push(delivery(1));
+    mock.push(delivery(3));
+    assert_eq!(sequence(client.next_push()), 1);
+    // 3 is held back until 2 arrives
+    assert!(client.next_push().is_err());
+    let received = mock.wait_for(2, Duration::from_secs(1));
+    assert_eq!(
+        received[1].message,
+        Some(client_message::Message::ResyncRequest(ResyncRequest {
+            from: 2,
+            to: 3
+        }))
+    );
+    mock.push(delivery(2));
+    assert_eq!(sequence(client.next_push()), 2);
+    assert_eq!(sequence(client.next_push()), 3);
+
+    // The server no longer has 4, so it is skipped
+    mock.push(delivery(5));
+    assert_eq!(sequence(client.next_push()), 5);
+    let received = mock.wait_for(3, Duration::from_secs(1));
+    assert_eq!(
+        received[2].message,
+        Some(client_message::Message::ResyncRequest(ResyncRequest {
+            from: 4,
+            to: 5
+        }))
+    );
+}
@@ -0,0 +1,183 @@
+#![cfg(all(feature = "client", feature = "native-plugins"))]
+
+mod common;
+
+use common::add;
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::message::{
+    client_message, server_message, AddResponse, PingRequest, PingResponse,
+};
+use embedded_recruitment_task::native::{PluginDir, ABI_VERSION};
+use embedded_recruitment_task::server::Server;
+use std::{
+    env::consts::{DLL_EXTENSION, DLL_PREFIX},
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Arc,
+    thread,
+};
+
+// Answers `AddRequest`s with the request itself, which decodes as an
+// `AddResponse` whose result is `a`, and passes everything else on
+const MIRROR: &str = r#"
+#[repr(C)]
+pub struct Buffer { ptr: *mut u8, len: usize }
+
+#[repr(C)]
+pub struct Vtable {
+    abi_version: u32,
+    handle: unsafe extern "C" fn(*const u8, usize, *mut Buffer) -> i32,
+    free: unsafe extern "C" fn(Buffer),
+}
+
+unsafe extern "C" fn handle(request: *const u8, len: usize, response: *mut Buffer) -> i32 {
+    let bytes = unsafe { std::slice::from_raw_parts(request, len) };
+    if bytes.first() != Some(&0x12) {
+        return 0;
+    }
+    let bytes = bytes.to_vec().into_boxed_slice();
+    let len = bytes.len();
+    unsafe { *response = Buffer { ptr: Box::into_raw(bytes) as *mut u8, len } };
+    1
+}
+
+unsafe extern "C" fn free(response: Buffer) {
+    drop(unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(response.ptr, response.len)) });
+}
+
+static VTABLE: Vtable = Vtable { abi_version: VERSION, handle, free };
+
+#[no_mangle]
+pub extern "C" fn plugin_register() -> *const Vtable {
+    &VTABLE
+}
+"#;
+
+fn plugin_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("native-test-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).expect("Failed to create plugin directory");
+    dir
+}
+
+// Builds the mirror plugin for `abi_version` into `dir`, returning its file name
+fn build(dir: &Path, name: &str, abi_version: u32) -> String {
+    let source = dir.join(format!("{}.rs", name));
+    fs::write(
+        &source,
+        format!("const VERSION: u32 = {};\n{}", abi_version, MIRROR),
+    )
+    .expect("Failed to write plugin source");
+    let file = format!("{}{}.{}", DLL_PREFIX, name, DLL_EXTENSION);
+    let status = Command::new(std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string()))
+        .args(["--edition", "2021", "--crate-type", "cdylib", "-o"])
+        .arg(dir.join(&file))
+        .arg(&source)
+        .status()
+        .expect("Failed to run rustc");
+    assert!(status.success(), "Failed to build plugin {}", name);
+    file
+}
+
+#[test]
+fn test_plugins_answer_requests_over_the_network() {
+    let dir = plugin_dir("network");
+    build(&dir, "mirror", ABI_VERSION);
+    let plugins = PluginDir::open(&dir).expect("Failed to open plugin directory");
+    let server = Arc::new(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .layer(plugins.clone()),
+    );
+    let port = server.local_addr().expect("No local address").port();
+    let server_clone = Arc::clone(&server);
+    let handle = thread::spawn(move || server_clone.run().expect("Server encountered an error"));
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+
+    client.send(add(5, 7)).expect("Failed to send message");
+    assert_eq!(
+        client
+            .receive()
+            .expect("Failed to receive response")
+            .message,
+        Some(server_message::Message::AddResponse(AddResponse {
+            result: 5
+        }))
+    );
+    client
+        .send(client_message::Message::PingRequest(PingRequest {
+            timestamp: 9,
+        }))
+        .expect("Failed to send message");
+    assert_eq!(
+        client
+            .receive()
+            .expect("Failed to receive response")
+            .message,
+        Some(server_message::Message::PingResponse(PingResponse {
+            timestamp: 9
+        })),
+        "Passed on by the plugin"
+    );
+
+    // Unloaded, the built-in handler answers again
+    assert!(plugins.unload(&format!("{}mirror.{}", DLL_PREFIX, DLL_EXTENSION)));
+    client.send(add(5, 7)).expect("Failed to send message");
+    assert_eq!(
+        client
+            .receive()
+            .expect("Failed to receive response")
+            .message,
+        Some(server_message::Message::AddResponse(AddResponse {
+            result: 12
+        }))
+    );
+
+    client.disconnect().ok();
+    server.stop();
+    handle.join().expect("Server thread panicked");
+    fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn test_load_unload_and_rescan() {
+    let dir = plugin_dir("admin");
+    let plugins = PluginDir::open(&dir).expect("Failed to open plugin directory");
+    assert!(plugins.loaded().is_empty());
+    assert_eq!(plugins.handle(&add(5, 7)), None);
+
+    let mirror = build(&dir, "mirror", ABI_VERSION);
+    let outdated = build(&dir, "outdated", ABI_VERSION + 1);
+    assert_eq!(
+        plugins.rescan().expect("Failed to rescan"),
+        std::slice::from_ref(&mirror),
+        "A plugin for another ABI version is skipped"
+    );
+    assert_eq!(plugins.loaded(), std::slice::from_ref(&mirror));
+    assert!(plugins.rescan().expect("Failed to rescan").is_empty());
+    assert_eq!(
+        plugins.handle(&add(5, 7)),
+        Some(server_message::Message::AddResponse(AddResponse {
+            result: 5
+        }))
+    );
+
+    let error = plugins
+        .load(&outdated)
+        .expect_err("Loaded an outdated plugin");
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    assert!(error.to_string().contains("ABI version"), "{}", error);
+    let error = plugins
+        .load("../mirror.so")
+        .expect_err("Loaded outside the directory");
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+
+    assert!(plugins.unload(&mirror));
+    assert!(!plugins.unload(&mirror));
+    assert_eq!(plugins.handle(&add(5, 7)), None);
+    plugins.load(&mirror).expect("Failed to load plugin again");
+    assert_eq!(plugins.loaded(), [mirror]);
+
+    fs::remove_dir_all(dir).ok();
+}
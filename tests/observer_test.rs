@@ -0,0 +1,141 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::start;
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::handler::MessageKind;
+use embedded_recruitment_task::message::{client_message, server_message, EchoMessage};
+use embedded_recruitment_task::observer::{ConnectionInfo, Observer};
+use embedded_recruitment_task::server::Server;
+use std::{
+    io,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+// Records every event as a line of text
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<String>>>);
+
+impl Recorder {
+    fn events(&self) -> Vec<String> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn push(&self, event: String) {
+        self.0.lock().unwrap().push(event);
+    }
+
+    // Waits for the connection to be reported closed
+    fn wait_for_disconnect(&self) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !self.events().iter().any(|e| e.starts_with("disconnect")) {
+            assert!(
+                Instant::now() < deadline,
+                "No disconnect: {:?}",
+                self.events()
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+impl Observer for Recorder {
+    fn on_connect(&self, connection: &ConnectionInfo) {
+        assert!(connection.peer.is_some());
+        self.push(format!("connect {}", connection.id));
+    }
+
+    fn on_message(&self, connection: &ConnectionInfo, kind: MessageKind, _elapsed: Duration) {
+        self.push(format!("message {} {}", connection.id, kind.name()));
+    }
+
+    fn on_error(&self, connection: &ConnectionInfo, _error: &io::Error) {
+        self.push(format!("error {}", connection.id));
+    }
+
+    fn on_disconnect(&self, connection: &ConnectionInfo, _duration: Duration) {
+        self.push(format!("disconnect {}", connection.id));
+    }
+}
+
+#[test]
+fn test_observer_sees_connection_lifecycle() {
+    let recorder = Recorder::default();
+    let (server, handle, port) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .observer(recorder.clone()),
+    );
+
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    client
+        .send(client_message::Message::EchoMessage(EchoMessage {
+            content: "observed".to_string(),
+        }))
+        .expect("Failed to send message");
+    let response = client.receive().expect("Failed to receive response");
+    assert!(matches!(
+        response.message,
+        Some(server_message::Message::EchoMessage(_))
+    ));
+    client.disconnect().expect("Failed to disconnect");
+    recorder.wait_for_disconnect();
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+    assert_eq!(
+        recorder.events(),
+        ["connect 1", "message 1 EchoMessage", "disconnect 1"]
+    );
+}
+
+#[test]
+fn test_observers_called_in_order_added() {
+    let first = Recorder::default();
+    let second = Recorder(first.0.clone());
+    struct Tagged(Recorder, &'static str);
+    impl Observer for Tagged {
+        fn on_connect(&self, _connection: &ConnectionInfo) {
+            self.0.push(format!("connect {}", self.1));
+        }
+
+        fn on_disconnect(&self, _connection: &ConnectionInfo, _duration: Duration) {
+            self.0.push(format!("disconnect {}", self.1));
+        }
+    }
+    let (server, handle, port) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .observer(Tagged(first.clone(), "first"))
+            .observer(Tagged(second, "second")),
+    );
+
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    client.disconnect().expect("Failed to disconnect");
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while first.events().len() < 4 {
+        assert!(
+            Instant::now() < deadline,
+            "Missing events: {:?}",
+            first.events()
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+    assert_eq!(
+        first.events(),
+        [
+            "connect first",
+            "connect second",
+            "disconnect first",
+            "disconnect second"
+        ]
+    );
+}
@@ -0,0 +1,376 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::start;
+use embedded_recruitment_task::authz::{Grant, StaticPolicy};
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::deadletter::{DeadLetters, DeadReason};
+use embedded_recruitment_task::handler::MessageKind;
+use embedded_recruitment_task::message::{
+    client_message, dead_letter_record, server_message, Delivery, ResumeRequest, ResyncRequest,
+    ResyncResponse,
+};
+use embedded_recruitment_task::outbox::{Dropped, Outboxes};
+use embedded_recruitment_task::server::Server;
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+// Connects and names the device, returning the client once the resume is answered
+fn connect_as(port: u16, device: &str) -> Client {
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    client
+        .send(client_message::Message::ResumeRequest(ResumeRequest {
+            device_id: device.to_string(),
+            ..Default::default()
+        }))
+        .expect("Failed to send message");
+    let response = client.receive().expect("Failed to receive response");
+    assert!(matches!(
+        response.message,
+        Some(server_message::Message::ResumeResponse(_))
+    ));
+    client
+}
+
+fn receive_delivery(client: &mut Client) -> Delivery {
+    match client
+        .receive()
+        .expect("Failed to receive delivery")
+        .message
+    {
+        Some(server_message::Message::Delivery(delivery)) => delivery,
+        other => panic!("Expected a Delivery, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_queued_messages_delivered_in_order_on_connect() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    assert_eq!(server.send_to("sensor-1", b"first".to_vec()), 1);
+    assert_eq!(server.send_to("sensor-1", b"second".to_vec()), 2);
+    server.send_to("sensor-2", b"other".to_vec());
+    assert_eq!(server.queue_depth("sensor-1"), 2);
+    assert_eq!(server.queue_depths().len(), 2);
+
+    let mut client = connect_as(port, "sensor-1");
+    let first = receive_delivery(&mut client);
+    let second = receive_delivery(&mut client);
+    assert_eq!(
+        (first.sequence, first.payload.to_vec()),
+        (1, b"first".to_vec())
+    );
+    assert_eq!(
+        (second.sequence, second.payload.to_vec()),
+        (2, b"second".to_vec())
+    );
+    assert_eq!(server.queue_depth("sensor-1"), 0);
+    assert_eq!(server.queue_depth("sensor-2"), 1);
+    client.disconnect().expect("Failed to disconnect");
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_connected_device_gets_messages_without_asking() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let mut client = connect_as(port, "sensor-1");
+
+    server.send_to("sensor-1", b"command".to_vec());
+    let delivery = receive_delivery(&mut client);
+    assert_eq!(
+        (delivery.sequence, delivery.payload.to_vec()),
+        (1, b"command".to_vec())
+    );
+    client.disconnect().expect("Failed to disconnect");
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_limits_drop_and_count_messages() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    server.set_queue_limits(2, Duration::from_secs(60));
+    for payload in [b"a", b"b", b"c"] {
+        server.send_to("sensor-1", payload.to_vec());
+    }
+    assert_eq!(server.queue_depth("sensor-1"), 2);
+    assert_eq!(server.stats().overflowed_messages, 1);
+
+    server.set_queue_limits(2, Duration::ZERO);
+    let mut client = connect_as(port, "sensor-1");
+    client.disconnect().expect("Failed to disconnect");
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+    assert_eq!(server.stats().expired_messages, 2);
+}
+
+#[test]
+fn test_outbox_keeps_numbering_once_empty() {
+    let mut outboxes = Outboxes::new(4, Duration::from_secs(60));
+    assert_eq!(
+        outboxes.push("sensor-1", vec![1], None),
+        (1, Dropped::default())
+    );
+    let (deliveries, _) = outboxes.take("sensor-1");
+    assert_eq!(deliveries.len(), 1);
+    assert!(outboxes.depths().is_empty());
+
+    assert_eq!(outboxes.push("sensor-1", vec![2], None).0, 2);
+    assert_eq!(outboxes.take("unknown").0, Vec::new());
+}
+
+#[test]
+fn test_sweeps_forget_the_queues_of_devices_gone() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    server.set_queue_limits(4, Duration::from_millis(500));
+    server.send_to("sensor-1", b"stale".to_vec());
+    server.send_to("sensor-2", b"taken".to_vec());
+    let mut present = connect_as(port, "sensor-2");
+    receive_delivery(&mut present);
+    thread::sleep(Duration::from_millis(600));
+
+    // The connected device keeps its queue, and its numbering
+    assert_eq!(server.sweep_queues(), 1);
+    assert_eq!(server.stats().expired_messages, 1);
+    assert_eq!(server.dead_letters()[0].reason, DeadReason::Expired);
+    assert_eq!(server.send_to("sensor-1", b"again".to_vec()), 1);
+    assert_eq!(server.send_to("sensor-2", b"next".to_vec()), 2);
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_queues_are_swept_periodically() {
+    let server = Server::new("localhost:0").expect("Failed to start server");
+    server.set_sweep_interval(Duration::from_millis(20));
+    let (server, handle, _) = start(server);
+    server.send_to_with_ttl("sensor-1", b"stale".to_vec(), Duration::ZERO);
+
+    // Dropped without the device connecting
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while server.stats().expired_messages == 0 {
+        assert!(Instant::now() < deadline, "Queue not swept");
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(server.queue_depth("sensor-1"), 0);
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_stale_messages_are_not_delivered() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    server.send_to_with_ttl("sensor-1", b"stale".to_vec(), Duration::ZERO);
+    server.send_to("sensor-1", b"fresh".to_vec());
+    server.send_to_with_ttl("sensor-1", b"also fresh".to_vec(), Duration::from_secs(60));
+
+    let mut client = connect_as(port, "sensor-1");
+    let fresh = receive_delivery(&mut client);
+    let also_fresh = receive_delivery(&mut client);
+    assert_eq!(
+        (fresh.sequence, fresh.payload.to_vec()),
+        (2, b"fresh".to_vec())
+    );
+    assert_eq!(also_fresh.sequence, 3);
+    client.disconnect().expect("Failed to disconnect");
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+    assert_eq!(server.stats().expired_messages, 1);
+}
+
+#[test]
+fn test_message_ttl_cannot_outlast_queue_ttl() {
+    let mut outboxes = Outboxes::new(4, Duration::ZERO);
+    outboxes.push("sensor-1", vec![1], Some(Duration::from_secs(60)));
+
+    let (deliveries, dropped) = outboxes.take("sensor-1");
+    assert!(deliveries.is_empty());
+    assert_eq!(dropped.expired, 1);
+}
+
+#[test]
+fn test_dropped_messages_become_dead_letters() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    server.set_queue_limits(1, Duration::from_secs(60));
+    server.send_to("sensor-1", b"pushed out".to_vec());
+    server.send_to_with_ttl("sensor-1", b"stale".to_vec(), Duration::ZERO);
+
+    let mut client = connect_as(port, "sensor-1");
+    client.disconnect().expect("Failed to disconnect");
+    server.stop();
+    handle.join().expect("Server thread panicked");
+
+    let letters = server.dead_letters();
+    let dropped: Vec<_> = letters
+        .iter()
+        .map(|letter| (letter.device.as_str(), letter.sequence, letter.reason))
+        .collect();
+    assert_eq!(
+        dropped,
+        [
+            ("sensor-1", 1, DeadReason::Overflowed),
+            ("sensor-1", 2, DeadReason::Expired)
+        ]
+    );
+    assert_eq!(letters[0].payload, b"pushed out");
+}
+
+#[test]
+fn test_dead_letter_buffer_keeps_the_latest() {
+    let mut outboxes = Outboxes::new(0, Duration::from_secs(60));
+    outboxes.set_sink(Arc::new(DeadLetters::new(2)));
+    for payload in 1..=3 {
+        outboxes.push("sensor-1", vec![payload], None);
+    }
+
+    let kept: Vec<u64> = outboxes
+        .sink()
+        .recent()
+        .iter()
+        .map(|letter| letter.sequence)
+        .collect();
+    assert_eq!(kept, [2, 3]);
+}
+
+#[test]
+fn test_messages_for_unknown_devices_become_dead_letters() {
+    let policy = StaticPolicy::new()
+        .everyone(Grant::all_requests())
+        .grant("operator", Grant::new().send(MessageKind::DeadLetters));
+    let (server, handle, port) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .authorizer(policy),
+    );
+    server.set_known_devices(Some(HashSet::from(["sensor-1".to_string()])));
+    assert_eq!(server.send_to("sensor-1", b"known".to_vec()), 1);
+    assert_eq!(server.send_to("sensor-9", b"typo".to_vec()), 0);
+    assert_eq!(server.queue_depth("sensor-9"), 0);
+    let letters = server.dead_letters();
+    assert_eq!(letters.len(), 1);
+    assert_eq!(
+        (letters[0].device.as_str(), letters[0].reason),
+        ("sensor-9", DeadReason::UnknownDevice)
+    );
+
+    // Operators list them over the protocol, newest first, devices cannot
+    server.set_known_devices(Some(HashSet::new()));
+    server.send_to("sensor-1", b"forgotten".to_vec());
+    let mut device = connect_as(port, "sensor-2");
+    assert!(device.dead_letters("", 0).is_err());
+    let mut operator = connect_as(port, "operator");
+    let letters = operator.dead_letters("", 0).expect("Query failed");
+    let listed: Vec<_> = letters
+        .iter()
+        .map(|letter| (letter.device.as_str(), &letter.payload[..], letter.reason))
+        .collect();
+    let unknown = dead_letter_record::Reason::UnknownDevice as i32;
+    assert_eq!(
+        listed,
+        [
+            ("sensor-1", &b"forgotten"[..], unknown),
+            ("sensor-9", &b"typo"[..], unknown)
+        ]
+    );
+    let letters = operator.dead_letters("sensor-9", 1).expect("Query failed");
+    assert_eq!(letters.len(), 1);
+    assert_eq!(letters[0].payload, b"typo");
+
+    // Any device may be addressed again
+    server.set_known_devices(None);
+    assert_eq!(server.send_to("sensor-9", b"welcome".to_vec()), 1);
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+// Sends a `ResyncRequest` for `from..to` and returns its response
+fn resync(client: &mut Client, from: u64, to: u64) -> ResyncResponse {
+    client
+        .send(client_message::Message::ResyncRequest(ResyncRequest {
+            from,
+            to,
+        }))
+        .expect("Failed to send message");
+    match client
+        .receive()
+        .expect("Failed to receive response")
+        .message
+    {
+        Some(server_message::Message::ResyncResponse(response)) => response,
+        other => panic!("Expected a ResyncResponse, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_missed_deliveries_are_sent_again() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    server.set_queue_limits(2, Duration::from_secs(60));
+    let mut client = connect_as(port, "sensor-1");
+    for payload in [b"a", b"b", b"c"] {
+        server.send_to("sensor-1", payload.to_vec());
+        receive_delivery(&mut client);
+    }
+
+    let response = resync(&mut client, 2, 4);
+    assert_eq!((response.replayed, response.first), (2, 2));
+    let replayed = [receive_delivery(&mut client), receive_delivery(&mut client)];
+    assert_eq!(
+        replayed.map(|delivery| (delivery.sequence, delivery.payload.to_vec())),
+        [(2, b"b".to_vec()), (3, b"c".to_vec())]
+    );
+    // Only as many as a queue holds are kept
+    let response = resync(&mut client, 1, 3);
+    assert_eq!((response.replayed, response.first), (1, 2));
+    assert_eq!(receive_delivery(&mut client).sequence, 2);
+    let refused = client
+        .send(client_message::Message::ResyncRequest(ResyncRequest {
+            from: 3,
+            to: 3,
+        }))
+        .and_then(|()| client.receive())
+        .expect("Failed to receive response");
+    assert!(matches!(
+        refused.message,
+        Some(server_message::Message::ErrorResponse(_))
+    ));
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_replay_returns_the_run_ending_the_range() {
+    let mut outboxes = Outboxes::new(4, Duration::from_secs(60));
+    for payload in 1..=3 {
+        outboxes.push("sensor-1", vec![payload], None);
+    }
+    outboxes.take("sensor-1");
+    outboxes.push("sensor-1", vec![4], None); // Queued, not yet taken
+
+    let sequences = |deliveries: Vec<Delivery>| -> Vec<u64> {
+        deliveries
+            .iter()
+            .map(|delivery| delivery.sequence)
+            .collect()
+    };
+    assert_eq!(sequences(outboxes.replay("sensor-1", 1..3)), [1, 2]);
+    assert_eq!(
+        sequences(outboxes.replay("sensor-1", 2..9)),
+        Vec::<u64>::new()
+    );
+    assert_eq!(sequences(outboxes.replay("sensor-1", 2..4)), [2, 3]);
+    assert!(outboxes.replay("unknown", 1..3).is_empty());
+}
@@ -0,0 +1,79 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::{add, call_with_id, start};
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::message::{error_response, server_message, ErrorResponse};
+use embedded_recruitment_task::overload::OverloadPolicy;
+use embedded_recruitment_task::server::Server;
+use std::time::Duration;
+
+fn busy(retry_after_ms: u32) -> Option<server_message::Message> {
+    Some(server_message::Message::ErrorResponse(ErrorResponse {
+        code: error_response::Code::Busy as i32,
+        retry_after_ms,
+        ..Default::default()
+    }))
+}
+
+#[test]
+fn test_connections_over_the_limit_are_refused_as_busy() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    server.set_overload_policy(OverloadPolicy {
+        max_connections: Some(1),
+        retry_after: Duration::from_millis(250),
+        ..OverloadPolicy::default()
+    });
+
+    let mut first = Client::new("localhost", port.into(), 1000);
+    first.connect().expect("Failed to connect to the server");
+    assert!(matches!(
+        call_with_id(&mut first, 0, add(1, 2)),
+        Some(server_message::Message::AddResponse(_))
+    ));
+
+    // Answered at once and closed, without waiting for a request
+    let mut second = Client::new("localhost", port.into(), 1000);
+    second.connect().expect("Failed to connect to the server");
+    let refusal = second.receive().expect("Expected a busy response");
+    assert_eq!(refusal.message, busy(250));
+    assert_eq!(server.stats().shed_connections, 1);
+    let health = server.health();
+    assert!(health.live && !health.ready);
+    assert!(health
+        .problems
+        .contains(&"Shedding load at the connection limit".to_string()));
+
+    first.disconnect().ok();
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_requests_over_the_memory_limit_are_refused_as_busy() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+
+    // Any process uses more than one byte
+    server.set_overload_policy(OverloadPolicy {
+        max_memory: Some(1),
+        ..OverloadPolicy::default()
+    });
+    assert_eq!(call_with_id(&mut client, 7, add(1, 2)), busy(1000));
+    assert_eq!(server.stats().shed_requests, 1);
+
+    // The refusal was not remembered, so the retry is handled
+    server.set_overload_policy(OverloadPolicy::default());
+    assert!(matches!(
+        call_with_id(&mut client, 7, add(1, 2)),
+        Some(server_message::Message::AddResponse(_))
+    ));
+    assert_eq!(server.stats().duplicates, 0);
+
+    client.disconnect().ok();
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
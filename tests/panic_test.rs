@@ -0,0 +1,97 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::start;
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::message::{server_message, AddResponse};
+use embedded_recruitment_task::panics;
+use embedded_recruitment_task::router::Router;
+use embedded_recruitment_task::server::Server;
+use log::kv::{self, Key, Value, VisitSource};
+use log::{LevelFilter, Log, Metadata, Record};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+// A logged record: target, message and key-value pairs as text
+type Logged = (String, String, HashMap<String, String>);
+
+struct Collector(Mutex<Vec<Logged>>);
+
+struct Pairs(HashMap<String, String>);
+
+impl<'kvs> VisitSource<'kvs> for Pairs {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+}
+
+impl Log for Collector {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let mut pairs = Pairs(HashMap::new());
+        record.key_values().visit(&mut pairs).unwrap();
+        self.0.lock().unwrap().push((
+            record.target().to_string(),
+            record.args().to_string(),
+            pairs.0,
+        ));
+    }
+
+    fn flush(&self) {}
+}
+
+#[test]
+fn test_handler_panics_are_logged_with_their_connection() {
+    let collector: &'static Collector = Box::leak(Box::new(Collector(Mutex::new(Vec::new()))));
+    log::set_logger(collector).expect("Failed to install the logger");
+    log::set_max_level(LevelFilter::Error);
+    panics::install_hook();
+    panics::install_hook(); // A second call changes nothing
+
+    let router = Router::new().on_add(|add| {
+        assert_ne!(add.a, 13, "unlucky number");
+        server_message::Message::AddResponse(AddResponse {
+            result: add.a + add.b,
+        })
+    });
+    let (server, handle, port) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .router(router),
+    );
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    assert_eq!(client.add(1, 2).expect("Add failed"), 3);
+    assert!(client.add(13, 1).is_err(), "The handler did not panic");
+
+    let panic = || {
+        (collector.0.lock().unwrap().iter())
+            .find(|(target, _, _)| target == "panic")
+            .cloned()
+    };
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while (panic().is_none() || server.stats().pool.panicked == 0) && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+    server.stop();
+    handle.join().unwrap();
+
+    let (_, message, fields) = panic().expect("The panic was not logged");
+    assert!(message.contains("unlucky number"), "{}", message);
+    assert!(message.contains("panic_test"), "No backtrace: {}", message);
+    assert_eq!(fields["connection_id"], "1");
+    assert_eq!(fields["message_type"], "AddRequest");
+    assert!(fields["location"].contains("panic_test.rs"));
+    assert_ne!(fields["peer"], "None");
+    assert!(panics::count() >= 1);
+    assert_eq!(server.stats().pool.panicked, 1);
+}
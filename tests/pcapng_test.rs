@@ -0,0 +1,108 @@
+#![cfg(feature = "std")]
+
+use embedded_recruitment_task::capture::{Direction, Record};
+use embedded_recruitment_task::codec;
+use embedded_recruitment_task::message::{client_message, AddRequest, ClientMessage};
+use embedded_recruitment_task::pcapng::{PcapngWriter, LINKTYPE_USER0, SERVER_TO_CLIENT};
+use std::time::Duration;
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+// Splits a pcapng file into (block type, body) pairs, checking the framing of each block
+fn blocks(file: &[u8]) -> Vec<(u32, &[u8])> {
+    let mut blocks = Vec::new();
+    let mut offset = 0;
+    while offset < file.len() {
+        let len = u32_at(file, offset + 4) as usize;
+        assert_eq!(len % 4, 0, "Block length is not 32-bit aligned");
+        assert_eq!(
+            u32_at(file, offset + len - 4) as usize,
+            len,
+            "Trailing length differs"
+        );
+        blocks.push((u32_at(file, offset), &file[offset + 8..offset + len - 4]));
+        offset += len;
+    }
+    blocks
+}
+
+fn record(micros: u64, direction: Direction, bytes: &[u8]) -> Record {
+    Record {
+        timestamp: Duration::from_micros(micros),
+        direction,
+        bytes: bytes.to_vec(),
+    }
+}
+
+#[test]
+fn test_reads_are_reframed_into_packets() {
+    let frame = codec::encode(&ClientMessage {
+        message: Some(client_message::Message::AddRequest(AddRequest {
+            a: 1,
+            b: 2,
+        })),
+        ..Default::default()
+    })
+    .unwrap();
+    let mut two_frames = frame.clone();
+    two_frames.extend(&frame);
+
+    // One frame split over two reads, then two frames in one read, then a response
+    let records = vec![
+        record(10, Direction::Inbound, &frame[..2]),
+        record(20, Direction::Inbound, &frame[2..]),
+        record(30, Direction::Inbound, &two_frames),
+        record(40, Direction::Outbound, &[0x02, 0x08, 0x03]),
+    ];
+
+    let mut writer = PcapngWriter::new(Vec::new()).unwrap();
+    assert_eq!(
+        writer.write_connection("127.0.0.1:5000", &records).unwrap(),
+        4
+    );
+    let file = writer.into_inner().unwrap();
+
+    let blocks = blocks(&file);
+    assert_eq!(blocks[0].0, 0x0a0d0d0a, "Missing section header");
+    assert_eq!(blocks[1].0, 1, "Missing interface description");
+    assert_eq!(
+        u16::from_le_bytes([blocks[1].1[0], blocks[1].1[1]]),
+        LINKTYPE_USER0
+    );
+
+    let packets: Vec<_> = blocks[2..]
+        .iter()
+        .map(|(block_type, body)| {
+            assert_eq!(*block_type, 6, "Expected an enhanced packet block");
+            let micros = (u64::from(u32_at(body, 4)) << 32) | u64::from(u32_at(body, 8));
+            let len = u32_at(body, 12) as usize;
+            (micros, body[20..20 + len].to_vec())
+        })
+        .collect();
+
+    let mut inbound = vec![0];
+    inbound.extend(&frame);
+    assert_eq!(
+        packets,
+        [
+            (20, inbound.clone()),
+            (30, inbound.clone()),
+            (30, inbound),
+            (40, vec![SERVER_TO_CLIENT, 0x02, 0x08, 0x03]),
+        ]
+    );
+}
+
+#[test]
+fn test_unframeable_bytes_become_one_packet() {
+    let records = vec![record(
+        5,
+        Direction::Inbound,
+        &[0xff, 0xff, 0xff, 0xff, 0x01],
+    )];
+    let mut writer = PcapngWriter::new(Vec::new()).unwrap();
+    assert_eq!(writer.write_connection("peer", &records).unwrap(), 1);
+    assert_eq!(blocks(&writer.into_inner().unwrap()).len(), 3);
+}
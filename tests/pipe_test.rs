@@ -0,0 +1,95 @@
+#![cfg(all(feature = "named-pipe", windows))]
+
+mod common;
+
+use common::add;
+use embedded_recruitment_task::message::{
+    client_message, server_message, AddResponse, EchoMessage,
+};
+use embedded_recruitment_task::overload::OverloadPolicy;
+use embedded_recruitment_task::pipe::{self, PipeServer};
+use embedded_recruitment_task::server::Server;
+use std::{io, sync::Arc, thread, time::Duration};
+
+// A server listening on a pipe no other test run uses, and the pipe's name
+fn start_server(test: &str) -> (Arc<Server>, thread::JoinHandle<()>, String) {
+    let name = format!(r"\\.\pipe\embedded-{}-{}", test, std::process::id());
+    let server = Server::new("localhost:0")
+        .expect("Failed to start server")
+        .listener(PipeServer::new(&name).expect("Failed to create the pipe"));
+    let server = Arc::new(server);
+    let server_clone = Arc::clone(&server);
+    let handle = thread::spawn(move || server_clone.run().expect("Server encountered an error"));
+    (server, handle, name)
+}
+
+#[test]
+fn test_pipe_carries_framed_messages() {
+    let (server, handle, name) = start_server("frames");
+    let mut clients: Vec<_> = (0..2)
+        .map(|_| pipe::connect(&name, Duration::from_secs(5)).expect("Failed to connect"))
+        .collect();
+    for (a, client) in clients.iter_mut().enumerate() {
+        client.send(add(a as i32, 2)).expect("Failed to send");
+        let response = client.receive().expect("Failed to receive");
+        assert_eq!(
+            response.message,
+            Some(server_message::Message::AddResponse(AddResponse {
+                result: a as i32 + 2
+            }))
+        );
+    }
+    let echo = EchoMessage {
+        content: "over a pipe".to_string(),
+    };
+    clients[0]
+        .send(client_message::Message::EchoMessage(echo.clone()))
+        .expect("Failed to send");
+    let response = clients[0].receive().expect("Failed to receive");
+    assert_eq!(
+        response.message,
+        Some(server_message::Message::EchoMessage(echo))
+    );
+
+    // Served by the server itself, so counted with its TCP connections
+    assert_eq!(server.stats().requests, 3);
+
+    // Stopping disconnects the clients still open
+    server.stop();
+    handle.join().unwrap();
+    assert!(clients[1].receive().is_err());
+}
+
+#[test]
+fn test_no_instance_is_offered_at_the_connection_limit() {
+    let (server, handle, name) = start_server("limit");
+    server.set_overload_policy(OverloadPolicy {
+        max_connections: Some(1),
+        ..Default::default()
+    });
+    let mut first = pipe::connect(&name, Duration::from_secs(5)).expect("Failed to connect");
+    first.send(add(1, 2)).expect("Failed to send");
+    first.receive().expect("Failed to receive");
+
+    // The second client waits as in a listen backlog until the first leaves
+    let busy = pipe::connect(&name, Duration::from_millis(200));
+    assert!(busy.is_err(), "A second instance was offered");
+    drop(first);
+    let mut second = pipe::connect(&name, Duration::from_secs(5)).expect("Failed to connect");
+    second.send(add(3, 4)).expect("Failed to send");
+    second.receive().expect("Failed to receive");
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+#[test]
+fn test_names_are_checked_and_owned() {
+    let refused = PipeServer::new("gateway").err().expect("Name accepted");
+    assert_eq!(refused.kind(), io::ErrorKind::InvalidInput);
+
+    let (server, handle, name) = start_server("owned");
+    assert!(PipeServer::new(&name).is_err());
+    server.stop();
+    handle.join().unwrap();
+}
@@ -0,0 +1,80 @@
+#![cfg(feature = "message")]
+
+use embedded_recruitment_task::handler::MessageKind;
+use embedded_recruitment_task::priority::{Priority, PriorityQueue, MAX_BURST};
+
+#[test]
+fn test_higher_classes_go_first_in_arrival_order() {
+    let mut queue = PriorityQueue::new();
+    queue.push(Priority::Bulk, "telemetry 1");
+    queue.push(Priority::Normal, "echo");
+    queue.push(Priority::Bulk, "telemetry 2");
+    queue.push(Priority::Control, "ping");
+    assert_eq!(queue.len(), 4);
+
+    let order: Vec<_> = std::iter::from_fn(|| queue.pop()).collect();
+    assert_eq!(order, ["ping", "echo", "telemetry 1", "telemetry 2"]);
+    assert!(queue.is_empty());
+}
+
+#[test]
+fn test_message_kinds_map_to_classes() {
+    assert_eq!(Priority::of(MessageKind::Ping), Priority::Control);
+    assert_eq!(Priority::of(MessageKind::Echo), Priority::Normal);
+    assert_eq!(Priority::of(MessageKind::Add), Priority::Normal);
+    assert_eq!(Priority::of(MessageKind::Telemetry), Priority::Bulk);
+}
+
+#[test]
+fn test_lower_classes_do_not_starve() {
+    let mut queue = PriorityQueue::new();
+    queue.push(Priority::Bulk, 0);
+    queue.push(Priority::Normal, 1);
+    for i in 0..100 {
+        queue.push(Priority::Control, 10 + i);
+    }
+
+    // Each waiting request is served after at most `MAX_BURST` control requests
+    let order: Vec<_> = std::iter::from_fn(|| queue.pop()).collect();
+    let bulk = order.iter().position(|&i| i == 0).unwrap();
+    let normal = order.iter().position(|&i| i == 1).unwrap();
+    assert_eq!(bulk, MAX_BURST, "The oldest waiting request goes first");
+    assert_eq!(normal, 2 * MAX_BURST + 1);
+    assert_eq!(order.len(), 102);
+
+    // Control requests keep their order
+    let control: Vec<_> = order.into_iter().filter(|&i| i >= 10).collect();
+    assert_eq!(control, (10..110).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_burst_resets_when_nothing_waits() {
+    let mut queue = PriorityQueue::new();
+    for i in 0..MAX_BURST {
+        queue.push(Priority::Control, i);
+    }
+    while queue.pop().is_some() {}
+
+    // No lower-class request was waiting, so a new one does not jump the queue
+    queue.push(Priority::Control, 100);
+    queue.push(Priority::Bulk, 200);
+    assert_eq!(queue.pop(), Some(100));
+    assert_eq!(queue.pop(), Some(200));
+}
+
+#[test]
+fn test_reclassified_items_keep_their_arrival_order() {
+    let mut queue = PriorityQueue::new();
+    for i in 0..4 {
+        queue.push(Priority::Normal, i);
+    }
+
+    // The later items turn out to be control traffic and the first bulk
+    queue.reclassify(|&i| match i {
+        0 => Priority::Bulk,
+        1 => Priority::Normal,
+        _ => Priority::Control,
+    });
+    let order: Vec<_> = std::iter::from_fn(|| queue.pop()).collect();
+    assert_eq!(order, [2, 3, 1, 0]);
+}
@@ -0,0 +1,61 @@
+#![cfg(all(feature = "privileges", unix))]
+
+use embedded_recruitment_task::privileges::DropPrivileges;
+use std::{io::ErrorKind, os::unix::fs::MetadataExt, path::Path, process::Command};
+
+// Set in the child process that actually drops its privileges
+const CHILD: &str = "PRIVILEGES_TEST_CHILD";
+
+#[test]
+fn test_unknown_user_or_group_is_rejected() {
+    let error = DropPrivileges::to_user("no-such-user-here")
+        .apply()
+        .expect_err("Unknown user should be rejected");
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+
+    let error = DropPrivileges::to_user("root")
+        .group("no-such-group-here")
+        .apply()
+        .expect_err("Unknown group should be rejected");
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+}
+
+// Runs in a child process, so the rest of the suite keeps its privileges
+#[test]
+fn drop_to_nobody_in_chroot() {
+    let Some(root) = std::env::var_os(CHILD) else {
+        return; // Only meaningful in the child
+    };
+    DropPrivileges::to_user("nobody")
+        .chroot(&root)
+        .apply()
+        .expect("Failed to drop privileges");
+
+    assert!(
+        !Path::new("/etc/passwd").exists(),
+        "Still outside the chroot"
+    );
+    let error = std::fs::File::create("/written-as-root")
+        .expect_err("The root-owned chroot should not be writable any more");
+    assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn test_drops_to_unprivileged_user_in_chroot() {
+    let root = std::env::temp_dir().join(format!("privileges-test-{}", std::process::id()));
+    std::fs::create_dir_all(&root).expect("Failed to create the chroot directory");
+    // Owned by whoever created it
+    if std::fs::metadata(&root).map(|m| m.uid()).ok() != Some(0) {
+        eprintln!("Not running as root; skipping");
+        std::fs::remove_dir_all(&root).ok();
+        return;
+    }
+
+    let status = Command::new(std::env::current_exe().expect("No test binary"))
+        .args(["--exact", "drop_to_nobody_in_chroot", "--nocapture"])
+        .env(CHILD, &root)
+        .status()
+        .expect("Failed to run the child");
+    std::fs::remove_dir_all(&root).ok();
+    assert!(status.success(), "Child failed to drop privileges");
+}
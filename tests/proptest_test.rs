@@ -0,0 +1,111 @@
+#![cfg(feature = "message")]
+
+use embedded_recruitment_task::codec::{
+    self, decode_frame, CodecError, FrameDecoder, MAX_FRAME_SIZE,
+};
+use embedded_recruitment_task::fixed::{FrameBuf, Request};
+use embedded_recruitment_task::message::{
+    client_message, AddRequest, ClientMessage, EchoMessage, PingRequest, TelemetryReport,
+};
+use embedded_recruitment_task::protocol::{Event, ServerProtocol};
+use proptest::prelude::*;
+
+fn any_request() -> impl Strategy<Value = client_message::Message> {
+    prop_oneof![
+        // Long enough to need multi-byte length prefixes
+        ".{0,400}"
+            .prop_map(|content| client_message::Message::EchoMessage(EchoMessage { content })),
+        (any::<i32>(), any::<i32>())
+            .prop_map(|(a, b)| client_message::Message::AddRequest(AddRequest { a, b })),
+        any::<u64>()
+            .prop_map(|timestamp| client_message::Message::PingRequest(PingRequest { timestamp })),
+        // NaN never compares equal, so keep values ordinary
+        (any::<u32>(), -1e9f32..1e9f32, any::<u64>()).prop_map(|(sensor_id, value, timestamp)| {
+            client_message::Message::TelemetryReport(TelemetryReport {
+                sensor_id,
+                value,
+                timestamp,
+            })
+        }),
+    ]
+}
+
+fn any_message() -> impl Strategy<Value = ClientMessage> {
+    proptest::option::weighted(0.95, any_request()).prop_map(|message| ClientMessage {
+        message,
+        ..Default::default()
+    })
+}
+
+// Cuts `bytes` into reads at the given (unsorted, possibly repeated) offsets
+fn chunk<'a>(bytes: &'a [u8], cuts: &[usize]) -> Vec<&'a [u8]> {
+    let mut cuts: Vec<usize> = cuts.iter().map(|cut| cut % (bytes.len() + 1)).collect();
+    cuts.push(0);
+    cuts.push(bytes.len());
+    cuts.sort_unstable();
+    cuts.windows(2)
+        .map(|pair| &bytes[pair[0]..pair[1]])
+        .collect()
+}
+
+proptest! {
+    #[test]
+    fn prop_frame_round_trip(message in any_message()) {
+        let frame = codec::encode(&message).unwrap();
+        prop_assert_eq!(decode_frame(&frame), Ok(Some((message, frame.len()))));
+
+        // Every strict prefix of a frame is incomplete, never an error
+        for end in 0..frame.len() {
+            prop_assert_eq!(decode_frame::<ClientMessage>(&frame[..end]), Ok(None));
+        }
+    }
+
+    #[test]
+    fn prop_stream_survives_arbitrary_chunking(
+        messages in proptest::collection::vec(any_message(), 0..20),
+        cuts in proptest::collection::vec(any::<usize>(), 0..40),
+    ) {
+        let mut stream = Vec::new();
+        for message in &messages {
+            stream.extend(codec::encode(message).unwrap());
+        }
+
+        let mut decoder = FrameDecoder::new();
+        let mut protocol = ServerProtocol::new();
+        let mut decoded = Vec::new();
+        let mut events = Vec::new();
+        for read in chunk(&stream, &cuts) {
+            decoder.extend(read);
+            while let Some(message) = decoder.next_message::<ClientMessage>().unwrap() {
+                decoded.push(message);
+            }
+            events.extend(protocol.feed_bytes(read));
+        }
+
+        prop_assert_eq!(decoder.buffered(), 0);
+        prop_assert_eq!(&decoded, &messages);
+        let expected: Vec<_> = messages.into_iter().map(Event::Message).collect();
+        prop_assert_eq!(events, expected);
+    }
+
+    #[test]
+    fn prop_fixed_codec_matches_prost(request in any_request()) {
+        let frame = codec::encode(&ClientMessage { message: Some(request), ..Default::default() }).unwrap();
+        let (decoded, used) = Request::decode(&frame).unwrap().unwrap();
+        prop_assert_eq!(used, frame.len());
+
+        let mut buf = FrameBuf::<2048>::new(); // Room for 400 four-byte characters
+        prop_assert_eq!(buf.encode_request(&decoded).unwrap(), &frame[..]);
+    }
+
+    #[test]
+    fn prop_oversized_messages_are_rejected(extra in 1usize..1024) {
+        let message = ClientMessage {
+            message: Some(client_message::Message::EchoMessage(EchoMessage {
+                content: "x".repeat(MAX_FRAME_SIZE + extra),
+            })),
+            ..Default::default()
+        };
+        prop_assert!(matches!(codec::encode(&message), Err(CodecError::FrameTooLarge(_))));
+    }
+}
@@ -0,0 +1,108 @@
+#![cfg(feature = "message")]
+
+use embedded_recruitment_task::codec::{self, Bytes, CodecError};
+use embedded_recruitment_task::message::{
+    client_message, server_message, AddRequest, AddResponse, ClientMessage, ServerMessage,
+};
+use embedded_recruitment_task::protocol::{ClientProtocol, Event, ServerProtocol, State};
+use prost::Message;
+
+fn add_request(a: i32, b: i32) -> ClientMessage {
+    ClientMessage {
+        message: Some(client_message::Message::AddRequest(AddRequest { a, b })),
+        ..Default::default()
+    }
+}
+
+fn add_response(result: i32) -> ServerMessage {
+    ServerMessage {
+        message: Some(server_message::Message::AddResponse(AddResponse { result })),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_client_and_server_protocols_in_memory() {
+    let mut client = ClientProtocol::new();
+    let mut server = ServerProtocol::new();
+
+    client.send(&add_request(1, 2)).unwrap();
+    client.send(&add_request(3, 4)).unwrap();
+
+    // Deliver the client's bytes to the server one at a time
+    let mut events = Vec::new();
+    while let Some(bytes) = client.poll_transmit() {
+        for byte in bytes {
+            events.extend(server.feed_bytes(&[byte]));
+        }
+    }
+    assert_eq!(
+        events,
+        vec![
+            Event::Message(add_request(1, 2)),
+            Event::Message(add_request(3, 4))
+        ]
+    );
+
+    // Deliver both responses in a single chunk
+    server.send(&add_response(3)).unwrap();
+    server.send(&add_response(7)).unwrap();
+    assert_eq!(server.queued(), 2 * add_response(3).encoded_len() + 2);
+    let mut bytes = Vec::new();
+    while let Some(chunk) = server.poll_transmit() {
+        bytes.extend(chunk);
+    }
+    assert!(!server.has_pending_transmit());
+    assert_eq!(server.queued(), 0);
+    assert_eq!(
+        client.feed_bytes(&bytes),
+        vec![
+            Event::Message(add_response(3)),
+            Event::Message(add_response(7))
+        ]
+    );
+
+    assert_eq!(client.feed_eof(), vec![Event::Closed]);
+    assert_eq!(client.state(), State::Closed);
+    assert!(
+        client.feed_eof().is_empty(),
+        "Close must only be reported once"
+    );
+}
+
+#[test]
+fn test_corrupt_stream_fails_the_connection() {
+    let mut server = ServerProtocol::new();
+
+    let events = server.feed_bytes(&[0xff, 0xff, 0xff, 0xff]);
+    assert_eq!(events, vec![Event::Error(CodecError::InvalidLength)]);
+    assert_eq!(server.state(), State::Failed);
+
+    // Bytes after the corruption are ignored rather than misinterpreted
+    let mut client = ClientProtocol::new();
+    client.send(&add_request(1, 2)).unwrap();
+    let bytes = client.poll_transmit().unwrap();
+    assert!(server.feed_bytes(&bytes).is_empty());
+    assert!(server.feed_eof().is_empty());
+}
+
+#[test]
+fn test_encoded_frames_are_sent_as_they_are() {
+    let frame = Bytes::from(codec::encode(&add_response(3)).unwrap());
+    let mut first = ServerProtocol::new();
+    let mut second = ServerProtocol::new();
+    first.send_frame(frame.clone());
+    second.send_frame(frame.clone());
+    second.send(&add_response(4)).unwrap();
+    assert_eq!(second.queued(), frame.len() * 2);
+
+    let sent = first.poll_transmit_bytes().unwrap();
+    assert_eq!(sent.as_ptr(), frame.as_ptr());
+    assert_eq!(first.poll_transmit_bytes(), None);
+    assert_eq!(second.poll_transmit(), Some(frame.to_vec()));
+    assert_eq!(
+        second.poll_transmit(),
+        Some(codec::encode(&add_response(4)).unwrap())
+    );
+    assert_eq!(second.queued(), 0);
+}
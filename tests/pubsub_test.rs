@@ -0,0 +1,272 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::start;
+use embedded_recruitment_task::authz::{Grant, StaticPolicy};
+use embedded_recruitment_task::client::{Client, Push};
+use embedded_recruitment_task::message::Publication;
+use embedded_recruitment_task::server::Server;
+use std::{
+    io, thread,
+    time::{Duration, Instant},
+};
+
+fn connect(port: u16) -> Client {
+    let mut client = Client::new("localhost", port.into(), 2000);
+    client.connect().expect("Failed to connect to the server");
+    client
+}
+
+// The next message published to `client`
+fn next_publication(client: &mut Client) -> Publication {
+    match client.next_push().expect("No publication") {
+        Push::Publication(publication) => publication,
+        other => panic!("Unexpected push {:?}", other),
+    }
+}
+
+// Waits for the handlers of `closed` connections to finish
+fn wait_for_history(server: &Server, closed: usize) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while server.connection_history(None, usize::MAX).len() < closed {
+        assert!(Instant::now() < deadline, "Connections not closed");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn test_publications_reach_matching_subscribers() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let mut rooms = connect(port);
+    rooms.subscribe_to("site/+/temp").expect("Subscribe failed");
+    let mut site = connect(port);
+    site.subscribe_to("site/#").expect("Subscribe failed");
+    let mut publisher = connect(port);
+
+    assert_eq!(publisher.publish("site/a/temp", &b"21.5"[..]).unwrap(), 2);
+    for subscriber in [&mut rooms, &mut site] {
+        let publication = next_publication(subscriber);
+        assert_eq!(publication.topic, "site/a/temp");
+        assert_eq!(&publication.payload[..], b"21.5");
+    }
+    assert_eq!(publisher.publish("site/a/humidity", &b"40"[..]).unwrap(), 1);
+    assert_eq!(next_publication(&mut site).topic, "site/a/humidity");
+    assert_eq!(publisher.publish("other", &b""[..]).unwrap(), 0);
+
+    // A connection gets each message once, however many of its filters match
+    rooms.subscribe_to("site/b/temp").expect("Subscribe failed");
+    assert_eq!(publisher.publish("site/b/temp", &b"19"[..]).unwrap(), 2);
+    assert_eq!(next_publication(&mut rooms).topic, "site/b/temp");
+    assert_eq!(next_publication(&mut site).topic, "site/b/temp");
+
+    assert!(rooms.unsubscribe_from("site/+/temp").unwrap());
+    assert!(!rooms.unsubscribe_from("site/+/temp").unwrap());
+    assert_eq!(publisher.publish("site/a/temp", &b"22"[..]).unwrap(), 1);
+    assert_eq!(publisher.publish("site/b/temp", &b"20"[..]).unwrap(), 2);
+    assert_eq!(&next_publication(&mut rooms).payload[..], b"20");
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+#[test]
+fn test_new_subscribers_get_retained_messages() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let mut publisher = connect(port);
+    assert_eq!(
+        publisher
+            .publish_retained("site/hall/temp", &b"20"[..])
+            .unwrap(),
+        0
+    );
+    // Published without the flag, so the retained message stays
+    assert_eq!(publisher.publish("site/hall/temp", &b"21"[..]).unwrap(), 0);
+    assert_eq!(
+        publisher
+            .publish_retained("site/yard/temp", &b"5"[..])
+            .unwrap(),
+        0
+    );
+
+    let mut dashboard = connect(port);
+    assert_eq!(dashboard.subscribe_to("site/+/temp").unwrap(), 2);
+    let hall = next_publication(&mut dashboard);
+    assert_eq!(
+        (hall.topic.as_str(), &hall.payload[..]),
+        ("site/hall/temp", &b"20"[..])
+    );
+    assert!(hall.retained);
+    assert_eq!(next_publication(&mut dashboard).topic, "site/yard/temp");
+    assert_eq!(publisher.publish("site/yard/temp", &b"6"[..]).unwrap(), 1);
+    assert!(!next_publication(&mut dashboard).retained);
+
+    // An empty retained message clears the topic
+    publisher
+        .publish_retained("site/hall/temp", Vec::new())
+        .unwrap();
+    let mut late = connect(port);
+    assert_eq!(late.subscribe_to("site/hall/temp").unwrap(), 0);
+
+    // Topics the server always retains need no flag
+    server.always_retain("config/#").expect("Valid filter");
+    assert_eq!(publisher.publish("config/interval", &b"60"[..]).unwrap(), 0);
+    assert_eq!(late.subscribe_to("config/#").unwrap(), 1);
+    assert_eq!(&next_publication(&mut late).payload[..], b"60");
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Publications `client` has received until none arrives within its timeout
+fn drain(client: &mut Client) -> Vec<Publication> {
+    let mut received = Vec::new();
+    loop {
+        match client.next_push() {
+            Ok(Push::Publication(publication)) => received.push(publication),
+            Ok(other) => panic!("Unexpected push {:?}", other),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return received
+            }
+            Err(e) => panic!("Connection failed: {}", e),
+        }
+    }
+}
+
+#[test]
+fn test_shared_subscriptions_split_messages() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let mut workers: Vec<Client> = (0..3)
+        .map(|_| {
+            let mut worker = Client::new("localhost", port.into(), 300);
+            worker.connect().expect("Failed to connect to the server");
+            worker
+                .subscribe_to("$share/workers/cmd/#")
+                .expect("Subscribe failed");
+            worker
+        })
+        .collect();
+    let mut audit = Client::new("localhost", port.into(), 300);
+    audit.connect().expect("Failed to connect to the server");
+    audit.subscribe_to("cmd/#").expect("Subscribe failed");
+    let mut publisher = connect(port);
+
+    // Each message goes to one worker, and to the plain subscriber
+    for i in 0..6u8 {
+        assert_eq!(publisher.publish("cmd/reboot", vec![i]).unwrap(), 2);
+    }
+    assert_eq!(drain(&mut audit).len(), 6);
+    let mut handled: Vec<u8> = Vec::new();
+    for worker in &mut workers {
+        let received = drain(worker);
+        assert!(!received.is_empty(), "A worker got nothing");
+        handled.extend(received.iter().map(|publication| publication.payload[0]));
+    }
+    handled.sort();
+    assert_eq!(handled, [0, 1, 2, 3, 4, 5]);
+
+    // Members that leave, or close, stop getting a share
+    assert!(workers[0].unsubscribe_from("$share/workers/cmd/#").unwrap());
+    workers
+        .remove(1)
+        .disconnect()
+        .expect("Failed to disconnect");
+    wait_for_history(&server, 1);
+    for i in 0..3u8 {
+        assert_eq!(publisher.publish("cmd/reboot", vec![i]).unwrap(), 2);
+    }
+    assert!(drain(&mut workers[0]).is_empty());
+    assert_eq!(drain(&mut workers[1]).len(), 3);
+
+    // Groups get no retained messages, and need a group and a filter
+    publisher
+        .publish_retained("cmd/mode", &b"safe"[..])
+        .unwrap();
+    assert_eq!(audit.subscribe_to("$share/late/cmd/#").unwrap(), 0);
+    for malformed in ["$share/workers", "$share//cmd", "$share/workers/cmd/#/x"] {
+        let refused = audit.subscribe_to(malformed).unwrap_err();
+        assert_eq!(refused.kind(), io::ErrorKind::InvalidInput, "{}", malformed);
+    }
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+#[test]
+fn test_invalid_topics_are_refused() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let mut client = connect(port);
+    let refused = client.subscribe_to("site/#/temp").unwrap_err();
+    assert_eq!(refused.kind(), io::ErrorKind::InvalidInput);
+    let refused = client.publish("site/+/temp", &b"1"[..]).unwrap_err();
+    assert_eq!(refused.kind(), io::ErrorKind::InvalidInput);
+    // The connection is still usable
+    assert_eq!(client.add(1, 2).expect("Add failed"), 3);
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+#[test]
+fn test_topics_are_authorized() {
+    let policy = StaticPolicy::new().everyone(
+        Grant::all_requests()
+            .subscribe("site/public/#")
+            .publish("site/public/+"),
+    );
+    let (server, handle, port) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .authorizer(policy),
+    );
+    let mut client = connect(port);
+    let refused = client.subscribe_to("site/#").unwrap_err();
+    assert_eq!(refused.kind(), io::ErrorKind::PermissionDenied);
+    client
+        .subscribe_to("site/public/+")
+        .expect("Subscribe failed");
+    let refused = client.publish("site/private", &b"1"[..]).unwrap_err();
+    assert_eq!(refused.kind(), io::ErrorKind::PermissionDenied);
+    assert_eq!(client.publish("site/public/news", &b"1"[..]).unwrap(), 1);
+    assert_eq!(next_publication(&mut client).topic, "site/public/news");
+    // A shared subscription is checked as the filter it shares
+    let refused = client.subscribe_to("$share/g/site/#").unwrap_err();
+    assert_eq!(refused.kind(), io::ErrorKind::PermissionDenied);
+    client
+        .subscribe_to("$share/g/site/public/+")
+        .expect("Subscribe failed");
+    assert_eq!(server.stats().denied_requests, 3);
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+#[test]
+fn test_subscriptions_follow_the_client_not_the_connection() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let mut gone = connect(port);
+    gone.subscribe_to("alerts").expect("Subscribe failed");
+    gone.disconnect().expect("Failed to disconnect");
+    let mut client = connect(port);
+    client.subscribe_to("alerts").expect("Subscribe failed");
+    wait_for_history(&server, 1);
+    let mut publisher = connect(port);
+    // The closed connection's subscription went with it
+    assert_eq!(publisher.publish("alerts", &b"1"[..]).unwrap(), 1);
+    assert_eq!(next_publication(&mut client).topic, "alerts");
+
+    // A new connection subscribes again before anything else is sent
+    client.connect().expect("Failed to reconnect");
+    assert_eq!(client.add(1, 2).expect("Add failed"), 3);
+    wait_for_history(&server, 2);
+    assert_eq!(publisher.publish("alerts", &b"2"[..]).unwrap(), 1);
+    assert_eq!(&next_publication(&mut client).payload[..], b"2");
+
+    server.stop();
+    handle.join().unwrap();
+}
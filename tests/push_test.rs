@@ -0,0 +1,123 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::{echo, start};
+use embedded_recruitment_task::client::{Client, Push};
+use embedded_recruitment_task::message::{server_message, EchoMessage, GoAway, ServerMessage};
+use embedded_recruitment_task::outbox::BROADCAST_SEQUENCE;
+use embedded_recruitment_task::server::Server;
+use std::io;
+
+fn subscribe(port: u16, device: &str) -> Client {
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    client.subscribe(device).expect("Failed to subscribe");
+    client
+}
+
+fn payload(push: io::Result<Push>) -> Vec<u8> {
+    match push.expect("Failed to receive push") {
+        Push::Delivery(delivery) => delivery.payload.to_vec(),
+        other => panic!("Expected a Delivery, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_pushes_are_kept_apart_from_responses() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let mut client = subscribe(port, "sensor-1");
+
+    server.send_to("sensor-1", b"direct".to_vec());
+    assert_eq!(server.broadcast(b"everyone".to_vec()), Ok(1));
+    client.send(echo("hello")).expect("Failed to send message");
+
+    // The response is returned past the pushes received before it
+    let response = client.receive().expect("Failed to receive response");
+    assert_eq!(
+        response.message,
+        Some(server_message::Message::EchoMessage(EchoMessage {
+            content: "hello".to_string(),
+        }))
+    );
+    assert_eq!(payload(client.next_push()), b"direct");
+    match client.next_push().expect("Failed to receive push") {
+        Push::Delivery(delivery) => assert_eq!(delivery.sequence, BROADCAST_SEQUENCE),
+        other => panic!("Expected a Delivery, got {:?}", other),
+    }
+
+    // And a push is returned past a response still unread
+    client.send(echo("later")).expect("Failed to send message");
+    server.send_to("sensor-1", b"again".to_vec());
+    assert_eq!(payload(client.next_push()), b"again");
+    assert!(client.receive().is_ok());
+    client.disconnect().expect("Failed to disconnect");
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_pushes_iterates_until_the_connection_ends() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let mut client = subscribe(port, "sensor-1");
+    for n in 0..3u8 {
+        server.send_to("sensor-1", vec![n]);
+    }
+    let received: Vec<Vec<u8>> = client.pushes().take(3).map(payload).collect();
+    assert_eq!(received, [[0], [1], [2]]);
+
+    let go_away = GoAway::default();
+    assert_eq!(server.go_away(go_away.clone()), Ok(1));
+    assert_eq!(
+        client.pushes().next().map(|push| push.ok()),
+        Some(Some(Push::GoAway(go_away)))
+    );
+    server.stop();
+    handle.join().expect("Server thread panicked");
+    let mut pushes = client.pushes();
+    assert!(matches!(pushes.next(), Some(Err(_))));
+    assert!(pushes.next().is_none());
+}
+
+#[test]
+fn test_subscription_is_renewed_on_reconnect() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let mut client = Client::new("localhost", port.into(), 1000);
+    // Before connecting, only recorded
+    client.subscribe("sensor-1").expect("Failed to subscribe");
+    client.connect().expect("Failed to connect to the server");
+    client.send(echo("named")).expect("Failed to send message");
+    assert!(client.receive().is_ok());
+    server.send_to("sensor-1", b"first".to_vec());
+    assert_eq!(payload(client.next_push()), b"first");
+
+    client.disconnect().expect("Failed to disconnect");
+    client.connect().expect("Failed to reconnect to the server");
+    client
+        .send(echo("renamed"))
+        .expect("Failed to send message");
+    assert!(client.receive().is_ok());
+    server.send_to("sensor-1", b"second".to_vec());
+    assert_eq!(payload(client.next_push()), b"second");
+    client.disconnect().expect("Failed to disconnect");
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_push_from_server_message() {
+    let response = ServerMessage {
+        message: Some(server_message::Message::EchoMessage(EchoMessage::default())),
+        ..Default::default()
+    };
+    assert!(!Push::is_push(&response));
+    assert_eq!(Push::try_from(response.clone()), Err(response));
+    let go_away = ServerMessage {
+        message: Some(server_message::Message::GoAway(GoAway::default())),
+        ..Default::default()
+    };
+    assert!(Push::is_push(&go_away));
+    assert_eq!(Push::try_from(go_away), Ok(Push::GoAway(GoAway::default())));
+}
@@ -0,0 +1,218 @@
+#![cfg(feature = "quic")]
+
+use embedded_recruitment_task::authz::{Grant, StaticPolicy};
+use embedded_recruitment_task::codec::{self, FrameDecoder};
+use embedded_recruitment_task::handler::MessageKind;
+use embedded_recruitment_task::message::error_response;
+use embedded_recruitment_task::message::{
+    client_message, server_message, AddRequest, AddResponse, ClientMessage, EchoMessage,
+    ResumeRequest, ServerMessage,
+};
+use embedded_recruitment_task::quic::QuicServer;
+use embedded_recruitment_task::router::Router;
+use embedded_recruitment_task::server::Server;
+use embedded_recruitment_task::vhost::VirtualHost;
+use quinn::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use quinn::rustls::RootCertStore;
+use quinn::{ClientConfig, Endpoint};
+use std::{net::SocketAddr, sync::Arc, thread};
+
+// A server also listening on QUIC, its QUIC address and the certificate it presents
+fn start_server(
+    server: Server,
+) -> (
+    Arc<Server>,
+    SocketAddr,
+    CertificateDer<'static>,
+    thread::JoinHandle<()>,
+) {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+        .expect("Failed to generate certificate");
+    let cert = certified.cert.der().clone();
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der()));
+
+    let quic =
+        QuicServer::new("127.0.0.1:0", vec![cert.clone()], key).expect("Failed to start server");
+    let addr = quic.local_addr().expect("No local address");
+    let server = Arc::new(server.listener(quic));
+    let server_clone = Arc::clone(&server);
+    let handle = thread::spawn(move || server_clone.run().expect("Server encountered an error"));
+    (server, addr, cert, handle)
+}
+
+// A client connection to the QUIC listener at `addr`, which presents `cert`
+async fn connect(addr: SocketAddr, cert: CertificateDer<'static>) -> (Endpoint, quinn::Connection) {
+    let mut roots = RootCertStore::empty();
+    roots.add(cert).expect("Failed to trust certificate");
+    let config =
+        ClientConfig::with_root_certificates(Arc::new(roots)).expect("Failed to configure client");
+    let mut endpoint =
+        Endpoint::client("127.0.0.1:0".parse().unwrap()).expect("Failed to bind client");
+    endpoint.set_default_client_config(config);
+    let connection = endpoint
+        .connect(addr, "localhost")
+        .expect("Failed to connect")
+        .await
+        .expect("Handshake failed");
+    (endpoint, connection)
+}
+
+// Sends `requests` on a fresh stream, closes it and returns every response
+async fn exchange(
+    connection: &quinn::Connection,
+    requests: &[ClientMessage],
+) -> Vec<ServerMessage> {
+    let (mut send, mut recv) = connection.open_bi().await.expect("Failed to open stream");
+    for request in requests {
+        let frame = codec::encode(request).expect("Failed to encode message");
+        send.write_all(&frame).await.expect("Failed to send");
+    }
+    send.finish().expect("Failed to finish stream");
+
+    let bytes = recv.read_to_end(64 * 1024).await.expect("Failed to read");
+    let mut decoder = FrameDecoder::new();
+    decoder.extend(&bytes);
+    std::iter::from_fn(|| decoder.next_message().expect("Invalid response")).collect()
+}
+
+fn request(message: client_message::Message) -> ClientMessage {
+    ClientMessage {
+        message: Some(message),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_streams_carry_framed_messages() {
+    let (server, addr, cert, handle) =
+        start_server(Server::new("localhost:0").expect("Failed to start server"));
+
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to start runtime");
+    runtime.block_on(async {
+        let (endpoint, connection) = connect(addr, cert).await;
+
+        // Two streams in flight at once on one connection
+        let echo = request(client_message::Message::EchoMessage(EchoMessage {
+            content: "over quic".to_string(),
+        }));
+        let add = request(client_message::Message::AddRequest(AddRequest {
+            a: 2,
+            b: 3,
+        }));
+        let echoing = tokio::spawn({
+            let connection = connection.clone();
+            async move { exchange(&connection, &[echo.clone(), echo]).await }
+        });
+        let added = exchange(&connection, &[add]).await;
+        let echoed = echoing.await.expect("Echo task panicked");
+
+        assert_eq!(echoed.len(), 2);
+        for response in echoed {
+            assert_eq!(
+                response.message,
+                Some(server_message::Message::EchoMessage(EchoMessage {
+                    content: "over quic".to_string(),
+                }))
+            );
+        }
+        assert_eq!(
+            added[0].message,
+            Some(server_message::Message::AddResponse(AddResponse {
+                result: 5
+            }))
+        );
+
+        connection.close(0u32.into(), b"done");
+        endpoint.wait_idle().await;
+    });
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_streams_take_the_server_pipeline() {
+    let policy = StaticPolicy::new().everyone(Grant::new().send(MessageKind::Add));
+    let (server, addr, cert, handle) = start_server(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .authorizer(policy),
+    );
+
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to start runtime");
+    let responses = runtime.block_on(async {
+        let (endpoint, connection) = connect(addr, cert).await;
+        let add = request(client_message::Message::AddRequest(AddRequest {
+            a: 1,
+            b: 1,
+        }));
+        let echo = request(client_message::Message::EchoMessage(EchoMessage {
+            content: "side door".to_string(),
+        }));
+        let responses = exchange(&connection, &[add, echo]).await;
+        connection.close(0u32.into(), b"done");
+        endpoint.wait_idle().await;
+        responses
+    });
+
+    // The server's authorizer is asked, and the requests counted, as over TCP
+    assert!(matches!(
+        responses[1].message,
+        Some(server_message::Message::ErrorResponse(ref error))
+            if error.code == error_response::Code::Forbidden as i32
+    ));
+    let stats = server.stats();
+    assert_eq!((stats.requests, stats.denied_requests), (1, 1));
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_server_name_picks_the_virtual_host() {
+    let shouting = Router::new().on_echo(|echo| {
+        server_message::Message::EchoMessage(EchoMessage {
+            content: echo.content.to_uppercase(),
+        })
+    });
+    let (server, addr, cert, handle) = start_server(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .virtual_host("localhost", VirtualHost::new().router(shouting))
+            .virtual_host("other.example.com", VirtualHost::new()),
+    );
+
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to start runtime");
+    let responses = runtime.block_on(async {
+        let (endpoint, connection) = connect(addr, cert).await;
+        let other = request(client_message::Message::ResumeRequest(ResumeRequest {
+            vhost: "other.example.com".to_string(),
+            ..Default::default()
+        }));
+        let echo = request(client_message::Message::EchoMessage(EchoMessage {
+            content: "by name".to_string(),
+        }));
+        let echoed = exchange(&connection, &[echo]).await;
+        let refused = exchange(&connection, &[other]).await;
+        connection.close(0u32.into(), b"done");
+        endpoint.wait_idle().await;
+        (echoed, refused)
+    });
+
+    // Every stream gets the host before its first frame, and a `vhost` field
+    // naming another one is refused
+    assert_eq!(
+        responses.0[0].message,
+        Some(server_message::Message::EchoMessage(EchoMessage {
+            content: "BY NAME".to_string(),
+        }))
+    );
+    assert!(matches!(
+        responses.1[0].message,
+        Some(server_message::Message::ErrorResponse(ref error))
+            if error.code == error_response::Code::Invalid as i32 && error.field == "vhost"
+    ));
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
@@ -0,0 +1,172 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::{echo, start};
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::message::{
+    client_message, error_response, server_message, QuotaRequest, QuotaStatus, ResumeRequest,
+};
+use embedded_recruitment_task::quota::{until_reset, Exceeded, Quota, Quotas};
+use embedded_recruitment_task::server::Server;
+use std::{
+    io, thread,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+fn connect_as(port: u16, device: &str) -> Client {
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    client
+        .send(client_message::Message::ResumeRequest(ResumeRequest {
+            device_id: device.to_string(),
+            ..Default::default()
+        }))
+        .expect("Failed to send message");
+    client.receive().expect("Failed to receive response");
+    client
+}
+
+fn request(client: &mut Client, message: client_message::Message) -> server_message::Message {
+    client.send(message).expect("Failed to send message");
+    let response = client.receive().expect("Failed to receive response");
+    response.message.expect("Empty response")
+}
+
+fn quota_status(client: &mut Client) -> QuotaStatus {
+    let request_status = client_message::Message::QuotaRequest(QuotaRequest {});
+    match request(client, request_status) {
+        server_message::Message::QuotaStatus(status) => status,
+        other => panic!("Expected a QuotaStatus, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_requests_over_quota_are_refused() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    server.set_default_quota(Quota {
+        messages_per_day: Some(2),
+        ..Default::default()
+    });
+    let mut client = connect_as(port, "sensor-1");
+
+    for _ in 0..2 {
+        assert!(matches!(
+            request(&mut client, echo("reading")),
+            server_message::Message::EchoMessage(_)
+        ));
+    }
+    match request(&mut client, echo("reading")) {
+        server_message::Message::ErrorResponse(error) => {
+            assert_eq!(error.code, error_response::Code::QuotaExceeded as i32);
+            assert!(error.retry_after_ms > 0);
+        }
+        other => panic!("Expected an ErrorResponse, got {:?}", other),
+    }
+    let status = quota_status(&mut client);
+    assert_eq!((status.messages, status.max_messages), (2, 2));
+    assert!(status.bytes > 0);
+    assert_eq!(status.max_bytes, 0);
+
+    // Another device has a quota of its own
+    let mut other = connect_as(port, "sensor-2");
+    assert!(matches!(
+        request(&mut other, echo("reading")),
+        server_message::Message::EchoMessage(_)
+    ));
+    client.disconnect().expect("Failed to disconnect");
+    other.disconnect().expect("Failed to disconnect");
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+    assert_eq!(server.stats().quota_refusals, 1);
+}
+
+#[test]
+fn test_device_quota_limits_its_queue() {
+    let server = Server::new("localhost:0").expect("Failed to start server");
+    server.set_default_quota(Quota {
+        max_queued: Some(1),
+        ..Default::default()
+    });
+    server.set_quota("gateway", Some(Quota::default()));
+    for _ in 0..3 {
+        server.send_to("sensor-1", b"command".to_vec());
+        server.send_to("gateway", b"command".to_vec());
+    }
+
+    assert_eq!(server.queue_depth("sensor-1"), 1);
+    assert_eq!(server.queue_depth("gateway"), 3);
+    let status = server.quota_status("sensor-1");
+    assert_eq!((status.queued, status.max_queued), (1, 1));
+}
+
+#[test]
+fn test_subscriptions_over_quota_are_refused() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    server.set_session_expiry(Duration::ZERO);
+    server.set_quota(
+        "sensor-1",
+        Some(Quota {
+            max_subscriptions: Some(2),
+            ..Default::default()
+        }),
+    );
+    let mut first = connect_as(port, "sensor-1");
+    let mut second = connect_as(port, "sensor-1");
+    first.subscribe_to("site/a").expect("Subscribe failed");
+    second.subscribe_to("site/b").expect("Subscribe failed");
+
+    // Counted across the device's connections
+    let refused = first.subscribe_to("site/c").unwrap_err();
+    assert_eq!(refused.kind(), io::ErrorKind::QuotaExceeded);
+    let status = quota_status(&mut first);
+    assert_eq!((status.subscriptions, status.max_subscriptions), (2, 2));
+    assert_eq!(server.stats().quota_refusals, 1);
+
+    // Subscribing again to a filter it holds takes nothing more
+    first.subscribe_to("site/a").expect("Subscribe failed");
+    assert!(first.unsubscribe_from("site/a").unwrap());
+    first.subscribe_to("site/c").expect("Subscribe failed");
+
+    // A closed connection's subscriptions are given back once its session
+    // can no longer be resumed, which the next resume notices
+    second.disconnect().expect("Failed to disconnect");
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while server.quota_status("sensor-1").subscriptions > 1 {
+        assert!(Instant::now() < deadline, "Subscriptions not given back");
+        thread::sleep(Duration::from_millis(10));
+        connect_as(port, "sensor-3");
+    }
+    first.subscribe_to("site/d").expect("Subscribe failed");
+
+    // Other devices have no limit
+    let mut other = connect_as(port, "sensor-2");
+    for filter in ["site/a", "site/b", "site/c"] {
+        other.subscribe_to(filter).expect("Subscribe failed");
+    }
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+#[test]
+fn test_usage_starts_over_each_day() {
+    let mut quotas = Quotas::new();
+    quotas.set(
+        "sensor-1",
+        Some(Quota {
+            bytes_per_day: Some(100),
+            ..Default::default()
+        }),
+    );
+    let evening = UNIX_EPOCH + Duration::from_secs(20_000 * 86_400 - 60);
+    let morning = evening + Duration::from_secs(120);
+
+    assert_eq!(quotas.charge("sensor-1", 80, evening), Ok(()));
+    assert_eq!(quotas.charge("sensor-1", 40, evening), Err(Exceeded::Bytes));
+    assert_eq!(quotas.charge("sensor-2", 400, evening), Ok(()));
+    assert_eq!(until_reset(evening), Duration::from_secs(60));
+    assert_eq!(quotas.charge("sensor-1", 40, morning), Ok(()));
+    assert_eq!(quotas.status("sensor-1", 0, morning).bytes, 40);
+}
@@ -0,0 +1,122 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::start;
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::server::Server;
+use std::thread;
+
+#[test]
+fn test_edge_relays_requests_upstream() {
+    let (upstream, upstream_handle, _) =
+        start(Server::new("localhost:0").expect("Failed to start upstream server"));
+    let upstream_addr = upstream.local_addr().expect("No local address");
+    let (edge, edge_handle, edge_port) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start edge server")
+            .relay_to(&upstream_addr.to_string(), 2),
+    );
+
+    // More devices than upstream links
+    let devices: Vec<_> = (0..5)
+        .map(|i| {
+            thread::spawn(move || {
+                let mut client = Client::new("localhost", edge_port.into(), 1000);
+                client.connect().expect("Failed to connect to the edge");
+                for j in 0..10 {
+                    assert_eq!(client.add(i, j).expect("Relayed request failed"), i + j);
+                }
+                client.disconnect().ok();
+            })
+        })
+        .collect();
+    for device in devices {
+        device.join().expect("Device thread panicked");
+    }
+
+    // Every request was handled upstream, over no more than the configured links
+    assert_eq!(upstream.stats().requests, 50);
+    assert!(upstream.stats().pool.completed + upstream.stats().pool.active <= 2);
+
+    edge.stop();
+    edge_handle.join().expect("Edge thread panicked");
+    upstream.stop();
+    upstream_handle.join().expect("Upstream thread panicked");
+}
+
+#[test]
+fn test_edge_relays_for_more_devices_than_workers() {
+    let (upstream, upstream_handle, _) =
+        start(Server::new("localhost:0").expect("Failed to start upstream server"));
+    let upstream_addr = upstream.local_addr().expect("No local address");
+    let (edge, edge_handle, edge_port) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start edge server")
+            .relay_to(&upstream_addr.to_string(), 4),
+    );
+
+    // Every device stays connected throughout, twice as many as the edge has workers
+    let devices = 2 * edge.stats().pool.workers + 1;
+    let clients: Vec<_> = (0..devices)
+        .map(|_| {
+            let mut client = Client::new("localhost", edge_port.into(), 1000);
+            client.connect().expect("Failed to connect to the edge");
+            client
+        })
+        .collect();
+    let devices: Vec<_> = (0..)
+        .zip(clients)
+        .map(|(i, mut client)| {
+            thread::spawn(move || {
+                for j in 0..5 {
+                    assert_eq!(client.add(i, j).expect("Relayed request failed"), i + j);
+                }
+                client
+            })
+        })
+        .collect();
+    let mut clients: Vec<_> = devices
+        .into_iter()
+        .map(|device| device.join().expect("Device thread panicked"))
+        .collect();
+    assert_eq!(edge.stats().pool.active, clients.len() as u64);
+    assert_eq!(upstream.stats().requests, 5 * clients.len() as u64);
+
+    for client in &mut clients {
+        client.disconnect().ok();
+    }
+    edge.stop();
+    edge_handle.join().expect("Edge thread panicked");
+    upstream.stop();
+    upstream_handle.join().expect("Upstream thread panicked");
+}
+
+#[test]
+fn test_unreachable_upstream_leaves_request_unanswered() {
+    // Nothing listens on the upstream address once the listener is dropped
+    let upstream_addr = std::net::TcpListener::bind("localhost:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("Failed to reserve a port");
+    let (edge, edge_handle, edge_port) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start edge server")
+            .relay_to(&upstream_addr.to_string(), 1),
+    );
+
+    let mut client = Client::new("localhost", edge_port.into(), 300);
+    client.connect().expect("Failed to connect to the edge");
+    let error = client.add(1, 2).expect_err("Request should go unanswered");
+    assert!(
+        matches!(
+            error.kind(),
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+        ),
+        "Unexpected error: {}",
+        error
+    );
+
+    client.disconnect().ok();
+    edge.stop();
+    edge_handle.join().expect("Edge thread panicked");
+}
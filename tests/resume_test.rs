@@ -0,0 +1,304 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::start;
+use embedded_recruitment_task::client::{Client, Push};
+use embedded_recruitment_task::dedup::DedupWindow;
+use embedded_recruitment_task::handler::MessageKind;
+use embedded_recruitment_task::message::{
+    client_message, server_message, AddRequest, AddResponse, ResumeRequest, ResumeResponse,
+};
+use embedded_recruitment_task::middleware;
+use embedded_recruitment_task::observer::{ConnectionInfo, Observer};
+use embedded_recruitment_task::resume::{SessionState, SessionStore, TOKEN_LEN};
+use embedded_recruitment_task::router::Router;
+use embedded_recruitment_task::server::Server;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+// Counts closed connections, whose sessions are parked by then
+#[derive(Clone, Default)]
+struct Disconnects(Arc<AtomicUsize>);
+
+impl Observer for Disconnects {
+    fn on_disconnect(&self, _connection: &ConnectionInfo, _duration: Duration) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+impl Disconnects {
+    fn wait_for(&self, count: usize) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while self.0.load(Ordering::SeqCst) < count {
+            assert!(Instant::now() < deadline, "Connection never closed");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+fn resume(client: &mut Client, token: &[u8]) -> ResumeResponse {
+    resume_as(client, "", token)
+}
+
+fn resume_as(client: &mut Client, device: &str, token: &[u8]) -> ResumeResponse {
+    client
+        .send(client_message::Message::ResumeRequest(ResumeRequest {
+            token: token.to_vec(),
+            device_id: device.to_string(),
+            ..Default::default()
+        }))
+        .expect("Failed to send message");
+    match client
+        .receive()
+        .expect("Failed to receive response")
+        .message
+    {
+        Some(server_message::Message::ResumeResponse(response)) => response,
+        other => panic!("Expected a ResumeResponse, got {:?}", other),
+    }
+}
+
+// Sends an `AddRequest` with message ID 7, as a retry would
+fn add_with_id(client: &mut Client) -> Option<server_message::Message> {
+    client
+        .send_with_id(
+            0,
+            7,
+            client_message::Message::AddRequest(AddRequest { a: 1, b: 2 }),
+        )
+        .expect("Failed to send message");
+    client
+        .receive()
+        .expect("Failed to receive response")
+        .message
+}
+
+#[test]
+fn test_resumed_session_keeps_dedup_window() {
+    let handled = Arc::new(AtomicUsize::new(0));
+    let handled_clone = handled.clone();
+    let router = Router::new().on_add(move |add| {
+        handled_clone.fetch_add(1, Ordering::SeqCst);
+        server_message::Message::AddResponse(AddResponse {
+            result: add.a + add.b,
+        })
+    });
+    let disconnects = Disconnects::default();
+    let (server, handle, port) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .router(router)
+            .observer(disconnects.clone()),
+    );
+
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    let started = resume(&mut client, &[]);
+    assert!(!started.resumed);
+    assert_eq!(started.token.len(), TOKEN_LEN);
+    let first = add_with_id(&mut client);
+    client.disconnect().expect("Failed to disconnect");
+    disconnects.wait_for(1);
+
+    // The retry over the new connection is answered from the resumed window
+    client.connect().expect("Failed to reconnect to the server");
+    let resumed = resume(&mut client, &started.token);
+    assert!(resumed.resumed);
+    assert_eq!(resumed.token, started.token);
+    assert_eq!(add_with_id(&mut client), first);
+    assert_eq!(handled.load(Ordering::SeqCst), 1);
+    client.disconnect().expect("Failed to disconnect");
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+    let stats = server.stats();
+    assert_eq!(stats.resumed_sessions, 1);
+    assert_eq!(stats.duplicates, 1);
+}
+
+#[test]
+fn test_unknown_or_expired_token_starts_new_session() {
+    let disconnects = Disconnects::default();
+    let server = Server::new("localhost:0")
+        .expect("Failed to start server")
+        .observer(disconnects.clone());
+    server.set_session_expiry(Duration::ZERO);
+    let (server, handle, port) = start(server);
+
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    let unknown = resume(&mut client, &[1; TOKEN_LEN]);
+    assert!(!unknown.resumed);
+    assert_ne!(unknown.token, [1; TOKEN_LEN]);
+    client.disconnect().expect("Failed to disconnect");
+    disconnects.wait_for(1);
+
+    client.connect().expect("Failed to reconnect to the server");
+    let expired = resume(&mut client, &unknown.token);
+    assert!(!expired.resumed);
+    assert_ne!(expired.token, unknown.token);
+    client.disconnect().expect("Failed to disconnect");
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+    assert_eq!(server.stats().resumed_sessions, 0);
+}
+
+// Runs a server counting the subscribe requests it is sent
+fn start_counting_subscribes(
+    configure: impl FnOnce(Server) -> Server,
+) -> (Arc<Server>, thread::JoinHandle<()>, u16, Arc<AtomicUsize>) {
+    let subscribes = Arc::new(AtomicUsize::new(0));
+    let counted = subscribes.clone();
+    let layer = middleware::from_fn(move |request, next| {
+        if request.kind == MessageKind::Subscribe {
+            counted.fetch_add(1, Ordering::SeqCst);
+        }
+        next.run(request)
+    });
+    let server = Server::new("localhost:0")
+        .expect("Failed to start server")
+        .layer(layer);
+    let (server, handle, port) = start(configure(server));
+    (server, handle, port, subscribes)
+}
+
+fn next_topic(client: &mut Client) -> String {
+    match client.next_push().expect("No publication") {
+        Push::Publication(publication) => publication.topic,
+        other => panic!("Unexpected push {:?}", other),
+    }
+}
+
+#[test]
+fn test_resumed_session_keeps_topic_subscriptions() {
+    let disconnects = Disconnects::default();
+    let observer = disconnects.clone();
+    let (server, handle, port, subscribes) =
+        start_counting_subscribes(|server| server.observer(observer));
+    let mut subscriber = Client::new("localhost", port.into(), 1000);
+    subscriber
+        .connect()
+        .expect("Failed to connect to the server");
+    subscriber
+        .subscribe("sensor-1")
+        .expect("Failed to subscribe");
+    subscriber.subscribe_to("site/#").expect("Subscribe failed");
+    subscriber.disconnect().expect("Failed to disconnect");
+    disconnects.wait_for(1);
+
+    // Kept for the session while it is parked
+    let mut publisher = Client::new("localhost", port.into(), 1000);
+    publisher
+        .connect()
+        .expect("Failed to connect to the server");
+    assert_eq!(publisher.publish("site/a/temp", &b"21"[..]).unwrap(), 1);
+
+    // The client resumes the session rather than subscribing again
+    subscriber
+        .connect()
+        .expect("Failed to reconnect to the server");
+    assert_eq!(next_topic(&mut subscriber), "site/a/temp");
+    assert_eq!(publisher.publish("site/b/temp", &b"19"[..]).unwrap(), 1);
+    assert_eq!(next_topic(&mut subscriber), "site/b/temp");
+    assert_eq!(subscribes.load(Ordering::SeqCst), 1);
+    assert_eq!(server.stats().resumed_sessions, 1);
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_subscriptions_renewed_when_session_expired() {
+    let disconnects = Disconnects::default();
+    let observer = disconnects.clone();
+    let (server, handle, port, subscribes) = start_counting_subscribes(|server| {
+        server.set_session_expiry(Duration::ZERO);
+        server.observer(observer)
+    });
+    let mut subscriber = Client::new("localhost", port.into(), 1000);
+    subscriber
+        .connect()
+        .expect("Failed to connect to the server");
+    subscriber
+        .subscribe("sensor-1")
+        .expect("Failed to subscribe");
+    subscriber.subscribe_to("site/#").expect("Subscribe failed");
+    subscriber.disconnect().expect("Failed to disconnect");
+    disconnects.wait_for(1);
+
+    subscriber
+        .connect()
+        .expect("Failed to reconnect to the server");
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while subscribes.load(Ordering::SeqCst) < 2 {
+        assert!(Instant::now() < deadline, "Subscription not renewed");
+        subscriber.ping().ok(); // Reads the answer to the resume on the way
+    }
+
+    // Sent once, to the new connection only
+    let mut publisher = Client::new("localhost", port.into(), 1000);
+    publisher
+        .connect()
+        .expect("Failed to connect to the server");
+    assert_eq!(publisher.publish("site/a/temp", &b"21"[..]).unwrap(), 1);
+    assert_eq!(next_topic(&mut subscriber), "site/a/temp");
+    assert_eq!(server.stats().resumed_sessions, 0);
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_only_its_device_resumes_a_session() {
+    let disconnects = Disconnects::default();
+    let (server, handle, port) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .observer(disconnects.clone()),
+    );
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    let started = resume_as(&mut client, "sensor-1", &[]);
+    client.disconnect().expect("Failed to disconnect");
+    disconnects.wait_for(1);
+
+    client.connect().expect("Failed to reconnect to the server");
+    assert!(!resume_as(&mut client, "sensor-2", &started.token).resumed);
+    client.disconnect().expect("Failed to disconnect");
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+    assert_eq!(server.stats().resumed_sessions, 0);
+}
+
+#[test]
+fn test_store_resumes_each_session_once() {
+    let mut store = SessionStore::default();
+    let token = store.issue().expect("No random source");
+    let mut dedup = DedupWindow::default();
+    dedup.insert(
+        3,
+        server_message::Message::AddResponse(AddResponse { result: 5 }),
+    );
+    store.park(
+        token.clone(),
+        SessionState {
+            dedup,
+            device: None,
+        },
+    );
+    assert_eq!(store.len(), 1);
+
+    let state = store.resume(&token).expect("Session not parked");
+    assert!(state.dedup.get(3).is_some());
+    assert!(store.resume(&token).is_none());
+    assert!(store.is_empty());
+}
@@ -0,0 +1,72 @@
+#![cfg(feature = "message")]
+
+use embedded_recruitment_task::retained::Retained;
+use embedded_recruitment_task::topic::TopicError;
+
+#[test]
+fn test_only_flagged_messages_are_retained() {
+    let mut retained = Retained::new(16);
+    assert_eq!(retained.publish("site/hall/thermo-1", 20, true), Ok(true));
+    assert_eq!(retained.publish("site/hall/thermo-1", 21, false), Ok(false));
+    assert_eq!(retained.get("site/hall/thermo-1"), Some(&20));
+
+    assert_eq!(retained.publish("site/hall/thermo-1", 22, true), Ok(true));
+    assert_eq!(retained.get("site/hall/thermo-1"), Some(&22));
+    assert_eq!(retained.clear("site/hall/thermo-1"), Some(22));
+    assert!(retained.is_empty());
+    assert_eq!(
+        retained.publish("site/+/thermo-1", 0, true),
+        Err(TopicError::Wildcard)
+    );
+}
+
+#[test]
+fn test_always_retained_topics_need_no_flag() {
+    let mut retained = Retained::new(16);
+    retained
+        .always_retain("site/+/+/temperature")
+        .expect("Valid filter");
+    assert_eq!(
+        retained.publish("site/hall/thermo-1/temperature", 20, false),
+        Ok(true)
+    );
+    assert_eq!(
+        retained.publish("site/hall/thermo-1/humidity", 40, false),
+        Ok(false)
+    );
+    assert_eq!(retained.len(), 1);
+}
+
+#[test]
+fn test_new_subscription_gets_matching_messages() {
+    let mut retained = Retained::new(16);
+    for (topic, value) in [
+        ("site/hall/thermo-1/temperature", 1),
+        ("site/kitchen/thermo-1/temperature", 2),
+        ("site/kitchen/thermo-1/humidity", 3),
+        ("sites/hall/thermo-1/temperature", 4),
+        ("$server/load", 5),
+    ] {
+        retained.publish(topic, value, true).expect("Valid topic");
+    }
+
+    assert_eq!(
+        retained.matching("site/+/thermo-1/temperature"),
+        [
+            ("site/hall/thermo-1/temperature", &1),
+            ("site/kitchen/thermo-1/temperature", &2)
+        ]
+    );
+    assert_eq!(retained.matching("site/kitchen/#").len(), 2);
+    assert_eq!(retained.matching("#").len(), 4);
+    assert!(retained.matching("site/#/x").is_empty());
+}
+
+#[test]
+fn test_capacity_limits_new_topics() {
+    let mut retained = Retained::new(1);
+    assert_eq!(retained.publish("a", 1, true), Ok(true));
+    assert_eq!(retained.publish("b", 2, true), Ok(false));
+    assert_eq!(retained.publish("a", 3, true), Ok(true));
+    assert_eq!(retained.get("a"), Some(&3));
+}
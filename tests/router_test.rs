@@ -0,0 +1,123 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::echo;
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::message::{
+    client_message, server_message, AddRequest, AddResponse, EchoBytes, EchoMessage, PingRequest,
+    PingResponse,
+};
+use embedded_recruitment_task::router::Router;
+use embedded_recruitment_task::server::Server;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+#[test]
+fn test_requests_go_to_the_handler_for_their_type() {
+    let router = Router::new().on_add(|add| {
+        server_message::Message::AddResponse(AddResponse {
+            result: add.a * add.b,
+        })
+    });
+
+    assert_eq!(
+        router.handle(client_message::Message::AddRequest(AddRequest {
+            a: 3,
+            b: 4
+        })),
+        server_message::Message::AddResponse(AddResponse { result: 12 })
+    );
+    // No handler of its own, so the built-in response
+    assert_eq!(
+        router.handle(echo("hello")),
+        server_message::Message::EchoMessage(EchoMessage {
+            content: "hello".to_string()
+        })
+    );
+}
+
+#[test]
+fn test_byte_echoes_have_a_handler_of_their_own() {
+    let router = Router::new().on_echo_bytes(|mut echo| {
+        echo.data.reverse();
+        server_message::Message::EchoBytes(echo)
+    });
+    assert_eq!(
+        router.handle(client_message::Message::EchoBytes(EchoBytes {
+            data: vec![1, 2, 3]
+        })),
+        server_message::Message::EchoBytes(EchoBytes {
+            data: vec![3, 2, 1]
+        })
+    );
+}
+
+#[test]
+fn test_fallback_answers_unhandled_types() {
+    let router = Router::new()
+        .on_ping(|ping| {
+            server_message::Message::PingResponse(PingResponse {
+                timestamp: ping.timestamp + 1,
+            })
+        })
+        .fallback(|_| {
+            server_message::Message::EchoMessage(EchoMessage {
+                content: "unsupported".to_string(),
+            })
+        });
+
+    assert_eq!(
+        router.handle(client_message::Message::PingRequest(PingRequest {
+            timestamp: 1
+        })),
+        server_message::Message::PingResponse(PingResponse { timestamp: 2 })
+    );
+    assert_eq!(
+        router.handle(echo("hello")),
+        server_message::Message::EchoMessage(EchoMessage {
+            content: "unsupported".to_string()
+        })
+    );
+}
+
+#[test]
+fn test_server_answers_with_the_router() {
+    let echoes = Arc::new(AtomicU64::new(0));
+    let echoes_clone = echoes.clone();
+    let router = Router::new().on_echo(move |echo| {
+        echoes_clone.fetch_add(1, Ordering::Relaxed);
+        server_message::Message::EchoMessage(EchoMessage {
+            content: echo.content.to_uppercase(),
+        })
+    });
+    let server = Arc::new(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .router(router),
+    );
+    let port = server.local_addr().expect("No local address").port();
+    let server_clone = Arc::clone(&server);
+    let handle = thread::spawn(move || server_clone.run().expect("Server encountered an error"));
+
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    client.send(echo("hello")).expect("Failed to send message");
+    let response = client.receive().expect("Failed to receive response");
+    assert_eq!(
+        response.message,
+        Some(server_message::Message::EchoMessage(EchoMessage {
+            content: "HELLO".to_string()
+        }))
+    );
+    assert_eq!(echoes.load(Ordering::Relaxed), 1);
+
+    client.disconnect().ok();
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
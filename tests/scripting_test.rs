@@ -0,0 +1,176 @@
+#![cfg(all(feature = "client", feature = "scripting"))]
+
+mod common;
+
+use common::{add, echo, start_connected};
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::handler::MessageKind;
+use embedded_recruitment_task::message::{
+    client_message, error_response, server_message, AddResponse, EchoMessage, ErrorResponse,
+};
+use embedded_recruitment_task::scripting::Script;
+use embedded_recruitment_task::server::Server;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+fn script_file(name: &str, source: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("{}-{}.rhai", name, std::process::id()));
+    fs::write(&path, source).expect("Failed to write script");
+    path
+}
+
+// Rewrites the script with a later modification time than before
+fn rewrite(path: &Path, source: &str) {
+    fs::write(path, source).expect("Failed to write script");
+    let file = fs::File::options().write(true).open(path).unwrap();
+    file.set_modified(SystemTime::now() + Duration::from_secs(10))
+        .unwrap();
+}
+
+fn request(client: &mut Client, message: client_message::Message) -> server_message::Message {
+    client.send(message).expect("Failed to send message");
+    client
+        .receive()
+        .expect("Failed to receive response")
+        .message
+        .expect("Empty response")
+}
+
+#[test]
+fn test_script_answers_or_passes_requests_on() {
+    let path = script_file(
+        "scripting-test-answer",
+        r#"
+        fn handle(request) {
+            if request.kind == "add" && request.b == 0 {
+                return #{ kind: "error", code: 3, field: "b", detail: "must not be 0" };
+            }
+            if request.kind == "echo" {
+                return #{ kind: "echo", content: request.content.to_upper() };
+            }
+        }
+        "#,
+    );
+    let script = Script::load(&path).expect("Failed to load script");
+    let (server, handle, mut client) = start_connected(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .layer(script),
+    );
+
+    assert_eq!(
+        request(&mut client, add(1, 0)),
+        server_message::Message::ErrorResponse(ErrorResponse {
+            code: error_response::Code::Invalid as i32,
+            field: "b".to_string(),
+            detail: "must not be 0".to_string(),
+            ..Default::default()
+        })
+    );
+    // Returning nothing leaves the request to the built-in handler
+    assert_eq!(
+        request(&mut client, add(1, 2)),
+        server_message::Message::AddResponse(AddResponse { result: 3 })
+    );
+    assert_eq!(
+        request(&mut client, echo("hi")),
+        server_message::Message::EchoMessage(EchoMessage {
+            content: "HI".to_string()
+        })
+    );
+
+    client.disconnect().ok();
+    server.stop();
+    handle.join().expect("Server thread panicked");
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn test_script_only_sees_its_message_types() {
+    let path = script_file(
+        "scripting-test-only",
+        r#"fn handle(request) { #{ kind: "add", result: 42 } }"#,
+    );
+    let script = Script::load(&path)
+        .expect("Failed to load script")
+        .only(&[MessageKind::Add]);
+
+    assert_eq!(
+        script.handle(&add(1, 2)),
+        Some(server_message::Message::AddResponse(AddResponse {
+            result: 42
+        }))
+    );
+    let (server, handle, mut client) = start_connected(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .layer(script),
+    );
+    assert_eq!(
+        request(&mut client, echo("unchanged")),
+        server_message::Message::EchoMessage(EchoMessage {
+            content: "unchanged".to_string()
+        })
+    );
+
+    client.disconnect().ok();
+    server.stop();
+    handle.join().expect("Server thread panicked");
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn test_script_is_reloaded_when_changed() {
+    let path = script_file(
+        "scripting-test-reload",
+        r#"fn handle(request) { #{ kind: "add", result: 1 } }"#,
+    );
+    let script = Script::load(&path)
+        .expect("Failed to load script")
+        .reload_interval(Duration::ZERO);
+    let result = |script: &Script| match script.handle(&add(0, 0)) {
+        Some(server_message::Message::AddResponse(response)) => Some(response.result),
+        _ => None,
+    };
+    assert_eq!(result(&script), Some(1));
+
+    rewrite(
+        &path,
+        r#"fn handle(request) { #{ kind: "add", result: 2 } }"#,
+    );
+    assert_eq!(result(&script), Some(2));
+
+    // A broken edit keeps the previous script running
+    rewrite(&path, "fn handle(request) {");
+    assert_eq!(result(&script), Some(2));
+
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn test_script_errors_pass_the_request_on() {
+    let broken = script_file("scripting-test-broken", "fn handle(request) {");
+    assert!(Script::load(&broken).is_err());
+    fs::remove_file(broken).ok();
+
+    let path = script_file(
+        "scripting-test-errors",
+        r#"
+        fn handle(request) {
+            if request.a == 1 { return 5; }
+            if request.a == 2 { return #{ kind: "add", result: "three" }; }
+            if request.a == 3 { return #{ kind: "nope" }; }
+            if request.a == 4 { loop {} }
+            throw "failed";
+        }
+        "#,
+    );
+    let script = Script::load(&path).expect("Failed to load script");
+    for a in 1..=5 {
+        assert_eq!(script.handle(&add(a, 0)), None, "a = {}", a);
+    }
+    fs::remove_file(path).ok();
+}
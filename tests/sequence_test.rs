@@ -0,0 +1,43 @@
+#![cfg(feature = "message")]
+
+use embedded_recruitment_task::sequence::{Overflow, Reorderer};
+
+fn drain(reorderer: &mut Reorderer<&'static str>) -> Vec<&'static str> {
+    core::iter::from_fn(|| reorderer.pop()).collect()
+}
+
+#[test]
+fn test_out_of_order_messages_are_delivered_in_order() {
+    let mut reorderer = Reorderer::new(8);
+    reorderer.push(2, "b").unwrap();
+    assert_eq!(drain(&mut reorderer), Vec::<&str>::new());
+    assert_eq!(reorderer.missing(), Some(1..2));
+
+    reorderer.push(1, "a").unwrap();
+    reorderer.push(3, "c").unwrap();
+    assert_eq!(drain(&mut reorderer), ["a", "b", "c"]);
+    assert_eq!(reorderer.missing(), None);
+    assert_eq!(reorderer.next_sequence(), 4);
+}
+
+#[test]
+fn test_duplicates_are_dropped() {
+    let mut reorderer = Reorderer::new(8);
+    reorderer.push(1, "a").unwrap();
+    reorderer.push(1, "again").unwrap();
+    assert_eq!(drain(&mut reorderer), ["a"]);
+
+    reorderer.push(1, "late").unwrap();
+    assert_eq!(drain(&mut reorderer), Vec::<&str>::new());
+}
+
+#[test]
+fn test_overflow_reports_the_gap_and_skip_recovers() {
+    let mut reorderer = Reorderer::new(2);
+    reorderer.push(4, "d").unwrap();
+    reorderer.push(5, "e").unwrap();
+    assert_eq!(reorderer.push(6, "f"), Err(Overflow { missing: 1..4 }));
+
+    reorderer.skip_to(4);
+    assert_eq!(drain(&mut reorderer), ["d", "e", "f"]);
+}
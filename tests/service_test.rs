@@ -0,0 +1,28 @@
+#![cfg(all(feature = "windows-service", windows))]
+
+use embedded_recruitment_task::server::Server;
+use embedded_recruitment_task::service;
+use std::time::Duration;
+
+// ERROR_FAILED_SERVICE_CONTROLLER_CONNECT
+const NOT_STARTED_BY_SCM: i32 = 1063;
+
+// ERROR_SERVICE_DOES_NOT_EXIST
+const NO_SUCH_SERVICE: i32 = 1060;
+
+#[test]
+fn test_run_fails_outside_the_service_control_manager() {
+    let server = Server::new("localhost:0").expect("Failed to start server");
+    let error = service::run("embedded-test", server, Duration::from_secs(1))
+        .expect_err("Ran without the service control manager");
+    assert_eq!(error.raw_os_error(), Some(NOT_STARTED_BY_SCM));
+}
+
+#[test]
+fn test_controlling_an_unknown_service_fails() {
+    let name = format!("embedded-missing-{}", std::process::id());
+    for result in [service::start(&name), service::stop(&name)] {
+        let error = result.expect_err("Controlled a service that does not exist");
+        assert_eq!(error.raw_os_error(), Some(NO_SUCH_SERVICE));
+    }
+}
@@ -0,0 +1,286 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::start;
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::codec;
+use embedded_recruitment_task::handler::MessageKind;
+use embedded_recruitment_task::message::{
+    client_message, server_message, AddResponse, ClientMessage, PingRequest, TelemetryReport,
+};
+use embedded_recruitment_task::observer::{ConnectionInfo, Observer};
+use embedded_recruitment_task::priority::MAX_BURST;
+use embedded_recruitment_task::router::Router;
+use embedded_recruitment_task::server::Server;
+use std::{
+    collections::HashMap,
+    io::Write,
+    net::{Shutdown, TcpStream},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+// Records the threads each connection's events ran on, by connection number
+#[derive(Clone, Default)]
+struct Threads(Arc<Mutex<HashMap<u64, Vec<String>>>>);
+
+impl Threads {
+    fn push(&self, connection: &ConnectionInfo) {
+        let name = thread::current().name().unwrap_or_default().to_string();
+        let mut threads = self.0.lock().unwrap();
+        threads.entry(connection.id).or_default().push(name);
+    }
+
+    // The shard of the last connection to open, once it has
+    fn shard_of_newest(&self, connections: usize) -> String {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            {
+                let threads = self.0.lock().unwrap();
+                if threads.len() == connections {
+                    let newest = threads.keys().max().unwrap();
+                    return shard(&threads[newest][0]);
+                }
+            }
+            assert!(Instant::now() < deadline, "Connection not handled");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+impl Observer for Threads {
+    fn on_connect(&self, connection: &ConnectionInfo) {
+        self.push(connection);
+    }
+
+    fn on_message(&self, connection: &ConnectionInfo, _kind: MessageKind, _elapsed: Duration) {
+        self.push(connection);
+    }
+}
+
+// Records the order in which connections had their first turn
+#[derive(Clone, Default)]
+struct Started(Arc<Mutex<Vec<u64>>>);
+
+impl Observer for Started {
+    fn on_connect(&self, connection: &ConnectionInfo) {
+        self.0.lock().unwrap().push(connection.id);
+    }
+}
+
+// Connects and sends `message`, then closes its side so the handler ends once
+// it has answered
+fn send_and_close(port: u16, message: client_message::Message) -> TcpStream {
+    let mut stream = TcpStream::connect(("localhost", port)).expect("Failed to connect");
+    let request = ClientMessage {
+        message: Some(message),
+        ..Default::default()
+    };
+    stream.write_all(&codec::encode(&request).unwrap()).unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+    stream
+}
+
+// The shard a worker thread belongs to, from its name
+fn shard(thread: &str) -> String {
+    assert!(thread.starts_with("shard-"), "Not a worker: {:?}", thread);
+    thread.to_string()
+}
+
+// Waits until `active` connections are being handled
+fn wait_for_active(server: &Server, active: u64) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while server.stats().pool.active != active {
+        assert!(Instant::now() < deadline, "Connections not finished");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn test_connections_go_to_the_least_loaded_shard() {
+    let threads = Threads::default();
+    let (server, handle, port) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .shards(2)
+            .observer(threads.clone()),
+    );
+    let mut clients = Vec::new();
+    let mut shards = Vec::new();
+    for opened in 1..=4 {
+        let mut client = Client::new("localhost", port.into(), 1000);
+        client.connect().expect("Failed to connect to the server");
+        shards.push(threads.shard_of_newest(opened));
+        clients.push(client);
+    }
+    // Ties go to the first shard, so open connections alternate between them
+    assert_ne!(shards[0], shards[1]);
+    assert_eq!(shards[0], shards[2]);
+    assert_eq!(shards[1], shards[3]);
+
+    // Freeing the first shard sends the next two connections there
+    for index in [2, 0] {
+        let mut client = clients.remove(index);
+        client.disconnect().expect("Failed to disconnect");
+    }
+    wait_for_active(&server, 2);
+    for opened in 5..=6 {
+        let mut client = Client::new("localhost", port.into(), 1000);
+        client.connect().expect("Failed to connect to the server");
+        assert_eq!(threads.shard_of_newest(opened), shards[0]);
+        clients.push(client);
+    }
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+#[test]
+fn test_idle_connections_leave_the_workers_free() {
+    let (server, handle, port) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .workers(2)
+            .shards(1),
+    );
+    // Many more open connections than threads, all of them served
+    let mut clients: Vec<Client> = (0..20)
+        .map(|_| {
+            let mut client = Client::new("localhost", port.into(), 1000);
+            client.connect().expect("Failed to connect to the server");
+            client
+        })
+        .collect();
+    for round in 0..3 {
+        for client in &mut clients {
+            assert_eq!(client.add(round, 1).expect("Add failed"), round + 1);
+        }
+    }
+    let stats = server.stats().pool;
+    assert_eq!((stats.workers, stats.active, stats.queued), (2, 20, 0));
+
+    for client in &mut clients {
+        client.disconnect().expect("Failed to disconnect");
+    }
+    wait_for_active(&server, 0);
+    assert_eq!(server.stats().pool.completed, 20);
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+#[test]
+fn test_waiting_connections_take_threads_by_priority() {
+    let started = Started::default();
+    let (release, released) = mpsc::channel::<()>();
+    let released = Mutex::new(released);
+    let router = Router::new().on_add(move |add| {
+        released.lock().unwrap().recv().ok();
+        server_message::Message::AddResponse(AddResponse {
+            result: add.a + add.b,
+        })
+    });
+    let (server, handle, port) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .workers(1)
+            .router(router)
+            .observer(started.clone()),
+    );
+    // The only thread is busy with a request
+    let mut busy = Client::new("localhost", port.into(), 1000);
+    busy.connect().expect("Failed to connect to the server");
+    let busy = thread::spawn(move || busy.add(1, 2).expect("Add failed"));
+    wait_for_active(&server, 1);
+
+    // Telemetry queues first, then more pings than may go ahead of it
+    let telemetry = TelemetryReport {
+        sensor_id: 1,
+        value: 20.5,
+        timestamp: 1,
+    };
+    let mut waiting = vec![send_and_close(
+        port,
+        client_message::Message::TelemetryReport(telemetry),
+    )];
+    for timestamp in 0..=MAX_BURST as u64 {
+        let ping = client_message::Message::PingRequest(PingRequest { timestamp });
+        waiting.push(send_and_close(port, ping));
+    }
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while server.stats().pool.queued != waiting.len() as u64 {
+        assert!(Instant::now() < deadline, "Connections not queued");
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    // Once the thread is free it takes them all, pings first until the telemetry's turn
+    release.send(()).unwrap();
+    assert_eq!(busy.join().unwrap(), 3);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while started.0.lock().unwrap().len() < 1 + waiting.len() {
+        assert!(Instant::now() < deadline, "Queued connections not served");
+        thread::sleep(Duration::from_millis(10));
+    }
+    let telemetry = 2;
+    let pings = 3..3 + MAX_BURST as u64;
+    let last_ping = 3 + MAX_BURST as u64;
+    let expected: Vec<u64> = pings.chain([telemetry, last_ping]).collect();
+    assert_eq!(started.0.lock().unwrap()[1..], expected);
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+#[test]
+fn test_connections_stay_on_one_shard() {
+    let threads = Threads::default();
+    let (server, handle, port) = start(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .shards(4)
+            .observer(threads.clone()),
+    );
+    let mut clients: Vec<Client> = (0..8)
+        .map(|_| {
+            let mut client = Client::new("localhost", port.into(), 1000);
+            client.connect().expect("Failed to connect to the server");
+            client
+        })
+        .collect();
+    for round in 0..10 {
+        for client in &mut clients {
+            assert_eq!(client.add(round, 1).expect("Add failed"), round + 1);
+        }
+    }
+
+    // Observers may hear of a request after its response is sent
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while threads
+        .0
+        .lock()
+        .unwrap()
+        .values()
+        .any(|names| names.len() < 11)
+    {
+        assert!(Instant::now() < deadline, "Requests not observed");
+        thread::sleep(Duration::from_millis(10));
+    }
+    let threads = threads.0.lock().unwrap();
+    assert_eq!(threads.len(), 8);
+    let mut shards = Vec::new();
+    for names in threads.values() {
+        // The connect and all ten requests, on threads of one shard
+        assert_eq!(names.len(), 11);
+        let first = shard(&names[0]);
+        assert!(names.iter().all(|name| shard(name) == first), "{:?}", names);
+        shards.push(first);
+    }
+    shards.sort();
+    shards.dedup();
+    assert_eq!(shards.len(), 4, "A shard was left idle");
+    drop(threads);
+    server.stop();
+    handle.join().unwrap();
+}
@@ -0,0 +1,37 @@
+#![cfg(feature = "std")]
+
+use embedded_recruitment_task::sharded::{Sharded, DEFAULT_SHARDS};
+use std::collections::HashMap;
+
+#[test]
+fn test_a_key_always_lands_in_the_same_shard() {
+    let sharded: Sharded<HashMap<String, u32>> = Sharded::default();
+    assert_eq!(sharded.shard_count(), DEFAULT_SHARDS);
+    for device in 0..1000 {
+        let key = format!("device-{}", device);
+        *sharded.lock(&key).entry(key.clone()).or_default() += 1;
+        *sharded.lock(&key).entry(key).or_default() += 1;
+    }
+
+    let mut total = 0;
+    let mut used = 0;
+    sharded.for_each(|shard| {
+        assert!(shard.values().all(|&count| count == 2));
+        total += shard.len();
+        used += usize::from(!shard.is_empty());
+    });
+    assert_eq!(total, 1000);
+    assert!(
+        used > DEFAULT_SHARDS / 2,
+        "keys spread over {} shards",
+        used
+    );
+}
+
+#[test]
+fn test_at_least_one_shard() {
+    let sharded = Sharded::new(0, || 0u32);
+    assert_eq!(sharded.shard_count(), 1);
+    *sharded.lock("anything") += 1;
+    assert_eq!(sharded.shards().map(|shard| *shard).sum::<u32>(), 1);
+}
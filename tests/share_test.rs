@@ -0,0 +1,74 @@
+#![cfg(feature = "message")]
+
+use embedded_recruitment_task::share::{parse, SharedSubscriptions};
+
+#[test]
+fn test_parse_shared_subscription() {
+    assert_eq!(
+        parse("$share/workers/site/+/commands"),
+        Some(("workers", "site/+/commands"))
+    );
+    assert_eq!(parse("site/+/commands"), None);
+    assert_eq!(parse("$share/workers"), None);
+    assert_eq!(parse("$share//site"), None);
+    assert_eq!(parse("$shared/workers/site"), None);
+}
+
+#[test]
+fn test_each_message_goes_to_one_member_in_turn() {
+    let mut shared = SharedSubscriptions::new();
+    for worker in ["a", "b", "c"] {
+        shared
+            .subscribe("workers", "site/+/commands", worker)
+            .expect("Valid filter");
+    }
+    shared
+        .subscribe("audit", "site/#", "auditor")
+        .expect("Valid filter");
+    assert_eq!(shared.len(), 2);
+
+    let mut workers = Vec::new();
+    for _ in 0..4 {
+        let picked = shared.round_robin("site/hall/commands");
+        assert_eq!(picked.len(), 2, "One member of each group");
+        assert!(picked.contains(&&"auditor"));
+        workers.extend(picked.into_iter().filter(|m| **m != "auditor").copied());
+    }
+    assert_eq!(workers, ["a", "b", "c", "a"]);
+    assert!(shared.round_robin("other/topic").is_empty());
+}
+
+#[test]
+fn test_least_loaded_member_is_picked() {
+    let mut shared = SharedSubscriptions::new();
+    for (worker, load) in [("a", 3), ("b", 1), ("c", 1)] {
+        shared
+            .subscribe("workers", "commands", (worker, load))
+            .expect("Valid filter");
+    }
+
+    let mut picked = Vec::new();
+    for _ in 0..3 {
+        let member = shared.least_loaded("commands", |(_, load)| *load);
+        picked.push(member[0].0);
+    }
+    assert_eq!(picked, ["b", "c", "b"]);
+}
+
+#[test]
+fn test_subscription_ends_with_last_member() {
+    let mut shared = SharedSubscriptions::new();
+    shared
+        .subscribe("workers", "commands", "a")
+        .expect("Valid filter");
+    shared
+        .subscribe("workers", "commands", "b")
+        .expect("Valid filter");
+
+    assert!(shared.unsubscribe("workers", "commands", &"a"));
+    assert!(!shared.unsubscribe("workers", "commands", &"a"));
+    assert_eq!(shared.round_robin("commands"), [&"b"]);
+    assert!(shared.unsubscribe("workers", "commands", &"b"));
+    assert!(shared.is_empty());
+    assert!(shared.round_robin("commands").is_empty());
+}
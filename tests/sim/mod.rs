@@ -0,0 +1,373 @@
+//! Deterministic in-memory simulation of a client talking to the server.
+//!
+//! Both ends run the real sans-IO [`Protocol`] state machines and the server
+//! answers with the real request handler, but bytes travel over simulated
+//! links instead of sockets. Time only advances when the simulation steps, and
+//! every random decision (chunk sizes, latency, loss) comes from a seeded RNG,
+//! so a failing seed replays exactly.
+//!
+//! [`Protocol`]: embedded_recruitment_task::protocol::Protocol
+
+#![allow(dead_code)] // Not every test binary uses every helper
+
+use embedded_recruitment_task::codec::CodecError;
+use embedded_recruitment_task::handler::handle_message;
+use embedded_recruitment_task::message::{client_message, ClientMessage, ServerMessage};
+use embedded_recruitment_task::protocol::{ClientProtocol, Event, ServerProtocol, State};
+use std::collections::{BTreeMap, VecDeque};
+
+/// Small xorshift generator; good enough for test scheduling and fully reproducible
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed.max(1)) // xorshift never leaves the all-zero state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Uniform value in `low..=high`
+    pub fn between(&mut self, low: u64, high: u64) -> u64 {
+        low + self.next_u64() % (high - low + 1)
+    }
+
+    /// Returns true with the given probability
+    pub fn chance(&mut self, probability: f64) -> bool {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+}
+
+/// Behaviour of one direction of a simulated link
+#[derive(Debug, Clone)]
+pub struct LinkConfig {
+    /// Written bytes are split into packets of 1..=max_chunk bytes
+    pub max_chunk: usize,
+    /// Each packet is delivered after a latency drawn from this range (ms)
+    pub latency: (u64, u64),
+    /// Probability that a packet is silently dropped
+    pub loss: f64,
+    /// Reassemble packets in send order like TCP does; when false, packets reach
+    /// the protocol in arrival order and reordering corrupts the byte stream
+    pub in_order: bool,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        LinkConfig {
+            max_chunk: 1500,
+            latency: (1, 1),
+            loss: 0.0,
+            in_order: true,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Packet {
+    deliver_at: u64,
+    seq: u64,
+    bytes: Vec<u8>,
+}
+
+// One direction of the connection
+#[derive(Debug)]
+struct Link {
+    name: &'static str,
+    config: LinkConfig,
+    next_seq: u64,
+    in_flight: Vec<Packet>,
+    expected_seq: u64,            // Next packet the receiver can hand on in order
+    held: BTreeMap<u64, Vec<u8>>, // Packets that arrived ahead of a gap
+}
+
+impl Link {
+    fn new(name: &'static str, config: LinkConfig) -> Self {
+        Link {
+            name,
+            config,
+            next_seq: 0,
+            in_flight: Vec::new(),
+            expected_seq: 0,
+            held: BTreeMap::new(),
+        }
+    }
+
+    fn write(&mut self, now: u64, mut bytes: &[u8], rng: &mut Rng, trace: &mut Vec<String>) {
+        while !bytes.is_empty() {
+            let size = rng.between(1, self.config.max_chunk.min(bytes.len()) as u64) as usize;
+            let (packet, rest) = bytes.split_at(size);
+            bytes = rest;
+
+            let seq = self.next_seq;
+            self.next_seq += 1;
+            if rng.chance(self.config.loss) {
+                trace.push(format!(
+                    "t={} {} #{} lost ({} bytes)",
+                    now, self.name, seq, size
+                ));
+                continue;
+            }
+
+            let (low, high) = self.config.latency;
+            self.in_flight.push(Packet {
+                deliver_at: now + rng.between(low, high),
+                seq,
+                bytes: packet.to_vec(),
+            });
+        }
+    }
+
+    fn next_delivery(&self) -> Option<u64> {
+        self.in_flight.iter().map(|packet| packet.deliver_at).min()
+    }
+
+    // Removes the packets due by `now` and returns the byte chunks the receiver sees
+    fn deliver(&mut self, now: u64, trace: &mut Vec<String>) -> Vec<Vec<u8>> {
+        let mut due: Vec<Packet> = Vec::new();
+        let mut index = 0;
+        while index < self.in_flight.len() {
+            if self.in_flight[index].deliver_at <= now {
+                due.push(self.in_flight.swap_remove(index));
+            } else {
+                index += 1;
+            }
+        }
+        due.sort_by_key(|packet| (packet.deliver_at, packet.seq));
+
+        let mut chunks = Vec::new();
+        for packet in due {
+            trace.push(format!(
+                "t={} {} #{} arrived ({} bytes)",
+                now,
+                self.name,
+                packet.seq,
+                packet.bytes.len()
+            ));
+            if !self.config.in_order {
+                chunks.push(packet.bytes);
+                continue;
+            }
+            self.held.insert(packet.seq, packet.bytes);
+            while let Some(bytes) = self.held.remove(&self.expected_seq) {
+                self.expected_seq += 1;
+                chunks.push(bytes);
+            }
+        }
+        chunks
+    }
+}
+
+/// What became of a request sent by the simulated client
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Response(ServerMessage),
+    TimedOut,
+    Failed(CodecError),
+    Closed,
+}
+
+// A request the client is still waiting on
+#[derive(Debug)]
+struct Outstanding {
+    deadline: u64,
+}
+
+/// A client and the server connected by two simulated links
+#[derive(Debug)]
+pub struct Simulation {
+    now: u64,
+    rng: Rng,
+    client: ClientProtocol,
+    server: ServerProtocol,
+    to_server: Link,
+    to_client: Link,
+    outstanding: VecDeque<Outstanding>,
+    outcomes: Vec<Outcome>,
+    abandoned: bool, // The client gave up on the connection after a timeout or error
+    trace: Vec<String>,
+}
+
+impl Simulation {
+    /// Creates a simulation with the same link behaviour in both directions
+    pub fn new(seed: u64, link: LinkConfig) -> Self {
+        Self::with_links(seed, link.clone(), link)
+    }
+
+    pub fn with_links(seed: u64, to_server: LinkConfig, to_client: LinkConfig) -> Self {
+        Simulation {
+            now: 0,
+            rng: Rng::new(seed),
+            client: ClientProtocol::new(),
+            server: ServerProtocol::new(),
+            to_server: Link::new("client->server", to_server),
+            to_client: Link::new("server->client", to_client),
+            outstanding: VecDeque::new(),
+            outcomes: Vec::new(),
+            abandoned: false,
+            trace: Vec::new(),
+        }
+    }
+
+    /// Current simulated time in milliseconds
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Sends a request that fails with [`Outcome::TimedOut`] unless answered within `timeout` ms
+    pub fn send(&mut self, message: client_message::Message, timeout: u64) {
+        self.send_partial(message, usize::MAX, timeout);
+    }
+
+    /// Like [`Simulation::send`], but only the first `len` bytes of the frame are ever written
+    pub fn send_partial(&mut self, message: client_message::Message, len: usize, timeout: u64) {
+        self.client
+            .send(&ClientMessage {
+                message: Some(message),
+                ..Default::default()
+            })
+            .expect("Failed to encode request");
+        self.outstanding.push_back(Outstanding {
+            deadline: self.now + timeout,
+        });
+        while let Some(bytes) = self.client.poll_transmit() {
+            let len = len.min(bytes.len());
+            self.to_server
+                .write(self.now, &bytes[..len], &mut self.rng, &mut self.trace);
+        }
+    }
+
+    /// Writes raw bytes from the client, bypassing the encoder
+    pub fn send_raw(&mut self, bytes: &[u8]) {
+        self.to_server
+            .write(self.now, bytes, &mut self.rng, &mut self.trace);
+    }
+
+    /// Advances time to the next delivery or deadline; returns false once nothing is left to happen
+    pub fn step(&mut self) -> bool {
+        let deadline = self.outstanding.front().map(|request| request.deadline);
+        let next = [
+            self.to_server.next_delivery(),
+            self.to_client.next_delivery(),
+            deadline,
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        let Some(next) = next else {
+            return false;
+        };
+        self.now = next;
+
+        for chunk in self.to_server.deliver(self.now, &mut self.trace) {
+            self.server_received(&chunk);
+        }
+        for chunk in self.to_client.deliver(self.now, &mut self.trace) {
+            self.client_received(&chunk);
+        }
+        self.check_deadlines();
+        true
+    }
+
+    /// Steps until no packet is in flight and no request is outstanding
+    pub fn run(&mut self) {
+        while self.step() {}
+    }
+
+    /// Outcomes of the requests sent so far, in send order
+    pub fn outcomes(&self) -> &[Outcome] {
+        &self.outcomes
+    }
+
+    /// Everything that happened on the links, for comparing runs
+    pub fn trace(&self) -> &[String] {
+        &self.trace
+    }
+
+    pub fn client_state(&self) -> State {
+        self.client.state()
+    }
+
+    pub fn server_state(&self) -> State {
+        self.server.state()
+    }
+
+    /// Bytes the server has received but not yet decoded
+    pub fn server_buffered(&self) -> usize {
+        self.server.buffered()
+    }
+
+    fn server_received(&mut self, bytes: &[u8]) {
+        for event in self.server.feed_bytes(bytes) {
+            match event {
+                Event::Message(ClientMessage {
+                    message: Some(request),
+                    ..
+                }) => {
+                    let response = ServerMessage {
+                        message: Some(handle_message(request)),
+                        ..Default::default()
+                    };
+                    self.server
+                        .send(&response)
+                        .expect("Failed to encode response");
+                }
+                Event::Message(_) => {}
+                Event::Error(e) => {
+                    self.trace
+                        .push(format!("t={} server failed: {}", self.now, e));
+                }
+                Event::Closed => {}
+            }
+        }
+        while let Some(bytes) = self.server.poll_transmit() {
+            self.to_client
+                .write(self.now, &bytes, &mut self.rng, &mut self.trace);
+        }
+    }
+
+    fn client_received(&mut self, bytes: &[u8]) {
+        if self.abandoned {
+            return;
+        }
+        for event in self.client.feed_bytes(bytes) {
+            match event {
+                Event::Message(response) => {
+                    if self.outstanding.pop_front().is_some() {
+                        self.outcomes.push(Outcome::Response(response));
+                    }
+                }
+                Event::Error(e) => {
+                    self.trace
+                        .push(format!("t={} client failed: {}", self.now, e));
+                    self.abandon(Outcome::Failed(e));
+                }
+                Event::Closed => self.abandon(Outcome::Closed),
+            }
+        }
+    }
+
+    fn check_deadlines(&mut self) {
+        let expired = self
+            .outstanding
+            .front()
+            .is_some_and(|request| request.deadline <= self.now);
+        if expired {
+            self.trace.push(format!("t={} client timed out", self.now));
+            self.abandon(Outcome::TimedOut);
+        }
+    }
+
+    // Fails every outstanding request and stops listening, as a real client drops the connection
+    fn abandon(&mut self, outcome: Outcome) {
+        self.abandoned = true;
+        for _ in self.outstanding.drain(..) {
+            self.outcomes.push(outcome.clone());
+        }
+    }
+}
@@ -0,0 +1,148 @@
+#![cfg(feature = "message")]
+
+mod common;
+mod sim;
+
+use common::add;
+use embedded_recruitment_task::message::{
+    client_message, server_message, AddResponse, EchoMessage, ServerMessage,
+};
+use embedded_recruitment_task::protocol::State;
+use sim::{LinkConfig, Outcome, Simulation};
+
+fn add_response(result: i32) -> Outcome {
+    Outcome::Response(ServerMessage {
+        message: Some(server_message::Message::AddResponse(AddResponse { result })),
+        ..Default::default()
+    })
+}
+
+// Small packets, jittery latency; packets overtake each other but are reassembled in order
+fn choppy() -> LinkConfig {
+    LinkConfig {
+        max_chunk: 3,
+        latency: (1, 40),
+        ..LinkConfig::default()
+    }
+}
+
+#[test]
+fn test_responses_survive_partitioning_and_reordering() {
+    for seed in 1..=50 {
+        let mut sim = Simulation::new(seed, choppy());
+        for i in 0..10 {
+            sim.send(add(i, i), 1_000);
+        }
+        sim.send(
+            client_message::Message::EchoMessage(EchoMessage {
+                content: "x".repeat(300),
+            }),
+            1_000,
+        );
+        sim.run();
+
+        let mut expected: Vec<Outcome> = (0..10).map(|i| add_response(i + i)).collect();
+        expected.push(Outcome::Response(ServerMessage {
+            message: Some(server_message::Message::EchoMessage(EchoMessage {
+                content: "x".repeat(300),
+            })),
+            ..Default::default()
+        }));
+        assert_eq!(sim.outcomes(), expected, "seed {}", seed);
+        assert_eq!(sim.server_state(), State::Open, "seed {}", seed);
+    }
+}
+
+#[test]
+fn test_partial_frame_times_out() {
+    let mut sim = Simulation::new(7, LinkConfig::default());
+
+    // The length prefix arrives but the rest of the frame never does
+    sim.send_partial(add(1, 2), 3, 500);
+    sim.run();
+
+    assert_eq!(sim.outcomes(), [Outcome::TimedOut]);
+    assert_eq!(sim.now(), 500, "Timeout fired at the wrong simulated time");
+    assert_eq!(sim.server_state(), State::Open);
+    assert!(
+        sim.server_buffered() > 0,
+        "Server discarded the partial frame"
+    );
+}
+
+#[test]
+fn test_lost_packet_stalls_stream_until_timeout() {
+    let lossy = LinkConfig {
+        max_chunk: 4,
+        latency: (1, 10),
+        loss: 0.2,
+        ..LinkConfig::default()
+    };
+    let mut timed_out = 0;
+    for seed in 1..=50 {
+        let mut sim = Simulation::new(seed, lossy.clone());
+        for i in 0..5 {
+            sim.send(add(i, 1), 200);
+        }
+        sim.run();
+
+        // Every request gets exactly one outcome, and a response is never wrong
+        assert_eq!(sim.outcomes().len(), 5, "seed {}", seed);
+        for (i, outcome) in sim.outcomes().iter().enumerate() {
+            match outcome {
+                Outcome::TimedOut => timed_out += 1,
+                response => assert_eq!(*response, add_response(i as i32 + 1), "seed {}", seed),
+            }
+        }
+    }
+    assert!(timed_out > 0, "20% loss never caused a timeout");
+}
+
+#[test]
+fn test_unordered_delivery_fails_without_panicking() {
+    // Without reassembly, reordered packets corrupt the byte stream
+    let unordered = LinkConfig {
+        in_order: false,
+        ..choppy()
+    };
+    let mut failures = 0;
+    for seed in 1..=50 {
+        let mut sim = Simulation::new(seed, unordered.clone());
+        for i in 0..10 {
+            sim.send(add(i, i), 1_000);
+        }
+        sim.run();
+
+        // Each request is answered, fails or times out; nothing is left hanging
+        assert_eq!(sim.outcomes().len(), 10, "seed {}", seed);
+        if sim.server_state() == State::Failed {
+            failures += 1;
+            assert!(sim.outcomes().contains(&Outcome::TimedOut), "seed {}", seed);
+        }
+    }
+    assert!(failures > 0, "Reordering never corrupted the stream");
+}
+
+#[test]
+fn test_same_seed_replays_identically() {
+    let run = |seed| {
+        let lossy = LinkConfig {
+            loss: 0.1,
+            in_order: false,
+            ..choppy()
+        };
+        let mut sim = Simulation::new(seed, lossy);
+        for i in 0..20 {
+            sim.send(add(i, -i), 300);
+        }
+        sim.run();
+        (sim.trace().to_vec(), sim.outcomes().to_vec(), sim.now())
+    };
+
+    assert_eq!(run(42), run(42));
+    assert_ne!(
+        run(42).0,
+        run(43).0,
+        "Different seeds produced the same trace"
+    );
+}
@@ -0,0 +1,63 @@
+#![cfg(feature = "message")]
+
+use embedded_recruitment_task::slab::{Key, Slab};
+
+#[test]
+fn test_removed_slots_are_reused() {
+    let mut slab = Slab::new();
+    let first = slab.insert("first");
+    let second = slab.insert("second");
+    assert_eq!(
+        (slab.get(first), slab.get(second)),
+        (Some(&"first"), Some(&"second"))
+    );
+
+    assert_eq!(slab.remove(first), Some("first"));
+    let third = slab.insert("third");
+    assert_eq!(third.index(), first.index());
+    assert_eq!(slab.slots(), 2);
+    assert_eq!(slab.len(), 2);
+    let values: Vec<_> = slab.values().copied().collect();
+    assert_eq!(values, ["third", "second"]);
+}
+
+#[test]
+fn test_stale_keys_miss() {
+    let mut slab = Slab::new();
+    let old = slab.insert(1);
+    slab.remove(old);
+    let new = slab.insert(2);
+
+    assert_ne!(old, new);
+    assert!(!slab.contains(old));
+    assert_eq!(slab.get_mut(old), None);
+    assert_eq!(slab.remove(old), None);
+    assert_eq!(slab.get(new), Some(&2));
+    assert_eq!(slab.remove(new), Some(2));
+    assert_eq!(slab.remove(new), None);
+    assert!(slab.is_empty());
+}
+
+#[test]
+fn test_free_slots_are_taken_last_freed_first() {
+    let mut slab = Slab::with_capacity(3);
+    let keys: Vec<Key> = (0..3).map(|value| slab.insert(value)).collect();
+    slab.remove(keys[0]);
+    slab.remove(keys[2]);
+
+    assert_eq!(slab.insert(10).index(), 2);
+    assert_eq!(slab.insert(11).index(), 0);
+    assert_eq!(slab.insert(12).index(), 3);
+    assert_eq!(slab.slots(), 4);
+}
+
+#[test]
+fn test_keys_round_trip_through_bits() {
+    let mut slab = Slab::new();
+    let key = slab.insert(());
+    slab.remove(key);
+    let key = slab.insert(());
+
+    assert_eq!(Key::from_bits(key.to_bits()), key);
+    assert_eq!(key.to_string(), "0.1");
+}
@@ -0,0 +1,92 @@
+#![cfg(feature = "smoltcp")]
+
+use embedded_recruitment_task::codec::{self, FrameDecoder};
+use embedded_recruitment_task::message::{
+    client_message, server_message, AddRequest, AddResponse, ClientMessage, ServerMessage,
+};
+use embedded_recruitment_task::smoltcp_client::{ClientState, SmoltcpClient};
+use smoltcp::iface::{Config, Interface, SocketSet, SocketStorage};
+use smoltcp::phy::{Loopback, Medium};
+use smoltcp::socket::tcp;
+use smoltcp::time::Instant;
+use smoltcp::wire::{HardwareAddress, IpAddress, IpCidr};
+
+fn tcp_socket() -> tcp::Socket<'static> {
+    tcp::Socket::new(
+        tcp::SocketBuffer::new(vec![0; 1024]),
+        tcp::SocketBuffer::new(vec![0; 1024]),
+    )
+}
+
+#[test]
+fn test_smoltcp_client_over_loopback() {
+    let mut device = Loopback::new(Medium::Ip);
+    let mut iface = Interface::new(Config::new(HardwareAddress::Ip), &mut device, Instant::ZERO);
+    iface.update_ip_addrs(|addrs| {
+        addrs
+            .push(IpCidr::new(IpAddress::v4(127, 0, 0, 1), 8))
+            .unwrap();
+    });
+
+    let mut storage: [SocketStorage; 2] = Default::default();
+    let mut sockets = SocketSet::new(&mut storage[..]);
+    let server_handle = sockets.add(tcp_socket());
+    let client_handle = sockets.add(tcp_socket());
+
+    sockets
+        .get_mut::<tcp::Socket>(server_handle)
+        .listen(1234)
+        .unwrap();
+    sockets
+        .get_mut::<tcp::Socket>(client_handle)
+        .connect(iface.context(), (IpAddress::v4(127, 0, 0, 1), 1234), 65000)
+        .unwrap();
+
+    let mut client = SmoltcpClient::new();
+    let request = client_message::Message::AddRequest(AddRequest { a: 10, b: 20 });
+    client.send(request).expect("Failed to queue request");
+
+    // Minimal in-test server answering AddRequests over the second socket
+    let mut server_decoder = FrameDecoder::new();
+    let mut response = None;
+    let mut now = Instant::ZERO;
+    for _ in 0..100 {
+        iface.poll(now, &mut device, &mut sockets);
+
+        let server = sockets.get_mut::<tcp::Socket>(server_handle);
+        if server.can_recv() {
+            server
+                .recv(|bytes| {
+                    server_decoder.extend(bytes);
+                    (bytes.len(), ())
+                })
+                .unwrap();
+        }
+        if let Some(ClientMessage {
+            message: Some(client_message::Message::AddRequest(add)),
+            ..
+        }) = server_decoder.next_message().unwrap()
+        {
+            let reply = ServerMessage {
+                message: Some(server_message::Message::AddResponse(AddResponse {
+                    result: add.a + add.b,
+                })),
+                ..Default::default()
+            };
+            server.send_slice(&codec::encode(&reply).unwrap()).unwrap();
+        }
+
+        if let Some(message) = client.poll(sockets.get_mut::<tcp::Socket>(client_handle)) {
+            response = Some(message);
+            break;
+        }
+        now += smoltcp::time::Duration::from_millis(10);
+    }
+
+    assert_eq!(client.state(), &ClientState::Established);
+    assert_eq!(client.pending(), 0, "Client did not flush its request");
+    match response.and_then(|r| r.message) {
+        Some(server_message::Message::AddResponse(add)) => assert_eq!(add.result, 30),
+        _ => panic!("Expected AddResponse, but received a different message"),
+    }
+}
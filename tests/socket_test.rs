@@ -0,0 +1,54 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::start;
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::message::{client_message, server_message, EchoMessage};
+use embedded_recruitment_task::server::Server;
+use embedded_recruitment_task::socket::{ServerConfig, DEFAULT_BACKLOG};
+
+#[test]
+fn test_configured_server_answers_requests() {
+    let config = ServerConfig {
+        backlog: 1024,
+        recv_buffer: Some(64 * 1024),
+        send_buffer: Some(64 * 1024),
+        ..Default::default()
+    }
+    .dscp(46);
+    let server = Server::with_config("localhost:0", config).expect("Failed to start server");
+    let (server, handle, port) = start(server);
+
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    client
+        .send(client_message::Message::EchoMessage(EchoMessage {
+            content: "marked".to_string(),
+        }))
+        .expect("Failed to send message");
+    let response = client.receive().expect("Failed to receive response");
+    assert_eq!(
+        response.message,
+        Some(server_message::Message::EchoMessage(EchoMessage {
+            content: "marked".to_string(),
+        }))
+    );
+    client.disconnect().expect("Failed to disconnect");
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_dscp_fills_the_upper_six_bits() {
+    let config = ServerConfig::default();
+    assert_eq!((config.backlog, config.tos), (DEFAULT_BACKLOG, None));
+    assert_eq!(config.dscp(46).tos, Some(0xb8));
+    assert_eq!(config.dscp(0xff).tos, Some(0xfc));
+}
+
+#[test]
+fn test_unresolvable_address_is_an_error() {
+    assert!(Server::with_config("not an address", ServerConfig::default()).is_err());
+}
@@ -0,0 +1,134 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::start;
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::message::{client_message, server_message, TelemetryReport};
+use embedded_recruitment_task::server::Server;
+use embedded_recruitment_task::spool::Spool;
+use std::sync::{Arc, Mutex};
+
+fn report(client: &mut Client, id: u64) {
+    let reading = client_message::Message::TelemetryReport(TelemetryReport {
+        sensor_id: id as u32,
+        value: 1.0,
+        timestamp: id,
+    });
+    client
+        .send_with_id(0, id, reading)
+        .expect("Failed to send or keep report");
+}
+
+// Message IDs of the next `count` acknowledgements
+fn acknowledged(client: &mut Client, count: usize) -> Vec<u64> {
+    (0..count)
+        .map(|_| {
+            let response = client.receive().expect("Failed to receive response");
+            assert!(matches!(
+                response.message,
+                Some(server_message::Message::TelemetryAck(_))
+            ));
+            response.message_id
+        })
+        .collect()
+}
+
+#[test]
+fn test_reports_kept_while_disconnected_are_sent_in_order() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.set_spool(Some(Spool::new(10)));
+    for id in 1..=3 {
+        report(&mut client, id);
+    }
+    assert_eq!(client.spooled(), 3);
+
+    client.connect().expect("Failed to connect to the server");
+    assert_eq!(client.spooled(), 0);
+    report(&mut client, 4);
+    assert_eq!(acknowledged(&mut client, 4), [1, 2, 3, 4]);
+    client.disconnect().expect("Failed to disconnect");
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_full_spool_drops_the_oldest() {
+    let dropped = Arc::new(Mutex::new(Vec::new()));
+    let dropped_clone = dropped.clone();
+    let spool = Spool::new(2).on_drop(move |message| {
+        dropped_clone.lock().unwrap().push(message.message_id);
+    });
+    let mut client = Client::new("localhost", 1, 1000);
+    client.set_spool(Some(spool));
+    for id in 1..=3 {
+        report(&mut client, id);
+    }
+    assert_eq!(client.spooled(), 2);
+    assert_eq!(*dropped.lock().unwrap(), [1]);
+}
+
+#[test]
+fn test_broken_connection_keeps_reports_for_the_next() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.set_spool(Some(Spool::default()));
+    client.connect().expect("Failed to connect to the server");
+    report(&mut client, 1);
+    assert_eq!(acknowledged(&mut client, 1), [1]);
+    server.stop();
+    handle.join().expect("Server thread panicked");
+    drop(server); // Frees the port
+
+    // A write into a reset connection may appear to succeed; the next one fails
+    for id in 2..=4 {
+        report(&mut client, id);
+    }
+    assert!(!client.is_connected());
+    let kept = client.spooled();
+    assert!(kept >= 2);
+
+    let server = Server::new(&format!("localhost:{}", port)).expect("Failed to restart server");
+    let (server, handle, _) = start(server);
+    client.connect().expect("Failed to reconnect to the server");
+    assert_eq!(client.spooled(), 0);
+    let ids = acknowledged(&mut client, kept);
+    assert_eq!(ids.last(), Some(&4));
+    client.disconnect().expect("Failed to disconnect");
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_spool_file_survives_a_restart() {
+    let path = std::env::temp_dir().join(format!("spool-test-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut client = Client::new("localhost", 1, 1000);
+    client.set_spool(Some(Spool::open(&path, 2).expect("Failed to open spool")));
+    for id in 1..=3 {
+        report(&mut client, id);
+    }
+    drop(client);
+
+    // A frame cut short at the end is discarded
+    let mut bytes = std::fs::read(&path).expect("Spool file missing");
+    bytes.extend_from_slice(&[40, 1, 2]);
+    std::fs::write(&path, bytes).expect("Failed to write spool file");
+    let spool = Spool::open(&path, 10).expect("Failed to reopen spool");
+    assert_eq!(spool.len(), 2);
+
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.set_spool(Some(spool));
+    client.connect().expect("Failed to connect to the server");
+    assert_eq!(acknowledged(&mut client, 2), [2, 3]);
+    assert_eq!(std::fs::read(&path).expect("Spool file missing"), b"");
+    client.disconnect().expect("Failed to disconnect");
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,197 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+use embedded_recruitment_task::client;
+use embedded_recruitment_task::handler::MessageKind;
+use embedded_recruitment_task::message::{client_message, AddRequest, EchoMessage};
+use embedded_recruitment_task::server::Server;
+use embedded_recruitment_task::stats::Stats;
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+#[test]
+fn test_slow_requests_are_counted() {
+    let server = Arc::new(Server::new("localhost:8093").expect("Failed to start server"));
+    let server_clone = Arc::clone(&server);
+    let handle = thread::spawn(move || server_clone.run().expect("Server encountered an error"));
+
+    let mut client = client::Client::new("localhost", 8093, 1000);
+    client.connect().expect("Failed to connect to the server");
+    let round_trip = |client: &mut client::Client| {
+        client
+            .send(client_message::Message::AddRequest(AddRequest {
+                a: 1,
+                b: 2,
+            }))
+            .expect("Failed to send message");
+        client.receive().expect("Failed to receive response");
+    };
+
+    // Disabled by default
+    round_trip(&mut client);
+    assert_eq!(server.stats().slow_requests, 0);
+
+    // Every request takes at least zero time
+    server.set_slow_request_threshold(Some(Duration::ZERO));
+    round_trip(&mut client);
+    round_trip(&mut client);
+
+    server.set_slow_request_threshold(Some(Duration::from_secs(60)));
+    round_trip(&mut client);
+
+    let stats = server.stats();
+    assert_eq!(stats.requests, 4);
+    assert_eq!(stats.slow_requests, 2);
+
+    client.disconnect().ok();
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_latency_is_tracked_per_message_type() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let port = server.local_addr().expect("No local address").port();
+    let server_clone = Arc::clone(&server);
+    let handle = thread::spawn(move || server_clone.run().expect("Server encountered an error"));
+
+    let mut client = client::Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    for i in 0..10 {
+        client
+            .send(client_message::Message::EchoMessage(EchoMessage {
+                content: format!("echo {}", i),
+            }))
+            .expect("Failed to send message");
+        client.receive().expect("Failed to receive response");
+    }
+    for _ in 0..3 {
+        client
+            .send(client_message::Message::AddRequest(AddRequest {
+                a: 1,
+                b: 2,
+            }))
+            .expect("Failed to send message");
+        client.receive().expect("Failed to receive response");
+    }
+
+    let stats = server.stats();
+    assert_eq!(stats.latency[&MessageKind::Echo].count(), 10);
+    assert_eq!(stats.latency[&MessageKind::Add].count(), 3);
+    assert!(!stats.latency.contains_key(&MessageKind::Ping));
+
+    let echo = &stats.latency[&MessageKind::Echo];
+    assert!(echo.percentile(50.0) <= echo.percentile(99.0));
+    assert!(echo.percentile(99.0) <= echo.max());
+    assert!(echo.mean() <= echo.max());
+
+    client.disconnect().ok();
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+// Polls the server's stats until `done` holds, failing after five seconds
+fn wait_for(server: &Server, done: impl Fn(&Stats) -> bool) -> Stats {
+    let start = Instant::now();
+    loop {
+        let stats = server.stats();
+        if done(&stats) {
+            return stats;
+        }
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "Timed out with {:?}",
+            stats.pool
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn test_connections_beyond_the_workers_are_served() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let port = server.local_addr().expect("No local address").port();
+    let server_clone = Arc::clone(&server);
+    let handle = thread::spawn(move || server_clone.run().expect("Server encountered an error"));
+
+    // One more client than there are workers
+    let workers = server.stats().pool.workers;
+    let mut clients: Vec<_> = (0..=workers)
+        .map(|_| {
+            let mut client = client::Client::new("localhost", port.into(), 1000);
+            client.connect().expect("Failed to connect to the server");
+            client
+        })
+        .collect();
+
+    for client in &mut clients {
+        assert_eq!(client.add(1, 2).expect("Connection not served"), 3);
+    }
+    let stats = server.stats();
+    assert_eq!(stats.pool.active, workers as u64 + 1);
+    assert_eq!(stats.pool.queued, 0);
+    assert_eq!(stats.pool.completed, 0);
+
+    for client in &mut clients {
+        client.disconnect().ok();
+    }
+    let stats = wait_for(&server, |stats| stats.pool.completed == workers as u64 + 1);
+    assert_eq!(stats.pool.active, 0);
+    assert_eq!(stats.pool.queued, 0);
+    assert_eq!(stats.pool.panicked, 0);
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_snapshots_give_counts_between_them() {
+    let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+    let port = server.local_addr().expect("No local address").port();
+    let server_clone = Arc::clone(&server);
+    let handle = thread::spawn(move || server_clone.run().expect("Server encountered an error"));
+    let round_trips = |message: client_message::Message, count: usize| {
+        let mut client = client::Client::new("localhost", port.into(), 1000);
+        client.connect().expect("Failed to connect to the server");
+        for _ in 0..count {
+            client
+                .send(message.clone())
+                .expect("Failed to send message");
+            client.receive().expect("Failed to receive response");
+        }
+        client.disconnect().ok();
+    };
+    let echo = client_message::Message::EchoMessage(EchoMessage {
+        content: "before".to_string(),
+    });
+    round_trips(echo, 3);
+    let before = server.snapshot();
+
+    // Counted on several workers, so in several stripes
+    thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                round_trips(
+                    client_message::Message::AddRequest(AddRequest { a: 1, b: 2 }),
+                    5,
+                )
+            });
+        }
+    });
+    let after = server.snapshot();
+
+    assert_eq!(after.stats.requests, 23);
+    let delta = after.since(&before);
+    assert_eq!(delta.elapsed, after.taken - before.taken);
+    assert_eq!(delta.stats.requests, 20);
+    assert_eq!(delta.stats.latency[&MessageKind::Add].count(), 20);
+    assert!(!delta.stats.latency.contains_key(&MessageKind::Echo));
+    assert_eq!(delta.stats.pool.workers, after.stats.pool.workers);
+    assert!(delta.per_second(|stats| stats.requests) > 0.0);
+    assert_eq!(after.since(&after).per_second(|stats| stats.requests), 0.0);
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
@@ -0,0 +1,169 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::{call, echo, start};
+use embedded_recruitment_task::client::{Client, Push};
+use embedded_recruitment_task::message::{
+    client_message, error_response, server_message, Delivery, ResumeRequest,
+};
+use embedded_recruitment_task::quota::Quota;
+use embedded_recruitment_task::server::Server;
+use embedded_recruitment_task::tenant::{scope, split};
+use std::{collections::HashMap, io};
+
+fn connect_as(port: u16, device: &str) -> Client {
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    client
+        .send(client_message::Message::ResumeRequest(ResumeRequest {
+            device_id: device.to_string(),
+            ..Default::default()
+        }))
+        .expect("Failed to send message");
+    client.receive().expect("Failed to receive response");
+    client
+}
+
+#[test]
+fn test_tenants_only_see_their_own_devices() {
+    let server = Server::new("localhost:0").expect("Failed to start server");
+    server.add_tenant("acme", Quota::default());
+    server.add_tenant("globex", Quota::default());
+    let acme = server.tenant("acme");
+    let globex = server.tenant("globex");
+
+    acme.send_to("thermo-1", b"on".to_vec());
+    acme.send_to("thermo-1", b"off".to_vec());
+    globex.send_to("thermo-1", b"on".to_vec());
+    server.send_to("gateway", b"on".to_vec());
+
+    assert_eq!(acme.queue_depth("thermo-1"), 2);
+    assert_eq!(
+        acme.queue_depths(),
+        HashMap::from([("thermo-1".to_string(), 2)])
+    );
+    assert_eq!(
+        globex.queue_depths(),
+        HashMap::from([("thermo-1".to_string(), 1)])
+    );
+    assert_eq!(server.queue_depth("acme/thermo-1"), 2);
+    assert_eq!(acme.topic("site/hall"), "acme/site/hall");
+    assert_eq!(acme.quota_status().queued, 2);
+}
+
+#[test]
+fn test_tenant_quota_covers_all_its_devices() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    server.add_tenant(
+        "acme",
+        Quota {
+            messages_per_day: Some(2),
+            ..Default::default()
+        },
+    );
+    server.tenant("acme").send_to("thermo-2", b"on".to_vec());
+    let mut first = connect_as(port, "acme/thermo-1");
+    let mut second = connect_as(port, "acme/thermo-2");
+    let mut outsider = connect_as(port, "globex/thermo-1");
+
+    assert!(matches!(
+        call(&mut first, echo("reading")),
+        Some(server_message::Message::EchoMessage(_))
+    ));
+    match second
+        .receive()
+        .expect("Failed to receive delivery")
+        .message
+    {
+        Some(server_message::Message::Delivery(Delivery { payload, .. })) => {
+            assert_eq!(payload, &b"on"[..])
+        }
+        other => panic!("Expected a Delivery, got {:?}", other),
+    }
+    assert!(matches!(
+        call(&mut second, echo("reading")),
+        Some(server_message::Message::EchoMessage(_))
+    ));
+    match call(&mut first, echo("reading")) {
+        Some(server_message::Message::ErrorResponse(error)) => {
+            assert_eq!(error.code, error_response::Code::QuotaExceeded as i32)
+        }
+        other => panic!("Expected an ErrorResponse, got {:?}", other),
+    }
+    assert!(matches!(
+        call(&mut outsider, echo("reading")),
+        Some(server_message::Message::EchoMessage(_))
+    ));
+
+    let stats = server.tenant("acme").stats();
+    assert_eq!(
+        (stats.connections, stats.requests, stats.quota_refusals),
+        (2, 4, 1)
+    );
+    assert!(stats.request_bytes > 0);
+    assert!(!server.stats().tenants.contains_key("globex"));
+    for client in [&mut first, &mut second, &mut outsider] {
+        client.disconnect().expect("Failed to disconnect");
+    }
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+    assert_eq!(server.stats().tenants["acme"].connections, 0);
+}
+
+#[test]
+fn test_tenants_only_see_their_own_topics() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    server.add_tenant("acme", Quota::default());
+    server.add_tenant("globex", Quota::default());
+    let mut thermo = connect_as(port, "acme/thermo");
+    let mut dashboard = connect_as(port, "acme/dashboard");
+    let mut spy = connect_as(port, "globex/spy");
+    let mut gateway = connect_as(port, "gateway");
+    thermo
+        .publish_retained("site/secret", &b"21.5"[..])
+        .expect("Publish failed");
+
+    // Retained messages and new ones alike stay within the tenant
+    assert_eq!(dashboard.subscribe_to("#").unwrap(), 1);
+    assert_eq!(spy.subscribe_to("#").unwrap(), 0);
+    assert_eq!(spy.subscribe_to("site/secret").unwrap(), 0);
+    assert_eq!(spy.subscribe_to("$share/all/#").unwrap(), 0);
+    assert_eq!(gateway.subscribe_to("#").unwrap(), 0);
+    assert_eq!(thermo.publish("site/secret", &b"22.0"[..]).unwrap(), 1);
+    for payload in [&b"21.5"[..], &b"22.0"[..]] {
+        match dashboard.next_push().expect("No publication") {
+            Push::Publication(publication) => {
+                assert_eq!(publication.topic, "site/secret");
+                assert_eq!(publication.payload, payload);
+            }
+            other => panic!("Unexpected push {:?}", other),
+        }
+    }
+    for outsider in [&mut spy, &mut gateway] {
+        let nothing = outsider.next_push().unwrap_err();
+        assert_eq!(nothing.kind(), io::ErrorKind::WouldBlock);
+    }
+
+    // Nor can a device reach another tenant's topics by naming them
+    assert_eq!(spy.publish("acme/site/secret", &b"0"[..]).unwrap(), 1); // Its own `#`
+    let nothing = dashboard.next_push().unwrap_err();
+    assert_eq!(nothing.kind(), io::ErrorKind::WouldBlock);
+    let refused = spy.publish("$acme/site/secret", &b"0"[..]).unwrap_err();
+    assert_eq!(refused.kind(), io::ErrorKind::InvalidInput);
+    let refused = gateway.subscribe_to("$acme/#").unwrap_err();
+    assert_eq!(refused.kind(), io::ErrorKind::InvalidInput);
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_identity_split() {
+    assert_eq!(split("acme/thermo-1"), (Some("acme"), "thermo-1"));
+    assert_eq!(split("acme/site/thermo-1"), (Some("acme"), "site/thermo-1"));
+    assert_eq!(split("thermo-1"), (None, "thermo-1"));
+    assert_eq!(split("/thermo-1"), (None, "/thermo-1"));
+    assert_eq!(scope("acme", "thermo-1"), "acme/thermo-1");
+}
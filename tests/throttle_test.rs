@@ -0,0 +1,137 @@
+#![cfg(feature = "server")]
+
+use embedded_recruitment_task::throttle::TokenBucket;
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_full_bucket_allows_one_second_burst() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(start);
+
+    assert_eq!(bucket.take(1000, 600, start), Duration::ZERO);
+    assert_eq!(bucket.take(1000, 400, start), Duration::ZERO);
+    // Overdrawn by 500 bytes at 1000 bytes per second
+    assert_eq!(bucket.take(1000, 500, start), Duration::from_millis(500));
+}
+
+#[test]
+fn test_tokens_refill_at_the_rate() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(start);
+    assert_eq!(bucket.take(1000, 1000, start), Duration::ZERO);
+
+    // A quarter of a second earns 250 bytes
+    let later = start + Duration::from_millis(250);
+    assert_eq!(bucket.take(1000, 250, later), Duration::ZERO);
+    assert_eq!(bucket.take(1000, 100, later), Duration::from_millis(100));
+
+    // An idle bucket never holds more than one second's worth
+    let much_later = later + Duration::from_secs(60);
+    assert_eq!(bucket.take(1000, 1000, much_later), Duration::ZERO);
+    assert_eq!(bucket.take(1000, 1, much_later), Duration::from_millis(1));
+}
+
+#[test]
+fn test_oversized_frame_is_paid_back() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(start);
+
+    // Larger than the bucket: passes, then the connection waits out the overdraft
+    assert_eq!(bucket.take(1000, 3000, start), Duration::from_secs(2));
+    let paid = start + Duration::from_secs(2);
+    assert_eq!(bucket.take(1000, 1, paid), Duration::from_millis(1));
+}
+
+#[cfg(feature = "client")]
+mod server {
+    use embedded_recruitment_task::client;
+    use embedded_recruitment_task::message::{client_message, server_message, EchoMessage};
+    use embedded_recruitment_task::server::Server;
+    use std::{
+        sync::Arc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    fn echo_all(client: &mut client::Client, count: usize, size: usize) -> Duration {
+        let content = "x".repeat(size);
+        let start = Instant::now();
+        for _ in 0..count {
+            client
+                .send(client_message::Message::EchoMessage(EchoMessage {
+                    content: content.clone(),
+                }))
+                .expect("Failed to send message");
+            let response = client.receive().expect("Failed to receive response");
+            match response.message {
+                Some(server_message::Message::EchoMessage(echo)) => {
+                    assert_eq!(echo.content, content)
+                }
+                other => panic!("Unexpected response: {:?}", other),
+            }
+        }
+        start.elapsed()
+    }
+
+    #[test]
+    fn test_download_limit_slows_responses() {
+        let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+        let port = server.local_addr().expect("No local address").port();
+        let server_clone = Arc::clone(&server);
+        let handle =
+            thread::spawn(move || server_clone.run().expect("Server encountered an error"));
+
+        let mut client = client::Client::new("localhost", port.into(), 2000);
+        client.connect().expect("Failed to connect to the server");
+
+        // About 10 KB of responses at 4 KB/s, of which the first 4 KB are a free burst
+        server.set_download_limit(Some(4000));
+        let throttled = echo_all(&mut client, 5, 2000);
+        assert!(
+            throttled >= Duration::from_millis(1200),
+            "Responses arrived too fast: {:?}",
+            throttled
+        );
+
+        server.set_download_limit(None);
+        let unthrottled = echo_all(&mut client, 5, 2000);
+        assert!(
+            unthrottled < Duration::from_millis(500),
+            "Responses still throttled: {:?}",
+            unthrottled
+        );
+
+        client.disconnect().ok();
+        server.stop();
+        handle.join().expect("Server thread panicked");
+    }
+
+    #[test]
+    fn test_stop_interrupts_a_throttled_connection() {
+        let server = Arc::new(Server::new("localhost:0").expect("Failed to start server"));
+        let port = server.local_addr().expect("No local address").port();
+        let server_clone = Arc::clone(&server);
+        let handle =
+            thread::spawn(move || server_clone.run().expect("Server encountered an error"));
+
+        // The first read of up to 512 bytes overdraws the bucket by several seconds
+        server.set_upload_limit(Some(100));
+        let mut client = client::Client::new("localhost", port.into(), 1000);
+        client.connect().expect("Failed to connect to the server");
+        client
+            .send(client_message::Message::EchoMessage(EchoMessage {
+                content: "x".repeat(2000),
+            }))
+            .expect("Failed to send message");
+        thread::sleep(Duration::from_millis(200));
+
+        let stopping = Instant::now();
+        server.stop();
+        handle.join().expect("Server thread panicked");
+        assert!(
+            stopping.elapsed() < Duration::from_secs(2),
+            "Stop waited for the throttle: {:?}",
+            stopping.elapsed()
+        );
+    }
+}
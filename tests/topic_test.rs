@@ -0,0 +1,99 @@
+#![cfg(feature = "message")]
+
+use embedded_recruitment_task::topic::{
+    matches, validate_filter, validate_topic, TopicError, TopicTrie, MAX_LEVELS,
+};
+
+#[test]
+fn test_filters_and_names_are_validated() {
+    assert_eq!(validate_topic("site/room/device/metric"), Ok(()));
+    assert_eq!(validate_topic(""), Err(TopicError::Empty));
+    assert_eq!(validate_topic("site/+/device"), Err(TopicError::Wildcard));
+
+    for filter in ["site/+/device/#", "#", "+", "+/+", "site//metric"] {
+        assert_eq!(validate_filter(filter), Ok(()), "{}", filter);
+    }
+    for filter in ["site/#/metric", "site/room+", "site/#a"] {
+        assert_eq!(
+            validate_filter(filter),
+            Err(TopicError::MisplacedWildcard),
+            "{}",
+            filter
+        );
+    }
+    let deep = vec!["a"; MAX_LEVELS + 1].join("/");
+    assert_eq!(validate_filter(&deep), Err(TopicError::TooDeep));
+}
+
+#[test]
+fn test_wildcards_match_levels() {
+    let topic = "site/kitchen/thermo-1/temperature";
+    for filter in [
+        topic,
+        "site/+/thermo-1/temperature",
+        "site/+/+/+",
+        "site/#",
+        "site/kitchen/thermo-1/temperature/#",
+        "#",
+    ] {
+        assert!(matches(filter, topic), "{} should match", filter);
+    }
+    for filter in [
+        "site/+/temperature",
+        "site/hall/#",
+        "site/+/thermo-1",
+        "site/kitchen/thermo-1/temperature/+",
+    ] {
+        assert!(!matches(filter, topic), "{} should not match", filter);
+    }
+}
+
+#[test]
+fn test_reserved_topics_skip_leading_wildcards() {
+    assert!(!matches("#", "$server/load"));
+    assert!(!matches("+/load", "$server/load"));
+    assert!(matches("$server/#", "$server/load"));
+}
+
+#[test]
+fn test_trie_finds_every_matching_filter() {
+    let mut trie = TopicTrie::new();
+    for (filter, value) in [
+        ("site/kitchen/thermo-1/temperature", 1),
+        ("site/+/thermo-1/#", 2),
+        ("site/hall/#", 3),
+        ("#", 4),
+        ("site/+/+/humidity", 5),
+    ] {
+        trie.insert(filter, value).expect("Valid filter");
+    }
+    assert_eq!(trie.len(), 5);
+
+    let mut found: Vec<i32> = trie
+        .matches("site/kitchen/thermo-1/temperature")
+        .into_iter()
+        .copied()
+        .collect();
+    found.sort();
+    assert_eq!(found, [1, 2, 4]);
+    let mut found: Vec<i32> = trie.matches("site/hall").into_iter().copied().collect();
+    found.sort();
+    assert_eq!(found, [3, 4]);
+    assert!(trie.matches("$server/load").is_empty());
+    assert!(trie.matches("site/+/x").is_empty());
+    assert_eq!(trie.insert("a/#/b", 6), Err(TopicError::MisplacedWildcard));
+}
+
+#[test]
+fn test_trie_remove() {
+    let mut trie = TopicTrie::new();
+    trie.insert("site/+/thermo-1/#", "a").expect("Valid filter");
+    trie.insert("site/+/thermo-1/#", "b").expect("Valid filter");
+
+    assert!(trie.remove("site/+/thermo-1/#", &"a"));
+    assert!(!trie.remove("site/+/thermo-1/#", &"a"));
+    assert!(!trie.remove("site/+", &"b"));
+    assert_eq!(trie.matches("site/hall/thermo-1"), [&"b"]);
+    assert!(trie.remove("site/+/thermo-1/#", &"b"));
+    assert!(trie.is_empty());
+}
@@ -0,0 +1,274 @@
+#![cfg(feature = "message")]
+
+use embedded_recruitment_task::codec::{self, PROTOCOL_VERSION};
+use embedded_recruitment_task::message::{
+    client_message, server_message, AddRequest, AddResponse, ClientMessage, ServerMessage,
+};
+use embedded_recruitment_task::transport::{Connection, Error, Transport};
+use std::convert::Infallible;
+
+// In-memory transport returning scripted bytes a few at a time and recording writes
+struct ScriptedTransport {
+    incoming: Vec<u8>,
+    chunk: usize,
+    written: Vec<u8>,
+    writes: usize,
+}
+
+impl Transport for ScriptedTransport {
+    type Error = Infallible;
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let len = self.chunk.min(buf.len()).min(self.incoming.len());
+        buf[..len].copy_from_slice(&self.incoming[..len]);
+        self.incoming.drain(..len);
+        Ok(len)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        self.written.extend_from_slice(buf);
+        self.writes += 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+fn add_response(result: i32) -> ServerMessage {
+    ServerMessage {
+        message: Some(server_message::Message::AddResponse(AddResponse { result })),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_connection_over_custom_transport() {
+    let mut incoming = codec::encode(&add_response(30)).expect("Failed to encode message");
+    incoming.extend(codec::encode(&add_response(-1)).expect("Failed to encode message"));
+
+    let mut connection = Connection::new(ScriptedTransport {
+        incoming,
+        chunk: 1, // Force every frame to be reassembled byte by byte
+        written: Vec::new(),
+        writes: 0,
+    });
+
+    let request = client_message::Message::AddRequest(AddRequest { a: 10, b: 20 });
+    assert!(
+        connection.send(request.clone()).is_ok(),
+        "Failed to send message"
+    );
+    assert_eq!(
+        codec::decode::<ClientMessage>(&connection.get_ref().written[1..]),
+        Ok(ClientMessage {
+            message: Some(request),
+            protocol_version: PROTOCOL_VERSION, // Stated on the first request only
+            ..Default::default()
+        }),
+        "Written frame does not contain the request"
+    );
+
+    assert_eq!(connection.receive().ok(), Some(add_response(30)));
+    assert_eq!(connection.receive().ok(), Some(add_response(-1)));
+    assert!(
+        matches!(connection.receive(), Err(Error::Closed)),
+        "Expected the connection to report the closed stream"
+    );
+}
+
+#[test]
+fn test_batched_requests_are_written_together() {
+    let mut connection = Connection::new(ScriptedTransport {
+        incoming: codec::encode(&add_response(3)).expect("Failed to encode message"),
+        chunk: 64,
+        written: Vec::new(),
+        writes: 0,
+    });
+    let request = client_message::Message::AddRequest(AddRequest { a: 1, b: 2 });
+    let frame_len = |protocol_version| {
+        let message = ClientMessage {
+            message: Some(request.clone()),
+            protocol_version,
+            ..Default::default()
+        };
+        codec::encode(&message)
+            .expect("Failed to encode message")
+            .len()
+    };
+    // The first request also states the protocol version
+    let (first_len, frame_len) = (frame_len(PROTOCOL_VERSION), frame_len(0));
+    connection.set_batch_bytes(3 * frame_len);
+
+    connection.send(request.clone()).expect("Failed to send");
+    connection.send(request.clone()).expect("Failed to send");
+    assert_eq!(connection.get_ref().writes, 0);
+    assert_eq!(connection.held(), first_len + frame_len);
+    connection.send(request.clone()).expect("Failed to send");
+    assert_eq!(connection.get_ref().writes, 1);
+    assert_eq!(
+        connection.get_ref().written.len(),
+        first_len + 2 * frame_len
+    );
+
+    // Receiving writes what is held first
+    connection.send(request).expect("Failed to send");
+    assert_eq!(connection.receive().ok(), Some(add_response(3)));
+    assert_eq!((connection.get_ref().writes, connection.held()), (2, 0));
+}
+
+#[test]
+fn test_batch_envelope_makes_one_frame() {
+    let mut connection = Connection::new(ScriptedTransport {
+        incoming: Vec::new(),
+        chunk: 64,
+        written: Vec::new(),
+        writes: 0,
+    });
+    connection.set_batch_bytes(4096);
+    connection.set_batch_envelope(true);
+    let add = |a| client_message::Message::AddRequest(AddRequest { a, b: 1 });
+    for a in 1..=3 {
+        connection
+            .send_with_id(0, a as u64, add(a))
+            .expect("Failed to send");
+    }
+    connection.flush().expect("Failed to flush");
+
+    // Stated on the batch and kept on its first request
+    let written = &connection.get_ref().written;
+    let Ok(Some((batch, used))) = codec::decode_frame::<ClientMessage>(written) else {
+        panic!("No frame written");
+    };
+    assert_eq!(used, written.len(), "Written as more than one frame");
+    assert_eq!(batch.protocol_version, PROTOCOL_VERSION);
+    let Some(client_message::Message::Batch(batch)) = batch.message else {
+        panic!("Not a batch: {:?}", batch);
+    };
+    let requests: Vec<_> = batch
+        .messages
+        .iter()
+        .map(|request| (request.message_id, request.protocol_version))
+        .collect();
+    assert_eq!(requests, [(1, PROTOCOL_VERSION), (2, 0), (3, 0)]);
+
+    // One request alone goes out as it is
+    connection.send(add(4)).expect("Failed to send");
+    connection.flush().expect("Failed to flush");
+    let written = &connection.get_ref().written[used..];
+    assert_eq!(
+        codec::decode_frame::<ClientMessage>(written)
+            .ok()
+            .flatten()
+            .and_then(|(request, _)| request.message),
+        Some(add(4))
+    );
+}
+
+#[cfg(feature = "embedded-io")]
+#[test]
+fn test_connection_over_embedded_io() {
+    use embedded_recruitment_task::transport::EmbeddedIo;
+
+    // Minimal embedded-io device: reads come from a fixed buffer, writes are discarded
+    struct Serial<'a>(&'a [u8]);
+
+    impl embedded_io::ErrorType for Serial<'_> {
+        type Error = Infallible;
+    }
+
+    impl embedded_io::Read for Serial<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            self.0.read(buf)
+        }
+    }
+
+    impl embedded_io::Write for Serial<'_> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    let incoming = codec::encode(&add_response(30)).expect("Failed to encode message");
+    let mut connection = Connection::new(EmbeddedIo(Serial(&incoming)));
+
+    let request = client_message::Message::AddRequest(AddRequest { a: 10, b: 20 });
+    assert!(connection.send(request).is_ok(), "Failed to send message");
+    assert_eq!(connection.receive().ok(), Some(add_response(30)));
+}
+
+#[cfg(feature = "embedded-io-async")]
+#[test]
+fn test_async_connection() {
+    use embedded_recruitment_task::transport::AsyncConnection;
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+
+    // Async serial port that is only ready every other poll, like a real driver
+    struct Serial<'a> {
+        incoming: &'a [u8],
+        ready: bool,
+    }
+
+    impl embedded_io_async::ErrorType for Serial<'_> {
+        type Error = Infallible;
+    }
+
+    impl embedded_io_async::Read for Serial<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            std::future::poll_fn(|cx| {
+                self.ready = !self.ready;
+                if self.ready {
+                    Poll::Ready(())
+                } else {
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            })
+            .await;
+            let len = buf.len().min(self.incoming.len()).min(2);
+            buf[..len].copy_from_slice(&self.incoming[..len]);
+            self.incoming = &self.incoming[len..];
+            Ok(len)
+        }
+    }
+
+    impl embedded_io_async::Write for Serial<'_> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            Ok(buf.len())
+        }
+    }
+
+    // Minimal executor: poll until the future completes
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    let incoming = codec::encode(&add_response(30)).expect("Failed to encode message");
+    let mut connection = AsyncConnection::new(Serial {
+        incoming: &incoming,
+        ready: false,
+    });
+
+    block_on(async {
+        let request = client_message::Message::AddRequest(AddRequest { a: 10, b: 20 });
+        assert!(
+            connection.send(request).await.is_ok(),
+            "Failed to send message"
+        );
+        assert_eq!(connection.receive().await.ok(), Some(add_response(30)));
+        assert!(matches!(connection.receive().await, Err(Error::Closed)));
+    });
+}
@@ -0,0 +1,120 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::start;
+use embedded_recruitment_task::client::{Client, Push};
+use embedded_recruitment_task::message::{
+    client_message, error_response, transform_request, ErrorResponse, ResumeRequest,
+};
+use embedded_recruitment_task::server::Server;
+use std::io;
+
+#[test]
+fn test_typed_calls_return_the_response_value() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+
+    assert_eq!(client.echo("hi").expect("Echo failed"), "hi");
+    assert_eq!(
+        client.echo_bytes(&[0, 159, 255]).expect("Echo failed"),
+        [0, 159, 255]
+    );
+    assert_eq!(client.add(20, 22).expect("Add failed"), 42);
+    assert_eq!(client.calc("2 * (3 + 4)").expect("Calc failed"), 14.0);
+    assert_eq!(
+        client
+            .transform("abc", transform_request::Op::Reverse)
+            .expect("Transform failed"),
+        "cba"
+    );
+    assert_eq!(client.random(16).expect("Random failed").len(), 16);
+    client.ping().expect("Ping failed");
+    client.report(7, 21.5, 1000).expect("Report failed");
+    assert!(!client
+        .describe()
+        .expect("Describe failed")
+        .message_types
+        .is_empty());
+    client.disconnect().expect("Failed to disconnect");
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_refused_request_is_an_error_carrying_the_response() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+
+    let error = client.calc("1 +").expect_err("Bad expression accepted");
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    let refusal = error
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<ErrorResponse>())
+        .expect("Error does not carry the response");
+    assert_eq!(refusal.code, error_response::Code::Invalid as i32);
+    assert_eq!(refusal.field, "expression");
+    assert!(error
+        .to_string()
+        .starts_with("Request refused: INVALID (expression)"));
+
+    // The connection is still usable
+    assert_eq!(client.add(1, 2).expect("Add failed"), 3);
+    client.disconnect().expect("Failed to disconnect");
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_pushes_during_a_call_are_kept() {
+    let (server, handle, port) = start(Server::new("localhost:0").expect("Failed to start server"));
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    // Named without subscribing, so pushes are not kept apart by the client
+    client
+        .send(client_message::Message::ResumeRequest(ResumeRequest {
+            device_id: "sensor-1".to_string(),
+            ..Default::default()
+        }))
+        .expect("Failed to send message");
+    client.receive().expect("Failed to receive response");
+
+    server.send_to("sensor-1", b"meanwhile".to_vec());
+    assert_eq!(client.echo("after").expect("Echo failed"), "after");
+    match client.next_push().expect("Failed to receive push") {
+        Push::Delivery(delivery) => assert_eq!(&delivery.payload[..], b"meanwhile"),
+        other => panic!("Expected a Delivery, got {:?}", other),
+    }
+    client.disconnect().expect("Failed to disconnect");
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_refusal_kinds() {
+    let refusal = |code: error_response::Code| ErrorResponse {
+        code: code as i32,
+        ..Default::default()
+    };
+    assert_eq!(
+        refusal(error_response::Code::Busy).kind(),
+        io::ErrorKind::ResourceBusy
+    );
+    assert_eq!(
+        refusal(error_response::Code::Forbidden).kind(),
+        io::ErrorKind::PermissionDenied
+    );
+    assert_eq!(
+        refusal(error_response::Code::QuotaExceeded).kind(),
+        io::ErrorKind::QuotaExceeded
+    );
+    assert_eq!(
+        refusal(error_response::Code::Unspecified).kind(),
+        io::ErrorKind::Other
+    );
+}
@@ -0,0 +1,137 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::{add, echo};
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::handler::MessageKind;
+use embedded_recruitment_task::message::{
+    client_message, error_response, server_message, EchoBytes, ErrorResponse, RandomRequest,
+    TelemetryReport,
+};
+use embedded_recruitment_task::server::Server;
+use embedded_recruitment_task::validation::{Validator, Violation};
+use std::{sync::Arc, thread};
+
+fn telemetry(value: f32) -> client_message::Message {
+    client_message::Message::TelemetryReport(TelemetryReport {
+        sensor_id: 1,
+        value,
+        timestamp: 0,
+    })
+}
+
+#[test]
+fn test_built_in_rules() {
+    let validator = Validator::new()
+        .max_string_len(5)
+        .add_operands(-10..=10)
+        .telemetry_values(0.0..=100.0);
+
+    assert_eq!(validator.validate(&echo("hello")), Ok(()));
+    assert_eq!(
+        validator.validate(&echo("hello!")),
+        Err(Violation::new(
+            "content",
+            "6 bytes, more than the limit of 5"
+        ))
+    );
+    assert_eq!(validator.validate(&add(-10, 10)), Ok(()));
+    assert_eq!(
+        validator.validate(&add(1, 11)),
+        Err(Violation::new("b", "11 is outside -10..=10"))
+    );
+    assert_eq!(validator.validate(&telemetry(42.5)), Ok(()));
+    assert_eq!(
+        validator
+            .validate(&telemetry(f32::NAN))
+            .map_err(|v| v.field),
+        Err("value".to_string())
+    );
+    assert_eq!(
+        validator.validate(&telemetry(-1.0)).map_err(|v| v.field),
+        Err("value".to_string())
+    );
+
+    let bytes = |len| client_message::Message::EchoBytes(EchoBytes { data: vec![0; len] });
+    assert_eq!(Validator::new().validate(&bytes(16 * 1024)), Ok(()));
+    assert_eq!(
+        Validator::new().max_bytes_len(4).validate(&bytes(5)),
+        Err(Violation::new("data", "5 bytes, more than the limit of 4"))
+    );
+
+    let random = |num_bytes| client_message::Message::RandomRequest(RandomRequest { num_bytes });
+    assert_eq!(Validator::new().validate(&random(1024)), Ok(()));
+    assert_eq!(
+        Validator::new().max_random_bytes(16).validate(&random(17)),
+        Err(Violation::new(
+            "num_bytes",
+            "17 is more than the limit of 16"
+        ))
+    );
+
+    // Only non-finite telemetry values by default
+    assert_eq!(Validator::new().validate(&add(i32::MAX, 1)), Ok(()));
+    assert!(Validator::new()
+        .validate(&telemetry(f32::INFINITY))
+        .is_err());
+}
+
+#[test]
+fn test_rules_can_be_overridden_per_type() {
+    let validator = Validator::new()
+        .max_string_len(3)
+        .rule(MessageKind::Echo, |request| match request {
+            client_message::Message::EchoMessage(echo) if echo.content.contains('\0') => {
+                Err(Violation::new("content", "contains a NUL character"))
+            }
+            _ => Ok(()),
+        });
+
+    // The length limit no longer applies to echoes
+    assert_eq!(validator.validate(&echo("longer than three")), Ok(()));
+    assert_eq!(
+        validator.validate(&echo("a\0b")),
+        Err(Violation::new("content", "contains a NUL character"))
+    );
+}
+
+#[test]
+fn test_invalid_requests_are_answered_with_an_error() {
+    let server = Arc::new(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .layer(Validator::new().add_operands(0..=100)),
+    );
+    let port = server.local_addr().expect("No local address").port();
+    let server_clone = Arc::clone(&server);
+    let handle = thread::spawn(move || server_clone.run().expect("Server encountered an error"));
+
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    client.send(add(1, -1)).expect("Failed to send message");
+    assert_eq!(
+        client
+            .receive()
+            .expect("Failed to receive response")
+            .message,
+        Some(server_message::Message::ErrorResponse(ErrorResponse {
+            code: error_response::Code::Invalid as i32,
+            field: "b".to_string(),
+            detail: "-1 is outside 0..=100".to_string(),
+            ..Default::default()
+        }))
+    );
+    client.send(add(1, 2)).expect("Failed to send message");
+    assert!(matches!(
+        client
+            .receive()
+            .expect("Failed to receive response")
+            .message,
+        Some(server_message::Message::AddResponse(_))
+    ));
+
+    client.disconnect().ok();
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
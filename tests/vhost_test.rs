@@ -0,0 +1,115 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+mod common;
+
+use common::{echo, start};
+use embedded_recruitment_task::authz::{Grant, StaticPolicy};
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::message::{
+    client_message, error_response, server_message, EchoMessage, ResumeRequest,
+};
+use embedded_recruitment_task::router::Router;
+use embedded_recruitment_task::server::Server;
+use embedded_recruitment_task::vhost::VirtualHost;
+
+fn request(client: &mut Client, message: client_message::Message) -> server_message::Message {
+    client.send(message).expect("Failed to send message");
+    let response = client.receive().expect("Failed to receive response");
+    response.message.expect("Empty response")
+}
+
+fn resume(device: &str, vhost: &str) -> client_message::Message {
+    client_message::Message::ResumeRequest(ResumeRequest {
+        device_id: device.to_string(),
+        vhost: vhost.to_string(),
+        ..Default::default()
+    })
+}
+
+fn error_code(response: server_message::Message) -> Option<i32> {
+    match response {
+        server_message::Message::ErrorResponse(error) => Some(error.code),
+        _ => None,
+    }
+}
+
+fn test_server() -> Server {
+    let shouting = Router::new().on_echo(|echo| {
+        server_message::Message::EchoMessage(EchoMessage {
+            content: echo.content.to_uppercase(),
+        })
+    });
+    Server::new("localhost:0")
+        .expect("Failed to start server")
+        .virtual_host(
+            "acme.example.com",
+            VirtualHost::new().tenant("acme").router(shouting),
+        )
+        .virtual_host(
+            "locked.example.com",
+            VirtualHost::new().authorizer(StaticPolicy::new()),
+        )
+        .authorizer(StaticPolicy::new().everyone(Grant::all_requests()))
+}
+
+#[test]
+fn test_host_picks_handlers_and_tenant() {
+    let (server, handle, port) = start(test_server());
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+
+    let response = request(&mut client, resume("thermo-1", "acme.example.com"));
+    assert!(matches!(
+        response,
+        server_message::Message::ResumeResponse(_)
+    ));
+    match request(&mut client, echo("hello")) {
+        server_message::Message::EchoMessage(echo) => assert_eq!(echo.content, "HELLO"),
+        other => panic!("Expected an EchoMessage, got {:?}", other),
+    }
+    assert_eq!(server.tenant("acme").stats().connections, 1);
+
+    // The host cannot be swapped for another on the same connection
+    let response = request(&mut client, resume("", "locked.example.com"));
+    assert_eq!(
+        error_code(response),
+        Some(error_response::Code::Invalid as i32)
+    );
+    assert!(matches!(
+        request(&mut client, echo("hello")),
+        server_message::Message::EchoMessage(_)
+    ));
+    client.disconnect().expect("Failed to disconnect");
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_host_picks_authorizer() {
+    let (server, handle, port) = start(test_server());
+    let forbidden = Some(error_response::Code::Forbidden as i32);
+
+    let mut locked = Client::new("localhost", port.into(), 1000);
+    locked.connect().expect("Failed to connect to the server");
+    request(&mut locked, resume("thermo-1", "locked.example.com"));
+    assert_eq!(error_code(request(&mut locked, echo("hello"))), forbidden);
+
+    let mut plain = Client::new("localhost", port.into(), 1000);
+    plain.connect().expect("Failed to connect to the server");
+    let response = request(&mut plain, resume("thermo-1", "unknown.example.com"));
+    assert_eq!(
+        error_code(response),
+        Some(error_response::Code::Invalid as i32)
+    );
+    match request(&mut plain, echo("hello")) {
+        server_message::Message::EchoMessage(echo) => assert_eq!(echo.content, "hello"),
+        other => panic!("Expected an EchoMessage, got {:?}", other),
+    }
+    locked.disconnect().expect("Failed to disconnect");
+    plain.disconnect().expect("Failed to disconnect");
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+    assert_eq!(server.stats().denied_requests, 1);
+}
@@ -0,0 +1,180 @@
+#![cfg(all(feature = "client", feature = "wasm"))]
+
+mod common;
+
+use common::add;
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::handler::MessageKind;
+use embedded_recruitment_task::message::{
+    client_message, server_message, AddResponse, PingRequest, PingResponse,
+};
+use embedded_recruitment_task::server::Server;
+use embedded_recruitment_task::wasm::Plugin;
+use std::{fs, path::PathBuf, sync::Arc, thread};
+
+fn module_file(name: &str, wat: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("{}-{}.wat", name, std::process::id()));
+    fs::write(&path, wat).expect("Failed to write module");
+    path
+}
+
+// Hands the request back as the response. An `AddRequest`'s field numbers
+// match those of an `AddResponse`, so `a` comes back as the result.
+const MIRROR: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+  (func (export "handle") (param $ptr i32) (param $len i32) (result i64)
+    (i64.or
+      (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+      (i64.extend_i32_u (local.get $len)))))
+"#;
+
+// Counts its calls in the key-value store and answers each with a
+// `PingResponse` carrying the count
+const COUNTER: &str = r#"
+(module
+  (import "host" "log" (func $log (param i32 i32 i32)))
+  (import "host" "kv_get" (func $kv_get (param i32 i32 i32 i32) (result i32)))
+  (import "host" "kv_set" (func $kv_set (param i32 i32 i32 i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 0) "n")
+  (data (i32.const 16) "\1a\02\08\00")
+  (data (i32.const 32) "counting")
+  (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+  (func (export "handle") (param i32 i32) (result i64)
+    (call $log (i32.const 3) (i32.const 32) (i32.const 8))
+    (drop (call $kv_get (i32.const 0) (i32.const 1) (i32.const 19) (i32.const 1)))
+    (i32.store8 (i32.const 19) (i32.add (i32.load8_u (i32.const 19)) (i32.const 1)))
+    (call $kv_set (i32.const 0) (i32.const 1) (i32.const 19) (i32.const 1))
+    (i64.or (i64.shl (i64.const 16) (i64.const 32)) (i64.const 4))))
+"#;
+
+#[test]
+fn test_plugin_answers_requests_over_the_network() {
+    let path = module_file("wasm-test-mirror", MIRROR);
+    let plugin = Plugin::load(&path)
+        .expect("Failed to load plugin")
+        .only(&[MessageKind::Add]);
+    let server = Arc::new(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .layer(plugin),
+    );
+    let port = server.local_addr().expect("No local address").port();
+    let server_clone = Arc::clone(&server);
+    let handle = thread::spawn(move || server_clone.run().expect("Server encountered an error"));
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+
+    client.send(add(5, 7)).expect("Failed to send message");
+    assert_eq!(
+        client
+            .receive()
+            .expect("Failed to receive response")
+            .message,
+        Some(server_message::Message::AddResponse(AddResponse {
+            result: 5
+        }))
+    );
+    // Other types never reach the plugin
+    client
+        .send(client_message::Message::PingRequest(PingRequest {
+            timestamp: 9,
+        }))
+        .expect("Failed to send message");
+    assert_eq!(
+        client
+            .receive()
+            .expect("Failed to receive response")
+            .message,
+        Some(server_message::Message::PingResponse(PingResponse {
+            timestamp: 9
+        }))
+    );
+
+    client.disconnect().ok();
+    server.stop();
+    handle.join().expect("Server thread panicked");
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn test_plugin_state_lives_in_the_key_value_store() {
+    let path = module_file("wasm-test-counter", COUNTER);
+    let plugin = Plugin::load(&path).expect("Failed to load plugin");
+    for count in 1..=3 {
+        assert_eq!(
+            plugin.handle(&add(0, 0)),
+            Some(server_message::Message::PingResponse(PingResponse {
+                timestamp: count
+            }))
+        );
+    }
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn test_failing_plugins_pass_requests_on() {
+    let spinning = module_file(
+        "wasm-test-spinning",
+        r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+          (func (export "handle") (param i32 i32) (result i64)
+            (loop $forever (br $forever))
+            (i64.const 0)))
+        "#,
+    );
+    let plugin = Plugin::load(&spinning).expect("Failed to load plugin");
+    assert_eq!(plugin.handle(&add(1, 2)), None, "Out of fuel");
+    fs::remove_file(spinning).ok();
+
+    let garbage = module_file(
+        "wasm-test-garbage",
+        r#"
+        (module
+          (memory (export "memory") 1)
+          (data (i32.const 0) "\ff\ff\ff")
+          (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+          (func (export "handle") (param i32 i32) (result i64) (i64.const 3)))
+        "#,
+    );
+    let plugin = Plugin::load(&garbage).expect("Failed to load plugin");
+    assert_eq!(plugin.handle(&add(1, 2)), None, "Undecodable response");
+    fs::remove_file(garbage).ok();
+}
+
+#[test]
+fn test_modules_must_follow_the_abi() {
+    let cases = [
+        (
+            "wasm-test-no-handle",
+            r#"(module (memory (export "memory") 1))"#,
+        ),
+        (
+            "wasm-test-unknown-import",
+            r#"
+            (module
+              (import "host" "exec" (func (param i32)))
+              (memory (export "memory") 1)
+              (func (export "alloc") (param i32) (result i32) (i32.const 0))
+              (func (export "handle") (param i32 i32) (result i64) (i64.const 0)))
+            "#,
+        ),
+        ("wasm-test-not-wasm", "not a module"),
+    ];
+    for (name, wat) in cases {
+        let path = module_file(name, wat);
+        let error = Plugin::load(&path).err();
+        assert_eq!(
+            error.map(|e| e.kind()),
+            Some(std::io::ErrorKind::InvalidData),
+            "{}",
+            name
+        );
+        fs::remove_file(path).ok();
+    }
+    assert!(Plugin::load("/nonexistent/plugin.wasm").is_err());
+}
@@ -0,0 +1,74 @@
+#![cfg(all(feature = "client", feature = "server"))]
+
+use embedded_recruitment_task::client::Client;
+use embedded_recruitment_task::message::{client_message, AddRequest};
+use embedded_recruitment_task::server::Server;
+use embedded_recruitment_task::watchdog::{Watchdog, WatchdogAction};
+use std::{net::TcpListener, sync::mpsc, sync::Arc, thread, time::Duration};
+
+#[test]
+fn test_trips_once_per_stuck_handler() {
+    // An upstream that accepts the relay's link but never answers, until released
+    let upstream = TcpListener::bind("localhost:0").expect("Failed to bind upstream");
+    let upstream_addr = upstream.local_addr().expect("No local address");
+    let (release, released) = mpsc::channel::<()>();
+    let silent = thread::spawn(move || {
+        let (_link, _) = upstream.accept().expect("Relay never connected");
+        let _ = released.recv();
+    });
+
+    let server = Arc::new(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .relay_to(&upstream_addr.to_string(), 1)
+            .watchdog(Watchdog {
+                pool_window: Duration::from_millis(200),
+                action: WatchdogAction::Log,
+                ..Watchdog::default()
+            }),
+    );
+    let port = server.local_addr().expect("No local address").port();
+    let server_clone = Arc::clone(&server);
+    let handle = thread::spawn(move || server_clone.run().expect("Server encountered an error"));
+
+    let mut client = Client::new("localhost", port.into(), 1000);
+    client.connect().expect("Failed to connect to the server");
+    client
+        .send(client_message::Message::AddRequest(AddRequest {
+            a: 1,
+            b: 2,
+        }))
+        .expect("Failed to send message");
+
+    // Stuck well past the window, but reported only once
+    thread::sleep(Duration::from_millis(800));
+    assert_eq!(server.stats().watchdog_trips, 1);
+
+    release.send(()).ok();
+    silent.join().expect("Upstream thread panicked");
+    client.disconnect().ok();
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
+
+#[test]
+fn test_quiet_server_never_trips() {
+    let server = Arc::new(
+        Server::new("localhost:0")
+            .expect("Failed to start server")
+            .watchdog(Watchdog {
+                accept_window: Duration::from_millis(500),
+                pool_window: Duration::from_millis(500),
+                action: WatchdogAction::Exit(1),
+            }),
+    );
+    let server_clone = Arc::clone(&server);
+    let handle = thread::spawn(move || server_clone.run().expect("Server encountered an error"));
+
+    // An idle accept loop still turns every 100 ms
+    thread::sleep(Duration::from_secs(1));
+    assert_eq!(server.stats().watchdog_trips, 0);
+
+    server.stop();
+    handle.join().expect("Server thread panicked");
+}
@@ -0,0 +1,36 @@
+#![cfg(feature = "std")]
+
+use embedded_recruitment_task::capture::Direction;
+use embedded_recruitment_task::wirelog::{hex_dump, WireLog, MAX_DUMP_BYTES};
+
+#[test]
+fn test_hex_dump_format() {
+    assert_eq!(
+        hex_dump(b"\x0f\x0a\x0dHello, World!\x00\xff"),
+        "0000  0f 0a 0d 48 65 6c 6c 6f 2c 20 57 6f 72 6c 64 21  |...Hello, World!|\n\
+         0010  00 ff                                            |..|"
+    );
+    assert_eq!(hex_dump(&[]), "");
+
+    let dump = hex_dump(&[0; MAX_DUMP_BYTES + 10]);
+    assert!(dump.ends_with("... 10 more bytes"), "{}", dump);
+}
+
+#[test]
+fn test_wire_log_is_opt_in_and_rate_limited() {
+    let log = WireLog::new();
+    assert!(
+        !log.log(None, Direction::Inbound, b"frame"),
+        "Logged while disabled"
+    );
+
+    log.enable();
+    log.set_rate_limit(2);
+    let logged: Vec<bool> = (0..5)
+        .map(|_| log.log(None, Direction::Outbound, b"frame"))
+        .collect();
+    assert_eq!(logged, [true, true, false, false, false]);
+
+    log.disable();
+    assert!(!log.is_enabled());
+}
@@ -0,0 +1,72 @@
+-- Wireshark dissector for pcapng files written by the `pcapng` tool.
+--
+-- Packets use link type USER0 (147): one direction byte (0 = client to
+-- server, 1 = server to client), then a frame as sent on the wire, a varint
+-- length followed by a protobuf `ClientMessage` or `ServerMessage`. The body
+-- is handed to Wireshark's protobuf dissector, so field decoding always
+-- follows proto/messages.proto; add the repository's proto/ directory under
+-- Preferences > Protocols > ProtoBuf > Protobuf search paths.
+--
+-- Usage: wireshark -X lua_script:wireshark/ert.lua traffic.pcapng
+
+local ert = Proto("ert", "Embedded recruitment task protocol")
+
+local directions = { [0] = "Client to server", [1] = "Server to client" }
+local f_direction = ProtoField.uint8("ert.direction", "Direction", base.DEC, directions)
+local f_length = ProtoField.uint32("ert.length", "Frame length", base.DEC)
+ert.fields = { f_direction, f_length }
+
+local e_truncated = ProtoExpert.new("ert.truncated", "Frame is shorter than its length prefix",
+    expert.group.MALFORMED, expert.severity.ERROR)
+local e_bad_length = ProtoExpert.new("ert.bad_length", "Invalid length prefix",
+    expert.group.MALFORMED, expert.severity.ERROR)
+ert.experts = { e_truncated, e_bad_length }
+
+local protobuf = Dissector.get("protobuf")
+
+-- Same rules as the codec: at most three bytes, at most 64 KiB
+local function read_length(tvb, offset)
+    local value = 0
+    for i = 0, 2 do
+        if offset + i >= tvb:len() then
+            return nil
+        end
+        local byte = tvb(offset + i, 1):uint()
+        value = value + bit32.lshift(bit32.band(byte, 0x7f), 7 * i)
+        if bit32.band(byte, 0x80) == 0 then
+            return value, i + 1
+        end
+    end
+    return nil
+end
+
+function ert.dissector(tvb, pinfo, tree)
+    pinfo.cols.protocol = "ERT"
+    local subtree = tree:add(ert, tvb())
+
+    local direction = tvb(0, 1):uint()
+    subtree:add(f_direction, tvb(0, 1))
+    local message = direction == 0 and "ClientMessage" or "ServerMessage"
+
+    local length, prefix = read_length(tvb, 1)
+    if length == nil or length > 65536 then
+        subtree:add_proto_expert_info(e_bad_length)
+        pinfo.cols.info = message .. " [invalid length prefix]"
+        return
+    end
+    subtree:add(f_length, tvb(1, prefix), length)
+    pinfo.cols.info = string.format("%s, %d bytes", message, length)
+
+    local body = 1 + prefix
+    if tvb:len() - body < length then
+        subtree:add_proto_expert_info(e_truncated)
+        return
+    end
+    if length > 0 and protobuf ~= nil then
+        pinfo.private["pb_msg_type"] = "message,messages." .. message
+        protobuf:call(tvb(body, length):tvb(), pinfo, subtree)
+    end
+end
+
+local encap = (wtap_encaps or wtap).USER0
+DissectorTable.get("wtap_encap"):add(encap, ert)